@@ -0,0 +1,198 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ndarray::Array;
+use read_hdf_as_raster::compression::H5Compression;
+use read_hdf_as_raster::error::Error;
+use read_hdf_as_raster::hdf::{create_dataset, DatasetLayout};
+use read_hdf_as_raster::reverse::{rev_array, reverse_rows, reverse_rows_parallel, HdfChunks};
+use read_hdf_as_raster::stats::{mean_sd, mean_sd_into};
+use std::iter::FromIterator;
+
+const XSIZE: usize = 4000;
+const YSIZE: usize = 1000;
+
+fn bench_rev_array(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rev_array");
+    for &n_lines in &[10usize, 100, 1000] {
+        let n_vals = n_lines * XSIZE;
+
+        group.throughput(Throughput::Bytes(
+            (n_vals * std::mem::size_of::<u8>()) as u64,
+        ));
+        let input_u8 = Array::from(vec![1u8; n_vals]);
+        group.bench_with_input(BenchmarkId::new("u8", n_lines), &n_lines, |b, &n_lines| {
+            b.iter(|| rev_array(black_box(input_u8.clone()), n_lines, XSIZE))
+        });
+
+        group.throughput(Throughput::Bytes(
+            (n_vals * std::mem::size_of::<f32>()) as u64,
+        ));
+        let input_f32 = Array::from(vec![1f32; n_vals]);
+        group.bench_with_input(BenchmarkId::new("f32", n_lines), &n_lines, |b, &n_lines| {
+            b.iter(|| rev_array(black_box(input_f32.clone()), n_lines, XSIZE))
+        });
+    }
+    group.finish();
+}
+
+fn bench_hdf5_reverse<T: hdf5::H5Type + Clone + Default>(c: &mut Criterion, dtype: &str) {
+    let path = std::env::temp_dir().join(format!("read_hdf_as_raster_bench_{}.h5", dtype));
+    let file = hdf5::File::create(&path).unwrap();
+    let compression = H5Compression::default();
+    let layout = DatasetLayout {
+        chunk_len: 100 * XSIZE,
+        resizable: false,
+    };
+    let create = |name: &str| {
+        create_dataset::<T>(
+            &file,
+            name,
+            XSIZE * YSIZE,
+            T::default(),
+            layout,
+            &compression,
+        )
+        .unwrap()
+    };
+    let ds = create("values");
+    let ds_out = create("values_rev");
+    ds.write(&vec![T::default(); XSIZE * YSIZE]).unwrap();
+
+    let mut group = c.benchmark_group(format!("hdf5_reverse_rows/{}", dtype));
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(
+        (2 * XSIZE * YSIZE * std::mem::size_of::<T>()) as u64,
+    ));
+    for &n_lines_read in &[10usize, 64, 100, 128, 500] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(n_lines_read),
+            &n_lines_read,
+            |b, &n_lines_read| {
+                b.iter(|| {
+                    reverse_rows::<T, _, _, _, _>(
+                        &HdfChunks(&ds),
+                        &HdfChunks(&ds_out),
+                        XSIZE,
+                        YSIZE,
+                        n_lines_read,
+                        |_| Ok(()),
+                    )
+                    .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+    drop(file);
+    let _ = std::fs::remove_file(&path);
+}
+
+/// Reversal of a gzip-compressed dataset of varied values by 1 worker and
+/// more, to measure what the workers gain: HDF5 calls, decompression
+/// included, run one at a time behind the library's global lock, so only
+/// the flips and the writes overlap with the reads.
+fn bench_hdf5_reverse_parallel<T>(c: &mut Criterion, dtype: &str)
+where
+    T: hdf5::H5Type + Clone + Default + Send + From<u8>,
+{
+    let path = std::env::temp_dir().join(format!("read_hdf_as_raster_bench_parallel_{}.h5", dtype));
+    let file = hdf5::File::create(&path).unwrap();
+    let compression = H5Compression::default();
+    let layout = DatasetLayout {
+        chunk_len: 100 * XSIZE,
+        resizable: false,
+    };
+    let create = |name: &str| {
+        create_dataset::<T>(
+            &file,
+            name,
+            XSIZE * YSIZE,
+            T::default(),
+            layout,
+            &compression,
+        )
+        .unwrap()
+    };
+    let ds = create("values");
+    let ds_out = create("values_rev");
+    let vals: Vec<T> = (0..XSIZE * YSIZE)
+        .map(|ii| T::from((ii * 7919 % 251) as u8))
+        .collect();
+    ds.write(&vals).unwrap();
+
+    let mut group = c.benchmark_group(format!("hdf5_reverse_rows_parallel/{}", dtype));
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(
+        (2 * XSIZE * YSIZE * std::mem::size_of::<T>()) as u64,
+    ));
+    let mut worker_counts = vec![1usize, 2, 4];
+    worker_counts.push(rayon::current_num_threads());
+    worker_counts.dedup();
+    for &n_workers in &worker_counts {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(n_workers),
+            &n_workers,
+            |b, &n_workers| {
+                b.iter(|| {
+                    reverse_rows_parallel::<T, _, _, Error, _, _>(
+                        || Ok(file.dataset("values")?),
+                        &HdfChunks(&ds_out),
+                        XSIZE,
+                        YSIZE,
+                        100,
+                        n_workers,
+                        |_| Ok(()),
+                    )
+                    .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+    drop(file);
+    let _ = std::fs::remove_file(&path);
+}
+
+fn bench_hdf5(c: &mut Criterion) {
+    bench_hdf5_reverse::<u8>(c, "u8");
+    bench_hdf5_reverse::<f32>(c, "f32");
+    bench_hdf5_reverse_parallel::<u8>(c, "u8");
+    bench_hdf5_reverse_parallel::<f32>(c, "f32");
+}
+
+fn bench_mean_sd(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mean_sd");
+    for &chunk_size in &[10_000usize, 100_000, 1_000_000] {
+        let sum = Array::from_iter((0..chunk_size).map(|ii| (ii % 97) as f32));
+        let sumsq = sum.mapv(|el| el * el * 2f32);
+        let count = Array::from_iter((0..chunk_size).map(|ii| (ii % 5) as u8));
+
+        group.throughput(Throughput::Elements(chunk_size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(chunk_size),
+            &chunk_size,
+            |b, _| b.iter(|| mean_sd(black_box(&sum), black_box(&sumsq), black_box(&count))),
+        );
+
+        let mut mean = vec![0f32; chunk_size];
+        let mut sd = vec![0f32; chunk_size];
+        group.bench_with_input(
+            BenchmarkId::new("serial", chunk_size),
+            &chunk_size,
+            |b, _| {
+                b.iter(|| {
+                    mean_sd_into(
+                        black_box(sum.as_slice().unwrap()),
+                        black_box(sumsq.as_slice().unwrap()),
+                        black_box(count.as_slice().unwrap()),
+                        &mut mean,
+                        &mut sd,
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_rev_array, bench_hdf5, bench_mean_sd);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+fn main() {
+    // Virtual datasets (see src/vds.rs) need HDF5 1.10.
+    println!("cargo:rustc-check-cfg=cfg(hdf5_1_10)");
+    if std::env::var_os("DEP_HDF5_VERSION_1_10_0").is_some() {
+        println!("cargo:rustc-cfg=hdf5_1_10");
+    }
+    // Chunk allocation is queried (see src/allocation.rs) with HDF5 1.10.5
+    // and later, as reported by hdf5-sys.
+    println!("cargo:rustc-check-cfg=cfg(hdf5_1_10_5)");
+    if std::env::var_os("DEP_HDF5_VERSION_1_10_5").is_some() {
+        println!("cargo:rustc-cfg=hdf5_1_10_5");
+    }
+    // The header is generated into OUT_DIR, as build scripts must leave the
+    // source tree alone; `cbindgen` writes it elsewhere (see the README).
+    #[cfg(feature = "capi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        cbindgen::generate(&crate_dir)
+            .expect("could not generate the C header")
+            .write_to_file(std::path::Path::new(&out_dir).join("read_hdf_as_raster.h"));
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+    }
+}
@@ -0,0 +1,237 @@
+//! Chunks of HDF5 datasets that were never written.
+//!
+//! Sparse global grids are often written only where there are observations,
+//! leaving most chunks unallocated; HDF5 reads them back as the fill value.
+//! Reads (see [`crate::hdf::read_flat`]) ask which chunks a range covers and
+//! return the fill value without going through HDF5 when none is allocated,
+//! in every pass over the dataset.
+//!
+//! Allocation is queried with `H5Dget_chunk_info_by_coord`, from HDF5
+//! 1.10.5; with older libraries every range is read.
+
+use crate::attrs;
+use hdf5::types::TypeDescriptor;
+use ndarray::Array1;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Chunking of a dataset some of whose chunks are not allocated.
+#[derive(Debug, Clone, PartialEq)]
+struct ChunkGrid {
+    shape: Vec<usize>,
+    chunk: Vec<usize>,
+    /// No chunk is allocated at all.
+    empty: bool,
+}
+
+/// Chunk grid of each dataset read so far, by file name and path; `None`
+/// for datasets that are contiguous, fully allocated or cannot be queried.
+static GRIDS: Mutex<Option<HashMap<(String, String), Option<ChunkGrid>>>> = Mutex::new(None);
+
+fn key(ds: &hdf5::Dataset) -> (String, String) {
+    return (ds.filename(), ds.name());
+}
+
+fn grid_of(ds: &hdf5::Dataset) -> Option<ChunkGrid> {
+    let key = key(ds);
+    if let Ok(grids) = GRIDS.lock() {
+        if let Some(grid) = grids.as_ref().and_then(|grids| grids.get(&key)) {
+            return grid.clone();
+        }
+    }
+    let grid = query_grid(ds);
+    if let Ok(mut grids) = GRIDS.lock() {
+        grids
+            .get_or_insert_with(HashMap::new)
+            .insert(key, grid.clone());
+    }
+    return grid;
+}
+
+/// Drops what is known of the chunks of `ds`, e.g. when it is written.
+pub fn forget(ds: &hdf5::Dataset) {
+    if let Ok(mut grids) = GRIDS.lock() {
+        if let Some(grids) = grids.as_mut() {
+            grids.remove(&key(ds));
+        }
+    }
+}
+
+/// Chunk indices, per dimension, covering the elements `start..end` of a
+/// row-major grid of `shape` chunked by `chunk`: the rows they span and, for
+/// ranges within one row, the columns. `None` for ranks other than 1 and 2.
+fn covered_chunks(
+    shape: &[usize],
+    chunk: &[usize],
+    start: usize,
+    end: usize,
+) -> Option<Vec<std::ops::Range<usize>>> {
+    return match (shape, chunk) {
+        ([_], [len]) => Some(vec![start / len..(end - 1) / len + 1]),
+        ([_, ncols], [chunk_rows, chunk_cols]) => {
+            let (first_row, last_row) = (start / ncols, (end - 1) / ncols);
+            let cols = match first_row == last_row {
+                true => (start % ncols) / chunk_cols..((end - 1) % ncols) / chunk_cols + 1,
+                false => 0..(ncols + chunk_cols - 1) / chunk_cols,
+            };
+            Some(vec![
+                first_row / chunk_rows..last_row / chunk_rows + 1,
+                cols,
+            ])
+        }
+        _ => None,
+    };
+}
+
+/// Whether no chunk covering the elements `start..end` of `ds` is allocated.
+pub fn unallocated(ds: &hdf5::Dataset, start: usize, end: usize) -> bool {
+    if start >= end {
+        return false;
+    }
+    let grid = match grid_of(ds) {
+        Some(grid) => grid,
+        None => return false,
+    };
+    if grid.empty {
+        return true;
+    }
+    let ranges = match covered_chunks(&grid.shape, &grid.chunk, start, end) {
+        Some(ranges) => ranges,
+        None => return false,
+    };
+    let mut coords = vec![vec![]];
+    for range in ranges {
+        coords = coords
+            .into_iter()
+            .flat_map(|coord: Vec<usize>| {
+                return range.clone().map(move |index| {
+                    let mut coord = coord.clone();
+                    coord.push(index);
+                    return coord;
+                });
+            })
+            .collect();
+    }
+    return coords
+        .iter()
+        .all(|coord| is_allocated(ds, &grid.chunk, coord) == Some(false));
+}
+
+/// `n` copies of the fill value of `ds` as `T`, for plain numeric `T`; `None`
+/// if it cannot be converted.
+pub fn fill_values<T: hdf5::H5Type>(ds: &hdf5::Dataset, n: usize) -> Option<Array1<T>> {
+    match T::type_descriptor() {
+        TypeDescriptor::Integer(_)
+        | TypeDescriptor::Unsigned(_)
+        | TypeDescriptor::Float(_)
+        | TypeDescriptor::Boolean => {}
+        _ => return None,
+    }
+    let fill = attrs::fill_value::<T>(ds).ok()??;
+    let mut vals: Vec<T> = Vec::with_capacity(n);
+    // Plain numbers are copied bitwise and own nothing to drop.
+    unsafe {
+        for ii in 0..n {
+            std::ptr::copy_nonoverlapping(&fill, vals.as_mut_ptr().add(ii), 1);
+        }
+        vals.set_len(n);
+    }
+    return Some(Array1::from(vals));
+}
+
+#[cfg(hdf5_1_10_5)]
+fn query_grid(ds: &hdf5::Dataset) -> Option<ChunkGrid> {
+    use hdf5_sys::h5::hsize_t;
+    use hdf5_sys::h5d::{H5Dget_num_chunks, H5Dget_space};
+    use hdf5_sys::h5s::H5Sclose;
+
+    let shape = ds.shape();
+    let chunk = crate::meta::DatasetMeta::read(ds).ok()?.chunk?;
+    let n_chunks: usize = shape
+        .iter()
+        .zip(&chunk)
+        .map(|(&len, &chunk_len)| (len + chunk_len - 1) / chunk_len.max(1))
+        .product();
+    let _silence = hdf5::silence_errors();
+    let n_allocated = hdf5::sync::sync(|| unsafe {
+        let space = H5Dget_space(ds.id());
+        if space < 0 {
+            return None;
+        }
+        let mut n_allocated: hsize_t = 0;
+        let status = H5Dget_num_chunks(ds.id(), space, &mut n_allocated);
+        H5Sclose(space);
+        return match status >= 0 {
+            true => Some(n_allocated as usize),
+            false => None,
+        };
+    })?;
+    if n_allocated >= n_chunks {
+        return None;
+    }
+    return Some(ChunkGrid {
+        shape,
+        chunk,
+        empty: n_allocated == 0,
+    });
+}
+
+#[cfg(not(hdf5_1_10_5))]
+fn query_grid(_ds: &hdf5::Dataset) -> Option<ChunkGrid> {
+    return None;
+}
+
+/// Whether the chunk at index `coord` of `ds` is allocated; `None` if that
+/// cannot be told.
+#[cfg(hdf5_1_10_5)]
+fn is_allocated(ds: &hdf5::Dataset, chunk: &[usize], coord: &[usize]) -> Option<bool> {
+    use hdf5_sys::h5::{haddr_t, hsize_t, HADDR_UNDEF};
+    use hdf5_sys::h5d::H5Dget_chunk_info_by_coord;
+
+    let offset: Vec<hsize_t> = coord
+        .iter()
+        .zip(chunk)
+        .map(|(&index, &len)| (index * len) as hsize_t)
+        .collect();
+    let _silence = hdf5::silence_errors();
+    return hdf5::sync::sync(|| unsafe {
+        let (mut filter_mask, mut addr, mut size) = (0, 0 as haddr_t, 0 as hsize_t);
+        let status = H5Dget_chunk_info_by_coord(
+            ds.id(),
+            offset.as_ptr(),
+            &mut filter_mask,
+            &mut addr,
+            &mut size,
+        );
+        return match status >= 0 {
+            true => Some(addr != HADDR_UNDEF),
+            false => None,
+        };
+    });
+}
+
+#[cfg(not(hdf5_1_10_5))]
+fn is_allocated(_ds: &hdf5::Dataset, _chunk: &[usize], _coord: &[usize]) -> Option<bool> {
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_covered_by_flat_ranges() {
+        assert_eq!(covered_chunks(&[100], &[10], 15, 35), Some(vec![1..4]));
+        // Within one row of a 2-d grid, only the columns it spans.
+        assert_eq!(
+            covered_chunks(&[8, 12], &[4, 4], 13, 18),
+            Some(vec![0..1, 0..2])
+        );
+        // Across rows, every column.
+        assert_eq!(
+            covered_chunks(&[8, 12], &[4, 4], 40, 72),
+            Some(vec![0..2, 0..3])
+        );
+        assert_eq!(covered_chunks(&[2, 2, 2], &[1, 1, 1], 0, 8), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Fill values and attributes carried over from source datasets to the
+//! datasets derived from them, so HDF5 consumers read the derived values the
+//! same way.
+
+use crate::error::{Error, Result};
+use hdf5_sys::h5::hid_t;
+use hdf5_sys::h5a::{
+    H5Acreate2, H5Adelete, H5Aexists, H5Aget_space, H5Aget_type, H5Aopen, H5Aread, H5Awrite,
+};
+use hdf5_sys::h5d::{H5D_fill_value_t, H5Dget_create_plist, H5Dvlen_reclaim};
+use hdf5_sys::h5i::H5Idec_ref;
+use hdf5_sys::h5p::{H5Pfill_value_defined, H5Pget_fill_value, H5P_DEFAULT};
+use hdf5_sys::h5s::H5Sget_simple_extent_npoints;
+use hdf5_sys::h5t::{H5T_class_t, H5Tdetect_class, H5Tget_class, H5Tget_size};
+use std::ffi::CString;
+
+/// Attributes copied from a source dataset to its `_rev` dataset.
+pub const REV_ATTRIBUTES: &[&str] = &[
+    "units",
+    "valid_range",
+    "valid_min",
+    "valid_max",
+    "_FillValue",
+];
+
+/// Attributes copied from the `sum` dataset to `mean_rev` and `sd_rev`, whose
+/// values are in the same units but not in the same range.
+pub const STAT_ATTRIBUTES: &[&str] = &["units"];
+
+/// Fill value set explicitly on `ds`, converted to `T`, or `None` if it uses
+/// the library default or cannot be converted (e.g. compound datasets).
+pub fn user_fill_value<T: hdf5::H5Type>(ds: &hdf5::Dataset) -> Result<Option<T>> {
+    return fill_value_of(ds, true);
+}
+
+/// Value HDF5 returns for the elements of `ds` never written, converted to
+/// `T`: its fill value, set explicitly or the library default. `None` if
+/// it cannot be converted.
+pub fn fill_value<T: hdf5::H5Type>(ds: &hdf5::Dataset) -> Result<Option<T>> {
+    return fill_value_of(ds, false);
+}
+
+fn fill_value_of<T: hdf5::H5Type>(ds: &hdf5::Dataset, user_only: bool) -> Result<Option<T>> {
+    let mem_type = hdf5::Datatype::from_type::<T>()?;
+    let mut value = std::mem::MaybeUninit::<T>::uninit();
+    let _silence = hdf5::silence_errors();
+    let defined = hdf5::sync::sync(|| unsafe {
+        let dcpl = H5Dget_create_plist(ds.id());
+        if dcpl < 0 {
+            return false;
+        }
+        let mut status = H5D_fill_value_t::H5D_FILL_VALUE_ERROR;
+        let defined = H5Pfill_value_defined(dcpl, &mut status) >= 0
+            && (status == H5D_fill_value_t::H5D_FILL_VALUE_USER_DEFINED
+                || !user_only && status == H5D_fill_value_t::H5D_FILL_VALUE_DEFAULT)
+            && H5Pget_fill_value(dcpl, mem_type.id(), value.as_mut_ptr() as *mut _) >= 0;
+        H5Idec_ref(dcpl);
+        return defined;
+    });
+    return Ok(if defined {
+        Some(unsafe { value.assume_init() })
+    } else {
+        None
+    });
+}
+
+/// Copies the attributes `names` that exist on `from` to `to`, replacing
+/// attributes of the same name. Numeric attributes are converted to `T`, the
+/// element type of `to`, as CF expects of `_FillValue` and `valid_range`;
+/// others (e.g. string `units`) keep their type.
+pub fn copy_attributes<T: hdf5::H5Type>(
+    from: &hdf5::Dataset,
+    to: &hdf5::Dataset,
+    names: &[&str],
+) -> Result<()> {
+    let numeric_type = hdf5::Datatype::from_type::<T>()?;
+    for name in names {
+        let c_name = CString::new(*name).unwrap();
+        let copied = hdf5::sync::sync(|| unsafe {
+            copy_attribute(from.id(), to.id(), &c_name, Some(numeric_type.id()))
+        });
+        if !copied {
+            println!("Could not copy attribute {} of {}", name, from.name());
+        }
+    }
+    return Ok(());
+}
+
+/// Copies every attribute of `from` to `to` as it is, e.g. those of the root
+/// group of a file copied object by object.
+pub fn copy_all_attributes(from: &hdf5::Location, to: &hdf5::Location) -> Result<()> {
+    for name in from.attr_names()? {
+        let c_name = CString::new(name.as_str()).unwrap();
+        let copied =
+            hdf5::sync::sync(|| unsafe { copy_attribute(from.id(), to.id(), &c_name, None) });
+        if !copied {
+            return Err(Error::Invalid(format!(
+                "could not copy attribute {} of {}",
+                name,
+                from.name()
+            )));
+        }
+    }
+    return Ok(());
+}
+
+/// Copies attribute `name` of `from` to `to`, if it exists, converting
+/// numeric values to `numeric_type` if any. Returns whether it is absent or
+/// was copied.
+unsafe fn copy_attribute(
+    from: hid_t,
+    to: hid_t,
+    name: &CString,
+    numeric_type: Option<hid_t>,
+) -> bool {
+    if H5Aexists(from, name.as_ptr()) <= 0 {
+        return true;
+    }
+    let attr = H5Aopen(from, name.as_ptr(), H5P_DEFAULT);
+    if attr < 0 {
+        return false;
+    }
+    let file_type = H5Aget_type(attr);
+    let space = H5Aget_space(attr);
+    let mem_type = match (H5Tget_class(file_type), numeric_type) {
+        (H5T_class_t::H5T_INTEGER, Some(numeric_type))
+        | (H5T_class_t::H5T_FLOAT, Some(numeric_type)) => numeric_type,
+        _ => file_type,
+    };
+    let n_points = H5Sget_simple_extent_npoints(space).max(0) as usize;
+    let mut buf = vec![0u8; n_points * H5Tget_size(mem_type)];
+
+    let mut copied = H5Aread(attr, mem_type, buf.as_mut_ptr() as *mut _) >= 0;
+    if copied {
+        if H5Aexists(to, name.as_ptr()) > 0 {
+            H5Adelete(to, name.as_ptr());
+        }
+        let out = H5Acreate2(to, name.as_ptr(), mem_type, space, H5P_DEFAULT, H5P_DEFAULT);
+        copied = out >= 0 && H5Awrite(out, mem_type, buf.as_ptr() as *const _) >= 0;
+        if out >= 0 {
+            H5Idec_ref(out);
+        }
+        // Variable-length strings were allocated by HDF5 while reading.
+        if H5Tdetect_class(mem_type, H5T_class_t::H5T_VLEN) > 0
+            || H5Tdetect_class(mem_type, H5T_class_t::H5T_STRING) > 0
+        {
+            H5Dvlen_reclaim(mem_type, space, H5P_DEFAULT, buf.as_mut_ptr() as *mut _);
+        }
+    }
+    H5Idec_ref(space);
+    H5Idec_ref(file_type);
+    H5Idec_ref(attr);
+    return copied;
+}
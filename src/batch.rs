@@ -0,0 +1,280 @@
+//! Conversions of many products listed in a manifest (`batch --manifest`),
+//! so a day's granules are converted with one invocation.
+//!
+//! The manifest is a JSON file of named profiles, the conversion flags
+//! shared by several jobs, and of the jobs, each converting one input to an
+//! output directory with a profile:
+//!
+//! ```json
+//! {
+//!     "profiles": {
+//!         "l4a": ["--config", "l4a.json", "--quicklook", "--stac"]
+//!     },
+//!     "jobs": [
+//!         { "input": "granule_001.h5", "profile": "l4a", "outdir": "out/001" },
+//!         { "input": "granule_002.h5", "profile": "l4a", "outdir": "out/002" }
+//!     ]
+//! }
+//! ```
+//!
+//! Each job is a conversion in a process of its own, as run from the
+//! command line, since a conversion holds process-wide state (warnings,
+//! output directory, thread pool). Up to `--jobs` run at once, sharing the
+//! worker threads; the output of each goes to `<input stem>.log` in its
+//! output directory and its report, kept there as `<input stem>.report.json`,
+//! into the consolidated report.
+
+use crate::error::{exit_code, Error, Result};
+use crate::interrupt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Flags set by the batch for each job, which profiles must not give.
+const JOB_FLAGS: &[&str] = &["--input", "--outdir", "--report"];
+
+/// One conversion of the manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub input: String,
+    /// Profile whose flags the conversion runs with, else none.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Directory of the outputs, else the working directory.
+    #[serde(default)]
+    pub outdir: Option<String>,
+}
+
+impl Job {
+    fn dir(&self) -> PathBuf {
+        return PathBuf::from(self.outdir.as_deref().unwrap_or("."));
+    }
+
+    fn stem(&self) -> String {
+        return Path::new(&self.input)
+            .file_stem()
+            .map_or(self.input.clone(), |stem| {
+                return stem.to_string_lossy().into_owned();
+            });
+    }
+
+    /// File the output of the conversion goes to.
+    pub fn log_path(&self) -> PathBuf {
+        return self.dir().join(format!("{}.log", self.stem()));
+    }
+
+    /// Report of the conversion.
+    pub fn report_path(&self) -> PathBuf {
+        return self.dir().join(format!("{}.report.json", self.stem()));
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Flags of conversions by profile name.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    /// Jobs of a batch; manifests given to `watch` need only profiles.
+    #[serde(default)]
+    pub jobs: Vec<Job>,
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`, checking that the profiles of its jobs
+    /// exist and leave the flags of the batch alone, and that no two jobs
+    /// write the same log and report.
+    pub fn read(path: &Path) -> Result<Manifest> {
+        let text = std::fs::read_to_string(path)?;
+        return Manifest::parse(&text);
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest> {
+        let manifest: Manifest = serde_json::from_str(text)?;
+        for (name, args) in manifest.profiles.iter() {
+            if let Some(flag) = args.iter().find(|arg| JOB_FLAGS.contains(&arg.as_str())) {
+                return Err(Error::Invalid(format!(
+                    "profile {} sets {}, which the batch sets for each job",
+                    name, flag
+                )));
+            }
+        }
+        let mut reports = vec![];
+        for job in manifest.jobs.iter() {
+            if let Some(profile) = &job.profile {
+                if !manifest.profiles.contains_key(profile) {
+                    return Err(Error::Invalid(format!(
+                        "job {} uses the unknown profile {}",
+                        job.input, profile
+                    )));
+                }
+            }
+            let report = job.report_path();
+            if reports.contains(&report) {
+                return Err(Error::Invalid(format!(
+                    "two jobs would write {}: give them other output directories",
+                    report.display()
+                )));
+            }
+            reports.push(report);
+        }
+        return Ok(manifest);
+    }
+
+    /// Command line of `job`, without the program name, with `threads`
+    /// worker threads unless its profile sets `--jobs`.
+    pub fn arguments(&self, job: &Job, threads: usize) -> Vec<String> {
+        let profile = job
+            .profile
+            .as_ref()
+            .and_then(|profile| self.profiles.get(profile))
+            .cloned()
+            .unwrap_or_default();
+        let mut args = vec!["--input".to_owned(), job.input.clone()];
+        if let Some(outdir) = &job.outdir {
+            args.push("--outdir".to_owned());
+            args.push(outdir.clone());
+        }
+        args.push("--report".to_owned());
+        args.push(job.report_path().to_string_lossy().into_owned());
+        if !profile.iter().any(|arg| arg == "--jobs") {
+            args.push("--jobs".to_owned());
+            args.push(threads.to_string());
+        }
+        args.extend(profile);
+        return args;
+    }
+}
+
+/// Outcome of one job.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    #[serde(flatten)]
+    pub job: Job,
+    pub log: String,
+    /// Exit code of the conversion, see [`crate::error::exit_code`].
+    pub exit_code: i32,
+    /// Why the conversion could not be run, if it was not.
+    pub error: Option<String>,
+    pub seconds: f64,
+    /// Report of the conversion, if it wrote one.
+    pub report: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BatchReport {
+    pub interrupted: bool,
+    /// Jobs run, in the order of the manifest.
+    pub jobs: Vec<JobRecord>,
+    /// Jobs not started, after Ctrl-C.
+    pub not_started: Vec<String>,
+    /// [`exit_code::OK`] if every job was, else [`exit_code::PARTIAL`], or
+    /// [`exit_code::INTERRUPTED`].
+    pub exit_code: i32,
+}
+
+impl BatchReport {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        return Ok(());
+    }
+}
+
+/// Runs the conversion of `job` with the executable at `exe`, with
+/// `threads` worker threads unless its profile sets them.
+pub fn run_job(manifest: &Manifest, job: &Job, exe: &Path, threads: usize) -> JobRecord {
+    let start = Instant::now();
+    let mut record = JobRecord {
+        job: job.clone(),
+        log: job.log_path().to_string_lossy().into_owned(),
+        exit_code: exit_code::IO,
+        error: None,
+        seconds: 0f64,
+        report: None,
+    };
+    // A report left by an earlier run must not pass for this one's.
+    let _ = std::fs::remove_file(job.report_path());
+    let status = std::fs::create_dir_all(job.dir())
+        .and_then(|()| std::fs::File::create(job.log_path()))
+        .and_then(|log| {
+            let stderr = log.try_clone()?;
+            return Command::new(exe)
+                .args(manifest.arguments(job, threads))
+                .stdin(Stdio::null())
+                .stdout(log)
+                .stderr(stderr)
+                .status();
+        });
+    match status {
+        Ok(status) => record.exit_code = status.code().unwrap_or(exit_code::INTERRUPTED),
+        Err(err) => record.error = Some(err.to_string()),
+    }
+    record.report = std::fs::read_to_string(job.report_path())
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok());
+    record.seconds = start.elapsed().as_secs_f64();
+    return record;
+}
+
+/// Runs the jobs of `manifest` with the executable at `exe`, up to `jobs`
+/// at once, each with an equal share of `threads` worker threads. Jobs
+/// still waiting are not started after Ctrl-C; running ones stop as they
+/// would on their own.
+pub fn run(manifest: &Manifest, exe: &Path, jobs: usize, threads: usize) -> BatchReport {
+    let total = manifest.jobs.len();
+    let jobs = jobs.max(1).min(total.max(1));
+    let threads = (threads / jobs).max(1);
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let records: Mutex<Vec<Option<JobRecord>>> = Mutex::new((0..total).map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                if interrupt::is_interrupted() {
+                    return;
+                }
+                let ii = next.fetch_add(1, Ordering::SeqCst);
+                let job = match manifest.jobs.get(ii) {
+                    Some(job) => job,
+                    None => return,
+                };
+                println!("Converting {} ({} of {})", job.input, ii + 1, total);
+                let record = run_job(manifest, job, exe, threads);
+                println!(
+                    "Finished {} with exit code {} in {:.1} s ({} of {} done), log in {}",
+                    job.input,
+                    record.exit_code,
+                    record.seconds,
+                    done.fetch_add(1, Ordering::SeqCst) + 1,
+                    total,
+                    record.log
+                );
+                records.lock().unwrap()[ii] = Some(record);
+            });
+        }
+    });
+    let mut report = BatchReport::default();
+    for (job, record) in manifest
+        .jobs
+        .iter()
+        .zip(records.into_inner().unwrap().into_iter())
+    {
+        match record {
+            Some(record) => report.jobs.push(record),
+            None => report.not_started.push(job.input.clone()),
+        }
+    }
+    report.interrupted = interrupt::is_interrupted();
+    report.exit_code = match report.interrupted {
+        true => exit_code::INTERRUPTED,
+        false if report.jobs.iter().all(|job| job.exit_code == exit_code::OK) => exit_code::OK,
+        false => exit_code::PARTIAL,
+    };
+    return report;
+}
@@ -0,0 +1,362 @@
+//! Strips of pixels and their layout in GDAL buffers.
+//!
+//! Strips are read from HDF5 as row-major `(lines, xsize)` data, while GDAL
+//! sizes buffers and windows as `(xsize, lines)`, columns first. Mixing the
+//! two up transposes the output without any error, so every conversion
+//! between them goes through [`RasterBlock`], which also cuts strips into
+//! the tiles of tiled outputs.
+
+use crate::error::{Error, Result};
+
+/// Order of the pixels in the buffers handed to the raster driver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BandOrder {
+    /// Rows one after the other, as GDAL expects.
+    RowMajor,
+    /// Columns one after the other, for drivers that expect transposed
+    /// buffers.
+    ColumnMajor,
+}
+
+impl Default for BandOrder {
+    fn default() -> Self {
+        return BandOrder::RowMajor;
+    }
+}
+
+impl BandOrder {
+    pub fn parse(name: &str) -> std::result::Result<BandOrder, String> {
+        return match name {
+            "row-major" => Ok(BandOrder::RowMajor),
+            "column-major" => Ok(BandOrder::ColumnMajor),
+            _ => Err(format!(
+                "unknown band order '{}' (expected row-major or column-major)",
+                name
+            )),
+        };
+    }
+}
+
+/// How the bands of a multi-band GeoTIFF are stored (`INTERLEAVE`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interleave {
+    /// Each band in blocks of its own.
+    Band,
+    /// The values of every band of a pixel next to each other.
+    Pixel,
+}
+
+impl Interleave {
+    pub fn parse(name: &str) -> std::result::Result<Interleave, String> {
+        return match name.to_ascii_lowercase().as_str() {
+            "band" => Ok(Interleave::Band),
+            "pixel" => Ok(Interleave::Pixel),
+            _ => Err(format!(
+                "unknown interleave '{}' (expected band or pixel)",
+                name
+            )),
+        };
+    }
+
+    /// Value of the `INTERLEAVE` creation option.
+    pub fn option_value(self) -> &'static str {
+        return match self {
+            Interleave::Band => "BAND",
+            Interleave::Pixel => "PIXEL",
+        };
+    }
+}
+
+/// Internal layout of new GeoTIFF outputs, instead of the template's.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BlockLayout {
+    pub interleave: Option<Interleave>,
+    /// Tile width and height; outputs are tiled when given.
+    pub block_size: Option<(usize, usize)>,
+}
+
+impl BlockLayout {
+    /// Parses a tile size as `WIDTHxHEIGHT`, or a single side for square
+    /// tiles. GeoTIFF tile sides are multiples of 16.
+    pub fn parse_block_size(text: &str) -> std::result::Result<(usize, usize), String> {
+        let (width, height) = text
+            .split_once(|c| c == 'x' || c == 'X')
+            .unwrap_or((text, text));
+        let side = |side: &str| -> std::result::Result<usize, String> {
+            return match side.trim().parse::<usize>() {
+                Ok(side) if side > 0 && side % 16 == 0 => Ok(side),
+                _ => Err(format!(
+                    "invalid block size '{}', expected WIDTHxHEIGHT in multiples of 16, e.g. 512x512",
+                    text
+                )),
+            };
+        };
+        return Ok((side(width)?, side(height)?));
+    }
+
+    pub fn is_default(self) -> bool {
+        return self == BlockLayout::default();
+    }
+
+    /// Creation options of the layout.
+    pub fn creation_options(self) -> Vec<(&'static str, String)> {
+        let mut options = vec![];
+        if let Some((block_xsize, block_ysize)) = self.block_size {
+            options.push(("TILED", "YES".to_owned()));
+            options.push(("BLOCKXSIZE", block_xsize.to_string()));
+            options.push(("BLOCKYSIZE", block_ysize.to_string()));
+        }
+        if let Some(interleave) = self.interleave {
+            options.push(("INTERLEAVE", interleave.option_value().to_owned()));
+        }
+        return options;
+    }
+}
+
+/// `lines` rows of `xsize` pixels, stored row-major.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RasterBlock<T> {
+    vals: Vec<T>,
+    xsize: usize,
+    lines: usize,
+}
+
+impl<T: Copy> RasterBlock<T> {
+    /// Wraps row-major `vals`, checking they hold `lines` rows of `xsize`.
+    pub fn from_rows(vals: Vec<T>, xsize: usize, lines: usize) -> Result<RasterBlock<T>> {
+        if vals.len() != xsize * lines {
+            return Err(Error::Invalid(format!(
+                "block of {} values cannot hold {} rows of {} pixels",
+                vals.len(),
+                lines,
+                xsize
+            )));
+        }
+        return Ok(RasterBlock { vals, xsize, lines });
+    }
+
+    /// Rebuilds a block from values laid out in `order`, as read from a
+    /// driver window of `xsize` by `lines`.
+    pub fn from_ordered(
+        vals: Vec<T>,
+        xsize: usize,
+        lines: usize,
+        order: BandOrder,
+    ) -> Result<RasterBlock<T>> {
+        let block = RasterBlock::from_rows(vals, xsize, lines)?;
+        return Ok(match order {
+            BandOrder::RowMajor => block,
+            BandOrder::ColumnMajor => RasterBlock {
+                vals: transpose(&block.vals, lines, xsize),
+                xsize,
+                lines,
+            },
+        });
+    }
+
+    pub fn xsize(&self) -> usize {
+        return self.xsize;
+    }
+
+    pub fn lines(&self) -> usize {
+        return self.lines;
+    }
+
+    /// Size as GDAL expects it: (columns, rows).
+    pub fn gdal_size(&self) -> (usize, usize) {
+        return (self.xsize, self.lines);
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> T {
+        return self.vals[row * self.xsize + col];
+    }
+
+    pub fn into_rows(self) -> Vec<T> {
+        return self.vals;
+    }
+
+    /// Splits the block into tiles of up to `tile_xsize` by `tile_lines`
+    /// pixels, row of tiles by row of tiles, each with its (column, row)
+    /// offset in the block. Tiles on the right and bottom edges are cut short.
+    pub fn tiles(
+        &self,
+        (tile_xsize, tile_lines): (usize, usize),
+    ) -> Vec<((usize, usize), RasterBlock<T>)> {
+        let mut tiles = vec![];
+        for y in (0..self.lines).step_by(tile_lines.max(1)) {
+            let lines = tile_lines.min(self.lines - y);
+            for x in (0..self.xsize).step_by(tile_xsize.max(1)) {
+                let xsize = tile_xsize.min(self.xsize - x);
+                let mut vals = Vec::with_capacity(xsize * lines);
+                for row in y..y + lines {
+                    let start = row * self.xsize + x;
+                    vals.extend_from_slice(&self.vals[start..start + xsize]);
+                }
+                tiles.push(((x, y), RasterBlock { vals, xsize, lines }));
+            }
+        }
+        return tiles;
+    }
+
+    /// The values laid out in `order`.
+    pub fn into_ordered(self, order: BandOrder) -> Vec<T> {
+        return match order {
+            BandOrder::RowMajor => self.vals,
+            BandOrder::ColumnMajor => transpose(&self.vals, self.xsize, self.lines),
+        };
+    }
+
+    /// GDAL buffer of the block, with the pixels laid out in `order`.
+    #[cfg(feature = "gdal")]
+    pub fn into_gdal_buffer(self, order: BandOrder) -> gdal::raster::Buffer<T>
+    where
+        T: gdal::raster::GdalType,
+    {
+        let size = self.gdal_size();
+        return gdal::raster::Buffer::new(size, self.into_ordered(order));
+    }
+}
+
+/// Strip height to pull for an output with blocks of `block_lines` rows:
+/// `n_lines` rounded down to whole rows of blocks, but at least one, so that
+/// no block is written in several parts.
+pub fn aligned_lines(n_lines: usize, block_lines: usize) -> usize {
+    if block_lines <= 1 {
+        return n_lines;
+    }
+    return (n_lines / block_lines).max(1) * block_lines;
+}
+
+/// Transposes `vals`, stored as `outer` runs of `inner` values, into `inner`
+/// runs of `outer` values.
+fn transpose<T: Copy>(vals: &[T], inner: usize, outer: usize) -> Vec<T> {
+    let mut out = Vec::with_capacity(vals.len());
+    for ii in 0..inner {
+        for oo in 0..outer {
+            out.push(vals[oo * inner + ii]);
+        }
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2x3-pixel checkerboard cells over a non-square grid, numbered so every
+    /// pixel also carries its own position.
+    fn checkerboard(xsize: usize, lines: usize) -> Vec<u32> {
+        let mut vals = vec![];
+        for row in 0..lines {
+            for col in 0..xsize {
+                let cell = ((row / 2) + (col / 3)) % 2;
+                vals.push((cell * 100_000 + row * 100 + col) as u32);
+            }
+        }
+        return vals;
+    }
+
+    #[test]
+    fn row_major_keeps_rows() {
+        let block = RasterBlock::from_rows(checkerboard(7, 4), 7, 4).unwrap();
+        assert_eq!(block.gdal_size(), (7, 4));
+        assert_eq!(block.get(0, 0), 0);
+        assert_eq!(block.get(0, 3), 100_003);
+        assert_eq!(block.get(2, 0), 100_200);
+        assert_eq!(block.get(3, 6), 100_306);
+        assert_eq!(block.into_ordered(BandOrder::RowMajor), checkerboard(7, 4));
+    }
+
+    #[test]
+    fn column_major_transposes_and_back() {
+        let block = RasterBlock::from_rows(checkerboard(5, 3), 5, 3).unwrap();
+        let ordered = block.clone().into_ordered(BandOrder::ColumnMajor);
+        // Column 1 comes after all 3 rows of column 0.
+        assert_eq!(&ordered[..4], &[0, 100, 100_200, 1]);
+        let back = RasterBlock::from_ordered(ordered, 5, 3, BandOrder::ColumnMajor).unwrap();
+        assert_eq!(back, block);
+    }
+
+    #[test]
+    fn checkerboard_cells_survive_round_trip() {
+        for &(xsize, lines) in &[(1, 1), (6, 4), (13, 9), (4, 13)] {
+            let block = RasterBlock::from_rows(checkerboard(xsize, lines), xsize, lines).unwrap();
+            for &order in &[BandOrder::RowMajor, BandOrder::ColumnMajor] {
+                let ordered = block.clone().into_ordered(order);
+                let back = RasterBlock::from_ordered(ordered, xsize, lines, order).unwrap();
+                for row in 0..lines {
+                    for col in 0..xsize {
+                        let cell = ((row / 2) + (col / 3)) % 2;
+                        assert_eq!(back.get(row, col) as usize / 100_000, cell);
+                        assert_eq!(back.get(row, col) as usize % 100_000, row * 100 + col);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_cover_the_block_once() {
+        let block = RasterBlock::from_rows(checkerboard(13, 9), 13, 9).unwrap();
+        let tiles = block.tiles((5, 4));
+        // 3 columns by 3 rows of tiles, the last ones cut to 3 columns and 1 row.
+        assert_eq!(tiles.len(), 9);
+        assert_eq!(tiles[2].0, (10, 0));
+        assert_eq!(tiles[2].1.gdal_size(), (3, 4));
+        assert_eq!(tiles[8].0, (10, 8));
+        assert_eq!(tiles[8].1.gdal_size(), (3, 1));
+
+        let mut seen = vec![false; 13 * 9];
+        for ((x, y), tile) in &tiles {
+            for row in 0..tile.lines() {
+                for col in 0..tile.xsize() {
+                    assert_eq!(tile.get(row, col), block.get(y + row, x + col));
+                    assert!(!seen[(y + row) * 13 + x + col]);
+                    seen[(y + row) * 13 + x + col] = true;
+                }
+            }
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn aligned_lines_are_whole_block_rows() {
+        assert_eq!(aligned_lines(100, 1), 100);
+        assert_eq!(aligned_lines(1200, 512), 1024);
+        assert_eq!(aligned_lines(100, 512), 512);
+    }
+
+    #[test]
+    fn rejects_wrong_length() {
+        assert!(RasterBlock::from_rows(vec![0u8; 10], 4, 3).is_err());
+    }
+
+    #[test]
+    fn block_layout_options() {
+        assert_eq!(BlockLayout::parse_block_size("512x256"), Ok((512, 256)));
+        assert_eq!(BlockLayout::parse_block_size("256"), Ok((256, 256)));
+        assert!(BlockLayout::parse_block_size("500x512").is_err());
+        assert!(BlockLayout::parse_block_size("0").is_err());
+        assert_eq!(Interleave::parse("PIXEL"), Ok(Interleave::Pixel));
+        assert!(BlockLayout::default().creation_options().is_empty());
+
+        let layout = BlockLayout {
+            interleave: Some(Interleave::Band),
+            block_size: Some((256, 512)),
+        };
+        let options = layout.creation_options();
+        let options: Vec<(&str, &str)> = options
+            .iter()
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect();
+        assert_eq!(
+            options,
+            vec![
+                ("TILED", "YES"),
+                ("BLOCKXSIZE", "256"),
+                ("BLOCKYSIZE", "512"),
+                ("INTERLEAVE", "BAND")
+            ]
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! Change detection between two groups (`change` subcommand), e.g. two
+//! epochs of a product in one file or the same group in two files.
+//!
+//! From the count, mean and sd of both groups, recomputed from their
+//! statistic datasets strip by strip, three outputs are written: the
+//! difference of the means (B - A), their relative change ((B - A) / |A|)
+//! and a significance mask from Welch's t-test of the means, which does not
+//! assume equal variances or counts.
+
+use crate::error::Result;
+use crate::raster::RasterSink;
+use crate::stats::StatNames;
+use crate::verify::read_group_rows;
+use crate::{interrupt, status};
+use serde::Serialize;
+use std::cell::Cell;
+
+/// Names of the outputs of a change detection.
+pub const CHANGE_OUTPUTS: [&str; 3] = ["diff", "rel_change", "significant"];
+
+/// Value of the significance mask where the test cannot be made: a pixel
+/// with fewer than two observations, or no mean or sd, in either group.
+pub const UNTESTED: u8 = 255;
+
+/// A group compared, with how its datasets are read.
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeSide<'a> {
+    pub file: &'a hdf5::File,
+    pub group: &'a str,
+    /// Whether the rows of its file run opposite to the outputs.
+    pub flip: bool,
+    /// As given by [`crate::stats::is_wide`].
+    pub wide: bool,
+}
+
+/// Summary of a change detection over the pixels tested.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChangeSummary {
+    pub group_a: String,
+    pub group_b: String,
+    /// Significance level of the test.
+    pub alpha: f64,
+    /// Pixels with a difference of the means.
+    pub compared: u64,
+    /// Pixels whose difference was tested.
+    pub tested: u64,
+    /// Tested pixels whose mean increased or decreased significantly.
+    pub increased: u64,
+    pub decreased: u64,
+    /// Mean difference (B - A) over the compared pixels.
+    pub mean_diff: f64,
+}
+
+/// Change of one strip: the difference, relative change and significance
+/// mask of each pixel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeRows {
+    pub diff: Vec<f32>,
+    pub rel_change: Vec<f32>,
+    pub significant: Vec<u8>,
+}
+
+/// Change from A to B of pixels with the given counts, means and sds. The
+/// mask is 1 where Welch's t-test rejects equal means at level `alpha`, 0
+/// where it does not and [`UNTESTED`] where it cannot be made.
+pub fn change_rows(
+    (count_a, mean_a, sd_a): (&[u32], &[f32], &[f32]),
+    (count_b, mean_b, sd_b): (&[u32], &[f32], &[f32]),
+    alpha: f64,
+) -> ChangeRows {
+    let mut rows = ChangeRows::default();
+    for ii in 0..mean_a.len() {
+        let (a, b) = (mean_a[ii] as f64, mean_b[ii] as f64);
+        let diff = b - a;
+        rows.diff.push(diff as f32);
+        rows.rel_change.push(match a != 0f64 {
+            true => (diff / a.abs()) as f32,
+            false => f32::NAN,
+        });
+        let p_value = welch_p_value(
+            (a, sd_a[ii] as f64, count_a[ii]),
+            (b, sd_b[ii] as f64, count_b[ii]),
+        );
+        rows.significant.push(match p_value {
+            Some(p_value) => (p_value < alpha) as u8,
+            None => UNTESTED,
+        });
+    }
+    return rows;
+}
+
+/// Two-sided p-value of Welch's t-test of the means of two samples given as
+/// (mean, sd, count), or `None` without two observations, a mean and a
+/// finite sd in each.
+pub fn welch_p_value(
+    (mean_a, sd_a, n_a): (f64, f64, u32),
+    (mean_b, sd_b, n_b): (f64, f64, u32),
+) -> Option<f64> {
+    let valid_sd = |sd: f64| sd.is_finite() && sd >= 0f64;
+    if n_a < 2 || n_b < 2 || !(mean_a - mean_b).is_finite() || !valid_sd(sd_a) || !valid_sd(sd_b) {
+        return None;
+    }
+    let (n_a, n_b) = (n_a as f64, n_b as f64);
+    let (var_a, var_b) = (sd_a * sd_a / n_a, sd_b * sd_b / n_b);
+    let se2 = var_a + var_b;
+    if se2 == 0f64 {
+        // Both samples are constant: any difference is certain.
+        return Some(if mean_a == mean_b { 1f64 } else { 0f64 });
+    }
+    let t = (mean_b - mean_a) / se2.sqrt();
+    // Welch–Satterthwaite degrees of freedom.
+    let df = se2 * se2 / (var_a * var_a / (n_a - 1f64) + var_b * var_b / (n_b - 1f64));
+    if !t.is_finite() || !df.is_finite() {
+        return None;
+    }
+    return Some(reg_inc_beta(df / 2f64, 0.5, df / (df + t * t)));
+}
+
+/// Natural logarithm of the gamma function of `x` > 0 (Lanczos).
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000000000190015;
+    for (ii, coefficient) in COEFFICIENTS.iter().enumerate() {
+        series += coefficient / (x + 1f64 + ii as f64);
+    }
+    return -tmp + (2.5066282746310005 * series / x).ln();
+}
+
+/// Regularized incomplete beta function I_x(a, b), from its continued
+/// fraction.
+fn reg_inc_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0f64 {
+        return 0f64;
+    }
+    if x >= 1f64 {
+        return 1f64;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1f64 - x).ln()).exp();
+    // The fraction converges quickly below the mean of the distribution.
+    if x < (a + 1f64) / (a + b + 2f64) {
+        return front * beta_fraction(a, b, x) / a;
+    }
+    return 1f64 - front * beta_fraction(b, a, 1f64 - x) / b;
+}
+
+/// Continued fraction of the incomplete beta function (modified Lentz).
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let clamp = |value: f64| if value.abs() < TINY { TINY } else { value };
+    let mut c = 1f64;
+    let mut d = 1f64 / clamp(1f64 - (a + b) * x / (a + 1f64));
+    let mut fraction = d;
+    for m in 1..300 {
+        let m = m as f64;
+        let even = m * (b - m) * x / ((a + 2f64 * m - 1f64) * (a + 2f64 * m));
+        d = 1f64 / clamp(1f64 + even * d);
+        c = clamp(1f64 + even / c);
+        fraction *= d * c;
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2f64 * m) * (a + 2f64 * m + 1f64));
+        d = 1f64 / clamp(1f64 + odd * d);
+        c = clamp(1f64 + odd / c);
+        let delta = d * c;
+        fraction *= delta;
+        if (delta - 1f64).abs() < 1e-14 {
+            break;
+        }
+    }
+    return fraction;
+}
+
+/// Writes the change from group `a` to group `b` on a grid of `size`
+/// pixels, in strips of `n_lines` rows, to the sinks of [`CHANGE_OUTPUTS`]
+/// given (`None` for outputs kept from an earlier run). Each output is
+/// written in its own pass over both groups.
+#[allow(clippy::too_many_arguments)]
+pub fn write_change(
+    a: ChangeSide,
+    b: ChangeSide,
+    stat_names: &StatNames,
+    field: Option<&str>,
+    (xsize, ysize): (usize, usize),
+    min_count: u32,
+    alpha: f64,
+    n_lines: usize,
+    diff: Option<&mut dyn RasterSink<f32>>,
+    rel_change: Option<&mut dyn RasterSink<f32>>,
+    significant: Option<&mut dyn RasterSink<u8>>,
+) -> Result<ChangeSummary> {
+    let n_passes = [diff.is_some(), rel_change.is_some(), significant.is_some()]
+        .iter()
+        .filter(|&&some| some)
+        .count()
+        .max(1);
+    let pass = Cell::new(0);
+    let mut summary = ChangeSummary {
+        group_a: a.group.to_owned(),
+        group_b: b.group.to_owned(),
+        alpha,
+        ..ChangeSummary::default()
+    };
+    let mut diff_sum = 0f64;
+    let mut read_change = |yy: usize, lines: usize, tally: bool| -> Result<ChangeRows> {
+        interrupt::check()?;
+        status::set_progress((pass.get() as f32 + yy as f32 / ysize as f32) / n_passes as f32);
+        let read = |side: ChangeSide| {
+            return read_group_rows(
+                side.file,
+                side.group,
+                stat_names,
+                field,
+                (xsize, ysize),
+                side.flip,
+                (min_count, side.wide),
+                yy,
+                lines,
+            );
+        };
+        let (count_a, mean_a, sd_a) = read(a)?;
+        let (count_b, mean_b, sd_b) = read(b)?;
+        let rows = change_rows(
+            (&count_a, &mean_a, &sd_a),
+            (&count_b, &mean_b, &sd_b),
+            alpha,
+        );
+        if tally {
+            for (&diff, &significant) in rows.diff.iter().zip(rows.significant.iter()) {
+                if diff.is_finite() {
+                    summary.compared += 1;
+                    diff_sum += diff as f64;
+                }
+                if significant == UNTESTED {
+                    continue;
+                }
+                summary.tested += 1;
+                if significant == 1 && diff > 0f32 {
+                    summary.increased += 1;
+                } else if significant == 1 {
+                    summary.decreased += 1;
+                }
+            }
+        }
+        return Ok(rows);
+    };
+
+    // The summary is tallied in the first pass made.
+    let mut tallied = false;
+    if let Some(sink) = significant {
+        sink.write_strips(xsize, ysize, n_lines, &mut |yy, lines| {
+            return Ok(read_change(yy, lines, true)?.significant);
+        })?;
+        tallied = true;
+        pass.set(pass.get() + 1);
+    }
+    if let Some(sink) = diff {
+        sink.write_strips(xsize, ysize, n_lines, &mut |yy, lines| {
+            return Ok(read_change(yy, lines, !tallied)?.diff);
+        })?;
+        tallied = true;
+        pass.set(pass.get() + 1);
+    }
+    if let Some(sink) = rel_change {
+        sink.write_strips(xsize, ysize, n_lines, &mut |yy, lines| {
+            return Ok(read_change(yy, lines, !tallied)?.rel_change);
+        })?;
+        tallied = true;
+    }
+    if !tallied {
+        for yy in (0..ysize).step_by(n_lines.max(1)) {
+            read_change(yy, n_lines.min(ysize - yy), true)?;
+        }
+    }
+    summary.mean_diff = diff_sum / summary.compared.max(1) as f64;
+    return Ok(summary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn ln_gamma_matches_factorials_and_half_integers() {
+        assert_close(ln_gamma(1f64), 0f64, 1e-9);
+        assert_close(ln_gamma(2f64), 0f64, 1e-9);
+        assert_close(ln_gamma(5f64), 24f64.ln(), 1e-9);
+        assert_close(ln_gamma(10f64), 362880f64.ln(), 1e-9);
+        let sqrt_pi = std::f64::consts::PI.sqrt();
+        assert_close(ln_gamma(0.5), sqrt_pi.ln(), 1e-9);
+        assert_close(ln_gamma(1.5), (sqrt_pi / 2f64).ln(), 1e-9);
+        assert_close(ln_gamma(2.5), (3f64 * sqrt_pi / 4f64).ln(), 1e-9);
+    }
+
+    #[test]
+    fn reg_inc_beta_matches_closed_forms() {
+        for &x in [0.1, 0.3, 0.5, 0.9].iter() {
+            // I_x(1, 1) = x and I_x(a, 1) = x^a.
+            assert_close(reg_inc_beta(1f64, 1f64, x), x, 1e-9);
+            assert_close(reg_inc_beta(3f64, 1f64, x), x.powi(3), 1e-9);
+        }
+        // Symmetric about 1/2 when a = b.
+        assert_close(reg_inc_beta(4.5, 4.5, 0.5), 0.5, 1e-9);
+        assert_eq!(reg_inc_beta(2f64, 3f64, 0f64), 0f64);
+        assert_eq!(reg_inc_beta(2f64, 3f64, 1f64), 1f64);
+    }
+
+    #[test]
+    fn welch_p_values_match_reference_values() {
+        // Two-sided p-values of the Student t distribution with the
+        // Welch-Satterthwaite degrees of freedom, computed with mpmath at 40
+        // digits.
+        let cases = [
+            ((10f64, 2f64, 20), (12f64, 3f64, 25), 0.010686816561632886),
+            ((5f64, 1f64, 5), (5.5, 1.5, 8), 0.4862707060825453),
+            ((100f64, 15f64, 3), (130f64, 20f64, 4), 0.0728198928533927),
+            ((0f64, 1f64, 1000), (0.2, 1f64, 1000), 8.178887881530014e-6),
+        ];
+        for &(a, b, expected) in cases.iter() {
+            assert_close(welch_p_value(a, b).unwrap(), expected, 1e-10);
+            // The test is symmetric in the samples.
+            assert_close(welch_p_value(b, a).unwrap(), expected, 1e-10);
+        }
+    }
+
+    #[test]
+    fn welch_p_value_needs_two_observations_and_finite_stats() {
+        let a = (10f64, 2f64, 20);
+        assert_eq!(welch_p_value(a, (12f64, 3f64, 1)), None);
+        assert_eq!(welch_p_value(a, (f64::NAN, 3f64, 25)), None);
+        assert_eq!(welch_p_value(a, (12f64, f64::NAN, 25)), None);
+        assert_eq!(welch_p_value(a, (12f64, f64::INFINITY, 25)), None);
+        assert_eq!(welch_p_value(a, (12f64, -1f64, 25)), None);
+        assert_eq!(welch_p_value((1f64, 0f64, 5), (1f64, 0f64, 5)), Some(1f64));
+        assert_eq!(welch_p_value((1f64, 0f64, 5), (2f64, 0f64, 5)), Some(0f64));
+    }
+
+    #[test]
+    fn change_rows_marks_pixels_without_finite_sds_untested() {
+        let rows = change_rows(
+            (
+                &[20, 20, 1],
+                &[10f32, 10f32, 10f32],
+                &[2f32, f32::NAN, 2f32],
+            ),
+            (&[25, 25, 25], &[12f32, 12f32, 12f32], &[3f32, 3f32, 3f32]),
+            0.05,
+        );
+        assert_eq!(rows.significant, vec![1, UNTESTED, UNTESTED]);
+        assert_eq!(rows.diff, vec![2f32, 2f32, 2f32]);
+        assert_eq!(rows.rel_change, vec![0.2, 0.2, 0.2]);
+    }
+}
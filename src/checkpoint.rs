@@ -0,0 +1,74 @@
+//! Record of completed steps, used to resume an interrupted run.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(skip)]
+    path: PathBuf,
+    completed: BTreeSet<String>,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint at `path`, or starts an empty one when the file
+    /// does not exist yet.
+    pub fn load(path: &Path) -> Result<Checkpoint> {
+        let mut checkpoint: Checkpoint = if path.exists() {
+            let file = std::fs::File::open(path)?;
+            serde_json::from_reader(file).map_err(|err| {
+                return Error::Invalid(format!(
+                    "checkpoint {} is not valid ({}); remove it to start over",
+                    path.display(),
+                    err
+                ));
+            })?
+        } else {
+            Checkpoint::default()
+        };
+        checkpoint.path = path.to_owned();
+        return Ok(checkpoint);
+    }
+
+    pub fn empty(path: &Path) -> Checkpoint {
+        return Checkpoint {
+            path: path.to_owned(),
+            completed: BTreeSet::new(),
+        };
+    }
+
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    pub fn is_done(&self, step: &str) -> bool {
+        return self.completed.contains(step);
+    }
+
+    /// Marks `step` as completed and persists the checkpoint immediately.
+    pub fn mark_done(&mut self, step: String) -> Result<()> {
+        self.completed.insert(step);
+        return self.save();
+    }
+
+    /// Writes the checkpoint next to its path and renames it into place, so
+    /// a run killed while saving leaves the previous checkpoint whole.
+    pub fn save(&self) -> Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let file = std::fs::File::create(&tmp_path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        return Ok(());
+    }
+
+    /// Removes the checkpoint file after a run that finished every step.
+    pub fn remove(&self) -> Result<()> {
+        if self.path.exists() {
+            std::fs::remove_file(&self.path)?;
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,238 @@
+//! Blocks of a dataset read in output order, for library users plugging the
+//! reader into their own sinks (e.g. Arrow or image crates) instead of the
+//! raster backends.
+//!
+//! Blocks are the strips, or row blocks of rows too wide for the memory
+//! limit, of [`chunking::blocks`], with their rows flipped as for the
+//! conversion; each is read only when the iterator gets to it.
+
+use crate::attrs;
+use crate::chunking::{self, Block};
+use crate::compound::{self, FieldChunks};
+use crate::error::{Error, Result};
+use crate::reverse::{ChunkReader, HdfChunks};
+use crate::valid_range::RangeValue;
+use ndarray::{s, Array1, Array2};
+use std::marker::PhantomData;
+
+/// An owned dataset read as plain values, through `field` for compound
+/// datasets.
+pub struct DatasetReader {
+    ds: hdf5::Dataset,
+    field: Option<String>,
+}
+
+impl DatasetReader {
+    /// Fails if `ds` is compound and `field` is not one of its fields.
+    pub fn new(ds: hdf5::Dataset, field: Option<&str>) -> Result<DatasetReader> {
+        let field = match compound::field_reader(&ds, field)? {
+            Some(_) => field.map(str::to_owned),
+            None => None,
+        };
+        return Ok(DatasetReader { ds, field });
+    }
+}
+
+impl<T: hdf5::H5Type> ChunkReader<T> for DatasetReader {
+    type Error = Error;
+
+    fn read_range(&self, start: usize, end: usize) -> Result<Array1<T>> {
+        return match &self.field {
+            Some(field) => FieldChunks::new(&self.ds, field)?.read_range(start, end),
+            None => HdfChunks(&self.ds).read_range(start, end),
+        };
+    }
+}
+
+/// Yields each block of an `xsize` by `ysize` grid, top to bottom in output
+/// order, with its values as a (lines, cols) array. Values equal to the
+/// nodata value, or NaN, can be replaced by one of the caller's.
+pub struct ChunkIterator<T, R = DatasetReader> {
+    reader: R,
+    xsize: usize,
+    ysize: usize,
+    flip: bool,
+    nodata: Option<f64>,
+    replacement: Option<T>,
+    blocks: Box<dyn Iterator<Item = Block> + Send>,
+    _values: PhantomData<T>,
+}
+
+impl<T: hdf5::H5Type + RangeValue> ChunkIterator<T> {
+    /// Blocks of `ds`, a flat or 2-d dataset of `xsize * ysize` elements,
+    /// read through `field` if it is compound. The nodata value is its fill
+    /// value, if set.
+    pub fn of_dataset(
+        ds: hdf5::Dataset,
+        field: Option<&str>,
+        (xsize, ysize): (usize, usize),
+        flip: bool,
+        n_lines: usize,
+        block_cols: usize,
+    ) -> Result<ChunkIterator<T>> {
+        if ds.size() != xsize * ysize {
+            return Err(Error::Invalid(format!(
+                "dataset {} does not match the {}x{} grid",
+                ds.name(),
+                xsize,
+                ysize
+            )));
+        }
+        let nodata = attrs::user_fill_value::<f64>(&ds)?;
+        let reader = DatasetReader::new(ds, field)?;
+        return Ok(
+            ChunkIterator::new(reader, (xsize, ysize), flip, n_lines, block_cols)
+                .with_nodata(nodata),
+        );
+    }
+}
+
+impl<T, R, E> ChunkIterator<T, R>
+where
+    T: RangeValue,
+    R: ChunkReader<T, Error = E>,
+{
+    /// Blocks of the flat row-major values of `reader`, in strips of up to
+    /// `n_lines` rows or, when `block_cols` is less than `xsize`, single rows
+    /// split into blocks of `block_cols` columns. With `flip`, the first
+    /// block holds the last rows of `reader`, reversed.
+    pub fn new(
+        reader: R,
+        (xsize, ysize): (usize, usize),
+        flip: bool,
+        n_lines: usize,
+        block_cols: usize,
+    ) -> ChunkIterator<T, R> {
+        return ChunkIterator {
+            reader,
+            xsize,
+            ysize,
+            flip,
+            nodata: None,
+            replacement: None,
+            blocks: Box::new(chunking::blocks(xsize, ysize, n_lines, block_cols)),
+            _values: PhantomData,
+        };
+    }
+
+    /// Takes `nodata` as the nodata value of the values read.
+    pub fn with_nodata(mut self, nodata: Option<f64>) -> Self {
+        self.nodata = nodata;
+        return self;
+    }
+
+    /// Replaces the nodata values, and NaN, by `value` in the blocks.
+    pub fn replace_nodata(mut self, value: T) -> Self {
+        self.replacement = Some(value);
+        return self;
+    }
+
+    /// Nodata value of the values read, before any replacement.
+    pub fn nodata(&self) -> Option<f64> {
+        return self.nodata;
+    }
+
+    /// Grid shape as (xsize, ysize).
+    pub fn shape(&self) -> (usize, usize) {
+        return (self.xsize, self.ysize);
+    }
+
+    fn read_block(&self, block: Block) -> std::result::Result<Array2<T>, E> {
+        let source = Block {
+            yy: match self.flip {
+                true => self.ysize - block.yy - block.lines,
+                false => block.yy,
+            },
+            ..block
+        };
+        let range = source.range(self.xsize);
+        let vals = self.reader.read_range(range.start, range.end)?;
+        let mut vals = vals.into_shape((block.lines, block.cols)).unwrap();
+        if self.flip && block.lines > 1 {
+            vals = vals.slice(s![..;-1, ..]).to_owned();
+        }
+        if let Some(replacement) = self.replacement {
+            let nodata = self.nodata;
+            vals.mapv_inplace(|value| {
+                let as_f64: f64 = value.into();
+                return match as_f64.is_nan() || Some(as_f64) == nodata {
+                    true => replacement,
+                    false => value,
+                };
+            });
+        }
+        return Ok(vals);
+    }
+}
+
+impl<T, R, E> Iterator for ChunkIterator<T, R>
+where
+    T: RangeValue,
+    R: ChunkReader<T, Error = E>,
+{
+    type Item = std::result::Result<(Block, Array2<T>), E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block = self.blocks.next()?;
+        return Some(self.read_block(block).map(|vals| (block, vals)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MemGrid(Vec<f32>);
+
+    impl ChunkReader<f32> for MemGrid {
+        type Error = ();
+
+        fn read_range(&self, start: usize, end: usize) -> std::result::Result<Array1<f32>, ()> {
+            return Ok(Array1::from(self.0[start..end].to_vec()));
+        }
+    }
+
+    #[test]
+    fn blocks_in_output_order_with_nodata_replaced() {
+        // 3 columns by 4 rows, the last row first in output order.
+        let vals = vec![0., 1., 2., 3., -1., 5., 6., 7., f32::NAN, 9., 10., 11.];
+        let chunks: Vec<(Block, Array2<f32>)> =
+            ChunkIterator::new(MemGrid(vals.clone()), (3, 4), true, 3, 3)
+                .with_nodata(Some(-1.))
+                .replace_nodata(0.)
+                .map(|chunk| chunk.unwrap())
+                .collect();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            chunks[0].0,
+            Block {
+                yy: 0,
+                lines: 3,
+                x0: 0,
+                cols: 3
+            }
+        );
+        assert_eq!(
+            chunks[0].1,
+            ndarray::arr2(&[[9., 10., 11.], [6., 7., 0.], [3., 0., 5.]])
+        );
+        assert_eq!(chunks[1].1, ndarray::arr2(&[[0., 1., 2.]]));
+
+        // Rows too wide are split into blocks of columns.
+        let chunks: Vec<(Block, Array2<f32>)> =
+            ChunkIterator::new(MemGrid(vals), (3, 4), false, 3, 2)
+                .map(|chunk| chunk.unwrap())
+                .collect();
+        assert_eq!(chunks.len(), 8);
+        assert_eq!(
+            chunks[3].0,
+            Block {
+                yy: 1,
+                lines: 1,
+                x0: 2,
+                cols: 1
+            }
+        );
+        assert_eq!(chunks[3].1, ndarray::arr2(&[[5.]]));
+    }
+}
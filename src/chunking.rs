@@ -0,0 +1,309 @@
+//! Chunk sizing from a memory budget.
+//!
+//! Every stage processes data in strips of whole rows (or flat chunks of
+//! elements), keeping a fixed number of buffers alive at once. The sizes
+//! below turn a memory limit into the largest strip that fits, given how many
+//! bytes each pixel costs across all buffers of the stage. Rows too wide for
+//! the limit are split into blocks of columns (see [`blocks`]).
+
+use std::ops::Range;
+
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 30;
+
+/// Bytes held per pixel while reversing a dataset of `dtype_size`-byte
+/// elements: the top and bottom strips plus their reversed copies.
+pub fn reverse_bytes_per_pixel(dtype_size: usize) -> usize {
+    return 4 * dtype_size;
+}
+
+/// Bytes held per pixel by the mean/sd stage: sum, sumsq (f32), count (u8)
+/// and the mean/sd outputs (f32).
+pub const STATS_BYTES_PER_PIXEL: usize = 4 + 4 + 1 + 4 + 4;
+
+/// Bytes held per pixel while writing rasters: count (u8), mean and sd (f32),
+/// each with the strip being read from HDF5, the strip its output is writing
+/// and that strip's copy in a GDAL buffer.
+pub const RASTER_BYTES_PER_PIXEL: usize = 3 * (1 + 4 + 4);
+
+/// Bytes held per pixel while writing rasters with `extra_outputs` float
+/// outputs (e.g. the standard error) besides the count, mean and sd.
+pub fn group_raster_bytes_per_pixel(extra_outputs: usize) -> usize {
+    return RASTER_BYTES_PER_PIXEL + extra_outputs * 3 * 4;
+}
+
+/// Bytes held per pixel while comparing a statistic with a reference raster:
+/// sum, sumsq (f32), count (u8), mean and sd (f32), the reference (f64) and
+/// the difference (f32).
+pub const COMPARE_BYTES_PER_PIXEL: usize = 4 + 4 + 1 + 4 + 4 + 8 + 4;
+
+/// Bytes held per element while adding an increment to a statistic
+/// dataset: the chunk of the file and that of the increment, at most `f64`.
+pub const UPDATE_BYTES_PER_PIXEL: usize = 8 + 8;
+
+/// Bytes held per pixel while detecting change between two groups: the
+/// count (u32), mean and sd (f32) of each, the two sums read for them (f64)
+/// and the outputs (f32 and u8).
+pub const CHANGE_BYTES_PER_PIXEL: usize = 2 * (4 + 4 + 4 + 8 + 8) + 4 + 4 + 1;
+
+/// Bytes held per pixel while writing a single dataset of `dtype_size`-byte
+/// elements to a raster: the HDF5 read and its copy into a GDAL buffer.
+pub fn dataset_raster_bytes_per_pixel(dtype_size: usize) -> usize {
+    return 2 * dtype_size;
+}
+
+/// Largest chunk of the datasets written back to HDF5, so that partial reads
+/// (e.g. `--verify` windows) decompress little more than they need.
+pub const MAX_H5_CHUNK_BYTES: usize = 8 << 20;
+
+/// Chunk length, in elements, of a dataset written in strips of `lines` rows
+/// of `xsize` `dtype_size`-byte elements: whole rows, as many as a strip holds
+/// within [`MAX_H5_CHUNK_BYTES`].
+/// A row larger than that limit is split into flat chunks instead.
+pub fn h5_chunk_len(xsize: usize, lines: usize, dtype_size: usize) -> usize {
+    let row_bytes = xsize.saturating_mul(dtype_size);
+    if row_bytes > MAX_H5_CHUNK_BYTES {
+        return h5_flat_chunk_len(xsize, dtype_size);
+    }
+    let rows = (MAX_H5_CHUNK_BYTES / row_bytes.max(1)).clamp(1, lines.max(1));
+    return rows * xsize.max(1);
+}
+
+/// Chunk length, in elements, of a dataset written in flat chunks of `len`
+/// `dtype_size`-byte elements, within [`MAX_H5_CHUNK_BYTES`].
+pub fn h5_flat_chunk_len(len: usize, dtype_size: usize) -> usize {
+    return len.min(MAX_H5_CHUNK_BYTES / dtype_size.max(1)).max(1);
+}
+
+/// Parses sizes such as `512M`, `2G`, `1.5GiB` or a plain byte count.
+/// Suffixes are binary (K = 1024).
+pub fn parse_memory_size(text: &str) -> Result<usize, String> {
+    let text = text.trim();
+    let upper = text.to_uppercase();
+    let number_end = upper
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or_else(|| upper.len());
+    let (number, unit) = upper.split_at(number_end);
+    let multiplier: f64 = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1f64,
+        "K" => 1024f64,
+        "M" => 1024f64.powi(2),
+        "G" => 1024f64.powi(3),
+        "T" => 1024f64.powi(4),
+        _ => return Err(format!("invalid memory size unit in '{}'", text)),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid memory size '{}'", text))?;
+    let bytes = (number * multiplier) as usize;
+    if bytes == 0 {
+        return Err(format!("memory size must be positive, got '{}'", text));
+    }
+    return Ok(bytes);
+}
+
+/// Number of full rows of `xsize` pixels that fit in `memory_limit` when each
+/// pixel costs `bytes_per_pixel`. Always at least one row.
+pub fn strip_lines(memory_limit: usize, xsize: usize, bytes_per_pixel: usize) -> usize {
+    let row_bytes = xsize.saturating_mul(bytes_per_pixel).max(1);
+    return (memory_limit / row_bytes).max(1);
+}
+
+/// Columns of the row blocks of an `xsize` wide grid that fit in
+/// `memory_limit` when each pixel costs `bytes_per_pixel`: `xsize` when a
+/// full row fits, else fewer, and rows are then processed one at a time in
+/// blocks of that many columns.
+pub fn block_cols(memory_limit: usize, xsize: usize, bytes_per_pixel: usize) -> usize {
+    return chunk_len(memory_limit, bytes_per_pixel).min(xsize).max(1);
+}
+
+/// Rows `yy..yy + lines` and columns `x0..x0 + cols` of a grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
+    pub yy: usize,
+    pub lines: usize,
+    pub x0: usize,
+    pub cols: usize,
+}
+
+impl Block {
+    /// Elements of the block in a flat row-major grid `xsize` wide. They are
+    /// contiguous, as a block spans either full rows or a single row.
+    pub fn range(&self, xsize: usize) -> Range<usize> {
+        let start = self.yy * xsize + self.x0;
+        return start..start + (self.lines - 1) * xsize + self.cols;
+    }
+
+    /// Number of elements.
+    pub fn size(&self) -> usize {
+        return self.lines * self.cols;
+    }
+}
+
+/// Rows of the strips of [`blocks`]: `n_lines`, or 1 when rows are split
+/// into blocks of `block_cols` columns.
+pub fn block_lines(xsize: usize, n_lines: usize, block_cols: usize) -> usize {
+    return match block_cols < xsize {
+        true => 1,
+        false => n_lines.max(1),
+    };
+}
+
+/// Blocks covering an `xsize` by `ysize` grid top to bottom: strips of up to
+/// `n_lines` full rows or, when `block_cols` is less than `xsize`, each row
+/// in blocks of up to `block_cols` columns, left to right.
+pub fn blocks(
+    xsize: usize,
+    ysize: usize,
+    n_lines: usize,
+    block_cols: usize,
+) -> impl Iterator<Item = Block> {
+    let n_lines = block_lines(xsize, n_lines, block_cols);
+    let block_cols = block_cols.min(xsize).max(1);
+    return (0..ysize).step_by(n_lines).flat_map(move |yy| {
+        return (0..xsize).step_by(block_cols).map(move |x0| Block {
+            yy,
+            lines: n_lines.min(ysize - yy),
+            x0,
+            cols: block_cols.min(xsize - x0),
+        });
+    });
+}
+
+/// Number of elements that fit in `memory_limit` when each costs
+/// `bytes_per_pixel`. Always at least one element.
+pub fn chunk_len(memory_limit: usize, bytes_per_pixel: usize) -> usize {
+    return (memory_limit / bytes_per_pixel.max(1)).max(1);
+}
+
+/// Largest width or height of a raster: GDAL stores raster sizes as C ints.
+pub const MAX_RASTER_SIDE: usize = i32::MAX as usize;
+
+/// Number of pixels of an `xsize` x `ysize` grid. Every index into the grid
+/// (`row * xsize + col`, or the end of a strip) is at most this, so once it
+/// is known to fit in a `usize` the index arithmetic cannot overflow.
+pub fn grid_len(xsize: usize, ysize: usize) -> Result<usize, String> {
+    if xsize > MAX_RASTER_SIDE || ysize > MAX_RASTER_SIDE {
+        return Err(format!(
+            "{}x{} grid exceeds the largest raster side of {} pixels",
+            xsize, ysize, MAX_RASTER_SIDE
+        ));
+    }
+    return xsize.checked_mul(ysize).ok_or_else(|| {
+        format!(
+            "{}x{} grid has more pixels than this platform can index",
+            xsize, ysize
+        )
+    });
+}
+
+/// Largest classic (32-bit offset) TIFF file.
+pub const CLASSIC_TIFF_MAX_BYTES: u64 = u32::MAX as u64;
+
+/// Room left in a classic TIFF for headers, strip or tile offsets and
+/// metadata besides the pixels.
+pub const TIFF_OVERHEAD_BYTES: u64 = 64 << 20;
+
+/// Uncompressed size in bytes of `n_bands` bands of `xsize` x `ysize`
+/// `dtype_size`-byte pixels, computed in `u64` so it is exact on every
+/// platform.
+pub fn raster_bytes(xsize: usize, ysize: usize, dtype_size: usize, n_bands: usize) -> u64 {
+    return (xsize as u64)
+        .saturating_mul(ysize as u64)
+        .saturating_mul(dtype_size as u64)
+        .saturating_mul(n_bands as u64);
+}
+
+/// Whether a TIFF of the given raster must be written as BigTIFF, its pixels
+/// alone possibly reaching the 4 GB limit of classic TIFF (compression is not
+/// counted on).
+pub fn needs_bigtiff(xsize: usize, ysize: usize, dtype_size: usize, n_bands: usize) -> bool {
+    return raster_bytes(xsize, ysize, dtype_size, n_bands)
+        > CLASSIC_TIFF_MAX_BYTES - TIFF_OVERHEAD_BYTES;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A continental grid of 5 billion pixels at 30 m, e.g. 100000 x 50000.
+    const XSIZE: usize = 100_000;
+    const YSIZE: usize = 50_000;
+
+    #[test]
+    fn grid_len_of_more_than_four_billion_pixels() {
+        if usize::BITS < 64 {
+            assert!(grid_len(XSIZE, YSIZE).is_err());
+            return;
+        }
+        let len = grid_len(XSIZE, YSIZE).unwrap();
+        assert_eq!(len as u64, 5_000_000_000);
+        assert!(len > u32::MAX as usize);
+        // The last index of the grid is in range.
+        assert_eq!((YSIZE - 1) * XSIZE + (XSIZE - 1), len - 1);
+    }
+
+    #[test]
+    fn grid_len_rejects_sides_gdal_cannot_create() {
+        assert!(grid_len(MAX_RASTER_SIDE + 1, 1).is_err());
+        assert!(grid_len(1, MAX_RASTER_SIDE + 1).is_err());
+        assert_eq!(grid_len(MAX_RASTER_SIDE, 1), Ok(MAX_RASTER_SIDE));
+    }
+
+    #[test]
+    fn strips_of_wide_grids_fit_in_memory() {
+        let lines = strip_lines(DEFAULT_MEMORY_LIMIT, XSIZE, RASTER_BYTES_PER_PIXEL);
+        assert_eq!(
+            lines,
+            DEFAULT_MEMORY_LIMIT / (XSIZE * RASTER_BYTES_PER_PIXEL)
+        );
+        assert!(lines * XSIZE * RASTER_BYTES_PER_PIXEL <= DEFAULT_MEMORY_LIMIT);
+        // Rows larger than the limit are read one at a time.
+        assert_eq!(strip_lines(DEFAULT_MEMORY_LIMIT, MAX_RASTER_SIDE, 8), 1);
+        assert_eq!(strip_lines(DEFAULT_MEMORY_LIMIT, usize::MAX, 8), 1);
+    }
+
+    #[test]
+    fn rows_wider_than_the_limit_are_split_into_blocks() {
+        assert_eq!(block_cols(DEFAULT_MEMORY_LIMIT, XSIZE, 8), XSIZE);
+        let cols = block_cols(DEFAULT_MEMORY_LIMIT, MAX_RASTER_SIDE, 8);
+        assert_eq!(cols, DEFAULT_MEMORY_LIMIT / 8);
+
+        let strips: Vec<Block> = blocks(10, 5, 2, 10).collect();
+        assert_eq!(strips.len(), 3);
+        assert_eq!(strips[2].lines, 1);
+        assert_eq!(strips[1].range(10), 20..40);
+
+        let row_blocks: Vec<Block> = blocks(10, 2, 2, 4).collect();
+        let ranges: Vec<Range<usize>> = row_blocks.iter().map(|b| b.range(10)).collect();
+        assert_eq!(ranges, vec![0..4, 4..8, 8..10, 10..14, 14..18, 18..20]);
+        assert_eq!(row_blocks.iter().map(Block::size).sum::<usize>(), 20);
+    }
+
+    #[test]
+    fn h5_chunks_stay_within_the_limit() {
+        let len = h5_chunk_len(XSIZE, YSIZE, 4);
+        assert_eq!(len % XSIZE, 0);
+        assert!(len * 4 <= MAX_H5_CHUNK_BYTES);
+        // A row larger than a chunk is split.
+        let len = h5_chunk_len(MAX_RASTER_SIDE, YSIZE, 8);
+        assert_eq!(len, MAX_H5_CHUNK_BYTES / 8);
+    }
+
+    #[test]
+    fn bigtiff_above_four_gigabytes() {
+        assert_eq!(raster_bytes(XSIZE, YSIZE, 4, 1), 20_000_000_000);
+        assert_eq!(
+            raster_bytes(MAX_RASTER_SIDE, MAX_RASTER_SIDE, 1, 1),
+            (MAX_RASTER_SIDE as u64).pow(2)
+        );
+        assert_eq!(
+            raster_bytes(MAX_RASTER_SIDE, MAX_RASTER_SIDE, 8, 4),
+            u64::MAX
+        );
+        assert!(needs_bigtiff(XSIZE, YSIZE, 1, 1));
+        assert!(!needs_bigtiff(40_000, 40_000, 2, 1));
+        // Bands add up.
+        assert!(!needs_bigtiff(30_000, 30_000, 4, 1));
+        assert!(needs_bigtiff(30_000, 30_000, 4, 2));
+    }
+}
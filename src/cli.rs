@@ -0,0 +1,1916 @@
+use read_hdf_as_raster::block::{BandOrder, BlockLayout, Interleave};
+use read_hdf_as_raster::chunking;
+use read_hdf_as_raster::colors::{ColorMap, ColorStyle, Sidecar};
+use read_hdf_as_raster::compression::{Codec, H5Compression};
+use read_hdf_as_raster::converter::ConvertOptions;
+#[cfg(feature = "gdal")]
+use read_hdf_as_raster::gdal_config::{self, GdalConfig};
+use read_hdf_as_raster::geolocation::{ArrayPaths, GeolocationMode};
+use read_hdf_as_raster::grid::GroupGridRule;
+use read_hdf_as_raster::h5_cache::ChunkCache;
+use read_hdf_as_raster::hdf::TraversalOptions;
+#[cfg(feature = "gdal")]
+use read_hdf_as_raster::import;
+use read_hdf_as_raster::inventory;
+use read_hdf_as_raster::nan_policy::NanPolicy;
+use read_hdf_as_raster::notify::NotifyUrl;
+use read_hdf_as_raster::overrides::{self, DatasetOverride};
+use read_hdf_as_raster::policy::ExistsPolicy;
+use read_hdf_as_raster::quicklook::{self, ColorRamp, QuicklookOptions};
+use read_hdf_as_raster::raster::OutputFormat;
+use read_hdf_as_raster::retry;
+use read_hdf_as_raster::row_order::FlipOption;
+use read_hdf_as_raster::shards::{self, ShardedDataset};
+use read_hdf_as_raster::stats::{
+    ErrorOutputs, QualityClasses, SingleObsPolicy, StatNames, WeightNames,
+};
+use read_hdf_as_raster::swmr::SwmrOptions;
+use read_hdf_as_raster::templates::{RasterType, TypedTemplate};
+use read_hdf_as_raster::tiles::TileScheme;
+use read_hdf_as_raster::units::{UnitConversion, UnitRule};
+use read_hdf_as_raster::valid_range::{OutOfRange, RangeOption};
+use read_hdf_as_raster::verify::{self, VerifyOptions};
+use read_hdf_as_raster::xyz::{self, XyzOptions};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: read_hdf_as_raster [OPTIONS]
+       read_hdf_as_raster list [--json] [LIST OPTIONS]
+       read_hdf_as_raster extract --points FILE [EXTRACT OPTIONS]
+       read_hdf_as_raster compare --reference FILE --group NAME [COMPARE OPTIONS]
+       read_hdf_as_raster tiles --group NAME --out DIR [TILES OPTIONS]
+       read_hdf_as_raster change --group-a NAME --group-b NAME [CHANGE OPTIONS]
+       read_hdf_as_raster import --raster FILE --dataset PATH [IMPORT OPTIONS]
+       read_hdf_as_raster undo [UNDO OPTIONS]
+       read_hdf_as_raster batch --manifest FILE [BATCH OPTIONS]
+       read_hdf_as_raster watch --dir DIR [WATCH OPTIONS]
+       read_hdf_as_raster serve [SERVE OPTIONS]
+
+Options:
+    --memory-limit SIZE    Memory budget used to size read/write chunks
+                           (e.g. 512M, 2G; default 1G)
+    --jobs N               Worker threads for the statistics, groups whose
+                           rasters are written at once and GDAL compression
+                           (default: number of CPUs)
+    --gdal-threads N       GDAL_NUM_THREADS used to compress outputs, a
+                           count or ALL_CPUS (default: --jobs)
+    --gdal-cache SIZE      GDAL_CACHEMAX block cache (e.g. 256M; default
+                           64M per job, within --memory-limit)
+    --report FILE          Write a JSON report of the run to FILE
+    --keep-partial         Keep rasters left incomplete by an interrupted run
+                           (marked incomplete in the report) instead of
+                           deleting them
+    --resume               Skip steps recorded as completed in the checkpoint
+                           of a previous interrupted run
+    --scratch FILE         Write the *_rev, mean_rev and sd_rev datasets to
+                           the HDF5 file FILE (created if missing) and open
+                           the converted file read-only
+    --swmr                 Open the converted file for SWMR reading while
+                           another process writes it (HDF5 1.10 or later;
+                           needs --scratch)
+    --swmr-wait DELAY      As --swmr, waiting up to DELAY (seconds, or with
+                           an ms or s suffix) for each dataset to hold the
+                           whole grid, refreshing it every second
+    --flip auto|yes|no     Reverse the rows of the datasets: when they run
+                           opposite to the template (auto, default; from
+                           the file's GeoTransform or origin attribute,
+                           else bottom-up), always or never
+    --min-count N          Leave the mean and sd of pixels with fewer than N
+                           observations empty (default 1)
+    --nan-policy nodata|zero|keep
+                           Write means and sds of pixels with observations
+                           that come out NaN or infinite as nodata (default),
+                           0 or as they are; they are counted in the report
+    --single-obs-policy nodata|zero
+                           Write the sd of pixels with a single observation
+                           as nodata, -1 (default), or 0
+    --se                   Also write the standard error of each group's
+                           mean, sd / sqrt(count)
+    --ci Z                 Also write the bounds of each group's confidence
+                           interval, mean -/+ Z * se (e.g. 1.96 for 95%)
+    --quality-mask LOW,HIGH
+                           Also write each group's quality class per pixel
+                           as bytes: 0 no observations, 1 fewer than LOW,
+                           2 fewer than HIGH, 3 HIGH or more
+    --kernel FILE          Also write the outputs of the per-pixel statistic
+                           kernel in the WASM module FILE for each group;
+                           may be repeated (wasm builds only)
+    --direct-stats         Compute the group means and sds from the *_rev
+                           sums while writing the rasters, without writing
+                           mean_rev and sd_rev
+    --force                Replace the lock of the HDF5 file left by a run
+                           that was killed
+    --retries N            Retry HDF5 reads and writes and GDAL writes that
+                           fail with I/O errors up to N times (default 0)
+    --retry-delay DELAY    Wait before the first retry, doubled for each
+                           next one (e.g. 500ms or 2s; default 1s)
+    --update FILE          Add the sum, sumsq and count datasets of the HDF5
+                           file FILE, new increments of the product, to
+                           those of the converted file before converting;
+                           the *_rev datasets and outputs of the updated
+                           groups are produced again. May be repeated
+    --skip-existing        Keep existing *_rev datasets and output rasters
+                           and skip producing them (default; --overwrite
+                           with --update)
+    --overwrite            Replace existing *_rev datasets and output rasters
+    --fail-if-exists       Stop with an error if any output already exists
+    --groups A,B,C         Only process the listed groups
+    --datasets A,B         Also convert the listed datasets on their own,
+                           each to one raster named after its path
+    --time-series A,B      Also convert the listed (time, y, x) datasets,
+                           each to one raster per time slice
+    --time-coord PATH|@ATTR
+                           Time coordinate naming the time slices: a 1-d
+                           dataset, or an attribute of each time series
+                           dataset (default: slice index, t000)
+    --select FILE|-        Convert the groups, datasets and time series
+                           listed in the JSON file FILE, or on stdin, e.g.
+                           entries of list --json; --groups, --datasets
+                           and --time-series given after it replace them
+    --config FILE          JSON file overriding the data type, flip and
+                           nodata of chosen datasets and groups, or
+                           skipping them, and listing datasets split into
+                           row blocks (see the README)
+    --field NAME           Member field to extract from compound-typed
+                           statistic datasets
+    --strings-csv FILE     Export string and variable-length datasets, which
+                           are skipped, to a CSV sidecar
+    --follow-external-links
+                           Descend into HDF5 external links to companion
+                           files instead of skipping them
+    --stat-names S,SQ,N    Names of the sum, sum of squares and count
+                           datasets a group must contain
+                           (default sum,sumsq,count)
+    --weight-names W,WX,WX2[,W2]
+                           Compute weighted means and sds from the datasets
+                           of the sums of the weights, weighted values and
+                           weighted squares; the variance is corrected with
+                           the effective sample size, from the sums of the
+                           squared weights W2 if given, else the count
+    --h5-compress CODEC    Compression of the datasets written back to HDF5:
+                           zstd:LEVEL, gzip:LEVEL (default gzip:1), lz4 or
+                           none; zstd and lz4 need their HDF5 filter plugins
+                           on HDF5_PLUGIN_PATH
+    --h5-shuffle on|off    Byte shuffle before compressing (default off)
+    --h5-checksum          Add a Fletcher32 checksum to the chunks of the
+                           datasets written back to HDF5, checked when they
+                           are read to write the rasters
+    --h5-resizable         Create the datasets written back to HDF5 with an
+                           unlimited maximum size
+    --h5-virtual           Write the reversed datasets as HDF5 virtual
+                           datasets mapping the rows of their sources
+                           instead of copies (HDF5 1.10 or later)
+    --repack               Copy the HDF5 file written to (the converted or
+                           the --scratch file) once the run completed, to
+                           reclaim the space of the datasets it replaced
+    --repack-compress CODEC
+                           As --repack, writing the numeric datasets again
+                           with CODEC (as --h5-compress), with --h5-shuffle
+                           and --h5-checksum
+    --h5-cache SIZE[,NSLOTS[,W0]]
+                           HDF5 chunk cache of each dataset read (e.g. 64M
+                           or 256M,12421,1; default HDF5's 1M,521,0.75)
+    --status-port PORT     Serve a JSON status (current dataset, percent
+                           complete, throughput, errors) on
+                           http://127.0.0.1:PORT/
+    --notify-url URL       POST the JSON report to the http:// webhook URL
+                           when the run ends, completed or failed
+    --on-complete CMD      Run the shell command CMD when the run ends, with
+                           the JSON report on stdin and the exit code in
+                           RS_H2R_EXIT_CODE
+    --template TYPE=FILE[,...]
+                           Template of the outputs of a GDAL data type
+                           (e.g. Int16=base_int16.tif), else base_float.tif;
+                           Float32 and Byte replace base_float.tif and
+                           base_byte.tif
+    --group-grid GROUP=FILE
+                           Template of a group on its own grid, e.g. at
+                           another resolution; repeatable. Groups with
+                           shape and GeoTransform attributes, or lat/lon
+                           dimension scales, get their grid from them
+                           otherwise
+    --snap-scales          Snap irregularly spaced dimension scales to the
+                           nearest regular grid instead of ignoring them
+    --geolocation MODE     For grids with 2-d lat/lon arrays instead of a
+                           geotransform: attach writes them next to every
+                           output (OUTPUT_lat.tif, OUTPUT_lon.tif) as its
+                           GDAL geolocation arrays; warp[=CRS] also warps
+                           every output through them to OUTPUT_warped.tif
+                           in CRS, EPSG:4326 by default (gdal builds only)
+    --geolocation-arrays LAT,LON
+                           Latitude and longitude datasets of
+                           --geolocation, else those named lat or latitude
+                           and lon or longitude in the group or the root
+    --ot TYPE              Data type of dataset outputs (Byte, Int16, UInt16,
+                           Int32, UInt32, Float32 or Float64) instead of
+                           their own; values are rounded and clipped to it
+                           and nodata remapped, reporting pixels clipped
+    --format NAME          Output format: gdal (default), geotiff, netcdf or
+                           zarr, among those enabled at build time
+    --band-order ORDER     Pixel order of the buffers handed to GDAL:
+                           row-major (default) or column-major, for drivers
+                           that expect transposed buffers; gdal format only
+    --sparse               Create outputs as sparse files initialised to
+                           nodata and skip strips without observations;
+                           gdal format only
+    --band N               Write band N of the outputs: existing outputs,
+                           e.g. a prepared stack, are updated in place and
+                           new ones copy a multi-band template; implies
+                           --overwrite unless a policy is given; gdal
+                           format only
+    --float16              Store Float32 outputs (mean, sd, Float32
+                           datasets) as 16-bit floats, halving their size:
+                           about 3 significant digits, magnitudes up to
+                           65504; GeoTIFF templates and gdal format only
+    --interleave band|pixel
+                           Interleave of the bands of new GeoTIFF outputs,
+                           e.g. the --stack; gdal format only
+    --block-size WxH       Tile new GeoTIFF outputs in WxH blocks (multiples
+                           of 16, or one side for square tiles) instead of
+                           copying the template's layout; gdal format only
+    --stack FILE           Also write the statistic of every group as one
+                           band of the GeoTIFF FILE, named after the group
+                           (gdal builds only)
+    --stack-stat STAT      Statistic stacked by --stack: mean (default), sd
+                           or count
+    --quicklook            Render a PNG quicklook next to each output
+    --quicklook-ramp NAME  Color ramp of the quicklooks: gray, viridis
+                           (default), magma or terrain; implies --quicklook
+    --quicklook-stretch LOW,HIGH
+                           Percentiles stretched to the ends of the ramp
+                           (default 2,98); implies --quicklook
+    --preview N            Only write a quick preview of every group and
+                           --dataset (OUTPUT_preview.tif) from every Nth
+                           row and column, read with strided hyperslabs,
+                           leaving the HDF5 file untouched (gdal builds
+                           only)
+    --verify               Read back random windows of every output and
+                           compare them with values recomputed from the
+                           HDF5 source
+    --verify-windows N     Windows checked per output (default 16); implies
+                           --verify
+    --verify-epsilon E     Accepted absolute difference (default 0,
+                           bit-exact); implies --verify
+    --verify-reverse       Check the first, middle, last and 16 random rows
+                           of every _rev dataset against its source once
+                           written, stopping before any raster is written
+                           if they do not mirror it
+    --verify-reverse-rows N
+                           Random rows checked per dataset (default 16);
+                           implies --verify-reverse
+    --strict               Stop with an error on data quality warnings:
+                           size mismatch, nodata fallback, precision loss
+    --valid-range MIN,MAX|attrs
+                           Valid range of dataset values and group means,
+                           or attrs for the valid_range (valid_min,
+                           valid_max) attributes of each dataset
+    --out-of-range ACTION  Values outside the valid range: nodata (default)
+                           or clamp
+    --units TARGET=FROM:TO[,...]
+                           Convert the values of a dataset, or the mean and
+                           sd of a group, between named units: Mg/ha, t/ha,
+                           kg/ha, kg/m2, g/m2; m, cm, mm, km
+    --unit-scale TARGET=FACTOR:UNITS[,...]
+                           Multiply the values of a dataset, or the mean and
+                           sd of a group, by FACTOR, giving UNITS
+    --color-table FILE     Colors of count and byte dataset outputs, as
+                           lines of VALUE R G B [A] [LABEL] (VALUE may be
+                           MIN-MAX), embedded in gdal outputs
+    --color-sidecar qml|clr[,...]
+                           Also write the colors as QGIS .qml or .clr
+                           sidecars; needed by formats other than gdal
+    --deterministic        Write bitwise-identical outputs on reruns: one
+                           group at a time and no timings in the report;
+                           implies --provenance
+    --provenance           Record the source file and its SHA-256, the
+                           datasets used, the tool version and arguments in
+                           a PROVENANCE metadata block of every output, an
+                           ISO 19115 XML sidecar (OUTPUT.iso.xml) and the
+                           report
+    --stac                 Write a STAC Item (OUTPUT.json) next to every
+                           output raster, dated from the datetime, date or
+                           time attributes of its source
+    --tile-scheme SIZE     Also split every output raster into GeoTIFF
+                           tiles of WIDTHxHEIGHT pixels (e.g. 4096x4096)
+                           or degrees (e.g. 1x1deg) in OUTPUT_tiles/, with
+                           an index.geojson (gdal builds only)
+    --vrt FILE             Also write a GDAL VRT with a band per output
+                           raster of the run, sorted by statistic (gdal
+                           builds only)
+    --qgis-project FILE    Also write a QGIS project (.qgz, or .qgs) with a
+                           layer group per statistic of the run's outputs
+    --summary              Print the min, max, mean, sd and histogram of
+                           every output and add them to the report
+    --tui                  Pick groups, datasets and options in a terminal
+                           UI and follow the run there (tui builds only)
+    -h, --help             Print this help
+
+List options (datasets and statistic groups of the file):
+    --json                 Print the inventory as JSON on stdout, for
+                           --select
+    --stat-names S,SQ,N    As above
+    --weight-names W,WX,WX2[,W2]
+                           As above
+    --follow-external-links
+                           As above
+
+Extract options (statistics at points, without writing rasters):
+    --points FILE          CSV with x,y (or lon,lat) and optional id
+                           columns, or GeoJSON points, in the grid CRS
+    --out FILE             Write the CSV of values to FILE (default stdout)
+    --groups A,B,C         Only extract the listed groups
+    --stat-names S,SQ,N    As above
+    --weight-names W,WX,WX2[,W2]
+                           As above
+    --field NAME           As above
+    --flip auto|yes|no     As above
+    --min-count N          As above
+    --follow-external-links
+                           As above
+
+Compare options (a statistic against a reference raster, gdal builds only):
+    --reference FILE       Raster on the same grid to compare with
+    --group NAME           Group whose statistic is compared
+    --stat STAT            mean (default), sd or count
+    --diff FILE            Write statistic - reference to FILE, in --format
+    --report FILE          Write the metrics (bias, RMSE, MAE) as JSON
+    --memory-limit SIZE    As above
+    --overwrite            Replace an existing --diff output
+    --format NAME, --stat-names S,SQ,N, --weight-names W,WX,WX2[,W2],
+    --field NAME, --flip auto|yes|no, --min-count N
+                           As above
+
+Tiles options (an XYZ PNG tile pyramid of a statistic, for web maps):
+    --group NAME           Group whose statistic is rendered
+    --out DIR              Directory of the DIR/Z/X/Y.png tiles
+    --stat STAT            mean (default), sd or count
+    --zoom MIN-MAX         Zoom levels rendered (default 0 to the level
+                           matching the grid resolution, at most 18)
+    --ramp NAME            Color ramp: gray, viridis (default), magma or
+                           terrain
+    --stretch LOW,HIGH     Percentiles mapped to the ends of the ramp
+                           (default 2,98)
+    --memory-limit SIZE, --stat-names S,SQ,N, --weight-names W,WX,WX2[,W2],
+    --field NAME, --flip auto|yes|no, --min-count N
+                           As above
+
+Change options (change of the means from one group to another):
+    --group-a NAME         Group changed from
+    --group-b NAME         Group changed to (default: --group-a, with
+                           --other)
+    --other FILE           Take --group-b from the HDF5 file FILE, on the
+                           same grid
+    --out PREFIX           Write PREFIX_diff, PREFIX_rel_change and
+                           PREFIX_significant in --format (default
+                           change_<A>_<B>)
+    --alpha P              Significance level of Welch's t-test of the means
+                           (default 0.05)
+    --report FILE          Write a summary of the change as JSON
+    --memory-limit SIZE    As above
+    --overwrite            Replace existing outputs
+    --format NAME, --stat-names S,SQ,N, --weight-names W,WX,WX2[,W2],
+    --field NAME, --flip auto|yes|no, --min-count N
+                           As above
+
+Import options (a raster band into an HDF5 dataset, gdal builds only):
+    --raster FILE          Raster to import, e.g. a GeoTIFF
+    --dataset PATH         Dataset written, with its parent groups
+    --band N               Band imported (default 1)
+    --ot TYPE              Type of the dataset: Byte, Int16, UInt16, Int32,
+                           UInt32, Float32 or Float64 (default: that of
+                           the band)
+    --flip auto|yes|no     Reverse the rows when the raster runs opposite
+                           to the file (auto, default), always or never
+    --overwrite            Replace an existing dataset
+    --fail-if-exists       Stop with an error if the dataset exists (default
+                           keeps it)
+    --force, --memory-limit SIZE, --h5-compress CODEC, --h5-shuffle on|off,
+    --h5-checksum, --h5-resizable
+                           As above
+
+Undo options (removal of the datasets recorded in /processing_history):
+    --dry-run              List the datasets that would be removed
+    --repack               Copy the file to reclaim the space they took
+    --repack-compress CODEC
+                           As --repack, writing the numeric datasets again
+                           with CODEC (as --h5-compress)
+    --force                As above
+
+Batch options (conversions of the jobs of a JSON manifest, each with the
+flags of its profile, in processes of their own):
+    --manifest FILE        Manifest of profiles and of jobs (input, profile,
+                           outdir)
+    --jobs N               Jobs run at once (default 1), sharing the worker
+                           threads unless their profile sets --jobs
+    --report FILE          Write the reports of every job, with their exit
+                           codes, to FILE as JSON
+
+Watch options (conversion of the HDF5 files arriving in a directory):
+    --dir DIR              Directory polled for new files
+    --pattern GLOB         Names of the files converted (default *.h5)
+    --outdir DIR           Directory of the output directories, one per file
+                           named after it (default: the working directory)
+    --manifest FILE, --profile NAME
+                           Convert with the flags of profile NAME of the
+                           manifest (see batch), else with none
+    --interval SECONDS     Time between two polls (default 5); a file is
+                           converted once it holds still for one interval
+    --once                 Stop once the files found are converted
+
+Serve options (HTTP conversion service; server builds only):
+    --bind ADDR            Address listened on (default 127.0.0.1)
+    --port N               Port listened on (default 8642)
+    --manifest FILE        Manifest whose profiles jobs may name (see batch)
+    --input-root DIR       Directory the inputs of jobs are relative to and
+                           must stay in (default: the working directory)
+    --output-root DIR      Directory the outdirs of jobs are relative to and
+                           must stay in (default: --outdir, else the working
+                           directory)
+
+Global options (any command):
+    --input FILE           HDF5 file converted (default cerrado_100.h5)
+    --outdir DIR           Directory the rasters named after groups and
+                           datasets are written to, created if missing
+                           (default: the working directory)
+
+Environment:
+    RS_H2R_INPUT, RS_H2R_OUTDIR, RS_H2R_JOBS, RS_H2R_MEMORY_LIMIT,
+    RS_H2R_REPORT, RS_H2R_SCRATCH, RS_H2R_CONFIG, RS_H2R_FORMAT,
+    RS_H2R_RETRIES, RS_H2R_RETRY_DELAY, RS_H2R_STATUS_PORT,
+    RS_H2R_NOTIFY_URL, RS_H2R_ON_COMPLETE, RS_H2R_GDAL_THREADS,
+    RS_H2R_GDAL_CACHE
+                           Values of the flags of the same name, empty
+                           ones ignored; command-line flags take
+                           precedence over these. All but INPUT and
+                           OUTDIR only apply to conversions
+
+Exit codes:
+    0    All outputs written
+    1    Invalid command line
+    2    Run finished, but some outputs failed to convert or verify
+    3    Invalid input or options, or an existing output that must not
+         be replaced
+    4    I/O error reading or writing HDF5 or raster files
+    5    Data quality warning made an error by --strict
+    130  Stopped by Ctrl-C";
+
+/// Prefix of the environment variables standing for flags, e.g.
+/// `RS_H2R_MEMORY_LIMIT` for `--memory-limit`.
+pub const ENV_PREFIX: &str = "RS_H2R_";
+
+/// Flags of every command, taken out before the command's own.
+const GLOBAL_FLAGS: &[&str] = &["--input", "--outdir"];
+
+/// Flags of conversions that can be given through the environment, besides
+/// the global ones.
+const ENV_FLAGS: &[&str] = &[
+    "--jobs",
+    "--memory-limit",
+    "--report",
+    "--scratch",
+    "--config",
+    "--format",
+    "--retries",
+    "--retry-delay",
+    "--status-port",
+    "--notify-url",
+    "--on-complete",
+    #[cfg(feature = "gdal")]
+    "--gdal-threads",
+    #[cfg(feature = "gdal")]
+    "--gdal-cache",
+];
+
+const SUBCOMMANDS: &[&str] = &[
+    "list", "extract", "compare", "tiles", "change", "import", "undo", "batch", "watch", "serve",
+];
+
+/// Name of the environment variable standing for `flag`.
+pub fn env_name(flag: &str) -> String {
+    return format!(
+        "{}{}",
+        ENV_PREFIX,
+        flag.trim_start_matches('-')
+            .replace('-', "_")
+            .to_uppercase()
+    );
+}
+
+/// `args` (without the program name) preceded, after the subcommand if
+/// any, by the flags set in `vars`, so that those of `args` take
+/// precedence. Empty variables are ignored.
+pub fn with_env(
+    args: Vec<String>,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<String> {
+    let vars: HashMap<String, String> = vars.into_iter().collect();
+    let subcommand = args
+        .first()
+        .filter(|arg| SUBCOMMANDS.contains(&arg.as_str()))
+        .cloned();
+    let mut flags: Vec<&str> = GLOBAL_FLAGS.to_vec();
+    if subcommand.is_none() {
+        flags.extend(ENV_FLAGS);
+    }
+    let mut with_env: Vec<String> = subcommand.iter().cloned().collect();
+    for flag in flags {
+        match vars.get(&env_name(flag)) {
+            Some(value) if !value.is_empty() => {
+                with_env.push(flag.to_owned());
+                with_env.push(value.clone());
+            }
+            _ => {}
+        }
+    }
+    let skip = match subcommand {
+        Some(_) => 1,
+        None => 0,
+    };
+    with_env.extend(args.into_iter().skip(skip));
+    return with_env;
+}
+
+/// Options of every command: the HDF5 file and where outputs go.
+#[derive(Debug, Clone)]
+pub struct Globals {
+    pub input: String,
+    pub outdir: Option<PathBuf>,
+}
+
+impl Default for Globals {
+    fn default() -> Self {
+        return Globals {
+            input: "cerrado_100.h5".to_owned(),
+            outdir: None,
+        };
+    }
+}
+
+impl Globals {
+    /// Takes the global flags out of `args`, wherever they are, returning
+    /// the rest.
+    pub fn take(args: Vec<String>) -> Result<(Globals, Vec<String>), String> {
+        let mut globals = Globals::default();
+        let mut rest = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--input" => globals.input = value(&mut args, &arg)?,
+                "--outdir" => globals.outdir = Some(PathBuf::from(value(&mut args, &arg)?)),
+                _ => rest.push(arg),
+            }
+        }
+        return Ok((globals, rest));
+    }
+}
+
+/// What the command line asks to run.
+#[derive(Debug)]
+pub enum Command {
+    Convert(Options),
+    List(ListOptions),
+    Extract(ExtractOptions),
+    #[cfg(feature = "gdal")]
+    Compare(CompareOptions),
+    Tiles(TilesOptions),
+    Change(ChangeOptions),
+    #[cfg(feature = "gdal")]
+    Import(ImportOptions),
+    Undo(UndoOptions),
+    Batch(BatchOptions),
+    Watch(WatchOptions),
+    #[cfg(feature = "server")]
+    Serve(ServeOptions),
+}
+
+impl Command {
+    /// Parses the command line, with the flags set in the environment.
+    pub fn from_args() -> Result<(Command, Globals), String> {
+        let args = with_env(std::env::args().skip(1).collect(), std::env::vars());
+        let (globals, args) = Globals::take(args)?;
+        return Ok((Command::parse(args)?, globals));
+    }
+
+    /// Parses `args`, without the program name and the global flags.
+    pub fn parse(args: Vec<String>) -> Result<Command, String> {
+        let mut args = args.into_iter().peekable();
+        if args.peek().map(String::as_str) == Some("list") {
+            args.next();
+            return Ok(Command::List(ListOptions::parse(args)?));
+        }
+        if args.peek().map(String::as_str) == Some("extract") {
+            args.next();
+            return Ok(Command::Extract(ExtractOptions::parse(args)?));
+        }
+        if args.peek().map(String::as_str) == Some("tiles") {
+            args.next();
+            return Ok(Command::Tiles(TilesOptions::parse(args)?));
+        }
+        if args.peek().map(String::as_str) == Some("change") {
+            args.next();
+            return Ok(Command::Change(ChangeOptions::parse(args)?));
+        }
+        if args.peek().map(String::as_str) == Some("undo") {
+            args.next();
+            return Ok(Command::Undo(UndoOptions::parse(args)?));
+        }
+        if args.peek().map(String::as_str) == Some("batch") {
+            args.next();
+            return Ok(Command::Batch(BatchOptions::parse(args)?));
+        }
+        if args.peek().map(String::as_str) == Some("watch") {
+            args.next();
+            return Ok(Command::Watch(WatchOptions::parse(args)?));
+        }
+        #[cfg(feature = "server")]
+        {
+            if args.peek().map(String::as_str) == Some("serve") {
+                args.next();
+                return Ok(Command::Serve(ServeOptions::parse(args)?));
+            }
+        }
+        #[cfg(feature = "gdal")]
+        {
+            if args.peek().map(String::as_str) == Some("compare") {
+                args.next();
+                return Ok(Command::Compare(CompareOptions::parse(args)?));
+            }
+            if args.peek().map(String::as_str) == Some("import") {
+                args.next();
+                return Ok(Command::Import(ImportOptions::parse(args)?));
+            }
+        }
+        return Ok(Command::Convert(Options::parse(args)?));
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub memory_limit: usize,
+    pub jobs: Option<usize>,
+    #[cfg(feature = "gdal")]
+    pub gdal: GdalConfig,
+    #[cfg(feature = "gdal")]
+    pub stack: Option<String>,
+    #[cfg(feature = "gdal")]
+    pub stack_stat: String,
+    pub report: Option<PathBuf>,
+    pub keep_partial: bool,
+    pub resume: bool,
+    pub force: bool,
+    pub scratch: Option<String>,
+    pub swmr: Option<SwmrOptions>,
+    /// HDF5 files whose statistic datasets are added to those of the file.
+    pub update: Vec<String>,
+    pub flip: FlipOption,
+    pub direct_stats: bool,
+    pub min_count: u32,
+    pub nan_policy: NanPolicy,
+    pub single_obs_policy: SingleObsPolicy,
+    pub errors: ErrorOutputs,
+    pub quality: Option<QualityClasses>,
+    /// WASM modules of kernels whose outputs are written for each group.
+    #[cfg(feature = "wasm")]
+    pub wasm_kernels: Vec<String>,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub exists_policy: ExistsPolicy,
+    pub groups: Option<Vec<String>>,
+    pub datasets: Vec<String>,
+    pub time_series: Vec<String>,
+    pub time_coord: Option<String>,
+    pub tui: bool,
+    pub stat_names: StatNames,
+    pub follow_external_links: bool,
+    pub field: Option<String>,
+    pub strings_csv: Option<PathBuf>,
+    pub status_port: Option<u16>,
+    pub notify_url: Option<NotifyUrl>,
+    pub on_complete: Option<String>,
+    pub compression: H5Compression,
+    pub h5_resizable: bool,
+    pub h5_virtual: bool,
+    pub repack: bool,
+    /// Codec the numeric datasets are written again with when repacking.
+    pub repack_codec: Option<Codec>,
+    pub h5_cache: Option<ChunkCache>,
+    pub format: OutputFormat,
+    pub quicklook: Option<QuicklookOptions>,
+    pub verify: Option<VerifyOptions>,
+    pub verify_reverse: Option<usize>,
+    pub strict: bool,
+    pub summary: bool,
+    pub deterministic: bool,
+    pub provenance: bool,
+    pub stac: bool,
+    pub tile_scheme: Option<TileScheme>,
+    pub vrt: Option<String>,
+    pub qgis_project: Option<String>,
+    pub valid_range: Option<RangeOption>,
+    pub out_of_range: OutOfRange,
+    pub units: Vec<UnitRule>,
+    pub templates: Vec<TypedTemplate>,
+    pub group_grids: Vec<GroupGridRule>,
+    pub output_type: Option<RasterType>,
+    pub colors: Option<ColorStyle>,
+    pub overrides: Vec<DatasetOverride>,
+    pub shards: Vec<ShardedDataset>,
+    pub snap_scales: bool,
+    pub preview: Option<usize>,
+    pub geolocation: Option<GeolocationMode>,
+    pub geolocation_arrays: Option<ArrayPaths>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        return Options {
+            memory_limit: chunking::DEFAULT_MEMORY_LIMIT,
+            jobs: None,
+            #[cfg(feature = "gdal")]
+            gdal: GdalConfig::default(),
+            #[cfg(feature = "gdal")]
+            stack: None,
+            #[cfg(feature = "gdal")]
+            stack_stat: "mean".to_owned(),
+            report: None,
+            keep_partial: false,
+            resume: false,
+            force: false,
+            scratch: None,
+            swmr: None,
+            update: vec![],
+            flip: FlipOption::default(),
+            direct_stats: false,
+            min_count: 1,
+            nan_policy: NanPolicy::default(),
+            single_obs_policy: SingleObsPolicy::default(),
+            errors: ErrorOutputs::default(),
+            quality: None,
+            #[cfg(feature = "wasm")]
+            wasm_kernels: vec![],
+            retries: 0,
+            retry_delay: Duration::from_secs(1),
+            exists_policy: ExistsPolicy::default(),
+            groups: None,
+            datasets: vec![],
+            time_series: vec![],
+            time_coord: None,
+            tui: false,
+            stat_names: StatNames::default(),
+            follow_external_links: false,
+            field: None,
+            strings_csv: None,
+            status_port: None,
+            notify_url: None,
+            on_complete: None,
+            compression: H5Compression::default(),
+            h5_resizable: false,
+            h5_virtual: false,
+            repack: false,
+            repack_codec: None,
+            h5_cache: None,
+            format: OutputFormat::default(),
+            quicklook: None,
+            verify: None,
+            verify_reverse: None,
+            strict: false,
+            summary: false,
+            deterministic: false,
+            provenance: false,
+            stac: false,
+            tile_scheme: None,
+            vrt: None,
+            qgis_project: None,
+            valid_range: None,
+            out_of_range: OutOfRange::default(),
+            units: vec![],
+            templates: vec![],
+            group_grids: vec![],
+            output_type: None,
+            colors: None,
+            overrides: vec![],
+            shards: vec![],
+            snap_scales: false,
+            preview: None,
+            geolocation: None,
+            geolocation_arrays: None,
+        };
+    }
+}
+
+impl Options {
+    /// Compression the numeric datasets are written again with when
+    /// repacking, if any.
+    pub fn repack_compression(&self) -> Option<H5Compression> {
+        return self.repack_codec.map(|codec| H5Compression {
+            codec,
+            ..self.compression
+        });
+    }
+
+    pub fn convert_options(&self) -> ConvertOptions {
+        return ConvertOptions {
+            memory_limit: self.memory_limit,
+            exists_policy: self.exists_policy,
+            stat_names: self.stat_names.clone(),
+            field: self.field.clone(),
+            traversal: TraversalOptions {
+                follow_external_links: self.follow_external_links,
+            },
+            format: self.format,
+            quicklook: self.quicklook.clone(),
+            compression: self.compression,
+            resizable: self.h5_resizable,
+            virtual_rev: self.h5_virtual,
+            h5_cache: self.h5_cache,
+            valid_range: self.valid_range,
+            out_of_range: self.out_of_range,
+            units: self.units.clone(),
+            templates: self.templates.clone(),
+            output_type: self.output_type,
+            scratch: self.scratch.clone(),
+            swmr: self.swmr,
+            flip: self.flip,
+            group_jobs: 1,
+            direct_stats: self.direct_stats,
+            min_count: self.min_count,
+            errors: self.errors,
+            quality: self.quality,
+            kernels: vec![],
+            stac: self.stac,
+            tile_scheme: self.tile_scheme,
+            group_grids: self.group_grids.clone(),
+            verify_reverse: self.verify_reverse,
+            colors: self.colors.clone(),
+            overrides: self.overrides.clone(),
+            shards: self.shards.clone(),
+            snap_scales: self.snap_scales,
+            geolocation: self.geolocation.clone(),
+            geolocation_arrays: self.geolocation_arrays.clone(),
+        };
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        let mut band_order = None;
+        let mut sparse = false;
+        let mut float16 = false;
+        let mut layout = BlockLayout::default();
+        let mut band = None;
+        let mut color_map = None;
+        let mut sidecars = vec![];
+        let mut exists_policy = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--memory-limit" => {
+                    options.memory_limit = chunking::parse_memory_size(&value(&mut args, &arg)?)?;
+                }
+                "--retries" => {
+                    let retries = value(&mut args, &arg)?;
+                    options.retries = retries
+                        .parse()
+                        .map_err(|_| format!("invalid retry count '{}'", retries))?;
+                }
+                "--retry-delay" => {
+                    options.retry_delay = retry::parse_delay(&value(&mut args, &arg)?)?
+                }
+                "--jobs" => {
+                    let jobs = value(&mut args, &arg)?;
+                    options.jobs = match jobs.parse() {
+                        Ok(jobs) if jobs > 0 => Some(jobs),
+                        _ => return Err(format!("invalid job count '{}'", jobs)),
+                    };
+                }
+                #[cfg(feature = "gdal")]
+                "--gdal-threads" => {
+                    options.gdal.num_threads =
+                        Some(gdal_config::parse_num_threads(&value(&mut args, &arg)?)?);
+                }
+                #[cfg(feature = "gdal")]
+                "--gdal-cache" => {
+                    options.gdal.cache_max =
+                        Some(chunking::parse_memory_size(&value(&mut args, &arg)?)?);
+                }
+                #[cfg(feature = "gdal")]
+                "--stack" => options.stack = Some(value(&mut args, &arg)?),
+                #[cfg(feature = "gdal")]
+                "--stack-stat" => {
+                    options.stack_stat = parse_stat(&value(&mut args, &arg)?)?;
+                }
+                "--report" => options.report = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--keep-partial" => options.keep_partial = true,
+                "--resume" => options.resume = true,
+                "--force" => options.force = true,
+                "--scratch" => options.scratch = Some(value(&mut args, &arg)?),
+                "--swmr" => options.swmr = Some(options.swmr.unwrap_or_default()),
+                "--swmr-wait" => {
+                    options.swmr = Some(SwmrOptions {
+                        wait: retry::parse_delay(&value(&mut args, &arg)?)?,
+                        ..options.swmr.unwrap_or_default()
+                    })
+                }
+                "--update" => options.update.push(value(&mut args, &arg)?),
+                "--skip-existing" | "--overwrite" | "--fail-if-exists" => {
+                    exists_policy = ExistsPolicy::from_flag(&arg)
+                }
+                "--groups" => options.groups = Some(split_list(&value(&mut args, &arg)?)),
+                "--select" => {
+                    let selection = inventory::read_selection(&value(&mut args, &arg)?)
+                        .map_err(|err| err.to_string())?;
+                    options.groups = selection.groups;
+                    options.datasets = selection.datasets;
+                    options.time_series = selection.time_series;
+                }
+                "--datasets" => options.datasets = split_list(&value(&mut args, &arg)?),
+                "--time-series" => options.time_series = split_list(&value(&mut args, &arg)?),
+                "--time-coord" => options.time_coord = Some(value(&mut args, &arg)?),
+                "--config" => {
+                    let path = PathBuf::from(value(&mut args, &arg)?);
+                    options.overrides = overrides::load(&path).map_err(|err| err.to_string())?;
+                    options.shards = shards::load(&path).map_err(|err| err.to_string())?;
+                }
+                "--strict" => options.strict = true,
+                "--summary" => options.summary = true,
+                "--deterministic" => options.deterministic = true,
+                "--provenance" => options.provenance = true,
+                "--stac" => options.stac = true,
+                "--tile-scheme" => {
+                    options.tile_scheme = Some(TileScheme::parse(&value(&mut args, &arg)?)?)
+                }
+                "--vrt" => options.vrt = Some(value(&mut args, &arg)?),
+                "--qgis-project" => options.qgis_project = Some(value(&mut args, &arg)?),
+                "--valid-range" => {
+                    options.valid_range = Some(RangeOption::parse(&value(&mut args, &arg)?)?)
+                }
+                "--template" => {
+                    for template in split_list(&value(&mut args, &arg)?) {
+                        options.templates.push(TypedTemplate::parse(&template)?);
+                    }
+                }
+                "--snap-scales" => options.snap_scales = true,
+                "--geolocation" => {
+                    options.geolocation = Some(GeolocationMode::parse(&value(&mut args, &arg)?)?)
+                }
+                "--geolocation-arrays" => {
+                    options.geolocation_arrays = Some(ArrayPaths::parse(&value(&mut args, &arg)?)?)
+                }
+                "--group-grid" => options
+                    .group_grids
+                    .push(GroupGridRule::parse(&value(&mut args, &arg)?)?),
+                "--ot" => options.output_type = Some(RasterType::parse(&value(&mut args, &arg)?)?),
+                "--units" => {
+                    for rule in split_list(&value(&mut args, &arg)?) {
+                        let rule = UnitRule::parse(&rule, UnitConversion::parse_named)?;
+                        options.units.push(rule);
+                    }
+                }
+                "--unit-scale" => {
+                    for rule in split_list(&value(&mut args, &arg)?) {
+                        let rule = UnitRule::parse(&rule, UnitConversion::parse_scale)?;
+                        options.units.push(rule);
+                    }
+                }
+                "--color-table" => {
+                    let path = PathBuf::from(value(&mut args, &arg)?);
+                    color_map = Some(ColorMap::read(&path).map_err(|err| err.to_string())?);
+                }
+                "--color-sidecar" => {
+                    for name in split_list(&value(&mut args, &arg)?) {
+                        sidecars.push(Sidecar::parse(&name)?);
+                    }
+                }
+                "--out-of-range" => {
+                    options.out_of_range = OutOfRange::parse(&value(&mut args, &arg)?)?
+                }
+                #[cfg(feature = "tui")]
+                "--tui" => options.tui = true,
+                "--stat-names" => {
+                    options.stat_names =
+                        parse_stat_names(&value(&mut args, &arg)?, &options.stat_names)?
+                }
+                "--weight-names" => {
+                    options.stat_names.weights = Some(WeightNames::parse(&value(&mut args, &arg)?)?)
+                }
+                "--follow-external-links" => options.follow_external_links = true,
+                "--field" => options.field = Some(value(&mut args, &arg)?),
+                "--flip" => options.flip = FlipOption::parse(&value(&mut args, &arg)?)?,
+                "--direct-stats" => options.direct_stats = true,
+                "--se" => options.errors.se = true,
+                #[cfg(feature = "wasm")]
+                "--kernel" => options.wasm_kernels.push(value(&mut args, &arg)?),
+                "--quality-mask" => {
+                    options.quality = Some(QualityClasses::parse(&value(&mut args, &arg)?)?)
+                }
+                "--ci" => {
+                    options.errors.ci_z = Some(ErrorOutputs::parse_z(&value(&mut args, &arg)?)?)
+                }
+                "--min-count" => options.min_count = parse_min_count(&value(&mut args, &arg)?)?,
+                "--nan-policy" => options.nan_policy = NanPolicy::parse(&value(&mut args, &arg)?)?,
+                "--single-obs-policy" => {
+                    options.single_obs_policy = SingleObsPolicy::parse(&value(&mut args, &arg)?)?
+                }
+                "--strings-csv" => {
+                    options.strings_csv = Some(PathBuf::from(value(&mut args, &arg)?))
+                }
+                "--status-port" => {
+                    let port = value(&mut args, &arg)?;
+                    options.status_port = Some(
+                        port.parse()
+                            .map_err(|_| format!("invalid port '{}'", port))?,
+                    );
+                }
+                "--notify-url" => {
+                    options.notify_url = Some(NotifyUrl::parse(&value(&mut args, &arg)?)?)
+                }
+                "--on-complete" => options.on_complete = Some(value(&mut args, &arg)?),
+                "--h5-compress" => {
+                    options.compression.codec = Codec::parse(&value(&mut args, &arg)?)?
+                }
+                "--h5-shuffle" => {
+                    options.compression.shuffle = parse_on_off(&value(&mut args, &arg)?)?
+                }
+                "--h5-checksum" => options.compression.checksum = true,
+                "--h5-resizable" => options.h5_resizable = true,
+                "--h5-virtual" => options.h5_virtual = true,
+                "--repack" => options.repack = true,
+                "--repack-compress" => {
+                    options.repack = true;
+                    options.repack_codec = Some(Codec::parse(&value(&mut args, &arg)?)?);
+                }
+                "--h5-cache" => {
+                    options.h5_cache = Some(ChunkCache::parse(&value(&mut args, &arg)?)?)
+                }
+                "--format" => options.format = OutputFormat::parse(&value(&mut args, &arg)?)?,
+                "--band-order" => band_order = Some(BandOrder::parse(&value(&mut args, &arg)?)?),
+                "--sparse" => sparse = true,
+                "--float16" => float16 = true,
+                "--interleave" => {
+                    layout.interleave = Some(Interleave::parse(&value(&mut args, &arg)?)?)
+                }
+                "--block-size" => {
+                    layout.block_size =
+                        Some(BlockLayout::parse_block_size(&value(&mut args, &arg)?)?)
+                }
+                "--band" => {
+                    let text = value(&mut args, &arg)?;
+                    band = match text.parse() {
+                        Ok(band) if band > 0 => Some(band),
+                        _ => return Err(format!("invalid band '{}'", text)),
+                    };
+                }
+                "--quicklook" => {
+                    options
+                        .quicklook
+                        .get_or_insert_with(QuicklookOptions::default);
+                }
+                "--quicklook-ramp" => {
+                    let ramp = ColorRamp::parse(&value(&mut args, &arg)?)?;
+                    options
+                        .quicklook
+                        .get_or_insert_with(QuicklookOptions::default)
+                        .ramp = ramp;
+                }
+                "--quicklook-stretch" => {
+                    let stretch = quicklook::parse_stretch(&value(&mut args, &arg)?)?;
+                    options
+                        .quicklook
+                        .get_or_insert_with(QuicklookOptions::default)
+                        .stretch = stretch;
+                }
+                "--verify" => {
+                    options.verify.get_or_insert_with(VerifyOptions::default);
+                }
+                "--verify-windows" => {
+                    let windows = value(&mut args, &arg)?;
+                    options
+                        .verify
+                        .get_or_insert_with(VerifyOptions::default)
+                        .windows = windows
+                        .parse()
+                        .map_err(|_| format!("invalid window count '{}'", windows))?;
+                }
+                "--verify-epsilon" => {
+                    let epsilon = value(&mut args, &arg)?;
+                    options
+                        .verify
+                        .get_or_insert_with(VerifyOptions::default)
+                        .epsilon = epsilon
+                        .parse()
+                        .map_err(|_| format!("invalid epsilon '{}'", epsilon))?;
+                }
+                "--preview" => {
+                    let step = value(&mut args, &arg)?;
+                    options.preview = match step.parse::<usize>() {
+                        Ok(step) if step > 0 => Some(step),
+                        _ => return Err(format!("invalid preview step '{}'", step)),
+                    };
+                }
+                "--verify-reverse" => {
+                    options
+                        .verify_reverse
+                        .get_or_insert(verify::REVERSE_CHECK_ROWS);
+                }
+                "--verify-reverse-rows" => {
+                    let rows = value(&mut args, &arg)?;
+                    options.verify_reverse = Some(
+                        rows.parse()
+                            .map_err(|_| format!("invalid row count '{}'", rows))?,
+                    );
+                }
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+        if !options.update.is_empty() && options.resume {
+            return Err(
+                "--update cannot resume a run: the increments would be added again".to_owned(),
+            );
+        }
+        // The outputs of updated groups are stale, so they are replaced
+        // unless asked otherwise, and those written to a band are updated.
+        options.exists_policy = match exists_policy {
+            Some(policy) => policy,
+            None if !options.update.is_empty() || band.is_some() => ExistsPolicy::Overwrite,
+            None => ExistsPolicy::default(),
+        };
+        if band_order.is_some() || sparse || band.is_some() || float16 || !layout.is_default() {
+            options.format =
+                with_gdal_options(options.format, band_order, sparse, band, float16, layout)?;
+        }
+        options.colors = match color_map {
+            Some(map) => {
+                let embed = options.format.has_color_table();
+                if !embed && sidecars.is_empty() {
+                    return Err(format!(
+                        "--color-table needs --color-sidecar with .{} outputs",
+                        options.format.extension()
+                    ));
+                }
+                Some(ColorStyle {
+                    map,
+                    embed,
+                    sidecars,
+                })
+            }
+            None if !sidecars.is_empty() => {
+                return Err("--color-sidecar needs --color-table".to_owned())
+            }
+            None => None,
+        };
+        return Ok(options);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ListOptions {
+    pub json: bool,
+    pub stat_names: StatNames,
+    pub follow_external_links: bool,
+}
+
+impl ListOptions {
+    pub fn traversal(&self) -> TraversalOptions {
+        return TraversalOptions {
+            follow_external_links: self.follow_external_links,
+        };
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<ListOptions, String> {
+        let mut options = ListOptions::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => options.json = true,
+                "--stat-names" => {
+                    options.stat_names =
+                        parse_stat_names(&value(&mut args, &arg)?, &options.stat_names)?
+                }
+                "--weight-names" => {
+                    options.stat_names.weights = Some(WeightNames::parse(&value(&mut args, &arg)?)?)
+                }
+                "--follow-external-links" => options.follow_external_links = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown list argument '{}'", arg)),
+            }
+        }
+        return Ok(options);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractOptions {
+    pub points: PathBuf,
+    pub out: Option<PathBuf>,
+    pub groups: Option<Vec<String>>,
+    pub stat_names: StatNames,
+    pub field: Option<String>,
+    pub flip: FlipOption,
+    pub min_count: u32,
+    pub follow_external_links: bool,
+}
+
+impl ExtractOptions {
+    pub fn convert_options(&self) -> ConvertOptions {
+        return ConvertOptions {
+            stat_names: self.stat_names.clone(),
+            field: self.field.clone(),
+            traversal: TraversalOptions {
+                follow_external_links: self.follow_external_links,
+            },
+            flip: self.flip,
+            min_count: self.min_count,
+            ..ConvertOptions::default()
+        };
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<ExtractOptions, String> {
+        let mut options = ExtractOptions::default();
+        let mut points = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--points" => points = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--out" => options.out = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--groups" => options.groups = Some(split_list(&value(&mut args, &arg)?)),
+                "--stat-names" => {
+                    options.stat_names =
+                        parse_stat_names(&value(&mut args, &arg)?, &options.stat_names)?
+                }
+                "--weight-names" => {
+                    options.stat_names.weights = Some(WeightNames::parse(&value(&mut args, &arg)?)?)
+                }
+                "--field" => options.field = Some(value(&mut args, &arg)?),
+                "--flip" => options.flip = FlipOption::parse(&value(&mut args, &arg)?)?,
+                "--min-count" => options.min_count = parse_min_count(&value(&mut args, &arg)?)?,
+                "--follow-external-links" => options.follow_external_links = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown extract argument '{}'", arg)),
+            }
+        }
+        options.points = points.ok_or_else(|| "extract needs --points".to_owned())?;
+        return Ok(options);
+    }
+}
+
+#[cfg(feature = "gdal")]
+#[derive(Debug)]
+pub struct CompareOptions {
+    pub reference: String,
+    pub group: String,
+    pub stat: String,
+    pub diff: Option<String>,
+    pub report: Option<PathBuf>,
+    pub memory_limit: usize,
+    pub exists_policy: ExistsPolicy,
+    pub format: OutputFormat,
+    pub stat_names: StatNames,
+    pub field: Option<String>,
+    pub flip: FlipOption,
+    pub min_count: u32,
+}
+
+#[cfg(feature = "gdal")]
+impl CompareOptions {
+    pub fn convert_options(&self) -> ConvertOptions {
+        return ConvertOptions {
+            memory_limit: self.memory_limit,
+            exists_policy: self.exists_policy,
+            stat_names: self.stat_names.clone(),
+            field: self.field.clone(),
+            format: self.format,
+            flip: self.flip,
+            min_count: self.min_count,
+            ..ConvertOptions::default()
+        };
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<CompareOptions, String> {
+        let mut reference = None;
+        let mut group = None;
+        let mut options = CompareOptions {
+            reference: String::new(),
+            group: String::new(),
+            stat: "mean".to_owned(),
+            diff: None,
+            report: None,
+            memory_limit: chunking::DEFAULT_MEMORY_LIMIT,
+            exists_policy: ExistsPolicy::default(),
+            format: OutputFormat::default(),
+            stat_names: StatNames::default(),
+            field: None,
+            flip: FlipOption::default(),
+            min_count: 1,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--reference" => reference = Some(value(&mut args, &arg)?),
+                "--group" => group = Some(value(&mut args, &arg)?),
+                "--stat" => options.stat = parse_stat(&value(&mut args, &arg)?)?,
+                "--diff" => options.diff = Some(value(&mut args, &arg)?),
+                "--report" => options.report = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--memory-limit" => {
+                    options.memory_limit = chunking::parse_memory_size(&value(&mut args, &arg)?)?;
+                }
+                "--overwrite" => options.exists_policy = ExistsPolicy::Overwrite,
+                "--format" => options.format = OutputFormat::parse(&value(&mut args, &arg)?)?,
+                "--stat-names" => {
+                    options.stat_names =
+                        parse_stat_names(&value(&mut args, &arg)?, &options.stat_names)?
+                }
+                "--weight-names" => {
+                    options.stat_names.weights = Some(WeightNames::parse(&value(&mut args, &arg)?)?)
+                }
+                "--field" => options.field = Some(value(&mut args, &arg)?),
+                "--flip" => options.flip = FlipOption::parse(&value(&mut args, &arg)?)?,
+                "--min-count" => options.min_count = parse_min_count(&value(&mut args, &arg)?)?,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown compare argument '{}'", arg)),
+            }
+        }
+        options.reference = reference.ok_or_else(|| "compare needs --reference".to_owned())?;
+        options.group = group.ok_or_else(|| "compare needs --group".to_owned())?;
+        return Ok(options);
+    }
+}
+
+#[derive(Debug)]
+pub struct TilesOptions {
+    pub group: String,
+    pub stat: String,
+    pub out: PathBuf,
+    pub xyz: XyzOptions,
+    pub memory_limit: usize,
+    pub stat_names: StatNames,
+    pub field: Option<String>,
+    pub flip: FlipOption,
+    pub min_count: u32,
+}
+
+impl TilesOptions {
+    pub fn convert_options(&self) -> ConvertOptions {
+        return ConvertOptions {
+            memory_limit: self.memory_limit,
+            stat_names: self.stat_names.clone(),
+            field: self.field.clone(),
+            flip: self.flip,
+            min_count: self.min_count,
+            ..ConvertOptions::default()
+        };
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<TilesOptions, String> {
+        let mut group = None;
+        let mut out = None;
+        let mut options = TilesOptions {
+            group: String::new(),
+            stat: "mean".to_owned(),
+            out: PathBuf::new(),
+            xyz: XyzOptions::default(),
+            memory_limit: chunking::DEFAULT_MEMORY_LIMIT,
+            stat_names: StatNames::default(),
+            field: None,
+            flip: FlipOption::default(),
+            min_count: 1,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--group" => group = Some(value(&mut args, &arg)?),
+                "--out" => out = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--stat" => options.stat = parse_stat(&value(&mut args, &arg)?)?,
+                "--zoom" => options.xyz.zooms = Some(xyz::parse_zooms(&value(&mut args, &arg)?)?),
+                "--ramp" => options.xyz.ramp = ColorRamp::parse(&value(&mut args, &arg)?)?,
+                "--stretch" => {
+                    options.xyz.stretch = quicklook::parse_stretch(&value(&mut args, &arg)?)?
+                }
+                "--memory-limit" => {
+                    options.memory_limit = chunking::parse_memory_size(&value(&mut args, &arg)?)?;
+                }
+                "--stat-names" => {
+                    options.stat_names =
+                        parse_stat_names(&value(&mut args, &arg)?, &options.stat_names)?
+                }
+                "--weight-names" => {
+                    options.stat_names.weights = Some(WeightNames::parse(&value(&mut args, &arg)?)?)
+                }
+                "--field" => options.field = Some(value(&mut args, &arg)?),
+                "--flip" => options.flip = FlipOption::parse(&value(&mut args, &arg)?)?,
+                "--min-count" => options.min_count = parse_min_count(&value(&mut args, &arg)?)?,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown tiles argument '{}'", arg)),
+            }
+        }
+        options.group = group.ok_or_else(|| "tiles needs --group".to_owned())?;
+        options.out = out.ok_or_else(|| "tiles needs --out".to_owned())?;
+        return Ok(options);
+    }
+}
+
+#[derive(Debug)]
+pub struct ChangeOptions {
+    pub group_a: String,
+    pub group_b: String,
+    pub other: Option<String>,
+    pub out: Option<String>,
+    pub alpha: f64,
+    pub report: Option<PathBuf>,
+    pub memory_limit: usize,
+    pub exists_policy: ExistsPolicy,
+    pub format: OutputFormat,
+    pub stat_names: StatNames,
+    pub field: Option<String>,
+    pub flip: FlipOption,
+    pub min_count: u32,
+}
+
+impl ChangeOptions {
+    pub fn convert_options(&self) -> ConvertOptions {
+        return ConvertOptions {
+            memory_limit: self.memory_limit,
+            exists_policy: self.exists_policy,
+            stat_names: self.stat_names.clone(),
+            field: self.field.clone(),
+            format: self.format,
+            flip: self.flip,
+            min_count: self.min_count,
+            ..ConvertOptions::default()
+        };
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<ChangeOptions, String> {
+        let mut group_a = None;
+        let mut group_b = None;
+        let mut options = ChangeOptions {
+            group_a: String::new(),
+            group_b: String::new(),
+            other: None,
+            out: None,
+            alpha: 0.05,
+            report: None,
+            memory_limit: chunking::DEFAULT_MEMORY_LIMIT,
+            exists_policy: ExistsPolicy::default(),
+            format: OutputFormat::default(),
+            stat_names: StatNames::default(),
+            field: None,
+            flip: FlipOption::default(),
+            min_count: 1,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--group-a" => group_a = Some(value(&mut args, &arg)?),
+                "--group-b" => group_b = Some(value(&mut args, &arg)?),
+                "--other" => options.other = Some(value(&mut args, &arg)?),
+                "--out" => options.out = Some(value(&mut args, &arg)?),
+                "--alpha" => {
+                    let alpha = value(&mut args, &arg)?;
+                    options.alpha = match alpha.parse() {
+                        Ok(alpha) if alpha > 0f64 && alpha < 1f64 => alpha,
+                        _ => return Err(format!("invalid significance level '{}'", alpha)),
+                    };
+                }
+                "--report" => options.report = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--memory-limit" => {
+                    options.memory_limit = chunking::parse_memory_size(&value(&mut args, &arg)?)?;
+                }
+                "--overwrite" => options.exists_policy = ExistsPolicy::Overwrite,
+                "--format" => options.format = OutputFormat::parse(&value(&mut args, &arg)?)?,
+                "--stat-names" => {
+                    options.stat_names =
+                        parse_stat_names(&value(&mut args, &arg)?, &options.stat_names)?
+                }
+                "--weight-names" => {
+                    options.stat_names.weights = Some(WeightNames::parse(&value(&mut args, &arg)?)?)
+                }
+                "--field" => options.field = Some(value(&mut args, &arg)?),
+                "--flip" => options.flip = FlipOption::parse(&value(&mut args, &arg)?)?,
+                "--min-count" => options.min_count = parse_min_count(&value(&mut args, &arg)?)?,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown change argument '{}'", arg)),
+            }
+        }
+        options.group_a = group_a.ok_or_else(|| "change needs --group-a".to_owned())?;
+        options.group_b = match (group_b, &options.other) {
+            (Some(group_b), _) => group_b,
+            (None, Some(_)) => options.group_a.clone(),
+            (None, None) => return Err("change needs --group-b or --other".to_owned()),
+        };
+        return Ok(options);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct UndoOptions {
+    pub dry_run: bool,
+    pub repack: bool,
+    pub repack_codec: Option<Codec>,
+    pub force: bool,
+}
+
+impl UndoOptions {
+    pub fn repack_compression(&self) -> Option<H5Compression> {
+        return self.repack_codec.map(|codec| H5Compression {
+            codec,
+            ..H5Compression::default()
+        });
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<UndoOptions, String> {
+        let mut options = UndoOptions::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dry-run" => options.dry_run = true,
+                "--repack" => options.repack = true,
+                "--repack-compress" => {
+                    options.repack = true;
+                    options.repack_codec = Some(Codec::parse(&value(&mut args, &arg)?)?);
+                }
+                "--force" => options.force = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown undo argument '{}'", arg)),
+            }
+        }
+        return Ok(options);
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchOptions {
+    pub manifest: PathBuf,
+    pub jobs: usize,
+    pub report: Option<PathBuf>,
+}
+
+impl BatchOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<BatchOptions, String> {
+        let mut manifest = None;
+        let mut jobs = 1;
+        let mut report = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--manifest" => manifest = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--jobs" => {
+                    let text = value(&mut args, &arg)?;
+                    jobs = match text.parse::<usize>() {
+                        Ok(jobs) if jobs > 0 => jobs,
+                        _ => return Err(format!("invalid job count '{}'", text)),
+                    };
+                }
+                "--report" => report = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown batch argument '{}'", arg)),
+            }
+        }
+        return Ok(BatchOptions {
+            manifest: manifest.ok_or_else(|| "batch needs --manifest FILE".to_owned())?,
+            jobs,
+            report,
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct WatchOptions {
+    pub dir: PathBuf,
+    pub pattern: String,
+    pub manifest: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub interval: Duration,
+    pub once: bool,
+}
+
+impl WatchOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<WatchOptions, String> {
+        let mut dir = None;
+        let mut options = WatchOptions {
+            dir: PathBuf::new(),
+            pattern: "*.h5".to_owned(),
+            manifest: None,
+            profile: None,
+            interval: Duration::from_secs(5),
+            once: false,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dir" => dir = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--pattern" => options.pattern = value(&mut args, &arg)?,
+                "--manifest" => options.manifest = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--profile" => options.profile = Some(value(&mut args, &arg)?),
+                "--interval" => {
+                    let text = value(&mut args, &arg)?;
+                    options.interval = match text.parse::<f64>() {
+                        Ok(secs) if secs > 0f64 && secs.is_finite() => {
+                            Duration::from_secs_f64(secs)
+                        }
+                        _ => return Err(format!("invalid interval '{}'", text)),
+                    };
+                }
+                "--once" => options.once = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown watch argument '{}'", arg)),
+            }
+        }
+        if options.profile.is_some() && options.manifest.is_none() {
+            return Err("--profile needs the --manifest defining it".to_owned());
+        }
+        options.dir = dir.ok_or_else(|| "watch needs --dir DIR".to_owned())?;
+        return Ok(options);
+    }
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug)]
+pub struct ServeOptions {
+    pub bind: String,
+    pub port: u16,
+    pub manifest: Option<PathBuf>,
+    pub input_root: Option<PathBuf>,
+    pub output_root: Option<PathBuf>,
+}
+
+#[cfg(feature = "server")]
+impl ServeOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<ServeOptions, String> {
+        let mut options = ServeOptions {
+            bind: "127.0.0.1".to_owned(),
+            port: 8642,
+            manifest: None,
+            input_root: None,
+            output_root: None,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bind" => options.bind = value(&mut args, &arg)?,
+                "--port" => {
+                    let port = value(&mut args, &arg)?;
+                    options.port = port
+                        .parse()
+                        .map_err(|_| format!("invalid port '{}'", port))?;
+                }
+                "--manifest" => options.manifest = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--input-root" => options.input_root = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--output-root" => {
+                    options.output_root = Some(PathBuf::from(value(&mut args, &arg)?))
+                }
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown serve argument '{}'", arg)),
+            }
+        }
+        return Ok(options);
+    }
+}
+
+#[cfg(feature = "gdal")]
+#[derive(Debug)]
+pub struct ImportOptions {
+    pub raster: String,
+    pub dataset: String,
+    pub force: bool,
+    pub import: import::ImportOptions,
+}
+
+#[cfg(feature = "gdal")]
+impl ImportOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<ImportOptions, String> {
+        let mut raster = None;
+        let mut dataset = None;
+        let mut options = ImportOptions {
+            raster: String::new(),
+            dataset: String::new(),
+            force: false,
+            import: import::ImportOptions::default(),
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--raster" => raster = Some(value(&mut args, &arg)?),
+                "--dataset" => dataset = Some(value(&mut args, &arg)?),
+                "--band" => {
+                    let band = value(&mut args, &arg)?;
+                    options.import.band = match band.parse() {
+                        Ok(band) if band > 0 => band,
+                        _ => return Err(format!("invalid band '{}'", band)),
+                    };
+                }
+                "--ot" => {
+                    options.import.data_type = Some(RasterType::parse(&value(&mut args, &arg)?)?)
+                }
+                "--flip" => options.import.flip = FlipOption::parse(&value(&mut args, &arg)?)?,
+                "--overwrite" => options.import.exists_policy = ExistsPolicy::Overwrite,
+                "--fail-if-exists" => options.import.exists_policy = ExistsPolicy::FailIfExists,
+                "--force" => options.force = true,
+                "--memory-limit" => {
+                    options.import.memory_limit =
+                        chunking::parse_memory_size(&value(&mut args, &arg)?)?;
+                }
+                "--h5-compress" => {
+                    options.import.compression.codec = Codec::parse(&value(&mut args, &arg)?)?
+                }
+                "--h5-shuffle" => {
+                    options.import.compression.shuffle = parse_on_off(&value(&mut args, &arg)?)?
+                }
+                "--h5-checksum" => options.import.compression.checksum = true,
+                "--h5-resizable" => options.import.resizable = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown import argument '{}'", arg)),
+            }
+        }
+        options.raster = raster.ok_or_else(|| "import needs --raster".to_owned())?;
+        options.dataset = dataset.ok_or_else(|| "import needs --dataset".to_owned())?;
+        return Ok(options);
+    }
+}
+
+/// Parses an on|off switch.
+fn parse_on_off(text: &str) -> Result<bool, String> {
+    return match text {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        other => Err(format!("expected on or off, got '{}'", other)),
+    };
+}
+
+/// Parses `--stat-names`, keeping the weighted sums of `current` set by
+/// `--weight-names`.
+fn parse_stat_names(text: &str, current: &StatNames) -> Result<StatNames, String> {
+    return Ok(StatNames {
+        weights: current.weights.clone(),
+        ..StatNames::parse(text)?
+    });
+}
+
+/// Parses `--min-count`, the observations a pixel needs for a mean and sd.
+fn parse_min_count(text: &str) -> Result<u32, String> {
+    return text
+        .parse()
+        .map_err(|_| format!("invalid minimum count '{}'", text));
+}
+
+/// Parses a statistic output name: mean, sd or count.
+fn parse_stat(stat: &str) -> Result<String, String> {
+    return match stat {
+        "mean" | "sd" | "count" => Ok(stat.to_owned()),
+        _ => Err(format!(
+            "unknown statistic '{}' (expected mean, sd or count)",
+            stat
+        )),
+    };
+}
+
+/// Applies `--band-order`, `--sparse`, `--band`, `--float16`,
+/// `--interleave` and `--block-size`, which only the gdal format accepts.
+fn with_gdal_options(
+    format: OutputFormat,
+    band_order: Option<BandOrder>,
+    sparse: bool,
+    band: Option<usize>,
+    float16: bool,
+    layout: BlockLayout,
+) -> Result<OutputFormat, String> {
+    match format {
+        #[cfg(feature = "gdal")]
+        OutputFormat::Gdal(mut options) => {
+            if let Some(order) = band_order {
+                options.band_order = order;
+            }
+            options.sparse |= sparse;
+            if options.sparse && band.is_some() {
+                return Err("--sparse creates new outputs, it cannot write a --band".to_owned());
+            }
+            if (float16 || !layout.is_default()) && band.is_some() {
+                return Err(
+                    "--float16, --interleave and --block-size create new outputs, they cannot \
+                     write a --band"
+                        .to_owned(),
+                );
+            }
+            options.band = band;
+            options.float16 |= float16;
+            options.layout = layout;
+            return Ok(OutputFormat::Gdal(options));
+        }
+        #[allow(unreachable_patterns)]
+        _ => {
+            let _ = (band_order, sparse, band, float16, layout);
+            return Err(
+                "--band-order, --sparse, --band, --float16, --interleave and \
+                        --block-size only apply to the gdal format"
+                    .to_owned(),
+            );
+        }
+    }
+}
+
+/// Splits a comma-separated list, dropping empty names.
+fn split_list(text: &str) -> Vec<String> {
+    return text
+        .split(',')
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect();
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    return args
+        .next()
+        .ok_or_else(|| format!("missing value for '{}'", flag));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        return args.iter().map(|arg| (*arg).to_owned()).collect();
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        return vars
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect();
+    }
+
+    #[test]
+    fn env_names_are_the_flags_in_upper_snake_case() {
+        assert_eq!(env_name("--input"), "RS_H2R_INPUT");
+        assert_eq!(env_name("--memory-limit"), "RS_H2R_MEMORY_LIMIT");
+        assert_eq!(env_name("--retry-delay"), "RS_H2R_RETRY_DELAY");
+    }
+
+    #[test]
+    fn command_line_flags_take_precedence_over_the_environment() {
+        let args = with_env(
+            strings(&["--input", "b.h5", "--jobs", "8"]),
+            vars(&[("RS_H2R_INPUT", "a.h5"), ("RS_H2R_JOBS", "2")]),
+        );
+        assert_eq!(
+            args,
+            strings(&["--input", "a.h5", "--jobs", "2", "--input", "b.h5", "--jobs", "8"])
+        );
+        let (globals, rest) = Globals::take(args).unwrap();
+        assert_eq!(globals.input, "b.h5");
+        match Command::parse(rest).unwrap() {
+            Command::Convert(options) => assert_eq!(options.jobs, Some(8)),
+            command => panic!("parsed {:?}", command),
+        }
+    }
+
+    #[test]
+    fn subcommands_only_take_the_global_flags_from_the_environment() {
+        let args = with_env(
+            strings(&["list", "--json"]),
+            vars(&[("RS_H2R_OUTDIR", "out"), ("RS_H2R_JOBS", "2")]),
+        );
+        assert_eq!(args, strings(&["list", "--outdir", "out", "--json"]));
+    }
+
+    #[test]
+    fn empty_variables_are_ignored() {
+        let args = with_env(
+            strings(&["--groups", "agbd"]),
+            vars(&[("RS_H2R_JOBS", ""), ("RS_H2R_REPORT", "run.json")]),
+        );
+        assert_eq!(args, strings(&["--report", "run.json", "--groups", "agbd"]));
+    }
+}
@@ -0,0 +1,291 @@
+//! Color tables for byte outputs (counts and class rasters), embedded in
+//! GDAL outputs or written as QGIS `.qml` or `.clr` sidecars, so they render
+//! meaningfully without styling them by hand.
+
+use crate::error::{Error, Result};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorEntry {
+    pub value: u8,
+    /// Red, green, blue and alpha.
+    pub rgba: [u8; 4],
+    pub label: Option<String>,
+}
+
+/// Colors of byte values, sorted by value.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColorMap {
+    pub entries: Vec<ColorEntry>,
+}
+
+/// Sidecar file styling an output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sidecar {
+    /// QGIS layer style with a paletted renderer, loaded with the layer.
+    Qml,
+    /// `value red green blue` lines, read by QGIS, ArcGIS and GDAL.
+    Clr,
+}
+
+impl Sidecar {
+    pub fn parse(text: &str) -> std::result::Result<Sidecar, String> {
+        return match text {
+            "qml" => Ok(Sidecar::Qml),
+            "clr" => Ok(Sidecar::Clr),
+            _ => Err(format!(
+                "unknown color sidecar '{}' (expected qml or clr)",
+                text
+            )),
+        };
+    }
+
+    pub fn extension(self) -> &'static str {
+        return match self {
+            Sidecar::Qml => "qml",
+            Sidecar::Clr => "clr",
+        };
+    }
+}
+
+/// Color map of byte outputs and how it is attached to them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColorStyle {
+    pub map: ColorMap,
+    /// Embed the map as the color table of GDAL outputs.
+    pub embed: bool,
+    pub sidecars: Vec<Sidecar>,
+}
+
+impl ColorMap {
+    pub fn read(path: &Path) -> Result<ColorMap> {
+        return ColorMap::parse(&std::fs::read_to_string(path)?);
+    }
+
+    /// Parses lines of `VALUE RED GREEN BLUE [ALPHA] [LABEL]`, separated by
+    /// spaces or commas, where `VALUE` may be a `MIN-MAX` range of values
+    /// sharing a color. Empty lines and lines starting with `#` are skipped;
+    /// later lines override earlier ones.
+    pub fn parse(text: &str) -> Result<ColorMap> {
+        let mut colors: Vec<Option<ColorEntry>> = vec![None; 256];
+        for (ii, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::Invalid(format!("invalid color in line {}: {}", ii + 1, line));
+            let fields: Vec<&str> = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|field| !field.is_empty())
+                .collect();
+            if fields.len() < 4 {
+                return Err(invalid());
+            }
+            let (min, max) = match fields[0].split_once('-') {
+                Some((min, max)) => (min.parse::<u8>(), max.parse::<u8>()),
+                None => (fields[0].parse::<u8>(), fields[0].parse::<u8>()),
+            };
+            let (min, max) = match (min, max) {
+                (Ok(min), Ok(max)) if min <= max => (min, max),
+                _ => return Err(invalid()),
+            };
+            let mut rgba = [0u8, 0, 0, 255];
+            for (channel, field) in rgba.iter_mut().zip(fields[1..4].iter()) {
+                *channel = field.parse().map_err(|_| invalid())?;
+            }
+            let mut rest = &fields[4..];
+            if let Some(alpha) = rest.first().and_then(|field| field.parse::<u8>().ok()) {
+                rgba[3] = alpha;
+                rest = &rest[1..];
+            }
+            let label = if rest.is_empty() {
+                None
+            } else {
+                Some(rest.join(" "))
+            };
+            for value in min..=max {
+                colors[value as usize] = Some(ColorEntry {
+                    value,
+                    rgba,
+                    label: label.clone(),
+                });
+            }
+        }
+        let entries: Vec<ColorEntry> = colors.into_iter().flatten().collect();
+        if entries.is_empty() {
+            return Err(Error::Invalid("color table has no colors".to_owned()));
+        }
+        return Ok(ColorMap { entries });
+    }
+
+    /// The map as a `.clr` file.
+    pub fn to_clr(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.entries {
+            let [red, green, blue, _] = entry.rgba;
+            text.push_str(&format!("{} {} {} {}\n", entry.value, red, green, blue));
+        }
+        return text;
+    }
+
+    /// The map as a QGIS style with a paletted renderer of band 1; values
+    /// without a label are labelled with the value.
+    pub fn to_qml(&self) -> String {
+        let mut text = String::from(
+            "<!DOCTYPE qgis PUBLIC 'http://mrcc.com/qgis.dtd' 'SYSTEM'>\n\
+             <qgis styleCategories=\"Symbology\">\n  <pipe>\n    \
+             <rasterrenderer type=\"paletted\" band=\"1\" opacity=\"1\" alphaBand=\"-1\">\n      \
+             <colorPalette>\n",
+        );
+        for entry in &self.entries {
+            let [red, green, blue, alpha] = entry.rgba;
+            let label = match &entry.label {
+                Some(label) => xml_escape(label),
+                None => entry.value.to_string(),
+            };
+            text.push_str(&format!(
+                "        <paletteEntry value=\"{}\" color=\"#{:02x}{:02x}{:02x}\" alpha=\"{}\" label=\"{}\"/>\n",
+                entry.value, red, green, blue, alpha, label
+            ));
+        }
+        text.push_str("      </colorPalette>\n    </rasterrenderer>\n  </pipe>\n</qgis>\n");
+        return text;
+    }
+
+    /// Writes the `sidecar` of the output at `out_path`, named like it with
+    /// the sidecar's extension, and returns its path.
+    pub fn write_sidecar(&self, out_path: &str, sidecar: Sidecar) -> Result<String> {
+        let path = Path::new(out_path).with_extension(sidecar.extension());
+        let text = match sidecar {
+            Sidecar::Qml => self.to_qml(),
+            Sidecar::Clr => self.to_clr(),
+        };
+        std::fs::write(&path, text)?;
+        return Ok(path.to_string_lossy().into_owned());
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+/// Sets `map` as the color table of band `band` (from 1) of the GDAL raster
+/// at `out_path`; values missing from the map are transparent.
+#[cfg(feature = "gdal")]
+pub fn embed_color_table(out_path: &str, band: usize, map: &ColorMap) -> Result<()> {
+    let rast = gdal::Dataset::open_ex(
+        Path::new(out_path),
+        Some(gdal_sys::GDALAccess::GA_Update),
+        None,
+        None,
+        None,
+    )?;
+    let band = rast.rasterband(band as isize)?;
+    let err = unsafe {
+        let table = gdal_sys::GDALCreateColorTable(gdal_sys::GDALPaletteInterp::GPI_RGB);
+        for entry in &map.entries {
+            let [red, green, blue, alpha] = entry.rgba;
+            let color = gdal_sys::GDALColorEntry {
+                c1: red as i16,
+                c2: green as i16,
+                c3: blue as i16,
+                c4: alpha as i16,
+            };
+            gdal_sys::GDALSetColorEntry(table, entry.value as i32, &color);
+        }
+        let err = gdal_sys::GDALSetRasterColorTable(band.c_rasterband(), table);
+        gdal_sys::GDALDestroyColorTable(table);
+        err
+    };
+    if err != gdal_sys::CPLErr::CE_None {
+        return Err(Error::Invalid(format!(
+            "could not set the color table of {}",
+            out_path
+        )));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: u8, rgba: [u8; 4], label: Option<&str>) -> ColorEntry {
+        return ColorEntry {
+            value,
+            rgba,
+            label: label.map(|label| label.to_owned()),
+        };
+    }
+
+    #[test]
+    fn parses_ranges_alpha_and_labels() {
+        let map = ColorMap::parse(
+            "# class colours\n\
+             \n\
+             0 0 0 0 0 No data\n\
+             1-3, 255, 0, 0, Low\n\
+             2 0 255 0 128\n\
+             255 255 255 255\n",
+        )
+        .unwrap();
+        assert_eq!(
+            map.entries,
+            vec![
+                entry(0, [0, 0, 0, 0], Some("No data")),
+                entry(1, [255, 0, 0, 255], Some("Low")),
+                entry(2, [0, 255, 0, 128], None),
+                entry(3, [255, 0, 0, 255], Some("Low")),
+                entry(255, [255, 255, 255, 255], None),
+            ]
+        );
+        assert_eq!(
+            map.to_clr(),
+            "0 0 0 0\n1 255 0 0\n2 0 255 0\n3 255 0 0\n255 255 255 255\n"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        for text in &[
+            "1 255 0",
+            "256 0 0 0",
+            "-1 0 0 0",
+            "3-1 0 0 0",
+            "1 0 0 300",
+            "one 0 0 0",
+            "# only a comment",
+        ] {
+            assert!(
+                matches!(ColorMap::parse(text), Err(Error::Invalid(_))),
+                "parsed {}",
+                text
+            );
+        }
+        match ColorMap::parse("0 0 0 0\n1 red 0 0") {
+            Err(Error::Invalid(msg)) => assert!(msg.contains("line 2"), "{}", msg),
+            other => panic!("parsed {:?}", other),
+        }
+    }
+
+    #[test]
+    fn labels_are_escaped_in_styles() {
+        let map = ColorMap::parse("7 1 2 3 Trees & <shrubs>").unwrap();
+        let qml = map.to_qml();
+        assert!(qml.contains(
+            "<paletteEntry value=\"7\" color=\"#010203\" alpha=\"255\" \
+             label=\"Trees &amp; &lt;shrubs&gt;\"/>"
+        ));
+    }
+
+    #[test]
+    fn parses_sidecars() {
+        assert_eq!(Sidecar::parse("qml"), Ok(Sidecar::Qml));
+        assert_eq!(Sidecar::parse("clr"), Ok(Sidecar::Clr));
+        assert!(Sidecar::parse("sld").is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Comparison of a group statistic with a reference raster (`gdal` feature).
+//!
+//! The statistic is recomputed from the original statistic datasets and the
+//! reference read through GDAL, strip by strip, accumulating the error
+//! metrics and optionally writing the difference (statistic - reference) to
+//! a raster. This validates a new product version against a previous one.
+
+use crate::error::{Error, Result};
+use crate::raster::{self, RasterSink};
+use crate::stats::{self, StatNames};
+use crate::verify::read_stat_rows;
+use crate::{interrupt, status};
+use serde::Serialize;
+
+/// Error metrics of a statistic against a reference raster, over the pixels
+/// where both have a value.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Comparison {
+    pub group: String,
+    pub stat: String,
+    pub reference: String,
+    /// Pixels compared.
+    pub compared: u64,
+    /// Pixels without a value in the statistic or the reference.
+    pub skipped: u64,
+    /// Mean difference, statistic - reference.
+    pub bias: f64,
+    pub rmse: f64,
+    pub mae: f64,
+    pub max_abs_diff: f64,
+}
+
+/// Sums accumulated over the strips.
+#[derive(Debug, Default)]
+struct Totals {
+    compared: u64,
+    skipped: u64,
+    sum: f64,
+    sum_sq: f64,
+    sum_abs: f64,
+    max_abs: f64,
+}
+
+impl Totals {
+    /// Adds one pixel and returns its difference, NaN if it is skipped.
+    fn add(
+        &mut self,
+        value: f64,
+        reference: f64,
+        nodata: Option<f64>,
+        ref_nodata: Option<f64>,
+    ) -> f32 {
+        let missing = |val: f64, nodata: Option<f64>| {
+            return !val.is_finite() || nodata.map_or(false, |nodata| val == nodata);
+        };
+        if missing(value, nodata) || missing(reference, ref_nodata) {
+            self.skipped += 1;
+            return f32::NAN;
+        }
+        let diff = value - reference;
+        self.compared += 1;
+        self.sum += diff;
+        self.sum_sq += diff * diff;
+        self.sum_abs += diff.abs();
+        self.max_abs = self.max_abs.max(diff.abs());
+        return diff as f32;
+    }
+}
+
+/// Compares statistic `stat` (`mean`, `sd` or `count`) of `group` with band
+/// 1 of the raster at `reference`, in strips of `n_lines` rows. The
+/// difference is written to `diff` if given, NaN where a pixel is skipped.
+pub fn compare_group(
+    file: &hdf5::File,
+    group: &str,
+    stat_names: &StatNames,
+    field: Option<&str>,
+    stat: &str,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    min_count: u32,
+    reference: &str,
+    n_lines: usize,
+    diff: Option<&mut dyn RasterSink<f32>>,
+) -> Result<Comparison> {
+    let ref_rast = gdal::Dataset::open(std::path::Path::new(reference))?;
+    if ref_rast.raster_size() != (xsize, ysize) {
+        return Err(Error::Invalid(format!(
+            "reference {} is {}x{}, the grid is {}x{}",
+            reference,
+            ref_rast.raster_size().0,
+            ref_rast.raster_size().1,
+            xsize,
+            ysize
+        )));
+    }
+    let ref_band = ref_rast.rasterband(1)?;
+    let ref_nodata = ref_band.no_data_value();
+    let wide = field.is_none() && stats::is_wide(file, group, stat_names, "")?;
+    // Counts of 0 are values; pixels without observations have no mean or sd.
+    let nodata = match stat {
+        "count" => None,
+        _ => Some(raster::empty_value(stat)),
+    };
+
+    let mut totals = Totals::default();
+    let mut next_strip = |yy: usize, lines: usize| -> Result<Vec<f32>> {
+        interrupt::check()?;
+        status::set_progress(yy as f32 / ysize as f32);
+        let values = read_stat_rows(
+            file,
+            group,
+            stat_names,
+            field,
+            stat,
+            (xsize, ysize),
+            flip,
+            (min_count, wide),
+            yy,
+            lines,
+        )?;
+        let reference = ref_band
+            .read_as::<f64>((0, yy as isize), (xsize, lines), (xsize, lines), None)?
+            .data;
+        return Ok(values
+            .iter()
+            .zip(reference.iter())
+            .map(|(&value, &reference)| totals.add(value, reference, nodata, ref_nodata))
+            .collect());
+    };
+    match diff {
+        Some(sink) => sink.write_strips(xsize, ysize, n_lines, &mut next_strip)?,
+        None => {
+            for yy in (0..ysize).step_by(n_lines.max(1)) {
+                next_strip(yy, n_lines.min(ysize - yy))?;
+            }
+        }
+    }
+
+    let n = totals.compared.max(1) as f64;
+    return Ok(Comparison {
+        group: group.to_owned(),
+        stat: stat.to_owned(),
+        reference: reference.to_owned(),
+        compared: totals.compared,
+        skipped: totals.skipped,
+        bias: totals.sum / n,
+        rmse: (totals.sum_sq / n).sqrt(),
+        mae: totals.sum_abs / n,
+        max_abs_diff: totals.max_abs,
+    });
+}
@@ -0,0 +1,139 @@
+//! Reading a single member field out of compound-typed datasets.
+
+use crate::error::{Error, Result};
+use crate::reverse::ChunkReader;
+use crate::status;
+use crate::timing::{self, Stage};
+use hdf5::types::TypeDescriptor;
+use hdf5_sys::h5d::H5Dread;
+use hdf5_sys::h5i::H5Idec_ref;
+use hdf5_sys::h5p::H5P_DEFAULT;
+use hdf5_sys::h5s::{H5S_seloper_t, H5Screate_simple, H5Sselect_hyperslab};
+use hdf5_sys::h5t::{H5T_class_t, H5Tcreate, H5Tinsert};
+use std::ffi::CString;
+use std::time::Instant;
+
+/// Names of the member fields if `ds` has a compound datatype.
+pub fn compound_fields(ds: &hdf5::Dataset) -> Result<Option<Vec<String>>> {
+    return match ds.dtype()?.to_descriptor()? {
+        TypeDescriptor::Compound(compound) => Ok(Some(
+            compound
+                .fields
+                .iter()
+                .map(|field| field.name.clone())
+                .collect(),
+        )),
+        _ => Ok(None),
+    };
+}
+
+/// Reader for the `field` of `ds` if it is a compound dataset, `None` for
+/// plain datasets. Fails if `ds` is compound and no field was given.
+pub fn field_reader<'a>(
+    ds: &'a hdf5::Dataset,
+    field: Option<&str>,
+) -> Result<Option<FieldChunks<'a>>> {
+    return match compound_fields(ds)? {
+        None => Ok(None),
+        Some(fields) => match field {
+            Some(field) => Ok(Some(FieldChunks::new(ds, field)?)),
+            None => Err(Error::Invalid(format!(
+                "dataset {} has a compound type, select one of its fields ({}) with --field",
+                ds.name(),
+                fields.join(", ")
+            ))),
+        },
+    };
+}
+
+/// Reads one member field of a compound dataset as plain `T` values, letting
+/// HDF5 convert the field to the native type of `T`.
+pub struct FieldChunks<'a> {
+    ds: &'a hdf5::Dataset,
+    field: CString,
+}
+
+impl<'a> FieldChunks<'a> {
+    pub fn new(ds: &'a hdf5::Dataset, field: &str) -> Result<FieldChunks<'a>> {
+        let fields = compound_fields(ds)?.unwrap_or_default();
+        if !fields.iter().any(|name| name == field) {
+            return Err(Error::Invalid(format!(
+                "dataset {} has no field '{}' (available: {})",
+                ds.name(),
+                field,
+                fields.join(", ")
+            )));
+        }
+        let field = CString::new(field)
+            .map_err(|_| Error::Invalid(format!("invalid field name '{}'", field)))?;
+        return Ok(FieldChunks { ds, field });
+    }
+}
+
+impl<'a, T: hdf5::H5Type> ChunkReader<T> for FieldChunks<'a> {
+    type Error = Error;
+
+    fn read_range(&self, start: usize, end: usize) -> Result<ndarray::Array1<T>> {
+        let n_vals = end - start;
+        // Of a 2-d dataset, the rows covering the range are read and
+        // trimmed to it.
+        let (offset, count, skip) = match self.ds.shape().as_slice() {
+            [_, ncols] if n_vals > 0 => {
+                let (first_row, end_row) = (start / ncols, (end + ncols - 1) / ncols);
+                (
+                    vec![first_row as u64, 0],
+                    vec![(end_row - first_row) as u64, *ncols as u64],
+                    start - first_row * ncols,
+                )
+            }
+            _ => (vec![start as u64], vec![n_vals as u64], 0),
+        };
+        let n_read = count.iter().product::<u64>() as usize;
+        let field_type = hdf5::Datatype::from_type::<T>()?;
+        let mut vals: Vec<T> = Vec::with_capacity(n_read);
+        let started = Instant::now();
+
+        let status = hdf5::sync::sync(|| unsafe {
+            let mem_type = H5Tcreate(H5T_class_t::H5T_COMPOUND, std::mem::size_of::<T>());
+            H5Tinsert(mem_type, self.field.as_ptr(), 0, field_type.id());
+
+            let file_space = hdf5_sys::h5d::H5Dget_space(self.ds.id());
+            H5Sselect_hyperslab(
+                file_space,
+                H5S_seloper_t::H5S_SELECT_SET,
+                offset.as_ptr(),
+                std::ptr::null(),
+                count.as_ptr(),
+                std::ptr::null(),
+            );
+            let mem_count = [n_read as u64];
+            let mem_space = H5Screate_simple(1, mem_count.as_ptr(), std::ptr::null());
+
+            let status = H5Dread(
+                self.ds.id(),
+                mem_type,
+                mem_space,
+                file_space,
+                H5P_DEFAULT,
+                vals.as_mut_ptr() as *mut _,
+            );
+            H5Idec_ref(mem_space);
+            H5Idec_ref(file_space);
+            H5Idec_ref(mem_type);
+            return status;
+        });
+        if status < 0 {
+            return Err(Error::Invalid(format!(
+                "could not read field '{}' of {}",
+                self.field.to_string_lossy(),
+                self.ds.name()
+            )));
+        }
+        unsafe { vals.set_len(n_read) };
+        let vals: Vec<T> = vals.into_iter().skip(skip).take(n_vals).collect();
+        let bytes = n_read * std::mem::size_of::<T>();
+        timing::add(Stage::Read, started.elapsed(), bytes);
+        status::add_bytes(bytes);
+        return Ok(ndarray::Array1::from(vals));
+    }
+}
@@ -0,0 +1,240 @@
+//! Compression of the datasets written back to HDF5 (`*_rev`, `mean_rev`,
+//! `sd_rev`).
+//!
+//! gzip is built into HDF5. ZSTD and LZ4 are registered filter plugins
+//! (e.g. from hdf5plugin), found through `HDF5_PLUGIN_PATH`; readers of the
+//! file need the same plugins.
+//!
+//! With a checksum, each chunk also gets a Fletcher32 checksum, which HDF5
+//! checks whenever the chunk is read back (to compute the mean and sd or
+//! write the rasters), so corruption on flaky storage fails the read instead
+//! of reaching the outputs.
+
+use crate::error::{Error, Result};
+use crate::hdf::DatasetLayout;
+use hdf5_sys::h5::hid_t;
+use hdf5_sys::h5d::H5Dcreate2;
+use hdf5_sys::h5i::H5Idec_ref;
+use hdf5_sys::h5p::{
+    H5Pcreate, H5Pset_chunk, H5Pset_create_intermediate_group, H5Pset_deflate, H5Pset_fill_value,
+    H5Pset_filter, H5Pset_fletcher32, H5Pset_shuffle, H5P_CLS_DATASET_CREATE, H5P_CLS_LINK_CREATE,
+    H5P_DEFAULT,
+};
+use hdf5_sys::h5s::{H5Screate_simple, H5S_UNLIMITED};
+use hdf5_sys::h5z::{H5Z_filter_t, H5Zfilter_avail, H5Z_FLAG_MANDATORY};
+use std::ffi::CString;
+
+/// Registered filter id of the ZSTD plugin.
+pub const ZSTD_FILTER: H5Z_filter_t = 32015;
+/// Registered filter id of the LZ4 plugin.
+pub const LZ4_FILTER: H5Z_filter_t = 32004;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    None,
+    Gzip(u8),
+    Zstd(u8),
+    Lz4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct H5Compression {
+    pub codec: Codec,
+    /// Byte shuffle before compressing, which helps floats compress.
+    pub shuffle: bool,
+    /// Fletcher32 checksum of each chunk, checked when it is read.
+    pub checksum: bool,
+}
+
+impl Default for H5Compression {
+    fn default() -> Self {
+        return H5Compression {
+            codec: Codec::Gzip(1),
+            shuffle: false,
+            checksum: false,
+        };
+    }
+}
+
+impl Codec {
+    /// Parses `zstd:3`, `gzip:6`, `lz4` or `none`; the level may be omitted.
+    pub fn parse(text: &str) -> std::result::Result<Codec, String> {
+        let mut parts = text.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let level = match parts.next() {
+            None => None,
+            Some(level) => Some(
+                level
+                    .parse::<u8>()
+                    .map_err(|_| format!("invalid compression level in '{}'", text))?,
+            ),
+        };
+        return match (name, level) {
+            ("none", None) => Ok(Codec::None),
+            ("gzip", level) => match level.unwrap_or(1) {
+                level @ 0..=9 => Ok(Codec::Gzip(level)),
+                _ => Err(format!("gzip level must be 0-9, got '{}'", text)),
+            },
+            ("zstd", level) => match level.unwrap_or(3) {
+                level @ 1..=22 => Ok(Codec::Zstd(level)),
+                _ => Err(format!("zstd level must be 1-22, got '{}'", text)),
+            },
+            ("lz4", None) => Ok(Codec::Lz4),
+            _ => Err(format!(
+                "unknown compression '{}' (expected zstd:LEVEL, gzip:LEVEL, lz4 or none)",
+                text
+            )),
+        };
+    }
+
+    /// Filter plugin id and parameters, for codecs that are not built in.
+    fn plugin(self) -> Option<(&'static str, H5Z_filter_t, Vec<u32>)> {
+        return match self {
+            Codec::Zstd(level) => Some(("zstd", ZSTD_FILTER, vec![level as u32])),
+            Codec::Lz4 => Some(("lz4", LZ4_FILTER, vec![0])),
+            Codec::None | Codec::Gzip(_) => None,
+        };
+    }
+}
+
+impl H5Compression {
+    /// Fails if the codec needs a filter plugin HDF5 cannot load.
+    pub fn check(&self) -> Result<()> {
+        if let Some((name, id, _)) = self.codec.plugin() {
+            if hdf5::sync::sync(|| unsafe { H5Zfilter_avail(id) }) <= 0 {
+                return Err(Error::Invalid(format!(
+                    "HDF5 filter {} ({}) is not available; point HDF5_PLUGIN_PATH to its plugin",
+                    name, id
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Creates the 1-d dataset `name` of `size` elements filled with
+    /// `fill_value`, chunked by `layout`.
+    pub fn create_dataset<T: hdf5::H5Type>(
+        &self,
+        file: &hdf5::File,
+        name: &str,
+        size: usize,
+        fill_value: T,
+        layout: DatasetLayout,
+    ) -> Result<hdf5::Dataset> {
+        let chunk_len = layout.chunk_len.min(size).max(1);
+        let (filter_name, _, _) = match self.codec.plugin() {
+            Some(plugin) => plugin,
+            None => {
+                let mut ds_builder = file.new_dataset::<T>();
+                ds_builder.chunk(chunk_len).fill_value(fill_value);
+                if layout.resizable {
+                    ds_builder.resizable(true);
+                }
+                if let Codec::Gzip(level) = self.codec {
+                    ds_builder.gzip(level);
+                }
+                if self.shuffle {
+                    ds_builder.shuffle(true);
+                }
+                if self.checksum {
+                    ds_builder.fletcher32(true);
+                }
+                return Ok(ds_builder.create(name, size)?);
+            }
+        };
+        self.check()?;
+
+        // The builder only knows the built-in filters, so plugin filters are
+        // set on a raw creation property list.
+        let dtype = hdf5::Datatype::from_type::<T>()?;
+        let c_name = CString::new(name)
+            .map_err(|_| Error::Invalid(format!("invalid dataset name '{}'", name)))?;
+        let dims = [size as u64];
+        let max_dims = [if layout.resizable {
+            H5S_UNLIMITED
+        } else {
+            size as u64
+        }];
+        let chunk = [chunk_len as u64];
+        let ds_id = hdf5::sync::sync(|| unsafe {
+            let dcpl = H5Pcreate(*H5P_CLS_DATASET_CREATE);
+            H5Pset_chunk(dcpl, 1, chunk.as_ptr());
+            H5Pset_fill_value(dcpl, dtype.id(), &fill_value as *const T as *const _);
+            self.set_filters(dcpl);
+            let space = H5Screate_simple(1, dims.as_ptr(), max_dims.as_ptr());
+            // Like the builder, create the groups missing on the way, e.g.
+            // in a scratch file.
+            let lcpl = H5Pcreate(*H5P_CLS_LINK_CREATE);
+            H5Pset_create_intermediate_group(lcpl, 1);
+            let ds_id = H5Dcreate2(
+                file.id(),
+                c_name.as_ptr(),
+                dtype.id(),
+                space,
+                lcpl,
+                dcpl,
+                H5P_DEFAULT,
+            );
+            H5Idec_ref(space);
+            H5Idec_ref(lcpl);
+            H5Idec_ref(dcpl);
+            if ds_id >= 0 {
+                H5Idec_ref(ds_id);
+            }
+            return ds_id;
+        });
+        if ds_id < 0 {
+            return Err(Error::Invalid(format!(
+                "could not create {} with the {} filter",
+                name, filter_name
+            )));
+        }
+        return Ok(file.dataset(name)?);
+    }
+
+    /// Sets the shuffle, codec and checksum filters on the dataset creation
+    /// property list `dcpl`, which must be chunked, in the order the
+    /// builder does. Returns whether HDF5 accepted them.
+    pub(crate) unsafe fn set_filters(&self, dcpl: hid_t) -> bool {
+        let mut status = 0;
+        if self.shuffle {
+            status |= H5Pset_shuffle(dcpl);
+        }
+        match self.codec.plugin() {
+            Some((_, id, params)) => {
+                status |=
+                    H5Pset_filter(dcpl, id, H5Z_FLAG_MANDATORY, params.len(), params.as_ptr());
+            }
+            None => {
+                if let Codec::Gzip(level) = self.codec {
+                    status |= H5Pset_deflate(dcpl, level as u32);
+                }
+            }
+        }
+        if self.checksum {
+            status |= H5Pset_fletcher32(dcpl);
+        }
+        return status >= 0;
+    }
+}
+
+/// Whether the chunks of `ds` have a Fletcher32 checksum.
+pub fn has_checksum(ds: &hdf5::Dataset) -> bool {
+    return ds.filters().get_fletcher32();
+}
+
+/// `err` from reading the dataset at `path`, made an `Error::Invalid`
+/// naming the dataset if a Fletcher32 checksum failed.
+pub fn checksum_error(path: &str, err: Error) -> Error {
+    let failed = match &err {
+        Error::Hdf5(err) => err.to_string().to_lowercase().contains("fletcher32"),
+        _ => false,
+    };
+    if !failed {
+        return err;
+    }
+    return Error::Invalid(format!(
+        "{} is corrupt, its checksum does not match ({}); recreate it with --overwrite",
+        path, err
+    ));
+}
@@ -0,0 +1,2083 @@
+//! Library entry point bundling an opened HDF5 file, its grid shape and the
+//! conversion settings, so each step can be run on its own.
+
+use crate::change::{self, ChangeSide, ChangeSummary};
+use crate::chunk_iter::ChunkIterator;
+use crate::colors::{self, ColorStyle};
+#[cfg(feature = "gdal")]
+use crate::compare::{self, Comparison};
+use crate::compression::{self, H5Compression};
+use crate::dim_scales;
+use crate::error::{Error, Result};
+use crate::extract::{self, Point, Sample};
+use crate::geolocation::{self, ArrayPaths, GeolocationMode};
+use crate::grid::{self, GroupGridRule};
+use crate::h5_cache::{self, ChunkCache};
+use crate::hdf::{self, H5NodeType, HdfSource, TraversalOptions};
+use crate::interrupt::{self, CancellationToken};
+use crate::kernel::StatisticKernel;
+use crate::nan_policy;
+use crate::narrowing::{self, Narrowed};
+use crate::overrides::{self, DatasetOverride};
+use crate::policy::{Decision, ExistsPolicy};
+use crate::preview;
+use crate::quicklook::{self, QuicklookOptions};
+use crate::raster::{MemorySink, OutputFormat, RasterSink, RasterValue};
+use crate::reverse::HdfChunks;
+use crate::row_order::{self, FlipOption};
+use crate::shards::{self, ShardedDataset};
+use crate::stats::{ErrorOutputs, QualityClasses, StatNames};
+use crate::status::{self, ProgressCallback};
+use crate::swmr::{self, SwmrOptions};
+use crate::templates::{self, RasterType, TypedTemplate};
+use crate::tiles::{self, TileScheme};
+use crate::timeseries::{self, TimeSlice};
+use crate::typed::{self, TypedJob};
+use crate::units::{self, UnitConversion, UnitRule};
+use crate::valid_range::{OutOfRange, RangeOption, RangeValue, ValidRange};
+use crate::vds;
+use crate::verify::{self, Mismatch, VerifyOptions};
+use crate::warnings::{self, Warning};
+use crate::xyz::{self, XyzOptions};
+use crate::{attrs, chunking, compound, meta, raster, reverse, stac, stats};
+use hdf5::file::OpenMode;
+use ndarray::Array2;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Settings shared by every conversion step.
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    pub memory_limit: usize,
+    pub exists_policy: ExistsPolicy,
+    pub stat_names: StatNames,
+    /// Member field read from compound-typed datasets.
+    pub field: Option<String>,
+    pub traversal: TraversalOptions,
+    pub format: OutputFormat,
+    /// Render a PNG quicklook next to each group output.
+    pub quicklook: Option<QuicklookOptions>,
+    /// Compression of the datasets written back to HDF5.
+    pub compression: H5Compression,
+    /// HDF5 file the derived datasets (`*_rev`, `mean_rev`, `sd_rev`) are
+    /// written to instead of the converted file, which is then opened
+    /// read-only.
+    pub scratch: Option<String>,
+    /// Create the datasets written back to HDF5 with an unlimited maximum
+    /// size.
+    pub resizable: bool,
+    /// Write the `_rev` datasets of numeric datasets as virtual datasets
+    /// mapping their rows instead of copies; see [`vds`].
+    pub virtual_rev: bool,
+    /// Chunk cache of the datasets read, or HDF5's default.
+    pub h5_cache: Option<ChunkCache>,
+    /// Read the file while another process writes it; see [`swmr`]. The
+    /// derived datasets then need `scratch`.
+    pub swmr: Option<SwmrOptions>,
+    /// Valid range of the values of converted datasets and group means.
+    pub valid_range: Option<RangeOption>,
+    /// What happens to values outside the valid range.
+    pub out_of_range: OutOfRange,
+    /// Unit conversions of datasets and group means and sds, applied after
+    /// the valid range is checked.
+    pub units: Vec<UnitRule>,
+    /// Colors of byte outputs: counts and `u8` datasets.
+    pub colors: Option<ColorStyle>,
+    /// Templates by data type. `Float32` and `Byte` templates replace the
+    /// float and byte ones; other types use the float one without a
+    /// template.
+    pub templates: Vec<TypedTemplate>,
+    /// Data type of dataset and time series outputs, instead of their own;
+    /// values are narrowed to it if needed.
+    pub output_type: Option<RasterType>,
+    /// Whether the rows of the datasets are flipped; see [`row_order`].
+    pub flip: FlipOption,
+    /// Groups whose rasters are written at once, sharing the memory limit.
+    pub group_jobs: usize,
+    /// Compute the group means and sds from the sums while writing the
+    /// rasters, instead of writing `mean_rev` and `sd_rev`.
+    pub direct_stats: bool,
+    /// Observations a pixel needs for a mean and sd; pixels with fewer get
+    /// the values of pixels without any.
+    pub min_count: u32,
+    /// Standard error and confidence interval outputs of each group.
+    pub errors: ErrorOutputs,
+    /// Count thresholds of the quality classes written for each group.
+    pub quality: Option<QualityClasses>,
+    /// Custom statistics written as extra outputs of each group.
+    pub kernels: Vec<Arc<dyn StatisticKernel>>,
+    /// Write a STAC Item next to each output raster.
+    pub stac: bool,
+    /// Also split each output raster into tiles.
+    pub tile_scheme: Option<TileScheme>,
+    /// Templates of groups on their own grids; see [`grid`].
+    pub group_grids: Vec<GroupGridRule>,
+    /// Random rows of each `_rev` dataset checked against its source once
+    /// written, besides the first, middle and last; see
+    /// [`verify::verify_reverse`].
+    pub verify_reverse: Option<usize>,
+    /// Settings of chosen datasets and groups replacing the ones above; see
+    /// [`overrides`].
+    pub overrides: Vec<DatasetOverride>,
+    /// Logical datasets stored as several datasets of consecutive rows; see
+    /// [`shards`].
+    pub shards: Vec<ShardedDataset>,
+    /// Snap irregular dimension scales to the nearest regular grid instead
+    /// of ignoring them; see [`dim_scales`].
+    pub snap_scales: bool,
+    /// Attach the latitude and longitude arrays of curvilinear grids to
+    /// their outputs, or warp them through those arrays; see
+    /// [`geolocation`].
+    pub geolocation: Option<GeolocationMode>,
+    /// Latitude and longitude arrays of `geolocation`, instead of those
+    /// found by name.
+    pub geolocation_arrays: Option<ArrayPaths>,
+    /// Token that stops the steps of the converter after their current
+    /// chunk with [`Error::Interrupted`], as Ctrl-C does; see
+    /// [`interrupt`]. It is set for the whole process while the converter
+    /// is open, so it also stops any other run in the process, and
+    /// [`HdfRasterConverter::open`] fails while another token is set: one
+    /// conversion at a time per process.
+    pub cancellation: Option<CancellationToken>,
+    /// Closure called with the [`status::Status`] after every chunk read or
+    /// written by the steps of the converter. Like `cancellation`, it is set
+    /// for the whole process while the converter is open, and sees the
+    /// chunks of any other run in the process.
+    pub progress: Option<ProgressCallback>,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        return ConvertOptions {
+            memory_limit: chunking::DEFAULT_MEMORY_LIMIT,
+            exists_policy: ExistsPolicy::default(),
+            stat_names: StatNames::default(),
+            field: None,
+            traversal: TraversalOptions::default(),
+            format: OutputFormat::default(),
+            quicklook: None,
+            compression: H5Compression::default(),
+            resizable: false,
+            virtual_rev: false,
+            h5_cache: None,
+            swmr: None,
+            valid_range: None,
+            out_of_range: OutOfRange::default(),
+            units: vec![],
+            colors: None,
+            templates: vec![],
+            output_type: None,
+            scratch: None,
+            flip: FlipOption::default(),
+            group_jobs: 1,
+            direct_stats: false,
+            min_count: 1,
+            errors: ErrorOutputs::default(),
+            quality: None,
+            kernels: vec![],
+            stac: false,
+            tile_scheme: None,
+            group_grids: vec![],
+            verify_reverse: None,
+            overrides: vec![],
+            shards: vec![],
+            snap_scales: false,
+            geolocation: None,
+            geolocation_arrays: None,
+            cancellation: None,
+            progress: None,
+        };
+    }
+}
+
+impl ConvertOptions {
+    /// Adds `kernel` to the statistics written for each group.
+    pub fn register_kernel<K: StatisticKernel + 'static>(&mut self, kernel: K) {
+        self.kernels.push(Arc::new(kernel));
+    }
+
+    /// Datasets of each group that are reversed: the statistic datasets and
+    /// the inputs of the kernels.
+    pub fn group_datasets(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .stat_names
+            .all()
+            .iter()
+            .map(|&name| name.to_owned())
+            .collect();
+        for kernel in self.kernels.iter() {
+            for name in kernel.inputs() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        return names;
+    }
+}
+
+/// Count, mean and sd of a statistic group as (ysize, xsize) arrays, rows in
+/// output (flipped) order. Counts are full `u32`s, not saturated at 255 as
+/// in the byte count rasters.
+#[derive(Debug, Clone)]
+pub struct GroupStats {
+    pub count: Array2<u32>,
+    pub mean: Array2<f32>,
+    pub sd: Array2<f32>,
+}
+
+pub struct HdfRasterConverter {
+    file: hdf5::File,
+    scratch: Option<hdf5::File>,
+    base_float_path: String,
+    base_byte_path: String,
+    xsize: usize,
+    ysize: usize,
+    /// Whether the rows of the datasets run opposite to the outputs.
+    flip: bool,
+    /// Grids of the groups on their own grids, by path.
+    group_grids: HashMap<String, OutputGrid>,
+    /// Templates written for group grids, removed on drop.
+    grid_files: Vec<String>,
+    pub options: ConvertOptions,
+}
+
+/// Grid the outputs of a group are written on: its shape, whether its rows
+/// are flipped and the templates of the float and byte outputs.
+#[derive(Debug, Clone)]
+struct OutputGrid {
+    xsize: usize,
+    ysize: usize,
+    flip: bool,
+    float_template: String,
+    byte_template: String,
+}
+
+impl Drop for HdfRasterConverter {
+    fn drop(&mut self) {
+        // Those of another converter opened since are left.
+        if let Some(token) = &self.options.cancellation {
+            if interrupt::is_token(token) {
+                interrupt::set_token(None);
+            }
+        }
+        if let Some(progress) = &self.options.progress {
+            if status::callback().map_or(false, |current| current.same_as(progress)) {
+                status::set_callback(None);
+            }
+        }
+        for path in &self.grid_files {
+            let _ = std::fs::remove_file(path);
+        }
+        if let Some(dir) = self
+            .grid_files
+            .first()
+            .and_then(|path| Path::new(path).parent())
+        {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Writes a template of `grid`, like the template at `like` if any, to
+/// `<stem>.tif` in a temporary directory of the process, returning its path.
+/// `what` names the grid in errors.
+#[cfg(feature = "gdal")]
+fn write_temp_template(
+    stem: &str,
+    _what: &str,
+    grid: &grid::Grid,
+    like: Option<&str>,
+) -> Result<String> {
+    let dir = std::env::temp_dir().join(format!("read_hdf_as_raster-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir
+        .join(format!("{}.tif", stem))
+        .to_string_lossy()
+        .into_owned();
+    grid::write_template(grid, like, &path)?;
+    return Ok(path);
+}
+
+#[cfg(not(feature = "gdal"))]
+fn write_temp_template(
+    _stem: &str,
+    what: &str,
+    grid: &grid::Grid,
+    _like: Option<&str>,
+) -> Result<String> {
+    return Err(Error::Invalid(format!(
+        "the {}x{} grid of {} has no template and writing one needs the gdal feature",
+        grid.xsize, grid.ysize, what
+    )));
+}
+
+/// Template of the grid of the 2-d statistic datasets of `file`, or else of
+/// its other 2-d datasets, for runs without templates: from their dimension
+/// scales (see [`dim_scales`]), else in pixel coordinates, without a CRS.
+/// Returns its path and, for a grid from scales, whether the rows are
+/// flipped.
+fn infer_template(file: &hdf5::File, options: &ConvertOptions) -> Result<(String, Option<bool>)> {
+    let nodes = hdf::ls_hdf5_with(file, "".to_owned(), &options.traversal);
+    let stat_names = options.stat_names.all();
+    let (groups, _) = hdf::stat_groups(file, &nodes, &stat_names);
+    let stat_paths: Vec<String> = groups
+        .iter()
+        .flat_map(|group| {
+            stat_names
+                .iter()
+                .map(move |name| hdf::join_path(group, name))
+        })
+        .collect();
+    let other_paths: Vec<String> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            H5NodeType::Dataset(path) => Some(path.clone()),
+            _ => None,
+        })
+        .collect();
+    let size = match hdf::grid_shape(file, &stat_paths)? {
+        Some(size) => Some(size),
+        None => hdf::grid_shape(file, &other_paths)?,
+    };
+    let two_d: Vec<String> = stat_paths
+        .iter()
+        .chain(other_paths.iter())
+        .filter(|path| meta::get(file, path).map_or(false, |meta| meta.shape.len() == 2))
+        .cloned()
+        .collect();
+    // Without 2-d datasets, scales of the root group may still give the grid
+    // of the 1-d statistic datasets.
+    let len = match size {
+        Some((xsize, ysize)) => xsize * ysize,
+        None => match stat_paths.first() {
+            Some(path) => meta::get(file, path)?.size(),
+            None => 0,
+        },
+    };
+    let scale_grid = match len {
+        0 => None,
+        _ => dim_scales::group_grid(file, "/", &two_d, Some(len), options.snap_scales)?,
+    };
+    if let Some(scale_grid) = scale_grid {
+        if size.map_or(true, |size| size == (scale_grid.xsize, scale_grid.ysize)) {
+            println!(
+                "No template given: outputs are on the {}x{} grid of the dimension scales",
+                scale_grid.xsize, scale_grid.ysize
+            );
+            let path = write_temp_template(
+                "inferred",
+                "the dimension scales",
+                &scale_grid.north_up(),
+                None,
+            )?;
+            return Ok((path, Some(scale_grid.needs_flip(options.flip))));
+        }
+    }
+    let size = size.ok_or_else(|| {
+        return Error::Invalid(
+            "no template and no 2-d dataset or dimension scales to take the grid shape from"
+                .to_owned(),
+        );
+    })?;
+    println!(
+        "No template given: outputs are on the {}x{} grid of the 2-d datasets, \
+         in pixel coordinates",
+        size.0, size.1
+    );
+    let grid = grid::Grid::pixels(size.0, size.1);
+    let path = write_temp_template("inferred", "the 2-d datasets", &grid, None)?;
+    return Ok((path, None));
+}
+
+/// Key of `group` in the group grids.
+fn group_key(group: &str) -> String {
+    return format!("/{}", group.trim_matches('/'));
+}
+
+/// Data type holding the values of `ds` (see [`RasterType::of_dataset`]),
+/// `Float32` for compound datasets read through a field.
+fn own_type(ds: &hdf5::Dataset) -> Result<RasterType> {
+    return Ok(RasterType::of_dataset(ds)?.unwrap_or(RasterType::Float32));
+}
+
+impl HdfRasterConverter {
+    /// Opens `h5_path` for update, or read-only with `options.scratch`, which
+    /// is created if missing. The grid shape is taken from the byte
+    /// template, which together with the float template and those of
+    /// `options.templates` is copied to create each output raster; they
+    /// must all have the same shape. Without either base template, it is
+    /// taken from the 2-d datasets (see [`infer_template`]). Fails with
+    /// `options.cancellation` or `options.progress` while a token or
+    /// callback is already set for the process, e.g. by another converter.
+    pub fn open(
+        h5_path: &str,
+        base_float_path: &str,
+        base_byte_path: &str,
+        options: ConvertOptions,
+    ) -> Result<HdfRasterConverter> {
+        options.compression.check()?;
+        // Both are process-wide: a second one would silently replace the
+        // first, cancelling or reporting on the wrong conversion.
+        if options.cancellation.is_some() && interrupt::token().is_some() {
+            return Err(Error::Invalid(
+                "a cancellation token is already set for the process, by another converter or \
+                 interrupt::set_token; converters with tokens run one at a time"
+                    .to_owned(),
+            ));
+        }
+        if options.progress.is_some() && status::callback().is_some() {
+            return Err(Error::Invalid(
+                "a progress callback is already set for the process, by another converter or \
+                 status::set_callback; converters with callbacks run one at a time"
+                    .to_owned(),
+            ));
+        }
+        let (file, scratch) = match &options.scratch {
+            _ if options.swmr.is_some() && options.scratch.is_none() => {
+                return Err(Error::Invalid(
+                    "--swmr opens the converted file read-only; give --scratch for the derived \
+                     datasets"
+                        .to_owned(),
+                ));
+            }
+            Some(scratch_path) if scratch_path == h5_path => {
+                return Err(Error::Invalid(format!(
+                    "the scratch file must not be the converted file {}",
+                    h5_path
+                )));
+            }
+            Some(scratch_path) => (
+                match options.swmr {
+                    Some(_) => swmr::open_read(h5_path, options.h5_cache)?,
+                    None => h5_cache::open_as(h5_path, OpenMode::Read, options.h5_cache)?,
+                },
+                Some(h5_cache::open_as(
+                    scratch_path,
+                    OpenMode::Append,
+                    options.h5_cache,
+                )?),
+            ),
+            None => (
+                h5_cache::open_as(h5_path, OpenMode::ReadWrite, options.h5_cache)?,
+                None,
+            ),
+        };
+        let base_float_path = templates::template_for(&options.templates, RasterType::Float32)
+            .unwrap_or(base_float_path);
+        let base_byte_path =
+            templates::template_for(&options.templates, RasterType::Byte).unwrap_or(base_byte_path);
+        // Without templates, the grid is that of the 2-d datasets; with them,
+        // 2-d datasets are checked against it when reversed.
+        let mut grid_files = vec![];
+        let mut scale_flip = None;
+        let (base_float_path, base_byte_path) =
+            match Path::new(base_byte_path).exists() || Path::new(base_float_path).exists() {
+                true => (base_float_path.to_owned(), base_byte_path.to_owned()),
+                false => {
+                    let (template, flip) = infer_template(&file, &options)?;
+                    scale_flip = flip;
+                    grid_files.push(template.clone());
+                    (template.clone(), template)
+                }
+            };
+        let (xsize, ysize) = raster::template_size(&base_byte_path)?;
+        chunking::grid_len(xsize, ysize).map_err(Error::Invalid)?;
+        for template in &options.templates {
+            if raster::template_size(&template.path)? != (xsize, ysize) {
+                return Err(Error::Invalid(format!(
+                    "{} template {} does not match the {}x{} byte template",
+                    template.data_type.name(),
+                    template.path,
+                    xsize,
+                    ysize
+                )));
+            }
+        }
+        let geo_transform = raster::template_georef(&base_byte_path)?.geo_transform;
+        let flip = match scale_flip {
+            Some(flip) => flip,
+            None => row_order::needs_flip(options.flip, &file, &geo_transform)?,
+        };
+        if !flip && options.flip == FlipOption::Auto {
+            println!("The datasets run like the template; their rows are kept in order");
+        }
+        // Polled and called from the loops of every step, like Ctrl-C and
+        // the status, for as long as the converter is open.
+        if options.cancellation.is_some() {
+            interrupt::set_token(options.cancellation.clone());
+        }
+        if options.progress.is_some() {
+            status::set_callback(options.progress.clone());
+        }
+        return Ok(HdfRasterConverter {
+            file,
+            scratch,
+            base_float_path,
+            base_byte_path,
+            xsize,
+            ysize,
+            flip,
+            group_grids: HashMap::new(),
+            grid_files,
+            options,
+        });
+    }
+
+    /// Finds the grids of `groups` on their own grids: those given a
+    /// template in `options.group_grids`, or else with grid attributes
+    /// differing from the templates (see [`grid`]). Their datasets are
+    /// reversed and their outputs written on that grid.
+    pub fn prepare_group_grids(&mut self, groups: &[&str]) -> Result<()> {
+        let geo_transform = raster::template_georef(&self.base_byte_path)?.geo_transform;
+        for group in groups {
+            let output_grid = match grid::template_for(&self.options.group_grids, group) {
+                Some(template) => {
+                    let (xsize, ysize) = raster::template_size(template)?;
+                    let gt = raster::template_georef(template)?.geo_transform;
+                    OutputGrid {
+                        xsize,
+                        ysize,
+                        flip: row_order::needs_flip(self.options.flip, &self.file, &gt)?,
+                        float_template: template.to_owned(),
+                        byte_template: template.to_owned(),
+                    }
+                }
+                None => {
+                    let group_grid = match grid::read_group_grid(&self.file, group)? {
+                        Some(group_grid) => group_grid,
+                        None => match self.scale_grid(group)? {
+                            Some(group_grid) => group_grid,
+                            None => continue,
+                        },
+                    };
+                    let north_up = group_grid.north_up();
+                    let flip = group_grid.needs_flip(self.options.flip);
+                    match (north_up.xsize, north_up.ysize) == (self.xsize, self.ysize)
+                        && grid::same_transform(&north_up.geo_transform, &geo_transform)
+                    {
+                        true if flip == self.flip => continue,
+                        // The grid of the templates, with rows running
+                        // another way than those of the file.
+                        true => OutputGrid {
+                            xsize: self.xsize,
+                            ysize: self.ysize,
+                            flip,
+                            float_template: self.base_float_path.clone(),
+                            byte_template: self.base_byte_path.clone(),
+                        },
+                        false => {
+                            let template = self.write_grid_template(group, &north_up)?;
+                            OutputGrid {
+                                xsize: north_up.xsize,
+                                ysize: north_up.ysize,
+                                flip,
+                                float_template: template.clone(),
+                                byte_template: template,
+                            }
+                        }
+                    }
+                }
+            };
+            chunking::grid_len(output_grid.xsize, output_grid.ysize).map_err(Error::Invalid)?;
+            println!(
+                "Group {} is on its own {}x{} grid{}",
+                group,
+                output_grid.xsize,
+                output_grid.ysize,
+                if output_grid.flip {
+                    ", rows flipped"
+                } else {
+                    ""
+                }
+            );
+            self.group_grids.insert(group_key(group), output_grid);
+        }
+        return Ok(());
+    }
+
+    /// Grid of `group` from dimension scales holding its statistic
+    /// datasets (see [`dim_scales`]), if any.
+    fn scale_grid(&self, group: &str) -> Result<Option<grid::Grid>> {
+        let paths: Vec<String> = self
+            .options
+            .stat_names
+            .all()
+            .iter()
+            .map(|name| hdf::join_path(group, name))
+            .filter(|path| self.file.link_exists(path))
+            .collect();
+        let len = match paths.first() {
+            Some(path) => meta::get(&self.file, path)?.size(),
+            None => return Ok(None),
+        };
+        return dim_scales::group_grid(
+            &self.file,
+            group,
+            &paths,
+            Some(len),
+            self.options.snap_scales,
+        );
+    }
+
+    /// Writes the template of the grid `north_up` of `group`, returning its
+    /// path.
+    fn write_grid_template(&mut self, group: &str, north_up: &grid::Grid) -> Result<String> {
+        let path = write_temp_template(
+            &format!("grid_{}", hdf::flatten_path(group)),
+            &format!("group {}", group),
+            north_up,
+            Some(&self.base_float_path),
+        )?;
+        self.grid_files.push(path.clone());
+        return Ok(path);
+    }
+
+    /// Grid the outputs of `group` are written on: its own, or that of the
+    /// templates.
+    fn grid_of(&self, group: &str) -> OutputGrid {
+        return match self.group_grids.get(&group_key(group)) {
+            Some(output_grid) => output_grid.clone(),
+            None => self.templates_grid(),
+        };
+    }
+
+    /// Grid of the templates, that of the datasets converted on their own.
+    fn templates_grid(&self) -> OutputGrid {
+        return OutputGrid {
+            xsize: self.xsize,
+            ysize: self.ysize,
+            flip: self.flip,
+            float_template: self.base_float_path.clone(),
+            byte_template: self.base_byte_path.clone(),
+        };
+    }
+
+    pub fn file(&self) -> &hdf5::File {
+        return &self.file;
+    }
+
+    /// File holding the derived datasets: the scratch file if there is one,
+    /// else the converted file.
+    pub fn derived_file(&self) -> &hdf5::File {
+        return self.scratch.as_ref().unwrap_or(&self.file);
+    }
+
+    /// Grid shape as (xsize, ysize).
+    pub fn shape(&self) -> (usize, usize) {
+        return (self.xsize, self.ysize);
+    }
+
+    pub fn list(&self) -> Vec<H5NodeType> {
+        return hdf::ls_hdf5_with(&self.file, "".to_owned(), &self.options.traversal);
+    }
+
+    /// Writes `<ds_path>_rev` with the rows of `ds_path` in output order
+    /// (reversed unless they already are), as `u8` for count datasets and
+    /// `f32` otherwise. Counts wider than a byte are kept as `u32` and sums
+    /// wider than `f32` as `f64`, so that groups mixing types are averaged
+    /// without clipping or rounding their sums. The rows are those of the
+    /// grid of its group.
+    pub fn reverse_dataset(&self, ds_path: &str, is_count: bool) -> Result<()> {
+        let group = ds_path.rsplit_once('/').map_or("", |(group, _)| group);
+        let grid = self.grid_of(group);
+        let meta = meta::get(&self.file, &self.source_path(ds_path)?)?;
+        let wide = match is_count {
+            true => meta.loses_precision::<u8>(),
+            false => meta.loses_precision::<f32>(),
+        };
+        let data_type = match (is_count, wide) {
+            (true, false) => RasterType::Byte,
+            (true, true) => RasterType::UInt32,
+            (false, false) => RasterType::Float32,
+            (false, true) => RasterType::Float64,
+        };
+        return typed::dispatch(
+            data_type,
+            ReverseJob {
+                converter: self,
+                ds_path,
+                grid: &grid,
+            },
+        );
+    }
+
+    /// Path of the dataset read for `ds_path`: its first shard if it is
+    /// sharded, else itself.
+    fn source_path(&self, ds_path: &str) -> Result<String> {
+        return match shards::find(&self.options.shards, ds_path) {
+            Some(sharded) => Ok(sharded.shard_paths(&self.file)?.remove(0)),
+            None => Ok(ds_path.to_owned()),
+        };
+    }
+
+    /// Reverses the statistic datasets of `group` and the inputs of the
+    /// kernels.
+    pub fn reverse_group(&self, group: &str) -> Result<()> {
+        let count = &self.options.stat_names.count;
+        for name in self.options.group_datasets().iter() {
+            self.reverse_dataset(&hdf::join_path(group, name), name == count)?;
+        }
+        return Ok(());
+    }
+
+    pub fn compute_mean_sd(&self, group: &str) -> Result<()> {
+        for name in self.options.group_datasets().iter() {
+            self.note_unverified(&hdf::join_path(group, &format!("{}_rev", name)));
+        }
+        if self.options.direct_stats {
+            return Ok(());
+        }
+        self.note_min_count(group);
+        let chunk_size =
+            chunking::chunk_len(self.options.memory_limit, chunking::STATS_BYTES_PER_PIXEL);
+        stats::calc_mean_sd(
+            self.derived_file(),
+            group,
+            &self.options.stat_names,
+            self.options.min_count,
+            chunk_size,
+            self.options.exists_policy,
+            &self.options.compression,
+            self.options.resizable,
+        )?;
+        self.derived_file().flush()?;
+        return Ok(());
+    }
+
+    /// Writes the count, mean and sd rasters of `group`, its error outputs
+    /// and the quicklooks if enabled; see [`raster::write_group_rasters`] for
+    /// `created`. Each call opens its own outputs, so the rasters of
+    /// `options.group_jobs` groups can be written at once from as many
+    /// threads.
+    pub fn write_group_rasters(&self, group: &str, created: &mut Vec<String>) -> Result<()> {
+        if !self.options.direct_stats {
+            for name in &["mean_rev", "sd_rev"] {
+                self.note_unverified(&hdf::join_path(group, name));
+            }
+        }
+        let mut extra_stats: Vec<String> = self
+            .options
+            .errors
+            .stats()
+            .iter()
+            .map(|&stat| stat.to_owned())
+            .collect();
+        for kernel in self.options.kernels.iter() {
+            extra_stats.extend(kernel.outputs());
+        }
+        if self.options.quality.is_some() {
+            extra_stats.push("quality".to_owned());
+        }
+        let grid = self.grid_of(group);
+        let memory_limit = self.options.memory_limit / self.options.group_jobs.max(1);
+        let bytes_per_pixel = chunking::group_raster_bytes_per_pixel(extra_stats.len());
+        let n_lines_read = chunking::strip_lines(memory_limit, grid.xsize, bytes_per_pixel);
+        let block_cols = chunking::block_cols(memory_limit, grid.xsize, bytes_per_pixel);
+        let source = self.stat_source();
+        raster::write_group_rasters(
+            &source,
+            group,
+            &self.options.stat_names.count,
+            self.options.format,
+            &grid.float_template,
+            &grid.byte_template,
+            grid.xsize,
+            grid.ysize,
+            n_lines_read,
+            block_cols,
+            self.options.exists_policy,
+            self.mean_range("mean")?,
+            units::conversion_for(&self.options.units, group),
+            &extra_stats,
+            created,
+        )?;
+        if self.options.direct_stats {
+            nan_policy::record(group, &source.non_finite());
+            nan_policy::warn(group);
+        }
+        let count_path = raster::output_path(group, "count", self.options.format.extension());
+        if created.contains(&count_path) {
+            self.style_byte_output(&count_path, created)?;
+        }
+        if let Some(options) = &self.options.quicklook {
+            self.write_quicklooks(
+                group,
+                options,
+                (grid.xsize, grid.ysize, n_lines_read),
+                created,
+            )?;
+        }
+        return Ok(());
+    }
+
+    /// Source of the group outputs: the derived datasets, with the means and
+    /// sds computed from the sums with `options.direct_stats`, and the error,
+    /// quality and kernel outputs.
+    fn stat_source(&self) -> stats::StatSource {
+        return stats::StatSource::new(
+            self.derived_file(),
+            &self.options.stat_names,
+            self.options.direct_stats,
+            self.options.min_count,
+            self.options.errors,
+            self.options.quality,
+            &self.options.kernels,
+        );
+    }
+
+    /// Notes a `mean_rev` kept from an earlier run with another
+    /// `--min-count`, which the existing policy would reuse.
+    fn note_min_count(&self, group: &str) {
+        if self.options.exists_policy != ExistsPolicy::SkipExisting {
+            return;
+        }
+        let path = hdf::join_path(group, "mean_rev");
+        if let Ok(ds) = self.derived_file().dataset(&path) {
+            let min_count = stats::min_count_of(&ds);
+            if min_count.max(1) != self.options.min_count.max(1) {
+                println!(
+                    "{} was computed with --min-count {}; recreate it with --overwrite",
+                    path, min_count
+                );
+            }
+        }
+    }
+
+    /// Notes a derived dataset read without a checksum although checksums
+    /// are asked for, e.g. one kept from an earlier run.
+    fn note_unverified(&self, path: &str) {
+        if !self.options.compression.checksum {
+            return;
+        }
+        if let Ok(ds) = self.derived_file().dataset(path) {
+            if !compression::has_checksum(&ds) {
+                println!(
+                    "{} has no checksum and is read unverified; recreate it with --overwrite",
+                    path
+                );
+            }
+        }
+    }
+
+    /// Valid range of the values of `ds`, or of every output without one,
+    /// according to [`ConvertOptions::valid_range`].
+    fn valid_range(&self, ds: Option<&hdf5::Dataset>) -> Result<Option<ValidRange>> {
+        let action = self.options.out_of_range;
+        return match (self.options.valid_range, ds) {
+            (Some(RangeOption::Fixed(min, max)), _) => Ok(Some(ValidRange { min, max, action })),
+            (Some(RangeOption::Attributes), Some(ds)) => ValidRange::from_attributes(ds, action),
+            _ => Ok(None),
+        };
+    }
+
+    /// Attaches the color style, if any, to the byte output at `out_path`,
+    /// pushing its sidecars to `created`.
+    fn style_byte_output(&self, out_path: &str, created: &mut Vec<String>) -> Result<()> {
+        let style = match &self.options.colors {
+            Some(style) => style,
+            None => return Ok(()),
+        };
+        if style.embed {
+            #[cfg(feature = "gdal")]
+            colors::embed_color_table(out_path, self.options.format.band(), &style.map)?;
+        }
+        for &sidecar in &style.sidecars {
+            created.push(style.map.write_sidecar(out_path, sidecar)?);
+        }
+        return Ok(());
+    }
+
+    /// Unit conversion of the dataset at `ds_path` written as `data_type`,
+    /// failing for integer types, whose converted values would be truncated.
+    fn dataset_units(
+        &self,
+        ds_path: &str,
+        data_type: RasterType,
+    ) -> Result<Option<&UnitConversion>> {
+        let units = units::conversion_for(&self.options.units, ds_path);
+        if !data_type.is_float() && units.is_some() {
+            return Err(Error::Invalid(format!(
+                "cannot convert the units of {} dataset {}",
+                data_type.name(),
+                ds_path
+            )));
+        }
+        return Ok(units);
+    }
+
+    /// Data type the dataset at `ds_path`, of `own_type`, is written as: that
+    /// of its override or `options.output_type`, or else its own, but
+    /// `Float32` for integers whose units are converted.
+    fn output_type(&self, ds_path: &str, own_type: RasterType) -> RasterType {
+        if let Some(data_type) = self.forced_type(ds_path) {
+            return data_type;
+        }
+        if !own_type.is_float() && units::conversion_for(&self.options.units, ds_path).is_some() {
+            return RasterType::Float32;
+        }
+        return own_type;
+    }
+
+    /// Data type the dataset at `ds_path` is written as whatever its own,
+    /// from its override or `options.output_type`.
+    fn forced_type(&self, ds_path: &str) -> Option<RasterType> {
+        return overrides::resolve(&self.options.overrides, ds_path)
+            .data_type
+            .or(self.options.output_type);
+    }
+
+    /// Whether the rows of the dataset at `ds_path` are flipped: as its
+    /// override says, or else as those of its grid, flipped with `grid_flip`.
+    fn flip_of(&self, ds_path: &str, grid_flip: bool) -> bool {
+        return overrides::resolve(&self.options.overrides, ds_path)
+            .flip
+            .unwrap_or(grid_flip);
+    }
+
+    /// Writes the STAC Items of the rasters among `created`, written from
+    /// the group or dataset `source`, if asked to, pushing their paths to
+    /// `created`.
+    pub fn write_stac_items(&self, source: &str, created: &mut Vec<String>) -> Result<()> {
+        if !self.options.stac {
+            return Ok(());
+        }
+        let grid = self.grid_of(source);
+        let georef = raster::template_georef(&grid.byte_template)?;
+        let times = stac::ItemTimes::read(&self.file, source);
+        let rasters: Vec<String> = created
+            .iter()
+            .filter(|path| stac::is_raster(path))
+            .cloned()
+            .collect();
+        for out_path in rasters {
+            created.push(stac::item_path(&out_path));
+            stac::write_item(&out_path, &georef, (grid.xsize, grid.ysize), &times)?;
+        }
+        return Ok(());
+    }
+
+    /// Splits the rasters among `created` into the tiles of
+    /// `options.tile_scheme`, if set, pushing their paths to `created`.
+    pub fn write_tiles(&self, created: &mut Vec<String>) -> Result<()> {
+        let scheme = match self.options.tile_scheme {
+            Some(scheme) => scheme,
+            None => return Ok(()),
+        };
+        let rasters: Vec<String> = created
+            .iter()
+            .filter(|path| stac::is_raster(path) && !tiles::is_tile(path))
+            .cloned()
+            .collect();
+        for out_path in rasters {
+            #[cfg(feature = "gdal")]
+            tiles::write_tiles(&out_path, scheme, created)?;
+            #[cfg(not(feature = "gdal"))]
+            warnings::record(
+                Warning::Skipped,
+                format!(
+                    "{} not tiled ({:?}): tiling needs the gdal feature",
+                    out_path, scheme
+                ),
+            );
+        }
+        return Ok(());
+    }
+
+    /// Attaches the geolocation arrays of the grid of the group or dataset
+    /// `source` to the rasters among `created`, and warps them through the
+    /// arrays, as `options.geolocation` says, pushing the paths of the files
+    /// written to `created`. Grids without the arrays are left as they are.
+    pub fn write_geolocation(&self, source: &str, created: &mut Vec<String>) -> Result<()> {
+        let mode = match &self.options.geolocation {
+            Some(mode) => mode,
+            None => return Ok(()),
+        };
+        let rasters: Vec<String> = created
+            .iter()
+            .filter(|path| {
+                stac::is_raster(path) && !tiles::is_tile(path) && !geolocation::is_derived(path)
+            })
+            .cloned()
+            .collect();
+        if rasters.is_empty() {
+            return Ok(());
+        }
+        let grid = self.grid_of(source);
+        let group = geolocation::source_group(&self.file, source);
+        let arrays = match geolocation::find(
+            &self.file,
+            &group,
+            self.options.geolocation_arrays.as_ref(),
+            (grid.xsize, grid.ysize),
+        )? {
+            Some(arrays) => arrays,
+            None => {
+                warnings::record(
+                    Warning::Skipped,
+                    format!(
+                        "no {}x{} latitude and longitude arrays for the outputs of {}, \
+                         written without geolocation",
+                        grid.xsize, grid.ysize, source
+                    ),
+                );
+                return Ok(());
+            }
+        };
+        println!(
+            "Geolocating the outputs of {} with {} and {}",
+            source, arrays.lat, arrays.lon
+        );
+        #[cfg(feature = "gdal")]
+        {
+            let flip = self.flip_of(source, grid.flip);
+            let lat = geolocation::read_array(&self.file, &arrays.lat, flip)?;
+            let lon = geolocation::read_array(&self.file, &arrays.lon, flip)?;
+            for out_path in rasters {
+                let lat_path = geolocation::array_path(&out_path, "lat");
+                let lon_path = geolocation::array_path(&out_path, "lon");
+                created.push(lat_path.clone());
+                geolocation::write_array(&lat, &lat_path)?;
+                created.push(lon_path.clone());
+                geolocation::write_array(&lon, &lon_path)?;
+                geolocation::attach(&out_path, &lat_path, &lon_path)?;
+                if let GeolocationMode::Warp(crs) = mode {
+                    let warped_path = geolocation::warped_path(&out_path);
+                    created.push(warped_path.clone());
+                    geolocation::warp(&out_path, crs, &warped_path)?;
+                }
+            }
+        }
+        #[cfg(not(feature = "gdal"))]
+        warnings::record(
+            Warning::Skipped,
+            format!(
+                "outputs of {} not geolocated ({:?}): geolocation needs the gdal feature",
+                source, mode
+            ),
+        );
+        return Ok(());
+    }
+
+    /// Writes the preview of the group or dataset `source` read every `step`
+    /// pixels to `out_path`: the count, mean and sd of a group as bands,
+    /// with the sd of pixels without one as nodata, or the values of a
+    /// dataset, on its grid and flipped like its outputs; see [`preview`].
+    #[cfg(feature = "gdal")]
+    pub fn write_preview(
+        &self,
+        source: &str,
+        is_group: bool,
+        step: usize,
+        out_path: &str,
+    ) -> Result<()> {
+        if shards::find(&self.options.shards, source).is_some() {
+            return Err(Error::Invalid(format!(
+                "no preview of the sharded dataset {}",
+                source
+            )));
+        }
+        let grid = match is_group {
+            true => self.grid_of(source),
+            false => self.templates_grid(),
+        };
+        let georef = raster::template_georef(&grid.float_template)?;
+        let size = (grid.xsize, grid.ysize);
+        let flip = self.flip_of(source, grid.flip);
+        if !is_group {
+            let vals = preview::read_strided::<f64>(&self.file.dataset(source)?, size, step, flip)?;
+            let vals = vals.mapv(|val| val as f32);
+            return preview::write_preview(out_path, &georef, step, &[(source, vals)]);
+        }
+        let names = &self.options.stat_names;
+        let read = |name: &str| -> Result<Array2<f64>> {
+            let ds = self.file.dataset(&hdf::join_path(source, name))?;
+            return preview::read_strided::<f64>(&ds, size, step, flip);
+        };
+        let count = preview::read_strided::<u32>(
+            &self.file.dataset(&hdf::join_path(source, &names.count))?,
+            size,
+            step,
+            flip,
+        )?;
+        let wide = stats::is_wide(&self.file, source, names, "")?;
+        let (mean, sd) = stats::mean_sd_with(
+            names,
+            wide,
+            self.options.min_count,
+            count.as_slice().unwrap(),
+            |name| {
+                return Ok(read(name)?.into_raw_vec());
+            },
+        )?;
+        let shape = count.dim();
+        let sd: Vec<f32> = sd
+            .into_iter()
+            .map(|sd| if sd < 0f32 { f32::NAN } else { sd })
+            .collect();
+        let bands = [
+            ("count", count.mapv(|count| count as f32)),
+            ("mean", Array2::from_shape_vec(shape, mean).unwrap()),
+            ("sd", Array2::from_shape_vec(shape, sd).unwrap()),
+        ];
+        return preview::write_preview(out_path, &georef, step, &bands);
+    }
+
+    /// Template of the outputs of `data_type`.
+    fn template(&self, data_type: RasterType) -> &str {
+        return match data_type {
+            RasterType::Byte => &self.base_byte_path,
+            RasterType::Float32 => &self.base_float_path,
+            _ => templates::template_for(&self.options.templates, data_type)
+                .unwrap_or(&self.base_float_path),
+        };
+    }
+
+    /// Valid range of the group outputs of statistic `stat`: only means are
+    /// checked, against a fixed range, as the attributes of the statistic
+    /// datasets describe sums.
+    fn mean_range(&self, stat: &str) -> Result<Option<ValidRange>> {
+        if stat != "mean" {
+            return Ok(None);
+        }
+        return self.valid_range(None);
+    }
+
+    /// Writes statistic `stat` (`mean`, `sd` or `count`) of each of `groups`
+    /// as one band of the GeoTIFF at `out_path`, named after the group; see
+    /// [`raster::write_stack`] for `created`. The groups must have been
+    /// reversed and, unless `options.direct_stats`, their mean and sd
+    /// computed.
+    #[cfg(feature = "gdal")]
+    pub fn write_stack(
+        &self,
+        groups: &[&str],
+        stat: &str,
+        out_path: &str,
+        created: &mut Vec<String>,
+    ) -> Result<()> {
+        if let Some(group) = groups
+            .iter()
+            .find(|group| self.group_grids.contains_key(&group_key(group)))
+        {
+            return Err(Error::Invalid(format!(
+                "group {} is on its own grid and cannot be stacked with the others",
+                group
+            )));
+        }
+        let count_name = &self.options.stat_names.count;
+        let bands: Vec<(String, String, Option<UnitConversion>)> = groups
+            .iter()
+            .map(|group| {
+                let outputs = raster::group_outputs(group, count_name);
+                let ds_path = outputs.iter().find(|(name, _)| *name == stat).unwrap();
+                let units = match stat {
+                    "count" => None,
+                    _ => units::conversion_for(&self.options.units, group).cloned(),
+                };
+                return (group.trim_matches('/').to_owned(), ds_path.1.clone(), units);
+            })
+            .collect();
+        let gdal_options = match self.options.format {
+            OutputFormat::Gdal(options) => options,
+            #[allow(unreachable_patterns)]
+            _ => Default::default(),
+        };
+        let nodata = raster::empty_value(stat);
+        if stat == "count" {
+            let bytes_per_pixel = chunking::dataset_raster_bytes_per_pixel(1);
+            let n_lines_read =
+                chunking::strip_lines(self.options.memory_limit, self.xsize, bytes_per_pixel);
+            let block_cols =
+                chunking::block_cols(self.options.memory_limit, self.xsize, bytes_per_pixel);
+            return raster::write_stack::<u8, _>(
+                &self.stat_source(),
+                &bands,
+                &self.base_byte_path,
+                out_path,
+                gdal_options,
+                nodata,
+                None,
+                self.xsize,
+                self.ysize,
+                n_lines_read,
+                block_cols,
+                self.options.exists_policy,
+                created,
+            );
+        }
+        let bytes_per_pixel = chunking::dataset_raster_bytes_per_pixel(4);
+        let n_lines_read =
+            chunking::strip_lines(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        let block_cols =
+            chunking::block_cols(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        return raster::write_stack::<f32, _>(
+            &self.stat_source(),
+            &bands,
+            &self.base_float_path,
+            out_path,
+            gdal_options,
+            nodata,
+            self.mean_range(stat)?,
+            self.xsize,
+            self.ysize,
+            n_lines_read,
+            block_cols,
+            self.options.exists_policy,
+            created,
+        );
+    }
+
+    fn write_quicklooks(
+        &self,
+        group: &str,
+        options: &QuicklookOptions,
+        (xsize, ysize, n_lines_read): (usize, usize, usize),
+        created: &mut Vec<String>,
+    ) -> Result<()> {
+        let count_name = &self.options.stat_names.count;
+        let source = self.stat_source();
+        for (stat, ds_path) in raster::group_outputs(group, count_name).iter() {
+            let out_path = raster::output_path(group, stat, "png");
+            if self.options.exists_policy.decide_file(&out_path)? == Decision::Skip {
+                continue;
+            }
+            println!("Rendering quicklook {}", out_path);
+            created.push(out_path.clone());
+            if *stat == "count" {
+                quicklook::write_quicklook::<u8, _>(
+                    &source,
+                    ds_path,
+                    xsize,
+                    ysize,
+                    n_lines_read,
+                    options,
+                    &out_path,
+                )?;
+            } else {
+                quicklook::write_quicklook::<f32, _>(
+                    &source,
+                    ds_path,
+                    xsize,
+                    ysize,
+                    n_lines_read,
+                    options,
+                    &out_path,
+                )?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Runs every step for `group`: reversal, mean/sd and rasters.
+    pub fn convert_group(&self, group: &str, created: &mut Vec<String>) -> Result<()> {
+        self.reverse_group(group)?;
+        self.derived_file().flush()?;
+        self.compute_mean_sd(group)?;
+        return self.write_group_rasters(group, created);
+    }
+
+    /// Reverses a single dataset and writes it to the raster at `out_path`,
+    /// as the type given by [`HdfRasterConverter::output_type`], with the
+    /// template of that type.
+    pub fn convert_dataset(&self, ds_path: &str, out_path: &str) -> Result<()> {
+        let ds = self.file.dataset(&self.source_path(ds_path)?)?;
+        let own_type = own_type(&ds)?;
+        drop(ds);
+        let grid = self.templates_grid();
+        typed::dispatch(
+            own_type,
+            ReverseJob {
+                converter: self,
+                ds_path,
+                grid: &grid,
+            },
+        )?;
+        self.derived_file().flush()?;
+
+        let rev_path = format!("{}_rev", ds_path);
+        let data_type = self.output_type(ds_path, own_type);
+        let mut created = vec![];
+        let result = typed::dispatch(
+            data_type,
+            WriteReversedJob {
+                converter: self,
+                rev_path: &rev_path,
+                out_path,
+                types: (own_type, data_type),
+                created: &mut created,
+            },
+        );
+        if result.is_err() {
+            for path in &created {
+                let _ = raster::remove_output(path);
+            }
+        }
+        return result;
+    }
+
+    fn reverse_dataset_as<T: RasterValue + Send>(
+        &self,
+        ds_path: &str,
+        grid: &OutputGrid,
+    ) -> Result<()> {
+        let flip = self.flip_of(ds_path, grid.flip);
+        if let Some(sharded) = shards::find(&self.options.shards, ds_path) {
+            return shards::reverse_shards::<T>(
+                &self.file,
+                self.derived_file(),
+                sharded,
+                (grid.xsize, grid.ysize),
+                flip,
+                self.options.memory_limit,
+                self.options.exists_policy,
+                &self.options.compression,
+                self.options.resizable,
+            );
+        }
+        if ds_path.ends_with("_rev") {
+            return Ok(());
+        }
+        if let Some(swmr) = self.options.swmr {
+            swmr::wait_for(&self.file, ds_path, grid.xsize * grid.ysize, swmr)?;
+        }
+        vds::check_sources(&self.file, &self.file.dataset(ds_path)?)?;
+        let numeric = meta::get(&self.file, ds_path)?.value_class() == hdf::ValueClass::Numeric;
+        if self.options.virtual_rev && numeric {
+            vds::create_virtual_rev::<T>(
+                &self.file,
+                self.derived_file(),
+                ds_path,
+                (grid.xsize, grid.ysize),
+                flip,
+                self.options.exists_policy,
+            )?;
+        } else {
+            reverse::reverse_ds_rows::<T>(
+                &self.file,
+                self.derived_file(),
+                ds_path.to_owned(),
+                grid.xsize,
+                grid.ysize,
+                flip,
+                self.options.memory_limit,
+                self.options.exists_policy,
+                self.options.field.as_deref(),
+                &self.options.compression,
+                self.options.resizable,
+            )?;
+        }
+        let rows = match self.options.verify_reverse {
+            Some(rows) => rows,
+            None => return Ok(()),
+        };
+        let mismatches = verify::verify_reverse::<T>(
+            &self.file,
+            self.derived_file(),
+            ds_path,
+            self.options.field.as_deref(),
+            (grid.xsize, grid.ysize),
+            flip,
+            rows,
+            VerifyOptions::default().seed,
+        )?;
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        for mismatch in mismatches.iter().take(10) {
+            warnings::record(
+                Warning::Verification,
+                format!(
+                    "{} differs at ({}, {}): expected {}, read {}",
+                    mismatch.path, mismatch.x, mismatch.y, mismatch.expected, mismatch.actual
+                ),
+            );
+        }
+        // A later run would otherwise keep it.
+        let rev_path = format!("{}_rev", ds_path);
+        self.derived_file().unlink(&rev_path)?;
+        return Err(Error::Invalid(format!(
+            "{} does not mirror the rows of {} ({} values differ); it was removed",
+            rev_path,
+            ds_path,
+            mismatches.len()
+        )));
+    }
+
+    /// Writes the reversed dataset at `rev_path`, of `own_type`, to the
+    /// raster at `out_path` as `data_type`.
+    fn write_reversed<T: RasterValue>(
+        &self,
+        rev_path: &str,
+        out_path: &str,
+        own_type: RasterType,
+        data_type: RasterType,
+        created: &mut Vec<String>,
+    ) -> Result<()> {
+        let ds_path = rev_path.trim_end_matches("_rev");
+        // The reversed dataset always has a fill value, the pixels without data.
+        self.note_unverified(rev_path);
+        let rev_ds = self.derived_file().dataset(rev_path)?;
+        let nodata = match overrides::resolve(&self.options.overrides, ds_path).nodata {
+            Some(nodata) => Some(nodata),
+            None => attrs::user_fill_value::<f64>(&rev_ds)?,
+        };
+        let valid_range = self.valid_range(Some(&rev_ds))?;
+        let narrow = self.forced_type(ds_path).is_some() && hdf::loses_precision::<T>(&rev_ds)?;
+        drop(rev_ds);
+        self.write_typed::<T, _>(
+            self.derived_file(),
+            rev_path,
+            ds_path,
+            out_path,
+            own_type,
+            data_type,
+            nodata,
+            valid_range,
+            narrow,
+            created,
+        )?;
+        if data_type == RasterType::Byte && created.iter().any(|path| path == out_path) {
+            self.style_byte_output(out_path, created)?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the values of `source` at `path`, the data of the dataset at
+    /// `ds_path` with `nodata`, to the raster at `out_path` as `data_type`.
+    /// With `narrow`, they are read as `f64` and narrowed (see
+    /// [`narrowing`]), and the pixels clipped recorded.
+    #[allow(clippy::too_many_arguments)]
+    fn write_typed<T, S>(
+        &self,
+        source: &S,
+        path: &str,
+        ds_path: &str,
+        out_path: &str,
+        own_type: RasterType,
+        data_type: RasterType,
+        nodata: Option<f64>,
+        valid_range: Option<ValidRange>,
+        narrow: bool,
+        created: &mut Vec<String>,
+    ) -> Result<()>
+    where
+        T: RasterValue,
+        S: HdfSource<T> + HdfSource<f64>,
+    {
+        let units = self.dataset_units(ds_path, data_type)?;
+        let name = ds_path.rsplit('/').next().unwrap_or(ds_path);
+        let bytes_per_pixel = chunking::dataset_raster_bytes_per_pixel(std::mem::size_of::<T>());
+        let n_lines_read =
+            chunking::strip_lines(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        let block_cols =
+            chunking::block_cols(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        if !narrow {
+            return raster::write_output::<T, _>(
+                source,
+                path,
+                self.options.format,
+                self.template(data_type),
+                out_path,
+                name,
+                self.xsize,
+                self.ysize,
+                n_lines_read,
+                block_cols,
+                self.options.exists_policy,
+                nodata,
+                valid_range,
+                units,
+                None,
+                created,
+            );
+        }
+        let narrowed = Narrowed::new::<T>(source, nodata, own_type.is_float());
+        let n_created = created.len();
+        raster::write_output::<T, _>(
+            &narrowed,
+            path,
+            self.options.format,
+            self.template(data_type),
+            out_path,
+            name,
+            self.xsize,
+            self.ysize,
+            n_lines_read,
+            block_cols,
+            self.options.exists_policy,
+            narrowed.nodata(),
+            valid_range,
+            units,
+            None,
+            created,
+        )?;
+        if created.len() > n_created {
+            narrowing::record(narrowed.finish(out_path, data_type.name()));
+        }
+        return Ok(());
+    }
+
+    /// Writes each time slice of the (time, y, x) dataset at `ds_path` to its
+    /// own raster, named after the dataset and the slice label taken from
+    /// `time_coord` (see [`timeseries::time_labels`]), as the type given by
+    /// [`HdfRasterConverter::output_type`]. Slices are read with their rows
+    /// reversed, without writing a `_rev` dataset. Completed outputs are
+    /// pushed to `created`; a slice left partial by an error is removed.
+    pub fn convert_time_series(
+        &self,
+        ds_path: &str,
+        time_coord: Option<&str>,
+        created: &mut Vec<String>,
+    ) -> Result<()> {
+        let ds = self.file.dataset(ds_path)?;
+        if compound::compound_fields(&ds)?.is_some() {
+            return Err(Error::Invalid(format!(
+                "time series dataset {} has a compound type",
+                ds_path
+            )));
+        }
+        let n_steps = timeseries::time_steps(&ds, self.xsize, self.ysize)?;
+        let labels = timeseries::time_labels(&self.file, &ds, n_steps, time_coord)?;
+        let own_type = own_type(&ds)?;
+        let types = (own_type, self.output_type(ds_path, own_type));
+        return typed::dispatch(
+            types.1,
+            TimeSlicesJob {
+                converter: self,
+                ds: &ds,
+                labels: &labels,
+                types,
+                created,
+            },
+        );
+    }
+
+    /// Writes the time slices of `ds`, of the first of `types`, as the
+    /// second.
+    fn write_time_slices<T: RasterValue>(
+        &self,
+        ds: &hdf5::Dataset,
+        labels: &[String],
+        types: (RasterType, RasterType),
+        created: &mut Vec<String>,
+    ) -> Result<()> {
+        let (own_type, data_type) = types;
+        let ds_path = ds.name();
+        let settings = overrides::resolve(&self.options.overrides, &ds_path);
+        let forced = self.forced_type(&ds_path).is_some();
+        let loses_precision = hdf::loses_precision::<T>(ds)?;
+        if loses_precision && !forced {
+            warnings::warn(
+                Warning::PrecisionLoss,
+                format!(
+                    "dataset {} is converted to {}, which cannot hold all its values",
+                    ds_path,
+                    data_type.name()
+                ),
+            )?;
+        }
+        let narrow = forced && loses_precision;
+        let nodata = match settings.nodata {
+            Some(nodata) => Some(nodata),
+            None => attrs::user_fill_value::<f64>(ds)?,
+        };
+        let flip = self.flip_of(&ds_path, self.flip);
+        let valid_range = self.valid_range(Some(ds))?;
+        for (index, label) in labels.iter().enumerate() {
+            let out_path = raster::output_path(&ds_path, label, self.options.format.extension());
+            println!(
+                "Writing time slice {} ({} of {}) to {}",
+                label,
+                index + 1,
+                labels.len(),
+                out_path
+            );
+            let n_created = created.len();
+            let result = self.write_typed::<T, _>(
+                &TimeSlice::new(ds, index, self.xsize, self.ysize, flip),
+                &ds_path,
+                &ds_path,
+                &out_path,
+                own_type,
+                data_type,
+                nodata,
+                valid_range,
+                narrow,
+                created,
+            );
+            let result = match result {
+                Ok(()) if data_type == RasterType::Byte && created.len() > n_created => {
+                    self.style_byte_output(&out_path, created)
+                }
+                result => result,
+            };
+            if result.is_err() {
+                for path in created.drain(n_created..) {
+                    let _ = raster::remove_output(&path);
+                }
+            }
+            result?;
+        }
+        return Ok(());
+    }
+
+    /// Reverses the dataset at `ds_path` like [`convert_dataset`] but returns
+    /// the raster as an (ysize, xsize) array instead of writing a file.
+    ///
+    /// [`convert_dataset`]: HdfRasterConverter::convert_dataset
+    pub fn convert_dataset_to_array<T: RasterValue>(&self, ds_path: &str) -> Result<Array2<T>> {
+        reverse::reverse_ds_rows::<T>(
+            &self.file,
+            self.derived_file(),
+            ds_path.to_owned(),
+            self.xsize,
+            self.ysize,
+            self.flip_of(ds_path, self.flip),
+            self.options.memory_limit,
+            self.options.exists_policy,
+            self.options.field.as_deref(),
+            &self.options.compression,
+            self.options.resizable,
+        )?;
+        self.derived_file().flush()?;
+
+        let bytes_per_pixel = chunking::dataset_raster_bytes_per_pixel(std::mem::size_of::<T>());
+        let n_lines_read =
+            chunking::strip_lines(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        let block_cols =
+            chunking::block_cols(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        let mut sink = MemorySink::new();
+        raster::write_dataset_raster(
+            self.derived_file(),
+            &format!("{}_rev", ds_path),
+            &mut sink,
+            self.xsize,
+            self.ysize,
+            n_lines_read,
+            block_cols,
+            None,
+        )?;
+        return Ok(sink.into_array());
+    }
+
+    /// Blocks of the dataset at `ds_path` in output order, sized by the
+    /// memory limit and flipped as when converting it, for sinks of the
+    /// caller's own. Their nodata value is that of its override, or else
+    /// its fill value; see [`ChunkIterator`].
+    pub fn chunks<T: hdf5::H5Type + RangeValue>(&self, ds_path: &str) -> Result<ChunkIterator<T>> {
+        let bytes_per_pixel = chunking::dataset_raster_bytes_per_pixel(std::mem::size_of::<T>());
+        let n_lines = chunking::strip_lines(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        let block_cols =
+            chunking::block_cols(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        let chunks = ChunkIterator::of_dataset(
+            self.file.dataset(ds_path)?,
+            self.options.field.as_deref(),
+            (self.xsize, self.ysize),
+            self.flip_of(ds_path, self.flip),
+            n_lines,
+            block_cols,
+        )?;
+        return Ok(
+            match overrides::resolve(&self.options.overrides, ds_path).nodata {
+                Some(nodata) => chunks.with_nodata(Some(nodata)),
+                None => chunks,
+            },
+        );
+    }
+
+    /// Reads the dataset at `ds_path` with its rows in output order, in strips sized
+    /// by the memory limit, without writing a `_rev` dataset. Compound
+    /// datasets are read through `options.field`.
+    pub fn read_dataset_as_array2<T: hdf5::H5Type + Clone>(
+        &self,
+        ds_path: &str,
+    ) -> Result<Array2<T>> {
+        let ds = self.file.dataset(ds_path)?;
+        if ds.size() != self.xsize * self.ysize {
+            return Err(Error::Invalid(format!(
+                "dataset {} does not match the {}x{} template",
+                ds_path, self.xsize, self.ysize
+            )));
+        }
+        let bytes_per_pixel = chunking::reverse_bytes_per_pixel(std::mem::size_of::<T>());
+        let n_lines_read =
+            chunking::strip_lines(self.options.memory_limit, self.xsize, bytes_per_pixel);
+        let flip = self.flip_of(ds_path, self.flip);
+        return match compound::field_reader(&ds, self.options.field.as_deref())? {
+            Some(reader) => {
+                reverse::read_flipped(&reader, self.xsize, self.ysize, n_lines_read, flip)
+            }
+            None => {
+                reverse::read_flipped(&HdfChunks(&ds), self.xsize, self.ysize, n_lines_read, flip)
+            }
+        };
+    }
+
+    /// Reads the statistic datasets of `group` and computes its mean and sd
+    /// in memory, without writing to the HDF5 file.
+    pub fn read_group_stats(&self, group: &str) -> Result<GroupStats> {
+        let names = &self.options.stat_names;
+        let count = self.read_dataset_as_array2::<u32>(&hdf::join_path(group, &names.count))?;
+        let wide = stats::is_wide(&self.file, group, names, "")?;
+
+        let shape = (self.ysize, self.xsize);
+        let (mean, sd) = stats::mean_sd_with(
+            names,
+            wide,
+            self.options.min_count,
+            count.as_slice().unwrap(),
+            |name| {
+                let vals = self.read_dataset_as_array2::<f64>(&hdf::join_path(group, name))?;
+                return Ok(vals.into_raw_vec());
+            },
+        )?;
+        return Ok(GroupStats {
+            count,
+            mean: Array2::from_shape_vec(shape, mean).unwrap(),
+            sd: Array2::from_shape_vec(shape, sd).unwrap(),
+        });
+    }
+
+    /// [`read_group_stats`] as an Arrow record batch with one row per pixel
+    /// and the columns `row`, `col`, `count`, `mean` and `sd`.
+    ///
+    /// [`read_group_stats`]: HdfRasterConverter::read_group_stats
+    #[cfg(feature = "arrow")]
+    pub fn read_group_stats_batch(&self, group: &str) -> Result<arrow::record_batch::RecordBatch> {
+        use arrow::array::{ArrayRef, Float32Array, UInt32Array};
+        use arrow::datatypes::{DataType, Field, Schema};
+        use std::sync::Arc;
+
+        let stats = self.read_group_stats(group)?;
+        let n_pixels = self.xsize * self.ysize;
+        let rows: Vec<u32> = (0..n_pixels).map(|ii| (ii / self.xsize) as u32).collect();
+        let cols: Vec<u32> = (0..n_pixels).map(|ii| (ii % self.xsize) as u32).collect();
+        let schema = Schema::new(vec![
+            Field::new("row", DataType::UInt32, false),
+            Field::new("col", DataType::UInt32, false),
+            Field::new("count", DataType::UInt32, false),
+            Field::new("mean", DataType::Float32, false),
+            Field::new("sd", DataType::Float32, false),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt32Array::from(rows)),
+            Arc::new(UInt32Array::from(cols)),
+            Arc::new(UInt32Array::from(stats.count.into_raw_vec())),
+            Arc::new(Float32Array::from(stats.mean.into_raw_vec())),
+            Arc::new(Float32Array::from(stats.sd.into_raw_vec())),
+        ];
+        return Ok(arrow::record_batch::RecordBatch::try_new(
+            Arc::new(schema),
+            columns,
+        )?);
+    }
+
+    /// Count, mean and sd of `group` at `points`, given in the CRS of the
+    /// templates, read from its statistic datasets.
+    pub fn extract_points(&self, group: &str, points: &[Point]) -> Result<Vec<Sample>> {
+        let georef = raster::template_georef(&self.base_byte_path)?;
+        return extract::extract_group(
+            &self.file,
+            group,
+            &self.options.stat_names,
+            self.options.field.as_deref(),
+            &georef.geo_transform,
+            (self.xsize, self.ysize),
+            self.flip,
+            self.options.min_count,
+            points,
+        );
+    }
+
+    /// Compares statistic `stat` of `group` with the raster at `reference`;
+    /// see [`compare::compare_group`]. With `diff_path`, the difference is
+    /// written there in the output format, and the path pushed to `created`
+    /// once the file exists.
+    #[cfg(feature = "gdal")]
+    pub fn compare_group(
+        &self,
+        group: &str,
+        stat: &str,
+        reference: &str,
+        diff_path: Option<&str>,
+        created: &mut Vec<String>,
+    ) -> Result<Comparison> {
+        let n_lines = chunking::strip_lines(
+            self.options.memory_limit,
+            self.xsize,
+            chunking::COMPARE_BYTES_PER_PIXEL,
+        );
+        let mut diff: Option<Box<dyn RasterSink<f32>>> = None;
+        if let Some(diff_path) = diff_path {
+            if self.options.exists_policy.decide_file(diff_path)? == Decision::Create {
+                let sink = self.options.format.create_sink::<f32>(
+                    &self.base_float_path,
+                    diff_path,
+                    "diff",
+                    None,
+                );
+                created.push(diff_path.to_owned());
+                diff = Some(sink?);
+            }
+        }
+        return compare::compare_group(
+            &self.file,
+            group,
+            &self.options.stat_names,
+            self.options.field.as_deref(),
+            stat,
+            (self.xsize, self.ysize),
+            self.flip,
+            self.options.min_count,
+            reference,
+            n_lines,
+            diff.as_mut()
+                .map(|sink| &mut **sink as &mut dyn RasterSink<f32>),
+        );
+    }
+
+    /// Writes the change from `group_a` to `group_b` of `other`, a file on
+    /// the same grid, or of the converted file without it; see
+    /// [`change::write_change`]. The outputs go to `<prefix>_diff`,
+    /// `<prefix>_rel_change` and `<prefix>_significant` in the output
+    /// format, their paths pushed to `created` once the files exist.
+    pub fn change_groups(
+        &self,
+        group_a: &str,
+        other: Option<&hdf5::File>,
+        group_b: &str,
+        alpha: f64,
+        prefix: &str,
+        created: &mut Vec<String>,
+    ) -> Result<ChangeSummary> {
+        let file_b = other.unwrap_or(&self.file);
+        self.check_stat_group(group_a)?;
+        self.check_stat_group_of(file_b, group_b)?;
+        let flip_b = match other {
+            Some(other) => {
+                let count_path = hdf::join_path(group_b, &self.options.stat_names.count);
+                let len = meta::get(other, &count_path)?.size();
+                if len != self.xsize * self.ysize {
+                    return Err(Error::Invalid(format!(
+                        "{} of the other file has {} elements, the grid is {}x{}",
+                        count_path, len, self.xsize, self.ysize
+                    )));
+                }
+                let geo_transform = raster::template_georef(&self.base_byte_path)?.geo_transform;
+                row_order::needs_flip(self.options.flip, other, &geo_transform)?
+            }
+            None => self.flip,
+        };
+        let wide = |file: &hdf5::File, group: &str| -> Result<bool> {
+            return Ok(self.options.field.is_none()
+                && stats::is_wide(file, group, &self.options.stat_names, "")?);
+        };
+        let a = ChangeSide {
+            file: &self.file,
+            group: group_a,
+            flip: self.flip,
+            wide: wide(&self.file, group_a)?,
+        };
+        let b = ChangeSide {
+            file: file_b,
+            group: group_b,
+            flip: flip_b,
+            wide: wide(file_b, group_b)?,
+        };
+
+        let extension = self.options.format.extension();
+        let out_path = |name: &str| format!("{}_{}.{}", prefix, name, extension);
+        let [diff_name, rel_name, significant_name] = change::CHANGE_OUTPUTS;
+        let mut float_sink = |name: &str| -> Result<Option<Box<dyn RasterSink<f32>>>> {
+            let path = out_path(name);
+            if self.options.exists_policy.decide_file(&path)? == Decision::Skip {
+                return Ok(None);
+            }
+            let sink =
+                self.options
+                    .format
+                    .create_sink::<f32>(&self.base_float_path, &path, name, None);
+            created.push(path);
+            let mut sink = sink?;
+            sink.set_nodata(f64::NAN)?;
+            return Ok(Some(sink));
+        };
+        let mut diff = float_sink(diff_name)?;
+        let mut rel_change = float_sink(rel_name)?;
+        let significant_path = out_path(significant_name);
+        let mut significant = None;
+        if self.options.exists_policy.decide_file(&significant_path)? == Decision::Create {
+            let sink = self.options.format.create_sink::<u8>(
+                &self.base_byte_path,
+                &significant_path,
+                significant_name,
+                None,
+            );
+            created.push(significant_path);
+            let mut sink = sink?;
+            sink.set_nodata(change::UNTESTED as f64)?;
+            significant = Some(sink);
+        }
+
+        let n_lines = chunking::strip_lines(
+            self.options.memory_limit,
+            self.xsize,
+            chunking::CHANGE_BYTES_PER_PIXEL,
+        );
+        return change::write_change(
+            a,
+            b,
+            &self.options.stat_names,
+            self.options.field.as_deref(),
+            (self.xsize, self.ysize),
+            self.options.min_count,
+            alpha,
+            n_lines,
+            diff.as_mut()
+                .map(|sink| &mut **sink as &mut dyn RasterSink<f32>),
+            rel_change
+                .as_mut()
+                .map(|sink| &mut **sink as &mut dyn RasterSink<f32>),
+            significant
+                .as_mut()
+                .map(|sink| &mut **sink as &mut dyn RasterSink<u8>),
+        );
+    }
+
+    /// Renders statistic `stat` of `group` as an XYZ tile pyramid in
+    /// `out_dir`; see [`xyz::render_group`]. Returns the number of tiles
+    /// written.
+    pub fn render_xyz(
+        &self,
+        group: &str,
+        stat: &str,
+        options: &XyzOptions,
+        out_dir: &Path,
+    ) -> Result<usize> {
+        let n_lines = chunking::strip_lines(
+            self.options.memory_limit,
+            self.xsize,
+            chunking::COMPARE_BYTES_PER_PIXEL,
+        );
+        // Samples kept take a quarter of the memory limit, as f32.
+        let max_values = self.options.memory_limit / 4 / 4;
+        return xyz::render_group(
+            &self.file,
+            group,
+            &self.options.stat_names,
+            self.options.field.as_deref(),
+            stat,
+            (self.xsize, self.ysize),
+            self.flip,
+            self.options.min_count,
+            &raster::template_georef(&self.base_byte_path)?,
+            (n_lines, max_values),
+            options,
+            out_dir,
+        );
+    }
+
+    /// Reads back random windows of the outputs of `group` and compares them
+    /// with values recomputed from its statistic datasets.
+    pub fn verify_group(&self, group: &str, options: &VerifyOptions) -> Result<Vec<Mismatch>> {
+        let grid = self.grid_of(group);
+        return verify::verify_group(
+            &self.file,
+            group,
+            &self.options.stat_names,
+            self.options.field.as_deref(),
+            self.options.format,
+            (grid.xsize, grid.ysize),
+            grid.flip,
+            self.options.min_count,
+            options,
+        );
+    }
+
+    /// Fails with `Error::Invalid` unless `group` contains every statistic
+    /// dataset.
+    pub fn check_stat_group(&self, group: &str) -> Result<()> {
+        return self.check_stat_group_of(&self.file, group);
+    }
+
+    /// As [`Self::check_stat_group`], for a group of `file`.
+    fn check_stat_group_of(&self, file: &hdf5::File, group: &str) -> Result<()> {
+        let nodes = vec![H5NodeType::Group(format!("/{}", group.trim_matches('/')))];
+        let (with_stats, _) = hdf::stat_groups(file, &nodes, &self.options.stat_names.all());
+        if with_stats.is_empty() {
+            return Err(Error::Invalid(format!(
+                "group {} does not contain numeric {}",
+                group,
+                self.options.stat_names.all().join(", ")
+            )));
+        }
+        return Ok(());
+    }
+}
+
+/// [`HdfRasterConverter::reverse_dataset_as`] for the type dispatched.
+struct ReverseJob<'a> {
+    converter: &'a HdfRasterConverter,
+    ds_path: &'a str,
+    grid: &'a OutputGrid,
+}
+
+impl TypedJob for ReverseJob<'_> {
+    type Output = Result<()>;
+
+    fn run<T: RasterValue + Send>(self) -> Result<()> {
+        return self
+            .converter
+            .reverse_dataset_as::<T>(self.ds_path, self.grid);
+    }
+}
+
+/// [`HdfRasterConverter::write_reversed`] for the type dispatched, the
+/// second of `types`.
+struct WriteReversedJob<'a> {
+    converter: &'a HdfRasterConverter,
+    rev_path: &'a str,
+    out_path: &'a str,
+    types: (RasterType, RasterType),
+    created: &'a mut Vec<String>,
+}
+
+impl TypedJob for WriteReversedJob<'_> {
+    type Output = Result<()>;
+
+    fn run<T: RasterValue + Send>(self) -> Result<()> {
+        let (own_type, data_type) = self.types;
+        return self.converter.write_reversed::<T>(
+            self.rev_path,
+            self.out_path,
+            own_type,
+            data_type,
+            self.created,
+        );
+    }
+}
+
+/// [`HdfRasterConverter::write_time_slices`] for the type dispatched, the
+/// second of `types`.
+struct TimeSlicesJob<'a> {
+    converter: &'a HdfRasterConverter,
+    ds: &'a hdf5::Dataset,
+    labels: &'a [String],
+    types: (RasterType, RasterType),
+    created: &'a mut Vec<String>,
+}
+
+impl TypedJob for TimeSlicesJob<'_> {
+    type Output = Result<()>;
+
+    fn run<T: RasterValue + Send>(self) -> Result<()> {
+        return self.converter.write_time_slices::<T>(
+            self.ds,
+            self.labels,
+            self.types,
+            self.created,
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Grids described by dimension scales: 1-d datasets of the coordinates of
+//! the rows and columns, e.g. `lat` and `lon`.
+//!
+//! The scales of a 2-d dataset are those attached to it (its
+//! `DIMENSION_LIST` attribute, as `H5DSattach_scale` writes it). Without
+//! attached scales, 1-d datasets named `lat`/`latitude`/`y` and
+//! `lon`/`longitude`/`x` are taken, in the group and then in the root
+//! group. The coordinates are those of the pixel centers; their spacing
+//! gives the geotransform when it is regular. Irregular spacing is
+//! reported as an `irregular-scale` warning and the scales are left unused,
+//! unless `--snap-scales` snaps them to the nearest regular grid (a least
+//! squares fit). Scales in degrees (named as latitudes and longitudes, or
+//! with `degrees_*` units) give the grid a WGS84 CRS.
+
+use crate::error::Result;
+use crate::grid::Grid;
+use crate::hdf::{self, HasMembers, ObjectKind, ValueClass};
+use crate::strings;
+use crate::warnings::{self, Warning};
+use std::ffi::CString;
+use std::sync::Mutex;
+
+/// Names of the scales of the rows and columns, by preference.
+const ROW_NAMES: &[&str] = &["lat", "latitude", "y"];
+const COLUMN_NAMES: &[&str] = &["lon", "longitude", "x"];
+
+/// Largest distance of a coordinate to the regular grid, in pixels, for
+/// its spacing to be regular.
+const TOLERANCE: f64 = 0.01;
+
+/// WKT of WGS84 longitudes and latitudes, the CRS of scales in degrees.
+const WGS84_WKT: &str = "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",\
+    SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],\
+    AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],\
+    UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],\
+    AUTHORITY[\"EPSG\",\"4326\"]]";
+
+/// Paths of the scales of the rows and columns of a grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalePaths {
+    pub rows: String,
+    pub columns: String,
+}
+
+/// Regular spacing fitted to the coordinates of a scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fit {
+    /// Coordinate of the first pixel center.
+    pub first: f64,
+    /// Distance between pixel centers, negative when decreasing.
+    pub step: f64,
+    /// Largest distance of a coordinate to the fit, in pixels.
+    pub max_offset: f64,
+}
+
+impl Fit {
+    /// Least squares fit of `first + index * step` to `values`; `None` with
+    /// fewer than two values or no spacing.
+    pub fn of(values: &[f64]) -> Option<Fit> {
+        let n = values.len() as f64;
+        if values.len() < 2 || values.iter().any(|value| !value.is_finite()) {
+            return None;
+        }
+        let mean_index = (n - 1f64) / 2f64;
+        let mean_value = values.iter().sum::<f64>() / n;
+        let (mut cov, mut var) = (0f64, 0f64);
+        for (index, value) in values.iter().enumerate() {
+            let d_index = index as f64 - mean_index;
+            cov += d_index * (value - mean_value);
+            var += d_index * d_index;
+        }
+        let step = cov / var;
+        if step == 0f64 {
+            return None;
+        }
+        let first = mean_value - step * mean_index;
+        let max_offset = values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| ((value - first - step * index as f64) / step).abs())
+            .fold(0f64, f64::max);
+        return Some(Fit {
+            first,
+            step,
+            max_offset,
+        });
+    }
+
+    pub fn is_regular(&self) -> bool {
+        return self.max_offset <= TOLERANCE;
+    }
+}
+
+/// Paths of the scales attached to the rows and columns of the 2-d dataset
+/// at `path`, if both are.
+pub fn attached(file: &hdf5::File, path: &str) -> Result<Option<ScalePaths>> {
+    let ds = file.dataset(path)?;
+    if ds.ndim() != 2 {
+        return Ok(None);
+    }
+    let names = hdf5::sync::sync(|| unsafe { dimension_list(ds.id()) });
+    return Ok(match names.as_slice() {
+        [Some(rows), Some(columns)] => Some(ScalePaths {
+            rows: rows.clone(),
+            columns: columns.clone(),
+        }),
+        _ => None,
+    });
+}
+
+/// Path of the first scale attached to each dimension of the dataset
+/// `ds_id`, as stored in its `DIMENSION_LIST` of object references.
+unsafe fn dimension_list(ds_id: hdf5_sys::h5::hid_t) -> Vec<Option<String>> {
+    use hdf5_sys::h5a::{H5Aexists, H5Aget_space, H5Aopen, H5Aread};
+    use hdf5_sys::h5d::H5Dvlen_reclaim;
+    use hdf5_sys::h5i::H5Idec_ref;
+    use hdf5_sys::h5p::H5P_DEFAULT;
+    use hdf5_sys::h5r::{hobj_ref_t, H5R_type_t, H5Rget_name};
+    use hdf5_sys::h5s::H5Sget_simple_extent_npoints;
+    use hdf5_sys::h5t::{hvl_t, H5Tvlen_create, H5T_STD_REF_OBJ};
+
+    let attr_name = CString::new("DIMENSION_LIST").unwrap();
+    let _silence = hdf5::silence_errors();
+    if H5Aexists(ds_id, attr_name.as_ptr()) <= 0 {
+        return vec![];
+    }
+    let attr = H5Aopen(ds_id, attr_name.as_ptr(), H5P_DEFAULT);
+    if attr < 0 {
+        return vec![];
+    }
+    let space = H5Aget_space(attr);
+    let mem_type = H5Tvlen_create(*H5T_STD_REF_OBJ);
+    let n_dims = H5Sget_simple_extent_npoints(space).max(0) as usize;
+    let mut lists = vec![
+        hvl_t {
+            len: 0,
+            p: std::ptr::null_mut()
+        };
+        n_dims
+    ];
+    let mut names = vec![];
+    if H5Aread(attr, mem_type, lists.as_mut_ptr() as *mut _) >= 0 {
+        for list in &lists {
+            if list.len == 0 || list.p.is_null() {
+                names.push(None);
+                continue;
+            }
+            let reference = list.p as *const hobj_ref_t;
+            let mut name = vec![0u8; 1024];
+            let len = H5Rget_name(
+                ds_id,
+                H5R_type_t::H5R_OBJECT,
+                reference as *const _,
+                name.as_mut_ptr() as *mut _,
+                name.len(),
+            );
+            names.push(match len > 0 && (len as usize) < name.len() {
+                true => Some(String::from_utf8_lossy(&name[..len as usize]).into_owned()),
+                false => None,
+            });
+        }
+        H5Dvlen_reclaim(mem_type, space, H5P_DEFAULT, lists.as_mut_ptr() as *mut _);
+    }
+    H5Idec_ref(mem_type);
+    H5Idec_ref(space);
+    H5Idec_ref(attr);
+    return names;
+}
+
+/// Path of the first 1-d numeric dataset named after one of `names` in
+/// `group`.
+fn named_scale(file: &hdf5::File, group: &str, names: &[&str]) -> Option<String> {
+    let _silence = hdf5::silence_errors();
+    return names
+        .iter()
+        .map(|name| hdf::join_path(group, name))
+        .find(|path| {
+            return file.object_kind(path) == ObjectKind::Dataset
+                && file.dataset(path).map_or(false, |ds| {
+                    ds.ndim() == 1 && hdf::value_class(&ds).ok() == Some(ValueClass::Numeric)
+                });
+        });
+}
+
+/// Scales of the rows and columns of `group`: those attached to the first
+/// of `datasets` that has them, or else named as such in the group or the
+/// root.
+pub fn find(file: &hdf5::File, group: &str, datasets: &[String]) -> Result<Option<ScalePaths>> {
+    for path in datasets {
+        if let Some(scales) = attached(file, path)? {
+            return Ok(Some(scales));
+        }
+    }
+    for parent in &[group, "/"] {
+        if let (Some(rows), Some(columns)) = (
+            named_scale(file, parent, ROW_NAMES),
+            named_scale(file, parent, COLUMN_NAMES),
+        ) {
+            return Ok(Some(ScalePaths { rows, columns }));
+        }
+    }
+    return Ok(None);
+}
+
+/// Whether the scale at `path` holds degrees: named as latitudes or
+/// longitudes, or with `degrees_*` units.
+fn in_degrees(file: &hdf5::File, path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+    if name.starts_with("lat") || name.starts_with("lon") {
+        return true;
+    }
+    let _silence = hdf5::silence_errors();
+    return file
+        .dataset(path)
+        .and_then(|ds| ds.attr("units"))
+        .ok()
+        .and_then(|attr| strings::read_strings(&attr))
+        .map_or(false, |units| {
+            units.concat().to_lowercase().starts_with("degree")
+        });
+}
+
+/// Scales warned about so far, once each however many groups use them.
+static WARNED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Warns of `kind` about the scale at `path`, unless it already was.
+fn warn_once(kind: Warning, path: &str, message: String) -> Result<()> {
+    let first = WARNED.lock().map_or(true, |mut warned| {
+        if warned.iter().any(|warned| warned == path) {
+            return false;
+        }
+        warned.push(path.to_owned());
+        return true;
+    });
+    if first {
+        warnings::warn(kind, message)?;
+    }
+    return Ok(());
+}
+
+/// Fit of the scale at `path`; `None` if it is not regular and `snap` is
+/// not set, with a warning.
+fn fit_scale(file: &hdf5::File, path: &str, snap: bool) -> Result<Option<Fit>> {
+    let values = file.dataset(path)?.read_raw::<f64>()?;
+    let fit = match Fit::of(&values) {
+        Some(fit) => fit,
+        None => {
+            let message = format!("dimension scale {} has no spacing, ignoring it", path);
+            warn_once(Warning::Skipped, path, message)?;
+            return Ok(None);
+        }
+    };
+    if fit.is_regular() {
+        return Ok(Some(fit));
+    }
+    let message = format!(
+        "dimension scale {} is irregular: its coordinates are up to {:.3} pixels from a \
+         regular spacing of {}",
+        path, fit.max_offset, fit.step
+    );
+    return match snap {
+        false => {
+            let message = format!(
+                "{}; ignoring it (--snap-scales snaps it to that grid)",
+                message
+            );
+            warn_once(Warning::IrregularScale, path, message)?;
+            Ok(None)
+        }
+        true => {
+            let message = format!("{}; snapped to that grid", message);
+            warn_once(Warning::IrregularScale, path, message)?;
+            Ok(Some(fit))
+        }
+    };
+}
+
+/// Grid of the scales `scales` of `what`, for its rows as stored, if it
+/// has `len` pixels when given and their spacing is regular or snapped with
+/// `snap`.
+pub fn grid_of(
+    file: &hdf5::File,
+    scales: &ScalePaths,
+    what: &str,
+    len: Option<usize>,
+    snap: bool,
+) -> Result<Option<Grid>> {
+    let xsize = file.dataset(&scales.columns)?.size();
+    let ysize = file.dataset(&scales.rows)?.size();
+    if let Some(len) = len {
+        if xsize * ysize != len {
+            println!(
+                "Ignoring dimension scales {} and {} of {}: their {}x{} grid does not                  hold its {} values",
+                scales.rows, scales.columns, what, xsize, ysize, len
+            );
+            return Ok(None);
+        }
+    }
+    let (x_fit, y_fit) = match (
+        fit_scale(file, &scales.columns, snap)?,
+        fit_scale(file, &scales.rows, snap)?,
+    ) {
+        (Some(x_fit), Some(y_fit)) => (x_fit, y_fit),
+        _ => return Ok(None),
+    };
+    let crs = match in_degrees(file, &scales.rows) && in_degrees(file, &scales.columns) {
+        true => Some(WGS84_WKT.to_owned()),
+        false => None,
+    };
+    return Ok(Some(Grid {
+        xsize,
+        ysize,
+        geo_transform: [
+            x_fit.first - x_fit.step / 2f64,
+            x_fit.step,
+            0f64,
+            y_fit.first - y_fit.step / 2f64,
+            0f64,
+            y_fit.step,
+        ],
+        crs,
+    }));
+}
+
+/// Grid of the scales of `group` (see [`find`]), whose datasets `datasets`
+/// hold `len` values when given.
+pub fn group_grid(
+    file: &hdf5::File,
+    group: &str,
+    datasets: &[String],
+    len: Option<usize>,
+    snap: bool,
+) -> Result<Option<Grid>> {
+    let scales = match find(file, group, datasets)? {
+        Some(scales) => scales,
+        None => return Ok(None),
+    };
+    let what = match group {
+        "" | "/" => "the file".to_owned(),
+        _ => format!("group {}", group),
+    };
+    let grid = grid_of(file, &scales, &what, len, snap)?;
+    if grid.is_some() {
+        println!(
+            "Taking the grid of {} from its dimension scales {} and {}",
+            what, scales.rows, scales.columns
+        );
+    }
+    return Ok(grid);
+}
@@ -0,0 +1,147 @@
+use crate::warnings::Warning;
+use std::fmt;
+
+/// Exit codes of the command line tool.
+pub mod exit_code {
+    pub const OK: i32 = 0;
+    /// Invalid command line.
+    pub const USAGE: i32 = 1;
+    /// The run finished but some outputs failed (a dataset could not be
+    /// converted, or outputs did not verify).
+    pub const PARTIAL: i32 = 2;
+    /// Invalid options or input layout, or an output that must not be
+    /// replaced, detected before it was written.
+    pub const VALIDATION: i32 = 3;
+    /// Reading or writing a file failed (HDF5, raster drivers, filesystem).
+    pub const IO: i32 = 4;
+    /// A data quality warning was made an error by `--strict`.
+    pub const STRICT: i32 = 5;
+    /// Stopped by Ctrl-C.
+    pub const INTERRUPTED: i32 = 130;
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Hdf5(hdf5::Error),
+    #[cfg(feature = "gdal")]
+    Gdal(gdal::errors::GdalError),
+    #[cfg(feature = "pure-tiff")]
+    Tiff(tiff::TiffError),
+    #[cfg(feature = "netcdf-sink")]
+    Netcdf(netcdf::error::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Png(png::EncodingError),
+    #[cfg(feature = "arrow")]
+    Arrow(arrow::error::ArrowError),
+    /// A WASM kernel failed to load or run.
+    #[cfg(feature = "wasm")]
+    Wasm(String),
+    /// Invalid options or input layout detected before processing.
+    Invalid(String),
+    /// An output exists and the policy forbids replacing it.
+    AlreadyExists(String),
+    /// The run was stopped by Ctrl-C before the current step finished.
+    Interrupted,
+    /// The run finished, but some of its outputs failed.
+    Partial(String),
+    /// A data quality warning raised in strict mode.
+    Strict(Warning, String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Hdf5(err) => write!(f, "HDF5 error: {}", err),
+            #[cfg(feature = "gdal")]
+            Error::Gdal(err) => write!(f, "GDAL error: {}", err),
+            #[cfg(feature = "pure-tiff")]
+            Error::Tiff(err) => write!(f, "TIFF error: {}", err),
+            #[cfg(feature = "netcdf-sink")]
+            Error::Netcdf(err) => write!(f, "netCDF error: {}", err),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Json(err) => write!(f, "JSON error: {}", err),
+            Error::Png(err) => write!(f, "PNG error: {}", err),
+            #[cfg(feature = "arrow")]
+            Error::Arrow(err) => write!(f, "Arrow error: {}", err),
+            #[cfg(feature = "wasm")]
+            Error::Wasm(msg) => write!(f, "WASM error: {}", msg),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+            Error::AlreadyExists(path) => write!(f, "output {} already exists", path),
+            Error::Interrupted => write!(f, "interrupted"),
+            Error::Partial(msg) => write!(f, "{}", msg),
+            Error::Strict(kind, msg) => {
+                write!(f, "{} ({}, an error with --strict)", msg, kind.name())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Exit code of the command line tool when a run fails with this error.
+    pub fn exit_code(&self) -> i32 {
+        return match self {
+            Error::Invalid(_) | Error::AlreadyExists(_) => exit_code::VALIDATION,
+            Error::Interrupted => exit_code::INTERRUPTED,
+            Error::Partial(_) => exit_code::PARTIAL,
+            Error::Strict(..) => exit_code::STRICT,
+            _ => exit_code::IO,
+        };
+    }
+}
+
+impl From<hdf5::Error> for Error {
+    fn from(err: hdf5::Error) -> Self {
+        return Error::Hdf5(err);
+    }
+}
+
+#[cfg(feature = "gdal")]
+impl From<gdal::errors::GdalError> for Error {
+    fn from(err: gdal::errors::GdalError) -> Self {
+        return Error::Gdal(err);
+    }
+}
+
+#[cfg(feature = "pure-tiff")]
+impl From<tiff::TiffError> for Error {
+    fn from(err: tiff::TiffError) -> Self {
+        return Error::Tiff(err);
+    }
+}
+
+#[cfg(feature = "netcdf-sink")]
+impl From<netcdf::error::Error> for Error {
+    fn from(err: netcdf::error::Error) -> Self {
+        return Error::Netcdf(err);
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        return Error::Io(err);
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        return Error::Json(err);
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(err: png::EncodingError) -> Self {
+        return Error::Png(err);
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow::error::ArrowError> for Error {
+    fn from(err: arrow::error::ArrowError) -> Self {
+        return Error::Arrow(err);
+    }
+}
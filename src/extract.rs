@@ -0,0 +1,261 @@
+//! Point extraction: statistic values at point coordinates, read from the
+//! statistic datasets of a group without writing any raster.
+//!
+//! Points are given in the CRS of the template grid, as a CSV with `x`/`y`
+//! (or `lon`/`lat`) columns or as GeoJSON point features. Only the parts of
+//! the HDF5 rows holding points are read.
+
+use crate::compound;
+use crate::error::{Error, Result};
+use crate::hdf;
+use crate::reverse::{ChunkReader, HdfChunks};
+use crate::stats::{self, StatNames};
+use crate::strings;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub id: String,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Statistics of one group at one point; the pixel and values are `None`
+/// for points outside the grid.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub id: String,
+    pub x: f64,
+    pub y: f64,
+    pub group: String,
+    /// Pixel as (column, row) of the output rasters.
+    pub pixel: Option<(usize, usize)>,
+    pub count: Option<u32>,
+    pub mean: Option<f32>,
+    pub sd: Option<f32>,
+}
+
+/// Reads points from a GeoJSON (`.geojson`, `.json`) or CSV file.
+pub fn read_points(path: &Path) -> Result<Vec<Point>> {
+    let text = std::fs::read_to_string(path)?;
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    if extension == "geojson" || extension == "json" {
+        return parse_geojson_points(&text);
+    }
+    return parse_csv_points(&text);
+}
+
+/// Points of a CSV with a header naming the `x`/`lon`/`longitude` and
+/// `y`/`lat`/`latitude` columns, and optionally an `id` column (the line
+/// number otherwise).
+pub fn parse_csv_points(text: &str) -> Result<Vec<Point>> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header = split_csv_line(lines.next().unwrap_or(""));
+    let column = |names: &[&str]| {
+        return header
+            .iter()
+            .position(|name| names.contains(&name.to_ascii_lowercase().as_str()));
+    };
+    let (x_col, y_col) = match (
+        column(&["x", "lon", "longitude"]),
+        column(&["y", "lat", "latitude"]),
+    ) {
+        (Some(x_col), Some(y_col)) => (x_col, y_col),
+        _ => {
+            return Err(Error::Invalid(
+                "points CSV needs x and y (or lon and lat) columns".to_owned(),
+            ))
+        }
+    };
+    let id_col = column(&["id"]);
+
+    let mut points = vec![];
+    for (ii, line) in lines.enumerate() {
+        let fields = split_csv_line(line);
+        let coord = |col: usize| -> Result<f64> {
+            let field = fields.get(col).map(|field| field.as_str()).unwrap_or("");
+            return field.parse().map_err(|_| {
+                Error::Invalid(format!("invalid coordinate '{}' in line {}", field, ii + 2))
+            });
+        };
+        points.push(Point {
+            id: match id_col.and_then(|col| fields.get(col)) {
+                Some(id) => id.clone(),
+                None => (ii + 1).to_string(),
+            },
+            x: coord(x_col)?,
+            y: coord(y_col)?,
+        });
+    }
+    return Ok(points);
+}
+
+/// Splits a CSV line on commas, dropping the quotes around fields.
+fn split_csv_line(line: &str) -> Vec<String> {
+    return line
+        .split(',')
+        .map(|field| field.trim().trim_matches('"').to_owned())
+        .collect();
+}
+
+/// Point features of a GeoJSON feature collection, identified by their `id`
+/// or `properties.id`, or their position otherwise.
+pub fn parse_geojson_points(text: &str) -> Result<Vec<Point>> {
+    let json: serde_json::Value = serde_json::from_str(text)?;
+    let features = json["features"]
+        .as_array()
+        .ok_or_else(|| Error::Invalid("GeoJSON has no features".to_owned()))?;
+    let mut points = vec![];
+    for (ii, feature) in features.iter().enumerate() {
+        let geometry = &feature["geometry"];
+        if geometry["type"] != "Point" {
+            return Err(Error::Invalid(format!(
+                "feature {} is not a point ({})",
+                ii, geometry["type"]
+            )));
+        }
+        let coord = |axis: usize| {
+            return geometry["coordinates"][axis]
+                .as_f64()
+                .ok_or_else(|| Error::Invalid(format!("feature {} has invalid coordinates", ii)));
+        };
+        let id = match (&feature["id"], &feature["properties"]["id"]) {
+            (serde_json::Value::String(id), _) | (_, serde_json::Value::String(id)) => id.clone(),
+            (id @ serde_json::Value::Number(_), _) | (_, id @ serde_json::Value::Number(_)) => {
+                id.to_string()
+            }
+            _ => (ii + 1).to_string(),
+        };
+        points.push(Point {
+            id,
+            x: coord(0)?,
+            y: coord(1)?,
+        });
+    }
+    return Ok(points);
+}
+
+/// Pixel of the output rasters holding (`x`, `y`), as (column, row), or
+/// `None` outside the `xsize` by `ysize` grid of `geo_transform`.
+pub fn pixel_at(
+    geo_transform: &[f64; 6],
+    (xsize, ysize): (usize, usize),
+    x: f64,
+    y: f64,
+) -> Result<Option<(usize, usize)>> {
+    if geo_transform[2] != 0f64 || geo_transform[4] != 0f64 {
+        return Err(Error::Invalid(
+            "cannot extract points from a rotated grid".to_owned(),
+        ));
+    }
+    let col = ((x - geo_transform[0]) / geo_transform[1]).floor();
+    let row = ((y - geo_transform[3]) / geo_transform[5]).floor();
+    if !(col >= 0f64 && row >= 0f64 && col < xsize as f64 && row < ysize as f64) {
+        return Ok(None);
+    }
+    return Ok(Some((col as usize, row as usize)));
+}
+
+/// Count, mean and sd of `group` at each of `points`. Each HDF5 row holding
+/// points is read once, from its first to its last point. With `flip`, the
+/// rows of the HDF5 datasets run opposite to the outputs.
+pub fn extract_group(
+    file: &hdf5::File,
+    group: &str,
+    stat_names: &StatNames,
+    field: Option<&str>,
+    geo_transform: &[f64; 6],
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    min_count: u32,
+    points: &[Point],
+) -> Result<Vec<Sample>> {
+    let mut samples = vec![];
+    // Points by row of the HDF5 datasets.
+    let mut rows: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for point in points {
+        let pixel = pixel_at(geo_transform, (xsize, ysize), point.x, point.y)?;
+        if let Some((_, row)) = pixel {
+            let src_row = if flip { ysize - 1 - row } else { row };
+            rows.entry(src_row).or_default().push(samples.len());
+        }
+        samples.push(Sample {
+            id: point.id.clone(),
+            x: point.x,
+            y: point.y,
+            group: group.to_owned(),
+            pixel,
+            count: None,
+            mean: None,
+            sd: None,
+        });
+    }
+
+    let wide = field.is_none() && stats::is_wide(file, group, stat_names, "")?;
+    let count_ds = file.dataset(&hdf::join_path(group, &stat_names.count))?;
+    for (src_row, indices) in rows.iter() {
+        let cols: Vec<usize> = indices
+            .iter()
+            .map(|&ii| samples[ii].pixel.unwrap().0)
+            .collect();
+        let first = cols.iter().min().unwrap();
+        let last = cols.iter().max().unwrap();
+        let (start, end) = (src_row * xsize + first, src_row * xsize + last + 1);
+        let count = read_range::<u32>(&count_ds, field, start, end)?;
+        let (mean, sd) = stats::mean_sd_with(stat_names, wide, min_count, &count, |name| {
+            let ds = file.dataset(&hdf::join_path(group, name))?;
+            return read_range::<f64>(&ds, field, start, end);
+        })?;
+        for (&ii, col) in indices.iter().zip(cols.iter()) {
+            let offset = col - first;
+            samples[ii].count = Some(count[offset]);
+            samples[ii].mean = Some(mean[offset]);
+            samples[ii].sd = Some(sd[offset]);
+        }
+    }
+    return Ok(samples);
+}
+
+fn read_range<T: hdf5::H5Type + Clone>(
+    ds: &hdf5::Dataset,
+    field: Option<&str>,
+    start: usize,
+    end: usize,
+) -> Result<Vec<T>> {
+    let vals = match compound::field_reader(ds, field)? {
+        Some(reader) => reader.read_range(start, end)?,
+        None => HdfChunks(ds).read_range(start, end)?,
+    };
+    return Ok(vals.to_vec());
+}
+
+/// Writes `samples` as CSV: `id,x,y,group,col,row,count,mean,sd`, with empty
+/// pixel and values outside the grid.
+pub fn write_csv(samples: &[Sample], out: &mut impl Write) -> Result<()> {
+    writeln!(out, "id,x,y,group,col,row,count,mean,sd")?;
+    let text = |value: Option<String>| value.unwrap_or_default();
+    for sample in samples {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            strings::csv_field(&sample.id),
+            sample.x,
+            sample.y,
+            strings::csv_field(&sample.group),
+            text(sample.pixel.map(|(col, _)| col.to_string())),
+            text(sample.pixel.map(|(_, row)| row.to_string())),
+            text(sample.count.map(|count| count.to_string())),
+            text(sample.mean.map(|mean| mean.to_string())),
+            text(sample.sd.map(|sd| sd.to_string())),
+        )?;
+    }
+    out.flush()?;
+    return Ok(());
+}
@@ -0,0 +1,221 @@
+//! C API over [`HdfRasterConverter`].
+//!
+//! Functions return an [`H2rStatus`]; on failure the message is kept per
+//! thread and can be read with [`h2r_last_error`]. Strings are UTF-8 and
+//! NUL-terminated. The header is generated by cbindgen into
+//! `include/read_hdf_as_raster.h` when building with the `capi` feature.
+
+use crate::converter::{ConvertOptions, HdfRasterConverter};
+use crate::error::Error;
+use crate::hdf::H5NodeType;
+use crate::raster;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+/// Opaque converter handle.
+pub struct H2rConverter(HdfRasterConverter);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum H2rStatus {
+    Ok = 0,
+    /// A null pointer or a string that is not UTF-8 was passed.
+    InvalidArgument = 1,
+    Error = 2,
+    Interrupted = 3,
+}
+
+/// Called once per listed node with its path and whether it is a group.
+pub type H2rListCallback =
+    extern "C" fn(path: *const c_char, is_group: c_int, user_data: *mut c_void);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn status_of(result: crate::error::Result<()>) -> H2rStatus {
+    return match result {
+        Ok(()) => H2rStatus::Ok,
+        Err(Error::Interrupted) => {
+            set_last_error(Error::Interrupted.to_string());
+            H2rStatus::Interrupted
+        }
+        Err(err) => {
+            set_last_error(err.to_string());
+            H2rStatus::Error
+        }
+    };
+}
+
+unsafe fn str_arg<'a>(arg: *const c_char, name: &str) -> Option<&'a str> {
+    if arg.is_null() {
+        set_last_error(format!("{} is null", name));
+        return None;
+    }
+    return match CStr::from_ptr(arg).to_str() {
+        Ok(text) => Some(text),
+        Err(_) => {
+            set_last_error(format!("{} is not valid UTF-8", name));
+            None
+        }
+    };
+}
+
+/// Opens `h5_path` for update with the default options. Returns null on
+/// failure.
+///
+/// # Safety
+/// All arguments must be valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn h2r_open(
+    h5_path: *const c_char,
+    base_float_path: *const c_char,
+    base_byte_path: *const c_char,
+) -> *mut H2rConverter {
+    let (h5_path, base_float_path, base_byte_path) = match (
+        str_arg(h5_path, "h5_path"),
+        str_arg(base_float_path, "base_float_path"),
+        str_arg(base_byte_path, "base_byte_path"),
+    ) {
+        (Some(h5), Some(float), Some(byte)) => (h5, float, byte),
+        _ => return ptr::null_mut(),
+    };
+    let options = ConvertOptions::default();
+    return match HdfRasterConverter::open(h5_path, base_float_path, base_byte_path, options) {
+        Ok(converter) => Box::into_raw(Box::new(H2rConverter(converter))),
+        Err(err) => {
+            set_last_error(err.to_string());
+            ptr::null_mut()
+        }
+    };
+}
+
+/// Closes the file and frees `converter`. Null is ignored.
+///
+/// # Safety
+/// `converter` must come from [`h2r_open`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn h2r_close(converter: *mut H2rConverter) {
+    if !converter.is_null() {
+        drop(Box::from_raw(converter));
+    }
+}
+
+/// Sets the memory budget, in bytes, used to size read and write chunks.
+///
+/// # Safety
+/// `converter` must come from [`h2r_open`].
+#[no_mangle]
+pub unsafe extern "C" fn h2r_set_memory_limit(
+    converter: *mut H2rConverter,
+    memory_limit: usize,
+) -> H2rStatus {
+    if converter.is_null() || memory_limit == 0 {
+        set_last_error("converter is null or memory_limit is zero".to_owned());
+        return H2rStatus::InvalidArgument;
+    }
+    (*converter).0.options.memory_limit = memory_limit;
+    return H2rStatus::Ok;
+}
+
+/// Calls `callback` for every group and dataset in the file. The path is only
+/// valid during the call.
+///
+/// # Safety
+/// `converter` must come from [`h2r_open`].
+#[no_mangle]
+pub unsafe extern "C" fn h2r_list(
+    converter: *const H2rConverter,
+    callback: H2rListCallback,
+    user_data: *mut c_void,
+) -> H2rStatus {
+    if converter.is_null() {
+        set_last_error("converter is null".to_owned());
+        return H2rStatus::InvalidArgument;
+    }
+    for node in (*converter).0.list() {
+        let (path, is_group) = match node {
+            H5NodeType::Group(path) => (path, 1),
+            H5NodeType::Dataset(path) => (path, 0),
+            H5NodeType::Other(..) => continue,
+        };
+        let path = match CString::new(path) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        callback(path.as_ptr(), is_group, user_data);
+    }
+    return H2rStatus::Ok;
+}
+
+/// Writes dataset `ds_path`, rows reversed, to the raster at `out_path`.
+///
+/// # Safety
+/// `converter` must come from [`h2r_open`]; the strings must be valid
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn h2r_convert_dataset(
+    converter: *const H2rConverter,
+    ds_path: *const c_char,
+    out_path: *const c_char,
+) -> H2rStatus {
+    if converter.is_null() {
+        set_last_error("converter is null".to_owned());
+        return H2rStatus::InvalidArgument;
+    }
+    let (ds_path, out_path) = match (str_arg(ds_path, "ds_path"), str_arg(out_path, "out_path")) {
+        (Some(ds_path), Some(out_path)) => (ds_path, out_path),
+        _ => return H2rStatus::InvalidArgument,
+    };
+    return status_of((*converter).0.convert_dataset(ds_path, out_path));
+}
+
+/// Writes the count, mean and sd rasters of `group` to the working
+/// directory. Rasters left incomplete by a failure are removed.
+///
+/// # Safety
+/// `converter` must come from [`h2r_open`]; `group` must be a valid
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn h2r_convert_group(
+    converter: *const H2rConverter,
+    group: *const c_char,
+) -> H2rStatus {
+    if converter.is_null() {
+        set_last_error("converter is null".to_owned());
+        return H2rStatus::InvalidArgument;
+    }
+    let group = match str_arg(group, "group") {
+        Some(group) => group,
+        None => return H2rStatus::InvalidArgument,
+    };
+    let converter = &(*converter).0;
+    if let Err(err) = converter.check_stat_group(group) {
+        return status_of(Err(err));
+    }
+    let mut created = vec![];
+    let result = converter.convert_group(group, &mut created);
+    if result.is_err() {
+        for path in &created {
+            let _ = raster::remove_output(path);
+        }
+    }
+    return status_of(result);
+}
+
+/// Message of the last failure on this thread, or null. Valid until the next
+/// call into this API on the same thread.
+#[no_mangle]
+pub extern "C" fn h2r_last_error() -> *const c_char {
+    return LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    });
+}
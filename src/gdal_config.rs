@@ -0,0 +1,63 @@
+//! GDAL threading and block cache settings (`gdal` feature).
+//!
+//! Compressed outputs (DEFLATE, ZSTD, ...) spend most of their time
+//! compressing blocks, which GDAL spreads over `GDAL_NUM_THREADS` workers.
+//! Each worker compresses blocks held in the block cache, whose size is
+//! `GDAL_CACHEMAX`, so both are tuned from the number of jobs.
+
+use crate::error::Result;
+
+/// Block cache given to each job when `GDAL_CACHEMAX` is not set.
+pub const CACHE_PER_JOB: usize = 64 << 20;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GdalConfig {
+    /// Value of `GDAL_NUM_THREADS`, a thread count or `ALL_CPUS`; the number
+    /// of jobs when unset.
+    pub num_threads: Option<String>,
+    /// `GDAL_CACHEMAX` in bytes; [`default_cache_max`] when unset.
+    pub cache_max: Option<usize>,
+}
+
+/// Block cache for `jobs` workers: [`CACHE_PER_JOB`] each, within
+/// `memory_limit`.
+pub fn default_cache_max(jobs: usize, memory_limit: usize) -> usize {
+    return (CACHE_PER_JOB * jobs.max(1)).min(memory_limit);
+}
+
+impl GdalConfig {
+    /// Resolved (`GDAL_NUM_THREADS`, `GDAL_CACHEMAX` in bytes) for `jobs`.
+    pub fn resolve(&self, jobs: usize, memory_limit: usize) -> (String, usize) {
+        let num_threads = self
+            .num_threads
+            .clone()
+            .unwrap_or_else(|| jobs.max(1).to_string());
+        let cache_max = self
+            .cache_max
+            .unwrap_or_else(|| default_cache_max(jobs, memory_limit));
+        return (num_threads, cache_max);
+    }
+
+    /// Sets the GDAL configuration options for the rest of the process.
+    pub fn apply(&self, jobs: usize, memory_limit: usize) -> Result<()> {
+        let (num_threads, cache_max) = self.resolve(jobs, memory_limit);
+        gdal::config::set_config_option("GDAL_NUM_THREADS", &num_threads)?;
+        // Values above 100000 are read as bytes rather than megabytes.
+        gdal::config::set_config_option("GDAL_CACHEMAX", &cache_max.max(100_001).to_string())?;
+        return Ok(());
+    }
+}
+
+/// Parses a `GDAL_NUM_THREADS` value: a positive count or `ALL_CPUS`.
+pub fn parse_num_threads(text: &str) -> std::result::Result<String, String> {
+    if text.eq_ignore_ascii_case("ALL_CPUS") {
+        return Ok("ALL_CPUS".to_owned());
+    }
+    return match text.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count.to_string()),
+        _ => Err(format!(
+            "invalid GDAL thread count '{}' (expected a positive number or ALL_CPUS)",
+            text
+        )),
+    };
+}
@@ -0,0 +1,300 @@
+//! Outputs of grids georeferenced by 2-d latitude and longitude arrays
+//! rather than a geotransform, such as swath and curvilinear products
+//! (`--geolocation`).
+//!
+//! The arrays are the 2-d datasets named by `--geolocation-arrays LAT,LON`,
+//! or else named `lat` or `latitude` and `lon` or `longitude`, of the shape
+//! of the outputs, in the group of the outputs or else in the root group.
+//! With `attach`, they are written in the row order of the outputs next to
+//! each output raster, as `<output>_lat.tif` and `<output>_lon.tif`, and
+//! named in its `GEOLOCATION` metadata, so `gdalwarp -geoloc` can project
+//! it. With `warp`, each output is also warped through them to
+//! `<output>_warped.tif` in a CRS, WGS84 unless given.
+
+use crate::error::{Error, Result};
+use crate::{hdf, meta};
+use ndarray::{s, Array2};
+use std::path::Path;
+
+/// Names of the latitude and longitude arrays looked for, lowercase.
+const LAT_NAMES: [&str; 2] = ["lat", "latitude"];
+const LON_NAMES: [&str; 2] = ["lon", "longitude"];
+
+/// CRS of the latitude and longitude arrays.
+pub const SRS: &str = "EPSG:4326";
+
+/// What is done with the outputs of a grid with geolocation arrays.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeolocationMode {
+    /// Attach the arrays to each output.
+    Attach,
+    /// Attach them and also warp each output to the CRS.
+    Warp(String),
+}
+
+impl GeolocationMode {
+    /// Parses `attach`, `warp` (to WGS84) or `warp=CRS`, e.g.
+    /// `warp=EPSG:32723`.
+    pub fn parse(text: &str) -> std::result::Result<GeolocationMode, String> {
+        let text = text.trim();
+        return match text.split_once('=') {
+            None if text == "attach" => Ok(GeolocationMode::Attach),
+            None if text == "warp" => Ok(GeolocationMode::Warp(SRS.to_owned())),
+            Some(("warp", crs)) if !crs.trim().is_empty() => {
+                Ok(GeolocationMode::Warp(crs.trim().to_owned()))
+            }
+            _ => Err(format!(
+                "invalid geolocation mode '{}', expected attach, warp or warp=CRS",
+                text
+            )),
+        };
+    }
+}
+
+/// Paths of the latitude and longitude arrays of a grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayPaths {
+    pub lat: String,
+    pub lon: String,
+}
+
+impl ArrayPaths {
+    /// Parses `LAT,LON`, paths or names relative to the group of the
+    /// outputs.
+    pub fn parse(text: &str) -> std::result::Result<ArrayPaths, String> {
+        return match text.split_once(',') {
+            Some((lat, lon)) if !lat.trim().is_empty() && !lon.trim().is_empty() => {
+                Ok(ArrayPaths {
+                    lat: lat.trim().to_owned(),
+                    lon: lon.trim().to_owned(),
+                })
+            }
+            _ => Err(format!(
+                "invalid geolocation arrays '{}', expected LAT,LON",
+                text
+            )),
+        };
+    }
+}
+
+/// Whether the dataset at `path` is `ysize` rows by `xsize` columns.
+fn has_shape(file: &hdf5::File, path: &str, (xsize, ysize): (usize, usize)) -> bool {
+    return meta::get(file, path).map_or(false, |meta| meta.shape == vec![ysize, xsize]);
+}
+
+/// Path of the array named `name` in `group`, or else at the root.
+fn resolve(file: &hdf5::File, group: &str, name: &str) -> Option<String> {
+    let in_group = hdf::join_path(group, name.trim_start_matches('/'));
+    if !name.starts_with('/') && file.link_exists(&in_group) {
+        return Some(in_group);
+    }
+    let absolute = format!("/{}", name.trim_start_matches('/'));
+    return match file.link_exists(&absolute) {
+        true => Some(absolute),
+        false => None,
+    };
+}
+
+/// Path of a dataset of `group` named like one of `names`, of `size`.
+fn named(file: &hdf5::File, group: &str, names: &[&str], size: (usize, usize)) -> Option<String> {
+    let _silence = hdf5::silence_errors();
+    let members = file
+        .group(group)
+        .and_then(|group| group.member_names())
+        .ok()?;
+    return members
+        .iter()
+        .filter(|member| names.contains(&member.to_lowercase().as_str()))
+        .map(|member| hdf::join_path(group, member))
+        .find(|path| has_shape(file, path, size));
+}
+
+/// Latitude and longitude arrays of the outputs of `group`, `xsize` by
+/// `ysize` pixels: those of `arrays` if given, which must exist and have
+/// the shape, or else those found by name in the group or the root.
+pub fn find(
+    file: &hdf5::File,
+    group: &str,
+    arrays: Option<&ArrayPaths>,
+    size: (usize, usize),
+) -> Result<Option<ArrayPaths>> {
+    if let Some(arrays) = arrays {
+        let mut found = vec![];
+        for name in [&arrays.lat, &arrays.lon].iter() {
+            let path = resolve(file, group, name).ok_or_else(|| {
+                return Error::Invalid(format!("no geolocation array {} in {}", name, group));
+            })?;
+            if !has_shape(file, &path, size) {
+                return Err(Error::Invalid(format!(
+                    "geolocation array {} is not {} rows by {} columns like the outputs of {}",
+                    path, size.1, size.0, group
+                )));
+            }
+            found.push(path);
+        }
+        return Ok(Some(ArrayPaths {
+            lon: found.pop().unwrap(),
+            lat: found.pop().unwrap(),
+        }));
+    }
+    for group in [group, "/"].iter() {
+        let lat = named(file, group, &LAT_NAMES, size);
+        let lon = named(file, group, &LON_NAMES, size);
+        if let (Some(lat), Some(lon)) = (lat, lon) {
+            return Ok(Some(ArrayPaths { lat, lon }));
+        }
+    }
+    return Ok(None);
+}
+
+/// Group of the outputs written from `source`: itself if a group, else the
+/// group of the dataset.
+pub fn source_group(file: &hdf5::File, source: &str) -> String {
+    let is_group = {
+        let _silence = hdf5::silence_errors();
+        file.group(source).is_ok()
+    };
+    if is_group {
+        return source.to_owned();
+    }
+    return match source.trim_end_matches('/').rsplit_once('/') {
+        Some(("", _)) | None => "/".to_owned(),
+        Some((parent, _)) => parent.to_owned(),
+    };
+}
+
+/// Path of the array of `axis` (`lat` or `lon`) written next to `out_path`.
+pub fn array_path(out_path: &str, axis: &str) -> String {
+    return sibling(out_path, axis);
+}
+
+/// Path of the warped copy of `out_path`.
+pub fn warped_path(out_path: &str) -> String {
+    return sibling(out_path, "warped");
+}
+
+fn sibling(out_path: &str, suffix: &str) -> String {
+    let path = Path::new(out_path);
+    let stem = path.file_stem().map_or(String::new(), |stem| {
+        return stem.to_string_lossy().into_owned();
+    });
+    return path
+        .with_file_name(format!("{}_{}.tif", stem, suffix))
+        .to_string_lossy()
+        .into_owned();
+}
+
+/// Whether `path` is an array or warped copy written next to an output.
+pub fn is_derived(path: &str) -> bool {
+    return ["_lat.tif", "_lon.tif", "_warped.tif"]
+        .iter()
+        .any(|suffix| path.ends_with(suffix));
+}
+
+/// The array at `path`, its rows reversed with `flip` like the outputs.
+pub fn read_array(file: &hdf5::File, path: &str, flip: bool) -> Result<Array2<f64>> {
+    let vals = file.dataset(path)?.read_2d::<f64>()?;
+    return match flip {
+        true => Ok(vals.slice(s![..;-1, ..]).to_owned()),
+        false => Ok(vals),
+    };
+}
+
+/// Writes `vals` to a single-band `Float64` GeoTIFF at `out_path`.
+#[cfg(feature = "gdal")]
+pub fn write_array(vals: &Array2<f64>, out_path: &str) -> Result<()> {
+    let (ysize, xsize) = vals.dim();
+    let driver = gdal::Driver::get("GTiff")?;
+    let rast =
+        driver.create_with_band_type::<f64, _>(out_path, xsize as isize, ysize as isize, 1)?;
+    let buffer = gdal::raster::Buffer::new((xsize, ysize), vals.iter().copied().collect());
+    rast.rasterband(1)?.write((0, 0), (xsize, ysize), &buffer)?;
+    return Ok(());
+}
+
+/// Names the arrays at `lat_path` and `lon_path` in the `GEOLOCATION`
+/// metadata of the raster at `out_path`, pixel for pixel.
+#[cfg(feature = "gdal")]
+pub fn attach(out_path: &str, lat_path: &str, lon_path: &str) -> Result<()> {
+    use gdal::Metadata;
+
+    let mut rast = gdal::Dataset::open_ex(
+        Path::new(out_path),
+        Some(gdal_sys::GDALAccess::GA_Update),
+        None,
+        None,
+        None,
+    )?;
+    let items = [
+        ("SRS", SRS),
+        ("X_DATASET", lon_path),
+        ("X_BAND", "1"),
+        ("Y_DATASET", lat_path),
+        ("Y_BAND", "1"),
+        ("PIXEL_OFFSET", "0"),
+        ("LINE_OFFSET", "0"),
+        ("PIXEL_STEP", "1"),
+        ("LINE_STEP", "1"),
+        ("GEOREFERENCING_CONVENTION", "PIXEL_CENTER"),
+    ];
+    for (name, value) in items.iter() {
+        rast.set_metadata_item(name, value, "GEOLOCATION")?;
+    }
+    return Ok(());
+}
+
+/// Warps the raster at `out_path`, with its geolocation attached, through
+/// its arrays to a GeoTIFF in `crs` at `warped_path`, keeping its nodata.
+#[cfg(feature = "gdal")]
+pub fn warp(out_path: &str, crs: &str, warped_path: &str) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::raw::c_char;
+
+    let rast = gdal::Dataset::open(Path::new(out_path))?;
+    let args: Vec<CString> = [
+        "-geoloc",
+        "-t_srs",
+        crs,
+        "-of",
+        "GTiff",
+        "-co",
+        "COMPRESS=DEFLATE",
+        "-co",
+        "TILED=YES",
+    ]
+    .iter()
+    .map(|arg| CString::new(*arg).unwrap())
+    .collect();
+    let mut argv: Vec<*mut c_char> = args.iter().map(|arg| arg.as_ptr() as *mut c_char).collect();
+    argv.push(std::ptr::null_mut());
+    let dest = CString::new(warped_path)
+        .map_err(|_| Error::Invalid(format!("invalid path {}", warped_path)))?;
+    // SAFETY: `argv` is NUL-terminated and outlives the options, and the
+    // dataset handle is valid while `rast` is borrowed.
+    let written = unsafe {
+        let options = gdal_sys::GDALWarpAppOptionsNew(argv.as_mut_ptr(), std::ptr::null_mut());
+        let mut sources = [rast.c_dataset()];
+        let mut usage_error = 0;
+        let warped = gdal_sys::GDALWarp(
+            dest.as_ptr(),
+            std::ptr::null_mut(),
+            1,
+            sources.as_mut_ptr(),
+            options,
+            &mut usage_error,
+        );
+        gdal_sys::GDALWarpAppOptionsFree(options);
+        let written = !warped.is_null();
+        if written {
+            gdal_sys::GDALClose(warped);
+        }
+        written
+    };
+    if !written {
+        return Err(Error::Invalid(format!(
+            "could not warp {} to {}",
+            out_path, crs
+        )));
+    }
+    return Ok(());
+}
@@ -0,0 +1,274 @@
+//! Pure-Rust GeoTIFF output (`pure-tiff` feature), usable without linking
+//! GDAL.
+//!
+//! Outputs are single-band strip TIFFs, written as BigTIFF when they may
+//! outgrow 4 GB. The georeferencing tags of the template (model tie points,
+//! pixel scale or transformation, GeoKeys) and its GDAL nodata value are
+//! copied to every output.
+
+use crate::chunking;
+use crate::error::Result;
+use crate::provenance::escape_xml;
+use crate::raster::{GeoRef, RasterSink};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use tiff::decoder::Decoder;
+use tiff::encoder::{colortype, TiffEncoder, TiffKind, TiffValue};
+use tiff::tags::Tag;
+
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const MODEL_TRANSFORMATION: u16 = 34264;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+const GEO_DOUBLE_PARAMS: u16 = 34736;
+const GEO_ASCII_PARAMS: u16 = 34737;
+const GDAL_METADATA: u16 = 42112;
+const GDAL_NODATA: u16 = 42113;
+
+const GEOGRAPHIC_TYPE_KEY: u16 = 2048;
+const PROJECTED_CS_TYPE_KEY: u16 = 3072;
+
+#[derive(Debug, Clone)]
+enum GeoTagValue {
+    Doubles(Vec<f64>),
+    Shorts(Vec<u16>),
+    Ascii(String),
+}
+
+/// Size and georeferencing of a template GeoTIFF.
+#[derive(Debug, Clone)]
+pub struct GeoTiffTemplate {
+    pub xsize: usize,
+    pub ysize: usize,
+    tags: Vec<(u16, GeoTagValue)>,
+}
+
+impl GeoTiffTemplate {
+    pub fn read(path: &str) -> Result<GeoTiffTemplate> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+        let (width, height) = decoder.dimensions()?;
+        let mut tags = vec![];
+        for &code in &[
+            MODEL_PIXEL_SCALE,
+            MODEL_TIEPOINT,
+            MODEL_TRANSFORMATION,
+            GEO_DOUBLE_PARAMS,
+        ] {
+            if let Some(value) = decoder.find_tag(Tag::Unknown(code))? {
+                tags.push((code, GeoTagValue::Doubles(value.into_f64_vec()?)));
+            }
+        }
+        if let Some(value) = decoder.find_tag(Tag::Unknown(GEO_KEY_DIRECTORY))? {
+            tags.push((
+                GEO_KEY_DIRECTORY,
+                GeoTagValue::Shorts(value.into_u16_vec()?),
+            ));
+        }
+        for &code in &[GEO_ASCII_PARAMS, GDAL_NODATA] {
+            if let Some(value) = decoder.find_tag(Tag::Unknown(code))? {
+                tags.push((code, GeoTagValue::Ascii(value.into_string()?)));
+            }
+        }
+        return Ok(GeoTiffTemplate {
+            xsize: width as usize,
+            ysize: height as usize,
+            tags,
+        });
+    }
+
+    fn doubles(&self, code: u16) -> Option<&[f64]> {
+        return self.tags.iter().find_map(|(tag, value)| match value {
+            GeoTagValue::Doubles(vals) if *tag == code => Some(&vals[..]),
+            _ => None,
+        });
+    }
+
+    /// Geotransform from the model transformation or the first tie point and
+    /// pixel scale, and the EPSG code of the CRS from the GeoKeys.
+    pub fn georef(&self) -> GeoRef {
+        let geo_transform = match (
+            self.doubles(MODEL_TRANSFORMATION),
+            self.doubles(MODEL_TIEPOINT),
+            self.doubles(MODEL_PIXEL_SCALE),
+        ) {
+            (Some(t), _, _) if t.len() >= 8 => [t[3], t[0], t[1], t[7], t[4], t[5]],
+            (_, Some(tie), Some(scale)) if tie.len() >= 5 && scale.len() >= 2 => [
+                tie[3] - tie[0] * scale[0],
+                scale[0],
+                0f64,
+                tie[4] + tie[1] * scale[1],
+                0f64,
+                -scale[1],
+            ],
+            _ => [0f64, 1f64, 0f64, 0f64, 0f64, 1f64],
+        };
+        let mut crs = String::new();
+        for (tag, value) in &self.tags {
+            if let (GEO_KEY_DIRECTORY, GeoTagValue::Shorts(keys)) = (*tag, value) {
+                // Header of 4 shorts, then (key, location, count, value)
+                // entries; location 0 means the value is inline.
+                for entry in keys.chunks_exact(4).skip(1) {
+                    let is_crs_key =
+                        entry[0] == PROJECTED_CS_TYPE_KEY || entry[0] == GEOGRAPHIC_TYPE_KEY;
+                    if is_crs_key
+                        && entry[1] == 0
+                        && (crs.is_empty() || entry[0] == PROJECTED_CS_TYPE_KEY)
+                    {
+                        crs = format!("EPSG:{}", entry[3]);
+                    }
+                }
+            }
+        }
+        return GeoRef { geo_transform, crs };
+    }
+}
+
+/// Sample types with a matching single-band TIFF color type.
+pub trait TiffSample: Copy + Sized {
+    /// Writes a `xsize` x `ysize` GeoTIFF at `out_path` with the tags of
+    /// `template`, filled top to bottom with strips of up to `rows_per_strip`
+    /// rows returned by `next_strip(yy, lines)`.
+    fn write_geotiff(
+        template: &GeoTiffTemplate,
+        out_path: &str,
+        xsize: usize,
+        ysize: usize,
+        rows_per_strip: usize,
+        next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<Self>>,
+    ) -> Result<()>;
+}
+
+/// Writes the image of [`TiffSample::write_geotiff`] with `tiff`, a classic
+/// TIFF or BigTIFF encoder.
+fn write_image<C, K>(
+    mut tiff: TiffEncoder<BufWriter<File>, K>,
+    template: &GeoTiffTemplate,
+    xsize: usize,
+    ysize: usize,
+    rows_per_strip: usize,
+    next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<C::Inner>>,
+) -> Result<()>
+where
+    C: colortype::ColorType,
+    K: TiffKind,
+    [C::Inner]: TiffValue,
+{
+    let mut image = tiff.new_image::<C>(xsize as u32, ysize as u32)?;
+    image.rows_per_strip(rows_per_strip as u32)?;
+    for (code, value) in &template.tags {
+        let tag = Tag::Unknown(*code);
+        match value {
+            GeoTagValue::Doubles(vals) => image.encoder().write_tag(tag, &vals[..])?,
+            GeoTagValue::Shorts(vals) => image.encoder().write_tag(tag, &vals[..])?,
+            GeoTagValue::Ascii(text) => image.encoder().write_tag(tag, &text[..])?,
+        }
+    }
+    for yy in (0..ysize).step_by(rows_per_strip) {
+        let lines = rows_per_strip.min(ysize - yy);
+        let vals = next_strip(yy, lines)?;
+        image.write_strip(&vals)?;
+    }
+    image.finish()?;
+    return Ok(());
+}
+
+macro_rules! impl_tiff_sample {
+    ($sample:ty, $color:ty) => {
+        impl TiffSample for $sample {
+            /// Outputs that may outgrow the 4 GB of a classic TIFF are
+            /// written as BigTIFF.
+            fn write_geotiff(
+                template: &GeoTiffTemplate,
+                out_path: &str,
+                xsize: usize,
+                ysize: usize,
+                rows_per_strip: usize,
+                next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<Self>>,
+            ) -> Result<()> {
+                let file = BufWriter::new(File::create(out_path)?);
+                let dtype_size = std::mem::size_of::<$sample>();
+                if chunking::needs_bigtiff(xsize, ysize, dtype_size, 1) {
+                    let tiff = TiffEncoder::new_big(file)?;
+                    return write_image::<$color, _>(
+                        tiff,
+                        template,
+                        xsize,
+                        ysize,
+                        rows_per_strip,
+                        next_strip,
+                    );
+                }
+                let tiff = TiffEncoder::new(file)?;
+                return write_image::<$color, _>(
+                    tiff,
+                    template,
+                    xsize,
+                    ysize,
+                    rows_per_strip,
+                    next_strip,
+                );
+            }
+        }
+    };
+}
+
+impl_tiff_sample!(u8, colortype::Gray8);
+impl_tiff_sample!(i16, colortype::GrayI16);
+impl_tiff_sample!(u16, colortype::Gray16);
+impl_tiff_sample!(i32, colortype::GrayI32);
+impl_tiff_sample!(u32, colortype::Gray32);
+impl_tiff_sample!(f32, colortype::Gray32Float);
+impl_tiff_sample!(f64, colortype::Gray64Float);
+
+/// Writes a GeoTIFF carrying the georeferencing of a template.
+pub struct TiffSink {
+    template: GeoTiffTemplate,
+    out_path: String,
+}
+
+impl TiffSink {
+    pub fn new(template_path: &str, out_path: &str) -> Result<TiffSink> {
+        return Ok(TiffSink {
+            template: GeoTiffTemplate::read(template_path)?,
+            out_path: out_path.to_owned(),
+        });
+    }
+}
+
+impl<T: TiffSample> RasterSink<T> for TiffSink {
+    fn write_strips(
+        &mut self,
+        xsize: usize,
+        ysize: usize,
+        n_lines: usize,
+        next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<T>>,
+    ) -> Result<()> {
+        return T::write_geotiff(
+            &self.template,
+            &self.out_path,
+            xsize,
+            ysize,
+            n_lines,
+            next_strip,
+        );
+    }
+
+    /// Written as the GDAL metadata tag, which GDAL reads back as the
+    /// dataset's metadata.
+    fn set_metadata(&mut self, domain: &str, items: &[(String, String)]) -> Result<()> {
+        let mut xml = "<GDALMetadata>".to_owned();
+        for (name, value) in items {
+            xml.push_str(&format!(
+                "<Item name=\"{}\" domain=\"{}\">{}</Item>",
+                escape_xml(name),
+                escape_xml(domain),
+                escape_xml(value)
+            ));
+        }
+        xml.push_str("</GDALMetadata>");
+        let tags = &mut self.template.tags;
+        tags.retain(|(code, _)| *code != GDAL_METADATA);
+        tags.push((GDAL_METADATA, GeoTagValue::Ascii(xml)));
+        return Ok(());
+    }
+}
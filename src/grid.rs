@@ -0,0 +1,190 @@
+//! Grids of groups at other resolutions than the templates.
+//!
+//! Some products hold grids at several resolutions, one per group. A group
+//! gets its own grid from a template raster given for it (`--group-grid
+//! GROUP=TEMPLATE`), or else from `shape` (rows, columns) and `GeoTransform`
+//! attributes of the group, with an optional `crs_wkt`, or else from its
+//! dimension scales (see [`crate::dim_scales`]); other groups use the
+//! templates of the run. Outputs of a grid from attributes are copied
+//! from a template written for it, north-up like the other templates.
+
+use crate::error::{Error, Result};
+use crate::row_order::FlipOption;
+use crate::strings;
+
+/// Template raster giving the grid of a group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupGridRule {
+    /// Group path, or its last component.
+    pub group: String,
+    pub template: String,
+}
+
+impl GroupGridRule {
+    /// Parses `GROUP=TEMPLATE`, e.g. `agbd_1km=base_1km.tif`.
+    pub fn parse(text: &str) -> std::result::Result<GroupGridRule, String> {
+        return match text.split_once('=') {
+            Some((group, template)) if !group.trim().is_empty() && !template.trim().is_empty() => {
+                Ok(GroupGridRule {
+                    group: group.trim().trim_matches('/').to_owned(),
+                    template: template.trim().to_owned(),
+                })
+            }
+            _ => Err(format!(
+                "invalid group grid '{}', expected GROUP=TEMPLATE",
+                text
+            )),
+        };
+    }
+}
+
+/// Template of the group at `path`: the rule naming its whole path, or else
+/// its last component.
+pub fn template_for<'a>(rules: &'a [GroupGridRule], path: &str) -> Option<&'a str> {
+    let path = path.trim_matches('/');
+    let name = path.rsplit('/').next().unwrap_or(path);
+    return rules
+        .iter()
+        .find(|rule| rule.group == path)
+        .or_else(|| rules.iter().find(|rule| rule.group == name))
+        .map(|rule| rule.template.as_str());
+}
+
+/// Grid described by the attributes of a group, for its rows as stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    pub xsize: usize,
+    pub ysize: usize,
+    pub geo_transform: [f64; 6],
+    /// CRS as WKT, if the group gives one.
+    pub crs: Option<String>,
+}
+
+impl Grid {
+    /// Grid of `xsize` by `ysize` pixels in pixel coordinates, north-up and
+    /// without a CRS, for datasets without georeferencing.
+    pub fn pixels(xsize: usize, ysize: usize) -> Grid {
+        return Grid {
+            xsize,
+            ysize,
+            geo_transform: [0f64, 1f64, 0f64, ysize as f64, 0f64, -1f64],
+            crs: None,
+        };
+    }
+
+    /// Whether the first row stored is the northernmost.
+    pub fn top_down(&self) -> bool {
+        return self.geo_transform[5] < 0f64;
+    }
+
+    /// The grid with its rows north-up, as the outputs run.
+    pub fn north_up(&self) -> Grid {
+        if self.top_down() {
+            return self.clone();
+        }
+        let mut gt = self.geo_transform;
+        gt[0] += self.ysize as f64 * gt[2];
+        gt[3] += self.ysize as f64 * gt[5];
+        gt[2] = -gt[2];
+        gt[5] = -gt[5];
+        return Grid {
+            geo_transform: gt,
+            ..self.clone()
+        };
+    }
+
+    /// Whether the rows of the group are flipped into the outputs, according
+    /// to `option`: with `auto`, when they are stored bottom-up.
+    pub fn needs_flip(&self, option: FlipOption) -> bool {
+        return match option {
+            FlipOption::Always => true,
+            FlipOption::Never => false,
+            FlipOption::Auto => !self.top_down(),
+        };
+    }
+}
+
+/// Whether the geotransforms `a` and `b` match to within a hundredth of a
+/// pixel of `a`, as grids fitted to coordinates do with the grid of a
+/// template.
+pub fn same_transform(a: &[f64; 6], b: &[f64; 6]) -> bool {
+    let tolerance = 0.01 * a[1].abs().min(a[5].abs());
+    return a
+        .iter()
+        .zip(b.iter())
+        .all(|(a, b)| (a - b).abs() <= tolerance);
+}
+
+/// Grid of `group` from its `shape` and `GeoTransform` attributes, or
+/// `None` if it has neither.
+pub fn read_group_grid(file: &hdf5::File, group: &str) -> Result<Option<Grid>> {
+    let _silence = hdf5::silence_errors();
+    let group_obj = file.group(group)?;
+    let shape = group_obj.attr("shape").ok();
+    let geo_transform = group_obj.attr("GeoTransform").ok();
+    let (shape, geo_transform) = match (shape, geo_transform) {
+        (None, None) => return Ok(None),
+        (Some(shape), Some(geo_transform)) => {
+            (shape.read_raw::<u64>()?, geo_transform.read_raw::<f64>()?)
+        }
+        _ => {
+            return Err(Error::Invalid(format!(
+                "group {} needs both shape and GeoTransform attributes for its own grid",
+                group
+            )))
+        }
+    };
+    if shape.len() != 2 || shape.contains(&0) {
+        return Err(Error::Invalid(format!(
+            "shape attribute of {} is not (rows, columns): {:?}",
+            group, shape
+        )));
+    }
+    if geo_transform.len() != 6 || geo_transform[1] == 0f64 || geo_transform[5] == 0f64 {
+        return Err(Error::Invalid(format!(
+            "GeoTransform attribute of {} is not a geotransform: {:?}",
+            group, geo_transform
+        )));
+    }
+    let crs = match group_obj.attr("crs_wkt") {
+        Ok(attr) => strings::read_strings(&attr).and_then(|crs| crs.into_iter().next()),
+        Err(_) => None,
+    };
+    let mut gt = [0f64; 6];
+    gt.copy_from_slice(&geo_transform);
+    return Ok(Some(Grid {
+        xsize: shape[1] as usize,
+        ysize: shape[0] as usize,
+        geo_transform: gt,
+        crs,
+    }));
+}
+
+/// Writes a single-band `Float32` GeoTIFF template of `grid` to `out_path`,
+/// with the CRS and nodata value of the template at `like`, if any, unless
+/// the grid has its own CRS.
+#[cfg(feature = "gdal")]
+pub fn write_template(grid: &Grid, like: Option<&str>, out_path: &str) -> Result<()> {
+    let like = match like {
+        Some(like) => Some(gdal::Dataset::open(std::path::Path::new(like))?),
+        None => None,
+    };
+    let driver = gdal::Driver::get("GTiff")?;
+    let mut rast = driver.create_with_band_type::<f32, _>(
+        out_path,
+        grid.xsize as isize,
+        grid.ysize as isize,
+        1,
+    )?;
+    rast.set_geo_transform(&grid.geo_transform)?;
+    let like_crs = like.as_ref().map(|like| like.projection());
+    if let Some(crs) = grid.crs.as_ref().or_else(|| like_crs.as_ref()) {
+        rast.set_projection(crs)?;
+    }
+    if let Some(like) = like {
+        if let Some(nodata) = like.rasterband(1)?.no_data_value() {
+            rast.rasterband(1)?.set_no_data_value(Some(nodata))?;
+        }
+    }
+    return Ok(());
+}
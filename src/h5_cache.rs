@@ -0,0 +1,89 @@
+//! Raw data chunk cache of the HDF5 file converted.
+//!
+//! HDF5 keeps decompressed chunks of each open dataset in a cache of 1 MB
+//! and 521 slots by default. Strips of rows read from compressed datasets
+//! whose chunks are larger than that, or span more chunks than fit, are
+//! decompressed again on every read; a larger cache keeps them.
+
+use crate::chunking;
+use crate::error::Result;
+use hdf5::file::OpenMode;
+
+/// Chunk cache of every dataset opened from the file, as set by
+/// `H5Pset_cache`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkCache {
+    /// Size of the cache of each dataset.
+    pub nbytes: usize,
+    /// Hash table slots; best a prime about 100 times the number of chunks
+    /// that fit in the cache.
+    pub nslots: usize,
+    /// Preemption policy from 0 to 1: how soon chunks that were fully read
+    /// are evicted before others (1 for data read once).
+    pub w0: f64,
+}
+
+impl Default for ChunkCache {
+    fn default() -> Self {
+        return ChunkCache {
+            nbytes: 1 << 20,
+            nslots: 521,
+            w0: 0.75,
+        };
+    }
+}
+
+impl ChunkCache {
+    /// Parses `SIZE[,NSLOTS[,W0]]`, e.g. `64M` or `256M,12421,1`. Without
+    /// slots, a prime of about 100 slots per MB is picked; W0 defaults to
+    /// HDF5's 0.75.
+    pub fn parse(text: &str) -> std::result::Result<ChunkCache, String> {
+        let mut parts = text.split(',').map(|part| part.trim());
+        let nbytes = chunking::parse_memory_size(parts.next().unwrap_or(""))?;
+        let nslots = match parts.next() {
+            None => default_slots(nbytes),
+            Some(nslots) => match nslots.parse::<usize>() {
+                Ok(nslots) if nslots > 0 => nslots,
+                _ => return Err(format!("invalid chunk cache slots '{}'", nslots)),
+            },
+        };
+        let w0 = match parts.next() {
+            None => ChunkCache::default().w0,
+            Some(w0) => match w0.parse::<f64>() {
+                Ok(w0) if (0f64..=1f64).contains(&w0) => w0,
+                _ => return Err(format!("chunk cache w0 must be 0-1, got '{}'", w0)),
+            },
+        };
+        if parts.next().is_some() {
+            return Err(format!(
+                "invalid chunk cache '{}', expected SIZE[,NSLOTS[,W0]]",
+                text
+            ));
+        }
+        return Ok(ChunkCache { nbytes, nslots, w0 });
+    }
+}
+
+/// Smallest prime of at least 100 slots per MB of cache, and at least
+/// HDF5's default of 521.
+fn default_slots(nbytes: usize) -> usize {
+    let is_prime = |n: usize| n > 1 && (2..).take_while(|d| d * d <= n).all(|d| n % d != 0);
+    let mut nslots = (nbytes / (10 << 10)).max(521);
+    while !is_prime(nslots) {
+        nslots += 1;
+    }
+    return nslots;
+}
+
+/// Opens the HDF5 file at `path` in `mode`, with `cache` as the chunk cache
+/// of its datasets, or HDF5's default without one.
+pub fn open_as(path: &str, mode: OpenMode, cache: Option<ChunkCache>) -> Result<hdf5::File> {
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return Ok(hdf5::File::open_as(path, mode)?),
+    };
+    let file = hdf5::File::with_options()
+        .with_fapl(|fapl| fapl.chunk_cache(cache.nslots, cache.nbytes, cache.w0))
+        .open_as(path, mode)?;
+    return Ok(file);
+}
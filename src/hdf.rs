@@ -0,0 +1,550 @@
+use crate::compression::{self, H5Compression};
+use crate::error::Result as CrateResult;
+use crate::timing::{self, Stage};
+use crate::{allocation, history, meta, retry, status};
+use hdf5::types::TypeDescriptor;
+use hdf5_sys::h5::hid_t;
+use hdf5_sys::h5l::{H5L_info_t, H5L_type_t, H5Lget_info};
+use hdf5_sys::h5o::{H5O_info_t, H5O_type_t, H5Oget_info, H5Oget_info_by_name};
+use hdf5_sys::h5p::H5P_DEFAULT;
+use ndarray::{s, Array1, ArrayView2, SliceInfo};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::ops::Range;
+use std::time::Instant;
+
+/// Flat, row-major datasets addressed by path and read in element ranges.
+pub trait HdfSource<T> {
+    /// Number of elements of the dataset at `path`.
+    fn len(&self, path: &str) -> CrateResult<usize>;
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> CrateResult<Vec<T>>;
+}
+
+impl<T: hdf5::H5Type> HdfSource<T> for hdf5::File {
+    fn len(&self, path: &str) -> CrateResult<usize> {
+        return Ok(meta::get(self, path)?.size());
+    }
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> CrateResult<Vec<T>> {
+        let started = Instant::now();
+        let vals = retry::with_retries(&format!("reading {}", path), || {
+            return Ok(read_flat::<T>(&self.dataset(path)?, start, end)?);
+        })
+        .map_err(|err| compression::checksum_error(path, err))?;
+        let bytes = vals.len() * std::mem::size_of::<T>();
+        timing::add(Stage::Read, started.elapsed(), bytes);
+        status::add_bytes(bytes);
+        return Ok(vals.to_vec());
+    }
+}
+
+pub trait HasMembers {
+    fn get_members(&self) -> Result<Vec<String>, hdf5::Error>;
+    fn get_group(&self, name: &str) -> Result<hdf5::Group, hdf5::Error>;
+    fn is_group(&self, name: &str) -> bool;
+    fn link_kind(&self, name: &str) -> LinkKind;
+    fn object_kind(&self, name: &str) -> ObjectKind;
+    fn address(&self) -> Option<ObjectAddress>;
+    fn get_dataset(&self, name: &str) -> Result<hdf5::Dataset, hdf5::Error>;
+    fn file_name(&self) -> String;
+}
+
+/// Type of the object a member link resolves to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectKind {
+    Group,
+    Dataset,
+    NamedDatatype,
+    /// The link does not resolve, e.g. a dangling soft link or an external
+    /// link whose file is missing.
+    Unresolved,
+    Unknown,
+}
+
+/// How a member is linked into its parent group.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkKind {
+    Hard,
+    Soft,
+    External,
+    Unknown,
+}
+
+/// File number and object header address, which identify an object no
+/// matter how many links point to it.
+pub type ObjectAddress = (u64, u64);
+
+/// Broad class of a dataset's element type, deciding whether it can be
+/// converted to a raster.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueClass {
+    Numeric,
+    Compound,
+    /// Fixed or variable-length strings.
+    Text,
+    /// Variable-length sequences of other types.
+    VarLen,
+    Other,
+}
+
+impl ValueClass {
+    pub fn is_convertible(self) -> bool {
+        return self == ValueClass::Numeric || self == ValueClass::Compound;
+    }
+}
+
+pub fn value_class(ds: &hdf5::Dataset) -> hdf5::Result<ValueClass> {
+    return Ok(class_of(&ds.dtype()?.to_descriptor()?));
+}
+
+pub fn class_of(dtype: &TypeDescriptor) -> ValueClass {
+    return match dtype {
+        TypeDescriptor::Integer(_)
+        | TypeDescriptor::Unsigned(_)
+        | TypeDescriptor::Float(_)
+        | TypeDescriptor::Boolean
+        | TypeDescriptor::Enum(_) => ValueClass::Numeric,
+        TypeDescriptor::Compound(_) => ValueClass::Compound,
+        TypeDescriptor::FixedAscii(_)
+        | TypeDescriptor::FixedUnicode(_)
+        | TypeDescriptor::VarLenAscii
+        | TypeDescriptor::VarLenUnicode => ValueClass::Text,
+        TypeDescriptor::VarLenArray(_) => ValueClass::VarLen,
+        _ => ValueClass::Other,
+    };
+}
+
+/// Whether converting the values of `ds` to `T` may change them: to a
+/// narrower type, signed to unsigned, floats to integers, or integers wider
+/// than the float mantissa.
+pub fn loses_precision<T: hdf5::H5Type>(ds: &hdf5::Dataset) -> hdf5::Result<bool> {
+    return Ok(narrows::<T>(&ds.dtype()?.to_descriptor()?));
+}
+
+/// Whether converting values of type `from` to `T` may change them (see
+/// [`loses_precision`]).
+pub fn narrows<T: hdf5::H5Type>(from: &TypeDescriptor) -> bool {
+    let to = T::type_descriptor();
+    let mantissa_bits = if to.size() == 4 { 24 } else { 53 };
+    return match (from, &to) {
+        (TypeDescriptor::Float(_), TypeDescriptor::Float(_))
+        | (TypeDescriptor::Integer(_), TypeDescriptor::Integer(_))
+        | (TypeDescriptor::Unsigned(_), TypeDescriptor::Unsigned(_)) => from.size() > to.size(),
+        (TypeDescriptor::Unsigned(_), TypeDescriptor::Integer(_)) => from.size() >= to.size(),
+        (TypeDescriptor::Integer(_), TypeDescriptor::Unsigned(_))
+        | (TypeDescriptor::Float(_), TypeDescriptor::Integer(_))
+        | (TypeDescriptor::Float(_), TypeDescriptor::Unsigned(_)) => true,
+        (TypeDescriptor::Integer(_), TypeDescriptor::Float(_))
+        | (TypeDescriptor::Unsigned(_), TypeDescriptor::Float(_)) => {
+            8 * from.size() > mantissa_bits
+        }
+        _ => false,
+    };
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraversalOptions {
+    /// Descend into external links to companion files instead of skipping
+    /// them.
+    pub follow_external_links: bool,
+}
+
+#[derive(Debug)]
+pub enum H5NodeType {
+    Dataset(String),
+    Group(String),
+    /// Anything that is neither a group nor a dataset (named datatypes,
+    /// broken links), listed for reporting but never processed.
+    Other(String, ObjectKind),
+}
+
+fn link_kind_at(loc_id: hid_t, name: &str) -> LinkKind {
+    let c_name = match CString::new(name) {
+        Ok(c_name) => c_name,
+        Err(_) => return LinkKind::Unknown,
+    };
+    let mut info = std::mem::MaybeUninit::<H5L_info_t>::uninit();
+    let status = hdf5::sync::sync(|| unsafe {
+        H5Lget_info(loc_id, c_name.as_ptr(), info.as_mut_ptr(), H5P_DEFAULT)
+    });
+    if status < 0 {
+        return LinkKind::Unknown;
+    }
+    let info = unsafe { info.assume_init() };
+    return match info.type_ {
+        H5L_type_t::H5L_TYPE_HARD => LinkKind::Hard,
+        H5L_type_t::H5L_TYPE_SOFT => LinkKind::Soft,
+        H5L_type_t::H5L_TYPE_EXTERNAL => LinkKind::External,
+        _ => LinkKind::Unknown,
+    };
+}
+
+fn object_kind_at(loc_id: hid_t, name: &str) -> ObjectKind {
+    let c_name = match CString::new(name) {
+        Ok(c_name) => c_name,
+        Err(_) => return ObjectKind::Unknown,
+    };
+    let mut info = std::mem::MaybeUninit::<H5O_info_t>::uninit();
+    let status = hdf5::sync::sync(|| unsafe {
+        H5Oget_info_by_name(loc_id, c_name.as_ptr(), info.as_mut_ptr(), H5P_DEFAULT)
+    });
+    if status < 0 {
+        return ObjectKind::Unresolved;
+    }
+    let info = unsafe { info.assume_init() };
+    return match info.type_ {
+        H5O_type_t::H5O_TYPE_GROUP => ObjectKind::Group,
+        H5O_type_t::H5O_TYPE_DATASET => ObjectKind::Dataset,
+        H5O_type_t::H5O_TYPE_NAMED_DATATYPE => ObjectKind::NamedDatatype,
+        _ => ObjectKind::Unknown,
+    };
+}
+
+fn address_of(obj_id: hid_t) -> Option<ObjectAddress> {
+    let mut info = std::mem::MaybeUninit::<H5O_info_t>::uninit();
+    let status = hdf5::sync::sync(|| unsafe { H5Oget_info(obj_id, info.as_mut_ptr()) });
+    if status < 0 {
+        return None;
+    }
+    let info = unsafe { info.assume_init() };
+    return Some((info.fileno as u64, info.addr as u64));
+}
+
+impl HasMembers for hdf5::File {
+    fn get_members(&self) -> Result<Vec<String>, hdf5::Error> {
+        return self.member_names();
+    }
+
+    fn get_group(&self, name: &str) -> Result<hdf5::Group, hdf5::Error> {
+        return self.group(name);
+    }
+
+    fn is_group(&self, name: &str) -> bool {
+        return self.link_exists(name);
+    }
+
+    fn link_kind(&self, name: &str) -> LinkKind {
+        return link_kind_at(self.id(), name);
+    }
+
+    fn object_kind(&self, name: &str) -> ObjectKind {
+        return object_kind_at(self.id(), name);
+    }
+
+    fn address(&self) -> Option<ObjectAddress> {
+        return address_of(self.id());
+    }
+
+    fn get_dataset(&self, name: &str) -> Result<hdf5::Dataset, hdf5::Error> {
+        return self.dataset(name);
+    }
+
+    fn file_name(&self) -> String {
+        return self.filename();
+    }
+}
+
+impl HasMembers for hdf5::Group {
+    fn get_members(&self) -> Result<Vec<String>, hdf5::Error> {
+        return self.member_names();
+    }
+
+    fn get_group(&self, name: &str) -> Result<hdf5::Group, hdf5::Error> {
+        return self.group(name);
+    }
+
+    fn is_group(&self, name: &str) -> bool {
+        return self.link_exists(name);
+    }
+
+    fn link_kind(&self, name: &str) -> LinkKind {
+        return link_kind_at(self.id(), name);
+    }
+
+    fn object_kind(&self, name: &str) -> ObjectKind {
+        return object_kind_at(self.id(), name);
+    }
+
+    fn address(&self) -> Option<ObjectAddress> {
+        return address_of(self.id());
+    }
+
+    fn get_dataset(&self, name: &str) -> Result<hdf5::Dataset, hdf5::Error> {
+        return self.dataset(name);
+    }
+
+    fn file_name(&self) -> String {
+        return self.filename();
+    }
+}
+
+/// Reads elements `start..end` of `ds` in row-major order, whether it is
+/// 1-d or a 2-d grid of (rows, columns). Ranges only covering unallocated
+/// chunks are the fill value, without reading (see [`allocation`]).
+pub fn read_flat<T: hdf5::H5Type>(
+    ds: &hdf5::Dataset,
+    start: usize,
+    end: usize,
+) -> hdf5::Result<Array1<T>> {
+    if allocation::unallocated(ds, start, end) {
+        if let Some(vals) = allocation::fill_values::<T>(ds, end - start) {
+            return Ok(vals);
+        }
+    }
+    let shape = ds.shape();
+    if shape.len() != 2 || start == end {
+        let slice = SliceInfo::new(s![start..end]).unwrap();
+        return ds.read_slice_1d::<T, _>(&slice);
+    }
+    // The rows covering the range are read and trimmed to it.
+    let ncols = shape[1];
+    let (first_row, end_row) = (start / ncols, (end + ncols - 1) / ncols);
+    let slice = SliceInfo::new(s![first_row..end_row, ..]).unwrap();
+    let mut vals = ds.read_slice_2d::<T, _>(&slice)?.into_raw_vec();
+    vals.drain(..start - first_row * ncols);
+    vals.truncate(end - start);
+    return Ok(Array1::from(vals));
+}
+
+/// Writes `vals` to elements `start..start + vals.len()` of `ds` in
+/// row-major order, whether it is 1-d or a 2-d grid of (rows, columns).
+pub fn write_flat<T: hdf5::H5Type>(
+    ds: &hdf5::Dataset,
+    start: usize,
+    vals: &[T],
+) -> hdf5::Result<()> {
+    allocation::forget(ds);
+    let shape = ds.shape();
+    if shape.len() != 2 || vals.is_empty() {
+        let slice = SliceInfo::new(s![start..start + vals.len()]).unwrap();
+        return ds.write_slice(vals, &slice);
+    }
+    for (rows, cols, offset) in row_blocks(start, start + vals.len(), shape[1]) {
+        let len = rows.len() * cols.len();
+        let block =
+            ArrayView2::from_shape((rows.len(), cols.len()), &vals[offset..offset + len]).unwrap();
+        let slice = SliceInfo::new(s![rows, cols]).unwrap();
+        ds.write_slice(block, &slice)?;
+    }
+    return Ok(());
+}
+
+/// Splits elements `start..end` of a grid `ncols` wide into rectangular
+/// blocks (rows, columns, offset in the range): the end of the first row,
+/// the whole rows and the start of the last row.
+fn row_blocks(start: usize, end: usize, ncols: usize) -> Vec<(Range<usize>, Range<usize>, usize)> {
+    let mut blocks = vec![];
+    let mut pos = start;
+    while pos < end {
+        let (row, col) = (pos / ncols, pos % ncols);
+        let block = match col == 0 && end - pos >= ncols {
+            true => {
+                let n_rows = (end - pos) / ncols;
+                (row..row + n_rows, 0..ncols)
+            }
+            false => (row..row + 1, col..ncols.min(col + end - pos)),
+        };
+        let len = block.0.len() * block.1.len();
+        blocks.push((block.0, block.1, pos - start));
+        pos += len;
+    }
+    return blocks;
+}
+
+/// Grid of the 2-d datasets among `paths` as (xsize, ysize), from their
+/// (rows, columns) shape; `None` if there are none. Fails if they differ.
+pub fn grid_shape(file: &hdf5::File, paths: &[String]) -> CrateResult<Option<(usize, usize)>> {
+    let mut found: Option<(&str, (usize, usize))> = None;
+    for path in paths {
+        let shape = meta::get(file, path)?.shape;
+        if shape.len() != 2 {
+            continue;
+        }
+        let size = (shape[1], shape[0]);
+        match found {
+            None => found = Some((path, size)),
+            Some((first, first_size)) if first_size != size => {
+                return Err(crate::error::Error::Invalid(format!(
+                    "2-d datasets {} ({}x{}) and {} ({}x{}) differ in shape; \
+                     give templates, or --group-grid for groups on their own grids",
+                    first, first_size.0, first_size.1, path, size.0, size.1
+                )));
+            }
+            Some(_) => {}
+        }
+    }
+    return Ok(found.map(|(_, size)| size));
+}
+
+/// Joins a group path and a member name into an absolute HDF5 path with
+/// single separators, e.g. (`/beam01/agbd`, `sum`) -> `/beam01/agbd/sum`.
+pub fn join_path(group: &str, name: &str) -> String {
+    let group = group.trim_matches('/');
+    let name = name.trim_start_matches('/');
+    if group.is_empty() {
+        return format!("/{}", name);
+    }
+    return format!("/{}/{}", group, name);
+}
+
+/// Flattens a possibly nested group path into a stem usable in file names:
+/// separators become `_` and anything outside `[A-Za-z0-9._-]` becomes `-`,
+/// e.g. `/beam01/agbd` -> `beam01_agbd`.
+pub fn flatten_path(path: &str) -> String {
+    return path
+        .trim_matches('/')
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            part.chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-' {
+                        c
+                    } else {
+                        '-'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("_");
+}
+
+pub fn ls_hdf5(obj: &impl HasMembers, parent: String) -> Vec<H5NodeType> {
+    return ls_hdf5_with(obj, parent, &TraversalOptions::default());
+}
+
+/// Lists every group and dataset below `obj`, keeping the metadata of the
+/// datasets (see [`meta`]) under the file `obj` belongs to.
+///
+/// Soft and hard links are resolved by HDF5 when members are opened. Each
+/// group is listed once, under the first path it is reached by, so links
+/// pointing back to an ancestor (or aliasing another group) cannot cause
+/// infinite recursion or duplicate outputs. External links are skipped
+/// unless `options.follow_external_links` is set.
+pub fn ls_hdf5_with(
+    obj: &impl HasMembers,
+    parent: String,
+    options: &TraversalOptions,
+) -> Vec<H5NodeType> {
+    let mut visited = HashSet::new();
+    if let Some(address) = obj.address() {
+        visited.insert(address);
+    }
+    let mut result: Vec<H5NodeType> = vec![];
+    let root = obj.file_name();
+    walk_hdf5(obj, &root, parent, options, &mut visited, &mut result);
+    return result;
+}
+
+fn walk_hdf5(
+    obj: &impl HasMembers,
+    root: &str,
+    parent: String,
+    options: &TraversalOptions,
+    visited: &mut HashSet<ObjectAddress>,
+    result: &mut Vec<H5NodeType>,
+) {
+    if let Ok(member_names) = obj.get_members() {
+        for member_name in member_names {
+            let new_parent = parent.clone() + "/" + member_name.as_str();
+            let _silence = hdf5::silence_errors();
+            let link_kind = obj.link_kind(&member_name);
+            if link_kind == LinkKind::External && !options.follow_external_links {
+                println!("Skipping external link {}", new_parent);
+                continue;
+            }
+            match obj.object_kind(&member_name) {
+                ObjectKind::Group => {
+                    let group = match obj.get_group(member_name.as_str()) {
+                        Ok(group) => group,
+                        Err(_) => {
+                            result.push(H5NodeType::Other(new_parent, ObjectKind::Unresolved));
+                            continue;
+                        }
+                    };
+                    if let Some(address) = group.address() {
+                        if !visited.insert(address) {
+                            println!(
+                                "Skipping {}: {:?} link to a group that is already listed",
+                                new_parent, link_kind
+                            );
+                            continue;
+                        }
+                    }
+                    result.push(H5NodeType::Group(new_parent.clone()));
+                    walk_hdf5(&group, root, new_parent, options, visited, result);
+                }
+                ObjectKind::Dataset => {
+                    if let Ok(ds) = obj.get_dataset(&member_name) {
+                        meta::record(root, &new_parent, &ds);
+                    }
+                    result.push(H5NodeType::Dataset(new_parent));
+                }
+                kind => {
+                    println!(
+                        "Skipping {}: not a group or dataset ({:?})",
+                        new_parent, kind
+                    );
+                    result.push(H5NodeType::Other(new_parent, kind));
+                }
+            }
+        }
+    }
+}
+
+/// Chunking of a dataset written back to HDF5.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DatasetLayout {
+    /// Elements per chunk, matching how the dataset is written.
+    pub chunk_len: usize,
+    /// Create with an unlimited maximum size, so the dataset can be extended.
+    pub resizable: bool,
+}
+
+/// Creates the 1-d dataset `name` of `size` elements filled with
+/// `fill_value`, chunked by `layout` and compressed with `compression`.
+pub fn create_dataset<T: hdf5::H5Type>(
+    file: &hdf5::File,
+    name: &str,
+    size: usize,
+    fill_value: T,
+    layout: DatasetLayout,
+    compression: &H5Compression,
+) -> CrateResult<hdf5::Dataset> {
+    meta::forget(file, name);
+    let ds = compression.create_dataset::<T>(file, name, size, fill_value, layout)?;
+    allocation::forget(&ds);
+    history::note_created(file, name);
+    return Ok(ds);
+}
+
+/// Splits the groups among `nodes` into those containing every dataset in
+/// `required` with a convertible (numeric or compound) type and those that do
+/// not (e.g. metadata groups).
+pub fn stat_groups<'a>(
+    file: &hdf5::File,
+    nodes: &'a [H5NodeType],
+    required: &[&str],
+) -> (Vec<&'a str>, Vec<&'a str>) {
+    let mut with_stats = vec![];
+    let mut without_stats = vec![];
+    for node in nodes {
+        if let H5NodeType::Group(group_name) = node {
+            let has_all = required.iter().all(|name| {
+                let _silence = hdf5::silence_errors();
+                let path = join_path(group_name, name);
+                return file.object_kind(&path) == ObjectKind::Dataset
+                    && meta::get(file, &path)
+                        .map_or(false, |meta| meta.value_class().is_convertible());
+            });
+            if has_all {
+                with_stats.push(group_name.as_str());
+            } else {
+                without_stats.push(group_name.as_str());
+            }
+        }
+    }
+    return (with_stats, without_stats);
+}
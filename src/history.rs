@@ -0,0 +1,195 @@
+//! Audit trail of the datasets a run writes into the converted file itself,
+//! when there is no `--scratch` file, so custodians can see what altered
+//! their archive.
+//!
+//! Each such run appends a group `/processing_history/entry_NNNN` whose
+//! attributes give the time (`timestamp`, UTC), the `tool_version`, the
+//! `datasets_created` and the `parameters` (the command line). Each dataset
+//! created is tagged with a `processing_history` attribute naming its
+//! entry. Datasets are noted as they are created through
+//! [`crate::hdf::create_dataset`], like the metadata cache is updated.
+
+use crate::error::{Error, Result};
+use crate::meta;
+use hdf5::types::VarLenUnicode;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Group holding the entries.
+pub const GROUP: &str = "/processing_history";
+
+/// Attribute of each dataset created naming its entry.
+pub const TAG_ATTRIBUTE: &str = "processing_history";
+
+/// File names and paths of the datasets created by this process.
+static CREATED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Notes that the dataset at `path` of `file` was created.
+pub fn note_created(file: &hdf5::File, path: &str) {
+    let path = format!("/{}", path.trim_start_matches('/'));
+    if let Ok(mut created) = CREATED.lock() {
+        let entry = (file.filename(), path);
+        if !created.contains(&entry) {
+            created.push(entry);
+        }
+    }
+}
+
+/// Paths of the datasets created in the file named `file_name` so far.
+pub fn created_in(file_name: &str) -> Vec<String> {
+    return match CREATED.lock() {
+        Ok(created) => created
+            .iter()
+            .filter(|(name, _)| name == file_name)
+            .map(|(_, path)| path.clone())
+            .collect(),
+        Err(_) => vec![],
+    };
+}
+
+fn unicode(name: &str, value: &str) -> Result<VarLenUnicode> {
+    return value
+        .parse()
+        .map_err(|_| Error::Invalid(format!("invalid {} attribute '{}'", name, value)));
+}
+
+/// Appends an entry to the HDF5 file at `path`, opened for writing only if
+/// datasets were created in it, as [`append`] does.
+pub fn append_to(path: &str, arguments: &[String]) -> Result<Option<String>> {
+    if CREATED.lock().map_or(true, |created| created.is_empty()) {
+        return Ok(None);
+    }
+    return append(&hdf5::File::open_rw(path)?, arguments);
+}
+
+/// Appends an entry for the datasets created in `file` that still exist,
+/// if any, run with `arguments`, and tags them with it. Returns the path of
+/// the entry.
+pub fn append(file: &hdf5::File, arguments: &[String]) -> Result<Option<String>> {
+    let created: Vec<String> = created_in(&file.filename())
+        .into_iter()
+        .filter(|path| file.link_exists(path))
+        .collect();
+    if created.is_empty() {
+        return Ok(None);
+    }
+    let history = match file.link_exists(GROUP) {
+        true => file.group(GROUP)?,
+        false => file.create_group(GROUP)?,
+    };
+    let entry_path = format!("{}/entry_{:04}", GROUP, history.member_names()?.len());
+    let entry = file.create_group(&entry_path)?;
+    let strings = [
+        ("timestamp", rfc3339_utc(SystemTime::now())),
+        (
+            "tool_version",
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ),
+        ("parameters", arguments.join(" ")),
+    ];
+    for (name, value) in strings.iter() {
+        entry
+            .new_attr::<VarLenUnicode>()
+            .create(*name)?
+            .write_scalar(&unicode(name, value)?)?;
+    }
+    let paths = created
+        .iter()
+        .map(|path| unicode("datasets_created", path))
+        .collect::<Result<Vec<VarLenUnicode>>>()?;
+    entry
+        .new_attr::<VarLenUnicode>()
+        .shape(paths.len())
+        .create("datasets_created")?
+        .write(&paths)?;
+    let tag = unicode(TAG_ATTRIBUTE, &entry_path)?;
+    for path in &created {
+        // Created by this run, so not tagged yet.
+        file.dataset(path)?
+            .new_attr::<VarLenUnicode>()
+            .create(TAG_ATTRIBUTE)?
+            .write_scalar(&tag)?;
+    }
+    return Ok(Some(entry_path));
+}
+
+/// Datasets recorded in the entries of `file` that still exist and are
+/// tagged with their entry, in the order they were created. Datasets no
+/// longer tagged were replaced since and are left out.
+pub fn recorded(file: &hdf5::File) -> Result<Vec<String>> {
+    if !file.link_exists(GROUP) {
+        return Ok(vec![]);
+    }
+    let mut entries = file.group(GROUP)?.member_names()?;
+    entries.sort();
+    let mut recorded = vec![];
+    for name in entries {
+        let entry_path = format!("{}/{}", GROUP, name);
+        let paths = file
+            .group(&entry_path)?
+            .attr("datasets_created")?
+            .read_raw::<VarLenUnicode>()?;
+        for path in paths {
+            let path = path.as_str();
+            if !file.link_exists(path) {
+                continue;
+            }
+            let tag = {
+                let _silence = hdf5::silence_errors();
+                file.dataset(path)
+                    .and_then(|ds| ds.attr(TAG_ATTRIBUTE))
+                    .and_then(|attr| attr.read_scalar::<VarLenUnicode>())
+            };
+            match tag {
+                Ok(tag) if tag.as_str() == entry_path => recorded.push(path.to_owned()),
+                _ => println!("Keeping {}: it was replaced since {}", path, entry_path),
+            }
+        }
+    }
+    return Ok(recorded);
+}
+
+/// Removes the datasets recorded in `file` and its history, restoring the
+/// datasets it had before the first run recorded. Returns the datasets
+/// removed, or that would be with `dry_run`.
+pub fn undo(file: &hdf5::File, dry_run: bool) -> Result<Vec<String>> {
+    let recorded = recorded(file)?;
+    if dry_run {
+        return Ok(recorded);
+    }
+    for path in &recorded {
+        file.unlink(path)?;
+        meta::forget(file, path);
+    }
+    if file.link_exists(GROUP) {
+        file.unlink(GROUP)?;
+    }
+    return Ok(recorded);
+}
+
+/// `time` as an RFC 3339 timestamp in UTC, to the second.
+fn rfc3339_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date of a day count, after Howard Hinnant's civil_from_days.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    );
+}
@@ -0,0 +1,229 @@
+//! Import of rasters into the HDF5 file (`import` subcommand), the reverse
+//! of the conversion, so the tool bridges both ways.
+//!
+//! A band of a GeoTIFF, or any raster GDAL reads, is written as a 1-d
+//! dataset like the statistic datasets: in the row order of the file (see
+//! [`crate::row_order`]), chunked by whole rows and compressed like the
+//! datasets written back. Its nodata value becomes the fill value, and its
+//! geotransform (for the rows as stored), CRS and shape become attributes.
+
+use crate::chunking;
+use crate::compression::H5Compression;
+use crate::error::{Error, Result};
+use crate::hdf::{self, DatasetLayout};
+use crate::interrupt;
+use crate::policy::{Decision, ExistsPolicy};
+use crate::raster::RasterValue;
+use crate::reverse::{self, ChunkWriter, HdfChunks};
+use crate::row_order::{self, FlipOption};
+use crate::status;
+use crate::templates::RasterType;
+use crate::typed::{self, TypedJob};
+use crate::valid_range::RangeValue;
+use hdf5::types::VarLenUnicode;
+use std::ffi::CStr;
+use std::path::Path;
+
+/// How rasters are written into the file.
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Band imported, from 1.
+    pub band: usize,
+    /// Type of the dataset, else that of the band.
+    pub data_type: Option<RasterType>,
+    pub flip: FlipOption,
+    pub memory_limit: usize,
+    pub compression: H5Compression,
+    pub resizable: bool,
+    pub exists_policy: ExistsPolicy,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        return ImportOptions {
+            band: 1,
+            data_type: None,
+            flip: FlipOption::default(),
+            memory_limit: chunking::DEFAULT_MEMORY_LIMIT,
+            compression: H5Compression::default(),
+            resizable: false,
+            exists_policy: ExistsPolicy::default(),
+        };
+    }
+}
+
+/// Writes band `options.band` of the raster at `raster_path` to the dataset
+/// `ds_path` of `file`. Returns whether it was written, not skipped as
+/// existing.
+pub fn import_raster(
+    file: &hdf5::File,
+    raster_path: &str,
+    ds_path: &str,
+    options: &ImportOptions,
+) -> Result<bool> {
+    let rast = gdal::Dataset::open(Path::new(raster_path))?;
+    let band = rast.rasterband(options.band as _)?;
+    // SAFETY: the band handle is valid while `rast` is open and the name is
+    // a static string owned by GDAL.
+    let band_type = unsafe {
+        let data_type = gdal_sys::GDALGetRasterDataType(band.c_rasterband());
+        CStr::from_ptr(gdal_sys::GDALGetDataTypeName(data_type))
+            .to_string_lossy()
+            .into_owned()
+    };
+    let data_type = match options.data_type {
+        Some(data_type) => data_type,
+        None => RasterType::parse(&band_type).map_err(|_| {
+            Error::Invalid(format!(
+                "{} has {} values; choose a dataset type with --ot",
+                raster_path, band_type
+            ))
+        })?,
+    };
+    drop(band);
+    return typed::dispatch(
+        data_type,
+        ImportJob {
+            file,
+            rast: &rast,
+            raster_path,
+            ds_path,
+            options,
+        },
+    );
+}
+
+/// [`import_as`] for the type dispatched.
+struct ImportJob<'a> {
+    file: &'a hdf5::File,
+    rast: &'a gdal::Dataset,
+    raster_path: &'a str,
+    ds_path: &'a str,
+    options: &'a ImportOptions,
+}
+
+impl TypedJob for ImportJob<'_> {
+    type Output = Result<bool>;
+
+    fn run<T: RasterValue + Send>(self) -> Result<bool> {
+        return import_as::<T>(
+            self.file,
+            self.rast,
+            self.raster_path,
+            self.ds_path,
+            self.options,
+        );
+    }
+}
+
+fn import_as<T: RasterValue>(
+    file: &hdf5::File,
+    rast: &gdal::Dataset,
+    raster_path: &str,
+    ds_path: &str,
+    options: &ImportOptions,
+) -> Result<bool> {
+    if options.exists_policy.decide_dataset(file, ds_path)? == Decision::Skip {
+        return Ok(false);
+    }
+    let band = rast.rasterband(options.band as _)?;
+    let (xsize, ysize) = rast.raster_size();
+    let len = chunking::grid_len(xsize, ysize).map_err(Error::Invalid)?;
+    let geo_transform = rast.geo_transform()?;
+    let flip = row_order::needs_flip(options.flip, file, &geo_transform)?;
+    let n_lines = chunking::strip_lines(
+        options.memory_limit,
+        xsize,
+        chunking::dataset_raster_bytes_per_pixel(std::mem::size_of::<T>()),
+    )
+    .min(ysize.max(1));
+    let layout = DatasetLayout {
+        chunk_len: chunking::h5_chunk_len(xsize, n_lines, std::mem::size_of::<T>()),
+        resizable: options.resizable,
+    };
+    let fill_value = match band.no_data_value() {
+        Some(nodata) => T::from_f64(nodata),
+        None => T::default(),
+    };
+    let ds = hdf::create_dataset(file, ds_path, len, fill_value, layout, &options.compression)?;
+
+    println!(
+        "Importing {} into {} ({}x{}{})...",
+        raster_path,
+        ds_path,
+        xsize,
+        ysize,
+        if flip { ", rows flipped" } else { "" }
+    );
+    let writer = HdfChunks(&ds);
+    let result = (|| -> Result<()> {
+        for yy in (0..ysize).step_by(n_lines) {
+            interrupt::check()?;
+            status::set_progress(yy as f32 / ysize as f32);
+            let lines = n_lines.min(ysize - yy);
+            let vals = band
+                .read_as::<T>((0, yy as isize), (xsize, lines), (xsize, lines), None)?
+                .data;
+            let (start, vals) = match flip {
+                true => {
+                    let vals = reverse::rev_array(ndarray::Array1::from(vals), lines, xsize);
+                    ((ysize - yy - lines) * xsize, vals.to_vec())
+                }
+                false => (yy * xsize, vals),
+            };
+            writer.write_range(start, start + vals.len(), &vals)?;
+        }
+        return Ok(());
+    })();
+    if let Err(err) = result {
+        drop(ds);
+        let _ = file.unlink(ds_path);
+        return Err(err);
+    }
+
+    write_georef_attrs(&ds, raster_path, rast, (xsize, ysize), flip)?;
+    file.flush()?;
+    return Ok(true);
+}
+
+/// Records the georeferencing of `rast` on `ds`: the geotransform of the
+/// rows as stored, flipped or not, the CRS as WKT (`crs_wkt`, as in CF),
+/// the `shape` of the grid as (rows, columns) and the `source` raster.
+fn write_georef_attrs(
+    ds: &hdf5::Dataset,
+    raster_path: &str,
+    rast: &gdal::Dataset,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+) -> Result<()> {
+    let mut gt = rast.geo_transform()?;
+    if flip {
+        // Row r of the dataset is row ysize - r of the raster.
+        gt[0] += ysize as f64 * gt[2];
+        gt[3] += ysize as f64 * gt[5];
+        gt[2] = -gt[2];
+        gt[5] = -gt[5];
+    }
+    ds.new_attr::<f64>()
+        .shape(6)
+        .create("GeoTransform")?
+        .write(&gt)?;
+    ds.new_attr::<u64>()
+        .shape(2)
+        .create("shape")?
+        .write(&[ysize as u64, xsize as u64])?;
+    let mut strings = vec![("source", raster_path.to_owned())];
+    let crs = rast.projection();
+    if !crs.is_empty() {
+        strings.push(("crs_wkt", crs));
+    }
+    for (name, value) in strings {
+        let value: VarLenUnicode = value
+            .parse()
+            .map_err(|_| Error::Invalid(format!("invalid {} attribute '{}'", name, value)))?;
+        ds.new_attr::<VarLenUnicode>()
+            .create(name)?
+            .write_scalar(&value)?;
+    }
+    return Ok(());
+}
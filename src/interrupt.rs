@@ -0,0 +1,100 @@
+//! Ctrl-C handling, and cancellation of library callers.
+//!
+//! The handler only raises a flag; long-running loops poll it between chunks
+//! through [`check`] so every HDF5 and GDAL handle is dropped (and flushed) on
+//! the normal unwinding path. A second Ctrl-C exits immediately.
+//!
+//! Programs embedding the crate, such as GUIs and services, cancel instead
+//! through the [`CancellationToken`] set for the process with [`set_token`]
+//! (or given to the converter, see
+//! [`crate::converter::ConvertOptions::cancellation`]), which the same loops
+//! poll, so a cancelled run stops like an interrupted one.
+//!
+//! There is a single token for the whole process, like the Ctrl-C flag, not
+//! one per conversion: cancelling it stops every run in the process, so
+//! programs wanting to cancel conversions one by one must run them one at a
+//! time (or in separate processes). A converter given a token refuses to
+//! open while another is set.
+
+use crate::error::{Error, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Flag cancelling the runs that poll it, from any thread; clones share it.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        return CancellationToken::default();
+    }
+
+    /// Stops the runs polling the token after their current chunk.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.0.load(Ordering::SeqCst);
+    }
+}
+
+static TOKEN: Mutex<Option<CancellationToken>> = Mutex::new(None);
+
+/// Polls `token`, if any, besides Ctrl-C for the rest of the process.
+pub fn set_token(token: Option<CancellationToken>) {
+    if let Ok(mut current) = TOKEN.lock() {
+        *current = token;
+    }
+}
+
+/// The token polled, if any.
+pub fn token() -> Option<CancellationToken> {
+    return TOKEN.lock().ok().and_then(|token| token.clone());
+}
+
+/// Whether `token` is the one polled.
+pub fn is_token(token: &CancellationToken) -> bool {
+    return TOKEN.lock().map_or(false, |current| {
+        return current
+            .as_ref()
+            .map_or(false, |current| Arc::ptr_eq(&current.0, &token.0));
+    });
+}
+
+pub fn install_handler() -> std::result::Result<(), ctrlc::Error> {
+    return ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!("\nInterrupted, stopping after the current chunk (press Ctrl-C again to abort)");
+    });
+}
+
+/// Whether Ctrl-C has been pressed, whatever the token polled.
+pub fn is_ctrl_c() -> bool {
+    return INTERRUPTED.load(Ordering::SeqCst);
+}
+
+/// Whether Ctrl-C has been pressed or the token polled cancelled.
+pub fn is_interrupted() -> bool {
+    if is_ctrl_c() {
+        return true;
+    }
+    return TOKEN.lock().map_or(false, |token| {
+        return token
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled);
+    });
+}
+
+/// Returns `Err(Error::Interrupted)` once Ctrl-C has been pressed or the
+/// token polled cancelled.
+pub fn check() -> Result<()> {
+    if is_interrupted() {
+        return Err(Error::Interrupted);
+    }
+    return Ok(());
+}
@@ -0,0 +1,157 @@
+//! Inventory of the datasets and statistic groups of a file, printed as
+//! JSON by `list --json`, and the selection of what a run converts, read
+//! back as JSON (`--select`), so that another tool can decide in between.
+//!
+//! Selections name groups and datasets by path, as strings or as objects
+//! with a `path` member, so entries of the inventory filtered by e.g. `jq`
+//! can be passed on as they are.
+
+use crate::error::{Error, Result};
+use crate::hdf::{self, H5NodeType, TraversalOptions};
+use crate::meta;
+use crate::stats::StatNames;
+use hdf5::types::TypeDescriptor;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// A dataset of the inventory, described from its cached metadata.
+#[derive(Debug, Clone, Serialize)]
+pub struct DatasetEntry {
+    pub path: String,
+    pub shape: Vec<usize>,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    /// `numeric`, `compound`, `text`, `varlen` or `other`; only numeric and
+    /// compound datasets can be converted.
+    pub class: String,
+    pub chunk: Option<Vec<usize>>,
+    pub bytes: u64,
+    pub attrs: Vec<String>,
+}
+
+/// A group with every statistic dataset, converted as a group.
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupEntry {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Inventory {
+    pub file: String,
+    pub groups: Vec<GroupEntry>,
+    pub datasets: Vec<DatasetEntry>,
+}
+
+/// Short name of an element type, e.g. `u8`, `f32` or `compound`.
+fn type_name(dtype: &TypeDescriptor) -> String {
+    let bits = dtype.size() * 8;
+    return match dtype {
+        TypeDescriptor::Integer(_) => format!("i{}", bits),
+        TypeDescriptor::Unsigned(_) => format!("u{}", bits),
+        TypeDescriptor::Float(_) => format!("f{}", bits),
+        TypeDescriptor::Boolean => "bool".to_owned(),
+        TypeDescriptor::Enum(_) => "enum".to_owned(),
+        TypeDescriptor::Compound(_) => "compound".to_owned(),
+        TypeDescriptor::FixedAscii(_)
+        | TypeDescriptor::FixedUnicode(_)
+        | TypeDescriptor::VarLenAscii
+        | TypeDescriptor::VarLenUnicode => "string".to_owned(),
+        TypeDescriptor::VarLenArray(_) => "varlen".to_owned(),
+        _ => "other".to_owned(),
+    };
+}
+
+/// Lists the datasets of `file` and the groups with all of `stat_names`.
+pub fn inventory(
+    file: &hdf5::File,
+    traversal: &TraversalOptions,
+    stat_names: &StatNames,
+) -> Result<Inventory> {
+    let nodes = hdf::ls_hdf5_with(file, "".to_owned(), traversal);
+    let (groups, _) = hdf::stat_groups(file, &nodes, &stat_names.all());
+    let mut datasets = vec![];
+    for node in &nodes {
+        if let H5NodeType::Dataset(path) = node {
+            let meta = meta::get(file, path)?;
+            datasets.push(DatasetEntry {
+                path: path.clone(),
+                shape: meta.shape.clone(),
+                data_type: type_name(&meta.dtype),
+                class: format!("{:?}", meta.value_class()).to_lowercase(),
+                chunk: meta.chunk.clone(),
+                bytes: meta.bytes(),
+                attrs: meta.attrs.clone(),
+            });
+        }
+    }
+    return Ok(Inventory {
+        file: file.filename(),
+        groups: groups
+            .iter()
+            .map(|&path| GroupEntry {
+                path: path.to_owned(),
+            })
+            .collect(),
+        datasets,
+    });
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Path(String),
+    Object { path: String },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSelection {
+    groups: Option<Vec<Entry>>,
+    #[serde(default)]
+    datasets: Vec<Entry>,
+    #[serde(default)]
+    time_series: Vec<Entry>,
+}
+
+/// What a run converts: the groups (all of them if `None`), and the
+/// datasets and time series converted on their own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selection {
+    pub groups: Option<Vec<String>>,
+    pub datasets: Vec<String>,
+    pub time_series: Vec<String>,
+}
+
+fn paths(entries: Vec<Entry>) -> Vec<String> {
+    return entries
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::Path(path) | Entry::Object { path } => path,
+        })
+        .collect();
+}
+
+/// Parses a selection, e.g. `{"groups": ["agbd"], "datasets": [{"path":
+/// "/quality/flags"}]}`.
+pub fn parse_selection(text: &str) -> Result<Selection> {
+    let raw: RawSelection = serde_json::from_str(text)?;
+    return Ok(Selection {
+        groups: raw.groups.map(paths),
+        datasets: paths(raw.datasets),
+        time_series: paths(raw.time_series),
+    });
+}
+
+/// Reads the selection in the file at `path`, or on stdin for `-`.
+pub fn read_selection(path: &str) -> Result<Selection> {
+    let mut text = String::new();
+    match path {
+        "-" => {
+            std::io::stdin().read_to_string(&mut text)?;
+        }
+        _ => text = std::fs::read_to_string(path)?,
+    }
+    return parse_selection(&text).map_err(|err| {
+        return Error::Invalid(format!("selection {}: {}", path, err));
+    });
+}
@@ -0,0 +1,85 @@
+//! Custom per-pixel statistics computed alongside the count, mean and sd of
+//! each group.
+
+use crate::error::{Error, Result};
+use std::fmt;
+
+/// Values of one chunk of a group handed to a [`StatisticKernel`]: its
+/// count, mean and sd, and the datasets named by
+/// [`inputs`](StatisticKernel::inputs), all in reversed (output) order.
+pub struct ChunkInputs<'a> {
+    pub count: &'a [u32],
+    pub mean: &'a [f32],
+    pub sd: &'a [f32],
+    /// The input datasets, in the order of `inputs`, as `f64`.
+    pub datasets: &'a [Vec<f64>],
+}
+
+impl<'a> ChunkInputs<'a> {
+    /// Number of pixels in the chunk.
+    pub fn len(&self) -> usize {
+        return self.count.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.count.is_empty();
+    }
+}
+
+/// A per-pixel formula written as extra float outputs of every group, e.g.
+/// `<group>_cv.tif` for an output named `cv`.
+///
+/// Kernels run on the chunks of the group writer, so each is computed once
+/// per strip, from the calling thread of each group written at once. Pixels
+/// without observations, or with fewer than the minimum count, are set to
+/// NaN after `compute`, which need not handle them.
+pub trait StatisticKernel: Send + Sync {
+    /// Names of the outputs, one per vector returned by `compute`; they must
+    /// differ from the built-in statistics.
+    fn outputs(&self) -> Vec<String>;
+
+    /// Datasets of the group read as inputs besides its count, mean and sd;
+    /// they are reversed with the statistic datasets.
+    fn inputs(&self) -> Vec<String> {
+        return vec![];
+    }
+
+    /// Computes the outputs of one chunk, each with a value per pixel.
+    fn compute(&self, chunk: &ChunkInputs) -> Result<Vec<Vec<f32>>>;
+}
+
+impl fmt::Debug for dyn StatisticKernel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "StatisticKernel({})", self.outputs().join(", "));
+    }
+}
+
+/// Runs `kernel` on `chunk`, checking the number and length of its outputs,
+/// and empties the pixels with fewer than `min_count` observations (at least
+/// one).
+pub fn run(
+    kernel: &dyn StatisticKernel,
+    chunk: &ChunkInputs,
+    min_count: u32,
+) -> Result<Vec<Vec<f32>>> {
+    let names = kernel.outputs();
+    let mut outputs = kernel.compute(chunk)?;
+    if outputs.len() != names.len() || outputs.iter().any(|vals| vals.len() != chunk.len()) {
+        return Err(Error::Invalid(format!(
+            "kernel {} returned {} outputs, expected {} of {} values",
+            names.join(", "),
+            outputs.len(),
+            names.len(),
+            chunk.len()
+        )));
+    }
+    let min_count = min_count.max(1);
+    for vals in outputs.iter_mut() {
+        for (val, &count) in vals.iter_mut().zip(chunk.count.iter()) {
+            if count < min_count {
+                *val = f32::NAN;
+            }
+        }
+    }
+    return Ok(outputs);
+}
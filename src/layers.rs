@@ -0,0 +1,316 @@
+//! Layers of a run: the output rasters with the statistic and group they
+//! hold, recorded as they are written, and the files linking them all so
+//! the product set opens at once: a GDAL VRT with a band per output
+//! (`--vrt`) and a QGIS project with a layer group per statistic
+//! (`--qgis-project`).
+//!
+//! Like warnings and summaries, layers are process-wide, so groups written
+//! at once record theirs without extra state.
+
+use crate::error::Result;
+use crate::provenance::escape_xml;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// An output raster of the run.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Layer {
+    /// Statistic held, e.g. `mean`, or the name of a converted dataset.
+    pub statistic: String,
+    /// Group of the statistic, or parent group of the dataset.
+    pub group: String,
+    pub path: String,
+}
+
+impl Layer {
+    /// Layer of the output at `path` of the dataset `ds_path`, named after
+    /// the dataset within its parent group.
+    pub fn of_dataset(ds_path: &str, path: &str) -> Layer {
+        let (group, name) = match ds_path.rfind('/') {
+            Some(index) => (&ds_path[..index.max(1)], &ds_path[index + 1..]),
+            None => ("/", ds_path),
+        };
+        return Layer {
+            statistic: name.to_owned(),
+            group: group.to_owned(),
+            path: path.to_owned(),
+        };
+    }
+
+    /// Name shown for the layer: its group and statistic.
+    pub fn name(&self) -> String {
+        return format!("{}/{}", self.group.trim_end_matches('/'), self.statistic);
+    }
+}
+
+static LAYERS: Mutex<Vec<Layer>> = Mutex::new(vec![]);
+
+/// Records the output raster `layer`.
+pub fn record(layer: Layer) {
+    if let Ok(mut layers) = LAYERS.lock() {
+        layers.push(layer);
+    }
+}
+
+/// Takes the layers recorded so far, sorted by statistic and group so the
+/// order does not depend on the groups written at once.
+pub fn take() -> Vec<Layer> {
+    let mut layers = match LAYERS.lock() {
+        Ok(mut layers) => std::mem::take(&mut *layers),
+        Err(_) => vec![],
+    };
+    layers.sort();
+    return layers;
+}
+
+/// Path of `target` as written in the file at `link_path`: relative to it
+/// when both are in the working directory, else absolute; and whether it
+/// is relative.
+fn link_target(link_path: &str, target: &str) -> (String, bool) {
+    let in_cwd = |path: &str| {
+        return Path::new(path)
+            .parent()
+            .map_or(true, |parent| parent.as_os_str().is_empty());
+    };
+    if in_cwd(link_path) && in_cwd(target) {
+        return (target.to_owned(), true);
+    }
+    let absolute = std::fs::canonicalize(target).unwrap_or_else(|_| PathBuf::from(target));
+    return (absolute.to_string_lossy().into_owned(), false);
+}
+
+/// Writes a GDAL VRT at `vrt_path` with one band per band of the `layers`,
+/// in their order, described by the layer name and with `STATISTIC` and
+/// `GROUP` metadata. The grid is that of the first layer; layers on other
+/// grids, of groups on their own grids, are left out with a warning.
+#[cfg(feature = "gdal")]
+pub fn write_vrt(vrt_path: &str, layers: &[Layer]) -> Result<()> {
+    use crate::error::Error;
+    use crate::warnings::{self, Warning};
+    use std::ffi::CStr;
+
+    let first = layers
+        .first()
+        .ok_or_else(|| Error::Invalid(format!("no output rasters to link in {}", vrt_path)))?;
+    let rast = gdal::Dataset::open(Path::new(&first.path))?;
+    let (xsize, ysize) = rast.raster_size();
+    let gt = rast.geo_transform()?;
+    let mut xml = format!(
+        "<VRTDataset rasterXSize=\"{}\" rasterYSize=\"{}\">\n",
+        xsize, ysize
+    );
+    xml.push_str(&format!(
+        "  <SRS dataAxisToSRSAxisMapping=\"1,2\">{}</SRS>\n",
+        escape_xml(&rast.projection())
+    ));
+    xml.push_str(&format!(
+        "  <GeoTransform>{}, {}, {}, {}, {}, {}</GeoTransform>\n",
+        gt[0], gt[1], gt[2], gt[3], gt[4], gt[5]
+    ));
+    drop(rast);
+
+    let mut n_bands = 0;
+    for layer in layers {
+        let rast = gdal::Dataset::open(Path::new(&layer.path))?;
+        if rast.raster_size() != (xsize, ysize) || rast.geo_transform()? != gt {
+            warnings::record(
+                Warning::Skipped,
+                format!(
+                    "{} left out of {}: it is not on the grid of the first layer",
+                    layer.path, vrt_path
+                ),
+            );
+            continue;
+        }
+        let (target, relative) = link_target(vrt_path, &layer.path);
+        for source_band in 1..=rast.raster_count() {
+            let band = rast.rasterband(source_band)?;
+            // SAFETY: the band handle is valid while `rast` is open and the
+            // returned strings are owned by GDAL.
+            let (type_name, source_description) = unsafe {
+                let data_type = gdal_sys::GDALGetRasterDataType(band.c_rasterband());
+                let type_name = CStr::from_ptr(gdal_sys::GDALGetDataTypeName(data_type));
+                let description = CStr::from_ptr(gdal_sys::GDALGetDescription(band.c_rasterband()));
+                (
+                    type_name.to_string_lossy().into_owned(),
+                    description.to_string_lossy().into_owned(),
+                )
+            };
+            let description = match source_description.is_empty() {
+                true => layer.name(),
+                false => format!("{} {}", layer.name(), source_description),
+            };
+            n_bands += 1;
+            xml.push_str(&format!(
+                "  <VRTRasterBand dataType=\"{}\" band=\"{}\">\n",
+                type_name, n_bands
+            ));
+            xml.push_str(&format!(
+                "    <Description>{}</Description>\n",
+                escape_xml(&description)
+            ));
+            if let Some(nodata) = band.no_data_value() {
+                xml.push_str(&format!("    <NoDataValue>{}</NoDataValue>\n", nodata));
+            }
+            xml.push_str("    <Metadata>\n");
+            xml.push_str(&format!(
+                "      <MDI key=\"STATISTIC\">{}</MDI>\n",
+                escape_xml(&layer.statistic)
+            ));
+            xml.push_str(&format!(
+                "      <MDI key=\"GROUP\">{}</MDI>\n",
+                escape_xml(&layer.group)
+            ));
+            xml.push_str("    </Metadata>\n");
+            xml.push_str("    <SimpleSource>\n");
+            xml.push_str(&format!(
+                "      <SourceFilename relativeToVRT=\"{}\">{}</SourceFilename>\n",
+                relative as u8,
+                escape_xml(&target)
+            ));
+            xml.push_str(&format!("      <SourceBand>{}</SourceBand>\n", source_band));
+            xml.push_str(&format!(
+                "      <SrcRect xOff=\"0\" yOff=\"0\" xSize=\"{0}\" ySize=\"{1}\" />\n      \
+                 <DstRect xOff=\"0\" yOff=\"0\" xSize=\"{0}\" ySize=\"{1}\" />\n",
+                xsize, ysize
+            ));
+            xml.push_str("    </SimpleSource>\n");
+            xml.push_str("  </VRTRasterBand>\n");
+        }
+    }
+    xml.push_str("</VRTDataset>\n");
+    std::fs::write(vrt_path, xml)?;
+    return Ok(());
+}
+
+/// QGIS project (`.qgs` XML) loading the `layers` through GDAL, in a layer
+/// tree group per statistic; sources are relative to `project_path` when
+/// possible.
+pub fn qgis_project(project_path: &str, layers: &[Layer]) -> String {
+    let mut tree = String::new();
+    let mut map_layers = String::new();
+    let mut statistic: Option<&str> = None;
+    for (ii, layer) in layers.iter().enumerate() {
+        if statistic != Some(layer.statistic.as_str()) {
+            if statistic.is_some() {
+                tree.push_str("    </layer-tree-group>\n");
+            }
+            tree.push_str(&format!(
+                "    <layer-tree-group name=\"{}\" checked=\"Qt::Checked\" expanded=\"1\">\n",
+                escape_xml(&layer.statistic)
+            ));
+            statistic = Some(layer.statistic.as_str());
+        }
+        let id = format!("layer_{}", ii + 1);
+        let name = escape_xml(&layer.name());
+        let source = match link_target(project_path, &layer.path) {
+            (target, true) => format!("./{}", target),
+            (target, false) => target,
+        };
+        let source = escape_xml(&source);
+        tree.push_str(&format!(
+            "      <layer-tree-layer id=\"{}\" name=\"{}\" source=\"{}\" providerKey=\"gdal\" \
+             checked=\"Qt::Unchecked\" expanded=\"0\"/>\n",
+            id, name, source
+        ));
+        map_layers.push_str(&format!(
+            "    <maplayer type=\"raster\">\n      <id>{}</id>\n      \
+             <datasource>{}</datasource>\n      <layername>{}</layername>\n      \
+             <provider>gdal</provider>\n    </maplayer>\n",
+            id, source, name
+        ));
+    }
+    if statistic.is_some() {
+        tree.push_str("    </layer-tree-group>\n");
+    }
+    let mut xml = String::new();
+    xml.push_str("<!DOCTYPE qgis PUBLIC 'http://mrcc.com/qgis.dtd' 'SYSTEM'>\n");
+    xml.push_str("<qgis projectname=\"\" version=\"3.28.0\">\n");
+    xml.push_str("  <layer-tree-group>\n");
+    xml.push_str(&tree);
+    xml.push_str("  </layer-tree-group>\n");
+    xml.push_str("  <projectlayers>\n");
+    xml.push_str(&map_layers);
+    xml.push_str("  </projectlayers>\n");
+    xml.push_str("  <properties>\n    <Paths>\n      <Absolute type=\"bool\">false</Absolute>\n    </Paths>\n  </properties>\n");
+    xml.push_str("</qgis>\n");
+    return xml;
+}
+
+/// Writes the QGIS project of the `layers` (see [`qgis_project`]) at
+/// `project_path`: a `.qgz` archive holding the `.qgs`, or the `.qgs`
+/// itself for other extensions.
+pub fn write_qgis_project(project_path: &str, layers: &[Layer]) -> Result<()> {
+    let xml = qgis_project(project_path, layers);
+    let path = Path::new(project_path);
+    if path
+        .extension()
+        .map_or(false, |extension| extension == "qgz")
+    {
+        let name = path.with_extension("qgs");
+        let name = name.file_name().unwrap_or_default().to_string_lossy();
+        let file = std::fs::File::create(path)?;
+        write_stored_zip(file, &name, xml.as_bytes())?;
+    } else {
+        std::fs::write(path, xml)?;
+    }
+    return Ok(());
+}
+
+/// Writes a zip archive holding `data` uncompressed as the file `name`.
+fn write_stored_zip(mut out: impl Write, name: &str, data: &[u8]) -> std::io::Result<()> {
+    let crc = crc32(data);
+    let size = data.len() as u32;
+    let name = name.as_bytes();
+    // Fields shared by the local and central headers: version needed, flags,
+    // method (stored), time, date, CRC-32, sizes and name length.
+    let mut common = vec![];
+    common.extend(20u16.to_le_bytes());
+    common.extend(0u16.to_le_bytes());
+    common.extend(0u16.to_le_bytes());
+    common.extend(0u16.to_le_bytes());
+    common.extend((1 << 5 | 1u16).to_le_bytes());
+    common.extend(crc.to_le_bytes());
+    common.extend(size.to_le_bytes());
+    common.extend(size.to_le_bytes());
+    common.extend((name.len() as u16).to_le_bytes());
+
+    let mut archive = vec![];
+    archive.extend(0x0403_4b50u32.to_le_bytes());
+    archive.extend(&common);
+    archive.extend(0u16.to_le_bytes());
+    archive.extend(name);
+    archive.extend(data);
+
+    let central_at = archive.len() as u32;
+    archive.extend(0x0201_4b50u32.to_le_bytes());
+    archive.extend(20u16.to_le_bytes());
+    archive.extend(&common);
+    // Extra and comment lengths, disk, attributes and local header offset.
+    archive.extend([0u8; 16]);
+    archive.extend(name);
+    let central_size = archive.len() as u32 - central_at;
+
+    archive.extend(0x0605_4b50u32.to_le_bytes());
+    archive.extend(0u32.to_le_bytes());
+    archive.extend(1u16.to_le_bytes());
+    archive.extend(1u16.to_le_bytes());
+    archive.extend(central_size.to_le_bytes());
+    archive.extend(central_at.to_le_bytes());
+    archive.extend(0u16.to_le_bytes());
+    return out.write_all(&archive);
+}
+
+/// CRC-32 (IEEE) of `data`, as used by zip.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    return !crc;
+}
@@ -0,0 +1,78 @@
+pub mod allocation;
+pub mod attrs;
+pub mod batch;
+pub mod block;
+pub mod change;
+pub mod checkpoint;
+pub mod chunk_iter;
+pub mod chunking;
+pub mod colors;
+#[cfg(feature = "gdal")]
+pub mod compare;
+pub mod compound;
+pub mod compression;
+pub mod converter;
+pub mod dim_scales;
+pub mod error;
+pub mod extract;
+#[cfg(feature = "capi")]
+pub mod ffi;
+#[cfg(feature = "gdal")]
+pub mod gdal_config;
+pub mod geolocation;
+#[cfg(feature = "pure-tiff")]
+pub mod geotiff;
+pub mod grid;
+pub mod h5_cache;
+pub mod hdf;
+pub mod history;
+#[cfg(feature = "gdal")]
+pub mod import;
+pub mod interrupt;
+pub mod inventory;
+pub mod kernel;
+pub mod layers;
+pub mod lock;
+pub mod meta;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod nan_policy;
+pub mod narrowing;
+#[cfg(feature = "netcdf-sink")]
+pub mod netcdf_sink;
+pub mod notify;
+pub mod overrides;
+pub mod policy;
+pub mod preview;
+pub mod provenance;
+pub mod quicklook;
+pub mod raster;
+pub mod repack;
+pub mod report;
+pub mod retry;
+pub mod reverse;
+pub mod row_order;
+pub mod shards;
+pub mod stac;
+pub mod stats;
+pub mod status;
+pub mod strings;
+pub mod summary;
+pub mod swmr;
+pub mod templates;
+pub mod tiles;
+pub mod timeseries;
+pub mod timing;
+pub mod typed;
+pub mod units;
+pub mod update;
+pub mod valid_range;
+pub mod vds;
+pub mod verify;
+pub mod warnings;
+#[cfg(feature = "wasm")]
+pub mod wasm_kernel;
+pub mod watch;
+pub mod xyz;
+#[cfg(feature = "zarr-sink")]
+pub mod zarr_sink;
@@ -0,0 +1,115 @@
+//! Advisory lock of the HDF5 file a run writes its `_rev`, `mean_rev` and
+//! `sd_rev` datasets into (the converted file, or `--scratch`), since two
+//! runs writing at once would corrupt it.
+//!
+//! The lock is a `<file>.lock` sidecar created exclusively, holding the PID
+//! and host of the run, and removed when the run ends. A lock left by a run
+//! that was killed stays until a run is started with `--force`.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Owner of a lock, as written in its file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub host: String,
+    /// Seconds since the Unix epoch when the lock was taken.
+    pub since: u64,
+}
+
+impl LockOwner {
+    fn current() -> LockOwner {
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        return LockOwner {
+            pid: std::process::id(),
+            host: host_name(),
+            since,
+        };
+    }
+
+    /// Whether the owner is known to have exited: a process of this host
+    /// that `/proc` does not list.
+    fn is_stale(&self) -> bool {
+        let proc = Path::new("/proc");
+        return self.host == host_name()
+            && proc.is_dir()
+            && !proc.join(self.pid.to_string()).exists();
+    }
+}
+
+fn host_name() -> String {
+    if let Ok(host) = std::env::var("HOSTNAME") {
+        if !host.is_empty() {
+            return host;
+        }
+    }
+    return std::fs::read_to_string("/etc/hostname")
+        .map(|host| host.trim().to_owned())
+        .ok()
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned());
+}
+
+/// A lock held until dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Locks `h5_path` for this run. Fails if another run holds the lock,
+    /// unless `force` replaces it.
+    pub fn acquire(h5_path: &str, force: bool) -> Result<FileLock> {
+        let path = PathBuf::from(format!("{}.lock", h5_path));
+        if force && path.exists() {
+            eprintln!("Replacing the lock {} (--force)", path.display());
+            std::fs::remove_file(&path)?;
+        }
+        let mut file = match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Err(locked_error(h5_path, &path));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let lock = FileLock { path };
+        file.write_all(&serde_json::to_vec_pretty(&LockOwner::current())?)?;
+        return Ok(lock);
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn locked_error(h5_path: &str, path: &Path) -> Error {
+    let owner = std::fs::read(path)
+        .ok()
+        .and_then(|text| serde_json::from_slice::<LockOwner>(&text).ok());
+    let held_by = match owner {
+        Some(owner) if owner.is_stale() => format!(
+            "by process {} on {}, which is no longer running",
+            owner.pid, owner.host
+        ),
+        Some(owner) => format!("by process {} on {}", owner.pid, owner.host),
+        None => "by another run".to_owned(),
+    };
+    return Error::Invalid(format!(
+        "{} is locked {} ({}); rerun with --force if no other run is using it",
+        h5_path,
+        held_by,
+        path.display()
+    ));
+}
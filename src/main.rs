@@ -1,327 +1,1345 @@
-use ndarray::{s, Array, SliceInfo};
-use std::iter::FromIterator;
-use std::{io, io::Write};
-
-pub trait HasMembers {
-    fn get_members(&self) -> Result<Vec<String>, hdf5::Error>;
-    fn get_group(&self, name: &str) -> Result<hdf5::Group, hdf5::Error>;
-    fn is_group(&self, name: &str) -> bool;
-}
+use rayon::prelude::*;
+use read_hdf_as_raster::batch::{self, Manifest};
+use read_hdf_as_raster::checkpoint::Checkpoint;
+use read_hdf_as_raster::converter::{ConvertOptions, HdfRasterConverter};
+use read_hdf_as_raster::error::{exit_code, Error, Result};
+use read_hdf_as_raster::extract;
+use read_hdf_as_raster::geolocation;
+use read_hdf_as_raster::hdf::{
+    flatten_path, join_path, ls_hdf5_with, stat_groups, H5NodeType, ValueClass,
+};
+use read_hdf_as_raster::history;
+#[cfg(feature = "gdal")]
+use read_hdf_as_raster::import;
+use read_hdf_as_raster::interrupt;
+use read_hdf_as_raster::inventory;
+use read_hdf_as_raster::layers::{self, Layer};
+use read_hdf_as_raster::lock::FileLock;
+use read_hdf_as_raster::meta;
+use read_hdf_as_raster::nan_policy;
+use read_hdf_as_raster::narrowing;
+use read_hdf_as_raster::notify;
+use read_hdf_as_raster::overrides;
+use read_hdf_as_raster::preview;
+use read_hdf_as_raster::provenance::{self, Provenance};
+use read_hdf_as_raster::raster;
+use read_hdf_as_raster::repack;
+use read_hdf_as_raster::report::{OutputStatus, Report};
+use read_hdf_as_raster::retry;
+use read_hdf_as_raster::stac;
+use read_hdf_as_raster::stats::{self, StatNames};
+use read_hdf_as_raster::status;
+use read_hdf_as_raster::strings;
+use read_hdf_as_raster::summary;
+use read_hdf_as_raster::tiles;
+use read_hdf_as_raster::timing;
+use read_hdf_as_raster::update;
+use read_hdf_as_raster::valid_range;
+use read_hdf_as_raster::warnings::{self, Warning};
+#[cfg(feature = "wasm")]
+use read_hdf_as_raster::wasm_kernel::WasmKernel;
+use read_hdf_as_raster::watch::Watch;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-#[derive(Debug)]
-enum H5NodeType {
-    Dataset(String),
-    Group(String),
-}
+mod cli;
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "tui")]
+mod tui;
 
-impl HasMembers for hdf5::File {
-    fn get_members(&self) -> Result<Vec<String>, hdf5::Error> {
-        return self.member_names();
+fn main() {
+    let (command, globals) = match cli::Command::from_args() {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            std::process::exit(exit_code::USAGE);
+        }
+    };
+    if let Some(outdir) = &globals.outdir {
+        if let Err(err) = std::fs::create_dir_all(outdir) {
+            eprintln!("error: could not create {}: {}", outdir.display(), err);
+            std::process::exit(exit_code::IO);
+        }
     }
-
-    fn get_group(&self, name: &str) -> Result<hdf5::Group, hdf5::Error> {
-        return self.group(name);
+    raster::set_output_dir(globals.outdir.clone());
+    let input = globals.input.as_str();
+    let options = match command {
+        cli::Command::Convert(options) => options,
+        cli::Command::List(options) => {
+            if let Err(err) = run_list(input, &options) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        cli::Command::Extract(options) => {
+            if let Err(err) = run_extract(input, &options) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        #[cfg(feature = "gdal")]
+        cli::Command::Compare(options) => {
+            if let Err(err) = run_compare(input, &options) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        #[cfg(feature = "gdal")]
+        cli::Command::Import(options) => {
+            if let Err(err) = run_import(input, &options) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        cli::Command::Change(options) => {
+            if let Err(err) = run_change(input, &options) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        cli::Command::Tiles(options) => {
+            if let Err(err) = run_tiles(input, &options) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        cli::Command::Undo(options) => {
+            if let Err(err) = run_undo(input, &options) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        cli::Command::Watch(options) => {
+            let outdir = globals.outdir.clone().unwrap_or_else(|| PathBuf::from("."));
+            if let Err(err) = run_watch(&options, outdir) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        #[cfg(feature = "server")]
+        cli::Command::Serve(options) => {
+            if let Err(err) = run_serve(&options, globals.outdir.clone()) {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+            std::process::exit(exit_code::OK);
+        }
+        cli::Command::Batch(options) => match run_batch(&options) {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(err.exit_code());
+            }
+        },
+    };
+    #[cfg(feature = "tui")]
+    {
+        if options.tui {
+            match tui::run(input, &options) {
+                Ok(code) => std::process::exit(code),
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(err.exit_code());
+                }
+            }
+        }
     }
-
-    fn is_group(&self, name: &str) -> bool {
-        return self.link_exists(name);
+    if let Some(jobs) = options.jobs {
+        if let Err(err) = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+        {
+            warnings::record(
+                Warning::Environment,
+                format!("could not start {} worker threads: {}", jobs, err),
+            );
+        }
     }
-}
-
-impl HasMembers for hdf5::Group {
-    fn get_members(&self) -> Result<Vec<String>, hdf5::Error> {
-        return self.member_names();
+    if let Err(err) = interrupt::install_handler() {
+        warnings::record(
+            Warning::Environment,
+            format!("could not install Ctrl-C handler: {}", err),
+        );
     }
-
-    fn get_group(&self, name: &str) -> Result<hdf5::Group, hdf5::Error> {
-        return self.group(name);
+    if let Some(port) = options.status_port {
+        match status::serve(port) {
+            Ok(()) => println!("Serving status on http://127.0.0.1:{}/", port),
+            Err(err) => warnings::record(
+                Warning::Environment,
+                format!("could not serve status on port {}: {}", port, err),
+            ),
+        }
     }
 
-    fn is_group(&self, name: &str) -> bool {
-        return self.link_exists(name);
-    }
+    let report = convert(&options, input, &std::env::args().collect::<Vec<_>>());
+    std::process::exit(report.exit_code);
 }
 
-fn ls_hdf5(obj: &impl HasMembers, parent: String) -> Vec<H5NodeType> {
-    let mut result: Vec<H5NodeType> = vec![];
-    if let Ok(member_names) = obj.get_members() {
-        for member_name in member_names {
-            let new_parent = parent.clone() + "/" + member_name.as_str();
-            {
-                let _silence = hdf5::silence_errors();
-                if let Ok(group) = obj.get_group(member_name.as_str()) {
-                    result.push(H5NodeType::Group(new_parent.clone()));
-                    result.append(&mut ls_hdf5(&group, new_parent))
-                } else {
-                    result.push(H5NodeType::Dataset(new_parent));
+/// Converts `input` with `options` as the command line `command_line`
+/// would, from the settings of the run to its report, written and sent
+/// as asked. Returns the report.
+fn convert(options: &cli::Options, input: &str, command_line: &[String]) -> Report {
+    warnings::set_strict(options.strict);
+    summary::set_enabled(options.summary);
+    retry::set_policy(options.retries, options.retry_delay);
+    nan_policy::set_policy(options.nan_policy);
+    stats::set_single_obs_policy(options.single_obs_policy);
+    #[cfg(feature = "gdal")]
+    {
+        let jobs = options.jobs.unwrap_or_else(rayon::current_num_threads);
+        let (num_threads, cache_max) = options.gdal.resolve(jobs, options.memory_limit);
+        match options.gdal.apply(jobs, options.memory_limit) {
+            Ok(()) => println!(
+                "GDAL_NUM_THREADS={}, GDAL_CACHEMAX={}M",
+                num_threads,
+                cache_max >> 20
+            ),
+            Err(err) => warnings::record(
+                Warning::Environment,
+                format!("could not configure GDAL: {}", err),
+            ),
+        }
+    }
+
+    let checkpoint_path = std::path::PathBuf::from(format!("{}.checkpoint.json", input));
+    let loaded = match options.resume {
+        true => Checkpoint::load(&checkpoint_path),
+        false => Ok(Checkpoint::empty(&checkpoint_path)),
+    };
+    let mut report = Report::default();
+
+    // A checkpoint that cannot be read is left as it is for inspection.
+    let (result, checkpoint) = match loaded {
+        Ok(mut checkpoint) => (
+            run(options, input, command_line, &mut checkpoint, &mut report),
+            Some(checkpoint),
+        ),
+        Err(err) => (Err(err), None),
+    };
+    let exit_code = match &result {
+        Ok(()) => {
+            if let Some(checkpoint) = &checkpoint {
+                if let Err(err) = checkpoint.remove() {
+                    warnings::record(
+                        Warning::Environment,
+                        format!(
+                            "could not remove the checkpoint {}: {}",
+                            checkpoint.path().display(),
+                            err
+                        ),
+                    );
+                }
+            }
+            exit_code::OK
+        }
+        Err(Error::Interrupted) => {
+            report.interrupted = true;
+            if let Some(checkpoint) = &checkpoint {
+                if save_checkpoint(checkpoint) {
+                    eprintln!(
+                        "Stopped. Completed steps were saved to {}; rerun with --resume to continue.",
+                        checkpoint.path().display()
+                    );
                 }
             }
+            exit_code::INTERRUPTED
+        }
+        Err(err) => {
+            if let Some(checkpoint) = &checkpoint {
+                save_checkpoint(checkpoint);
+            }
+            status::add_error(err.to_string());
+            eprintln!("error: {}", err);
+            err.exit_code()
+        }
+    };
+    // Derived datasets written into the input itself are recorded in it,
+    // whether or not the run completed.
+    if options.scratch.is_none() && checkpoint.is_some() {
+        if let Err(err) = history::append_to(input, command_line) {
+            warnings::record(
+                Warning::Environment,
+                format!(
+                    "could not append to the processing history of {}: {}",
+                    input, err
+                ),
+            );
+        }
+    }
+    if options.repack && result.is_ok() {
+        let written_path = options.scratch.as_deref().unwrap_or(input);
+        if let Err(err) = run_repack(written_path, options) {
+            warnings::record(
+                Warning::Environment,
+                format!("could not repack {}: {}", written_path, err),
+            );
+        }
+    }
+    report.warnings = warnings::take();
+    report.summaries = summary::take();
+    report.out_of_range = valid_range::take();
+    report.clipped = narrowing::take();
+    report.non_finite = nan_policy::take();
+    // Times differ between runs; deterministic reports leave them out.
+    report.timings = match options.deterministic {
+        true => vec![],
+        false => timing::take(),
+    };
+    report.provenance = provenance::get();
+    report.exit_code = exit_code;
+    if let Some(report_path) = &options.report {
+        if let Err(err) = report.write(report_path) {
+            eprintln!(
+                "error: could not write the report to {}: {}",
+                report_path.display(),
+                err
+            );
+            // A run that failed otherwise keeps its own exit code.
+            if report.exit_code == exit_code::OK {
+                report.exit_code = err.exit_code();
+            }
         }
     }
-    return result;
+    notify(options, &report);
+    return report;
 }
 
-fn rev_array<T: Clone>(
-    input: ndarray::Array<T, ndarray::Dim<[usize; 1]>>,
-    nrows: usize,
-    ncols: usize,
-) -> ndarray::Array<T, ndarray::Dim<[usize; 1]>> {
-    let input = input.into_shape((nrows, ncols)).unwrap();
-    let input = input.slice(s![..;-1, ..]);
-    let input = Array::from_iter(input.iter().cloned());
-    return input;
+/// Saves `checkpoint`, recording a warning instead of failing the run if it
+/// cannot be. Returns whether it was saved.
+fn save_checkpoint(checkpoint: &Checkpoint) -> bool {
+    return match checkpoint.save() {
+        Ok(()) => true,
+        Err(err) => {
+            warnings::record(
+                Warning::Environment,
+                format!(
+                    "could not save the checkpoint {}: {}",
+                    checkpoint.path().display(),
+                    err
+                ),
+            );
+            false
+        }
+    };
 }
 
-fn reverse_ds_rows<T: hdf5::H5Type + Clone>(
-    file: &hdf5::File,
-    base_ds: String,
-    xsize: usize,
-    ysize: usize,
-) {
-    if base_ds.ends_with("_rev") {
+/// Sends `report` to the webhook and command of `options`, if any. Failures
+/// are printed; they do not change the outcome of the run.
+fn notify(options: &cli::Options, report: &Report) {
+    if options.notify_url.is_none() && options.on_complete.is_none() {
         return;
     }
-    let ds_name_rev = base_ds.clone() + "_rev";
-    if file.link_exists(&ds_name_rev) {
-        return;
+    let json = match report.to_json() {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("warning: could not serialize the report to notify: {}", err);
+            return;
+        }
+    };
+    if let Some(url) = &options.notify_url {
+        match notify::post(url, &json) {
+            Ok(code) if (200..300).contains(&code) => {}
+            Ok(code) => eprintln!("warning: webhook answered HTTP {}", code),
+            Err(err) => eprintln!("warning: could not post the report to the webhook: {}", err),
+        }
+    }
+    if let Some(command) = &options.on_complete {
+        match notify::run_command(command, &json, report.exit_code) {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("warning: --on-complete command failed ({})", status),
+            Err(err) => eprintln!("warning: could not run the --on-complete command: {}", err),
+        }
     }
-    let ds: hdf5::Dataset = file.dataset(&base_ds).unwrap();
-    let ds_out = create_dataset::<T>(&file, &ds_name_rev, ds.size());
+}
 
-    let half_lines = (ysize as f32 / 2f32).ceil() as usize;
-    let n_lines_read = 100usize;
+fn run(
+    options: &cli::Options,
+    input: &str,
+    command_line: &[String],
+    checkpoint: &mut Checkpoint,
+    report: &mut Report,
+) -> Result<()> {
+    // The file written to is locked: the scratch file if any.
+    let written_path = options.scratch.as_deref().unwrap_or(input);
+    let _lock = FileLock::acquire(written_path, options.force)?;
+    #[allow(unused_mut)]
+    let mut convert_options = options.convert_options();
+    let updated = add_updates(options, input, &convert_options)?;
+    #[cfg(feature = "wasm")]
+    for path in &options.wasm_kernels {
+        convert_options.register_kernel(WasmKernel::load(path)?);
+    }
+    let mut converter =
+        HdfRasterConverter::open(input, "base_float.tif", "base_byte.tif", convert_options)?;
+    if options.provenance || options.deterministic {
+        // The base templates are optional with 2-d datasets.
+        let mut inputs: Vec<&str> = vec!["base_float.tif", "base_byte.tif"]
+            .into_iter()
+            .filter(|path| Path::new(path).exists())
+            .collect();
+        inputs.extend(
+            options
+                .templates
+                .iter()
+                .map(|template| template.path.as_str()),
+        );
+        #[cfg(feature = "wasm")]
+        inputs.extend(options.wasm_kernels.iter().map(String::as_str));
+        let stat_names = converter.options.stat_names.all();
+        let mut derived = vec![];
+        for kernel in converter.options.kernels.iter() {
+            derived.extend(kernel.outputs());
+        }
+        provenance::set(Provenance::new(
+            command_line.to_vec(),
+            input,
+            &inputs,
+            stat_names.iter().map(|&name| name.to_owned()).collect(),
+            derived,
+        )?);
+    }
+    update::remove_derived(converter.derived_file(), &updated)?;
+    let file = converter.file();
+    let hdf5_nodes: Vec<H5NodeType> = converter.list();
 
-    for yy in (0..half_lines).step_by(n_lines_read) {
-        let perc = 100f32 * yy as f32 / (half_lines) as f32;
-        print!("\r{:.2}%", perc);
-        io::stdout().flush().unwrap();
+    let mut non_numeric = vec![];
+    for node in &hdf5_nodes {
+        if let H5NodeType::Dataset(ds_path) = node {
+            let class = meta::get(file, ds_path)?.value_class();
+            if class == ValueClass::Text || class == ValueClass::VarLen {
+                warnings::record(
+                    Warning::Skipped,
+                    format!("skipping non-numeric dataset {} ({:?})", ds_path, class),
+                );
+                non_numeric.push((ds_path.clone(), class));
+            }
+        }
+    }
+    if let Some(sidecar_path) = &options.strings_csv {
+        strings::write_sidecar(file, &non_numeric, sidecar_path)?;
+        println!(
+            "Wrote {} non-numeric datasets to {}",
+            non_numeric.len(),
+            sidecar_path.display()
+        );
+    }
+
+    let mut groups = select_groups(file, &hdf5_nodes, &options.stat_names, &options.groups);
+    groups.retain(|group_name| {
+        let skip = overrides::skips(&options.overrides, group_name);
+        if skip {
+            println!("Skipping group {}: the config skips it", group_name);
+        }
+        return !skip;
+    });
 
-        let mut lines_to_read = n_lines_read;
+    let mut stems: HashMap<String, &str> = HashMap::new();
+    for group_name in &groups {
+        if let Some(other) = stems.insert(flatten_path(group_name), *group_name) {
+            return Err(Error::Invalid(format!(
+                "groups {} and {} would write to the same output files",
+                other, group_name
+            )));
+        }
+    }
+    converter.prepare_group_grids(&groups)?;
+    if let Some(step) = options.preview {
+        return write_previews(options, &converter, &groups, step, report);
+    }
 
-        if (yy + n_lines_read) > half_lines {
-            lines_to_read = half_lines - yy;
+    let mut datasets: Vec<(String, bool)> = vec![];
+    for group_name in &groups {
+        // The statistic datasets and the inputs of the kernels.
+        for name in converter.options.group_datasets().iter() {
+            let is_count = *name == options.stat_names.count;
+            datasets.push((join_path(group_name, name), is_count));
         }
+    }
 
-        let rev_yy = ysize - yy - 1 - lines_to_read;
-        let lower_bound = yy * xsize;
-        let upper_bound = yy * xsize + lines_to_read * xsize;
-        let rev_lower_bound = rev_yy * xsize;
-        let rev_upper_bound = rev_yy * xsize + lines_to_read * xsize;
-        let slice_or_info = s![lower_bound..upper_bound];
-        let slice = SliceInfo::new(slice_or_info).unwrap();
-        let rev_slice_or_info = s![rev_lower_bound..rev_upper_bound];
-        let rev_slice = SliceInfo::new(rev_slice_or_info).unwrap();
+    let total_datasets = datasets.len();
+    let mut counter = 0;
 
-        let vals = ds.read_slice_1d::<T, _>(&slice).unwrap();
-        let vals_final = rev_array(vals, lines_to_read, xsize);
+    // Reversing reads every dataset and writes it back about as large.
+    let mut expected_bytes = Vec::with_capacity(total_datasets);
+    for (ds_name, _) in &datasets {
+        expected_bytes.push(2 * meta::get(converter.file(), ds_name)?.bytes());
+    }
+    status::expect_run_bytes(expected_bytes.iter().sum());
 
-        let rev_vals = ds.read_slice_1d::<T, _>(&rev_slice).unwrap().clone();
-        let rev_vals_final = rev_array(rev_vals, lines_to_read, xsize);
+    println!("Inverting datasets rows!");
+    for ((ds_name, is_count), bytes) in datasets.iter().zip(expected_bytes) {
+        counter += 1;
+        println!(
+            "Processing dataset: {} ({} of {})",
+            ds_name, counter, total_datasets
+        );
+        status::start_task("reverse", ds_name, counter, total_datasets);
+        status::expect_task_bytes(bytes);
+        converter.reverse_dataset(ds_name, *is_count)?;
+        status::complete_task();
+    }
+    status::expect_run_bytes(0);
+    converter.derived_file().flush()?;
 
-        ds_out
-            .write_slice(rev_vals_final.as_slice().unwrap(), &slice)
-            .unwrap();
-        ds_out
-            .write_slice(vals_final.as_slice().unwrap(), &rev_slice)
-            .unwrap();
+    let total_groups = groups.len();
+    if options.direct_stats {
+        println!("The means and sds are computed while writing the rasters");
+    } else {
+        for (ii, group_name) in groups.iter().enumerate() {
+            println!(
+                "Computing mean and sd of group: {} ({} of {})",
+                group_name,
+                ii + 1,
+                total_groups
+            );
+            status::start_task("mean_sd", group_name, ii + 1, total_groups);
+            converter.compute_mean_sd(group_name)?;
+            status::complete_task();
+        }
+        println!("Finished!");
     }
-    println!("\r{:.2}%", 100f32);
-}
 
-fn create_dataset<T: hdf5::H5Type>(file: &hdf5::File, name: &str, size: usize) -> hdf5::Dataset {
-    let mut ds_builder = file.new_dataset::<T>();
-    ds_builder.gzip(1);
+    // The rasters of each group are independent files, so up to --jobs
+    // groups are written at once, each from its own thread and outputs.
+    // Deterministic runs write one group at a time, so the strip heights
+    // (and the chunks of formats that follow them) and the order of the
+    // report do not depend on the number of threads.
+    let group_jobs = match converter.options.format.writes_in_parallel() {
+        _ if options.deterministic => 1,
+        true => rayon::current_num_threads().min(total_groups).max(1),
+        false => 1,
+    };
+    converter.options.group_jobs = group_jobs;
+    if group_jobs > 1 {
+        println!("Writing the rasters of {} groups at a time", group_jobs);
+    }
+    let state = Mutex::new((&mut *checkpoint, &mut *report));
+    let write = |(ii, group_name): (usize, &&str)| {
+        return write_group(
+            options,
+            &converter,
+            group_name,
+            (ii + 1, total_groups),
+            &state,
+        );
+    };
+    if group_jobs > 1 {
+        groups.par_iter().enumerate().try_for_each(write)?;
+    } else {
+        groups.iter().enumerate().try_for_each(write)?;
+    }
+    drop(state);
 
-    let ds_out = ds_builder.create(name, size).unwrap();
-    return ds_out;
-}
+    #[cfg(feature = "gdal")]
+    {
+        if let Some(stack_path) = &options.stack {
+            write_stack(options, &converter, &groups, stack_path, checkpoint, report)?;
+        }
+    }
 
-fn calc_mean_sd(file: &hdf5::File, group_name: &str, chunk_size: usize) {
-    let sum_path = String::from("/") + group_name + "/sum_rev";
-    let sumsq_path = String::from("/") + group_name + "/sumsq_rev";
-    let count_path = String::from("/") + group_name + "/count_rev";
-    let mean_path_out = String::from("/") + group_name + "/mean_rev";
-    let sd_path_out = String::from("/") + group_name + "/sd_rev";
+    let total_datasets = options.datasets.len();
+    let mut failed_datasets = 0;
+    for (ii, ds_path) in options.datasets.iter().enumerate() {
+        let ds_path = format!("/{}", ds_path.trim_start_matches('/'));
+        let step = format!("dataset:{}", ds_path);
+        if overrides::skips(&options.overrides, &ds_path) {
+            println!("Skipping dataset {}: the config skips it", ds_path);
+            continue;
+        }
+        if checkpoint.is_done(&step) {
+            println!("{} already written by a previous run, skipping.", ds_path);
+            continue;
+        }
+        let out_path = raster::dataset_output_path(&ds_path, options.format.extension());
+        println!(
+            "Converting dataset: {} ({} of {})",
+            ds_path,
+            ii + 1,
+            total_datasets
+        );
+        status::start_task("dataset", &ds_path, ii + 1, total_datasets);
+        let mut created = vec![out_path.clone()];
+        let result = converter
+            .convert_dataset(&ds_path, &out_path)
+            .and_then(|()| converter.write_stac_items(&ds_path, &mut created))
+            .and_then(|()| converter.write_tiles(&mut created))
+            .and_then(|()| converter.write_geolocation(&ds_path, &mut created));
+        match result {
+            Ok(()) => {
+                for path in &created {
+                    report.add_output(path, OutputStatus::Complete);
+                }
+                layers::record(Layer::of_dataset(&ds_path, &out_path));
+                checkpoint.mark_done(step)?;
+            }
+            Err(err @ Error::Interrupted)
+            | Err(err @ Error::Strict(..))
+            | Err(err @ Error::AlreadyExists(_)) => return Err(err),
+            Err(err) => {
+                eprintln!("error: could not convert {}: {}", ds_path, err);
+                status::add_error(format!("{}: {}", ds_path, err));
+                report.add_output(&out_path, OutputStatus::Failed);
+                failed_datasets += 1;
+            }
+        }
+        status::complete_task();
+    }
 
-    if file.link_exists(&mean_path_out) {
-        return;
+    let total_series = options.time_series.len();
+    for (ii, ds_path) in options.time_series.iter().enumerate() {
+        let ds_path = format!("/{}", ds_path.trim_start_matches('/'));
+        let step = format!("time-series:{}", ds_path);
+        if overrides::skips(&options.overrides, &ds_path) {
+            println!("Skipping time series {}: the config skips it", ds_path);
+            continue;
+        }
+        if checkpoint.is_done(&step) {
+            println!("{} already written by a previous run, skipping.", ds_path);
+            continue;
+        }
+        println!(
+            "Converting time series: {} ({} of {})",
+            ds_path,
+            ii + 1,
+            total_series
+        );
+        status::start_task("time-series", &ds_path, ii + 1, total_series);
+        let mut created = vec![];
+        let result = converter
+            .convert_time_series(&ds_path, options.time_coord.as_deref(), &mut created)
+            .and_then(|()| converter.write_stac_items(&ds_path, &mut created))
+            .and_then(|()| converter.write_tiles(&mut created))
+            .and_then(|()| converter.write_geolocation(&ds_path, &mut created));
+        for path in &created {
+            report.add_output(path, OutputStatus::Complete);
+            if stac::is_raster(path) && !tiles::is_tile(path) && !geolocation::is_derived(path) {
+                layers::record(Layer::of_dataset(&ds_path, path));
+            }
+        }
+        match result {
+            Ok(()) => checkpoint.mark_done(step)?,
+            Err(err @ Error::Interrupted)
+            | Err(err @ Error::Strict(..))
+            | Err(err @ Error::AlreadyExists(_)) => return Err(err),
+            Err(err) => {
+                eprintln!("error: could not convert {}: {}", ds_path, err);
+                status::add_error(format!("{}: {}", ds_path, err));
+                failed_datasets += 1;
+            }
+        }
+        status::complete_task();
     }
-    let sum_ds: hdf5::Dataset = file.dataset(&sum_path).unwrap();
-    let sumsq_ds: hdf5::Dataset = file.dataset(&sumsq_path).unwrap();
-    let count_ds: hdf5::Dataset = file.dataset(&count_path).unwrap();
-    let max_size = sum_ds.size();
 
-    let mean_ds_out: hdf5::Dataset = create_dataset::<f32>(&file, &mean_path_out, max_size);
-    let sd_ds_out: hdf5::Dataset = create_dataset::<f32>(&file, &sd_path_out, max_size);
+    write_layer_links(options, report)?;
 
-    for ii in (0..max_size).step_by(chunk_size) {
-        let mut n_vals_read = chunk_size;
+    let mut failures = vec![];
+    if failed_datasets > 0 {
+        failures.push(format!(
+            "{} datasets could not be converted",
+            failed_datasets
+        ));
+    }
+    if !report.mismatches.is_empty() {
+        failures.push(format!(
+            "verification found {} mismatching pixels",
+            report.mismatches.len()
+        ));
+    }
+    if !failures.is_empty() {
+        return Err(Error::Partial(failures.join("; ")));
+    }
+    return Ok(());
+}
 
-        if (ii + n_vals_read) > max_size {
-            n_vals_read = max_size - ii;
+/// Adds the statistic datasets of the `--update` files to those of the
+/// selected groups, returning the groups updated.
+fn add_updates(
+    options: &cli::Options,
+    input: &str,
+    convert_options: &ConvertOptions,
+) -> Result<Vec<String>> {
+    if options.update.is_empty() {
+        return Ok(vec![]);
+    }
+    // The converted file is written too, so it is locked even when the run
+    // writes to a scratch file.
+    let _lock = match options.scratch {
+        Some(_) => Some(FileLock::acquire(input, options.force)?),
+        None => None,
+    };
+    let file = hdf5::File::open_rw(input)?;
+    let nodes = ls_hdf5_with(&file, "".to_owned(), &convert_options.traversal);
+    let groups = select_groups(&file, &nodes, &options.stat_names, &options.groups);
+    let mut updated: Vec<String> = vec![];
+    for increment_path in &options.update {
+        let increment = hdf5::File::open(increment_path)?;
+        let groups = update::add_increments(
+            &file,
+            &increment,
+            increment_path,
+            &groups,
+            &options.stat_names,
+            options.memory_limit,
+        )?;
+        println!("Updated {} groups from {}", groups.len(), increment_path);
+        for group in groups {
+            if !updated.contains(&group) {
+                updated.push(group);
+            }
         }
+    }
+    return Ok(updated);
+}
 
-        let slice = s![ii..(ii + n_vals_read)];
-        let the_slice = SliceInfo::new(slice).unwrap();
+/// Writes the rasters of the group `group_name`, task `index` of `total`,
+/// unless a previous run did, and verifies them if asked to. Outputs and
+/// completed steps go to the checkpoint and report in `state`, shared by
+/// the groups written at once.
+fn write_group(
+    options: &cli::Options,
+    converter: &HdfRasterConverter,
+    group_name: &str,
+    (index, total): (usize, usize),
+    state: &Mutex<(&mut Checkpoint, &mut Report)>,
+) -> Result<()> {
+    let step = format!("rasters:{}", group_name);
+    if state.lock().unwrap().0.is_done(&step) {
+        println!(
+            "Rasters of {} already written by a previous run, skipping.",
+            group_name
+        );
+        return Ok(());
+    }
 
-        let sum_vals = sum_ds.read_slice_1d::<f32, _>(&the_slice).unwrap();
-        let sumsq_vals = sumsq_ds.read_slice_1d::<f32, _>(&the_slice).unwrap();
-        let count_vals = count_ds.read_slice_1d::<u8, _>(&the_slice).unwrap();
-        
-        let mask = count_vals.mapv(|el| el == 0);
-        let count_vals = count_vals.mapv(|el| el as f32);
+    println!(
+        "Writing the rasters of group: {} ({} of {})",
+        group_name, index, total
+    );
+    status::start_task("rasters", group_name, index, total);
+    let mut created = vec![];
+    let result = converter
+        .write_group_rasters(group_name, &mut created)
+        .and_then(|()| converter.write_stac_items(group_name, &mut created))
+        .and_then(|()| converter.write_tiles(&mut created))
+        .and_then(|()| converter.write_geolocation(group_name, &mut created));
+    let mut guard = state.lock().unwrap();
+    let (checkpoint, report) = &mut *guard;
+    if let Err(err) = result {
+        for path in &created {
+            if options.keep_partial || raster::updated_in_place(path) {
+                report.add_output(path, OutputStatus::Incomplete);
+            } else {
+                let _ = raster::remove_output(path);
+                report.add_output(path, OutputStatus::Deleted);
+            }
+        }
+        return Err(err);
+    }
+    for path in &created {
+        report.add_output(path, OutputStatus::Complete);
+        if !stac::is_raster(path) || tiles::is_tile(path) || geolocation::is_derived(path) {
+            continue;
+        }
+        if let Some(stat) = raster::output_stat(group_name, path) {
+            layers::record(Layer {
+                statistic: stat.to_owned(),
+                group: group_name.to_owned(),
+                path: path.clone(),
+            });
+        }
+    }
+    checkpoint.mark_done(step)?;
+    drop(guard);
+    status::complete_task();
+    println!("Finished the rasters of {}!", group_name);
+
+    if let Some(verify_options) = &options.verify {
+        println!("Verifying the outputs of {}...", group_name);
+        let mismatches = converter.verify_group(group_name, verify_options)?;
+        for mismatch in mismatches.iter().take(10) {
+            warnings::record(
+                Warning::Verification,
+                format!(
+                    "{} differs at ({}, {}): expected {}, read {}",
+                    mismatch.path, mismatch.x, mismatch.y, mismatch.expected, mismatch.actual
+                ),
+            );
+        }
+        if mismatches.is_empty() {
+            println!("Outputs of {} match the HDF5 source.", group_name);
+        }
+        state.lock().unwrap().1.mismatches.extend(mismatches);
+    }
+    return Ok(());
+}
 
-        let mean = &sum_vals / &count_vals;
-        let variance =
-            (sumsq_vals - (sum_vals.mapv(|el| el.powi(2)) / &count_vals)) / (&count_vals - 1f32);
-        let mut sd = variance.mapv(|el| el.sqrt() );
+/// Writes the `--preview` of every group and `--dataset`, read every `step`
+/// pixels, instead of their outputs.
+#[cfg(feature = "gdal")]
+fn write_previews(
+    options: &cli::Options,
+    converter: &HdfRasterConverter,
+    groups: &[&str],
+    step: usize,
+    report: &mut Report,
+) -> Result<()> {
+    let sources = groups.iter().map(|group| (group.to_string(), true)).chain(
+        options
+            .datasets
+            .iter()
+            .map(|ds_path| (format!("/{}", ds_path.trim_start_matches('/')), false)),
+    );
+    for (source, is_group) in sources {
+        let out_path = preview::preview_path(&source);
+        println!(
+            "Writing the preview of {} every {} pixels to {}",
+            source, step, out_path
+        );
+        converter.write_preview(&source, is_group, step, &out_path)?;
+        report.add_output(&out_path, OutputStatus::Complete);
+    }
+    return Ok(());
+}
 
-        ndarray::Zip::from(&mut sd).and(&mask).apply(|x, &m| if m {
-            *x = -1f32;
-        });
+#[cfg(not(feature = "gdal"))]
+fn write_previews(
+    _options: &cli::Options,
+    _converter: &HdfRasterConverter,
+    _groups: &[&str],
+    _step: usize,
+    _report: &mut Report,
+) -> Result<()> {
+    return Err(Error::Invalid(
+        "--preview needs the gdal feature".to_owned(),
+    ));
+}
 
-        let _ = mean_ds_out.write_slice(&mean, &the_slice);
-        let _ = sd_ds_out.write_slice(&sd, &the_slice);
+/// Writes the `--stack` GeoTIFF of `groups`, unless a previous run did.
+#[cfg(feature = "gdal")]
+fn write_stack(
+    options: &cli::Options,
+    converter: &HdfRasterConverter,
+    groups: &[&str],
+    stack_path: &str,
+    checkpoint: &mut Checkpoint,
+    report: &mut Report,
+) -> Result<()> {
+    if groups.is_empty() {
+        warnings::record(
+            Warning::Skipped,
+            format!("no groups to stack into {}", stack_path),
+        );
+        return Ok(());
+    }
+    let step = format!("stack:{}", stack_path);
+    if checkpoint.is_done(&step) {
+        println!("Stack already written by a previous run, skipping.");
+        return Ok(());
     }
+    println!(
+        "Stacking the {} of {} groups into {}...",
+        options.stack_stat,
+        groups.len(),
+        stack_path
+    );
+    status::start_task("stack", stack_path, 1, 1);
+    let mut created = vec![];
+    let result = converter
+        .write_stack(groups, &options.stack_stat, stack_path, &mut created)
+        .and_then(|()| converter.write_stac_items("/", &mut created))
+        .and_then(|()| converter.write_tiles(&mut created))
+        .and_then(|()| converter.write_geolocation("/", &mut created));
+    if let Err(err) = result {
+        for path in &created {
+            if options.keep_partial || raster::updated_in_place(path) {
+                report.add_output(path, OutputStatus::Incomplete);
+            } else {
+                let _ = raster::remove_output(path);
+                report.add_output(path, OutputStatus::Deleted);
+            }
+        }
+        return Err(err);
+    }
+    for path in &created {
+        report.add_output(path, OutputStatus::Complete);
+    }
+    checkpoint.mark_done(step)?;
+    status::complete_task();
+    return Ok(());
 }
 
-fn main() {
-    let file: hdf5::file::File = hdf5::file::File::open_rw("cerrado_100.h5").unwrap();
-    let base_float_path = "base_float.tif";
-    let base_byte_path = "base_byte.tif";
-    let base_rast = gdal::Dataset::open(std::path::Path::new(base_byte_path)).unwrap();
-    let base_band = base_rast.rasterband(1).unwrap();
-    let xsize = base_band.x_size();
-    let ysize = base_band.y_size();
+/// Writes the `--vrt` and `--qgis-project` files linking the rasters
+/// written by the run.
+fn write_layer_links(options: &cli::Options, report: &mut Report) -> Result<()> {
+    let layers = layers::take();
+    if options.vrt.is_none() && options.qgis_project.is_none() {
+        return Ok(());
+    }
+    if layers.is_empty() {
+        warnings::record(
+            Warning::Skipped,
+            "no output rasters written by this run to link".to_owned(),
+        );
+        return Ok(());
+    }
+    if let Some(vrt_path) = &options.vrt {
+        #[cfg(feature = "gdal")]
+        {
+            println!("Linking {} outputs in {}...", layers.len(), vrt_path);
+            layers::write_vrt(vrt_path, &layers)?;
+            report.add_output(vrt_path, OutputStatus::Complete);
+        }
+        #[cfg(not(feature = "gdal"))]
+        warnings::record(
+            Warning::Skipped,
+            format!("{} not written: VRTs need the gdal feature", vrt_path),
+        );
+    }
+    if let Some(project_path) = &options.qgis_project {
+        println!("Linking {} outputs in {}...", layers.len(), project_path);
+        layers::write_qgis_project(project_path, &layers)?;
+        report.add_output(project_path, OutputStatus::Complete);
+    }
+    return Ok(());
+}
 
-    let hdf5_nodes: Vec<H5NodeType> = ls_hdf5(&file, "".to_owned());
+/// Statistic groups among `nodes`, restricted to `selected` if given.
+fn select_groups<'a>(
+    file: &hdf5::File,
+    nodes: &'a [H5NodeType],
+    stat_names: &StatNames,
+    selected: &Option<Vec<String>>,
+) -> Vec<&'a str> {
+    let (stat_groups, other_groups) = stat_groups(file, nodes, &stat_names.all());
+    for group_name in &other_groups {
+        println!(
+            "Skipping group {}: it does not contain numeric {}",
+            group_name,
+            stat_names.all().join(", ")
+        );
+    }
+    return match selected {
+        None => stat_groups,
+        Some(selected) => {
+            for name in selected {
+                if !stat_groups.iter().any(|group| group_matches(group, name)) {
+                    warnings::record(
+                        Warning::Skipped,
+                        format!("requested group {} has no statistic datasets", name),
+                    );
+                }
+            }
+            stat_groups
+                .into_iter()
+                .filter(|group| selected.iter().any(|name| group_matches(group, name)))
+                .collect()
+        }
+    };
+}
 
-    let datasets: Vec<&H5NodeType> = hdf5_nodes
-        .iter()
-        .filter(|&x| match x {
-            H5NodeType::Dataset(_) => true,
-            _ => false,
-        })
-        .collect();
+/// Runs the `list` subcommand: the datasets and statistic groups of the
+/// file, as text or as JSON on stdout.
+fn run_list(input: &str, options: &cli::ListOptions) -> Result<()> {
+    let file = hdf5::File::open(input)?;
+    let inventory = inventory::inventory(&file, &options.traversal(), &options.stat_names)?;
+    if options.json {
+        let stdout = std::io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), &inventory)?;
+        println!();
+        return Ok(());
+    }
+    println!("Statistic groups:");
+    for group in &inventory.groups {
+        println!("  {}", group.path);
+    }
+    println!("Datasets:");
+    for dataset in &inventory.datasets {
+        println!(
+            "  {} {} {:?} ({})",
+            dataset.path, dataset.data_type, dataset.shape, dataset.class
+        );
+    }
+    return Ok(());
+}
 
-    let groups: Vec<&H5NodeType> = hdf5_nodes
+/// Runs the `extract` subcommand: statistics of the selected groups at
+/// `--points`, as CSV.
+fn run_extract(input: &str, options: &cli::ExtractOptions) -> Result<()> {
+    let points = extract::read_points(&options.points)?;
+    let converter = HdfRasterConverter::open(
+        input,
+        "base_float.tif",
+        "base_byte.tif",
+        options.convert_options(),
+    )?;
+    let nodes = converter.list();
+    let groups = select_groups(
+        converter.file(),
+        &nodes,
+        &options.stat_names,
+        &options.groups,
+    );
+    let mut samples = vec![];
+    for group_name in &groups {
+        samples.extend(converter.extract_points(group_name, &points)?);
+    }
+    let outside = samples
         .iter()
-        .filter(|&x| match x {
-            H5NodeType::Group(_) => true,
-            _ => false,
-        })
-        .collect();
+        .filter(|sample| sample.pixel.is_none())
+        .count()
+        / groups.len().max(1);
+    if outside > 0 {
+        warnings::record(
+            Warning::Skipped,
+            format!(
+                "{} of {} points fall outside the grid",
+                outside,
+                points.len()
+            ),
+        );
+    }
+    match &options.out {
+        Some(out_path) => {
+            let mut out = std::io::BufWriter::new(std::fs::File::create(out_path)?);
+            extract::write_csv(&samples, &mut out)?;
+            eprintln!(
+                "Wrote {} values of {} points to {}",
+                samples.len(),
+                points.len(),
+                out_path.display()
+            );
+        }
+        None => extract::write_csv(&samples, &mut std::io::stdout().lock())?,
+    }
+    return Ok(());
+}
 
-    let total_datasets = datasets.len();
-    let mut counter = 0;
+/// Runs the `compare` subcommand: error metrics of a group statistic
+/// against a reference raster, and optionally their difference raster.
+#[cfg(feature = "gdal")]
+fn run_compare(input: &str, options: &cli::CompareOptions) -> Result<()> {
+    let converter = HdfRasterConverter::open(
+        input,
+        "base_float.tif",
+        "base_byte.tif",
+        options.convert_options(),
+    )?;
+    converter.check_stat_group(&options.group)?;
+    println!(
+        "Comparing the {} of {} with {}...",
+        options.stat, options.group, options.reference
+    );
+    let mut created = vec![];
+    let result = converter.compare_group(
+        &options.group,
+        &options.stat,
+        &options.reference,
+        options.diff.as_deref(),
+        &mut created,
+    );
+    let comparison = match result {
+        Ok(comparison) => comparison,
+        Err(err) => {
+            for path in &created {
+                let _ = raster::remove_output(path);
+            }
+            return Err(err);
+        }
+    };
+    println!(
+        "{} pixels compared, {} skipped: bias {}, RMSE {}, MAE {}, max |diff| {}",
+        comparison.compared,
+        comparison.skipped,
+        comparison.bias,
+        comparison.rmse,
+        comparison.mae,
+        comparison.max_abs_diff
+    );
+    if let Some(report_path) = &options.report {
+        let file = std::fs::File::create(report_path)?;
+        serde_json::to_writer_pretty(file, &comparison)?;
+    }
+    return Ok(());
+}
 
-    println!("Inverting datasets rows!");
-    datasets.iter().for_each(|ds| {
-        if let H5NodeType::Dataset(ds_name) = ds {
-            counter += 1;
-            println!("Processing dataset: {} ({} of {})", ds_name, counter, total_datasets);
-            if ds_name.ends_with("count") {
-                reverse_ds_rows::<u8>(&file, ds_name.to_string(), xsize, ysize);
-            } else {
-                reverse_ds_rows::<f32>(&file, ds_name.to_string(), xsize, ysize);
+/// Runs the `change` subcommand: the difference, relative change and
+/// significance mask of the means of two groups.
+fn run_change(input: &str, options: &cli::ChangeOptions) -> Result<()> {
+    let converter = HdfRasterConverter::open(
+        input,
+        "base_float.tif",
+        "base_byte.tif",
+        options.convert_options(),
+    )?;
+    let other = match &options.other {
+        Some(path) => Some(hdf5::File::open(path)?),
+        None => None,
+    };
+    let prefix = match &options.out {
+        Some(prefix) => prefix.clone(),
+        None => format!(
+            "change_{}_{}",
+            flatten_path(&options.group_a),
+            flatten_path(&options.group_b)
+        ),
+    };
+    println!(
+        "Detecting change from {} to {}{}...",
+        options.group_a,
+        options.group_b,
+        match &options.other {
+            Some(path) => format!(" of {}", path),
+            None => String::new(),
+        }
+    );
+    let mut created = vec![];
+    let result = converter.change_groups(
+        &options.group_a,
+        other.as_ref(),
+        &options.group_b,
+        options.alpha,
+        &prefix,
+        &mut created,
+    );
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(err) => {
+            for path in &created {
+                let _ = raster::remove_output(path);
             }
+            return Err(err);
+        }
+    };
+    println!(
+        "{} pixels compared, mean change {}; {} of {} tested changed significantly \
+         ({} increased, {} decreased)",
+        summary.compared,
+        summary.mean_diff,
+        summary.increased + summary.decreased,
+        summary.tested,
+        summary.increased,
+        summary.decreased
+    );
+    if let Some(report_path) = &options.report {
+        let file = std::fs::File::create(report_path)?;
+        serde_json::to_writer_pretty(file, &summary)?;
+    }
+    return Ok(());
+}
+
+/// Runs the `tiles` subcommand: an XYZ PNG tile pyramid of a group
+/// statistic.
+fn run_tiles(input: &str, options: &cli::TilesOptions) -> Result<()> {
+    let converter = HdfRasterConverter::open(
+        input,
+        "base_float.tif",
+        "base_byte.tif",
+        options.convert_options(),
+    )?;
+    converter.check_stat_group(&options.group)?;
+    println!(
+        "Rendering the {} of {} as tiles in {}...",
+        options.stat,
+        options.group,
+        options.out.display()
+    );
+    let n_tiles =
+        converter.render_xyz(&options.group, &options.stat, &options.xyz, &options.out)?;
+    println!("Wrote {} tiles to {}", n_tiles, options.out.display());
+    return Ok(());
+}
+
+/// Runs the `import` subcommand: a raster band written into the HDF5 file
+/// as a dataset.
+#[cfg(feature = "gdal")]
+fn run_import(input: &str, options: &cli::ImportOptions) -> Result<()> {
+    options.import.compression.check()?;
+    let _lock = FileLock::acquire(input, options.force)?;
+    let file = hdf5::File::open_rw(input)?;
+    if import::import_raster(&file, &options.raster, &options.dataset, &options.import)? {
+        println!("Imported {} into {}", options.raster, options.dataset);
+        if let Err(err) = history::append(&file, &std::env::args().collect::<Vec<_>>()) {
+            eprintln!(
+                "warning: could not append to the processing history: {}",
+                err
+            );
+        }
+    }
+    return Ok(());
+}
+
+/// Runs the `undo` subcommand: the datasets recorded in the processing
+/// history of the file removed, and the file repacked.
+fn run_undo(input: &str, options: &cli::UndoOptions) -> Result<()> {
+    let _lock = FileLock::acquire(input, options.force)?;
+    let removed = {
+        let file = match options.dry_run {
+            true => hdf5::File::open(input)?,
+            false => hdf5::File::open_rw(input)?,
         };
-    });
+        history::undo(&file, options.dry_run)?
+    };
+    let verb = match options.dry_run {
+        true => "Would remove",
+        false => "Removed",
+    };
+    for path in &removed {
+        println!("{} {}", verb, path);
+    }
+    println!("{} {} datasets from {}", verb, removed.len(), input);
+    if options.repack && !options.dry_run {
+        print_repacked(
+            input,
+            &repack::repack(input, options.repack_compression().as_ref())?,
+        );
+    }
+    return Ok(());
+}
 
-    let chunk_size = 1000000;
-    let total_groups = groups.len();
-    let mut counter = 0;
-    groups.iter().for_each(|group| {
-        counter += 1;
-        if let H5NodeType::Group(group_name) = group {
-            println!("Processing group: {} ({} of {})", group_name, counter, total_groups);
-            println!("Computing mean and sd...");
-            calc_mean_sd(&file, group_name, chunk_size);
-            println!("Finished!");
-            let group_name = group_name.replace("/", "");
-            
-            let ds_count_path = format!("/{}/count_rev", group_name);
-            let ds_mean_path = format!("/{}/mean_rev", group_name);
-            let ds_sd_path = format!("/{}/sd_rev", group_name);
-
-            let ds_count = file.dataset(&ds_count_path).unwrap();
-            let ds_mean = file.dataset(&ds_mean_path).unwrap();
-            let ds_sd = file.dataset(&ds_sd_path).unwrap();
-
-            // let the_slice = s![(1219+1088*2137)..(1230+1088*2137)];
-            // let the_slice_info = SliceInfo::new(the_slice).unwrap();
-            let out_mean_path = format!("{}_cerrado_{}_{}.tif", 100, group_name, "mean");
-            std::fs::copy(base_float_path, &out_mean_path).unwrap();
-            let rast_mean = gdal::Dataset::open_ex(
-                std::path::Path::new(&out_mean_path),
-                Some(gdal_sys::GDALAccess::GA_Update),
-                None,
-                None,
-                None,
-            )
-            .unwrap();
-            let band_mean = rast_mean.rasterband(1).unwrap();
-
-            let out_sd_path = format!("{}_cerrado_{}_{}.tif", 100, group_name, "sd");
-            std::fs::copy(base_float_path, &out_sd_path).unwrap();
-            let rast_sd = gdal::Dataset::open_ex(
-                std::path::Path::new(&out_sd_path),
-                Some(gdal_sys::GDALAccess::GA_Update),
-                None,
-                None,
-                None,
-            )
-            .unwrap();
-            let band_sd = rast_sd.rasterband(1).unwrap();
-
-            let out_count_path = format!("{}_cerrado_{}_{}.tif", 100, group_name, "count");
-            std::fs::copy(base_byte_path, &out_count_path).unwrap();
-            let rast_count = gdal::Dataset::open_ex(
-                std::path::Path::new(&out_count_path),
-                Some(gdal_sys::GDALAccess::GA_Update),
-                None,
-                None,
-                None,
-            )
-            .unwrap();
-            let band_count = rast_count.rasterband(1).unwrap();
-
-            let n_lines_read = 100;
-            println!("Reading HDF and writing to rasters...");
-            for yy in (0..ysize).step_by(n_lines_read) {
-                let perc = 100f32 * yy as f32 / ysize as f32;
-                if perc.round() as u32 % 2 == 0 {
-                    print!("\r{:.2}%", perc);
-                    io::stdout().flush().unwrap();
-                }
-                let mut lines_to_read = n_lines_read;
-                if (yy + n_lines_read) > ysize {
-                    lines_to_read = ysize - yy;
-                }
+/// Runs the jobs of the `batch` manifest with this executable, returning
+/// the exit code of the batch.
+fn run_batch(options: &cli::BatchOptions) -> Result<i32> {
+    let manifest = Manifest::read(&options.manifest)?;
+    if let Err(err) = interrupt::install_handler() {
+        warnings::record(
+            Warning::Environment,
+            format!("could not install Ctrl-C handler: {}", err),
+        );
+    }
+    let exe = std::env::current_exe()?;
+    println!(
+        "Converting the {} jobs of {}, {} at a time",
+        manifest.jobs.len(),
+        options.manifest.display(),
+        options.jobs
+    );
+    let report = batch::run(&manifest, &exe, options.jobs, rayon::current_num_threads());
+    let failed: Vec<&batch::JobRecord> = report
+        .jobs
+        .iter()
+        .filter(|job| job.exit_code != exit_code::OK)
+        .collect();
+    for job in failed.iter() {
+        eprintln!(
+            "error: {} failed with exit code {}{}, see {}",
+            job.job.input,
+            job.exit_code,
+            job.error
+                .as_ref()
+                .map_or(String::new(), |err| format!(" ({})", err)),
+            job.log
+        );
+    }
+    println!(
+        "{} of {} jobs succeeded, {} not started",
+        report.jobs.len() - failed.len(),
+        manifest.jobs.len(),
+        report.not_started.len()
+    );
+    if let Some(report_path) = &options.report {
+        report.write(report_path)?;
+    }
+    return Ok(report.exit_code);
+}
 
-                let lower_bound = yy * xsize;
-                let upper_bound = yy * xsize + lines_to_read * xsize;
-                let slice_or_info = s![lower_bound..upper_bound];
-                let slice = SliceInfo::new(slice_or_info).unwrap();
-                let count = ds_count.read_slice::<u8, _, _>(&slice).unwrap();
-                let mean = ds_mean.read_slice::<f32, _, _>(&slice).unwrap();
-                let sd = ds_sd.read_slice::<f32, _, _>(&slice).unwrap();
+/// Converts the files arriving in the directory of `options` with this
+/// executable, to directories in `outdir`.
+fn run_watch(options: &cli::WatchOptions, outdir: PathBuf) -> Result<()> {
+    let manifest = match &options.manifest {
+        Some(path) => Manifest::read(path)?,
+        None => Manifest::default(),
+    };
+    if let Some(profile) = &options.profile {
+        if !manifest.profiles.contains_key(profile) {
+            return Err(Error::Invalid(format!(
+                "no profile {} in {}",
+                profile,
+                options.manifest.as_ref().unwrap().display()
+            )));
+        }
+    }
+    if let Err(err) = interrupt::install_handler() {
+        warnings::record(
+            Warning::Environment,
+            format!("could not install Ctrl-C handler: {}", err),
+        );
+    }
+    let watch = Watch {
+        dir: options.dir.clone(),
+        pattern: options.pattern.clone(),
+        outdir,
+        manifest,
+        profile: options.profile.clone(),
+        interval: options.interval,
+        once: options.once,
+        threads: rayon::current_num_threads(),
+    };
+    let (converted, failed) = watch.run(&std::env::current_exe()?)?;
+    println!("Converted {} files, {} failed", converted, failed);
+    return Ok(());
+}
 
-                let buffer_count =
-                    gdal::raster::Buffer::<u8>::new((xsize, lines_to_read), count.to_vec());
+/// Serves conversions over HTTP with the profiles of the manifest of
+/// `options`, if any, to `outdir` unless a job gives its own, until Ctrl-C.
+#[cfg(feature = "server")]
+fn run_serve(options: &cli::ServeOptions, outdir: Option<PathBuf>) -> Result<()> {
+    let manifest = match &options.manifest {
+        Some(path) => Manifest::read(path)?,
+        None => Manifest::default(),
+    };
+    if let Err(err) = interrupt::install_handler() {
+        warnings::record(
+            Warning::Environment,
+            format!("could not install Ctrl-C handler: {}", err),
+        );
+    }
+    let input_root = options
+        .input_root
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."));
+    let output_root = options
+        .output_root
+        .clone()
+        .or(outdir)
+        .unwrap_or_else(|| PathBuf::from("."));
+    return server::serve(
+        &options.bind,
+        options.port,
+        manifest,
+        input_root,
+        output_root,
+    );
+}
 
-                let buffer_mean =
-                    gdal::raster::Buffer::<f32>::new((xsize, lines_to_read), mean.to_vec());
+/// Repacks the HDF5 file written to by a completed conversion, locked again
+/// since the run released it.
+fn run_repack(path: &str, options: &cli::Options) -> Result<()> {
+    let _lock = FileLock::acquire(path, options.force)?;
+    let repacked = repack::repack(path, options.repack_compression().as_ref())?;
+    print_repacked(path, &repacked);
+    return Ok(());
+}
 
-                let buffer_sd =
-                    gdal::raster::Buffer::<f32>::new((xsize, lines_to_read), sd.to_vec());
+/// Prints the sizes of a repacked file.
+fn print_repacked(path: &str, repacked: &repack::Repacked) {
+    println!(
+        "Repacked {} from {} to {} bytes ({} datasets recompressed)",
+        path, repacked.before, repacked.after, repacked.recompressed
+    );
+}
 
-                band_count.write((0, yy as isize), (xsize, lines_to_read), &buffer_count).unwrap();
-                band_mean.write((0, yy as isize), (xsize, lines_to_read), &buffer_mean).unwrap();
-                band_sd.write((0, yy as isize), (xsize, lines_to_read), &buffer_sd).unwrap();
-            }
-            println!("\r{:.2}%", 100f32);
-            println!("Finished!");
-        }
-    });
+/// Whether the group path `group` (e.g. `/agbd`) was selected as `name`,
+/// given with or without the leading slash.
+fn group_matches(group: &str, name: &str) -> bool {
+    return group.trim_start_matches('/') == name.trim_start_matches('/');
 }
@@ -0,0 +1,126 @@
+//! Metadata of the datasets of the files converted, gathered while listing
+//! them (see [`crate::hdf::ls_hdf5_with`]) and reused by every stage.
+//!
+//! Discovery, the type checks and the reversal each looked up shapes and
+//! types by opening the dataset again; on files with thousands of datasets
+//! those opens dominate before any value is read. Entries are keyed by file
+//! name and path, and dropped when a dataset is (re)created through
+//! [`crate::hdf::create_dataset`].
+
+use crate::hdf::{self, ValueClass};
+use hdf5::types::TypeDescriptor;
+use hdf5_sys::h5d::{H5D_layout_t, H5Dget_create_plist};
+use hdf5_sys::h5i::H5Idec_ref;
+use hdf5_sys::h5p::{H5Pget_chunk, H5Pget_layout};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// What the stages need to know of a dataset without reading it.
+#[derive(Debug, Clone)]
+pub struct DatasetMeta {
+    pub shape: Vec<usize>,
+    pub dtype: TypeDescriptor,
+    /// Chunk dimensions, `None` for contiguous or compact datasets.
+    pub chunk: Option<Vec<usize>>,
+    pub attrs: Vec<String>,
+}
+
+impl DatasetMeta {
+    pub fn read(ds: &hdf5::Dataset) -> hdf5::Result<DatasetMeta> {
+        return Ok(DatasetMeta {
+            shape: ds.shape(),
+            dtype: ds.dtype()?.to_descriptor()?,
+            chunk: chunk_dims(ds),
+            attrs: ds.attr_names().unwrap_or_default(),
+        });
+    }
+
+    /// Number of elements.
+    pub fn size(&self) -> usize {
+        return self.shape.iter().product();
+    }
+
+    /// Bytes taken by the values once read.
+    pub fn bytes(&self) -> u64 {
+        return (self.size() * self.dtype.size()) as u64;
+    }
+
+    pub fn value_class(&self) -> ValueClass {
+        return hdf::class_of(&self.dtype);
+    }
+
+    /// See [`hdf::loses_precision`].
+    pub fn loses_precision<T: hdf5::H5Type>(&self) -> bool {
+        return hdf::narrows::<T>(&self.dtype);
+    }
+
+    pub fn has_attr(&self, name: &str) -> bool {
+        return self.attrs.iter().any(|attr| attr == name);
+    }
+}
+
+static CACHE: Mutex<Option<HashMap<(String, String), DatasetMeta>>> = Mutex::new(None);
+
+fn key(file_name: &str, path: &str) -> (String, String) {
+    return (
+        file_name.to_owned(),
+        format!("/{}", path.trim_start_matches('/')),
+    );
+}
+
+/// Keeps the metadata of `ds`, found at `path` of the file `file_name`.
+pub fn record(file_name: &str, path: &str, ds: &hdf5::Dataset) {
+    if let Ok(meta) = DatasetMeta::read(ds) {
+        if let Ok(mut cache) = CACHE.lock() {
+            cache
+                .get_or_insert_with(HashMap::new)
+                .insert(key(file_name, path), meta);
+        }
+    }
+}
+
+/// Metadata of the dataset at `path` of `file`, opening it only if it was
+/// not listed or looked up before.
+pub fn get(file: &hdf5::File, path: &str) -> hdf5::Result<DatasetMeta> {
+    let file_name = file.filename();
+    if let Ok(cache) = CACHE.lock() {
+        if let Some(meta) = cache.as_ref().and_then(|c| c.get(&key(&file_name, path))) {
+            return Ok(meta.clone());
+        }
+    }
+    let meta = DatasetMeta::read(&file.dataset(path)?)?;
+    if let Ok(mut cache) = CACHE.lock() {
+        cache
+            .get_or_insert_with(HashMap::new)
+            .insert(key(&file_name, path), meta.clone());
+    }
+    return Ok(meta);
+}
+
+/// Drops what is known of `path` in `file`, e.g. when it is recreated.
+pub fn forget(file: &hdf5::File, path: &str) {
+    if let Ok(mut cache) = CACHE.lock() {
+        if let Some(cache) = cache.as_mut() {
+            cache.remove(&key(&file.filename(), path));
+        }
+    }
+}
+
+fn chunk_dims(ds: &hdf5::Dataset) -> Option<Vec<usize>> {
+    let rank = ds.shape().len();
+    let _silence = hdf5::silence_errors();
+    return hdf5::sync::sync(|| unsafe {
+        let dcpl = H5Dget_create_plist(ds.id());
+        if dcpl < 0 {
+            return None;
+        }
+        let mut dims = vec![0u64; rank.max(1)];
+        let chunked = H5Pget_layout(dcpl) == H5D_layout_t::H5D_CHUNKED
+            && H5Pget_chunk(dcpl, dims.len() as i32, dims.as_mut_ptr()) >= 0;
+        H5Idec_ref(dcpl);
+        return match chunked {
+            true => Some(dims.iter().map(|&dim| dim as usize).collect()),
+            false => None,
+        };
+    });
+}
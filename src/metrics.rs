@@ -0,0 +1,71 @@
+//! Prometheus text exposition of the run status.
+
+use crate::status::Status;
+use std::fmt::Write;
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+pub fn render(status: &Status) -> String {
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "read_hdf_as_raster_bytes_processed_total",
+        "counter",
+        "Bytes read from HDF5 datasets and written to datasets and rasters.",
+        status.bytes_processed as f64,
+    );
+    metric(
+        &mut out,
+        "read_hdf_as_raster_rows_written_total",
+        "counter",
+        "Raster rows written.",
+        status.rows_written as f64,
+    );
+    metric(
+        &mut out,
+        "read_hdf_as_raster_tasks_completed_total",
+        "counter",
+        "Datasets reversed, groups summarized and groups rasterized.",
+        status.tasks_completed as f64,
+    );
+    metric(
+        &mut out,
+        "read_hdf_as_raster_errors_total",
+        "counter",
+        "Warnings and errors raised by the run.",
+        status.errors.len() as f64,
+    );
+    metric(
+        &mut out,
+        "read_hdf_as_raster_task_progress_ratio",
+        "gauge",
+        "Completed fraction of the current task.",
+        (status.percent / 100f32) as f64,
+    );
+    metric(
+        &mut out,
+        "read_hdf_as_raster_run_progress_ratio",
+        "gauge",
+        "Completed fraction of the datasets being reversed, by bytes.",
+        (status.total_percent / 100f32) as f64,
+    );
+    metric(
+        &mut out,
+        "read_hdf_as_raster_throughput_bytes_per_second",
+        "gauge",
+        "Average read and write throughput since the start of the run.",
+        status.throughput_mb_s * 1e6,
+    );
+    metric(
+        &mut out,
+        "read_hdf_as_raster_elapsed_seconds",
+        "gauge",
+        "Seconds since the start of the run.",
+        status.elapsed_s,
+    );
+    return out;
+}
@@ -0,0 +1,204 @@
+//! Means and sds that come out NaN or infinite for pixels with
+//! observations, e.g. the sd of sums rounded to a negative variance or the
+//! mean of sums that overflowed (`--nan-policy`).
+//!
+//! Pixels without observations, or with fewer than `--min-count`, are
+//! nodata by design and not counted. The others are counted per group for
+//! the report, with the first few indices, and written as nodata (NaN
+//! means, -1 sds), as 0 or as they are. The policy is set once for the
+//! process, like strict mode, so values recomputed to verify or extract
+//! them match those written.
+
+use crate::warnings::{self, Warning};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+/// Offending indices kept per group.
+const FIRST_INDICES: usize = 5;
+
+/// What non-finite means and sds are written as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NanPolicy {
+    /// The nodata value of the output: NaN for means, -1 for sds.
+    Nodata,
+    Zero,
+    /// Written as computed.
+    Keep,
+}
+
+impl Default for NanPolicy {
+    fn default() -> Self {
+        return NanPolicy::Nodata;
+    }
+}
+
+impl NanPolicy {
+    pub fn parse(text: &str) -> std::result::Result<NanPolicy, String> {
+        return match text {
+            "nodata" => Ok(NanPolicy::Nodata),
+            "zero" => Ok(NanPolicy::Zero),
+            "keep" => Ok(NanPolicy::Keep),
+            _ => Err(format!(
+                "invalid NaN policy '{}', expected nodata, zero or keep",
+                text
+            )),
+        };
+    }
+
+    pub fn name(self) -> &'static str {
+        return match self {
+            NanPolicy::Nodata => "nodata",
+            NanPolicy::Zero => "zero",
+            NanPolicy::Keep => "keep",
+        };
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(0);
+
+/// Writes non-finite means and sds as `policy` for the rest of the process.
+pub fn set_policy(policy: NanPolicy) {
+    POLICY.store(policy as u8, Ordering::SeqCst);
+}
+
+pub fn policy() -> NanPolicy {
+    return match POLICY.load(Ordering::SeqCst) {
+        1 => NanPolicy::Zero,
+        2 => NanPolicy::Keep,
+        _ => NanPolicy::Nodata,
+    };
+}
+
+/// Non-finite means and sds of a range of pixels.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NonFinite {
+    pub mean_nan: u64,
+    pub mean_inf: u64,
+    pub sd_nan: u64,
+    pub sd_inf: u64,
+    /// Indices of the first pixels with either, in the `_rev` datasets.
+    pub first_indices: Vec<usize>,
+}
+
+impl NonFinite {
+    pub fn is_empty(&self) -> bool {
+        return self.mean_nan + self.mean_inf + self.sd_nan + self.sd_inf == 0;
+    }
+
+    pub fn add(&mut self, other: &NonFinite) {
+        self.mean_nan += other.mean_nan;
+        self.mean_inf += other.mean_inf;
+        self.sd_nan += other.sd_nan;
+        self.sd_inf += other.sd_inf;
+        for &index in &other.first_indices {
+            if self.first_indices.len() < FIRST_INDICES {
+                self.first_indices.push(index);
+            }
+        }
+    }
+}
+
+/// Counts the non-finite means and sds of the pixels with at least
+/// `min_count` observations (and at least one), the first of them at index
+/// `offset`, and replaces them as the policy says.
+pub fn apply<C: Copy + Into<u32>>(
+    count_vals: &[C],
+    min_count: u32,
+    offset: usize,
+    mean_out: &mut [f32],
+    sd_out: &mut [f32],
+) -> NonFinite {
+    let policy = policy();
+    let min_count = min_count.max(1);
+    let mut found = NonFinite::default();
+    for (ii, &count) in count_vals.iter().enumerate() {
+        let (mean, sd) = (mean_out[ii], sd_out[ii]);
+        if count.into() < min_count || (mean.is_finite() && sd.is_finite()) {
+            continue;
+        }
+        found.mean_nan += mean.is_nan() as u64;
+        found.mean_inf += mean.is_infinite() as u64;
+        found.sd_nan += sd.is_nan() as u64;
+        found.sd_inf += sd.is_infinite() as u64;
+        if found.first_indices.len() < FIRST_INDICES {
+            found.first_indices.push(offset + ii);
+        }
+        let (nodata_mean, nodata_sd) = match policy {
+            NanPolicy::Nodata => (f32::NAN, -1f32),
+            NanPolicy::Zero => (0f32, 0f32),
+            NanPolicy::Keep => continue,
+        };
+        if !mean.is_finite() {
+            mean_out[ii] = nodata_mean;
+        }
+        if !sd.is_finite() {
+            sd_out[ii] = nodata_sd;
+        }
+    }
+    return found;
+}
+
+/// Non-finite means and sds of a group, for the report.
+#[derive(Debug, Clone, Serialize)]
+pub struct NonFiniteRecord {
+    pub group: String,
+    #[serde(flatten)]
+    pub found: NonFinite,
+    /// `--nan-policy` they were written with.
+    pub policy: &'static str,
+}
+
+static RECORDED: Mutex<Vec<NonFiniteRecord>> = Mutex::new(Vec::new());
+
+/// Adds `found` to the record of `group`.
+pub fn record(group: &str, found: &NonFinite) {
+    if found.is_empty() {
+        return;
+    }
+    if let Ok(mut recorded) = RECORDED.lock() {
+        match recorded.iter_mut().find(|record| record.group == group) {
+            Some(record) => record.found.add(found),
+            None => recorded.push(NonFiniteRecord {
+                group: group.to_owned(),
+                found: found.clone(),
+                policy: policy().name(),
+            }),
+        }
+    }
+}
+
+/// Warns about the non-finite means and sds recorded for `group`, if any.
+pub fn warn(group: &str) {
+    let record = match RECORDED.lock() {
+        Ok(recorded) => recorded
+            .iter()
+            .find(|record| record.group == group)
+            .cloned(),
+        Err(_) => None,
+    };
+    if let Some(record) = record {
+        warnings::record(
+            Warning::NonFinite,
+            format!(
+                "group {}: {} NaN and {} infinite means, {} NaN and {} infinite sds of pixels \
+                 with observations, first at indices {:?}, written as {}",
+                record.group,
+                record.found.mean_nan,
+                record.found.mean_inf,
+                record.found.sd_nan,
+                record.found.sd_inf,
+                record.found.first_indices,
+                record.policy
+            ),
+        );
+    }
+}
+
+/// Records kept so far, leaving none.
+pub fn take() -> Vec<NonFiniteRecord> {
+    return match RECORDED.lock() {
+        Ok(mut recorded) => std::mem::take(&mut *recorded),
+        Err(_) => vec![],
+    };
+}
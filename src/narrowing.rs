@@ -0,0 +1,219 @@
+//! Narrowing of dataset values to a smaller output data type (`--ot`).
+//!
+//! Values are read as `f64`, rounded to the nearest integer (halves away
+//! from zero) for integer outputs and clipped to the range of the output
+//! type, the pixels clipped being counted for the report. A nodata value the
+//! output type cannot hold is remapped to the lowest value of signed types,
+//! the highest of unsigned ones or NaN, which clipped values never take.
+
+use crate::error::Result;
+use crate::hdf::HdfSource;
+use crate::valid_range::RangeValue;
+use serde::Serialize;
+use std::cell::Cell;
+use std::sync::Mutex;
+
+/// Whether `value` converts to `T` and back unchanged.
+fn holds<T: RangeValue>(value: f64) -> bool {
+    let back: f64 = T::from_f64(value).into();
+    return back == value || (back.is_nan() && value.is_nan());
+}
+
+/// Nodata value of `T` replacing one it cannot hold.
+fn default_nodata<T: RangeValue>() -> f64 {
+    if !T::IS_INTEGER {
+        return f64::NAN;
+    }
+    if T::LOWEST < 0f64 {
+        return T::LOWEST;
+    }
+    return T::HIGHEST;
+}
+
+/// Nodata value of outputs of `T` narrowed from values with `nodata`. Values
+/// read from floats without one (possibly NaN) get the default of `T`.
+pub fn remap_nodata<T: RangeValue>(nodata: Option<f64>, from_float: bool) -> Option<f64> {
+    return match nodata {
+        Some(nodata) if holds::<T>(nodata) => Some(nodata),
+        Some(_) => Some(default_nodata::<T>()),
+        None if from_float && T::IS_INTEGER => Some(default_nodata::<T>()),
+        None => None,
+    };
+}
+
+/// A source of `f64` values narrowed to the element type read from it.
+pub struct Narrowed<'a, S> {
+    source: &'a S,
+    from_nodata: Option<f64>,
+    nodata: Option<f64>,
+    min: f64,
+    max: f64,
+    clipped: Cell<u64>,
+}
+
+impl<'a, S> Narrowed<'a, S> {
+    /// Wraps `source`, whose values equal to `nodata` (and NaN) become the
+    /// nodata value of `T`; `from_float` tells whether the source values are
+    /// floats.
+    pub fn new<T: RangeValue>(source: &'a S, nodata: Option<f64>, from_float: bool) -> Self {
+        let to_nodata = remap_nodata::<T>(nodata, from_float);
+        let mut min = T::LOWEST;
+        let mut max = T::HIGHEST;
+        if T::IS_INTEGER && to_nodata == Some(min) {
+            min += 1f64;
+        }
+        if T::IS_INTEGER && to_nodata == Some(max) {
+            max -= 1f64;
+        }
+        return Narrowed {
+            source,
+            from_nodata: nodata,
+            nodata: to_nodata,
+            min,
+            max,
+            clipped: Cell::new(0),
+        };
+    }
+
+    /// Nodata value of the narrowed values.
+    pub fn nodata(&self) -> Option<f64> {
+        return self.nodata;
+    }
+
+    /// Record of the pixels clipped so far in the output at `path`, written
+    /// as `data_type`.
+    pub fn finish(&self, path: &str, data_type: &str) -> ClipRecord {
+        return ClipRecord {
+            path: path.to_owned(),
+            data_type: data_type.to_owned(),
+            min: self.min,
+            max: self.max,
+            nodata: self.nodata,
+            pixels: self.clipped.get(),
+        };
+    }
+}
+
+impl<'a, T, S> HdfSource<T> for Narrowed<'a, S>
+where
+    T: RangeValue,
+    S: HdfSource<f64>,
+{
+    fn len(&self, path: &str) -> Result<usize> {
+        return self.source.len(path);
+    }
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> Result<Vec<T>> {
+        let vals = self.source.read_range(path, start, end)?;
+        let nodata = T::from_f64(self.nodata.unwrap_or(f64::NAN));
+        let mut clipped = 0;
+        let narrowed = vals
+            .into_iter()
+            .map(|value| {
+                if value.is_nan() || Some(value) == self.from_nodata {
+                    return nodata;
+                }
+                let value = if T::IS_INTEGER { value.round() } else { value };
+                if value < self.min || value > self.max {
+                    clipped += 1;
+                    return T::from_f64(value.max(self.min).min(self.max));
+                }
+                return T::from_f64(value);
+            })
+            .collect();
+        self.clipped.set(self.clipped.get() + clipped);
+        return Ok(narrowed);
+    }
+}
+
+/// Pixels of an output clipped to the range of its data type.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClipRecord {
+    pub path: String,
+    pub data_type: String,
+    pub min: f64,
+    pub max: f64,
+    /// Nodata value of the output after remapping.
+    pub nodata: Option<f64>,
+    pub pixels: u64,
+}
+
+static RECORDED: Mutex<Vec<ClipRecord>> = Mutex::new(Vec::new());
+
+/// Prints and records the pixels of an output clipped while narrowing.
+pub fn record(record: ClipRecord) {
+    println!(
+        "{}: {} pixels clipped to [{}, {}] writing {}",
+        record.path, record.pixels, record.min, record.max, record.data_type
+    );
+    if let Ok(mut recorded) = RECORDED.lock() {
+        recorded.push(record);
+    }
+}
+
+/// Records kept so far, leaving none.
+pub fn take() -> Vec<ClipRecord> {
+    return match RECORDED.lock() {
+        Ok(mut recorded) => std::mem::take(&mut *recorded),
+        Err(_) => vec![],
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::RasterType;
+
+    struct Values(Vec<f64>);
+
+    impl HdfSource<f64> for Values {
+        fn len(&self, _path: &str) -> Result<usize> {
+            return Ok(self.0.len());
+        }
+
+        fn read_range(&self, _path: &str, start: usize, end: usize) -> Result<Vec<f64>> {
+            return Ok(self.0[start..end].to_vec());
+        }
+    }
+
+    #[test]
+    fn output_types_parse_by_name() {
+        assert_eq!(RasterType::parse("Int16"), Ok(RasterType::Int16));
+        assert_eq!(RasterType::parse(" uint16 "), Ok(RasterType::UInt16));
+        assert_eq!(RasterType::parse("byte"), Ok(RasterType::Byte));
+        assert!(RasterType::parse("Int64").is_err());
+        assert!(RasterType::parse("").is_err());
+    }
+
+    #[test]
+    fn nodata_is_kept_when_it_fits_and_remapped_otherwise() {
+        assert_eq!(remap_nodata::<i16>(Some(-9999.), true), Some(-9999.));
+        assert_eq!(remap_nodata::<u8>(Some(-9999.), true), Some(255.));
+        assert_eq!(remap_nodata::<i16>(Some(0.5), true), Some(-32768.));
+        assert!(remap_nodata::<f32>(Some(1e300), true).unwrap().is_nan());
+        // Floats may hold NaN without a nodata value; integers cannot.
+        assert_eq!(remap_nodata::<u16>(None, true), Some(65535.));
+        assert_eq!(remap_nodata::<u16>(None, false), None);
+        assert_eq!(remap_nodata::<f32>(None, true), None);
+    }
+
+    #[test]
+    fn values_are_rounded_and_clipped_short_of_the_nodata_value() {
+        let source = Values(vec![1.4, 1.5, -0.5, 300., -1., f64::NAN, -9999.]);
+        let narrowed = Narrowed::new::<u8>(&source, Some(-9999.), true);
+        let vals: Vec<u8> = narrowed.read_range("", 0, 7).unwrap();
+        // 255 is the nodata value, so 300 clips to 254.
+        assert_eq!(vals, vec![1, 2, 0, 254, 0, 255, 255]);
+        let record = narrowed.finish("agbd_mean.tif", "Byte");
+        assert_eq!((record.min, record.max), (0., 254.));
+        assert_eq!(record.nodata, Some(255.));
+        // -0.5 rounds away from zero to -1, which is clipped like -1.
+        assert_eq!(record.pixels, 3);
+
+        let source = Values(vec![40000., -40000., 12.]);
+        let narrowed = Narrowed::new::<i16>(&source, None, false);
+        let vals: Vec<i16> = narrowed.read_range("", 0, 3).unwrap();
+        assert_eq!(vals, vec![32767, -32768, 12]);
+        assert_eq!(narrowed.finish("", "Int16").pixels, 2);
+    }
+}
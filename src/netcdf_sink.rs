@@ -0,0 +1,130 @@
+//! netCDF-CF output (`netcdf-sink` feature).
+//!
+//! Each file holds one `y` x `x` statistic variable, the pixel-centre `x` and
+//! `y` coordinate variables and a scalar `spatial_ref` grid mapping variable
+//! with the CRS and GDAL geotransform, so CF-aware tools (xarray, CDO, QGIS)
+//! place the grid correctly.
+
+use crate::error::Result;
+use crate::raster::{GeoRef, RasterSink};
+
+pub struct NetcdfSink {
+    file: netcdf::MutableFile,
+    name: String,
+    georef: GeoRef,
+    units: Option<String>,
+}
+
+impl NetcdfSink {
+    /// Creates `out_path`, replacing an existing file, for the variable
+    /// `name` georeferenced like `georef`.
+    pub fn create(out_path: &str, name: &str, georef: GeoRef) -> Result<NetcdfSink> {
+        let mut file = netcdf::create(out_path)?;
+        file.add_attribute("Conventions", "CF-1.8")?;
+        return Ok(NetcdfSink {
+            file,
+            name: name.to_owned(),
+            georef,
+            units: None,
+        });
+    }
+
+    /// Whether the CRS is geographic, in which case the coordinates are
+    /// longitudes and latitudes rather than projected metres.
+    fn is_geographic(&self) -> bool {
+        let crs = self.georef.crs.trim_start();
+        return crs.starts_with("GEOGCS") || crs.starts_with("GEOGCRS") || crs == "EPSG:4326";
+    }
+
+    fn write_coordinates(&mut self, xsize: usize, ysize: usize) -> Result<()> {
+        let gt = self.georef.geo_transform;
+        let x: Vec<f64> = (0..xsize)
+            .map(|ii| gt[0] + (ii as f64 + 0.5) * gt[1])
+            .collect();
+        let y: Vec<f64> = (0..ysize)
+            .map(|ii| gt[3] + (ii as f64 + 0.5) * gt[5])
+            .collect();
+        let (x_attrs, y_attrs) = if self.is_geographic() {
+            (
+                ["longitude", "longitude", "degrees_east"],
+                ["latitude", "latitude", "degrees_north"],
+            )
+        } else {
+            (
+                ["projection_x_coordinate", "x coordinate of projection", "m"],
+                ["projection_y_coordinate", "y coordinate of projection", "m"],
+            )
+        };
+
+        for (dim, vals, attrs) in [("x", x, x_attrs), ("y", y, y_attrs)].iter() {
+            let mut var = self.file.add_variable::<f64>(dim, &[dim])?;
+            var.put_attribute("standard_name", attrs[0])?;
+            var.put_attribute("long_name", attrs[1])?;
+            var.put_attribute("units", attrs[2])?;
+            var.put_attribute("axis", dim.to_uppercase())?;
+            var.put_values(vals, None, None)?;
+        }
+
+        let gt_text = gt
+            .iter()
+            .map(|val| val.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let is_geographic = self.is_geographic();
+        let mut crs_var = self.file.add_variable::<i32>("spatial_ref", &[])?;
+        if is_geographic {
+            crs_var.put_attribute("grid_mapping_name", "latitude_longitude")?;
+        }
+        crs_var.put_attribute("crs_wkt", self.georef.crs.as_str())?;
+        crs_var.put_attribute("spatial_ref", self.georef.crs.as_str())?;
+        crs_var.put_attribute("GeoTransform", gt_text)?;
+        return Ok(());
+    }
+}
+
+impl<T: netcdf::Numeric> RasterSink<T> for NetcdfSink {
+    fn write_strips(
+        &mut self,
+        xsize: usize,
+        ysize: usize,
+        n_lines: usize,
+        next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<T>>,
+    ) -> Result<()> {
+        self.file.add_dimension("y", ysize)?;
+        self.file.add_dimension("x", xsize)?;
+        self.write_coordinates(xsize, ysize)?;
+
+        let is_count = std::mem::size_of::<T>() == 1;
+        let mut var = self.file.add_variable::<T>(&self.name, &["y", "x"])?;
+        var.put_attribute("long_name", self.name.as_str())?;
+        var.put_attribute("grid_mapping", "spatial_ref")?;
+        match &self.units {
+            Some(units) => {
+                var.put_attribute("units", units.as_str())?;
+            }
+            None if is_count => {
+                var.put_attribute("units", "1")?;
+            }
+            None => {}
+        }
+        for yy in (0..ysize).step_by(n_lines) {
+            let lines = n_lines.min(ysize - yy);
+            let vals = next_strip(yy, lines)?;
+            var.put_values(&vals, Some(&[yy, 0]), Some(&[lines, xsize]))?;
+        }
+        return Ok(());
+    }
+
+    fn set_units(&mut self, units: &str) -> Result<()> {
+        self.units = Some(units.to_owned());
+        return Ok(());
+    }
+
+    fn set_metadata(&mut self, domain: &str, items: &[(String, String)]) -> Result<()> {
+        for (name, value) in items {
+            let name = format!("{}_{}", domain.to_lowercase(), name);
+            self.file.add_attribute(&name, value.as_str())?;
+        }
+        return Ok(());
+    }
+}
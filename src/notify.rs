@@ -0,0 +1,228 @@
+//! Notifications sent when a run ends, whether it completed or failed: the
+//! JSON report posted to a webhook (`--notify-url`) or given on stdin to a
+//! command (`--on-complete`).
+//!
+//! The webhook is posted with a plain HTTP/1.1 request, like the status
+//! endpoint is served, so only `http://` URLs are supported; services only
+//! reachable over HTTPS (Slack, mail gateways) can be called from the
+//! command instead, e.g. with `curl`.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+/// Longest wait to connect to the webhook, and for each read and write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An `http://` webhook.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifyUrl {
+    /// Host name or address, IPv6 ones without their brackets.
+    pub host: String,
+    pub port: u16,
+    /// Path and query, from the `/`.
+    pub path: String,
+}
+
+impl NotifyUrl {
+    /// Parses `http://HOST[:PORT][/PATH]`, an IPv6 HOST being in brackets,
+    /// e.g. `http://[::1]:8080/`.
+    pub fn parse(text: &str) -> Result<NotifyUrl, String> {
+        let rest = match text.trim().strip_prefix("http://") {
+            Some(rest) => rest,
+            None if text.trim().starts_with("https://") => {
+                return Err(format!(
+                    "cannot post to {}: only http:// webhooks are supported, call https ones \
+                     from --on-complete",
+                    text
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "invalid webhook URL '{}', expected http://...",
+                    text
+                ))
+            }
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, port)) => (host, port),
+                None => return Err(format!("unclosed '[' in webhook URL '{}'", text)),
+            },
+            None => match authority.find(':') {
+                Some(index) => (&authority[..index], &authority[index..]),
+                None => (authority, ""),
+            },
+        };
+        if authority.starts_with('[') && !host.contains(':') {
+            return Err(format!(
+                "invalid webhook URL '{}', only IPv6 addresses go in brackets",
+                text
+            ));
+        }
+        let port = match port {
+            "" => 80,
+            port => match port.strip_prefix(':').map(str::parse::<u16>) {
+                Some(Ok(port)) => port,
+                // An IPv6 address without brackets lands here too.
+                _ => {
+                    return Err(format!(
+                        "invalid port in webhook URL '{}' (IPv6 hosts go in brackets)",
+                        text
+                    ))
+                }
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("invalid webhook URL '{}', no host", text));
+        }
+        return Ok(NotifyUrl {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        });
+    }
+}
+
+/// Posts `json` to `url`, returning the HTTP status code of the answer.
+pub fn post(url: &NotifyUrl, json: &str) -> io::Result<u16> {
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        host_header(&url.host),
+        json.len(),
+        json
+    )?;
+    stream.flush()?;
+    // The status line is all that is needed, even if the server then
+    // times out.
+    let mut answer = vec![];
+    let read = stream.take(1024).read_to_end(&mut answer);
+    if answer.is_empty() {
+        read?;
+    }
+    let answer = String::from_utf8_lossy(&answer);
+    let code = answer
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok());
+    return code.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid HTTP answer from the webhook",
+        )
+    });
+}
+
+/// `host` as in a URL: IPv6 addresses in brackets.
+fn host_header(host: &str) -> String {
+    return match host.contains(':') {
+        true => format!("[{}]", host),
+        false => host.to_owned(),
+    };
+}
+
+/// Runs `command` through the shell with `json` on its stdin and the exit
+/// code of the run in `RS_H2R_EXIT_CODE`, waiting for it to end.
+pub fn run_command(command: &str, json: &str, exit_code: i32) -> io::Result<ExitStatus> {
+    let mut shell = match cfg!(windows) {
+        true => {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        }
+        false => {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        }
+    };
+    let mut child = shell
+        .arg(command)
+        .env("RS_H2R_EXIT_CODE", exit_code.to_string())
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that does not read the report closes its stdin early.
+        if let Err(err) = stdin.write_all(json.as_bytes()) {
+            if err.kind() != io::ErrorKind::BrokenPipe {
+                return Err(err);
+            }
+        }
+    }
+    return child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(host: &str, port: u16, path: &str) -> NotifyUrl {
+        return NotifyUrl {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        };
+    }
+
+    #[test]
+    fn parses_host_names_and_ipv4_addresses() {
+        assert_eq!(
+            NotifyUrl::parse("http://example.org"),
+            Ok(url("example.org", 80, "/"))
+        );
+        assert_eq!(
+            NotifyUrl::parse("http://127.0.0.1:8080/hooks/run?id=3"),
+            Ok(url("127.0.0.1", 8080, "/hooks/run?id=3"))
+        );
+    }
+
+    #[test]
+    fn parses_bracketed_ipv6_addresses() {
+        assert_eq!(
+            NotifyUrl::parse("http://[::1]:8080/"),
+            Ok(url("::1", 8080, "/"))
+        );
+        assert_eq!(
+            NotifyUrl::parse("http://[fe80::1]/done"),
+            Ok(url("fe80::1", 80, "/done"))
+        );
+        assert_eq!(host_header("::1"), "[::1]");
+        assert_eq!(host_header("example.org"), "example.org");
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        for text in [
+            "https://example.org/",
+            "example.org",
+            "http://:8080/",
+            "http://example.org:port/",
+            "http://example.org:99999/",
+            "http://::1:8080/",
+            "http://[::1/",
+            "http://[::1]8080/",
+            "http://[]/",
+            "http://[example.org]/",
+        ]
+        .iter()
+        {
+            assert!(NotifyUrl::parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Overrides of the options given for the whole file, for chosen datasets,
+//! read from a JSON config file (`--config`).
+//!
+//! Real products usually have a few datasets the blanket options do not
+//! suit: one stored north-up among bottom-up ones, one without a fill
+//! value, one that must be written as bytes, one that cannot be converted
+//! at all. The config maps targets, named like those of the unit rules
+//! (see [`crate::units::conversion_for`]), to what changes for them:
+//!
+//! ```json
+//! {
+//!     "datasets": {
+//!         "/quality/flags": { "type": "Byte", "nodata": 255 },
+//!         "rh98_north_up": { "flip": false },
+//!         "/ancillary": { "skip": true }
+//!     }
+//! }
+//! ```
+//!
+//! A target is a dataset path, the last component of dataset paths or a
+//! group path, applying to the datasets below it; a statistic group that is
+//! skipped gets no outputs. Each setting is taken from the most specific
+//! target giving it.
+
+use crate::error::{Error, Result};
+use crate::templates::RasterType;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// What changes for the datasets of one target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DatasetOverride {
+    /// Group or dataset path, or the last component of a dataset path.
+    pub target: String,
+    /// Data type written instead of `--ot` or the dataset's own.
+    pub data_type: Option<RasterType>,
+    /// Whether the rows are flipped, instead of `--flip`.
+    pub flip: Option<bool>,
+    /// Nodata of the outputs instead of the fill value of the dataset.
+    pub nodata: Option<f64>,
+    /// Leave the datasets out of the conversion.
+    pub skip: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    datasets: BTreeMap<String, RawOverride>,
+    /// Read by [`crate::shards`].
+    #[serde(default)]
+    shards: IgnoredAny,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawOverride {
+    #[serde(rename = "type")]
+    data_type: Option<String>,
+    flip: Option<bool>,
+    nodata: Option<f64>,
+    #[serde(default)]
+    skip: bool,
+}
+
+/// Parses the overrides of a config file.
+pub fn parse(text: &str) -> Result<Vec<DatasetOverride>> {
+    let config: ConfigFile = serde_json::from_str(text)?;
+    let mut overrides = vec![];
+    for (target, raw) in config.datasets {
+        let target = target.trim().trim_matches('/').to_owned();
+        let data_type = match raw.data_type {
+            Some(name) => Some(
+                RasterType::parse(&name)
+                    .map_err(|err| Error::Invalid(format!("override of {}: {}", target, err)))?,
+            ),
+            None => None,
+        };
+        overrides.push(DatasetOverride {
+            target,
+            data_type,
+            flip: raw.flip,
+            nodata: raw.nodata,
+            skip: raw.skip,
+        });
+    }
+    return Ok(overrides);
+}
+
+/// Reads the overrides of the config file at `path`.
+pub fn load(path: &Path) -> Result<Vec<DatasetOverride>> {
+    return parse(&std::fs::read_to_string(path)?).map_err(|err| {
+        return Error::Invalid(format!("config {}: {}", path.display(), err));
+    });
+}
+
+/// Settings of the group or dataset at `path`, each from the most specific
+/// target giving it: its whole path, its last component, then the groups
+/// above it from the nearest. The target of the result is `path`.
+pub fn resolve(overrides: &[DatasetOverride], path: &str) -> DatasetOverride {
+    let path = path.trim_matches('/');
+    let mut targets = vec![path];
+    targets.extend(path.rsplit('/').next().filter(|&name| name != path));
+    let mut group = path;
+    while let Some((parent, _)) = group.rsplit_once('/') {
+        targets.push(parent);
+        group = parent;
+    }
+    let mut resolved = DatasetOverride {
+        target: path.to_owned(),
+        ..DatasetOverride::default()
+    };
+    for target in targets {
+        for found in overrides.iter().filter(|found| found.target == target) {
+            resolved.data_type = resolved.data_type.or(found.data_type);
+            resolved.flip = resolved.flip.or(found.flip);
+            resolved.nodata = resolved.nodata.or(found.nodata);
+            resolved.skip |= found.skip;
+        }
+    }
+    return resolved;
+}
+
+/// Whether the group or dataset at `path` is skipped.
+pub fn skips(overrides: &[DatasetOverride], path: &str) -> bool {
+    return resolve(overrides, path).skip;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_text(name: &str, text: &str) -> Result<Vec<DatasetOverride>> {
+        let path = std::env::temp_dir().join(format!(
+            "h2r-overrides-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, text).unwrap();
+        let overrides = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        return overrides;
+    }
+
+    fn rejected(name: &str, text: &str) -> String {
+        return match load_text(name, text) {
+            Err(Error::Invalid(msg)) => msg,
+            other => panic!("loaded {:?}", other),
+        };
+    }
+
+    #[test]
+    fn loads_targets_without_slashes() {
+        let overrides = load_text(
+            "valid",
+            r#"{"datasets": {"/quality/flags/": {"type": "byte", "nodata": 255}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            overrides,
+            vec![DatasetOverride {
+                target: "quality/flags".to_owned(),
+                data_type: Some(RasterType::Byte),
+                nodata: Some(255f64),
+                ..DatasetOverride::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_configs_naming_the_file() {
+        let msg = rejected("syntax", r#"{"datasets": {"#);
+        assert!(msg.starts_with("config "), "{}", msg);
+        assert!(msg.contains("h2r-overrides-"), "{}", msg);
+        let msg = rejected("field", r#"{"datasets": {"rh98": {"flipped": true}}}"#);
+        assert!(msg.contains("flipped"), "{}", msg);
+        let msg = rejected("type", r#"{"datasets": {"rh98": {"type": "Int8"}}}"#);
+        assert!(msg.contains("override of rh98"), "{}", msg);
+        assert!(msg.contains("Int8"), "{}", msg);
+    }
+
+    #[test]
+    fn missing_config_is_an_error() {
+        let path =
+            std::env::temp_dir().join(format!("h2r-overrides-{}-missing.json", std::process::id()));
+        assert!(load(&path).is_err());
+    }
+
+    #[test]
+    fn settings_come_from_the_most_specific_target() {
+        let overrides = parse(
+            r#"{"datasets": {
+                "/agbd": {"flip": true, "nodata": -9999, "type": "Float32"},
+                "/agbd/rh98": {"type": "Int16"},
+                "rh98": {"nodata": 0},
+                "/agbd/rh98/max": {"flip": false}
+            }}"#,
+        )
+        .unwrap();
+        let resolved = resolve(&overrides, "/agbd/rh98/max");
+        assert_eq!(resolved.target, "agbd/rh98/max");
+        assert_eq!(resolved.flip, Some(false));
+        assert_eq!(resolved.data_type, Some(RasterType::Int16));
+        assert_eq!(resolved.nodata, Some(-9999f64));
+        let resolved = resolve(&overrides, "/agbd/rh98");
+        assert_eq!(resolved.flip, Some(true));
+        assert_eq!(resolved.data_type, Some(RasterType::Int16));
+        assert_eq!(resolved.nodata, Some(0f64));
+        assert_eq!(
+            resolve(&overrides, "/other/max"),
+            DatasetOverride {
+                target: "other/max".to_owned(),
+                ..DatasetOverride::default()
+            }
+        );
+    }
+
+    #[test]
+    fn skipping_a_group_skips_the_datasets_below_it() {
+        let overrides = parse(r#"{"datasets": {"/ancillary": {"skip": true}}}"#).unwrap();
+        assert!(skips(&overrides, "/ancillary/dem/mean"));
+        assert!(skips(&overrides, "/ancillary"));
+        assert!(!skips(&overrides, "/agbd/mean"));
+    }
+}
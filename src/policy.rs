@@ -0,0 +1,175 @@
+//! What to do when an output (HDF5 dataset or raster file) already exists.
+
+use crate::error::{Error, Result};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExistsPolicy {
+    /// Keep the existing output and skip the step that would produce it.
+    SkipExisting,
+    /// Remove the existing output and produce it again.
+    Overwrite,
+    /// Stop with an error.
+    FailIfExists,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    Create,
+    Skip,
+}
+
+impl Default for ExistsPolicy {
+    fn default() -> Self {
+        return ExistsPolicy::SkipExisting;
+    }
+}
+
+impl ExistsPolicy {
+    /// Policy of a command-line flag: `--skip-existing`, `--overwrite` or
+    /// `--fail-if-exists`.
+    pub fn from_flag(flag: &str) -> Option<ExistsPolicy> {
+        return match flag {
+            "--skip-existing" => Some(ExistsPolicy::SkipExisting),
+            "--overwrite" => Some(ExistsPolicy::Overwrite),
+            "--fail-if-exists" => Some(ExistsPolicy::FailIfExists),
+            _ => None,
+        };
+    }
+
+    /// Applies the policy to the HDF5 object at `path`, unlinking it first
+    /// when overwriting.
+    pub fn decide_dataset(self, file: &hdf5::File, path: &str) -> Result<Decision> {
+        if !file.link_exists(path) {
+            return Ok(Decision::Create);
+        }
+        match self {
+            ExistsPolicy::SkipExisting => {
+                println!("Dataset {} already exists, skipping.", path);
+                return Ok(Decision::Skip);
+            }
+            ExistsPolicy::Overwrite => {
+                println!("Dataset {} already exists, overwriting.", path);
+                file.unlink(path)?;
+                return Ok(Decision::Create);
+            }
+            ExistsPolicy::FailIfExists => {
+                return Err(Error::AlreadyExists(path.to_owned()));
+            }
+        }
+    }
+
+    /// Applies the policy to the file at `path`. Overwritten files are
+    /// replaced when the output is created, not removed here.
+    pub fn decide_file(self, path: &str) -> Result<Decision> {
+        if !Path::new(path).exists() {
+            return Ok(Decision::Create);
+        }
+        match self {
+            ExistsPolicy::SkipExisting => {
+                println!("Raster {} already exists, skipping.", path);
+                return Ok(Decision::Skip);
+            }
+            ExistsPolicy::Overwrite => {
+                println!("Raster {} already exists, overwriting.", path);
+                return Ok(Decision::Create);
+            }
+            ExistsPolicy::FailIfExists => {
+                return Err(Error::AlreadyExists(path.to_owned()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: [ExistsPolicy; 3] = [
+        ExistsPolicy::SkipExisting,
+        ExistsPolicy::Overwrite,
+        ExistsPolicy::FailIfExists,
+    ];
+
+    fn temp_path(name: &str) -> String {
+        return std::env::temp_dir()
+            .join(format!("h2r-policy-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned();
+    }
+
+    #[test]
+    fn flags_parse_to_policies() {
+        assert_eq!(
+            ExistsPolicy::from_flag("--skip-existing"),
+            Some(ExistsPolicy::SkipExisting)
+        );
+        assert_eq!(
+            ExistsPolicy::from_flag("--overwrite"),
+            Some(ExistsPolicy::Overwrite)
+        );
+        assert_eq!(
+            ExistsPolicy::from_flag("--fail-if-exists"),
+            Some(ExistsPolicy::FailIfExists)
+        );
+        assert_eq!(ExistsPolicy::from_flag("--skip"), None);
+        assert_eq!(ExistsPolicy::from_flag("overwrite"), None);
+    }
+
+    #[test]
+    fn files_are_skipped_replaced_or_refused_when_they_exist() {
+        let path = temp_path("raster.tif");
+        let _ = std::fs::remove_file(&path);
+        for policy in POLICIES.iter() {
+            assert_eq!(policy.decide_file(&path).unwrap(), Decision::Create);
+        }
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(
+            ExistsPolicy::SkipExisting.decide_file(&path).unwrap(),
+            Decision::Skip
+        );
+        assert_eq!(
+            ExistsPolicy::Overwrite.decide_file(&path).unwrap(),
+            Decision::Create
+        );
+        // Replaced when created, not removed by the decision.
+        assert!(Path::new(&path).exists());
+        match ExistsPolicy::FailIfExists.decide_file(&path) {
+            Err(Error::AlreadyExists(existing)) => assert_eq!(existing, path),
+            other => panic!("decided {:?}", other),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn datasets_are_skipped_unlinked_or_refused_when_they_exist() {
+        let path = temp_path("policy.h5");
+        let file = hdf5::File::create(&path).unwrap();
+        for policy in POLICIES.iter() {
+            assert_eq!(
+                policy.decide_dataset(&file, "agbd").unwrap(),
+                Decision::Create
+            );
+        }
+        file.new_dataset::<u8>().create("agbd", 4).unwrap();
+        assert_eq!(
+            ExistsPolicy::SkipExisting
+                .decide_dataset(&file, "agbd")
+                .unwrap(),
+            Decision::Skip
+        );
+        assert!(matches!(
+            ExistsPolicy::FailIfExists.decide_dataset(&file, "agbd"),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert_eq!(
+            ExistsPolicy::Overwrite
+                .decide_dataset(&file, "agbd")
+                .unwrap(),
+            Decision::Create
+        );
+        assert!(!file.link_exists("agbd"));
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Low-resolution previews (`--preview N`) for visual QA before a full run.
+//!
+//! Every Nth pixel of every Nth row of the statistic datasets, or of the
+//! datasets converted on their own, is read from the source with strided
+//! hyperslabs, without reversing anything into the HDF5 file, and written
+//! as a `Float32` GeoTIFF on the grid of the outputs with N times its
+//! pixel size. The pixels kept are the top left ones of each N by N block
+//! of the outputs.
+
+use crate::error::{Error, Result};
+use crate::raster::{self, GeoRef};
+use hdf5::H5Type;
+use ndarray::{s, Array2, SliceInfo};
+
+/// Pixels of the preview of a grid of `len` pixels along one side read
+/// every `step`.
+pub fn preview_len(len: usize, step: usize) -> usize {
+    return (len + step - 1) / step;
+}
+
+/// Geotransform of the preview of `georef` read every `step` pixels.
+pub fn preview_transform(georef: &GeoRef, step: usize) -> [f64; 6] {
+    let mut gt = georef.geo_transform;
+    for ii in [1, 2, 4, 5].iter() {
+        gt[*ii] *= step as f64;
+    }
+    return gt;
+}
+
+/// Output file of the preview of the group or dataset at `path`.
+pub fn preview_path(path: &str) -> String {
+    return raster::output_path(path, "preview", "tif");
+}
+
+/// Every `step`th pixel of every `step`th row of `ds`, a grid of `xsize` by
+/// `ysize` pixels stored as rows of a 2-d dataset or one after the other in
+/// a 1-d dataset, in output order: from the last row stored up with
+/// `flip`.
+pub fn read_strided<T: H5Type + Clone>(
+    ds: &hdf5::Dataset,
+    (xsize, ysize): (usize, usize),
+    step: usize,
+    flip: bool,
+) -> Result<Array2<T>> {
+    if ds.size() != xsize * ysize {
+        return Err(Error::Invalid(format!(
+            "{} has {} values, not the {}x{} of its grid",
+            ds.name(),
+            ds.size(),
+            xsize,
+            ysize
+        )));
+    }
+    // The rows kept when flipped are those kept otherwise, counted from the
+    // last one stored.
+    let first_row = match flip {
+        true => (ysize - 1) % step,
+        false => 0,
+    };
+    let vals = match ds.ndim() {
+        2 => {
+            let slice = SliceInfo::new(s![first_row..ysize;step, 0..xsize;step]).unwrap();
+            ds.read_slice_2d::<T, _>(&slice)?
+        }
+        _ => {
+            let mut vals = Vec::with_capacity(preview_len(xsize, step) * preview_len(ysize, step));
+            for row in (first_row..ysize).step_by(step) {
+                let start = row * xsize;
+                let slice = SliceInfo::new(s![start..start + xsize;step]).unwrap();
+                vals.extend(ds.read_slice_1d::<T, _>(&slice)?.iter().cloned());
+            }
+            let shape = (preview_len(ysize, step), preview_len(xsize, step));
+            Array2::from_shape_vec(shape, vals).unwrap()
+        }
+    };
+    return match flip {
+        true => Ok(vals.slice(s![..;-1, ..]).to_owned()),
+        false => Ok(vals),
+    };
+}
+
+/// Writes `bands`, named by their descriptions, to a `Float32` GeoTIFF at
+/// `out_path` on the preview of `georef` read every `step` pixels, with NaN
+/// as nodata.
+#[cfg(feature = "gdal")]
+pub fn write_preview(
+    out_path: &str,
+    georef: &GeoRef,
+    step: usize,
+    bands: &[(&str, Array2<f32>)],
+) -> Result<()> {
+    let (ysize, xsize) = bands[0].1.dim();
+    let driver = gdal::Driver::get("GTiff")?;
+    let mut rast = driver.create_with_band_type::<f32, _>(
+        out_path,
+        xsize as isize,
+        ysize as isize,
+        bands.len() as isize,
+    )?;
+    rast.set_geo_transform(&preview_transform(georef, step))?;
+    if !georef.crs.is_empty() {
+        rast.set_projection(&georef.crs)?;
+    }
+    for (ii, (name, vals)) in bands.iter().enumerate() {
+        let mut band = rast.rasterband(ii as isize + 1)?;
+        band.set_description(name)?;
+        band.set_no_data_value(Some(f64::NAN))?;
+        let buffer = gdal::raster::Buffer::new((xsize, ysize), vals.iter().copied().collect());
+        band.write((0, 0), (xsize, ysize), &buffer)?;
+    }
+    return Ok(());
+}
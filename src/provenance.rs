@@ -0,0 +1,280 @@
+//! Provenance of the outputs (`--provenance`, implied by `--deterministic`):
+//! the source HDF5 file and its SHA-256, the datasets each output was
+//! computed from, the tool version and its arguments. It is written to a
+//! `PROVENANCE` metadata block of every output, to an ISO 19115 style XML
+//! sidecar next to it for data catalogs, and to the report, so archived
+//! products record how to reproduce them.
+//!
+//! Like warnings and summaries, it is process-wide, so the raster writers
+//! need no extra state; it is only recorded once set.
+
+use crate::error::Result;
+use crate::hdf;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::sync::Mutex;
+
+/// Metadata domain of the provenance block; formats without domains prefix
+/// its item names with `provenance_` instead.
+pub const DOMAIN: &str = "PROVENANCE";
+
+/// Statistics computed from all the accumulated datasets of a group rather
+/// than read from one of them.
+const DERIVED_STATS: [&str; 6] = ["mean", "sd", "se", "ci_lower", "ci_upper", "quality"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputChecksum {
+    pub path: String,
+    pub sha256: String,
+}
+
+impl InputChecksum {
+    /// Checksums the file at `path`.
+    pub fn of(path: &str) -> Result<InputChecksum> {
+        println!("Checksumming input: {}", path);
+        return Ok(InputChecksum {
+            path: path.to_owned(),
+            sha256: sha256_file(path)?,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    pub tool_version: String,
+    pub arguments: Vec<String>,
+    /// The HDF5 file converted.
+    pub source: InputChecksum,
+    /// Other inputs: templates and kernel modules.
+    pub inputs: Vec<InputChecksum>,
+    /// Accumulated datasets of each group.
+    pub stat_names: Vec<String>,
+    /// Other statistics computed from them, e.g. kernel outputs.
+    #[serde(skip)]
+    pub derived: Vec<String>,
+}
+
+impl Provenance {
+    /// Provenance of a run with `arguments` converting the HDF5 file at
+    /// `source`, with the other files at `inputs`; groups hold the
+    /// `stat_names` datasets, and `derived` statistics besides the mean, sd
+    /// and error outputs are computed from them.
+    pub fn new(
+        arguments: Vec<String>,
+        source: &str,
+        inputs: &[&str],
+        stat_names: Vec<String>,
+        derived: Vec<String>,
+    ) -> Result<Provenance> {
+        let mut checksums = vec![];
+        for path in inputs {
+            checksums.push(InputChecksum::of(path)?);
+        }
+        return Ok(Provenance {
+            tool_version: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            arguments,
+            source: InputChecksum::of(source)?,
+            inputs: checksums,
+            stat_names,
+            derived,
+        });
+    }
+
+    /// Datasets of the source file an output written from the reversed
+    /// datasets `ds_paths` (e.g. `group/mean_rev`) was computed from: all
+    /// the accumulated datasets of the group for derived statistics, else
+    /// the dataset reversed.
+    pub fn source_datasets(&self, ds_paths: &[&str]) -> Vec<String> {
+        let mut datasets = vec![];
+        for &ds_path in ds_paths {
+            let source = ds_path.strip_suffix("_rev").unwrap_or(ds_path);
+            let (group, name) = match source.rfind('/') {
+                Some(index) => (&source[..index], &source[index + 1..]),
+                None => ("", source),
+            };
+            let derived =
+                DERIVED_STATS.contains(&name) || self.derived.iter().any(|stat| stat == name);
+            let paths = match derived {
+                true => self
+                    .stat_names
+                    .iter()
+                    .map(|stat| hdf::join_path(group, stat))
+                    .collect(),
+                false => vec![source.to_owned()],
+            };
+            for path in paths {
+                if !datasets.contains(&path) {
+                    datasets.push(path);
+                }
+            }
+        }
+        return datasets;
+    }
+
+    /// Items of the provenance block of an output written from `ds_paths`.
+    pub fn metadata_items(&self, ds_paths: &[&str]) -> Vec<(String, String)> {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|input| format!("{} sha256:{}", input.path, input.sha256))
+            .collect();
+        return vec![
+            ("tool_version".to_owned(), self.tool_version.clone()),
+            ("tool_arguments".to_owned(), self.arguments.join(" ")),
+            ("source_file".to_owned(), self.source.path.clone()),
+            ("source_sha256".to_owned(), self.source.sha256.clone()),
+            (
+                "source_datasets".to_owned(),
+                self.source_datasets(ds_paths).join(","),
+            ),
+            ("input_checksums".to_owned(), inputs.join("; ")),
+        ];
+    }
+
+    /// ISO 19115 (gmd) style metadata of the output at `out_path` written
+    /// from `ds_paths`: its lineage, with the source file, its checksum and
+    /// datasets as the source and the tool run as the process step.
+    pub fn iso_xml(&self, out_path: &str, ds_paths: &[&str]) -> String {
+        let text = |value: &str| {
+            return format!(
+                "<gco:CharacterString>{}</gco:CharacterString>",
+                escape_xml(value)
+            );
+        };
+        let name = std::path::Path::new(out_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| out_path.to_owned());
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(
+            "<gmd:MD_Metadata xmlns:gmd=\"http://www.isotc211.org/2005/gmd\" \
+             xmlns:gco=\"http://www.isotc211.org/2005/gco\">\n",
+        );
+        xml.push_str(&format!(
+            "  <gmd:fileIdentifier>{}</gmd:fileIdentifier>\n",
+            text(&name)
+        ));
+        xml.push_str("  <gmd:identificationInfo><gmd:MD_DataIdentification><gmd:citation><gmd:CI_Citation>\n");
+        xml.push_str(&format!("    <gmd:title>{}</gmd:title>\n", text(&name)));
+        xml.push_str("  </gmd:CI_Citation></gmd:citation></gmd:MD_DataIdentification></gmd:identificationInfo>\n");
+        xml.push_str("  <gmd:dataQualityInfo><gmd:DQ_DataQuality><gmd:lineage><gmd:LI_Lineage>\n");
+        xml.push_str(&format!(
+            "    <gmd:statement>{}</gmd:statement>\n",
+            text(&format!(
+                "Converted from {} by {}",
+                self.source.path, self.tool_version
+            ))
+        ));
+        xml.push_str("    <gmd:processStep><gmd:LI_ProcessStep>\n");
+        xml.push_str(&format!(
+            "      <gmd:description>{}</gmd:description>\n",
+            text(&format!(
+                "{} {}",
+                self.tool_version,
+                self.arguments.join(" ")
+            ))
+        ));
+        xml.push_str("    </gmd:LI_ProcessStep></gmd:processStep>\n");
+        let mut sources = vec![(
+            format!(
+                "{}: {}",
+                self.source.path,
+                self.source_datasets(ds_paths).join(", ")
+            ),
+            &self.source,
+        )];
+        for input in self.inputs.iter() {
+            sources.push((input.path.clone(), input));
+        }
+        for (description, input) in sources {
+            xml.push_str("    <gmd:source><gmd:LI_Source>\n");
+            xml.push_str(&format!(
+                "      <gmd:description>{}</gmd:description>\n",
+                text(&description)
+            ));
+            xml.push_str("      <gmd:sourceCitation><gmd:CI_Citation>\n");
+            xml.push_str(&format!(
+                "        <gmd:title>{}</gmd:title>\n",
+                text(&input.path)
+            ));
+            xml.push_str(&format!(
+                "        <gmd:identifier><gmd:MD_Identifier><gmd:code>{}</gmd:code></gmd:MD_Identifier></gmd:identifier>\n",
+                text(&format!("sha256:{}", input.sha256))
+            ));
+            xml.push_str("      </gmd:CI_Citation></gmd:sourceCitation>\n");
+            xml.push_str("    </gmd:LI_Source></gmd:source>\n");
+        }
+        xml.push_str(
+            "  </gmd:LI_Lineage></gmd:lineage></gmd:DQ_DataQuality></gmd:dataQualityInfo>\n",
+        );
+        xml.push_str("</gmd:MD_Metadata>\n");
+        return xml;
+    }
+
+    /// Writes the XML sidecar of the output at `out_path` written from
+    /// `ds_paths`, pushing its path to `created` once the file exists.
+    pub fn write_sidecar(
+        &self,
+        out_path: &str,
+        ds_paths: &[&str],
+        created: &mut Vec<String>,
+    ) -> Result<()> {
+        let path = sidecar_path(out_path);
+        created.push(path.clone());
+        std::fs::write(&path, self.iso_xml(out_path, ds_paths))?;
+        return Ok(());
+    }
+}
+
+/// Path of the XML sidecar of the output at `out_path`.
+pub fn sidecar_path(out_path: &str) -> String {
+    return format!("{}.iso.xml", out_path);
+}
+
+/// Escapes the XML special characters of `text`.
+pub fn escape_xml(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+/// Hex SHA-256 of the file at `path`, read in blocks.
+pub fn sha256_file(path: &str) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n_read = file.read(&mut buf)?;
+        if n_read == 0 {
+            break;
+        }
+        hasher.update(&buf[..n_read]);
+    }
+    return Ok(format!("{:x}", hasher.finalize()));
+}
+
+static PROVENANCE: Mutex<Option<Provenance>> = Mutex::new(None);
+
+/// Records `provenance` in the outputs for the rest of the process.
+pub fn set(provenance: Provenance) {
+    if let Ok(mut current) = PROVENANCE.lock() {
+        *current = Some(provenance);
+    }
+}
+
+/// The provenance set for the run, if any.
+pub fn get() -> Option<Provenance> {
+    return PROVENANCE.lock().ok().and_then(|current| current.clone());
+}
+
+/// Forgets the provenance set, so the next run of the process records its
+/// own, if any.
+pub fn clear() {
+    if let Ok(mut current) = PROVENANCE.lock() {
+        *current = None;
+    }
+}
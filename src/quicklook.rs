@@ -0,0 +1,238 @@
+//! 8-bit PNG quicklooks of output statistics for fast visual QA.
+//!
+//! The dataset is read in strips and subsampled to at most `max_size` pixels
+//! on its longest side, stretched between two percentiles of its finite
+//! values and mapped through a colour ramp. Non-finite pixels are
+//! transparent.
+
+use crate::error::Result;
+use crate::hdf::HdfSource;
+use crate::interrupt;
+use std::fs::File;
+use std::io::BufWriter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorRamp {
+    Gray,
+    Viridis,
+    Magma,
+    Terrain,
+}
+
+impl ColorRamp {
+    pub fn parse(name: &str) -> std::result::Result<ColorRamp, String> {
+        return match name {
+            "gray" | "grey" => Ok(ColorRamp::Gray),
+            "viridis" => Ok(ColorRamp::Viridis),
+            "magma" => Ok(ColorRamp::Magma),
+            "terrain" => Ok(ColorRamp::Terrain),
+            _ => Err(format!(
+                "unknown color ramp '{}' (expected gray, viridis, magma or terrain)",
+                name
+            )),
+        };
+    }
+
+    /// Evenly spaced control colours, interpolated linearly.
+    fn stops(self) -> &'static [[u8; 3]] {
+        return match self {
+            ColorRamp::Gray => &[[0, 0, 0], [255, 255, 255]],
+            ColorRamp::Viridis => &[
+                [68, 1, 84],
+                [59, 82, 139],
+                [33, 145, 140],
+                [94, 201, 98],
+                [253, 231, 37],
+            ],
+            ColorRamp::Magma => &[
+                [0, 0, 4],
+                [81, 18, 124],
+                [183, 55, 121],
+                [252, 137, 97],
+                [252, 253, 191],
+            ],
+            ColorRamp::Terrain => &[
+                [51, 51, 153],
+                [0, 153, 255],
+                [0, 204, 102],
+                [255, 255, 153],
+                [128, 92, 84],
+                [255, 255, 255],
+            ],
+        };
+    }
+
+    /// Colour at `frac` in [0, 1].
+    pub fn color(self, frac: f64) -> [u8; 3] {
+        let stops = self.stops();
+        let pos = frac.max(0f64).min(1f64) * (stops.len() - 1) as f64;
+        let lower = (pos.floor() as usize).min(stops.len() - 2);
+        let weight = pos - lower as f64;
+        let mut rgb = [0u8; 3];
+        for (ii, channel) in rgb.iter_mut().enumerate() {
+            let from = stops[lower][ii] as f64;
+            let to = stops[lower + 1][ii] as f64;
+            *channel = (from + (to - from) * weight).round() as u8;
+        }
+        return rgb;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuicklookOptions {
+    pub ramp: ColorRamp,
+    /// Percentiles mapped to the ends of the ramp.
+    pub stretch: (f64, f64),
+    /// Longest side of the quicklook in pixels.
+    pub max_size: usize,
+}
+
+impl Default for QuicklookOptions {
+    fn default() -> Self {
+        return QuicklookOptions {
+            ramp: ColorRamp::Viridis,
+            stretch: (2f64, 98f64),
+            max_size: 1024,
+        };
+    }
+}
+
+/// Parses a `LOW,HIGH` percentile pair such as `2,98`.
+pub fn parse_stretch(text: &str) -> std::result::Result<(f64, f64), String> {
+    let invalid = || format!("invalid stretch '{}', expected LOW,HIGH percentiles", text);
+    let mut parts = text.split(',').map(|part| part.trim().parse::<f64>());
+    return match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(low)), Some(Ok(high)), None) if 0f64 <= low && low < high && high <= 100f64 => {
+            Ok((low, high))
+        }
+        _ => Err(invalid()),
+    };
+}
+
+/// Value at percentile `pct` of `sorted`.
+pub fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    let pos = pct / 100f64 * (sorted.len() - 1) as f64;
+    return sorted[pos.round() as usize];
+}
+
+/// Renders the (already reversed) dataset at `ds_path` as a PNG at
+/// `out_path`, reading strips of `n_lines_read` rows.
+pub fn write_quicklook<T, S>(
+    source: &S,
+    ds_path: &str,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    options: &QuicklookOptions,
+    out_path: &str,
+) -> Result<()>
+where
+    T: Copy + Into<f64>,
+    S: HdfSource<T>,
+{
+    let step = ((xsize.max(ysize) + options.max_size - 1) / options.max_size).max(1);
+    let width = (xsize + step - 1) / step;
+    let height = (ysize + step - 1) / step;
+
+    let mut sampled = Vec::with_capacity(width * height);
+    for yy in (0..ysize).step_by(n_lines_read) {
+        interrupt::check()?;
+        let lines_to_read = n_lines_read.min(ysize - yy);
+        let vals = source.read_range(ds_path, yy * xsize, (yy + lines_to_read) * xsize)?;
+        for row in (yy..yy + lines_to_read).filter(|row| row % step == 0) {
+            let offset = (row - yy) * xsize;
+            sampled.extend(
+                vals[offset..offset + xsize]
+                    .iter()
+                    .step_by(step)
+                    .map(|&val| val.into()),
+            );
+        }
+    }
+
+    let mut finite: Vec<f64> = sampled
+        .iter()
+        .cloned()
+        .filter(|val| val.is_finite())
+        .collect();
+    finite.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let (low, high) = if finite.is_empty() {
+        (0f64, 1f64)
+    } else {
+        (
+            percentile(&finite, options.stretch.0),
+            percentile(&finite, options.stretch.1),
+        )
+    };
+    let range = if high > low { high - low } else { 1f64 };
+
+    let mut rgba = Vec::with_capacity(sampled.len() * 4);
+    for val in &sampled {
+        if val.is_finite() {
+            rgba.extend_from_slice(&options.ramp.color((val - low) / range));
+            rgba.push(255);
+        } else {
+            rgba.extend_from_slice(&[0, 0, 0, 0]);
+        }
+    }
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(out_path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&rgba)?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_named_ramps() {
+        assert_eq!(ColorRamp::parse("gray"), Ok(ColorRamp::Gray));
+        assert_eq!(ColorRamp::parse("grey"), Ok(ColorRamp::Gray));
+        assert_eq!(ColorRamp::parse("viridis"), Ok(ColorRamp::Viridis));
+        assert_eq!(ColorRamp::parse("magma"), Ok(ColorRamp::Magma));
+        assert_eq!(ColorRamp::parse("terrain"), Ok(ColorRamp::Terrain));
+    }
+
+    #[test]
+    fn rejects_unknown_ramps() {
+        let err = ColorRamp::parse("jet").unwrap_err();
+        assert!(err.contains("'jet'"), "{}", err);
+        assert!(ColorRamp::parse("Viridis").is_err());
+    }
+
+    #[test]
+    fn ends_of_the_ramps_are_their_first_and_last_stops() {
+        for ramp in &[
+            ColorRamp::Gray,
+            ColorRamp::Viridis,
+            ColorRamp::Magma,
+            ColorRamp::Terrain,
+        ] {
+            let stops = ramp.stops();
+            assert_eq!(ramp.color(0f64), stops[0]);
+            assert_eq!(ramp.color(1f64), stops[stops.len() - 1]);
+            // Values beyond the stretch are clamped to the ends.
+            assert_eq!(ramp.color(-0.5), stops[0]);
+            assert_eq!(ramp.color(3f64), stops[stops.len() - 1]);
+        }
+        assert_eq!(ColorRamp::Viridis.color(0.5), [33, 145, 140]);
+        assert_eq!(ColorRamp::Gray.color(0.5), [128, 128, 128]);
+        assert_eq!(ColorRamp::Gray.color(0.25), [64, 64, 64]);
+    }
+
+    #[test]
+    fn parses_stretches() {
+        assert_eq!(parse_stretch("2,98"), Ok((2f64, 98f64)));
+        assert_eq!(parse_stretch(" 0 , 100 "), Ok((0f64, 100f64)));
+        for text in &["98,2", "5,5", "-1,50", "2,101", "2", "2,50,98", "a,b"] {
+            assert!(parse_stretch(text).is_err(), "parsed {}", text);
+        }
+    }
+}
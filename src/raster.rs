@@ -0,0 +1,1767 @@
+//! Output rasters.
+//!
+//! Each output is written through a [`RasterSink`], which pulls strips of
+//! whole rows from the caller top to bottom. The sinks available depend on
+//! the cargo features: `gdal` (GDAL, any format of the template), `pure-tiff`
+//! (GeoTIFF written in Rust) and `netcdf-sink` (netCDF).
+
+#[cfg(feature = "gdal")]
+use crate::block::{self, BandOrder, BlockLayout, RasterBlock};
+use crate::error::{Error, Result};
+use crate::hdf::{self, HdfSource};
+use crate::policy::{Decision, ExistsPolicy};
+use crate::provenance;
+use crate::summary::{self, Summarized};
+use crate::timing::{self, Stage};
+use crate::units::{Scaled, UnitConversion};
+use crate::valid_range::{self, RangeFiltered, RangeValue, ValidRange};
+use crate::{chunking, interrupt, retry, status};
+use ndarray::Array2;
+use std::cell::RefCell;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(not(any(feature = "gdal", feature = "pure-tiff")))]
+compile_error!("enable the gdal or pure-tiff feature to read raster templates");
+
+/// Element types the `gdal` sink can write.
+#[cfg(feature = "gdal")]
+pub trait GdalValue: gdal::raster::GdalType {}
+#[cfg(feature = "gdal")]
+impl<T: gdal::raster::GdalType> GdalValue for T {}
+#[cfg(not(feature = "gdal"))]
+pub trait GdalValue {}
+#[cfg(not(feature = "gdal"))]
+impl<T> GdalValue for T {}
+
+/// Element types the `pure-tiff` sink can write.
+#[cfg(feature = "pure-tiff")]
+pub trait TiffValue: crate::geotiff::TiffSample {}
+#[cfg(feature = "pure-tiff")]
+impl<T: crate::geotiff::TiffSample> TiffValue for T {}
+#[cfg(not(feature = "pure-tiff"))]
+pub trait TiffValue {}
+#[cfg(not(feature = "pure-tiff"))]
+impl<T> TiffValue for T {}
+
+/// Element types the `netcdf-sink` sink can write.
+#[cfg(feature = "netcdf-sink")]
+pub trait NetcdfValue: netcdf::Numeric {}
+#[cfg(feature = "netcdf-sink")]
+impl<T: netcdf::Numeric> NetcdfValue for T {}
+#[cfg(not(feature = "netcdf-sink"))]
+pub trait NetcdfValue {}
+#[cfg(not(feature = "netcdf-sink"))]
+impl<T> NetcdfValue for T {}
+
+/// Element types the `zarr-sink` sink can write.
+#[cfg(feature = "zarr-sink")]
+pub trait ZarrValue: crate::zarr_sink::ZarrValue {}
+#[cfg(feature = "zarr-sink")]
+impl<T: crate::zarr_sink::ZarrValue> ZarrValue for T {}
+#[cfg(not(feature = "zarr-sink"))]
+pub trait ZarrValue {}
+#[cfg(not(feature = "zarr-sink"))]
+impl<T> ZarrValue for T {}
+
+/// Element types that can be written to an output raster by every enabled
+/// sink, and range checked and summarized as `f64`.
+pub trait RasterValue:
+    hdf5::H5Type
+    + Copy
+    + Default
+    + RangeValue
+    + GdalValue
+    + TiffValue
+    + NetcdfValue
+    + ZarrValue
+    + 'static
+{
+}
+
+impl<T> RasterValue for T where
+    T: hdf5::H5Type
+        + Copy
+        + Default
+        + RangeValue
+        + GdalValue
+        + TiffValue
+        + NetcdfValue
+        + ZarrValue
+        + 'static
+{
+}
+
+/// Georeferencing of a template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoRef {
+    /// GDAL-style geotransform: origin x, pixel width, row rotation, origin
+    /// y, column rotation, pixel height.
+    pub geo_transform: [f64; 6],
+    /// CRS as WKT, or `EPSG:<code>` when read without GDAL; empty if unknown.
+    pub crs: String,
+}
+
+/// Destination of one output raster.
+pub trait RasterSink<T> {
+    /// Writes `ysize` rows of `xsize` pixels, pulling strips of up to
+    /// `n_lines` rows from `next_strip(yy, lines)` top to bottom.
+    fn write_strips(
+        &mut self,
+        xsize: usize,
+        ysize: usize,
+        n_lines: usize,
+        next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<T>>,
+    ) -> Result<()>;
+
+    /// Writes `ysize` rows of `xsize` pixels too wide to hold at once,
+    /// pulling each row top to bottom in blocks of up to `block_cols`
+    /// columns from `next_block(yy, x0, cols)`.
+    ///
+    /// Sinks that can only write whole rows get them assembled from their
+    /// blocks; a row is then empty, for sinks skipping empty strips, when
+    /// its blocks are.
+    fn write_blocks(
+        &mut self,
+        xsize: usize,
+        ysize: usize,
+        block_cols: usize,
+        next_block: &mut dyn FnMut(usize, usize, usize) -> Result<Vec<T>>,
+    ) -> Result<()> {
+        return self.write_strips(xsize, ysize, 1, &mut |yy, lines| {
+            let mut vals = Vec::with_capacity(lines * xsize);
+            let mut empty_rows = 0;
+            for block in chunking::blocks(xsize, lines, 1, block_cols) {
+                let block_vals = next_block(yy + block.yy, block.x0, block.cols)?;
+                if block_vals.is_empty() && block.x0 == 0 {
+                    empty_rows += 1;
+                }
+                vals.extend(block_vals);
+            }
+            return match empty_rows {
+                0 => Ok(vals),
+                _ if empty_rows == lines => Ok(vec![]),
+                _ => Err(Error::Invalid(format!(
+                    "rows {} to {} mix empty and written rows",
+                    yy,
+                    yy + lines - 1
+                ))),
+            };
+        });
+    }
+
+    /// Whether strips without any observation may be left unwritten because
+    /// the output already reads back as nodata there. `next_strip` then
+    /// returns an empty vector for such strips.
+    fn skips_empty(&self) -> bool {
+        return false;
+    }
+
+    /// Stores the units of the values in the output, for formats with a
+    /// place for them; called before `write_strips`.
+    fn set_units(&mut self, units: &str) -> Result<()> {
+        let _ = units;
+        return Ok(());
+    }
+
+    /// Stores the nodata value of the output, for formats with a place for
+    /// it; called before `write_strips`.
+    fn set_nodata(&mut self, nodata: f64) -> Result<()> {
+        let _ = nodata;
+        return Ok(());
+    }
+
+    /// Stores `items` (name, value) in the metadata domain `domain` of the
+    /// output, for formats with a place for them; formats without domains
+    /// prefix the names with `domain` in lower case. Called before
+    /// `write_strips`.
+    fn set_metadata(&mut self, domain: &str, items: &[(String, String)]) -> Result<()> {
+        let _ = (domain, items);
+        return Ok(());
+    }
+}
+
+/// Options of the `gdal` output format.
+#[cfg(feature = "gdal")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GdalOptions {
+    pub band_order: BandOrder,
+    /// Create outputs as sparse files initialised to nodata (`SPARSE_OK`)
+    /// and leave strips without observations unwritten.
+    pub sparse: bool,
+    /// Band the outputs are written to, from 1: of an existing output,
+    /// updated in place, or else of a copy of a multi-band template. `None`
+    /// writes band 1 of new outputs.
+    pub band: Option<usize>,
+    /// Store `Float32` outputs as 16-bit floats (GeoTIFF `NBITS=16`), at
+    /// half the size and about 3 significant digits.
+    pub float16: bool,
+    /// Interleave and tiling of new outputs, else those of the template.
+    pub layout: BlockLayout,
+}
+
+/// Output format, chosen among the sinks compiled in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// A copy of the template written through GDAL.
+    #[cfg(feature = "gdal")]
+    Gdal(GdalOptions),
+    /// A GeoTIFF with the template's georeferencing, written without GDAL.
+    #[cfg(feature = "pure-tiff")]
+    GeoTiff,
+    #[cfg(feature = "netcdf-sink")]
+    Netcdf,
+    /// A Zarr v3 directory store with CF/GeoZarr metadata.
+    #[cfg(feature = "zarr-sink")]
+    Zarr,
+}
+
+impl Default for OutputFormat {
+    #[cfg(feature = "gdal")]
+    fn default() -> Self {
+        return OutputFormat::Gdal(GdalOptions::default());
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    fn default() -> Self {
+        return OutputFormat::GeoTiff;
+    }
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> std::result::Result<OutputFormat, String> {
+        match name {
+            #[cfg(feature = "gdal")]
+            "gdal" => return Ok(OutputFormat::Gdal(GdalOptions::default())),
+            #[cfg(feature = "pure-tiff")]
+            "geotiff" => return Ok(OutputFormat::GeoTiff),
+            #[cfg(feature = "netcdf-sink")]
+            "netcdf" => return Ok(OutputFormat::Netcdf),
+            #[cfg(feature = "zarr-sink")]
+            "zarr" => return Ok(OutputFormat::Zarr),
+            _ => {
+                return Err(format!(
+                    "unknown or disabled output format '{}' (built with: {})",
+                    name,
+                    OutputFormat::available().join(", ")
+                ))
+            }
+        }
+    }
+
+    /// Names of the formats compiled in.
+    pub fn available() -> Vec<&'static str> {
+        let mut names = vec![];
+        if cfg!(feature = "gdal") {
+            names.push("gdal");
+        }
+        if cfg!(feature = "pure-tiff") {
+            names.push("geotiff");
+        }
+        if cfg!(feature = "netcdf-sink") {
+            names.push("netcdf");
+        }
+        if cfg!(feature = "zarr-sink") {
+            names.push("zarr");
+        }
+        return names;
+    }
+
+    /// Name accepted by [`OutputFormat::parse`].
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gdal")]
+            OutputFormat::Gdal(_) => return "gdal",
+            #[cfg(feature = "pure-tiff")]
+            OutputFormat::GeoTiff => return "geotiff",
+            #[cfg(feature = "netcdf-sink")]
+            OutputFormat::Netcdf => return "netcdf",
+            #[cfg(feature = "zarr-sink")]
+            OutputFormat::Zarr => return "zarr",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gdal")]
+            OutputFormat::Gdal(_) => return "tif",
+            #[cfg(feature = "pure-tiff")]
+            OutputFormat::GeoTiff => return "tif",
+            #[cfg(feature = "netcdf-sink")]
+            OutputFormat::Netcdf => return "nc",
+            #[cfg(feature = "zarr-sink")]
+            OutputFormat::Zarr => return "zarr",
+        }
+    }
+
+    /// Whether several outputs can be written at once from different
+    /// threads. netCDF files are written through the HDF5 library, which
+    /// the datasets are read through too.
+    pub fn writes_in_parallel(self) -> bool {
+        match self {
+            #[cfg(feature = "netcdf-sink")]
+            OutputFormat::Netcdf => return false,
+            #[allow(unreachable_patterns)]
+            _ => return true,
+        }
+    }
+
+    /// Band of the outputs written, from 1.
+    pub fn band(self) -> usize {
+        match self {
+            #[cfg(feature = "gdal")]
+            OutputFormat::Gdal(options) => return options.band.unwrap_or(1),
+            #[allow(unreachable_patterns)]
+            _ => return 1,
+        }
+    }
+
+    /// Whether outputs are created initialised to nodata, so that strips
+    /// without observations can be skipped.
+    pub fn is_sparse(self) -> bool {
+        match self {
+            #[cfg(feature = "gdal")]
+            OutputFormat::Gdal(options) => return options.sparse,
+            #[allow(unreachable_patterns)]
+            _ => return false,
+        }
+    }
+
+    /// Whether byte outputs can embed a color table.
+    pub fn has_color_table(self) -> bool {
+        match self {
+            #[cfg(feature = "gdal")]
+            OutputFormat::Gdal(_) => return true,
+            #[allow(unreachable_patterns)]
+            _ => return false,
+        }
+    }
+
+    /// Creates the sink writing `out_path`. `name` labels the values where
+    /// the format stores one, e.g. the netCDF variable name. `nodata` is the
+    /// value of pixels without observations, which sparse outputs are
+    /// initialised to.
+    pub fn create_sink<T: RasterValue>(
+        self,
+        template: &str,
+        out_path: &str,
+        name: &str,
+        nodata: Option<f64>,
+    ) -> Result<Box<dyn RasterSink<T>>> {
+        let _ = (name, nodata);
+        match self {
+            #[cfg(feature = "gdal")]
+            OutputFormat::Gdal(options) => {
+                let float16 = options.float16
+                    && T::gdal_type() == <f32 as gdal::raster::GdalType>::gdal_type();
+                let (dataset, sparse) = match (options.band, nodata) {
+                    (Some(band), _) => (open_band_target::<T>(template, out_path, band)?, false),
+                    (None, Some(nodata)) if options.sparse => {
+                        let layout = options.layout;
+                        let rast = create_sparse::<T>(template, out_path, nodata, float16, layout)?;
+                        (rast, true)
+                    }
+                    _ if float16 || !options.layout.is_default() => {
+                        let rast = create_new::<T>(template, out_path, float16, options.layout)?;
+                        (rast, false)
+                    }
+                    _ => (create_from_template::<T>(template, out_path)?, false),
+                };
+                return Ok(Box::new(GdalSink {
+                    dataset,
+                    band: options.band.unwrap_or(1) as isize,
+                    band_order: options.band_order,
+                    sparse,
+                }));
+            }
+            #[cfg(feature = "pure-tiff")]
+            OutputFormat::GeoTiff => {
+                return Ok(Box::new(crate::geotiff::TiffSink::new(template, out_path)?))
+            }
+            #[cfg(feature = "netcdf-sink")]
+            OutputFormat::Netcdf => {
+                return Ok(Box::new(crate::netcdf_sink::NetcdfSink::create(
+                    out_path,
+                    name,
+                    template_georef(template)?,
+                )?))
+            }
+            #[cfg(feature = "zarr-sink")]
+            OutputFormat::Zarr => {
+                return Ok(Box::new(crate::zarr_sink::ZarrSink::create(
+                    out_path,
+                    name,
+                    template_georef(template)?,
+                )?))
+            }
+        }
+    }
+}
+
+/// Keeps the raster in memory, for tests and for callers that consume the
+/// values directly instead of reading a file back.
+#[derive(Debug)]
+pub struct MemorySink<T> {
+    vals: Vec<T>,
+    xsize: usize,
+    ysize: usize,
+}
+
+impl<T> MemorySink<T> {
+    pub fn new() -> Self {
+        return MemorySink {
+            vals: vec![],
+            xsize: 0,
+            ysize: 0,
+        };
+    }
+
+    /// The written raster as (rows, columns).
+    pub fn into_array(self) -> Array2<T> {
+        return Array2::from_shape_vec((self.ysize, self.xsize), self.vals).unwrap();
+    }
+}
+
+impl<T> Default for MemorySink<T> {
+    fn default() -> Self {
+        return MemorySink::new();
+    }
+}
+
+impl<T> RasterSink<T> for MemorySink<T> {
+    fn write_strips(
+        &mut self,
+        xsize: usize,
+        ysize: usize,
+        n_lines: usize,
+        next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<T>>,
+    ) -> Result<()> {
+        self.vals = Vec::with_capacity(xsize * ysize);
+        for yy in (0..ysize).step_by(n_lines) {
+            let lines = n_lines.min(ysize - yy);
+            let vals = next_strip(yy, lines)?;
+            if vals.len() != lines * xsize {
+                return Err(Error::Invalid(format!(
+                    "strip at row {} has {} values, expected {}",
+                    yy,
+                    vals.len(),
+                    lines * xsize
+                )));
+            }
+            self.vals.extend(vals);
+        }
+        self.xsize = xsize;
+        self.ysize = ysize;
+        return Ok(());
+    }
+}
+
+/// Copies `template` to `out_path` and opens the copy for update. Paths
+/// under GDAL's `/vsimem/` are created in memory with GDAL's CreateCopy
+/// instead of a file copy, as are classic TIFF templates whose copy may
+/// outgrow 4 GB: those are copied to a BigTIFF keeping their compression and
+/// tiling. Templates of another data type than `T` are not copied; the
+/// output is created with their driver, size, georeferencing and nodata
+/// value (if `T` can hold it) instead.
+#[cfg(feature = "gdal")]
+pub fn create_from_template<T: gdal::raster::GdalType + RangeValue>(
+    template: &str,
+    out_path: &str,
+) -> Result<gdal::Dataset> {
+    let template_path = template;
+    let template = gdal::Dataset::open(std::path::Path::new(template_path))?;
+    let band = template.rasterband(1)?;
+    if band.band_type() != T::gdal_type() {
+        let nodata = band.no_data_value();
+        let rast = create_like::<T>(&template, out_path, vec![])?;
+        if let Some(nodata) = nodata {
+            let value: f64 = T::from_f64(nodata).into();
+            if value == nodata || (value.is_nan() && nodata.is_nan()) {
+                rast.rasterband(1)?.set_no_data_value(Some(nodata))?;
+            }
+        }
+        return Ok(rast);
+    }
+    if out_path.starts_with("/vsimem/") {
+        return Ok(template.create_copy(&template.driver(), out_path, &[])?);
+    }
+    if is_classic_tiff(template_path)? {
+        let (xsize, ysize) = template.raster_size();
+        let n_bands = template.raster_count() as usize;
+        let dtype_size = std::mem::size_of::<T>();
+        if chunking::needs_bigtiff(xsize, ysize, dtype_size, n_bands) {
+            let options = bigtiff_copy_options(&template)?;
+            let options = creation_options(&options);
+            return Ok(template.create_copy(&template.driver(), out_path, &options)?);
+        }
+    }
+    std::fs::copy(template_path, out_path)?;
+    let rast = gdal::Dataset::open_ex(
+        std::path::Path::new(out_path),
+        Some(gdal_sys::GDALAccess::GA_Update),
+        None,
+        None,
+        None,
+    )?;
+    return Ok(rast);
+}
+
+/// Rasters updated in place by [`open_band_target`], which hold other bands
+/// than the one written and so are never removed.
+static UPDATED_IN_PLACE: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+/// Whether the output at `path` is an existing raster whose band was
+/// updated in place.
+pub fn updated_in_place(path: &str) -> bool {
+    return UPDATED_IN_PLACE
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|updated| updated == path);
+}
+
+/// Opens the raster whose band `band` (from 1) an output is written to:
+/// `out_path` itself, for update, if it exists, such as a stack prepared
+/// beforehand or written by earlier runs; else a copy of `template`. The
+/// band must exist and be of `T`.
+#[cfg(feature = "gdal")]
+fn open_band_target<T: gdal::raster::GdalType + RangeValue>(
+    template: &str,
+    out_path: &str,
+    band: usize,
+) -> Result<gdal::Dataset> {
+    let exists = std::path::Path::new(out_path).exists();
+    let checked = match exists {
+        true => out_path,
+        false => template,
+    };
+    let rast = gdal::Dataset::open(std::path::Path::new(checked))?;
+    let n_bands = rast.raster_count() as usize;
+    if band == 0 || band > n_bands {
+        return Err(Error::Invalid(format!(
+            "{} has {} bands, so band {} cannot be written",
+            checked, n_bands, band
+        )));
+    }
+    if rast.rasterband(band as isize)?.band_type() != T::gdal_type() {
+        return Err(Error::Invalid(format!(
+            "band {} of {} is not of the output type {}",
+            band,
+            checked,
+            std::any::type_name::<T>()
+        )));
+    }
+    drop(rast);
+    if !exists {
+        return create_from_template::<T>(template, out_path);
+    }
+    println!("Writing band {} of {} in place", band, out_path);
+    UPDATED_IN_PLACE.lock().unwrap().push(out_path.to_owned());
+    return Ok(gdal::Dataset::open_ex(
+        std::path::Path::new(out_path),
+        Some(gdal_sys::GDALAccess::GA_Update),
+        None,
+        None,
+        None,
+    )?);
+}
+
+/// Whether the file at `path` is a classic TIFF, whose offsets are 32-bit,
+/// from its header. BigTIFFs and other formats are not.
+pub fn is_classic_tiff(path: &str) -> io::Result<bool> {
+    use std::io::Read;
+
+    let mut header = [0u8; 4];
+    if std::fs::File::open(path)?.read_exact(&mut header).is_err() {
+        return Ok(false);
+    }
+    return Ok(&header == b"II*\0" || &header == b"MM\0*");
+}
+
+/// Creation options copying a GeoTIFF `template` to a BigTIFF with the same
+/// compression and tiling.
+#[cfg(feature = "gdal")]
+fn bigtiff_copy_options(template: &gdal::Dataset) -> Result<Vec<(&'static str, String)>> {
+    use gdal::Metadata;
+
+    let mut options = vec![("BIGTIFF", "YES".to_owned())];
+    if let Some(compression) = template.metadata_item("COMPRESSION", "IMAGE_STRUCTURE") {
+        options.push(("COMPRESS", compression));
+    }
+    let (block_xsize, block_ysize) = template.rasterband(1)?.block_size();
+    if block_xsize < template.raster_size().0 {
+        options.push(("TILED", "YES".to_owned()));
+        options.push(("BLOCKXSIZE", block_xsize.to_string()));
+        options.push(("BLOCKYSIZE", block_ysize.to_string()));
+    }
+    return Ok(options);
+}
+
+/// `BIGTIFF=YES` when `driver` writes GeoTIFFs and the raster may outgrow
+/// the 4 GB of a classic TIFF; GDAL only switches to BigTIFF by itself for
+/// uncompressed outputs.
+#[cfg(feature = "gdal")]
+fn bigtiff_option(
+    driver: &gdal::Driver,
+    xsize: usize,
+    ysize: usize,
+    dtype_size: usize,
+    n_bands: usize,
+) -> Option<gdal::raster::RasterCreationOption<'static>> {
+    if driver.short_name() != "GTiff" || !chunking::needs_bigtiff(xsize, ysize, dtype_size, n_bands)
+    {
+        return None;
+    }
+    return Some(gdal::raster::RasterCreationOption {
+        key: "BIGTIFF",
+        value: "YES",
+    });
+}
+
+/// Creation options of new outputs of `template` with 16-bit floats
+/// (`NBITS=16`, IEEE half precision) if `float16`, and with `layout`. Only
+/// GeoTIFFs have them.
+#[cfg(feature = "gdal")]
+fn new_output_options(
+    template: &gdal::Dataset,
+    template_path: &str,
+    float16: bool,
+    layout: BlockLayout,
+) -> Result<Vec<(&'static str, String)>> {
+    let mut options = vec![];
+    if float16 {
+        options.push(("NBITS", "16".to_owned()));
+    }
+    options.extend(layout.creation_options());
+    if !options.is_empty() && template.driver().short_name() != "GTiff" {
+        return Err(Error::Invalid(format!(
+            "{} is not a GeoTIFF, so its outputs cannot be written as Float16 or with \
+             another interleave or block size",
+            template_path
+        )));
+    }
+    return Ok(options);
+}
+
+#[cfg(feature = "gdal")]
+fn creation_options<'a>(
+    options: &'a [(&'static str, String)],
+) -> Vec<gdal::raster::RasterCreationOption<'a>> {
+    return options
+        .iter()
+        .map(|(key, value)| gdal::raster::RasterCreationOption {
+            key: *key,
+            value: value.as_str(),
+        })
+        .collect();
+}
+
+/// Creates `out_path` as a single-band sparse file with the driver, size
+/// and georeferencing of `template`, with 16-bit floats if `float16` and
+/// with `layout`. Blocks that are never written take no space and read back
+/// as `nodata`.
+#[cfg(feature = "gdal")]
+pub fn create_sparse<T: gdal::raster::GdalType>(
+    template: &str,
+    out_path: &str,
+    nodata: f64,
+    float16: bool,
+    layout: BlockLayout,
+) -> Result<gdal::Dataset> {
+    let template_path = template;
+    let template = gdal::Dataset::open(std::path::Path::new(template_path))?;
+    let mut options = vec![("SPARSE_OK", "TRUE".to_owned())];
+    options.extend(new_output_options(
+        &template,
+        template_path,
+        float16,
+        layout,
+    )?);
+    let rast = create_like::<T>(&template, out_path, creation_options(&options))?;
+    rast.rasterband(1)?.set_no_data_value(Some(nodata))?;
+    return Ok(rast);
+}
+
+/// Creates `out_path` as a GeoTIFF with the size, georeferencing,
+/// compression and nodata value of `template`, with 16-bit floats if
+/// `float16` and with `layout`, instead of a copy of it, which keeps the
+/// template's own.
+#[cfg(feature = "gdal")]
+pub fn create_new<T: gdal::raster::GdalType>(
+    template: &str,
+    out_path: &str,
+    float16: bool,
+    layout: BlockLayout,
+) -> Result<gdal::Dataset> {
+    use gdal::Metadata;
+
+    let template_path = template;
+    let template = gdal::Dataset::open(std::path::Path::new(template_path))?;
+    let mut options = new_output_options(&template, template_path, float16, layout)?;
+    if let Some(compression) = template.metadata_item("COMPRESSION", "IMAGE_STRUCTURE") {
+        options.push(("COMPRESS", compression));
+    }
+    let rast = create_like::<T>(&template, out_path, creation_options(&options))?;
+    let nodata = template.rasterband(1)?.no_data_value();
+    rast.rasterband(1)?.set_no_data_value(nodata)?;
+    return Ok(rast);
+}
+
+/// Creates `out_path` as an empty single-band raster of `T` with the driver,
+/// size and georeferencing of `template`, with the creation `options` and
+/// BigTIFF when needed.
+#[cfg(feature = "gdal")]
+fn create_like<T: gdal::raster::GdalType>(
+    template: &gdal::Dataset,
+    out_path: &str,
+    mut options: Vec<gdal::raster::RasterCreationOption>,
+) -> Result<gdal::Dataset> {
+    let (xsize, ysize) = template.raster_size();
+    let driver = template.driver();
+    let dtype_size = std::mem::size_of::<T>();
+    options.extend(bigtiff_option(&driver, xsize, ysize, dtype_size, 1));
+    let mut rast = driver.create_with_band_type_with_options::<T, _>(
+        out_path,
+        xsize as isize,
+        ysize as isize,
+        1,
+        &options,
+    )?;
+    rast.set_geo_transform(&template.geo_transform()?)?;
+    rast.set_projection(&template.projection())?;
+    return Ok(rast);
+}
+
+/// Writes band `band` (from 1) of a GDAL dataset, laying buffers out in
+/// `band_order`. Strips are pulled in whole rows of the band's blocks and,
+/// for tiled outputs, written tile by tile, so no block is written more than
+/// once. Sparse datasets, created initialised to nodata, skip empty strips.
+#[cfg(feature = "gdal")]
+pub struct GdalSink {
+    pub dataset: gdal::Dataset,
+    pub band: isize,
+    pub band_order: BandOrder,
+    pub sparse: bool,
+}
+
+#[cfg(feature = "gdal")]
+impl<T: gdal::raster::GdalType + Copy> RasterSink<T> for GdalSink {
+    fn write_strips(
+        &mut self,
+        xsize: usize,
+        ysize: usize,
+        n_lines: usize,
+        next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<T>>,
+    ) -> Result<()> {
+        let band = self.dataset.rasterband(self.band)?;
+        let (block_xsize, block_lines) = band.block_size();
+        let n_lines = block::aligned_lines(n_lines, block_lines);
+        for yy in (0..ysize).step_by(n_lines) {
+            let lines = n_lines.min(ysize - yy);
+            let vals = next_strip(yy, lines)?;
+            if self.sparse && vals.is_empty() {
+                continue;
+            }
+            let strip = RasterBlock::from_rows(vals, xsize, lines)?;
+            if block_xsize >= xsize {
+                let size = strip.gdal_size();
+                let buffer = strip.into_gdal_buffer(self.band_order);
+                retry::with_retries(
+                    &format!("writing rows {} of band {}", yy, self.band),
+                    || {
+                        return Ok(band.write((0, yy as isize), size, &buffer)?);
+                    },
+                )?;
+                continue;
+            }
+            for ((x, y), tile) in strip.tiles((block_xsize, block_lines)) {
+                let size = tile.gdal_size();
+                let buffer = tile.into_gdal_buffer(self.band_order);
+                let offset = (x as isize, (yy + y) as isize);
+                retry::with_retries(
+                    &format!("writing block {:?} of band {}", offset, self.band),
+                    || {
+                        return Ok(band.write(offset, size, &buffer)?);
+                    },
+                )?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn write_blocks(
+        &mut self,
+        xsize: usize,
+        ysize: usize,
+        block_cols: usize,
+        next_block: &mut dyn FnMut(usize, usize, usize) -> Result<Vec<T>>,
+    ) -> Result<()> {
+        let band = self.dataset.rasterband(self.band)?;
+        for block in chunking::blocks(xsize, ysize, 1, block_cols) {
+            let vals = next_block(block.yy, block.x0, block.cols)?;
+            if self.sparse && vals.is_empty() {
+                continue;
+            }
+            let buffer =
+                RasterBlock::from_rows(vals, block.cols, 1)?.into_gdal_buffer(self.band_order);
+            let offset = (block.x0 as isize, block.yy as isize);
+            retry::with_retries(
+                &format!("writing block {:?} of band {}", offset, self.band),
+                || {
+                    return Ok(band.write(offset, (block.cols, 1), &buffer)?);
+                },
+            )?;
+        }
+        return Ok(());
+    }
+
+    fn skips_empty(&self) -> bool {
+        return self.sparse;
+    }
+
+    fn set_nodata(&mut self, nodata: f64) -> Result<()> {
+        self.dataset
+            .rasterband(self.band)?
+            .set_no_data_value(Some(nodata))?;
+        return Ok(());
+    }
+
+    fn set_units(&mut self, units: &str) -> Result<()> {
+        let c_units = std::ffi::CString::new(units)
+            .map_err(|_| Error::Invalid(format!("invalid units '{}'", units)))?;
+        let band = self.dataset.rasterband(self.band)?;
+        let err = unsafe { gdal_sys::GDALSetRasterUnitType(band.c_rasterband(), c_units.as_ptr()) };
+        if err != gdal_sys::CPLErr::CE_None {
+            return Err(Error::Invalid(format!(
+                "could not set the units to {}",
+                units
+            )));
+        }
+        return Ok(());
+    }
+
+    fn set_metadata(&mut self, domain: &str, items: &[(String, String)]) -> Result<()> {
+        use gdal::Metadata;
+
+        for (name, value) in items {
+            self.dataset.set_metadata_item(name, value, domain)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Writes the datasets `bands`, given as (band name, dataset path, unit
+/// conversion), as the bands of one GeoTIFF at `out_path` with the size and georeferencing of
+/// `template`, and the band order, layout and 16-bit floats of `options`.
+/// Every band gets its name as description and `nodata`; values outside
+/// `valid_range` are replaced.
+///
+/// `out_path` is pushed to `created` as soon as the file exists; outputs
+/// that already exist are handled according to `policy`.
+#[cfg(feature = "gdal")]
+pub fn write_stack<T, S>(
+    source: &S,
+    bands: &[(String, String, Option<UnitConversion>)],
+    template: &str,
+    out_path: &str,
+    options: GdalOptions,
+    nodata: f64,
+    valid_range: Option<ValidRange>,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    block_cols: usize,
+    policy: ExistsPolicy,
+    created: &mut Vec<String>,
+) -> Result<()>
+where
+    T: RasterValue,
+    S: HdfSource<T>,
+{
+    use gdal::Metadata;
+
+    if policy.decide_file(out_path)? == Decision::Skip {
+        return Ok(());
+    }
+    for (_, ds_path, _) in bands {
+        if source.len(ds_path)? < xsize * ysize {
+            return Err(Error::Invalid(format!(
+                "dataset {} is smaller than the {}x{} template",
+                ds_path, xsize, ysize
+            )));
+        }
+    }
+    let template = gdal::Dataset::open(std::path::Path::new(template))?;
+    let driver = gdal::Driver::get("GTiff")?;
+    let dtype_size = std::mem::size_of::<T>();
+    let mut layout = options.layout.creation_options();
+    if options.float16 && T::gdal_type() == <f32 as gdal::raster::GdalType>::gdal_type() {
+        layout.push(("NBITS", "16".to_owned()));
+    }
+    let mut creation = creation_options(&layout);
+    creation.extend(bigtiff_option(
+        &driver,
+        xsize,
+        ysize,
+        dtype_size,
+        bands.len(),
+    ));
+    let dataset = driver.create_with_band_type_with_options::<T, _>(
+        out_path,
+        xsize as isize,
+        ysize as isize,
+        bands.len() as isize,
+        &creation,
+    );
+    created.push(out_path.to_owned());
+    let mut dataset = dataset?;
+    dataset.set_geo_transform(&template.geo_transform()?)?;
+    dataset.set_projection(&template.projection())?;
+
+    let mut sink = GdalSink {
+        dataset,
+        band: 1,
+        band_order: options.band_order,
+        sparse: false,
+    };
+    let provenance = provenance::get();
+    let ds_paths: Vec<&str> = bands
+        .iter()
+        .map(|(_, ds_path, _)| ds_path.as_str())
+        .collect();
+    if let Some(provenance) = &provenance {
+        let items = provenance.metadata_items(&ds_paths);
+        RasterSink::<T>::set_metadata(&mut sink, provenance::DOMAIN, &items)?;
+    }
+    for (ii, (name, ds_path, units)) in bands.iter().enumerate() {
+        println!("Band {} of {}: {}", ii + 1, bands.len(), name);
+        sink.band = ii as isize + 1;
+        let mut band = sink.dataset.rasterband(sink.band)?;
+        band.set_description(name)?;
+        band.set_no_data_value(Some(nodata))?;
+        if let Some(units) = units {
+            RasterSink::<T>::set_units(&mut sink, &units.units)?;
+        }
+        let filtered = RangeFiltered::new(source, valid_range, Some(nodata));
+        let scaled = Scaled::new(&filtered, units.as_ref(), Some(nodata));
+        write_dataset_raster(
+            &scaled,
+            ds_path,
+            &mut sink,
+            xsize,
+            ysize,
+            n_lines_read,
+            block_cols,
+            None,
+        )?;
+        status::end_progress();
+        if let Some(record) = filtered.finish(&format!("{} band {}", out_path, ii + 1)) {
+            valid_range::record(record);
+        }
+    }
+    if let Some(provenance) = &provenance {
+        provenance.write_sidecar(out_path, &ds_paths, created)?;
+    }
+    return Ok(());
+}
+
+/// Raster shape of `template` as (xsize, ysize).
+#[cfg(feature = "gdal")]
+pub fn template_size(template: &str) -> Result<(usize, usize)> {
+    let rast = gdal::Dataset::open(std::path::Path::new(template))?;
+    let band = rast.rasterband(1)?;
+    return Ok((band.x_size(), band.y_size()));
+}
+
+/// Raster shape of `template` as (xsize, ysize).
+#[cfg(not(feature = "gdal"))]
+pub fn template_size(template: &str) -> Result<(usize, usize)> {
+    let template = crate::geotiff::GeoTiffTemplate::read(template)?;
+    return Ok((template.xsize, template.ysize));
+}
+
+/// Georeferencing of `template`.
+#[cfg(feature = "gdal")]
+pub fn template_georef(template: &str) -> Result<GeoRef> {
+    let rast = gdal::Dataset::open(std::path::Path::new(template))?;
+    return Ok(GeoRef {
+        geo_transform: rast.geo_transform()?,
+        crs: rast.projection(),
+    });
+}
+
+/// Georeferencing of `template`.
+#[cfg(not(feature = "gdal"))]
+pub fn template_georef(template: &str) -> Result<GeoRef> {
+    return Ok(crate::geotiff::GeoTiffTemplate::read(template)?.georef());
+}
+
+/// Removes an output left behind by a failed step, whether a file or a
+/// directory store such as Zarr. Rasters updated in place are kept.
+pub fn remove_output(path: &str) -> io::Result<()> {
+    if updated_in_place(path) {
+        return Ok(());
+    }
+    if std::path::Path::new(path).is_dir() {
+        return std::fs::remove_dir_all(path);
+    }
+    return std::fs::remove_file(path);
+}
+
+/// Statistic names and the reversed datasets they are written from, for the
+/// group at `group_name`.
+pub fn group_outputs(group_name: &str, count_name: &str) -> [(&'static str, String); 3] {
+    return [
+        ("mean", hdf::join_path(group_name, "mean_rev")),
+        ("sd", hdf::join_path(group_name, "sd_rev")),
+        (
+            "count",
+            hdf::join_path(group_name, &format!("{}_rev", count_name)),
+        ),
+    ];
+}
+
+/// Value of a pixel without observations in the output of statistic
+/// `stat`: no count, a NaN mean (0 / 0) and the sd of -1 set by the stats
+/// kernel.
+pub fn empty_value(stat: &str) -> f64 {
+    return match stat {
+        "count" | "quality" => 0f64,
+        "sd" => -1f64,
+        _ => f64::NAN,
+    };
+}
+
+/// Whether the output of `stat` is written as bytes, with the byte
+/// template: the count and the quality classes.
+pub fn is_byte_output(stat: &str) -> bool {
+    return stat == "count" || stat == "quality";
+}
+
+/// Flags the strips of `n_lines_read` rows of the count dataset at
+/// `count_path` in which no pixel has an observation. Rows wider than
+/// `block_cols` are strips of their own, read in blocks.
+pub fn empty_strips<S: HdfSource<u8>>(
+    source: &S,
+    count_path: &str,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    block_cols: usize,
+) -> Result<Vec<bool>> {
+    let mut empty: Vec<bool> = vec![];
+    for block in chunking::blocks(xsize, ysize, n_lines_read, block_cols) {
+        interrupt::check()?;
+        let range = block.range(xsize);
+        let counts = source.read_range(count_path, range.start, range.end)?;
+        let block_empty = counts.iter().all(|&count| count == 0);
+        match empty.last_mut() {
+            Some(strip_empty) if block.x0 > 0 => *strip_empty &= block_empty,
+            _ => empty.push(block_empty),
+        }
+    }
+    return Ok(empty);
+}
+
+/// Directory of the outputs named by [`output_path`] and
+/// [`dataset_output_path`], or the working directory.
+static OUTPUT_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Writes the outputs named after groups and datasets to `dir` instead of
+/// the working directory.
+pub fn set_output_dir(dir: Option<PathBuf>) {
+    *OUTPUT_DIR.lock().unwrap() = dir;
+}
+
+fn in_output_dir(name: String) -> String {
+    return match OUTPUT_DIR.lock().unwrap().as_ref() {
+        Some(dir) => dir.join(name).to_string_lossy().into_owned(),
+        None => name,
+    };
+}
+
+/// Output file of statistic `stat` of the group at `group_name`; the group
+/// path is flattened into the file name.
+pub fn output_path(group_name: &str, stat: &str, extension: &str) -> String {
+    return in_output_dir(format!(
+        "{}_cerrado_{}_{}.{}",
+        100,
+        hdf::flatten_path(group_name),
+        stat,
+        extension
+    ));
+}
+
+/// Statistic of the output at `path` of the group at path `group_name`, as
+/// named by [`output_path`].
+pub fn output_stat<'a>(group_name: &str, path: &'a str) -> Option<&'a str> {
+    let prefix = format!("{}_cerrado_{}_", 100, hdf::flatten_path(group_name));
+    let name = Path::new(path)
+        .file_name()?
+        .to_str()?
+        .strip_prefix(&prefix)?;
+    return Some(&name[..name.rfind('.')?]);
+}
+
+/// Output file of a dataset converted on its own; its path is flattened into
+/// the file name.
+pub fn dataset_output_path(ds_path: &str, extension: &str) -> String {
+    return in_output_dir(format!(
+        "{}_cerrado_{}.{}",
+        100,
+        hdf::flatten_path(ds_path),
+        extension
+    ));
+}
+
+/// Writes the count, mean and sd rasters of the group at path `group_name`,
+/// which may be nested; its path is flattened into the output file names.
+///
+/// The three datasets are read in a single pass on the calling thread, each
+/// strip once, and handed to the outputs, which are written at once from a
+/// thread each (one after the other in formats that cannot be written in
+/// parallel). With a [`StatSource`](crate::stats::StatSource) computing the
+/// mean and sd, each strip of the sums is read once too.
+///
+/// `extra_stats` (e.g. `se`, or the outputs of kernels) are written as float outputs too, from the
+/// datasets `<stat>_rev` of the group in `source`.
+///
+/// Every output path is pushed to `created` as soon as the file exists, so
+/// the caller knows which files are partial if this returns an error.
+/// Outputs that already exist are handled according to `policy`. With a
+/// sparse `format`, strips without any count are left unwritten. Means
+/// outside `mean_range` are replaced; float outputs are converted to
+/// `units`, counts are written as is.
+pub fn write_group_rasters<S>(
+    source: &S,
+    group_name: &str,
+    count_name: &str,
+    format: OutputFormat,
+    base_float_path: &str,
+    base_byte_path: &str,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    block_cols: usize,
+    policy: ExistsPolicy,
+    mean_range: Option<ValidRange>,
+    units: Option<&UnitConversion>,
+    extra_stats: &[String],
+    created: &mut Vec<String>,
+) -> Result<()>
+where
+    S: HdfSource<u8> + HdfSource<f32>,
+{
+    let [mean, sd, count] = group_outputs(group_name, count_name);
+    let empty = if format.is_sparse() {
+        let empty = empty_strips(source, &count.1, xsize, ysize, n_lines_read, block_cols)?;
+        let n_empty = empty.iter().filter(|&&empty| empty).count();
+        println!("{} of {} strips have no observations", n_empty, empty.len());
+        Some(empty)
+    } else {
+        None
+    };
+    // Extra statistics come first, so that a `StatSource` computes them
+    // from the strips it then hands out as the mean, sd and count, except
+    // those written as bytes, which are derived from the count and follow
+    // it.
+    let extra_output = |stat: &String| -> (&str, String) {
+        return (
+            stat.as_str(),
+            hdf::join_path(group_name, &format!("{}_rev", stat)),
+        );
+    };
+    let mut outputs: Vec<(&str, String)> = extra_stats
+        .iter()
+        .filter(|stat| !is_byte_output(stat))
+        .map(extra_output)
+        .collect();
+    outputs.extend(vec![mean, sd, count]);
+    outputs.extend(
+        extra_stats
+            .iter()
+            .filter(|stat| is_byte_output(stat))
+            .map(extra_output),
+    );
+    let group = GroupOutputs {
+        group_name,
+        outputs: &outputs,
+        format,
+        base_float_path,
+        base_byte_path,
+        xsize,
+        ysize,
+        n_lines_read,
+        block_cols,
+        policy,
+        mean_range,
+        units,
+        empty: empty.as_deref(),
+    };
+    if !format.writes_in_parallel() {
+        for (stat, ds_path) in outputs.iter() {
+            if is_byte_output(stat) {
+                group.write::<u8, S>(source, stat, ds_path, created)?;
+            } else {
+                group.write::<f32, S>(source, stat, ds_path, created)?;
+            }
+        }
+        return Ok(());
+    }
+    return group.write_in_one_pass(source, created);
+}
+
+/// The outputs of a group and how they are written.
+struct GroupOutputs<'a> {
+    group_name: &'a str,
+    /// Statistic and reversed dataset of each output, in the order they are
+    /// read.
+    outputs: &'a [(&'a str, String)],
+    format: OutputFormat,
+    base_float_path: &'a str,
+    base_byte_path: &'a str,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    /// Columns of the blocks of rows wider than the memory limit allows.
+    block_cols: usize,
+    policy: ExistsPolicy,
+    mean_range: Option<ValidRange>,
+    units: Option<&'a UnitConversion>,
+    empty: Option<&'a [bool]>,
+}
+
+impl<'a> GroupOutputs<'a> {
+    /// Writes the output of statistic `stat` from the dataset at `ds_path`
+    /// of `source`.
+    fn write<T, S>(
+        &self,
+        source: &S,
+        stat: &str,
+        ds_path: &str,
+        created: &mut Vec<String>,
+    ) -> Result<()>
+    where
+        T: RasterValue,
+        S: HdfSource<T>,
+    {
+        let is_byte = is_byte_output(stat);
+        return write_output::<T, S>(
+            source,
+            ds_path,
+            self.format,
+            if is_byte {
+                self.base_byte_path
+            } else {
+                self.base_float_path
+            },
+            &output_path(self.group_name, stat, self.format.extension()),
+            stat,
+            self.xsize,
+            self.ysize,
+            self.n_lines_read,
+            self.block_cols,
+            self.policy,
+            Some(empty_value(stat)),
+            if stat == "mean" {
+                self.mean_range
+            } else {
+                None
+            },
+            if is_byte { None } else { self.units },
+            self.empty
+                .map(|strips| EmptyStrips::new(strips, empty_value(stat))),
+            created,
+        );
+    }
+
+    /// Reads the strips of the datasets of the outputs once each on this
+    /// thread, in the order of the outputs, and hands them to the outputs,
+    /// written at once from a thread each.
+    fn write_in_one_pass<S>(&self, source: &S, created: &mut Vec<String>) -> Result<()>
+    where
+        S: HdfSource<u8> + HdfSource<f32>,
+    {
+        let (xsize, ysize) = (self.xsize, self.ysize);
+        for (stat, ds_path) in self.outputs.iter() {
+            let len = match is_byte_output(stat) {
+                true => HdfSource::<u8>::len(source, ds_path)?,
+                false => HdfSource::<f32>::len(source, ds_path)?,
+            };
+            if len < xsize * ysize {
+                return Err(too_small(ds_path, xsize, ysize));
+            }
+        }
+
+        let failed = AtomicBool::new(false);
+        let owner = std::thread::current().id();
+        let (read, written) = std::thread::scope(|scope| {
+            let mut senders = vec![];
+            let mut writers = vec![];
+            for (stat, ds_path) in self.outputs.iter() {
+                let receiver = if is_byte_output(stat) {
+                    let (sender, receiver) = mpsc::sync_channel(0);
+                    senders.push(StripSender::U8(Some(sender)));
+                    StripReceiver::U8(receiver)
+                } else {
+                    let (sender, receiver) = mpsc::sync_channel(0);
+                    senders.push(StripSender::F32(Some(sender)));
+                    StripReceiver::F32(receiver)
+                };
+                let failed = &failed;
+                writers.push(scope.spawn(move || {
+                    timing::help(owner);
+                    let mut created = vec![];
+                    let len = xsize * ysize;
+                    let result = match receiver {
+                        StripReceiver::U8(receiver) => {
+                            let feed = StripFeed::new(len, receiver);
+                            self.write::<u8, _>(&feed, stat, ds_path, &mut created)
+                        }
+                        StripReceiver::F32(receiver) => {
+                            let feed = StripFeed::new(len, receiver);
+                            self.write::<f32, _>(&feed, stat, ds_path, &mut created)
+                        }
+                    };
+                    // Only the first failure is the cause; the others come
+                    // from the reads stopping.
+                    let first = result.is_err() && !failed.swap(true, Ordering::SeqCst);
+                    return (result, first, created);
+                }));
+            }
+
+            let read = (|| -> Result<()> {
+                let strip_lines = chunking::block_lines(xsize, self.n_lines_read, self.block_cols);
+                let blocks = chunking::blocks(xsize, ysize, self.n_lines_read, self.block_cols);
+                for block in blocks {
+                    interrupt::check()?;
+                    if failed.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+                    let range = block.range(xsize);
+                    let range = (range.start, range.end);
+                    let strip = block.yy / strip_lines;
+                    let is_empty = self.empty.map_or(false, |empty| empty[strip]);
+                    for (sender, output) in senders.iter_mut().zip(self.outputs.iter()) {
+                        match sender {
+                            StripSender::U8(sender) => {
+                                send_strip(sender, source, output, range, is_empty)?
+                            }
+                            StripSender::F32(sender) => {
+                                send_strip(sender, source, output, range, is_empty)?
+                            }
+                        }
+                    }
+                    if senders.iter().all(StripSender::is_closed) {
+                        return Ok(());
+                    }
+                }
+                return Ok(());
+            })();
+            drop(senders);
+            let written: Vec<(Result<()>, bool, Vec<String>)> = writers
+                .into_iter()
+                .map(|writer| writer.join().expect("output writer panicked"))
+                .collect();
+            return (read, written);
+        });
+
+        let mut result = read;
+        for (written, first, paths) in written {
+            created.extend(paths);
+            if result.is_ok() && first {
+                result = written;
+            }
+        }
+        return result;
+    }
+}
+
+fn too_small(ds_path: &str, xsize: usize, ysize: usize) -> Error {
+    return Error::Invalid(format!(
+        "dataset {} is smaller than the {}x{} template",
+        ds_path, xsize, ysize
+    ));
+}
+
+/// Values of a strip of a group pass and the index of its first element.
+type Strip<T> = (usize, Vec<T>);
+
+enum StripSender {
+    U8(Option<mpsc::SyncSender<Strip<u8>>>),
+    F32(Option<mpsc::SyncSender<Strip<f32>>>),
+}
+
+impl StripSender {
+    /// Whether the output has stopped reading.
+    fn is_closed(&self) -> bool {
+        return match self {
+            StripSender::U8(sender) => sender.is_none(),
+            StripSender::F32(sender) => sender.is_none(),
+        };
+    }
+}
+
+enum StripReceiver {
+    U8(mpsc::Receiver<Strip<u8>>),
+    F32(mpsc::Receiver<Strip<f32>>),
+}
+
+/// Reads the elements `start..end` of the dataset of `output` and sends
+/// them on `sender`, unless the output has stopped reading, in which case
+/// `sender` is dropped. Empty strips are filled without reading.
+fn send_strip<T, S>(
+    sender: &mut Option<mpsc::SyncSender<Strip<T>>>,
+    source: &S,
+    (stat, ds_path): &(&str, String),
+    (start, end): (usize, usize),
+    is_empty: bool,
+) -> Result<()>
+where
+    T: RangeValue,
+    S: HdfSource<T>,
+{
+    let channel = match sender.as_ref() {
+        Some(channel) => channel,
+        None => return Ok(()),
+    };
+    let vals = match is_empty {
+        true => vec![T::from_f64(empty_value(stat)); end - start],
+        false => source.read_range(ds_path, start, end)?,
+    };
+    if channel.send((start, vals)).is_err() {
+        *sender = None;
+    }
+    return Ok(());
+}
+
+/// The strips of one output received from a group pass, read back as a
+/// source of ranges of whole rows, top to bottom. Rows the output skips
+/// are dropped.
+struct StripFeed<T> {
+    len: usize,
+    receiver: mpsc::Receiver<Strip<T>>,
+    /// Values received but not read yet, and the index of the first one.
+    pending: RefCell<Strip<T>>,
+}
+
+impl<T> StripFeed<T> {
+    fn new(len: usize, receiver: mpsc::Receiver<Strip<T>>) -> Self {
+        return StripFeed {
+            len,
+            receiver,
+            pending: RefCell::new((0, vec![])),
+        };
+    }
+}
+
+impl<T> HdfSource<T> for StripFeed<T> {
+    fn len(&self, _path: &str) -> Result<usize> {
+        return Ok(self.len);
+    }
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> Result<Vec<T>> {
+        let mut pending = self.pending.borrow_mut();
+        let (first, vals) = &mut *pending;
+        loop {
+            let skipped = start.saturating_sub(*first).min(vals.len());
+            vals.drain(..skipped);
+            *first += skipped;
+            if *first <= start && *first + vals.len() >= end {
+                break;
+            }
+            let (strip_first, strip) = self.receiver.recv().map_err(|_| {
+                return Error::Invalid(format!("reading {} stopped", path));
+            })?;
+            if vals.is_empty() {
+                *first = strip_first;
+            }
+            vals.extend(strip);
+        }
+        let out = vals.drain(..end - start).collect();
+        *first = end;
+        return Ok(out);
+    }
+}
+
+/// Strips of an output known to hold no observation, and the value of their
+/// pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct EmptyStrips<'a> {
+    pub strips: &'a [bool],
+    pub value: f64,
+}
+
+impl<'a> EmptyStrips<'a> {
+    pub fn new(strips: &'a [bool], value: f64) -> Self {
+        return EmptyStrips { strips, value };
+    }
+}
+
+/// Creates the output `out_path` in `format` from `template`, unless `policy`
+/// says to keep an existing one, and fills it with the dataset at `ds_path`.
+/// `nodata` is stored as the nodata value of the output. Values outside
+/// `valid_range` are replaced as they are written, then converted to
+/// `units`; when summaries are enabled, the values other than `nodata` are
+/// summarized in the converted units.
+pub fn write_output<T, S>(
+    source: &S,
+    ds_path: &str,
+    format: OutputFormat,
+    template: &str,
+    out_path: &str,
+    name: &str,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    block_cols: usize,
+    policy: ExistsPolicy,
+    nodata: Option<f64>,
+    valid_range: Option<ValidRange>,
+    units: Option<&UnitConversion>,
+    empty: Option<EmptyStrips>,
+    created: &mut Vec<String>,
+) -> Result<()>
+where
+    T: RasterValue,
+    S: HdfSource<T>,
+{
+    if policy.decide_file(out_path)? == Decision::Skip {
+        return Ok(());
+    }
+    // Values past the template grid were reported when the dataset was
+    // reversed, and are ignored.
+    if source.len(ds_path)? < xsize * ysize {
+        return Err(Error::Invalid(format!(
+            "dataset {} is smaller than the {}x{} template",
+            ds_path, xsize, ysize
+        )));
+    }
+    let sink = format.create_sink::<T>(template, out_path, name, empty.map(|empty| empty.value));
+    created.push(out_path.to_owned());
+    let mut sink = sink?;
+    if let Some(nodata) = nodata {
+        sink.set_nodata(nodata)?;
+    }
+    if let Some(units) = units {
+        sink.set_units(&units.units)?;
+    }
+    let provenance = provenance::get();
+    if let Some(provenance) = &provenance {
+        sink.set_metadata(provenance::DOMAIN, &provenance.metadata_items(&[ds_path]))?;
+    }
+    let empty_strips = empty.map(|empty| empty.strips);
+    let filtered = RangeFiltered::new(source, valid_range, nodata);
+    let scaled = Scaled::new(&filtered, units, nodata);
+    if summary::is_enabled() {
+        let summarized = Summarized::new(&scaled, nodata);
+        write_dataset_raster(
+            &summarized,
+            ds_path,
+            &mut *sink,
+            xsize,
+            ysize,
+            n_lines_read,
+            block_cols,
+            empty_strips,
+        )?;
+        status::end_progress();
+        summary::record(summarized.finish(out_path, xsize * ysize));
+    } else {
+        write_dataset_raster(
+            &scaled,
+            ds_path,
+            &mut *sink,
+            xsize,
+            ysize,
+            n_lines_read,
+            block_cols,
+            empty_strips,
+        )?;
+        status::end_progress();
+    }
+    if let Some(record) = filtered.finish(out_path) {
+        valid_range::record(record);
+    }
+    if let Some(provenance) = &provenance {
+        provenance.write_sidecar(out_path, &[ds_path], created)?;
+    }
+    return Ok(());
+}
+
+/// Writes the (already reversed) dataset at `ds_path` into `sink`, in strips
+/// of `n_lines_read` rows, or one row at a time in blocks of `block_cols`
+/// columns when that is less than `xsize`. Strips flagged in `empty_strips`
+/// are neither read nor written when the sink skips empty strips; sinks
+/// pulling strips of another height skip those covered only by flagged
+/// strips. The time the sink spends outside of pulling strips is timed as
+/// writing.
+pub fn write_dataset_raster<T, S>(
+    source: &S,
+    ds_path: &str,
+    sink: &mut dyn RasterSink<T>,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    block_cols: usize,
+    empty_strips: Option<&[bool]>,
+) -> Result<()>
+where
+    S: HdfSource<T>,
+{
+    let empty_strips = empty_strips.filter(|_| sink.skips_empty());
+    let strip_lines = chunking::block_lines(xsize, n_lines_read, block_cols);
+    let mut next_block = |yy: usize, lines: usize, x0: usize, cols: usize| -> Result<Vec<T>> {
+        interrupt::check()?;
+        status::set_progress(yy as f32 / ysize as f32);
+        status::print_progress()?;
+
+        let row_end = x0 + cols == xsize;
+        if let Some(empty_strips) = empty_strips {
+            let flagged = yy / strip_lines..(yy + lines - 1) / strip_lines + 1;
+            if empty_strips[flagged].iter().all(|&empty| empty) {
+                if row_end {
+                    status::add_rows(lines);
+                }
+                return Ok(vec![]);
+            }
+        }
+        let start = yy * xsize + x0;
+        let vals = source.read_range(ds_path, start, start + (lines - 1) * xsize + cols)?;
+        if row_end {
+            status::add_rows(lines);
+        }
+        return Ok(vals);
+    };
+    let started = Instant::now();
+    let mut pulling = Duration::ZERO;
+    let mut bytes = 0;
+    let mut timed_block = |yy: usize, lines: usize, x0: usize, cols: usize| -> Result<Vec<T>> {
+        let pull_started = Instant::now();
+        let vals = next_block(yy, lines, x0, cols)?;
+        pulling += pull_started.elapsed();
+        let block_bytes = vals.len() * std::mem::size_of::<T>();
+        status::add_bytes(block_bytes);
+        bytes += block_bytes;
+        return Ok(vals);
+    };
+    let result = match block_cols < xsize {
+        true => sink.write_blocks(xsize, ysize, block_cols, &mut |yy, x0, cols| {
+            return timed_block(yy, 1, x0, cols);
+        }),
+        false => sink.write_strips(xsize, ysize, n_lines_read, &mut |yy, lines| {
+            return timed_block(yy, lines, 0, xsize);
+        }),
+    };
+    timing::add(
+        Stage::Write,
+        started.elapsed().saturating_sub(pulling),
+        bytes,
+    );
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct MemSource<T>(HashMap<String, Vec<T>>);
+
+    impl<T: Clone> HdfSource<T> for MemSource<T> {
+        fn len(&self, path: &str) -> Result<usize> {
+            return Ok(self.0[path].len());
+        }
+
+        fn read_range(&self, path: &str, start: usize, end: usize) -> Result<Vec<T>> {
+            return Ok(self.0[path][start..end].to_vec());
+        }
+    }
+
+    /// Records which strips were handed to it, skipping empty ones.
+    struct SparseSink(Vec<usize>);
+
+    impl RasterSink<u8> for SparseSink {
+        fn write_strips(
+            &mut self,
+            _: usize,
+            ysize: usize,
+            n_lines: usize,
+            next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<u8>>,
+        ) -> Result<()> {
+            for yy in (0..ysize).step_by(n_lines) {
+                if !next_strip(yy, n_lines.min(ysize - yy))?.is_empty() {
+                    self.0.push(yy);
+                }
+            }
+            return Ok(());
+        }
+
+        fn skips_empty(&self) -> bool {
+            return true;
+        }
+    }
+
+    #[test]
+    fn write_dataset_raster_copies_every_strip() {
+        let mut datasets = HashMap::new();
+        datasets.insert(
+            "/g/mean_rev".to_owned(),
+            (0..35).map(|v| v as f32).collect(),
+        );
+        let source = MemSource(datasets);
+        let mut sink = MemorySink::new();
+
+        write_dataset_raster(&source, "/g/mean_rev", &mut sink, 5, 7, 3, 5, None).unwrap();
+
+        let array = sink.into_array();
+        assert_eq!(array.dim(), (7, 5));
+        assert_eq!(array[[0, 0]], 0f32);
+        assert_eq!(array[[6, 4]], 34f32);
+        assert_eq!(
+            array.iter().cloned().collect::<Vec<_>>(),
+            (0..35).map(|v| v as f32).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn empty_strips_are_not_written() {
+        // 4x7 counts with observations only in rows 3 and 6.
+        let mut counts = vec![0u8; 28];
+        counts[13] = 2;
+        counts[24] = 1;
+        let mut datasets = HashMap::new();
+        datasets.insert("/g/count_rev".to_owned(), counts);
+        let source = MemSource(datasets);
+
+        let empty = empty_strips(&source, "/g/count_rev", 4, 7, 3, 4).unwrap();
+        assert_eq!(empty, vec![true, false, false]);
+
+        let mut sink = SparseSink(vec![]);
+        write_dataset_raster(&source, "/g/count_rev", &mut sink, 4, 7, 3, 4, Some(&empty)).unwrap();
+        assert_eq!(sink.0, vec![3, 6]);
+    }
+
+    #[test]
+    fn classic_tiffs_are_told_from_bigtiffs() {
+        let dir = std::env::temp_dir();
+        let headers: [(&str, &[u8], bool); 5] = [
+            ("le.tif", b"II*\0\x08\0\0\0", true),
+            ("be.tif", b"MM\0*\0\0\0\x08", true),
+            ("big.tif", b"II+\0\x08\0\0\0", false),
+            ("other.img", b"HFA", false),
+            ("empty.tif", b"", false),
+        ];
+        for (name, header, classic) in headers.iter() {
+            let path = dir.join(format!("is_classic_tiff_{}_{}", std::process::id(), name));
+            std::fs::write(&path, header).unwrap();
+            let path = path.to_str().unwrap();
+            assert_eq!(is_classic_tiff(path).unwrap(), *classic, "{}", name);
+            std::fs::remove_file(path).unwrap();
+        }
+        assert!(is_classic_tiff("/nonexistent/template.tif").is_err());
+    }
+}
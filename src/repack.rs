@@ -0,0 +1,384 @@
+//! Repacking an HDF5 file, as `h5repack` does, to reclaim the space of the
+//! objects removed from it: HDF5 does not shrink a file when datasets are
+//! unlinked (`undo`, `--update`, `--overwrite`).
+//!
+//! The objects are copied group by group to a temporary file next to it,
+//! with their attributes, and the temporary file then replaces the file at
+//! once, so an interrupted repack leaves it as it was. Soft and external
+//! links are copied as links, and objects linked more than once are copied
+//! once. With a compression, numeric datasets are written again with it
+//! (chunked when they were not), leaving out their unallocated chunks;
+//! other objects are copied as they are.
+
+use crate::allocation;
+use crate::attrs;
+use crate::compression::H5Compression;
+use crate::error::{Error, Result};
+use crate::hdf::{self, HasMembers, LinkKind, ObjectAddress, ObjectKind, ValueClass};
+use hdf5_sys::h5::{hid_t, hsize_t};
+use hdf5_sys::h5d::{
+    H5D_layout_t, H5Dcreate2, H5Dget_create_plist, H5Dget_space, H5Dread, H5Dwrite,
+};
+use hdf5_sys::h5i::H5Idec_ref;
+use hdf5_sys::h5l::{
+    H5L_info_t, H5L_type_t, H5Lcreate_external, H5Lcreate_hard, H5Lcreate_soft, H5Lget_info,
+    H5Lget_val, H5Lunpack_elink_val,
+};
+use hdf5_sys::h5o::{H5O_info_t, H5Ocopy, H5Oget_info_by_name};
+use hdf5_sys::h5p::{
+    H5Pget_external_count, H5Pget_layout, H5Premove_filter, H5Pset_chunk, H5P_DEFAULT,
+};
+use hdf5_sys::h5s::{H5S_seloper_t, H5Screate_simple, H5Sselect_hyperslab};
+use hdf5_sys::h5z::H5Z_FILTER_ALL;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::path::Path;
+
+/// Bytes of a dataset read and written at once when it is recompressed.
+const BLOCK_BYTES: usize = 64 << 20;
+/// Largest chunk given to datasets that were not chunked.
+const CHUNK_BYTES: usize = 1 << 20;
+/// Longest soft or external link value copied.
+const LINK_VALUE_BYTES: usize = 64 << 10;
+
+/// Sizes of a repacked file, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Repacked {
+    pub before: u64,
+    pub after: u64,
+    /// Datasets written again with the compression.
+    pub recompressed: usize,
+}
+
+/// Repacks the HDF5 file at `path`, which must not be open for writing,
+/// writing its numeric datasets again with `compression` if any.
+pub fn repack(path: &str, compression: Option<&H5Compression>) -> Result<Repacked> {
+    if let Some(compression) = compression {
+        compression.check()?;
+    }
+    let before = std::fs::metadata(path)?.len();
+    let tmp_path = format!("{}.repack.tmp", path);
+    let recompressed = match copy_file(path, &tmp_path, compression) {
+        Ok(recompressed) => recompressed,
+        Err(err) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    };
+    std::fs::rename(&tmp_path, path)?;
+    return Ok(Repacked {
+        before,
+        after: std::fs::metadata(path)?.len(),
+        recompressed,
+    });
+}
+
+/// Copies the file at `from` to a new file at `to`, both closed on return.
+/// Returns the number of datasets recompressed.
+fn copy_file(from: &str, to: &str, compression: Option<&H5Compression>) -> Result<usize> {
+    if Path::new(to).exists() {
+        std::fs::remove_file(to)?;
+    }
+    let source = hdf5::File::open(from)?;
+    let copy = hdf5::File::create(to)?;
+    let mut copier = Copier {
+        compression,
+        copied: HashMap::new(),
+        recompressed: 0,
+    };
+    copier.copy_group(&source, &copy, "")?;
+    attrs::copy_all_attributes(&source, &copy)?;
+    copy.flush()?;
+    return Ok(copier.recompressed);
+}
+
+struct Copier<'a> {
+    compression: Option<&'a H5Compression>,
+    /// Path in the copy of each object copied so far.
+    copied: HashMap<ObjectAddress, String>,
+    recompressed: usize,
+}
+
+impl<'a> Copier<'a> {
+    fn copy_group(&mut self, source: &hdf5::Group, copy: &hdf5::Group, path: &str) -> Result<()> {
+        for name in source.member_names()? {
+            let member_path = format!("{}/{}", path, name);
+            let c_name = CString::new(name.as_str())
+                .map_err(|_| Error::Invalid(format!("invalid object name '{}'", name)))?;
+            let failed = || Error::Invalid(format!("could not copy {}", member_path));
+            let link_kind = source.link_kind(&name);
+            if link_kind == LinkKind::Soft || link_kind == LinkKind::External {
+                let copied =
+                    hdf5::sync::sync(|| unsafe { copy_link(source.id(), copy.id(), &c_name) });
+                if !copied {
+                    return Err(failed());
+                }
+                continue;
+            }
+            let address = address_at(source.id(), &c_name);
+            if let Some(first_path) = address.and_then(|address| self.copied.get(&address)) {
+                let c_first = CString::new(first_path.as_str()).unwrap();
+                let status = hdf5::sync::sync(|| unsafe {
+                    H5Lcreate_hard(
+                        copy.id(),
+                        c_first.as_ptr(),
+                        copy.id(),
+                        c_name.as_ptr(),
+                        H5P_DEFAULT,
+                        H5P_DEFAULT,
+                    )
+                });
+                if status < 0 {
+                    return Err(failed());
+                }
+                continue;
+            }
+            let kind = source.object_kind(&name);
+            let recompressed = match (kind, self.compression) {
+                (ObjectKind::Dataset, Some(compression)) => {
+                    let ds = source.dataset(&name)?;
+                    let recompressed = recompresses(&ds)?;
+                    if recompressed {
+                        recompress(&ds, copy, &c_name, compression)?;
+                        attrs::copy_all_attributes(&ds, &copy.dataset(&name)?)?;
+                        self.recompressed += 1;
+                    }
+                    recompressed
+                }
+                _ => false,
+            };
+            match kind {
+                _ if recompressed => {}
+                ObjectKind::Group => {
+                    let source_group = source.group(&name)?;
+                    let copy_group = copy.create_group(&name)?;
+                    attrs::copy_all_attributes(&source_group, &copy_group)?;
+                    self.copy_group(&source_group, &copy_group, &member_path)?;
+                }
+                _ => {
+                    let status = hdf5::sync::sync(|| unsafe {
+                        H5Ocopy(
+                            source.id(),
+                            c_name.as_ptr(),
+                            copy.id(),
+                            c_name.as_ptr(),
+                            H5P_DEFAULT,
+                            H5P_DEFAULT,
+                        )
+                    });
+                    if status < 0 {
+                        return Err(failed());
+                    }
+                }
+            }
+            if let Some(address) = address {
+                self.copied.insert(address, member_path);
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Whether `ds` is written again with a compression: a numeric dataset with
+/// values, stored in the file.
+fn recompresses(ds: &hdf5::Dataset) -> Result<bool> {
+    if hdf::value_class(ds)? != ValueClass::Numeric || ds.shape().is_empty() || ds.size() == 0 {
+        return Ok(false);
+    }
+    let stored = hdf5::sync::sync(|| unsafe {
+        let dcpl = H5Dget_create_plist(ds.id());
+        if dcpl < 0 {
+            return false;
+        }
+        let layout = H5Pget_layout(dcpl);
+        let external = H5Pget_external_count(dcpl);
+        H5Idec_ref(dcpl);
+        return external == 0
+            && (layout == H5D_layout_t::H5D_CHUNKED
+                || layout == H5D_layout_t::H5D_CONTIGUOUS
+                || layout == H5D_layout_t::H5D_COMPACT);
+    });
+    return Ok(stored);
+}
+
+/// File number and object header address of the object linked as `name`
+/// from `loc_id`.
+fn address_at(loc_id: hid_t, name: &CStr) -> Option<ObjectAddress> {
+    let mut info = std::mem::MaybeUninit::<H5O_info_t>::uninit();
+    let status = hdf5::sync::sync(|| unsafe {
+        H5Oget_info_by_name(loc_id, name.as_ptr(), info.as_mut_ptr(), H5P_DEFAULT)
+    });
+    if status < 0 {
+        return None;
+    }
+    let info = unsafe { info.assume_init() };
+    return Some((info.fileno as u64, info.addr as u64));
+}
+
+/// Links `name` in `to` as the soft or external link `name` of `from`.
+unsafe fn copy_link(from: hid_t, to: hid_t, name: &CStr) -> bool {
+    let mut info = std::mem::MaybeUninit::<H5L_info_t>::uninit();
+    if H5Lget_info(from, name.as_ptr(), info.as_mut_ptr(), H5P_DEFAULT) < 0 {
+        return false;
+    }
+    let mut value = vec![0u8; LINK_VALUE_BYTES];
+    if H5Lget_val(
+        from,
+        name.as_ptr(),
+        value.as_mut_ptr() as *mut _,
+        value.len(),
+        H5P_DEFAULT,
+    ) < 0
+    {
+        return false;
+    }
+    return match info.assume_init().type_ {
+        H5L_type_t::H5L_TYPE_SOFT => {
+            H5Lcreate_soft(
+                value.as_ptr() as *const c_char,
+                to,
+                name.as_ptr(),
+                H5P_DEFAULT,
+                H5P_DEFAULT,
+            ) >= 0
+        }
+        H5L_type_t::H5L_TYPE_EXTERNAL => {
+            let mut flags = 0;
+            let mut file_name: *const c_char = std::ptr::null();
+            let mut obj_path: *const c_char = std::ptr::null();
+            H5Lunpack_elink_val(
+                value.as_ptr() as *const _,
+                value.len(),
+                &mut flags,
+                &mut file_name,
+                &mut obj_path,
+            ) >= 0
+                && H5Lcreate_external(
+                    file_name,
+                    obj_path,
+                    to,
+                    name.as_ptr(),
+                    H5P_DEFAULT,
+                    H5P_DEFAULT,
+                ) >= 0
+        }
+        _ => false,
+    };
+}
+
+/// Writes `ds` again as `name` of `copy` with `compression`, block of rows
+/// by block of rows, skipping the blocks whose chunks were never written.
+fn recompress(
+    ds: &hdf5::Dataset,
+    copy: &hdf5::Group,
+    name: &CStr,
+    compression: &H5Compression,
+) -> Result<()> {
+    let _silence = hdf5::silence_errors();
+    let dtype = ds.dtype()?;
+    let shape = ds.shape();
+    let elem_bytes = dtype.size();
+    let row_len: usize = shape[1..].iter().product();
+    let block_rows = (BLOCK_BYTES / (row_len * elem_bytes).max(1)).max(1);
+    // Chunks of whole trailing dimensions, as many rows as fit.
+    let chunk: Vec<hsize_t> = std::iter::once(
+        (CHUNK_BYTES / (row_len * elem_bytes).max(1)).clamp(1, shape[0]) as hsize_t,
+    )
+    .chain(shape[1..].iter().map(|&len| len as hsize_t))
+    .collect();
+    let out_id = hdf5::sync::sync(|| unsafe {
+        let dcpl = H5Dget_create_plist(ds.id());
+        if dcpl < 0 {
+            return -1;
+        }
+        H5Premove_filter(dcpl, H5Z_FILTER_ALL);
+        if H5Pget_layout(dcpl) != H5D_layout_t::H5D_CHUNKED {
+            H5Pset_chunk(dcpl, chunk.len() as i32, chunk.as_ptr());
+        }
+        let space = H5Dget_space(ds.id());
+        let out_id = match compression.set_filters(dcpl) && space >= 0 {
+            true => H5Dcreate2(
+                copy.id(),
+                name.as_ptr(),
+                dtype.id(),
+                space,
+                H5P_DEFAULT,
+                dcpl,
+                H5P_DEFAULT,
+            ),
+            false => -1,
+        };
+        if space >= 0 {
+            H5Idec_ref(space);
+        }
+        H5Idec_ref(dcpl);
+        return out_id;
+    });
+    if out_id < 0 {
+        return Err(Error::Invalid(format!(
+            "could not create {} with the compression",
+            ds.name()
+        )));
+    }
+    let mut buf = vec![0u8; block_rows.min(shape[0]) * row_len * elem_bytes];
+    let mut status = 0;
+    for start in (0..shape[0]).step_by(block_rows) {
+        let rows = block_rows.min(shape[0] - start);
+        if allocation::unallocated(ds, start * row_len, (start + rows) * row_len) {
+            continue;
+        }
+        let offset: Vec<hsize_t> = std::iter::once(start as hsize_t)
+            .chain(shape[1..].iter().map(|_| 0))
+            .collect();
+        let count: Vec<hsize_t> = std::iter::once(rows as hsize_t)
+            .chain(shape[1..].iter().map(|&len| len as hsize_t))
+            .collect();
+        status = hdf5::sync::sync(|| unsafe {
+            let block_space = H5Dget_space(ds.id());
+            let mem_space = H5Screate_simple(count.len() as i32, count.as_ptr(), std::ptr::null());
+            let mut status = H5Sselect_hyperslab(
+                block_space,
+                H5S_seloper_t::H5S_SELECT_SET,
+                offset.as_ptr(),
+                std::ptr::null(),
+                count.as_ptr(),
+                std::ptr::null(),
+            );
+            if status >= 0 {
+                status = H5Dread(
+                    ds.id(),
+                    dtype.id(),
+                    mem_space,
+                    block_space,
+                    H5P_DEFAULT,
+                    buf.as_mut_ptr() as *mut _,
+                );
+            }
+            if status >= 0 {
+                status = H5Dwrite(
+                    out_id,
+                    dtype.id(),
+                    mem_space,
+                    block_space,
+                    H5P_DEFAULT,
+                    buf.as_ptr() as *const _,
+                );
+            }
+            H5Idec_ref(mem_space);
+            H5Idec_ref(block_space);
+            return status;
+        });
+        if status < 0 {
+            break;
+        }
+    }
+    hdf5::sync::sync(|| unsafe { H5Idec_ref(out_id) });
+    if status < 0 {
+        return Err(Error::Invalid(format!(
+            "could not recompress {}",
+            ds.name()
+        )));
+    }
+    return Ok(());
+}
@@ -0,0 +1,74 @@
+//! JSON summary of a run.
+
+use crate::error::Result;
+use crate::nan_policy::NonFiniteRecord;
+use crate::narrowing::ClipRecord;
+use crate::provenance::Provenance;
+use crate::summary::OutputSummary;
+use crate::timing::TaskTimings;
+use crate::valid_range::RangeRecord;
+use crate::verify::Mismatch;
+use crate::warnings::WarningRecord;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStatus {
+    Complete,
+    /// Partially written when the run stopped, kept on disk.
+    Incomplete,
+    /// Partially written when the run stopped, removed from disk.
+    Deleted,
+    /// Could not be written; the run went on with the other outputs.
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutputRecord {
+    pub path: String,
+    pub status: OutputStatus,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub interrupted: bool,
+    pub outputs: Vec<OutputRecord>,
+    /// Pixels that differed when read back with `--verify`.
+    pub mismatches: Vec<Mismatch>,
+    pub warnings: Vec<WarningRecord>,
+    /// Summaries of the outputs written with `--summary`.
+    pub summaries: Vec<OutputSummary>,
+    /// Pixels outside the valid range of each output checked.
+    pub out_of_range: Vec<RangeRecord>,
+    /// Pixels clipped narrowing each output to `--ot`.
+    pub clipped: Vec<ClipRecord>,
+    /// Means and sds of pixels with observations that came out NaN or
+    /// infinite, per group.
+    pub non_finite: Vec<NonFiniteRecord>,
+    /// Time spent reading, computing and writing in each task.
+    pub timings: Vec<TaskTimings>,
+    /// Version, arguments and input checksums of a `--deterministic` run.
+    pub provenance: Option<Provenance>,
+    /// Exit code of the run, see [`crate::error::exit_code`].
+    pub exit_code: i32,
+}
+
+impl Report {
+    pub fn add_output(&mut self, path: &str, status: OutputStatus) {
+        self.outputs.push(OutputRecord {
+            path: path.to_owned(),
+            status,
+        });
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        return Ok(serde_json::to_string_pretty(self)?);
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        return Ok(());
+    }
+}
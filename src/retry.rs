@@ -0,0 +1,143 @@
+//! Retries of HDF5 reads and writes and GDAL writes failing with I/O errors,
+//! as reads and writes on network filesystems intermittently do.
+//!
+//! The number of retries and the first delay are set once for the process,
+//! like strict mode. Each strip is retried on its own, so a dataset resumes
+//! at the strip that failed instead of starting over.
+
+use crate::error::{Error, Result};
+use crate::interrupt;
+use crate::warnings::{self, Warning};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+static RETRIES: AtomicU32 = AtomicU32::new(0);
+static DELAY_MS: AtomicU64 = AtomicU64::new(1000);
+
+/// Retries failed reads and writes up to `retries` times, waiting `delay`
+/// before the first retry and twice as long before each next one.
+pub fn set_policy(retries: u32, delay: Duration) {
+    RETRIES.store(retries, Ordering::SeqCst);
+    DELAY_MS.store(delay.as_millis() as u64, Ordering::SeqCst);
+}
+
+/// Parses a delay in seconds, or with an `ms` or `s` suffix (e.g. `500ms`,
+/// `2s`, `1.5`).
+pub fn parse_delay(text: &str) -> std::result::Result<Duration, String> {
+    let text = text.trim();
+    let (number, scale) = match text.strip_suffix("ms") {
+        Some(number) => (number, 0.001),
+        None => (text.strip_suffix('s').unwrap_or(text), 1f64),
+    };
+    return match number.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0f64 => {
+            Ok(Duration::from_secs_f64(value * scale))
+        }
+        _ => Err(format!("invalid retry delay '{}'", text)),
+    };
+}
+
+/// Whether `err` may go away when the operation is repeated: failures of
+/// HDF5, GDAL and the filesystem, but not invalid input or interruptions.
+fn is_transient(err: &Error) -> bool {
+    return match err {
+        Error::Hdf5(_) => true,
+        #[cfg(feature = "gdal")]
+        Error::Gdal(_) => true,
+        Error::Io(err) => !matches!(
+            err.kind(),
+            std::io::ErrorKind::NotFound
+                | std::io::ErrorKind::PermissionDenied
+                | std::io::ErrorKind::InvalidInput
+        ),
+        _ => false,
+    };
+}
+
+/// Runs `op`, repeating it while it fails with a transient error and
+/// retries are left. Each retry is recorded as a warning naming `what` was
+/// done (e.g. `reading /g/sum`); Ctrl-C stops waiting for the next one.
+pub fn with_retries<T, F>(what: &str, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let retries = RETRIES.load(Ordering::SeqCst);
+    let mut delay = Duration::from_millis(DELAY_MS.load(Ordering::SeqCst));
+    let mut attempt = 0;
+    loop {
+        let err = match op() {
+            Err(err) if attempt < retries && is_transient(&err) => err,
+            result => return result,
+        };
+        attempt += 1;
+        warnings::record(
+            Warning::Retried,
+            format!(
+                "{} failed ({}), retry {} of {} in {:.1} s",
+                what,
+                err,
+                attempt,
+                retries,
+                delay.as_secs_f64()
+            ),
+        );
+        interrupt::check()?;
+        std::thread::sleep(delay);
+        interrupt::check()?;
+        delay *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every test sets the same policy, as it is shared by the process.
+    const TIMES: u32 = 3;
+
+    fn transient() -> Error {
+        return Error::Io(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "stale handle",
+        ));
+    }
+
+    #[test]
+    fn succeeds_after_failing_as_many_times_as_retried() {
+        set_policy(TIMES, Duration::from_millis(0));
+        let mut calls = 0;
+        let value = with_retries("reading /g/sum", || {
+            calls += 1;
+            return match calls <= TIMES {
+                true => Err(transient()),
+                false => Ok(calls),
+            };
+        })
+        .unwrap();
+        assert_eq!(value, TIMES + 1);
+    }
+
+    #[test]
+    fn gives_up_after_the_configured_retries() {
+        set_policy(TIMES, Duration::from_millis(0));
+        let mut calls = 0;
+        let result: Result<()> = with_retries("writing /g/sum", || {
+            calls += 1;
+            return Err(transient());
+        });
+        assert!(matches!(result, Err(Error::Io(_))));
+        assert_eq!(calls, TIMES + 1);
+    }
+
+    #[test]
+    fn does_not_retry_invalid_input() {
+        set_policy(TIMES, Duration::from_millis(0));
+        let mut calls = 0;
+        let result: Result<()> = with_retries("reading /g/sum", || {
+            calls += 1;
+            return Err(Error::Invalid("bad".to_owned()));
+        });
+        assert!(matches!(result, Err(Error::Invalid(_))));
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,788 @@
+use crate::compression::H5Compression;
+use crate::error::{Error, Result};
+use crate::policy::{Decision, ExistsPolicy};
+use crate::timing::{self, Stage};
+use crate::warnings::{self, Warning};
+use crate::{attrs, chunking, compound, hdf, interrupt, retry, status};
+use ndarray::{s, Array, Array2};
+use std::iter::FromIterator;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::Instant;
+
+/// Source of flat, row-major values addressed by element range.
+pub trait ChunkReader<T> {
+    type Error;
+
+    fn read_range(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> std::result::Result<ndarray::Array1<T>, Self::Error>;
+}
+
+/// Destination of flat, row-major values addressed by element range.
+pub trait ChunkWriter<T> {
+    type Error;
+
+    fn write_range(
+        &self,
+        start: usize,
+        end: usize,
+        vals: &[T],
+    ) -> std::result::Result<(), Self::Error>;
+}
+
+/// Adapts an HDF5 dataset to the chunk traits, reporting crate errors.
+pub struct HdfChunks<'a>(pub &'a hdf5::Dataset);
+
+impl<'a, T: hdf5::H5Type> ChunkReader<T> for HdfChunks<'a> {
+    type Error = Error;
+
+    fn read_range(&self, start: usize, end: usize) -> Result<ndarray::Array1<T>> {
+        let started = Instant::now();
+        let vals = retry::with_retries(&format!("reading {}", self.0.name()), || {
+            return Ok(hdf::read_flat::<T>(self.0, start, end)?);
+        })?;
+        let bytes = vals.len() * std::mem::size_of::<T>();
+        timing::add(Stage::Read, started.elapsed(), bytes);
+        status::add_bytes(bytes);
+        return Ok(vals);
+    }
+}
+
+/// An owned dataset handle, one per worker of [`reverse_rows_parallel`].
+impl<T: hdf5::H5Type> ChunkReader<T> for hdf5::Dataset {
+    type Error = Error;
+
+    fn read_range(&self, start: usize, end: usize) -> Result<ndarray::Array1<T>> {
+        return HdfChunks(self).read_range(start, end);
+    }
+}
+
+impl<'a, T: hdf5::H5Type> ChunkWriter<T> for HdfChunks<'a> {
+    type Error = Error;
+
+    fn write_range(&self, start: usize, _end: usize, vals: &[T]) -> Result<()> {
+        let started = Instant::now();
+        retry::with_retries(&format!("writing {}", self.0.name()), || {
+            return Ok(hdf::write_flat(self.0, start, vals)?);
+        })?;
+        let bytes = std::mem::size_of_val(vals);
+        timing::add(Stage::Write, started.elapsed(), bytes);
+        status::add_bytes(bytes);
+        return Ok(());
+    }
+}
+
+pub fn rev_array<T: Clone>(
+    input: ndarray::Array<T, ndarray::Dim<[usize; 1]>>,
+    nrows: usize,
+    ncols: usize,
+) -> ndarray::Array<T, ndarray::Dim<[usize; 1]>> {
+    let input = input.into_shape((nrows, ncols)).unwrap();
+    let input = input.slice(s![..;-1, ..]);
+    let input = Array::from_iter(input.iter().cloned());
+    return input;
+}
+
+/// Writes the rows of `src` into `dst` in reverse order.
+///
+/// Strips of up to `n_lines_read` rows are taken from the top half and the
+/// mirrored strip from the bottom half, so every row is read and written
+/// exactly once. When `ysize` is odd the middle row is its own mirror and is
+/// copied on its own after the strip pairs.
+///
+/// `on_progress` receives the fraction of rows processed so far; returning an
+/// error from it stops the reversal, e.g. when the run is interrupted.
+pub fn reverse_rows<T, R, W, E, P>(
+    src: &R,
+    dst: &W,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    mut on_progress: P,
+) -> std::result::Result<(), E>
+where
+    T: Clone,
+    P: FnMut(f32) -> std::result::Result<(), E>,
+    R: ChunkReader<T, Error = E>,
+    W: ChunkWriter<T, Error = E>,
+{
+    assert!(n_lines_read > 0, "n_lines_read must be positive");
+    let half_lines = ysize / 2;
+
+    for yy in (0..half_lines).step_by(n_lines_read) {
+        on_progress(2f32 * yy as f32 / ysize as f32)?;
+
+        let lines_to_read = n_lines_read.min(half_lines - yy);
+        let rev_yy = ysize - yy - lines_to_read;
+
+        let lower_bound = yy * xsize;
+        let upper_bound = (yy + lines_to_read) * xsize;
+        let rev_lower_bound = rev_yy * xsize;
+        let rev_upper_bound = (rev_yy + lines_to_read) * xsize;
+
+        let vals = src.read_range(lower_bound, upper_bound)?;
+        let vals_final = rev_array(vals, lines_to_read, xsize);
+
+        let rev_vals = src.read_range(rev_lower_bound, rev_upper_bound)?;
+        let rev_vals_final = rev_array(rev_vals, lines_to_read, xsize);
+
+        dst.write_range(lower_bound, upper_bound, rev_vals_final.as_slice().unwrap())?;
+        dst.write_range(
+            rev_lower_bound,
+            rev_upper_bound,
+            vals_final.as_slice().unwrap(),
+        )?;
+    }
+
+    if ysize % 2 == 1 {
+        let middle_bound = half_lines * xsize;
+        let middle = src.read_range(middle_bound, middle_bound + xsize)?;
+        dst.write_range(
+            middle_bound,
+            middle_bound + xsize,
+            middle.as_slice().unwrap(),
+        )?;
+    }
+    on_progress(1f32)?;
+    return Ok(());
+}
+
+/// A strip pair of [`reverse_rows_parallel`]: the first row of the top strip,
+/// its number of rows, and both strips already flipped.
+type FlippedPair<T> = (usize, usize, ndarray::Array1<T>, ndarray::Array1<T>);
+
+/// Like [`reverse_rows`], but the strip pairs are read and flipped by
+/// `n_workers` threads, each with its own reader from `open_reader`, while
+/// the calling thread writes them to `dst` as they arrive, so only one
+/// thread writes. Each worker holds at most one pair, so up to `n_workers`
+/// pairs plus the one being written are in memory at once.
+///
+/// With HDF5 readers, the reads, decompression included, still run one at
+/// a time: the `hdf5` crate serializes every library call behind one
+/// process-wide lock, whatever the file or dataset handle. The workers only
+/// overlap the flips with the reads and the writes; the
+/// `hdf5_reverse_rows_parallel` benchmark measures what that gains.
+///
+/// When `on_progress` or a read fails, the workers stop after the pair
+/// they are reading and the first error is returned.
+pub fn reverse_rows_parallel<T, R, W, E, O, P>(
+    open_reader: O,
+    dst: &W,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    n_workers: usize,
+    mut on_progress: P,
+) -> std::result::Result<(), E>
+where
+    T: Clone + Send,
+    E: Send,
+    O: Fn() -> std::result::Result<R, E> + Sync,
+    P: FnMut(f32) -> std::result::Result<(), E>,
+    R: ChunkReader<T, Error = E>,
+    W: ChunkWriter<T, Error = E>,
+{
+    assert!(n_lines_read > 0, "n_lines_read must be positive");
+    assert!(n_workers > 0, "n_workers must be positive");
+    let half_lines = ysize / 2;
+    let starts: Vec<usize> = (0..half_lines).step_by(n_lines_read).collect();
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+
+    let result = std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel::<std::result::Result<FlippedPair<T>, E>>(0);
+        for _ in 0..n_workers.min(starts.len()) {
+            let sender = sender.clone();
+            let (open_reader, starts, next, stop) = (&open_reader, &starts, &next, &stop);
+            scope.spawn(move || {
+                let src = match open_reader() {
+                    Ok(src) => src,
+                    Err(err) => {
+                        let _ = sender.send(Err(err));
+                        return;
+                    }
+                };
+                while !stop.load(Ordering::SeqCst) {
+                    let yy = match starts.get(next.fetch_add(1, Ordering::SeqCst)) {
+                        Some(&yy) => yy,
+                        None => return,
+                    };
+                    let lines = n_lines_read.min(half_lines - yy);
+                    let rev_yy = ysize - yy - lines;
+                    let pair = src
+                        .read_range(yy * xsize, (yy + lines) * xsize)
+                        .and_then(|vals| {
+                            let rev_vals =
+                                src.read_range(rev_yy * xsize, (rev_yy + lines) * xsize)?;
+                            return Ok((
+                                yy,
+                                lines,
+                                rev_array(vals, lines, xsize),
+                                rev_array(rev_vals, lines, xsize),
+                            ));
+                        });
+                    let failed = pair.is_err();
+                    if sender.send(pair).is_err() || failed {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut rows_done = 0;
+        for pair in receiver.iter() {
+            let written = pair.and_then(|(yy, lines, vals_final, rev_vals_final)| {
+                let rev_yy = ysize - yy - lines;
+                dst.write_range(
+                    yy * xsize,
+                    (yy + lines) * xsize,
+                    rev_vals_final.as_slice().unwrap(),
+                )?;
+                dst.write_range(
+                    rev_yy * xsize,
+                    (rev_yy + lines) * xsize,
+                    vals_final.as_slice().unwrap(),
+                )?;
+                rows_done += 2 * lines;
+                return on_progress(rows_done as f32 / ysize as f32);
+            });
+            if written.is_err() {
+                // Dropping the receiver makes blocked workers give up.
+                stop.store(true, Ordering::SeqCst);
+                return written;
+            }
+        }
+        return Ok(());
+    });
+    result?;
+
+    if ysize % 2 == 1 {
+        let src = open_reader()?;
+        let middle_bound = half_lines * xsize;
+        let middle = src.read_range(middle_bound, middle_bound + xsize)?;
+        dst.write_range(
+            middle_bound,
+            middle_bound + xsize,
+            middle.as_slice().unwrap(),
+        )?;
+    }
+    on_progress(1f32)?;
+    return Ok(());
+}
+
+/// Copies the rows of `src` into `dst` in the same order, in strips of up to
+/// `n_lines_read` rows, for datasets that need no flip; see [`reverse_rows`]
+/// for `on_progress`.
+pub fn copy_rows<T, R, W, E, P>(
+    src: &R,
+    dst: &W,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    mut on_progress: P,
+) -> std::result::Result<(), E>
+where
+    P: FnMut(f32) -> std::result::Result<(), E>,
+    R: ChunkReader<T, Error = E>,
+    W: ChunkWriter<T, Error = E>,
+{
+    assert!(n_lines_read > 0, "n_lines_read must be positive");
+    for yy in (0..ysize).step_by(n_lines_read) {
+        on_progress(yy as f32 / ysize as f32)?;
+        let lines_to_read = n_lines_read.min(ysize - yy);
+        let (start, end) = (yy * xsize, (yy + lines_to_read) * xsize);
+        let vals = src.read_range(start, end)?;
+        dst.write_range(start, end, vals.as_slice().unwrap())?;
+    }
+    on_progress(1f32)?;
+    return Ok(());
+}
+
+/// Writes the rows of `src` into `dst` in reverse order, or in their order
+/// without `flip`, one row at a time in blocks of up to `block_cols`
+/// columns, for rows too wide to hold at once; see [`reverse_rows`] for
+/// `on_progress`.
+pub fn reverse_row_blocks<T, R, W, E, P>(
+    src: &R,
+    dst: &W,
+    (xsize, ysize): (usize, usize),
+    block_cols: usize,
+    flip: bool,
+    mut on_progress: P,
+) -> std::result::Result<(), E>
+where
+    P: FnMut(f32) -> std::result::Result<(), E>,
+    R: ChunkReader<T, Error = E>,
+    W: ChunkWriter<T, Error = E>,
+{
+    for block in chunking::blocks(xsize, ysize, 1, block_cols) {
+        if block.x0 == 0 {
+            on_progress(block.yy as f32 / ysize as f32)?;
+        }
+        let from = block.range(xsize);
+        let vals = src.read_range(from.start, from.end)?;
+        let to = match flip {
+            true => chunking::Block {
+                yy: ysize - 1 - block.yy,
+                ..block
+            }
+            .range(xsize),
+            false => from,
+        };
+        dst.write_range(to.start, to.end, vals.as_slice().unwrap())?;
+    }
+    on_progress(1f32)?;
+    return Ok(());
+}
+
+/// Reads the rows of `src` in reverse order, or in their order without
+/// `flip`, into an (ysize, xsize) array, in strips of up to `n_lines_read`
+/// rows, without writing anything back.
+pub fn read_flipped<T, R, E>(
+    src: &R,
+    xsize: usize,
+    ysize: usize,
+    n_lines_read: usize,
+    flip: bool,
+) -> std::result::Result<Array2<T>, E>
+where
+    T: Clone,
+    R: ChunkReader<T, Error = E>,
+{
+    assert!(n_lines_read > 0, "n_lines_read must be positive");
+    let mut vals = Vec::with_capacity(xsize * ysize);
+    for yy in (0..ysize).step_by(n_lines_read) {
+        let lines_to_read = n_lines_read.min(ysize - yy);
+        if !flip {
+            vals.extend(src.read_range(yy * xsize, (yy + lines_to_read) * xsize)?);
+            continue;
+        }
+        let src_yy = ysize - yy - lines_to_read;
+        let strip = src.read_range(src_yy * xsize, (src_yy + lines_to_read) * xsize)?;
+        vals.extend(rev_array(strip, lines_to_read, xsize).into_iter());
+    }
+    return Ok(Array2::from_shape_vec((ysize, xsize), vals).unwrap());
+}
+
+/// Writes `base_ds` of `file` with its rows reversed, or in their order
+/// without `flip` (see [`crate::row_order`]), into `<base_ds>_rev` of
+/// `out_file`, the same file or a scratch one. A partially written `_rev`
+/// dataset is removed again if the reversal fails.
+///
+/// Compound datasets need a `field`, whose values become the plain `T`
+/// values of the `_rev` dataset; `field` is ignored for other datasets.
+///
+/// The `_rev` dataset gets the fill value of `base_ds` (or `T`'s default)
+/// and, for plain datasets, its [`attrs::REV_ATTRIBUTES`].
+///
+/// Plain datasets are read and flipped by one worker per thread of the
+/// rayon pool (`--jobs`), each through its own dataset handle, though their
+/// HDF5 reads run one at a time; see [`reverse_rows_parallel`].
+pub fn reverse_ds_rows<T: hdf5::H5Type + Clone + Default + Send>(
+    file: &hdf5::File,
+    out_file: &hdf5::File,
+    base_ds: String,
+    xsize: usize,
+    ysize: usize,
+    flip: bool,
+    memory_limit: usize,
+    policy: ExistsPolicy,
+    field: Option<&str>,
+    compression: &H5Compression,
+    resizable: bool,
+) -> Result<()> {
+    if base_ds.ends_with("_rev") {
+        return Ok(());
+    }
+    let ds_name_rev = base_ds.clone() + "_rev";
+    if policy.decide_dataset(out_file, &ds_name_rev)? == Decision::Skip {
+        return Ok(());
+    }
+    let ds: hdf5::Dataset = file.dataset(&base_ds)?;
+    let field_reader = compound::field_reader(&ds, field)?;
+    let shape = ds.shape();
+    if shape.len() == 2 && (shape[0], shape[1]) != (ysize, xsize) {
+        return Err(Error::Invalid(format!(
+            "dataset {} is shaped {:?}, not like the {}x{} grid ({}, {})",
+            base_ds, shape, xsize, ysize, ysize, xsize
+        )));
+    }
+    let grid_len = chunking::grid_len(xsize, ysize).map_err(Error::Invalid)?;
+    if ds.size() < grid_len {
+        return Err(Error::Invalid(format!(
+            "dataset {} has {} values, fewer than the {}x{} template",
+            base_ds,
+            ds.size(),
+            xsize,
+            ysize
+        )));
+    }
+    if ds.size() > grid_len {
+        warnings::warn(
+            Warning::SizeMismatch,
+            format!(
+                "dataset {} has {} values, more than the {}x{} template; the rest are ignored",
+                base_ds,
+                ds.size(),
+                xsize,
+                ysize
+            ),
+        )?;
+    }
+    if field_reader.is_none() && hdf::loses_precision::<T>(&ds)? {
+        warnings::warn(
+            Warning::PrecisionLoss,
+            format!(
+                "dataset {} is converted to {}, which cannot hold all its values",
+                base_ds,
+                std::any::type_name::<T>()
+            ),
+        )?;
+    }
+    // Compound datasets are read through a single field reader. With more
+    // workers the budget is shared by the strip pairs held at once.
+    let n_workers = match field_reader {
+        Some(_) => 1,
+        None => rayon::current_num_threads().max(1),
+    };
+    let n_pairs = if n_workers > 1 { n_workers + 1 } else { 1 };
+    let bytes_per_pixel = chunking::reverse_bytes_per_pixel(std::mem::size_of::<T>());
+    let n_lines_read = chunking::strip_lines(memory_limit / n_pairs, xsize, bytes_per_pixel);
+    // Rows too wide for the budget are reversed in blocks on this thread.
+    let block_cols = chunking::block_cols(memory_limit, xsize, bytes_per_pixel);
+    let layout = hdf::DatasetLayout {
+        chunk_len: chunking::h5_chunk_len(xsize, n_lines_read, std::mem::size_of::<T>()),
+        resizable,
+    };
+    let fill_value = match attrs::user_fill_value::<T>(&ds)? {
+        Some(fill_value) => fill_value,
+        None => {
+            if field_reader.is_none() {
+                warnings::warn(
+                    Warning::NodataFallback,
+                    format!(
+                        "dataset {} has no fill value, using the {} default",
+                        base_ds,
+                        std::any::type_name::<T>()
+                    ),
+                )?;
+            }
+            T::default()
+        }
+    };
+    let ds_out = hdf::create_dataset::<T>(
+        out_file,
+        &ds_name_rev,
+        ds.size(),
+        fill_value,
+        layout,
+        compression,
+    )?;
+    if field_reader.is_none() {
+        attrs::copy_attributes::<T>(&ds, &ds_out, attrs::REV_ATTRIBUTES)?;
+    }
+    let on_progress = |frac: f32| -> Result<()> {
+        status::set_progress(frac);
+        status::print_progress()?;
+        return interrupt::check();
+    };
+    let result = match &field_reader {
+        Some(reader) if block_cols < xsize => reverse_row_blocks::<T, _, _, Error, _>(
+            reader,
+            &HdfChunks(&ds_out),
+            (xsize, ysize),
+            block_cols,
+            flip,
+            on_progress,
+        ),
+        None if block_cols < xsize => reverse_row_blocks::<T, _, _, Error, _>(
+            &HdfChunks(&ds),
+            &HdfChunks(&ds_out),
+            (xsize, ysize),
+            block_cols,
+            flip,
+            on_progress,
+        ),
+        Some(reader) if !flip => copy_rows::<T, _, _, Error, _>(
+            reader,
+            &HdfChunks(&ds_out),
+            xsize,
+            ysize,
+            n_lines_read,
+            on_progress,
+        ),
+        None if !flip => copy_rows::<T, _, _, Error, _>(
+            &HdfChunks(&ds),
+            &HdfChunks(&ds_out),
+            xsize,
+            ysize,
+            n_lines_read,
+            on_progress,
+        ),
+        Some(reader) => reverse_rows::<T, _, _, Error, _>(
+            reader,
+            &HdfChunks(&ds_out),
+            xsize,
+            ysize,
+            n_lines_read,
+            on_progress,
+        ),
+        None if n_workers == 1 => reverse_rows::<T, _, _, Error, _>(
+            &HdfChunks(&ds),
+            &HdfChunks(&ds_out),
+            xsize,
+            ysize,
+            n_lines_read,
+            on_progress,
+        ),
+        None => reverse_rows_parallel::<T, _, _, Error, _, _>(
+            || Ok(file.dataset(&base_ds)?),
+            &HdfChunks(&ds_out),
+            xsize,
+            ysize,
+            n_lines_read,
+            n_workers,
+            on_progress,
+        ),
+    };
+    if result.is_ok() {
+        status::end_progress();
+    } else {
+        println!();
+        drop(ds_out);
+        out_file.unlink(&ds_name_rev)?;
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct MemDataset {
+        vals: RefCell<Vec<i32>>,
+        writes: RefCell<Vec<u32>>,
+    }
+
+    impl MemDataset {
+        fn new(vals: Vec<i32>) -> Self {
+            let writes = vec![0; vals.len()];
+            return MemDataset {
+                vals: RefCell::new(vals),
+                writes: RefCell::new(writes),
+            };
+        }
+    }
+
+    impl ChunkReader<i32> for MemDataset {
+        type Error = ();
+
+        fn read_range(
+            &self,
+            start: usize,
+            end: usize,
+        ) -> std::result::Result<ndarray::Array1<i32>, ()> {
+            return Ok(Array::from(self.vals.borrow()[start..end].to_vec()));
+        }
+    }
+
+    impl ChunkWriter<i32> for MemDataset {
+        type Error = ();
+
+        fn write_range(
+            &self,
+            start: usize,
+            end: usize,
+            vals: &[i32],
+        ) -> std::result::Result<(), ()> {
+            assert_eq!(end - start, vals.len());
+            self.vals.borrow_mut()[start..end].copy_from_slice(vals);
+            for count in &mut self.writes.borrow_mut()[start..end] {
+                *count += 1;
+            }
+            return Ok(());
+        }
+    }
+
+    fn check(xsize: usize, ysize: usize, n_lines_read: usize) {
+        let src = MemDataset::new((0..(xsize * ysize) as i32).collect());
+        let dst = MemDataset::new(vec![-1; xsize * ysize]);
+
+        reverse_rows(&src, &dst, xsize, ysize, n_lines_read, |_| Ok(())).unwrap();
+
+        let vals = dst.vals.borrow();
+        for yy in 0..ysize {
+            for xx in 0..xsize {
+                let expected = ((ysize - 1 - yy) * xsize + xx) as i32;
+                assert_eq!(
+                    vals[yy * xsize + xx],
+                    expected,
+                    "xsize={} ysize={} n_lines_read={} row={} col={}",
+                    xsize,
+                    ysize,
+                    n_lines_read,
+                    yy,
+                    xx
+                );
+            }
+        }
+        assert!(
+            dst.writes.borrow().iter().all(|&count| count == 1),
+            "xsize={} ysize={} n_lines_read={}: every element must be written once",
+            xsize,
+            ysize,
+            n_lines_read
+        );
+    }
+
+    #[test]
+    fn rev_array_flips_rows() {
+        let input = Array::from(vec![1, 2, 3, 4, 5, 6]);
+        let output = rev_array(input, 3, 2);
+        assert_eq!(output.to_vec(), vec![5, 6, 3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn reverse_rows_all_small_shapes() {
+        for xsize in 1..=4 {
+            for ysize in 0..=24 {
+                for n_lines_read in 1..=13 {
+                    check(xsize, ysize, n_lines_read);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reverse_rows_odd_height_keeps_middle_row() {
+        check(5, 101, 100);
+        check(5, 201, 100);
+        check(3, 7, 3);
+    }
+
+    #[test]
+    fn reverse_rows_parallel_matches_reverse_rows() {
+        for &(xsize, ysize, n_lines_read, n_workers) in &[
+            (3, 7, 2, 3),
+            (4, 24, 1, 4),
+            (2, 9, 10, 2),
+            (1, 5, 1, 8),
+            (2, 0, 1, 2),
+        ] {
+            let n = (xsize * ysize) as i32;
+            let dst = MemDataset::new(vec![-1; xsize * ysize]);
+            reverse_rows_parallel(
+                || Ok(MemDataset::new((0..n).collect())),
+                &dst,
+                xsize,
+                ysize,
+                n_lines_read,
+                n_workers,
+                |_| Ok(()),
+            )
+            .unwrap();
+            let expected = MemDataset::new(vec![-1; xsize * ysize]);
+            let src = MemDataset::new((0..n).collect());
+            reverse_rows(&src, &expected, xsize, ysize, n_lines_read, |_| Ok(())).unwrap();
+            assert_eq!(*dst.vals.borrow(), *expected.vals.borrow());
+            assert!(dst.writes.borrow().iter().all(|&count| count == 1));
+        }
+    }
+
+    #[test]
+    fn reverse_row_blocks_matches_reverse_rows() {
+        for &(xsize, ysize, block_cols) in &[(7, 5, 3), (10, 4, 1), (9, 1, 4), (6, 6, 6)] {
+            let src = MemDataset::new((0..(xsize * ysize) as i32).collect());
+            for &flip in &[true, false] {
+                let dst = MemDataset::new(vec![-1; xsize * ysize]);
+                reverse_row_blocks(&src, &dst, (xsize, ysize), block_cols, flip, |_| Ok(()))
+                    .unwrap();
+                let expected = MemDataset::new(vec![-1; xsize * ysize]);
+                match flip {
+                    true => reverse_rows(&src, &expected, xsize, ysize, 2, |_| Ok(())),
+                    false => copy_rows(&src, &expected, xsize, ysize, 2, |_| Ok(())),
+                }
+                .unwrap();
+                assert_eq!(*dst.vals.borrow(), *expected.vals.borrow());
+                assert!(dst.writes.borrow().iter().all(|&count| count == 1));
+            }
+        }
+    }
+
+    #[test]
+    fn reverse_rows_parallel_stops_on_progress_error() {
+        let dst = MemDataset::new(vec![0; 60]);
+        let result = reverse_rows_parallel(
+            || Ok(MemDataset::new((0..60).collect())),
+            &dst,
+            3,
+            20,
+            1,
+            4,
+            |_| Err(()),
+        );
+        assert!(result.is_err());
+        let n_written = dst
+            .writes
+            .borrow()
+            .iter()
+            .filter(|&&count| count > 0)
+            .count();
+        assert_eq!(n_written, 6, "only the first pair is written");
+    }
+
+    #[test]
+    fn read_flipped_matches_reverse_rows() {
+        for &(xsize, ysize, n_lines_read) in &[(3, 7, 2), (4, 6, 6), (1, 5, 10), (2, 0, 1)] {
+            let src = MemDataset::new((0..(xsize * ysize) as i32).collect());
+            let array = read_flipped(&src, xsize, ysize, n_lines_read, true).unwrap();
+            assert_eq!(array.dim(), (ysize, xsize));
+            for yy in 0..ysize {
+                for xx in 0..xsize {
+                    assert_eq!(array[[yy, xx]], ((ysize - 1 - yy) * xsize + xx) as i32);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn copy_rows_and_read_flipped_keep_order_without_flip() {
+        for &(xsize, ysize, n_lines_read) in &[(3, 7, 2), (4, 6, 6), (1, 5, 10), (2, 0, 1)] {
+            let n = (xsize * ysize) as i32;
+            let src = MemDataset::new((0..n).collect());
+            let dst = MemDataset::new(vec![-1; xsize * ysize]);
+            copy_rows(&src, &dst, xsize, ysize, n_lines_read, |_| Ok(())).unwrap();
+            assert_eq!(*dst.vals.borrow(), (0..n).collect::<Vec<_>>());
+            assert!(dst.writes.borrow().iter().all(|&count| count == 1));
+            let array = read_flipped(&src, xsize, ysize, n_lines_read, false).unwrap();
+            assert_eq!(array.into_raw_vec(), (0..n).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn reverse_rows_reports_completion() {
+        let src = MemDataset::new((0..12).collect());
+        let dst = MemDataset::new(vec![0; 12]);
+        let mut last = 0f32;
+        reverse_rows(&src, &dst, 3, 4, 1, |perc| {
+            last = perc;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(last, 1f32);
+    }
+
+    #[test]
+    fn reverse_rows_stops_on_progress_error() {
+        let src = MemDataset::new((0..12).collect());
+        let dst = MemDataset::new(vec![0; 12]);
+        let result = reverse_rows(&src, &dst, 3, 4, 1, |_| Err(()));
+        assert!(result.is_err());
+        assert!(dst.writes.borrow().iter().all(|&count| count == 0));
+    }
+}
@@ -0,0 +1,227 @@
+//! Whether the rows of the HDF5 datasets are flipped to match the outputs.
+//!
+//! Products are usually stored bottom-up (the first row southernmost),
+//! while north-up rasters start at the top, which is why datasets are
+//! reversed. The direction of the HDF5 rows is taken from a `GeoTransform`
+//! attribute of the file (the sign of its y resolution) or an `origin`
+//! attribute (e.g. `lower-left`, `upper-left`), and is otherwise assumed
+//! bottom-up; outputs run like the y resolution of the template.
+
+use crate::error::{Error, Result};
+use crate::strings;
+
+/// How the flip of the rows is decided.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlipOption {
+    /// From the row directions of the file and the template.
+    Auto,
+    Always,
+    Never,
+}
+
+impl Default for FlipOption {
+    fn default() -> Self {
+        return FlipOption::Auto;
+    }
+}
+
+impl FlipOption {
+    pub fn parse(text: &str) -> std::result::Result<FlipOption, String> {
+        return match text {
+            "auto" => Ok(FlipOption::Auto),
+            "yes" => Ok(FlipOption::Always),
+            "no" => Ok(FlipOption::Never),
+            _ => Err(format!(
+                "unknown flip '{}' (expected auto, yes or no)",
+                text
+            )),
+        };
+    }
+}
+
+/// Whether the first row of the datasets of `file` is the northernmost,
+/// from the attributes of its root group, or `None` if they do not say.
+pub fn file_top_down(file: &hdf5::File) -> Result<Option<bool>> {
+    let _silence = hdf5::silence_errors();
+    for name in &["GeoTransform", "geotransform", "geo_transform"] {
+        if let Ok(attr) = file.attr(name) {
+            let geo_transform = attr.read_raw::<f64>()?;
+            if geo_transform.len() != 6 || geo_transform[5] == 0f64 {
+                return Err(Error::Invalid(format!(
+                    "{} attribute is not a geotransform: {:?}",
+                    name, geo_transform
+                )));
+            }
+            return Ok(Some(geo_transform[5] < 0f64));
+        }
+    }
+    for name in &["origin", "Origin"] {
+        let origin = match file.attr(name) {
+            Ok(attr) => strings::read_strings(&attr),
+            Err(_) => continue,
+        };
+        let origin = origin.unwrap_or_default().concat().to_lowercase();
+        if origin.contains("lower") || origin.contains("bottom") || origin.contains("south") {
+            return Ok(Some(false));
+        }
+        if origin.contains("upper") || origin.contains("top") || origin.contains("north") {
+            return Ok(Some(true));
+        }
+        return Err(Error::Invalid(format!(
+            "{} attribute '{}' is neither an upper nor a lower corner",
+            name, origin
+        )));
+    }
+    return Ok(None);
+}
+
+/// Whether the rows of `file` are flipped to run like the template with
+/// `geo_transform`, according to `option`.
+pub fn needs_flip(option: FlipOption, file: &hdf5::File, geo_transform: &[f64; 6]) -> Result<bool> {
+    return match option {
+        FlipOption::Always => Ok(true),
+        FlipOption::Never => Ok(false),
+        FlipOption::Auto => {
+            let file_top_down = file_top_down(file)?.unwrap_or(false);
+            let template_top_down = geo_transform[5] < 0f64;
+            Ok(file_top_down != template_top_down)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hdf5::types::VarLenUnicode;
+
+    const NORTH_UP: [f64; 6] = [-180f64, 1f64, 0f64, 90f64, 0f64, -1f64];
+    const SOUTH_UP: [f64; 6] = [-180f64, 1f64, 0f64, -90f64, 0f64, 1f64];
+
+    /// Runs `check` on a file whose root group gets the attributes written
+    /// by `write`.
+    fn with_file<W, C>(name: &str, write: W, check: C)
+    where
+        W: FnOnce(&hdf5::File),
+        C: FnOnce(&hdf5::File),
+    {
+        let path =
+            std::env::temp_dir().join(format!("h2r-row-order-{}-{}.h5", std::process::id(), name));
+        let file = hdf5::File::create(&path).unwrap();
+        write(&file);
+        check(&file);
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn geo_transform(file: &hdf5::File, values: &[f64]) {
+        file.new_attr::<f64>()
+            .shape(values.len())
+            .create("GeoTransform")
+            .unwrap()
+            .write(values)
+            .unwrap();
+    }
+
+    fn origin(file: &hdf5::File, value: &str) {
+        file.new_attr::<VarLenUnicode>()
+            .create("origin")
+            .unwrap()
+            .write_scalar(&value.parse::<VarLenUnicode>().unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn parses_flip_options() {
+        assert_eq!(FlipOption::parse("auto"), Ok(FlipOption::Auto));
+        assert_eq!(FlipOption::parse("yes"), Ok(FlipOption::Always));
+        assert_eq!(FlipOption::parse("no"), Ok(FlipOption::Never));
+        assert!(FlipOption::parse("true").is_err());
+    }
+
+    #[test]
+    fn north_up_files_are_flipped_only_for_south_up_templates() {
+        with_file(
+            "north-geo",
+            |file| geo_transform(file, &NORTH_UP),
+            |file| {
+                assert_eq!(file_top_down(file).unwrap(), Some(true));
+                assert!(!needs_flip(FlipOption::Auto, file, &NORTH_UP).unwrap());
+                assert!(needs_flip(FlipOption::Auto, file, &SOUTH_UP).unwrap());
+            },
+        );
+        with_file(
+            "north-origin",
+            |file| origin(file, "Upper-Left"),
+            |file| {
+                assert_eq!(file_top_down(file).unwrap(), Some(true));
+            },
+        );
+    }
+
+    #[test]
+    fn south_up_files_are_flipped_only_for_north_up_templates() {
+        with_file(
+            "south-geo",
+            |file| geo_transform(file, &SOUTH_UP),
+            |file| {
+                assert_eq!(file_top_down(file).unwrap(), Some(false));
+                assert!(needs_flip(FlipOption::Auto, file, &NORTH_UP).unwrap());
+                assert!(!needs_flip(FlipOption::Auto, file, &SOUTH_UP).unwrap());
+            },
+        );
+        with_file(
+            "south-origin",
+            |file| origin(file, "lower-left"),
+            |file| {
+                assert_eq!(file_top_down(file).unwrap(), Some(false));
+            },
+        );
+    }
+
+    #[test]
+    fn files_without_attributes_are_assumed_south_up() {
+        with_file(
+            "unknown",
+            |_| {},
+            |file| {
+                assert_eq!(file_top_down(file).unwrap(), None);
+                assert!(needs_flip(FlipOption::Auto, file, &NORTH_UP).unwrap());
+                assert!(!needs_flip(FlipOption::Never, file, &NORTH_UP).unwrap());
+                assert!(needs_flip(FlipOption::Always, file, &SOUTH_UP).unwrap());
+            },
+        );
+    }
+
+    #[test]
+    fn ambiguous_attributes_are_rejected() {
+        with_file(
+            "center",
+            |file| origin(file, "center"),
+            |file| {
+                assert!(matches!(file_top_down(file), Err(Error::Invalid(_))));
+            },
+        );
+        with_file(
+            "flat",
+            |file| geo_transform(file, &[0f64; 6]),
+            |file| {
+                assert!(matches!(file_top_down(file), Err(Error::Invalid(_))));
+            },
+        );
+        with_file(
+            "short",
+            |file| geo_transform(file, &[0f64, 1f64, 0f64]),
+            |file| {
+                assert!(matches!(file_top_down(file), Err(Error::Invalid(_))));
+            },
+        );
+        // Option flags do not look at the file.
+        with_file(
+            "ambiguous",
+            |file| origin(file, "center"),
+            |file| {
+                assert!(needs_flip(FlipOption::Always, file, &NORTH_UP).unwrap());
+            },
+        );
+    }
+}
@@ -0,0 +1,812 @@
+//! Conversion service (`serve`, `server` feature), so other services can
+//! request conversions over HTTP without spawning processes.
+//!
+//! Both endpoints take a JSON job spec: the HDF5 file, the directory of the
+//! outputs, a profile of the manifest given to `serve` (see
+//! [`read_hdf_as_raster::batch`]) and flags given after those of the
+//! profile:
+//!
+//! ```json
+//! { "input": "granule_001.h5", "outdir": "out/001", "profile": "l4a", "args": ["--groups", "agbd"] }
+//! ```
+//!
+//! The input and output directory are relative to the input and output
+//! roots given to `serve`, and cannot leave them, even through symbolic
+//! links. The flags of a job are limited to those of [`JOB_FLAGS`], which
+//! name no file, command or URL: only the profiles, written by whoever
+//! runs the server, can give `--report`, `--on-complete`, `--notify-url`
+//! and the like.
+//!
+//! `POST /list` answers the inventory of the file, as `list --json`, the
+//! flags being those of `list`. `POST /convert` converts it in the server's
+//! process and streams lines of JSON: the status, as served by
+//! `--status-port`, while the conversion runs, then `{"report": ...}` with
+//! its report, whose `exit_code` tells how it ended. Conversions hold
+//! process-wide state, so they run one at a time, others waiting their
+//! turn; closing the connection cancels one. The worker threads are those
+//! of the server: `--jobs` only sizes the threads of GDAL.
+
+use crate::cli;
+use read_hdf_as_raster::batch::Manifest;
+use read_hdf_as_raster::error::{exit_code, Error, Result};
+use read_hdf_as_raster::interrupt::{self, CancellationToken};
+use read_hdf_as_raster::inventory;
+use read_hdf_as_raster::provenance;
+use read_hdf_as_raster::raster;
+use read_hdf_as_raster::status;
+use serde::Deserialize;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Flags a job may give, with whether they take a value: those choosing
+/// what is converted and how, but none naming a file read or written, a
+/// command or a URL, which only profiles may give.
+const JOB_FLAGS: &[(&str, bool)] = &[
+    ("--memory-limit", true),
+    ("--retries", true),
+    ("--retry-delay", true),
+    ("--jobs", true),
+    ("--gdal-threads", true),
+    ("--gdal-cache", true),
+    ("--stack-stat", true),
+    ("--keep-partial", false),
+    ("--resume", false),
+    ("--force", false),
+    ("--swmr", false),
+    ("--swmr-wait", true),
+    ("--skip-existing", false),
+    ("--overwrite", false),
+    ("--fail-if-exists", false),
+    ("--groups", true),
+    ("--datasets", true),
+    ("--time-series", true),
+    ("--time-coord", true),
+    ("--strict", false),
+    ("--summary", false),
+    ("--deterministic", false),
+    ("--provenance", false),
+    ("--stac", false),
+    ("--tile-scheme", true),
+    ("--valid-range", true),
+    ("--snap-scales", false),
+    ("--geolocation", true),
+    ("--geolocation-arrays", true),
+    ("--group-grid", true),
+    ("--ot", true),
+    ("--units", true),
+    ("--unit-scale", true),
+    ("--color-sidecar", true),
+    ("--out-of-range", true),
+    ("--stat-names", true),
+    ("--weight-names", true),
+    ("--field", true),
+    ("--flip", true),
+    ("--direct-stats", false),
+    ("--se", false),
+    ("--quality-mask", true),
+    ("--ci", true),
+    ("--min-count", true),
+    ("--nan-policy", true),
+    ("--single-obs-policy", true),
+    ("--h5-compress", true),
+    ("--h5-shuffle", true),
+    ("--h5-checksum", false),
+    ("--h5-resizable", false),
+    ("--h5-virtual", false),
+    ("--repack", false),
+    ("--repack-compress", true),
+    ("--h5-cache", true),
+    ("--format", true),
+    ("--band-order", true),
+    ("--sparse", false),
+    ("--float16", false),
+    ("--interleave", true),
+    ("--block-size", true),
+    ("--band", true),
+    ("--quicklook", false),
+    ("--quicklook-ramp", true),
+    ("--quicklook-stretch", true),
+    ("--verify", false),
+    ("--verify-windows", true),
+    ("--verify-epsilon", true),
+    ("--preview", true),
+    ("--verify-reverse", false),
+    ("--verify-reverse-rows", true),
+    ("--json", false),
+];
+
+/// Flags not even a profile may give: the fields of a job spec, and flags
+/// of the server process.
+const SERVER_FLAGS: &[&str] = &[
+    "--input",
+    "--outdir",
+    "--tui",
+    "--status-port",
+    "-h",
+    "--help",
+];
+
+/// Largest job spec read.
+const MAX_BODY: usize = 1 << 20;
+
+/// Largest request line and headers read, together.
+const MAX_HEAD: usize = 16 << 10;
+
+/// Connections answered at once, conversions waiting their turn included;
+/// others are refused.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Longest wait for each read and write of a connection.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time between two checks for Ctrl-C while no request arrives.
+const ACCEPT_STEP: Duration = Duration::from_millis(100);
+
+/// Held by the conversion running.
+static TURN: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobSpec {
+    /// HDF5 file, relative to the input root.
+    input: String,
+    /// Directory of the outputs, relative to the output root, else the root.
+    #[serde(default)]
+    outdir: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+    /// Flags given after those of the profile.
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// What the jobs are converted with.
+struct Service {
+    manifest: Manifest,
+    /// Directory the inputs of the jobs are under.
+    input_root: PathBuf,
+    /// Directory the outputs of the jobs are under, and that of the jobs
+    /// giving none.
+    output_root: PathBuf,
+    /// Connections being answered.
+    connections: AtomicUsize,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endpoint {
+    List,
+    Convert,
+}
+
+/// Paths of a job checked, under the roots.
+#[derive(Debug, PartialEq)]
+struct JobPaths {
+    input: PathBuf,
+    outdir: PathBuf,
+}
+
+/// Serves conversions with the profiles of `manifest` on `bind:port`, of
+/// files under `input_root` to directories under `output_root`, until
+/// Ctrl-C, then waits for the conversion running, if any, to stop.
+pub fn serve(
+    bind: &str,
+    port: u16,
+    manifest: Manifest,
+    input_root: PathBuf,
+    output_root: PathBuf,
+) -> Result<()> {
+    if !input_root.is_dir() {
+        return Err(Error::Invalid(format!(
+            "input root {} is not a directory",
+            input_root.display()
+        )));
+    }
+    let listener = TcpListener::bind((bind, port))?;
+    listener.set_nonblocking(true)?;
+    println!(
+        "Serving conversions of the files under {} to {} on http://{}:{}/",
+        input_root.display(),
+        output_root.display(),
+        bind,
+        port
+    );
+    let service = Arc::new(Service {
+        manifest,
+        input_root,
+        output_root,
+        connections: AtomicUsize::new(0),
+    });
+    // Ctrl-C only: a cancelled token stops its conversion, not the server.
+    while !interrupt::is_ctrl_c() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if service.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    service.connections.fetch_sub(1, Ordering::SeqCst);
+                    if let Err(err) = refuse(stream) {
+                        eprintln!("warning: could not refuse a connection: {}", err);
+                    }
+                    continue;
+                }
+                let service = Arc::clone(&service);
+                std::thread::spawn(move || {
+                    let answered = answer(stream, &service);
+                    service.connections.fetch_sub(1, Ordering::SeqCst);
+                    if let Err(err) = answered {
+                        eprintln!("warning: could not answer a request: {}", err);
+                    }
+                });
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_STEP),
+            Err(err) => eprintln!("warning: could not accept a connection: {}", err),
+        }
+    }
+    let _turn = TURN.lock();
+    return Ok(());
+}
+
+/// Answers a connection over [`MAX_CONNECTIONS`] with 503.
+fn refuse(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    return respond(
+        &mut stream,
+        "503 Service Unavailable",
+        &error_body(&format!(
+            "{} connections are already open, try again later",
+            MAX_CONNECTIONS
+        )),
+    );
+}
+
+/// Reads a line of the request line and headers, which may only take
+/// [`MAX_HEAD`] bytes together, `head` being those read before.
+fn read_head_line<R: BufRead>(reader: &mut R, head: &mut usize) -> io::Result<String> {
+    let mut line = String::new();
+    *head += reader
+        .by_ref()
+        .take((MAX_HEAD - *head) as u64)
+        .read_line(&mut line)?;
+    if *head >= MAX_HEAD && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("request line and headers over {} bytes", MAX_HEAD),
+        ));
+    }
+    return Ok(line);
+}
+
+fn read_request<R: Read>(stream: R) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut head = 0;
+    let line = read_head_line(&mut reader, &mut head)?;
+    let mut words = line.split_whitespace();
+    let method = words.next().unwrap_or_default().to_owned();
+    let path = words.next().unwrap_or_default().to_owned();
+    let mut length = 0;
+    loop {
+        let header = read_head_line(&mut reader, &mut head)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("request of {} bytes, over {}", length, MAX_BODY),
+        ));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    return Ok(Request { method, path, body });
+}
+
+fn answer(mut stream: TcpStream, service: &Service) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            return respond(
+                &mut stream,
+                "400 Bad Request",
+                &error_body(&err.to_string()),
+            );
+        }
+        Err(err) => return Err(err),
+    };
+    let endpoint = match route(&request.method, &request.path) {
+        Ok(endpoint) => endpoint,
+        Err((code, message)) => return respond(&mut stream, code, &error_body(&message)),
+    };
+    let checked = serde_json::from_slice::<JobSpec>(&request.body)
+        .map_err(|err| err.to_string())
+        .and_then(|spec| check(&spec, service).map(|paths| (spec, paths)));
+    let (spec, paths) = match checked {
+        Ok(checked) => checked,
+        Err(err) => return respond(&mut stream, "400 Bad Request", &error_body(&err)),
+    };
+    return match endpoint {
+        Endpoint::List => list(&mut stream, &spec, &paths),
+        Endpoint::Convert => convert(&mut stream, &spec, &paths, service),
+    };
+}
+
+/// Endpoint of a request, else the status and error to answer.
+fn route(method: &str, path: &str) -> std::result::Result<Endpoint, (&'static str, String)> {
+    return match (method, path) {
+        ("POST", "/list") => Ok(Endpoint::List),
+        ("POST", "/convert") => Ok(Endpoint::Convert),
+        (_, "/list") | (_, "/convert") => Err(("405 Method Not Allowed", "use POST".to_owned())),
+        (_, path) => Err(("404 Not Found", format!("no endpoint {}", path))),
+    };
+}
+
+/// Checks that the profile of `spec` exists and gives no flag of
+/// [`SERVER_FLAGS`], that the flags of `spec` are of [`JOB_FLAGS`] and that
+/// its paths stay under the roots, returning those paths.
+fn check(spec: &JobSpec, service: &Service) -> std::result::Result<JobPaths, String> {
+    if let Some(profile) = &spec.profile {
+        let args = match service.manifest.profiles.get(profile) {
+            Some(args) => args,
+            None => return Err(format!("no profile {}", profile)),
+        };
+        if let Some(flag) = args.iter().find(|arg| SERVER_FLAGS.contains(&arg.as_str())) {
+            return Err(format!(
+                "profile {} gives {}, which jobs cannot",
+                profile, flag
+            ));
+        }
+    }
+    let mut args = spec.args.iter();
+    while let Some(arg) = args.next() {
+        match JOB_FLAGS.iter().find(|(flag, _)| *flag == arg.as_str()) {
+            Some((_, true)) => {
+                // A missing value is reported by the parser.
+                args.next();
+            }
+            Some((_, false)) => {}
+            None => {
+                return Err(format!(
+                    "a job cannot give {}: only the conversion flags naming no file, \
+                     command or URL are accepted, the others being left to the profiles",
+                    arg
+                ))
+            }
+        }
+    }
+    if spec.input.is_empty() {
+        return Err("a job needs an input".to_owned());
+    }
+    return Ok(JobPaths {
+        input: under(&service.input_root, &spec.input, "input")?,
+        outdir: under(
+            &service.output_root,
+            spec.outdir.as_deref().unwrap_or(""),
+            "outdir",
+        )?,
+    });
+}
+
+/// `path` of a job under `root`, refused unless relative and without `..`
+/// and, once symbolic links are resolved, still under `root`.
+fn under(root: &Path, path: &str, what: &str) -> std::result::Result<PathBuf, String> {
+    let inside = Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return Err(format!(
+            "{} {} must be relative to the {} root of the server, without ..",
+            what, path, what
+        ));
+    }
+    let unresolved = |err: io::Error| format!("{} {} cannot be resolved: {}", what, path, err);
+    let root = resolve(root).map_err(unresolved)?;
+    let resolved = resolve(&root.join(path)).map_err(unresolved)?;
+    return match resolved.starts_with(&root) {
+        true => Ok(resolved),
+        false => Err(format!(
+            "{} {} leads outside the {} root of the server",
+            what, path, what
+        )),
+    };
+}
+
+/// `path` with its deepest existing ancestor canonicalized, followed by
+/// the components that do not exist yet (e.g. of an outdir to create).
+/// Dangling symbolic links are refused, as they could be created through.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let mut missing = vec![];
+    let mut existing = path;
+    loop {
+        let err = match existing.canonicalize() {
+            Ok(found) => {
+                return Ok(missing
+                    .iter()
+                    .rev()
+                    .fold(found, |found, name| found.join(name)));
+            }
+            Err(err) => err,
+        };
+        if existing.symlink_metadata().is_ok() {
+            return Err(err);
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                // A relative path ends in the working directory.
+                existing = match parent.as_os_str().is_empty() {
+                    true => Path::new("."),
+                    false => parent,
+                };
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+fn error_body(message: &str) -> String {
+    return serde_json::json!({ "error": message }).to_string();
+}
+
+fn respond(stream: &mut TcpStream, code: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        body.len(),
+        body
+    )?;
+    return stream.flush();
+}
+
+fn list(stream: &mut TcpStream, spec: &JobSpec, paths: &JobPaths) -> io::Result<()> {
+    let mut args = vec!["list".to_owned()];
+    args.extend(spec.args.iter().cloned());
+    let options = match cli::Command::parse(args) {
+        Ok(cli::Command::List(options)) => options,
+        Ok(_) => {
+            return respond(
+                stream,
+                "400 Bad Request",
+                &error_body("the flags of a listing cannot start another subcommand"),
+            );
+        }
+        Err(err) => return respond(stream, "400 Bad Request", &error_body(&err)),
+    };
+    let inventory = hdf5::File::open(&paths.input)
+        .map_err(Error::from)
+        .and_then(|file| {
+            return inventory::inventory(&file, &options.traversal(), &options.stat_names);
+        });
+    return match inventory.and_then(|inventory| Ok(serde_json::to_string(&inventory)?)) {
+        Ok(body) => respond(stream, "200 OK", &body),
+        Err(err) => respond(
+            stream,
+            "500 Internal Server Error",
+            &serde_json::json!({ "error": err.to_string(), "exit_code": err.exit_code() })
+                .to_string(),
+        ),
+    };
+}
+
+/// Flags of the conversion of `spec`: those of its profile, then its own.
+fn arguments(spec: &JobSpec, manifest: &Manifest) -> Vec<String> {
+    let mut args = spec
+        .profile
+        .as_ref()
+        .and_then(|profile| manifest.profiles.get(profile))
+        .cloned()
+        .unwrap_or_default();
+    args.extend(spec.args.iter().cloned());
+    return args;
+}
+
+fn convert(
+    stream: &mut TcpStream,
+    spec: &JobSpec,
+    paths: &JobPaths,
+    service: &Service,
+) -> io::Result<()> {
+    let args = arguments(spec, &service.manifest);
+    let options = match cli::Command::parse(args.clone()) {
+        Ok(cli::Command::Convert(options)) => options,
+        Ok(_) => {
+            return respond(
+                stream,
+                "400 Bad Request",
+                &error_body("the flags of a conversion cannot start with a subcommand"),
+            );
+        }
+        Err(err) => return respond(stream, "400 Bad Request", &error_body(&err)),
+    };
+    let input = paths.input.to_string_lossy().into_owned();
+    let outdir = &paths.outdir;
+    // The command line the conversion stands for, in its history and
+    // provenance.
+    let mut command_line = vec![
+        std::env::args().next().unwrap_or_default(),
+        "--input".to_owned(),
+        input.clone(),
+        "--outdir".to_owned(),
+        outdir.to_string_lossy().into_owned(),
+    ];
+    command_line.extend(args);
+
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n",
+    )?;
+    stream.flush()?;
+    let _turn = TURN.lock().unwrap_or_else(|err| err.into_inner());
+    if let Err(err) = std::fs::create_dir_all(outdir) {
+        let message = format!("could not create {}: {}", outdir.display(), err);
+        return write_line(stream, &serde_json::json!({ "error": message }));
+    }
+    println!("Converting {}", input);
+    status::reset();
+    provenance::clear();
+    raster::set_output_dir(Some(outdir.clone()));
+    let token = CancellationToken::new();
+    interrupt::set_token(Some(token.clone()));
+    let report = std::thread::scope(|scope| {
+        let conversion = scope.spawn(|| return crate::convert(&options, &input, &command_line));
+        let mut connected = true;
+        while !conversion.is_finished() {
+            std::thread::sleep(status::PRINT_INTERVAL);
+            if connected && write_line(stream, &status::snapshot()).is_err() {
+                println!("Cancelling {}: the client left", input);
+                token.cancel();
+                connected = false;
+            }
+        }
+        return conversion.join();
+    });
+    interrupt::set_token(None);
+    return match report {
+        Ok(report) => {
+            println!("Finished {} with exit code {}", input, report.exit_code);
+            write_line(stream, &serde_json::json!({ "report": report }))
+        }
+        Err(_) => write_line(
+            stream,
+            &serde_json::json!({
+                "error": format!("the conversion of {} panicked", input),
+                "exit_code": exit_code::IO,
+            }),
+        ),
+    };
+}
+
+fn write_line<T: serde::Serialize>(stream: &mut TcpStream, value: &T) -> io::Result<()> {
+    let line = serde_json::to_string(value).map_err(io::Error::from)?;
+    writeln!(stream, "{}", line)?;
+    return stream.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input and output roots of a test, named `name`, under a temporary
+    /// directory removed when dropped.
+    struct Roots {
+        base: PathBuf,
+    }
+
+    impl Roots {
+        fn new(name: &str) -> Roots {
+            let base =
+                std::env::temp_dir().join(format!("h2r-server-{}-{}", std::process::id(), name));
+            std::fs::create_dir_all(base.join("granules/2021")).unwrap();
+            std::fs::create_dir_all(base.join("out")).unwrap();
+            std::fs::write(base.join("granules/2021/a.h5"), b"").unwrap();
+            // Temporary directories may themselves be behind a link.
+            let base = base.canonicalize().unwrap();
+            return Roots { base };
+        }
+
+        fn service(&self) -> Service {
+            return service(self.base.join("granules"), self.base.join("out"));
+        }
+    }
+
+    impl Drop for Roots {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn service(input_root: PathBuf, output_root: PathBuf) -> Service {
+        let mut manifest = Manifest::default();
+        manifest.profiles.insert(
+            "l4a".to_owned(),
+            vec!["--report".to_owned(), "reports/l4a.json".to_owned()],
+        );
+        manifest.profiles.insert(
+            "bad".to_owned(),
+            vec!["--status-port".to_owned(), "9000".to_owned()],
+        );
+        return Service {
+            manifest,
+            input_root,
+            output_root,
+            connections: AtomicUsize::new(0),
+        };
+    }
+
+    fn spec(json: &str) -> JobSpec {
+        return serde_json::from_str(json).unwrap();
+    }
+
+    #[test]
+    fn routes_posts_to_the_endpoints() {
+        assert_eq!(route("POST", "/list"), Ok(Endpoint::List));
+        assert_eq!(route("POST", "/convert"), Ok(Endpoint::Convert));
+        assert_eq!(
+            route("GET", "/convert").unwrap_err().0,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(route("POST", "/status").unwrap_err().0, "404 Not Found");
+    }
+
+    #[test]
+    fn reads_requests_within_bounds() {
+        let request = read_request(
+            &b"POST /convert HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n{}"[..],
+        )
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/convert");
+        assert_eq!(request.body, b"{}");
+
+        let mut long = b"POST /convert HTTP/1.1\r\nX-Padding: ".to_vec();
+        long.extend(vec![b'a'; MAX_HEAD]);
+        long.extend(b"\r\n\r\n");
+        let err = read_request(&long[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let large = format!(
+            "POST /convert HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        let err = read_request(large.as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn arguments_are_those_of_the_profile_then_the_job() {
+        let service = service(PathBuf::from("granules"), PathBuf::from("out"));
+        let job = spec(r#"{"input": "a.h5", "profile": "l4a", "args": ["--groups", "agbd"]}"#);
+        assert_eq!(
+            arguments(&job, &service.manifest),
+            vec!["--report", "reports/l4a.json", "--groups", "agbd"]
+        );
+        let job = spec(r#"{"input": "a.h5", "args": ["--ot", "Int16"]}"#);
+        assert_eq!(arguments(&job, &service.manifest), vec!["--ot", "Int16"]);
+    }
+
+    #[test]
+    fn jobs_stay_under_the_roots() {
+        let roots = Roots::new("roots");
+        let service = roots.service();
+        let paths = check(
+            &spec(r#"{"input": "2021/a.h5", "outdir": "out/001", "profile": "l4a"}"#),
+            &service,
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            JobPaths {
+                input: roots.base.join("granules/2021/a.h5"),
+                outdir: roots.base.join("out/out/001"),
+            }
+        );
+        let paths = check(&spec(r#"{"input": "./2021/a.h5"}"#), &service).unwrap();
+        assert_eq!(paths.input, roots.base.join("granules/2021/a.h5"));
+        assert_eq!(paths.outdir, roots.base.join("out"));
+        // Output roots are created with the first outputs.
+        let service = service(roots.base.join("granules"), roots.base.join("new/out"));
+        let paths = check(&spec(r#"{"input": "a.h5", "outdir": "001"}"#), &service).unwrap();
+        assert_eq!(paths.outdir, roots.base.join("new/out/001"));
+        for json in [
+            r#"{"input": "/etc/passwd"}"#,
+            r#"{"input": "../secret.h5"}"#,
+            r#"{"input": "2021/../../secret.h5"}"#,
+            r#"{"input": ""}"#,
+            r#"{"input": "a.h5", "outdir": "/tmp"}"#,
+            r#"{"input": "a.h5", "outdir": "out/../../elsewhere"}"#,
+        ]
+        .iter()
+        {
+            assert!(check(&spec(json), &service).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn jobs_only_give_conversion_flags() {
+        let roots = Roots::new("flags");
+        let service = roots.service();
+        let allowed = [
+            r#"{"input": "a.h5", "args": ["--groups", "agbd", "--ot", "Int16", "--stac"]}"#,
+            // Values are skipped, even when they look like flags.
+            r#"{"input": "a.h5", "args": ["--field", "--report"]}"#,
+            // Profiles may give flags jobs cannot.
+            r#"{"input": "a.h5", "profile": "l4a"}"#,
+        ];
+        for json in allowed.iter() {
+            assert_eq!(check(&spec(json), &service).err(), None, "{}", json);
+        }
+        let refused = [
+            r#"{"input": "a.h5", "args": ["--report", "/etc/cron.d/job"]}"#,
+            r#"{"input": "a.h5", "args": ["--on-complete", "rm -rf /"]}"#,
+            r#"{"input": "a.h5", "args": ["--notify-url", "http://10.0.0.1/"]}"#,
+            r#"{"input": "a.h5", "args": ["--scratch", "/tmp/s.h5"]}"#,
+            r#"{"input": "a.h5", "args": ["--vrt", "/tmp/a.vrt"]}"#,
+            r#"{"input": "a.h5", "args": ["--stack", "/tmp/a.tif"]}"#,
+            r#"{"input": "a.h5", "args": ["--qgis-project", "/tmp/a.qgs"]}"#,
+            r#"{"input": "a.h5", "args": ["--strings-csv", "/tmp/a.csv"]}"#,
+            r#"{"input": "a.h5", "args": ["--template", "/etc/passwd"]}"#,
+            r#"{"input": "a.h5", "args": ["--follow-external-links"]}"#,
+            r#"{"input": "a.h5", "args": ["--outdir", "/tmp"]}"#,
+            r#"{"input": "a.h5", "args": ["--help"]}"#,
+            r#"{"input": "a.h5", "args": ["list"]}"#,
+            r#"{"input": "a.h5", "profile": "none"}"#,
+            r#"{"input": "a.h5", "profile": "bad"}"#,
+        ];
+        for json in refused.iter() {
+            assert!(check(&spec(json), &service).is_err(), "{}", json);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_cannot_leave_the_roots() {
+        use std::os::unix::fs::symlink;
+
+        let roots = Roots::new("links");
+        let service = roots.service();
+        let secret = roots.base.join("secret");
+        std::fs::create_dir_all(&secret).unwrap();
+        std::fs::write(secret.join("b.h5"), b"").unwrap();
+        symlink(&secret, roots.base.join("granules/elsewhere")).unwrap();
+        symlink(secret.join("b.h5"), roots.base.join("granules/b.h5")).unwrap();
+        symlink(&secret, roots.base.join("out/escape")).unwrap();
+        symlink(roots.base.join("missing"), roots.base.join("out/dangling")).unwrap();
+        // Links staying under the roots are followed.
+        symlink(
+            roots.base.join("granules/2021"),
+            roots.base.join("granules/latest"),
+        )
+        .unwrap();
+
+        for json in [
+            r#"{"input": "elsewhere/b.h5"}"#,
+            r#"{"input": "b.h5"}"#,
+            r#"{"input": "a.h5", "outdir": "escape"}"#,
+            r#"{"input": "a.h5", "outdir": "escape/001"}"#,
+            r#"{"input": "a.h5", "outdir": "dangling"}"#,
+            r#"{"input": "a.h5", "outdir": "dangling/001"}"#,
+        ]
+        .iter()
+        {
+            assert!(check(&spec(json), &service).is_err(), "{}", json);
+        }
+        let paths = check(&spec(r#"{"input": "latest/a.h5"}"#), &service).unwrap();
+        assert_eq!(paths.input, roots.base.join("granules/2021/a.h5"));
+    }
+}
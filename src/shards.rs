@@ -0,0 +1,325 @@
+//! Grids split into datasets of consecutive rows, e.g. `values_block0` to
+//! `values_blockN`, converted as one logical dataset.
+//!
+//! The shards of a logical dataset are listed in the `shards` section of the
+//! config file (`--config`), in row order, or named by a pattern whose `{}`
+//! stands for 0, 1, 2... up to the first missing dataset:
+//!
+//! ```json
+//! {
+//!     "shards": {
+//!         "/agbd/values": "/agbd/values_block{}",
+//!         "/rh98": ["/rh98_north", "/rh98_south"]
+//!     }
+//! }
+//! ```
+//!
+//! Each shard holds whole rows of the grid, flat or shaped (rows, xsize).
+//! They are read through [`ShardChunks`] as if concatenated, by the reversal
+//! only: it writes the single `_rev` dataset of the logical path that the
+//! later steps read.
+
+use crate::compression::H5Compression;
+use crate::error::{Error, Result};
+use crate::policy::{Decision, ExistsPolicy};
+use crate::reverse::{self, ChunkReader, HdfChunks};
+use crate::warnings::{self, Warning};
+use crate::{attrs, chunking, hdf, interrupt, status};
+use ndarray::Array1;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Shards of a logical dataset: their paths in row order, or a pattern.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ShardSpec {
+    /// A path with `{}` for the shard number, from 0.
+    Pattern(String),
+    Paths(Vec<String>),
+}
+
+/// A logical dataset at `path`, stored as `shards`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardedDataset {
+    pub path: String,
+    pub shards: ShardSpec,
+}
+
+/// The `shards` section of the config file; the others are read by
+/// [`crate::overrides`].
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    shards: BTreeMap<String, ShardSpec>,
+}
+
+/// Parses the sharded datasets of a config file.
+pub fn parse(text: &str) -> Result<Vec<ShardedDataset>> {
+    let config: ConfigFile = serde_json::from_str(text)?;
+    let mut sharded = vec![];
+    for (path, shards) in config.shards {
+        let path = format!("/{}", path.trim().trim_matches('/'));
+        match &shards {
+            ShardSpec::Pattern(pattern) if !pattern.contains("{}") => {
+                return Err(Error::Invalid(format!(
+                    "shards of {}: pattern {} has no {{}} for the shard number",
+                    path, pattern
+                )))
+            }
+            ShardSpec::Paths(paths) if paths.is_empty() => {
+                return Err(Error::Invalid(format!("shards of {}: no shards", path)))
+            }
+            _ => {}
+        }
+        sharded.push(ShardedDataset { path, shards });
+    }
+    return Ok(sharded);
+}
+
+/// Reads the sharded datasets of the config file at `path`.
+pub fn load(path: &Path) -> Result<Vec<ShardedDataset>> {
+    return parse(&std::fs::read_to_string(path)?).map_err(|err| {
+        return Error::Invalid(format!("config {}: {}", path.display(), err));
+    });
+}
+
+/// The sharded dataset whose logical path is `ds_path`, if any.
+pub fn find<'a>(sharded: &'a [ShardedDataset], ds_path: &str) -> Option<&'a ShardedDataset> {
+    let ds_path = ds_path.trim_matches('/');
+    return sharded
+        .iter()
+        .find(|sharded| sharded.path.trim_matches('/') == ds_path);
+}
+
+impl ShardedDataset {
+    /// Paths of the shards in `file`, in row order.
+    pub fn shard_paths(&self, file: &hdf5::File) -> Result<Vec<String>> {
+        let paths = match &self.shards {
+            ShardSpec::Paths(paths) => paths.clone(),
+            ShardSpec::Pattern(pattern) => (0..)
+                .map(|number: usize| pattern.replace("{}", &number.to_string()))
+                .take_while(|path| file.link_exists(path))
+                .collect(),
+        };
+        if paths.is_empty() {
+            return Err(Error::Invalid(format!(
+                "no shards of {} found in {}",
+                self.path,
+                file.filename()
+            )));
+        }
+        return Ok(paths);
+    }
+}
+
+/// The shards of a logical dataset read as the flat, row-major values of
+/// their concatenation.
+pub struct ShardChunks {
+    datasets: Vec<hdf5::Dataset>,
+    /// Index of the first element of each shard, and the total size last.
+    starts: Vec<usize>,
+}
+
+impl ShardChunks {
+    /// Opens the shards of `sharded` in `file`, which must be numeric, of one
+    /// type, and hold whole rows of an `xsize` by `ysize` grid.
+    pub fn open(
+        file: &hdf5::File,
+        sharded: &ShardedDataset,
+        (xsize, ysize): (usize, usize),
+    ) -> Result<ShardChunks> {
+        let mut datasets: Vec<hdf5::Dataset> = vec![];
+        let mut starts = vec![0];
+        for path in sharded.shard_paths(file)? {
+            let ds = file.dataset(&path)?;
+            if hdf::value_class(&ds)? != hdf::ValueClass::Numeric {
+                return Err(Error::Invalid(format!(
+                    "shard {} of {} is not numeric",
+                    path, sharded.path
+                )));
+            }
+            if let Some(first) = datasets.first() {
+                if ds.dtype()?.to_descriptor()? != first.dtype()?.to_descriptor()? {
+                    return Err(Error::Invalid(format!(
+                        "shard {} of {} has another type than {}",
+                        path,
+                        sharded.path,
+                        first.name()
+                    )));
+                }
+            }
+            let shape = ds.shape();
+            let whole_rows = match shape.len() {
+                2 => shape[1] == xsize,
+                _ => ds.size() % xsize == 0,
+            };
+            if !whole_rows {
+                return Err(Error::Invalid(format!(
+                    "shard {} of {} is shaped {:?}, not whole rows of {} pixels",
+                    path, sharded.path, shape, xsize
+                )));
+            }
+            starts.push(starts.last().unwrap() + ds.size());
+            datasets.push(ds);
+        }
+        let size = *starts.last().unwrap();
+        if size < xsize * ysize {
+            return Err(Error::Invalid(format!(
+                "shards of {} have {} rows, fewer than the {} of the {}x{} template",
+                sharded.path,
+                size / xsize,
+                ysize,
+                xsize,
+                ysize
+            )));
+        }
+        if size > xsize * ysize {
+            warnings::warn(
+                Warning::SizeMismatch,
+                format!(
+                    "shards of {} have {} rows, more than the {} of the {}x{} template; the \
+                     rest are ignored",
+                    sharded.path,
+                    size / xsize,
+                    ysize,
+                    xsize,
+                    ysize
+                ),
+            )?;
+        }
+        return Ok(ShardChunks { datasets, starts });
+    }
+
+    /// The first shard, whose fill value and attributes are those of the
+    /// logical dataset.
+    pub fn first(&self) -> &hdf5::Dataset {
+        return &self.datasets[0];
+    }
+}
+
+impl<T: hdf5::H5Type> ChunkReader<T> for ShardChunks {
+    type Error = Error;
+
+    fn read_range(&self, start: usize, end: usize) -> Result<Array1<T>> {
+        let mut vals = Vec::with_capacity(end - start);
+        for (ii, ds) in self.datasets.iter().enumerate() {
+            let (shard_start, shard_end) = (self.starts[ii], self.starts[ii + 1]);
+            if shard_end <= start || shard_start >= end {
+                continue;
+            }
+            let range = (
+                start.max(shard_start) - shard_start,
+                end.min(shard_end) - shard_start,
+            );
+            let shard_vals: Array1<T> = HdfChunks(ds).read_range(range.0, range.1)?;
+            vals.extend(shard_vals);
+        }
+        return Ok(Array1::from(vals));
+    }
+}
+
+/// Reverses the rows of the logical dataset `sharded` of `file`, like
+/// [`reverse::reverse_ds_rows`], into its `_rev` dataset in `out_file`,
+/// with the fill value and attributes of its first shard. The shards are
+/// read on this thread.
+#[allow(clippy::too_many_arguments)]
+pub fn reverse_shards<T: hdf5::H5Type + Clone + Default + Send>(
+    file: &hdf5::File,
+    out_file: &hdf5::File,
+    sharded: &ShardedDataset,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    memory_limit: usize,
+    policy: ExistsPolicy,
+    compression: &H5Compression,
+    resizable: bool,
+) -> Result<()> {
+    let ds_name_rev = format!("{}_rev", sharded.path);
+    if policy.decide_dataset(out_file, &ds_name_rev)? == Decision::Skip {
+        return Ok(());
+    }
+    let reader = ShardChunks::open(file, sharded, (xsize, ysize))?;
+    if hdf::loses_precision::<T>(reader.first())? {
+        warnings::warn(
+            Warning::PrecisionLoss,
+            format!(
+                "dataset {} is converted to {}, which cannot hold all its values",
+                sharded.path,
+                std::any::type_name::<T>()
+            ),
+        )?;
+    }
+    let bytes_per_pixel = chunking::reverse_bytes_per_pixel(std::mem::size_of::<T>());
+    let n_lines_read = chunking::strip_lines(memory_limit, xsize, bytes_per_pixel);
+    let block_cols = chunking::block_cols(memory_limit, xsize, bytes_per_pixel);
+    let layout = hdf::DatasetLayout {
+        chunk_len: chunking::h5_chunk_len(xsize, n_lines_read, std::mem::size_of::<T>()),
+        resizable,
+    };
+    let fill_value = match attrs::user_fill_value::<T>(reader.first())? {
+        Some(fill_value) => fill_value,
+        None => {
+            warnings::warn(
+                Warning::NodataFallback,
+                format!(
+                    "dataset {} has no fill value, using the {} default",
+                    sharded.path,
+                    std::any::type_name::<T>()
+                ),
+            )?;
+            T::default()
+        }
+    };
+    let ds_out = hdf::create_dataset::<T>(
+        out_file,
+        &ds_name_rev,
+        xsize * ysize,
+        fill_value,
+        layout,
+        compression,
+    )?;
+    attrs::copy_attributes::<T>(reader.first(), &ds_out, attrs::REV_ATTRIBUTES)?;
+    let on_progress = |frac: f32| -> Result<()> {
+        status::set_progress(frac);
+        status::print_progress()?;
+        return interrupt::check();
+    };
+    let writer = HdfChunks(&ds_out);
+    let result = if block_cols < xsize {
+        reverse::reverse_row_blocks::<T, _, _, Error, _>(
+            &reader,
+            &writer,
+            (xsize, ysize),
+            block_cols,
+            flip,
+            on_progress,
+        )
+    } else if !flip {
+        reverse::copy_rows::<T, _, _, Error, _>(
+            &reader,
+            &writer,
+            xsize,
+            ysize,
+            n_lines_read,
+            on_progress,
+        )
+    } else {
+        reverse::reverse_rows::<T, _, _, Error, _>(
+            &reader,
+            &writer,
+            xsize,
+            ysize,
+            n_lines_read,
+            on_progress,
+        )
+    };
+    if result.is_ok() {
+        status::end_progress();
+    } else {
+        println!();
+        drop(ds_out);
+        out_file.unlink(&ds_name_rev)?;
+    }
+    return result;
+}
@@ -0,0 +1,359 @@
+//! STAC Items (`--stac`) describing each output raster, so products can be
+//! registered in a STAC catalog: its footprint in WGS84, its date read from
+//! the attributes of the source, the projection extension fields of the
+//! template grid and links to the raster and its sidecars.
+
+use crate::error::Result;
+use crate::provenance;
+use crate::raster::GeoRef;
+use crate::strings;
+use crate::warnings::{self, Warning};
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+pub const STAC_VERSION: &str = "1.0.0";
+
+pub const PROJECTION_EXTENSION: &str =
+    "https://stac-extensions.github.io/projection/v1.1.0/schema.json";
+
+/// Attributes holding the date of the values, searched on the source
+/// dataset or group and then its parents.
+const DATETIME_ATTRIBUTES: [&str; 3] = ["datetime", "date", "time"];
+const START_ATTRIBUTES: [&str; 2] = ["start_datetime", "time_coverage_start"];
+const END_ATTRIBUTES: [&str; 2] = ["end_datetime", "time_coverage_end"];
+
+/// Date of the values of an item, as RFC 3339 timestamps.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemTimes {
+    pub datetime: Option<String>,
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+impl ItemTimes {
+    /// Reads the date attributes of `source` in `file`, falling back to
+    /// those of its parents up to the root group.
+    pub fn read(file: &hdf5::File, source: &str) -> ItemTimes {
+        let mut times = ItemTimes::default();
+        for path in ancestors(source) {
+            if times.datetime.is_none() {
+                times.datetime = first_attr(file, &path, &DATETIME_ATTRIBUTES);
+            }
+            if times.start.is_none() {
+                times.start = first_attr(file, &path, &START_ATTRIBUTES);
+            }
+            if times.end.is_none() {
+                times.end = first_attr(file, &path, &END_ATTRIBUTES);
+            }
+        }
+        return times;
+    }
+}
+
+/// `path` and its parent groups, the root last.
+fn ancestors(path: &str) -> Vec<String> {
+    let mut paths = vec![];
+    let mut path = format!("/{}", path.trim_matches('/'));
+    while path != "/" {
+        paths.push(path.clone());
+        let index = path.rfind('/').unwrap_or(0);
+        path.truncate(index.max(1));
+    }
+    paths.push(path);
+    return paths;
+}
+
+/// Value of the first of the attributes `names` of the dataset or group at
+/// `path` holding a date.
+fn first_attr(file: &hdf5::File, path: &str, names: &[&str]) -> Option<String> {
+    let _silence = hdf5::silence_errors();
+    for name in names {
+        let attr = match file.dataset(path) {
+            Ok(ds) => ds.attr(name),
+            Err(_) => file.group(path).and_then(|group| group.attr(name)),
+        };
+        let values = match attr.ok().and_then(|attr| strings::read_strings(&attr)) {
+            Some(values) => values,
+            None => continue,
+        };
+        if let Some(value) = values.first().and_then(|value| rfc3339(value)) {
+            return Some(value);
+        }
+    }
+    return None;
+}
+
+/// `value` as an RFC 3339 timestamp: full timestamps are kept, dates
+/// (`YYYY-MM-DD`) and years start at midnight UTC.
+fn rfc3339(value: &str) -> Option<String> {
+    let value = value.trim();
+    let digits = |text: &str| !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit());
+    let date = value.get(..10).unwrap_or(value);
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() == 1 && parts[0].len() == 4 && digits(parts[0]) {
+        return Some(format!("{}-01-01T00:00:00Z", value));
+    }
+    if parts.len() != 3 || parts[0].len() != 4 || !parts.iter().all(|part| digits(part)) {
+        return None;
+    }
+    if value.len() == 10 {
+        return Some(format!("{}T00:00:00Z", value));
+    }
+    let time = match value.get(11..) {
+        Some(time) if !time.is_empty() => time,
+        _ => return None,
+    };
+    let zoned = time.ends_with('Z') || time.contains('+') || time.contains('-');
+    return match zoned {
+        true => Some(format!("{}T{}", date, time)),
+        false => Some(format!("{}T{}Z", date, time)),
+    };
+}
+
+/// EPSG code of `crs`, given as `EPSG:<code>` or WKT whose outermost
+/// authority is EPSG.
+pub fn epsg_code(crs: &str) -> Option<u32> {
+    if let Some(code) = crs.trim().strip_prefix("EPSG:") {
+        return code.parse().ok();
+    }
+    // The authority of the CRS itself is the last one, followed only by
+    // the bracket closing the WKT.
+    for key in &["AUTHORITY[\"EPSG\",", "ID[\"EPSG\","] {
+        if let Some(index) = crs.rfind(key) {
+            let rest = &crs[index + key.len()..];
+            let end = rest.find(']')?;
+            if rest[end + 1..].trim() == "]" {
+                return rest[..end].trim_matches('"').parse().ok();
+            }
+        }
+    }
+    return None;
+}
+
+/// Bounds `[xmin, ymin, xmax, ymax]` of a grid of `xsize` by `ysize`
+/// pixels in the CRS of `georef`.
+pub fn native_bounds(georef: &GeoRef, (xsize, ysize): (usize, usize)) -> [f64; 4] {
+    let gt = &georef.geo_transform;
+    let corners = [
+        (0.0, 0.0),
+        (xsize as f64, 0.0),
+        (0.0, ysize as f64),
+        (xsize as f64, ysize as f64),
+    ];
+    let mut bounds = [
+        f64::INFINITY,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
+    ];
+    for (col, row) in corners.iter() {
+        let x = gt[0] + col * gt[1] + row * gt[2];
+        let y = gt[3] + col * gt[4] + row * gt[5];
+        bounds = [
+            bounds[0].min(x),
+            bounds[1].min(y),
+            bounds[2].max(x),
+            bounds[3].max(y),
+        ];
+    }
+    return bounds;
+}
+
+/// Whether `crs` is geographic longitude and latitude, so its bounds are
+/// already the WGS84 footprint.
+pub fn is_geographic(crs: &str) -> bool {
+    let crs = crs.trim();
+    return crs == "EPSG:4326"
+        || crs.starts_with("GEOGCS[")
+        || crs.starts_with("GEOGCRS[")
+        || crs.starts_with("GEODCRS[");
+}
+
+/// Footprint `[west, south, east, north]` of `bounds` in the CRS `crs`,
+/// reprojected with GDAL when not geographic.
+pub fn wgs84_bounds(crs: &str, bounds: [f64; 4]) -> Option<[f64; 4]> {
+    if is_geographic(crs) {
+        return Some(bounds);
+    }
+    return reproject_bounds(crs, bounds);
+}
+
+#[cfg(feature = "gdal")]
+fn reproject_bounds(crs: &str, bounds: [f64; 4]) -> Option<[f64; 4]> {
+    use gdal::spatial_ref::{CoordTransform, SpatialRef};
+    if crs.trim().is_empty() {
+        return None;
+    }
+    let source = SpatialRef::from_definition(crs).ok()?;
+    // PROJ strings keep the longitude first, unlike EPSG:4326.
+    let target = SpatialRef::from_proj4("+proj=longlat +datum=WGS84 +no_defs").ok()?;
+    let transform = CoordTransform::new(&source, &target).ok()?;
+    return transform.transform_bounds(&bounds, 21).ok();
+}
+
+/// Without GDAL only geographic grids have a footprint.
+#[cfg(not(feature = "gdal"))]
+fn reproject_bounds(_crs: &str, _bounds: [f64; 4]) -> Option<[f64; 4]> {
+    return None;
+}
+
+/// Media type of the raster at `path`, from its extension; `None` for
+/// sidecars and other files.
+fn media_type(path: &str) -> Option<&'static str> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    return match extension {
+        "tif" => Some("image/tiff; application=geotiff"),
+        "nc" => Some("application/netcdf"),
+        "zarr" => Some("application/vnd+zarr"),
+        _ => None,
+    };
+}
+
+/// Whether the output at `path` is a raster, which gets an item.
+pub fn is_raster(path: &str) -> bool {
+    return media_type(path).is_some();
+}
+
+/// Path of the STAC Item of the output at `out_path`.
+pub fn item_path(out_path: &str) -> String {
+    return Path::new(out_path)
+        .with_extension("json")
+        .to_string_lossy()
+        .into_owned();
+}
+
+/// File name of `path`, the href of assets next to their item.
+fn file_name(path: &str) -> String {
+    return Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned());
+}
+
+/// STAC Item of the raster at `out_path`, on a grid of `size` pixels
+/// georeferenced by `georef`, with the values of `times`.
+pub fn item(out_path: &str, georef: &GeoRef, size: (usize, usize), times: &ItemTimes) -> Value {
+    let id = Path::new(out_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| out_path.to_owned());
+    let bounds = native_bounds(georef, size);
+    let gt = &georef.geo_transform;
+
+    let mut properties = Map::new();
+    properties.insert("datetime".to_owned(), json!(times.datetime));
+    if let Some(start) = &times.start {
+        properties.insert("start_datetime".to_owned(), json!(start));
+    }
+    if let Some(end) = &times.end {
+        properties.insert("end_datetime".to_owned(), json!(end));
+    }
+    properties.insert("proj:epsg".to_owned(), json!(epsg_code(&georef.crs)));
+    if epsg_code(&georef.crs).is_none() && !georef.crs.is_empty() {
+        properties.insert("proj:wkt2".to_owned(), json!(georef.crs));
+    }
+    properties.insert("proj:shape".to_owned(), json!([size.1, size.0]));
+    properties.insert(
+        "proj:transform".to_owned(),
+        json!([gt[1], gt[2], gt[0], gt[4], gt[5], gt[3]]),
+    );
+    properties.insert("proj:bbox".to_owned(), json!(bounds));
+
+    let mut assets = Map::new();
+    assets.insert(
+        "data".to_owned(),
+        json!({
+            "href": file_name(out_path),
+            "type": media_type(out_path),
+            "title": id,
+            "roles": ["data"],
+        }),
+    );
+    let sidecars = [
+        (
+            "metadata",
+            provenance::sidecar_path(out_path),
+            "application/xml",
+            "metadata",
+        ),
+        (
+            "overview",
+            Path::new(out_path)
+                .with_extension("png")
+                .to_string_lossy()
+                .into_owned(),
+            "image/png",
+            "overview",
+        ),
+    ];
+    for (key, path, media_type, role) in sidecars.iter() {
+        if Path::new(path).is_file() {
+            assets.insert(
+                key.to_string(),
+                json!({"href": file_name(path), "type": media_type, "roles": [role]}),
+            );
+        }
+    }
+
+    let footprint = wgs84_bounds(&georef.crs, bounds);
+    let geometry = footprint.map(|[west, south, east, north]| {
+        json!({
+            "type": "Polygon",
+            "coordinates": [[
+                [west, south],
+                [east, south],
+                [east, north],
+                [west, north],
+                [west, south],
+            ]],
+        })
+    });
+    let mut item = Map::new();
+    item.insert("type".to_owned(), json!("Feature"));
+    item.insert("stac_version".to_owned(), json!(STAC_VERSION));
+    item.insert("stac_extensions".to_owned(), json!([PROJECTION_EXTENSION]));
+    item.insert("id".to_owned(), json!(id));
+    item.insert("geometry".to_owned(), json!(geometry));
+    if let Some(footprint) = footprint {
+        item.insert("bbox".to_owned(), json!(footprint));
+    }
+    item.insert("properties".to_owned(), Value::Object(properties));
+    item.insert(
+        "links".to_owned(),
+        json!([{"rel": "self", "href": file_name(&item_path(out_path)), "type": "application/geo+json"}]),
+    );
+    item.insert("assets".to_owned(), Value::Object(assets));
+    return Value::Object(item);
+}
+
+/// Writes the STAC Item of the raster at `out_path` (see [`item`]) next to
+/// it (see [`item_path`]), warning when the item has no date or footprint.
+pub fn write_item(
+    out_path: &str,
+    georef: &GeoRef,
+    size: (usize, usize),
+    times: &ItemTimes,
+) -> Result<()> {
+    if times.datetime.is_none() && (times.start.is_none() || times.end.is_none()) {
+        warnings::record(
+            Warning::Skipped,
+            format!(
+                "no datetime attribute for the STAC item of {}; its datetime is null",
+                out_path
+            ),
+        );
+    }
+    let item = item(out_path, georef, size, times);
+    if item["geometry"].is_null() {
+        warnings::record(
+            Warning::Skipped,
+            format!(
+                "could not compute the WGS84 footprint of {}; its STAC item has no geometry",
+                out_path
+            ),
+        );
+    }
+    let file = std::fs::File::create(item_path(out_path))?;
+    serde_json::to_writer_pretty(file, &item)?;
+    return Ok(());
+}
@@ -0,0 +1,1130 @@
+use crate::compression::H5Compression;
+use crate::error::Result;
+use crate::hdf::HdfSource;
+use crate::kernel::{self, ChunkInputs, StatisticKernel};
+use crate::nan_policy::{self, NonFinite};
+use crate::policy::{Decision, ExistsPolicy};
+use crate::timing::{self, Stage};
+use crate::{attrs, chunking, hdf, interrupt, meta, retry, status};
+use ndarray::{s, Array1, SliceInfo};
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Names of the accumulated datasets a group must contain to be processed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatNames {
+    pub sum: String,
+    pub sumsq: String,
+    pub count: String,
+    /// Weighted sums the mean and sd are computed from instead of `sum` and
+    /// `sumsq`.
+    pub weights: Option<WeightNames>,
+}
+
+impl Default for StatNames {
+    fn default() -> Self {
+        return StatNames {
+            sum: "sum".to_owned(),
+            sumsq: "sumsq".to_owned(),
+            count: "count".to_owned(),
+            weights: None,
+        };
+    }
+}
+
+/// Names of the weighted sums of a group: of the weights, the weighted
+/// values and the weighted squares, and optionally of the squared weights.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightNames {
+    pub sum_w: String,
+    pub sum_wx: String,
+    pub sum_wx2: String,
+    pub sum_w2: Option<String>,
+}
+
+impl WeightNames {
+    /// Parses `sum_w,sum_wx,sum_wx2[,sum_w2]` style lists of dataset names.
+    pub fn parse(text: &str) -> std::result::Result<WeightNames, String> {
+        let names: Vec<&str> = text.split(',').map(|name| name.trim()).collect();
+        if !(names.len() == 3 || names.len() == 4) || names.iter().any(|name| name.is_empty()) {
+            return Err(format!(
+                "expected three or four comma-separated dataset names \
+                 (sum_w,sum_wx,sum_wx2[,sum_w2]), got '{}'",
+                text
+            ));
+        }
+        return Ok(WeightNames {
+            sum_w: names[0].to_owned(),
+            sum_wx: names[1].to_owned(),
+            sum_wx2: names[2].to_owned(),
+            sum_w2: names.get(3).map(|name| (*name).to_owned()),
+        });
+    }
+}
+
+impl StatNames {
+    /// Parses `sum,sumsq,count` style lists of the three dataset names.
+    pub fn parse(text: &str) -> std::result::Result<StatNames, String> {
+        let names: Vec<&str> = text.split(',').map(|name| name.trim()).collect();
+        if names.len() != 3 || names.iter().any(|name| name.is_empty()) {
+            return Err(format!(
+                "expected three comma-separated dataset names (sum,sumsq,count), got '{}'",
+                text
+            ));
+        }
+        return Ok(StatNames {
+            sum: names[0].to_owned(),
+            sumsq: names[1].to_owned(),
+            count: names[2].to_owned(),
+            weights: None,
+        });
+    }
+
+    /// Datasets a group must contain: the sums (weighted ones if set) and
+    /// the count.
+    pub fn all(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = match &self.weights {
+            Some(weights) => vec![&weights.sum_w, &weights.sum_wx, &weights.sum_wx2],
+            None => vec![&self.sum, &self.sumsq],
+        };
+        if let Some(sum_w2) = self.weights.as_ref().and_then(|w| w.sum_w2.as_ref()) {
+            names.push(sum_w2);
+        }
+        names.push(&self.count);
+        return names;
+    }
+
+    /// Dataset whose attributes (e.g. `units`) the mean and sd take: the
+    /// sum, or the weighted sum.
+    pub fn value_sum(&self) -> &str {
+        return match &self.weights {
+            Some(weights) => &weights.sum_wx,
+            None => &self.sum,
+        };
+    }
+}
+
+/// Number of pixels handled per unrolled step of the kernel.
+const LANES: usize = 8;
+
+/// Chunks at least this large are split across the rayon pool.
+const PAR_THRESHOLD: usize = 1 << 16;
+
+/// What the sd of a pixel with a single observation is written as; the
+/// sample variance divides by `count - 1`, which is then 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SingleObsPolicy {
+    /// The nodata value of sds, -1.
+    Nodata,
+    Zero,
+}
+
+impl Default for SingleObsPolicy {
+    fn default() -> Self {
+        return SingleObsPolicy::Nodata;
+    }
+}
+
+impl SingleObsPolicy {
+    pub fn parse(text: &str) -> std::result::Result<SingleObsPolicy, String> {
+        return match text {
+            "nodata" => Ok(SingleObsPolicy::Nodata),
+            "zero" => Ok(SingleObsPolicy::Zero),
+            _ => Err(format!(
+                "invalid single observation policy '{}', expected nodata or zero",
+                text
+            )),
+        };
+    }
+
+    /// The sd written.
+    pub fn sd(self) -> f32 {
+        return match self {
+            SingleObsPolicy::Nodata => -1f32,
+            SingleObsPolicy::Zero => 0f32,
+        };
+    }
+}
+
+static SINGLE_OBS_ZERO: AtomicBool = AtomicBool::new(false);
+
+/// Writes the sd of single observations as `policy` for the rest of the
+/// process, like [`crate::nan_policy`], so values recomputed to verify or
+/// extract them match those written.
+pub fn set_single_obs_policy(policy: SingleObsPolicy) {
+    SINGLE_OBS_ZERO.store(policy == SingleObsPolicy::Zero, Ordering::SeqCst);
+}
+
+pub fn single_obs_policy() -> SingleObsPolicy {
+    return match SINGLE_OBS_ZERO.load(Ordering::SeqCst) {
+        true => SingleObsPolicy::Zero,
+        false => SingleObsPolicy::Nodata,
+    };
+}
+
+/// Element type of the `sum` and `sumsq` datasets; the mean and sd are
+/// computed in it.
+pub trait SumValue: Copy + Send + Sync {
+    /// Mean and sample sd of a pixel, with an sd of -1 without observations
+    /// and of `single_sd` with one.
+    fn pixel_mean_sd(sum: Self, sumsq: Self, count: u32, single_sd: f32) -> (f32, f32);
+}
+
+impl SumValue for f32 {
+    #[inline(always)]
+    fn pixel_mean_sd(sum: f32, sumsq: f32, count: u32, single_sd: f32) -> (f32, f32) {
+        let n = count as f32;
+        let mean = sum / n;
+        let variance = (sumsq - sum * sum / n) / (n - 1f32);
+        let sd = match count {
+            0 => -1f32,
+            1 => single_sd,
+            _ => variance.sqrt(),
+        };
+        return (mean, sd);
+    }
+}
+
+impl SumValue for f64 {
+    #[inline(always)]
+    fn pixel_mean_sd(sum: f64, sumsq: f64, count: u32, single_sd: f32) -> (f32, f32) {
+        let n = count as f64;
+        let mean = sum / n;
+        let variance = (sumsq - sum * sum / n) / (n - 1f64);
+        let sd = match count {
+            0 => -1f32,
+            1 => single_sd,
+            _ => variance.sqrt() as f32,
+        };
+        return (mean as f32, sd);
+    }
+}
+
+/// Serial kernel over plain slices. The main loop works on fixed-size
+/// blocks of `LANES` pixels without bounds checks so the compiler can
+/// vectorize it; the remainder is handled pixel by pixel. Single
+/// observations get the sd of [`single_obs_policy`].
+pub fn mean_sd_into<S: SumValue, C: Copy + Into<u32>>(
+    sum_vals: &[S],
+    sumsq_vals: &[S],
+    count_vals: &[C],
+    mean_out: &mut [f32],
+    sd_out: &mut [f32],
+) {
+    let len = sum_vals.len();
+    assert!(sumsq_vals.len() == len && count_vals.len() == len);
+    assert!(mean_out.len() == len && sd_out.len() == len);
+
+    let single_sd = single_obs_policy().sd();
+    let split = len - len % LANES;
+    let blocks = sum_vals[..split]
+        .chunks_exact(LANES)
+        .zip(sumsq_vals[..split].chunks_exact(LANES))
+        .zip(count_vals[..split].chunks_exact(LANES))
+        .zip(mean_out[..split].chunks_exact_mut(LANES))
+        .zip(sd_out[..split].chunks_exact_mut(LANES));
+    for ((((sum, sumsq), count), mean), sd) in blocks {
+        for ii in 0..LANES {
+            let (m, s) = S::pixel_mean_sd(sum[ii], sumsq[ii], count[ii].into(), single_sd);
+            mean[ii] = m;
+            sd[ii] = s;
+        }
+    }
+    for ii in split..len {
+        let (m, s) = S::pixel_mean_sd(
+            sum_vals[ii],
+            sumsq_vals[ii],
+            count_vals[ii].into(),
+            single_sd,
+        );
+        mean_out[ii] = m;
+        sd_out[ii] = s;
+    }
+}
+
+/// Weighted mean and sample sd from the sums of the weights, the weighted
+/// values and the weighted squares. The variance is corrected with the
+/// effective sample size: (sum_w)^2 / sum_w2 given the sums of the squared
+/// weights, else the count. Pixels without observations get an sd of -1,
+/// and with one the sd of [`single_obs_policy`].
+pub fn weighted_mean_sd_into(
+    sum_w_vals: &[f64],
+    sum_wx_vals: &[f64],
+    sum_wx2_vals: &[f64],
+    sum_w2_vals: Option<&[f64]>,
+    count_vals: &[u32],
+    mean_out: &mut [f32],
+    sd_out: &mut [f32],
+) {
+    let len = sum_w_vals.len();
+    assert!(sum_wx_vals.len() == len && sum_wx2_vals.len() == len && count_vals.len() == len);
+    assert!(sum_w2_vals.map_or(true, |vals| vals.len() == len));
+    assert!(mean_out.len() == len && sd_out.len() == len);
+
+    let single_sd = single_obs_policy().sd();
+    for ii in 0..len {
+        let sum_w = sum_w_vals[ii];
+        let mean = sum_wx_vals[ii] / sum_w;
+        let biased = sum_wx2_vals[ii] / sum_w - mean * mean;
+        let n_eff = match sum_w2_vals {
+            Some(sum_w2) => sum_w * sum_w / sum_w2[ii],
+            None => count_vals[ii] as f64,
+        };
+        let variance = biased * n_eff / (n_eff - 1f64);
+        mean_out[ii] = mean as f32;
+        sd_out[ii] = match count_vals[ii] {
+            0 => -1f32,
+            1 => single_sd,
+            _ => variance.sqrt() as f32,
+        };
+    }
+}
+
+/// Computes the per-pixel mean and sample standard deviation from the
+/// accumulated `sum`, `sumsq` and `count` values of one chunk, e.g. `f32`
+/// sums with `u8` counts or `f64` sums with `u32` counts. Pixels without
+/// observations get an sd of -1.
+pub fn mean_sd<S: SumValue, C: Copy + Into<u32> + Sync>(
+    sum_vals: &Array1<S>,
+    sumsq_vals: &Array1<S>,
+    count_vals: &Array1<C>,
+) -> (Array1<f32>, Array1<f32>) {
+    return split_mean_sd(
+        sum_vals.as_slice().unwrap(),
+        sumsq_vals.as_slice().unwrap(),
+        count_vals.as_slice().unwrap(),
+        mean_sd_into,
+    );
+}
+
+/// Runs `kernel` over the chunk, split across the rayon pool when large.
+fn split_mean_sd<S: Sync, C: Sync>(
+    sum_vals: &[S],
+    sumsq_vals: &[S],
+    count_vals: &[C],
+    kernel: fn(&[S], &[S], &[C], &mut [f32], &mut [f32]),
+) -> (Array1<f32>, Array1<f32>) {
+    let len = sum_vals.len();
+    let mut mean = vec![0f32; len];
+    let mut sd = vec![0f32; len];
+
+    if len < PAR_THRESHOLD {
+        kernel(sum_vals, sumsq_vals, count_vals, &mut mean, &mut sd);
+    } else {
+        mean.par_chunks_mut(PAR_THRESHOLD)
+            .zip(sd.par_chunks_mut(PAR_THRESHOLD))
+            .enumerate()
+            .for_each(|(ii, (mean, sd))| {
+                let lower = ii * PAR_THRESHOLD;
+                let upper = lower + mean.len();
+                kernel(
+                    &sum_vals[lower..upper],
+                    &sumsq_vals[lower..upper],
+                    &count_vals[lower..upper],
+                    mean,
+                    sd,
+                );
+            });
+    }
+    return (Array1::from(mean), Array1::from(sd));
+}
+
+/// Attribute of `mean_rev` and `sd_rev` recording the minimum count they
+/// were computed with.
+pub const MIN_COUNT_ATTRIBUTE: &str = "min_count";
+
+/// Gives the pixels with fewer than `min_count` observations the mean and
+/// sd of pixels without any: NaN and -1. A `min_count` of 0 or 1 changes
+/// nothing.
+pub fn mask_min_count<C: Copy + Into<u32>>(
+    count_vals: &[C],
+    min_count: u32,
+    mean_out: &mut [f32],
+    sd_out: &mut [f32],
+) {
+    if min_count <= 1 {
+        return;
+    }
+    for (ii, &count) in count_vals.iter().enumerate() {
+        if count.into() < min_count {
+            mean_out[ii] = f32::NAN;
+            sd_out[ii] = -1f32;
+        }
+    }
+}
+
+/// Minimum count `ds` (`mean_rev` or `sd_rev`) was computed with; 1 for
+/// datasets written before `--min-count` existed.
+pub fn min_count_of(ds: &hdf5::Dataset) -> u32 {
+    let _silence = hdf5::silence_errors();
+    return ds
+        .attr(MIN_COUNT_ATTRIBUTE)
+        .and_then(|attr| attr.read_scalar::<u32>())
+        .unwrap_or(1);
+}
+
+/// Fills `mean`/`sd` for a chunk without observations, matching what
+/// `mean_sd` would produce for zero sums.
+pub fn empty_mean_sd(len: usize) -> (Array1<f32>, Array1<f32>) {
+    return (
+        Array1::from_elem(len, f32::NAN),
+        Array1::from_elem(len, -1f32),
+    );
+}
+
+/// Path of dataset `name` of `group`, with `suffix` (`""` or `"_rev"`)
+/// appended to its name.
+fn stat_path(group: &str, name: &str, suffix: &str) -> String {
+    return hdf::join_path(group, &format!("{}{}", name, suffix));
+}
+
+/// Whether the mean and sd of `group` are computed from `f64` sums and
+/// `u32` counts: when its datasets (with `suffix`) are weighted sums, or
+/// any of them is stored wider than `f32` sums and `u8` counts, e.g. sums
+/// as `f64` or counts as `u32`.
+pub fn is_wide(file: &hdf5::File, group: &str, names: &StatNames, suffix: &str) -> Result<bool> {
+    if names.weights.is_some() {
+        return Ok(true);
+    }
+    let ds = |name: &str| meta::get(file, &stat_path(group, name, suffix));
+    return Ok(ds(&names.sum)?.loses_precision::<f32>()
+        || ds(&names.sumsq)?.loses_precision::<f32>()
+        || ds(&names.count)?.loses_precision::<u8>());
+}
+
+/// Mean and sd of pixels with `count_vals` observations, from the other
+/// datasets of their group read by name through `read`: weighted if
+/// `names` has weights, else from `f64` sums when `wide` or `f32` sums and
+/// `u8` counts otherwise, as stored. Pixels with fewer than `min_count`
+/// observations are masked, and non-finite values of the others replaced
+/// as [`nan_policy`] says.
+pub fn mean_sd_with<R>(
+    names: &StatNames,
+    wide: bool,
+    min_count: u32,
+    count_vals: &[u32],
+    read: R,
+) -> Result<(Vec<f32>, Vec<f32>)>
+where
+    R: FnMut(&str) -> Result<Vec<f64>>,
+{
+    let (mean, sd, _) = tallied_mean_sd(names, wide, min_count, count_vals, 0, read)?;
+    return Ok((mean, sd));
+}
+
+/// [`mean_sd_with`] for pixels from index `offset`, with their non-finite
+/// means and sds.
+fn tallied_mean_sd<R>(
+    names: &StatNames,
+    wide: bool,
+    min_count: u32,
+    count_vals: &[u32],
+    offset: usize,
+    mut read: R,
+) -> Result<(Vec<f32>, Vec<f32>, NonFinite)>
+where
+    R: FnMut(&str) -> Result<Vec<f64>>,
+{
+    let len = count_vals.len();
+    let mut mean = vec![0f32; len];
+    let mut sd = vec![0f32; len];
+    match &names.weights {
+        Some(weights) => {
+            let sum_w2 = match &weights.sum_w2 {
+                Some(name) => Some(read(name)?),
+                None => None,
+            };
+            weighted_mean_sd_into(
+                &read(&weights.sum_w)?,
+                &read(&weights.sum_wx)?,
+                &read(&weights.sum_wx2)?,
+                sum_w2.as_deref(),
+                count_vals,
+                &mut mean,
+                &mut sd,
+            );
+        }
+        None if wide => {
+            let (sum, sumsq) = (read(&names.sum)?, read(&names.sumsq)?);
+            mean_sd_into(&sum, &sumsq, count_vals, &mut mean, &mut sd);
+        }
+        None => {
+            let narrow = |vals: Vec<f64>| -> Vec<f32> {
+                return vals.iter().map(|&val| val as f32).collect();
+            };
+            let (sum, sumsq) = (narrow(read(&names.sum)?), narrow(read(&names.sumsq)?));
+            mean_sd_into(&sum, &sumsq, count_vals, &mut mean, &mut sd);
+        }
+    }
+    mask_min_count(count_vals, min_count, &mut mean, &mut sd);
+    let found = nan_policy::apply(count_vals, min_count, offset, &mut mean, &mut sd);
+    return Ok((mean, sd, found));
+}
+
+/// Reads the elements `start..end` of the `_rev` datasets of `group` and
+/// computes their mean and sd, as [`mean_sd_with`]. The counts and the
+/// non-finite means and sds are returned too. Fully empty chunks are
+/// common on sparse grids; their sums are not read.
+fn read_mean_sd(
+    file: &hdf5::File,
+    group: &str,
+    names: &StatNames,
+    wide: bool,
+    min_count: u32,
+    start: usize,
+    end: usize,
+) -> Result<(Vec<u32>, Array1<f32>, Array1<f32>, NonFinite)> {
+    let path = |name: &str| stat_path(group, name, "_rev");
+    if !wide {
+        let count: Vec<u8> = file.read_range(&path(&names.count), start, end)?;
+        if count.iter().all(|&el| el == 0) {
+            let (mean, sd) = empty_mean_sd(count.len());
+            return Ok((vec![0; count.len()], mean, sd, NonFinite::default()));
+        }
+        let sum: Vec<f32> = file.read_range(&path(&names.sum), start, end)?;
+        let sumsq: Vec<f32> = file.read_range(&path(&names.sumsq), start, end)?;
+        let count = Array1::from(count);
+        let (mut mean, mut sd) = mean_sd(&Array1::from(sum), &Array1::from(sumsq), &count);
+        let (mean_out, sd_out) = (mean.as_slice_mut().unwrap(), sd.as_slice_mut().unwrap());
+        mask_min_count(count.as_slice().unwrap(), min_count, mean_out, sd_out);
+        let found = nan_policy::apply(
+            count.as_slice().unwrap(),
+            min_count,
+            start,
+            mean_out,
+            sd_out,
+        );
+        return Ok((count.iter().map(|&el| el as u32).collect(), mean, sd, found));
+    }
+    let count: Vec<u32> = file.read_range(&path(&names.count), start, end)?;
+    if count.iter().all(|&el| el == 0) {
+        let (mean, sd) = empty_mean_sd(count.len());
+        return Ok((count, mean, sd, NonFinite::default()));
+    }
+    let (mean, sd, found) = tallied_mean_sd(names, wide, min_count, &count, start, |name| {
+        return file.read_range(&path(name), start, end);
+    })?;
+    return Ok((count, Array1::from(mean), Array1::from(sd), found));
+}
+
+/// Computes `mean_rev` and `sd_rev` of `group_name` chunk by chunk, with
+/// the mean and sd of pixels with fewer than `min_count` observations left
+/// empty. Both outputs are removed again if the computation fails part way.
+pub fn calc_mean_sd(
+    file: &hdf5::File,
+    group_name: &str,
+    names: &StatNames,
+    min_count: u32,
+    chunk_size: usize,
+    policy: ExistsPolicy,
+    compression: &H5Compression,
+    resizable: bool,
+) -> Result<()> {
+    let mean_path_out = hdf::join_path(group_name, "mean_rev");
+    let sd_path_out = hdf::join_path(group_name, "sd_rev");
+
+    if policy.decide_dataset(file, &mean_path_out)? == Decision::Skip {
+        return Ok(());
+    }
+    // mean and sd are always produced together, so sd follows mean's decision.
+    if file.link_exists(&sd_path_out) {
+        file.unlink(&sd_path_out)?;
+    }
+    let sum_ds: hdf5::Dataset = file.dataset(&stat_path(group_name, names.value_sum(), "_rev"))?;
+    let max_size = sum_ds.size();
+    // Groups may mix types, e.g. f64 sums with u32 counts.
+    let wide = is_wide(file, group_name, names, "_rev")?;
+
+    let layout = hdf::DatasetLayout {
+        chunk_len: chunking::h5_flat_chunk_len(chunk_size, std::mem::size_of::<f32>()),
+        resizable,
+    };
+    let create = |path: &str| -> Result<hdf5::Dataset> {
+        let ds = hdf::create_dataset(&file, path, max_size, f32::NAN, layout, compression)?;
+        attrs::copy_attributes::<f32>(&sum_ds, &ds, attrs::STAT_ATTRIBUTES)?;
+        ds.new_attr::<u32>()
+            .create(MIN_COUNT_ATTRIBUTE)?
+            .write_scalar(&min_count)?;
+        return Ok(ds);
+    };
+    let mean_ds_out = create(&mean_path_out)?;
+    let sd_ds_out = create(&sd_path_out)?;
+
+    let result = (|| -> Result<()> {
+        for ii in (0..max_size).step_by(chunk_size) {
+            interrupt::check()?;
+            status::set_progress(ii as f32 / max_size as f32);
+            let mut n_vals_read = chunk_size;
+
+            if (ii + n_vals_read) > max_size {
+                n_vals_read = max_size - ii;
+            }
+
+            let slice = s![ii..(ii + n_vals_read)];
+            let the_slice = SliceInfo::new(slice).unwrap();
+
+            let (_, mean, sd, found) = read_mean_sd(
+                file,
+                group_name,
+                names,
+                wide,
+                min_count,
+                ii,
+                ii + n_vals_read,
+            )?;
+            nan_policy::record(group_name, &found);
+
+            let started = Instant::now();
+            retry::with_retries(&format!("writing {}", mean_path_out), || {
+                return Ok(mean_ds_out.write_slice(&mean, &the_slice)?);
+            })?;
+            retry::with_retries(&format!("writing {}", sd_path_out), || {
+                return Ok(sd_ds_out.write_slice(&sd, &the_slice)?);
+            })?;
+            let bytes = 2 * n_vals_read * std::mem::size_of::<f32>();
+            timing::add(Stage::Write, started.elapsed(), bytes);
+            status::add_bytes(bytes);
+        }
+        return Ok(());
+    })();
+
+    if result.is_err() {
+        drop(mean_ds_out);
+        drop(sd_ds_out);
+        file.unlink(&mean_path_out)?;
+        file.unlink(&sd_path_out)?;
+    } else {
+        nan_policy::warn(group_name);
+    }
+    return result;
+}
+
+/// Outputs of a group derived from its mean, sd and count: the standard
+/// error of the mean and the bounds of its confidence interval.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ErrorOutputs {
+    /// Write the standard error, sd / sqrt(count).
+    pub se: bool,
+    /// Write the bounds of the interval mean -/+ z * se, for this z (e.g.
+    /// 1.96 for 95%).
+    pub ci_z: Option<f64>,
+}
+
+impl ErrorOutputs {
+    /// Statistics written, in the order they are computed.
+    pub fn stats(&self) -> Vec<&'static str> {
+        let mut stats = vec![];
+        if self.se {
+            stats.push("se");
+        }
+        if self.ci_z.is_some() {
+            stats.extend(&["ci_lower", "ci_upper"]);
+        }
+        return stats;
+    }
+
+    /// Parses the z value of `--ci`, which must be positive.
+    pub fn parse_z(text: &str) -> std::result::Result<f64, String> {
+        return match text.parse::<f64>() {
+            Ok(z) if z > 0f64 && z.is_finite() => Ok(z),
+            _ => Err(format!("invalid z value '{}' (expected e.g. 1.96)", text)),
+        };
+    }
+}
+
+/// Count thresholds of the quality class of each pixel, written as the
+/// `quality` output of a group: 0 without observations, 1 below `low`, 2
+/// below `high` and 3 from `high` on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityClasses {
+    pub low: u32,
+    pub high: u32,
+}
+
+impl QualityClasses {
+    /// Parses `LOW,HIGH`, with 1 <= LOW < HIGH.
+    pub fn parse(text: &str) -> std::result::Result<QualityClasses, String> {
+        let invalid = || format!("invalid quality thresholds '{}' (expected e.g. 3,10)", text);
+        let (low, high) = text.split_once(',').ok_or_else(invalid)?;
+        let low: u32 = low.trim().parse().map_err(|_| invalid())?;
+        let high: u32 = high.trim().parse().map_err(|_| invalid())?;
+        if low == 0 || high <= low {
+            return Err(invalid());
+        }
+        return Ok(QualityClasses { low, high });
+    }
+
+    pub fn class(self, count: u32) -> u8 {
+        return match count {
+            0 => 0,
+            count if count < self.low => 1,
+            count if count < self.high => 2,
+            _ => 3,
+        };
+    }
+}
+
+/// Standard error and interval bounds (mean -/+ `z` * se) of each pixel.
+/// Pixels without an sd (fewer than two observations, or masked) get NaN.
+pub fn error_stats(
+    mean_vals: &[f32],
+    sd_vals: &[f32],
+    count_vals: &[u32],
+    z: f64,
+) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    let len = mean_vals.len();
+    let mut se_out = Vec::with_capacity(len);
+    let mut lower_out = Vec::with_capacity(len);
+    let mut upper_out = Vec::with_capacity(len);
+    for ii in 0..len {
+        let (mean, sd, count) = (mean_vals[ii] as f64, sd_vals[ii] as f64, count_vals[ii]);
+        let se = if count == 0 || !(sd >= 0f64) {
+            f64::NAN
+        } else {
+            sd / (count as f64).sqrt()
+        };
+        se_out.push(se as f32);
+        lower_out.push((mean - z * se) as f32);
+        upper_out.push((mean + z * se) as f32);
+    }
+    return (se_out, lower_out, upper_out);
+}
+
+/// Count, mean and sd of one range of a group, as read or computed by
+/// [`StatSource`], and the error and kernel outputs derived from them.
+struct Computed {
+    group: String,
+    /// Whether the group's datasets are read as `f64` sums and `u32` counts.
+    wide: bool,
+    start: usize,
+    end: usize,
+    count: Vec<u32>,
+    mean: Vec<f32>,
+    sd: Vec<f32>,
+    se: Vec<f32>,
+    ci_lower: Vec<f32>,
+    ci_upper: Vec<f32>,
+    /// Outputs of the kernels, in the order of their names.
+    custom: Vec<Vec<f32>>,
+}
+
+impl Computed {
+    fn covers(&self, group: &str, start: usize, end: usize) -> bool {
+        return self.group == group && self.start == start && self.end == end;
+    }
+
+    fn output(&self, output: &str, custom: &[String]) -> Vec<f32> {
+        return match output {
+            "mean" => self.mean.clone(),
+            "sd" => self.sd.clone(),
+            "se" => self.se.clone(),
+            "ci_lower" => self.ci_lower.clone(),
+            "ci_upper" => self.ci_upper.clone(),
+            _ => {
+                let index = custom.iter().position(|name| name == output).unwrap();
+                self.custom[index].clone()
+            }
+        };
+    }
+}
+
+/// Source of the reversed datasets of statistic groups in `file`, which
+/// also provides the error outputs of a group (`se_rev`, `ci_lower_rev` and
+/// `ci_upper_rev`) computed from its mean, sd and count, its `quality_rev`
+/// classes of `quality` from the count, and the outputs of `kernels`
+/// (`<name>_rev`).
+///
+/// With `direct`, the `mean_rev` and `sd_rev` of a group are computed from
+/// its reversed sum, sumsq and count datasets as they are read, with pixels
+/// with fewer than `min_count` observations left empty, instead of read
+/// from datasets written by [`calc_mean_sd`]. The last range read for a
+/// computation is kept, so reading the computed outputs of a range first
+/// and then the others reads each dataset once.
+pub struct StatSource<'a> {
+    file: &'a hdf5::File,
+    names: &'a StatNames,
+    direct: bool,
+    min_count: u32,
+    errors: ErrorOutputs,
+    quality: Option<QualityClasses>,
+    kernels: &'a [Arc<dyn StatisticKernel>],
+    /// Output names of `kernels`, in order.
+    custom: Vec<String>,
+    last: RefCell<Option<Computed>>,
+    /// Groups and starts of the ranges counted in `non_finite`.
+    tallied: RefCell<HashSet<(String, usize)>>,
+    non_finite: RefCell<NonFinite>,
+}
+
+impl<'a> StatSource<'a> {
+    pub fn new(
+        file: &'a hdf5::File,
+        names: &'a StatNames,
+        direct: bool,
+        min_count: u32,
+        errors: ErrorOutputs,
+        quality: Option<QualityClasses>,
+        kernels: &'a [Arc<dyn StatisticKernel>],
+    ) -> Self {
+        return StatSource {
+            file,
+            names,
+            direct,
+            min_count,
+            errors,
+            quality,
+            kernels,
+            custom: kernels.iter().flat_map(|kernel| kernel.outputs()).collect(),
+            last: RefCell::new(None),
+            tallied: RefCell::new(HashSet::new()),
+            non_finite: RefCell::new(NonFinite::default()),
+        };
+    }
+
+    /// Group and output (`mean`, `sd`, `count`, `se`, `ci_lower`,
+    /// `ci_upper`, `quality` or a kernel output) of `path`, if it is one of a
+    /// group's outputs.
+    fn group_output<'p>(&self, path: &'p str) -> Option<(&'p str, &'p str)> {
+        let (group, name) = match path.rfind('/') {
+            Some(index) => (&path[..index], &path[index + 1..]),
+            None => ("", path),
+        };
+        let output = match name.strip_suffix("_rev")? {
+            "mean" => "mean",
+            "sd" => "sd",
+            "se" => "se",
+            "ci_lower" if self.errors.ci_z.is_some() => "ci_lower",
+            "ci_upper" if self.errors.ci_z.is_some() => "ci_upper",
+            "quality" if self.quality.is_some() => "quality",
+            name if name == self.names.count => "count",
+            name if self.custom.iter().any(|custom| custom == name) => name,
+            _ => return None,
+        };
+        return Some((group, output));
+    }
+
+    /// Whether `output` is computed rather than read as is.
+    fn is_computed(&self, output: &str) -> bool {
+        return match output {
+            "mean" | "sd" => self.direct,
+            "count" => false,
+            _ => true,
+        };
+    }
+
+    /// Non-finite means and sds computed so far with `direct`, each range
+    /// counted once.
+    pub fn non_finite(&self) -> NonFinite {
+        return self.non_finite.borrow().clone();
+    }
+
+    fn compute(&self, group: &str, start: usize, end: usize) -> Result<()> {
+        let mut wide = None;
+        if let Some(last) = self.last.borrow().as_ref() {
+            if last.covers(group, start, end) {
+                return Ok(());
+            }
+            if last.group == group {
+                wide = Some(last.wide);
+            }
+        }
+        let (wide, count, mean, sd) = if self.direct {
+            let wide = match wide {
+                Some(wide) => wide,
+                None => is_wide(self.file, group, self.names, "_rev")?,
+            };
+            let (count, mean, sd, found) = read_mean_sd(
+                self.file,
+                group,
+                self.names,
+                wide,
+                self.min_count,
+                start,
+                end,
+            )?;
+            // Ranges may be computed again once evicted by another.
+            if self.tallied.borrow_mut().insert((group.to_owned(), start)) {
+                self.non_finite.borrow_mut().add(&found);
+            }
+            (wide, count, mean.to_vec(), sd.to_vec())
+        } else {
+            let path = |name: &str| hdf::join_path(group, name);
+            let mean: Vec<f32> = self.file.read_range(&path("mean_rev"), start, end)?;
+            let sd: Vec<f32> = self.file.read_range(&path("sd_rev"), start, end)?;
+            let count_path = stat_path(group, &self.names.count, "_rev");
+            let count: Vec<u32> = self.file.read_range(&count_path, start, end)?;
+            (false, count, mean, sd)
+        };
+        let (se, ci_lower, ci_upper) = match self.errors == ErrorOutputs::default() {
+            true => (vec![], vec![], vec![]),
+            false => error_stats(&mean, &sd, &count, self.errors.ci_z.unwrap_or(0f64)),
+        };
+        let mut custom = vec![];
+        for kernel in self.kernels.iter() {
+            let mut datasets: Vec<Vec<f64>> = vec![];
+            for name in kernel.inputs() {
+                let path = stat_path(group, &name, "_rev");
+                datasets.push(self.file.read_range(&path, start, end)?);
+            }
+            let chunk = ChunkInputs {
+                count: &count,
+                mean: &mean,
+                sd: &sd,
+                datasets: &datasets,
+            };
+            custom.extend(kernel::run(kernel.as_ref(), &chunk, self.min_count)?);
+        }
+        *self.last.borrow_mut() = Some(Computed {
+            group: group.to_owned(),
+            wide,
+            start,
+            end,
+            count,
+            mean,
+            sd,
+            se,
+            ci_lower,
+            ci_upper,
+            custom,
+        });
+        return Ok(());
+    }
+
+    fn len_of(&self, path: &str) -> Result<usize> {
+        return match self.group_output(path) {
+            Some((group, output)) if self.is_computed(output) => {
+                let count_path = hdf::join_path(group, &format!("{}_rev", self.names.count));
+                HdfSource::<u8>::len(self.file, &count_path)
+            }
+            _ => HdfSource::<u8>::len(self.file, path),
+        };
+    }
+}
+
+impl<'a> HdfSource<u8> for StatSource<'a> {
+    fn len(&self, path: &str) -> Result<usize> {
+        return self.len_of(path);
+    }
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> Result<Vec<u8>> {
+        // Quality classes need the counts only, from the last range computed
+        // or else read on their own.
+        if let (Some((group, "quality")), Some(quality)) = (self.group_output(path), self.quality) {
+            if let Some(last) = self.last.borrow().as_ref() {
+                if last.covers(group, start, end) {
+                    return Ok(last
+                        .count
+                        .iter()
+                        .map(|&count| quality.class(count))
+                        .collect());
+                }
+            }
+            let count_path = stat_path(group, &self.names.count, "_rev");
+            let count: Vec<u32> = self.file.read_range(&count_path, start, end)?;
+            return Ok(count.iter().map(|&count| quality.class(count)).collect());
+        }
+        // Counts are only taken from the last range computed, as reading
+        // them alone needs no sums. Count outputs are bytes, saturated at
+        // 255.
+        if let Some((group, "count")) = self.group_output(path) {
+            if let Some(last) = self.last.borrow().as_ref() {
+                if last.covers(group, start, end) {
+                    return Ok(last
+                        .count
+                        .iter()
+                        .map(|&count| count.min(u8::MAX as u32) as u8)
+                        .collect());
+                }
+            }
+        }
+        return self.file.read_range(path, start, end);
+    }
+}
+
+impl<'a> HdfSource<f32> for StatSource<'a> {
+    fn len(&self, path: &str) -> Result<usize> {
+        return self.len_of(path);
+    }
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> Result<Vec<f32>> {
+        let (group, output) = match self.group_output(path) {
+            Some((group, output)) if output != "count" && output != "quality" => (group, output),
+            _ => return self.file.read_range(path, start, end),
+        };
+        if self.is_computed(output) {
+            self.compute(group, start, end)?;
+        } else {
+            match self.last.borrow().as_ref() {
+                Some(last) if last.covers(group, start, end) => {}
+                _ => return self.file.read_range(path, start, end),
+            }
+        }
+        return Ok(self
+            .last
+            .borrow()
+            .as_ref()
+            .unwrap()
+            .output(output, &self.custom));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_sd_of_narrow_and_wide_sums() {
+        // Observations {1, 3}, {2, 2, 5} and none, then past the lanes.
+        let sum = [4., 9., 0., 4., 9., 0., 4., 9., 0., 4.];
+        let sumsq = [10., 33., 0., 10., 33., 0., 10., 33., 0., 10.];
+        let count = [2, 3, 0, 2, 3, 0, 2, 3, 0, 2];
+        let expected_sd = [2f32.sqrt(), 3f32.sqrt(), -1.];
+
+        let (mut mean, mut sd) = (vec![0f32; 10], vec![0f32; 10]);
+        let sum_f32: Vec<f32> = sum.iter().map(|&val| val as f32).collect();
+        let sumsq_f32: Vec<f32> = sumsq.iter().map(|&val| val as f32).collect();
+        let count_u8: Vec<u8> = count.iter().map(|&val| val as u8).collect();
+        mean_sd_into(&sum_f32, &sumsq_f32, &count_u8, &mut mean, &mut sd);
+        let (wide_mean, wide_sd) = mean_sd(
+            &Array1::from(sum.to_vec()),
+            &Array1::from(sumsq.to_vec()),
+            &Array1::from(count.iter().map(|&val| val as u32).collect::<Vec<u32>>()),
+        );
+        for ii in 0..10 {
+            for (mean, sd) in [(mean[ii], sd[ii]), (wide_mean[ii], wide_sd[ii])].iter() {
+                match count[ii] {
+                    0 => assert!(mean.is_nan()),
+                    _ => assert!((mean - 2. - (ii % 3) as f32).abs() < 1e-6),
+                }
+                assert!((sd - expected_sd[ii % 3]).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_chunks_match_a_naive_loop() {
+        // Past the rayon split and not a whole number of lanes, so both the
+        // chunk boundaries and the remainder of the last chunk are covered.
+        let len = 2 * PAR_THRESHOLD + LANES + 3;
+        let mut sum = vec![0f64; len];
+        let mut sumsq = vec![0f64; len];
+        let mut count = vec![0u32; len];
+        let mut expected = Vec::with_capacity(len);
+        for ii in 0..len {
+            let obs: Vec<f64> = (0..ii % 5)
+                .map(|kk| ((ii * 7 + kk * 13) % 50) as f64 / 4f64)
+                .collect();
+            sum[ii] = obs.iter().sum();
+            sumsq[ii] = obs.iter().map(|val| val * val).sum();
+            count[ii] = obs.len() as u32;
+            let n = obs.len() as f64;
+            let mean = sum[ii] / n;
+            let sd = match obs.len() {
+                0 | 1 => -1f64,
+                _ => (obs.iter().map(|val| (val - mean).powi(2)).sum::<f64>() / (n - 1f64)).sqrt(),
+            };
+            expected.push((mean, sd));
+        }
+
+        let (mean, sd) = mean_sd(
+            &Array1::from(sum.clone()),
+            &Array1::from(sumsq.clone()),
+            &Array1::from(count.clone()),
+        );
+        assert_eq!((mean.len(), sd.len()), (len, len));
+        for (ii, &(expected_mean, expected_sd)) in expected.iter().enumerate() {
+            match count[ii] {
+                0 => assert!(mean[ii].is_nan(), "mean of pixel {}", ii),
+                _ => assert!(
+                    (mean[ii] as f64 - expected_mean).abs() < 1e-5,
+                    "mean of pixel {}: {} != {}",
+                    ii,
+                    mean[ii],
+                    expected_mean
+                ),
+            }
+            assert!(
+                (sd[ii] as f64 - expected_sd).abs() < 1e-5,
+                "sd of pixel {}: {} != {}",
+                ii,
+                sd[ii],
+                expected_sd
+            );
+        }
+
+        // The split only changes which thread computes a pixel.
+        let (mut serial_mean, mut serial_sd) = (vec![0f32; len], vec![0f32; len]);
+        mean_sd_into(&sum, &sumsq, &count, &mut serial_mean, &mut serial_sd);
+        assert!(serial_sd.iter().zip(sd.iter()).all(|(a, b)| a == b));
+        assert!(serial_mean
+            .iter()
+            .zip(mean.iter())
+            .all(|(a, b)| a == b || (a.is_nan() && b.is_nan())));
+    }
+
+    #[test]
+    fn sd_of_single_observations() {
+        // sumsq rounded below sum² would give NaN, and exact sums 0 / 0.
+        for &(sum, sumsq) in [(3.1f64, 9.6f64), (3., 9.)].iter() {
+            let (mean, sd) = f64::pixel_mean_sd(sum, sumsq, 1, -1.);
+            assert_eq!((mean, sd), (sum as f32, -1.));
+            let (mean, sd) = f32::pixel_mean_sd(sum as f32, sumsq as f32, 1, 0.);
+            assert_eq!((mean, sd), (sum as f32, 0.));
+        }
+        assert_eq!(SingleObsPolicy::default().sd(), -1.);
+        assert_eq!(SingleObsPolicy::parse("zero").unwrap().sd(), 0.);
+        assert!(SingleObsPolicy::parse("nan").is_err());
+
+        // Also the default of the slice and weighted kernels.
+        let (mut mean, mut sd) = (vec![0f32; 2], vec![0f32; 2]);
+        mean_sd_into(&[5f32, 0.], &[25f32, 0.], &[1u8, 0], &mut mean, &mut sd);
+        assert_eq!((mean[0], sd[0], sd[1]), (5., -1., -1.));
+        weighted_mean_sd_into(
+            &[2.],
+            &[10.],
+            &[50.],
+            None,
+            &[1],
+            &mut mean[..1],
+            &mut sd[..1],
+        );
+        assert_eq!((mean[0], sd[0]), (5., -1.));
+    }
+
+    #[test]
+    fn non_finite_means_and_sds_are_counted() {
+        // A negative variance from rounded sums, an overflowed sum, a pixel
+        // below the minimum count and one without observations.
+        let sum = [4f32, f32::INFINITY, 4., 0.];
+        let sumsq = [1f32, 1., 1., 0.];
+        let count = [2u32, 2, 1, 0];
+        let (mut mean, mut sd) = (vec![0f32; 4], vec![0f32; 4]);
+        mean_sd_into(&sum, &sumsq, &count, &mut mean, &mut sd);
+        mask_min_count(&count, 2, &mut mean, &mut sd);
+        let found = nan_policy::apply(&count, 2, 10, &mut mean, &mut sd);
+        assert_eq!((found.mean_nan, found.mean_inf), (0, 1));
+        assert_eq!((found.sd_nan, found.sd_inf), (2, 0));
+        assert_eq!(found.first_indices, vec![10, 11]);
+        // Written as nodata by default.
+        assert_eq!(mean[0], 2.);
+        assert!(mean[1].is_nan());
+        assert_eq!((sd[0], sd[1]), (-1., -1.));
+    }
+
+    #[test]
+    fn quality_classes_of_counts() {
+        let quality = QualityClasses::parse("3,10").unwrap();
+        let classes: Vec<u8> = [0, 1, 2, 3, 9, 10, 300]
+            .iter()
+            .map(|&count| quality.class(count))
+            .collect();
+        assert_eq!(classes, vec![0, 1, 1, 2, 2, 3, 3]);
+        assert!(QualityClasses::parse("0,10").is_err());
+        assert!(QualityClasses::parse("10,10").is_err());
+        assert!(QualityClasses::parse("3").is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//! Live run status, optionally served as JSON over a local HTTP endpoint.
+//!
+//! Processing loops update a process-wide status as they go, the same way
+//! they poll the interrupt flag, so no state has to be threaded through them.
+//! Programs embedding the crate follow it through a [`ProgressCallback`]
+//! (see [`set_callback`]), called with the status after every chunk. Like
+//! the status, the callback is process-wide: it sees the chunks of every
+//! run in the process, so it only tells the progress of one conversion when
+//! conversions run one at a time. A converter given a callback refuses to
+//! open while another is set.
+
+use crate::timing;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shortest time between two progress lines printed by [`print_progress`].
+pub const PRINT_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Status {
+    pub stage: String,
+    pub current: String,
+    pub task_index: usize,
+    pub task_total: usize,
+    pub percent: f32,
+    /// Progress across all the tasks announced by [`expect_run_bytes`], or 0
+    /// when nothing was announced.
+    pub total_percent: f32,
+    pub bytes_processed: u64,
+    pub rows_written: u64,
+    pub tasks_completed: u64,
+    pub throughput_mb_s: f64,
+    pub elapsed_s: f64,
+    pub errors: Vec<String>,
+}
+
+struct State {
+    status: Status,
+    started: Option<Instant>,
+    task_bytes: u64,
+    task_bytes_expected: u64,
+    run_bytes_start: u64,
+    run_bytes_expected: u64,
+    last_print: Option<Instant>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    status: Status {
+        stage: String::new(),
+        current: String::new(),
+        task_index: 0,
+        task_total: 0,
+        percent: 0f32,
+        total_percent: 0f32,
+        bytes_processed: 0,
+        rows_written: 0,
+        tasks_completed: 0,
+        throughput_mb_s: 0f64,
+        elapsed_s: 0f64,
+        errors: Vec::new(),
+    },
+    started: None,
+    task_bytes: 0,
+    task_bytes_expected: 0,
+    run_bytes_start: 0,
+    run_bytes_expected: 0,
+    last_print: None,
+});
+
+/// Closure called with the status after every chunk read or written, from
+/// the thread that processed it.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&Status) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new<F: Fn(&Status) + Send + Sync + 'static>(callback: F) -> ProgressCallback {
+        return ProgressCallback(Arc::new(callback));
+    }
+
+    /// Whether `self` and `other` are clones of the same callback.
+    pub fn same_as(&self, other: &ProgressCallback) -> bool {
+        return Arc::ptr_eq(&self.0, &other.0);
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "ProgressCallback");
+    }
+}
+
+static CALLBACK: Mutex<Option<ProgressCallback>> = Mutex::new(None);
+
+/// Calls `callback`, if any, after every chunk for the rest of the process.
+pub fn set_callback(callback: Option<ProgressCallback>) {
+    if let Ok(mut current) = CALLBACK.lock() {
+        *current = callback;
+    }
+}
+
+/// The callback called, if any.
+pub fn callback() -> Option<ProgressCallback> {
+    return CALLBACK.lock().ok().and_then(|callback| callback.clone());
+}
+
+fn with_state<F: FnOnce(&mut State)>(update: F) {
+    if let Ok(mut state) = STATE.lock() {
+        if state.started.is_none() {
+            state.started = Some(Instant::now());
+        }
+        update(&mut state);
+    }
+}
+
+/// Marks the start of task `index` of `total` in `stage` (e.g. reversing a
+/// dataset or writing a group's rasters), and starts timing it (see
+/// [`crate::timing`]).
+pub fn start_task(stage: &str, current: &str, index: usize, total: usize) {
+    timing::start(stage, current);
+    with_state(|state| {
+        state.status.stage = stage.to_owned();
+        state.status.current = current.to_owned();
+        state.status.task_index = index;
+        state.status.task_total = total;
+        state.status.percent = 0f32;
+        state.task_bytes = 0;
+        state.task_bytes_expected = 0;
+    });
+}
+
+/// Sets the bytes the current task is expected to read and write, so that
+/// its progress follows the bytes processed instead of
+/// [`set_progress`].
+pub fn expect_task_bytes(bytes: u64) {
+    with_state(|state| state.task_bytes_expected = bytes);
+}
+
+/// Sets the bytes the tasks from now on are expected to read and write
+/// altogether (e.g. reversing every dataset), for the progress of the whole
+/// run. Zero clears it.
+pub fn expect_run_bytes(bytes: u64) {
+    with_state(|state| {
+        state.status.total_percent = 0f32;
+        state.run_bytes_start = state.status.bytes_processed;
+        state.run_bytes_expected = bytes;
+    });
+}
+
+/// Sets the completed fraction (0 to 1) of the current task, unless its
+/// progress follows its bytes (see [`expect_task_bytes`]).
+pub fn set_progress(fraction: f32) {
+    with_state(|state| {
+        if state.task_bytes_expected == 0 {
+            state.status.percent = 100f32 * fraction;
+        }
+    });
+}
+
+/// Counts bytes read or written.
+pub fn add_bytes(bytes: usize) {
+    with_state(|state| {
+        state.status.bytes_processed += bytes as u64;
+        state.task_bytes += bytes as u64;
+        if state.task_bytes_expected > 0 {
+            state.status.percent = percent_of(state.task_bytes, state.task_bytes_expected);
+        }
+        if state.run_bytes_expected > 0 {
+            state.status.total_percent = percent_of(
+                state.status.bytes_processed - state.run_bytes_start,
+                state.run_bytes_expected,
+            );
+        }
+    });
+    // Called outside the lock, so the callback may read the status itself.
+    if let Some(callback) = callback() {
+        (callback.0)(&snapshot());
+    }
+}
+
+fn percent_of(done: u64, expected: u64) -> f32 {
+    return (100f64 * done as f64 / expected as f64).min(100f64) as f32;
+}
+
+pub fn add_rows(rows: usize) {
+    with_state(|state| state.status.rows_written += rows as u64);
+}
+
+/// Counts a finished task (a reversed dataset, a group's mean/sd or a
+/// group's rasters) and keeps its timings.
+pub fn complete_task() {
+    timing::finish();
+    with_state(|state| {
+        state.status.percent = 100f32;
+        state.status.tasks_completed += 1;
+    });
+}
+
+/// Prints the progress of the current task, and of the run when known, on
+/// the current line. Lines are printed at most every [`PRINT_INTERVAL`], so
+/// loops may call this on every chunk.
+pub fn print_progress() -> io::Result<()> {
+    let mut due = false;
+    with_state(|state| {
+        due = state
+            .last_print
+            .map_or(true, |last| last.elapsed() >= PRINT_INTERVAL);
+        if due {
+            state.last_print = Some(Instant::now());
+        }
+    });
+    if due {
+        print!("\r{}", progress_line(&snapshot()));
+        io::stdout().flush()?;
+    }
+    return Ok(());
+}
+
+/// Ends the progress line of the current task at 100%.
+pub fn end_progress() {
+    with_state(|state| state.last_print = None);
+    let mut snapshot = snapshot();
+    snapshot.percent = 100f32;
+    println!("\r{}", progress_line(&snapshot));
+}
+
+fn progress_line(status: &Status) -> String {
+    let mut line = format!("{:.2}%", status.percent);
+    if status.total_percent > 0f32 {
+        line += &format!(" (all datasets {:.2}%)", status.total_percent);
+    }
+    return line + &format!(", {:.1} MB/s   ", status.throughput_mb_s);
+}
+
+/// Clears the status, so the next run of the process starts from none.
+pub fn reset() {
+    if let Ok(mut state) = STATE.lock() {
+        state.status = Status::default();
+        state.started = None;
+        state.task_bytes = 0;
+        state.task_bytes_expected = 0;
+        state.run_bytes_start = 0;
+        state.run_bytes_expected = 0;
+        state.last_print = None;
+    }
+}
+
+pub fn add_error(message: String) {
+    with_state(|state| state.status.errors.push(message));
+}
+
+/// Snapshot of the current status with derived timing fields filled in.
+pub fn snapshot() -> Status {
+    let mut snapshot = Status::default();
+    with_state(|state| {
+        snapshot = state.status.clone();
+        let elapsed = state
+            .started
+            .map_or(0f64, |started| started.elapsed().as_secs_f64());
+        snapshot.elapsed_s = elapsed;
+        if elapsed > 0f64 {
+            snapshot.throughput_mb_s = snapshot.bytes_processed as f64 / 1e6 / elapsed;
+        }
+    });
+    return snapshot;
+}
+
+/// Serves the status as JSON on `127.0.0.1:port` from a background thread.
+/// Every request gets the current snapshot; with the `metrics` feature,
+/// `GET /metrics` returns it in Prometheus text format instead.
+pub fn serve(port: u16) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            if let Ok(stream) = stream {
+                let _ = respond(stream);
+            }
+        }
+    });
+    return Ok(());
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = [0u8; 1024];
+    let n_read = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..n_read]);
+
+    #[cfg(feature = "metrics")]
+    let (content_type, body) = if request.starts_with("GET /metrics") {
+        (
+            "text/plain; version=0.0.4",
+            crate::metrics::render(&snapshot()),
+        )
+    } else {
+        (
+            "application/json",
+            serde_json::to_string(&snapshot()).unwrap_or_else(|_| "{}".to_owned()),
+        )
+    };
+    #[cfg(not(feature = "metrics"))]
+    let (content_type, body) = {
+        let _ = request;
+        (
+            "application/json",
+            serde_json::to_string(&snapshot()).unwrap_or_else(|_| "{}".to_owned()),
+        )
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    )?;
+    return stream.flush();
+}
@@ -0,0 +1,77 @@
+//! Export of string and variable-length datasets, which cannot become
+//! rasters, to a CSV sidecar.
+
+use crate::error::Result;
+use crate::hdf::ValueClass;
+use hdf5::types::{VarLenAscii, VarLenUnicode};
+use std::io::Write;
+use std::path::Path;
+
+/// Writes one CSV row per string value of each dataset in `datasets`
+/// (`path,type,shape,index,value`). Datasets whose values cannot be read as
+/// strings get a single row describing their type and shape.
+pub fn write_sidecar(
+    file: &hdf5::File,
+    datasets: &[(String, ValueClass)],
+    out_path: &Path,
+) -> Result<()> {
+    let mut out = std::io::BufWriter::new(std::fs::File::create(out_path)?);
+    writeln!(out, "path,type,shape,index,value")?;
+    for (ds_path, _) in datasets {
+        let ds = file.dataset(ds_path)?;
+        let dtype = format!("{:?}", ds.dtype()?.to_descriptor()?);
+        let shape = ds
+            .shape()
+            .iter()
+            .map(|dim| dim.to_string())
+            .collect::<Vec<String>>()
+            .join("x");
+        let prefix = format!(
+            "{},{},{}",
+            csv_field(ds_path),
+            csv_field(&dtype),
+            csv_field(&shape)
+        );
+        match read_strings(&ds) {
+            Some(values) => {
+                for (index, value) in values.iter().enumerate() {
+                    writeln!(out, "{},{},{}", prefix, index, csv_field(value))?;
+                }
+            }
+            None => writeln!(out, "{},,", prefix)?,
+        }
+    }
+    out.flush()?;
+    return Ok(());
+}
+
+/// Values of a string dataset or attribute, or `None` if they are not
+/// strings.
+pub fn read_strings(ds: &hdf5::Container) -> Option<Vec<String>> {
+    let _silence = hdf5::silence_errors();
+    if let Ok(values) = ds.read_raw::<VarLenUnicode>() {
+        return Some(
+            values
+                .iter()
+                .map(|value| value.as_str().to_owned())
+                .collect(),
+        );
+    }
+    if let Ok(values) = ds.read_raw::<VarLenAscii>() {
+        return Some(
+            values
+                .iter()
+                .map(|value| value.as_str().to_owned())
+                .collect(),
+        );
+    }
+    return None;
+}
+
+/// `text` as a CSV field, quoted if needed.
+pub fn csv_field(text: &str) -> String {
+    if text.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        return format!("\"{}\"", text.replace('"', "\"\""));
+    }
+    return text.to_owned();
+}
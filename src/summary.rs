@@ -0,0 +1,291 @@
+//! Summary statistics of the values written to each output: min, max, mean,
+//! sd and a histogram, accumulated during the streaming pass.
+//!
+//! Summaries are kept for the report like warnings, so the conversion
+//! functions need no extra state; they are only computed once enabled.
+
+use crate::error::Result;
+use crate::hdf::HdfSource;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Bins of the histogram while accumulating; bins left empty at either end
+/// are dropped from the summary.
+const BINS: usize = 32;
+
+/// Histogram of equal width bins, the first starting at `start`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    pub start: f64,
+    pub bin_width: f64,
+    pub counts: Vec<u64>,
+}
+
+/// Summary of the values of one output. Nodata and non-finite values are
+/// left out of every statistic.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputSummary {
+    pub path: String,
+    /// Pixels with a value.
+    pub valid: u64,
+    /// Pixels without a value, including those of skipped empty strips.
+    pub nodata: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub sd: Option<f64>,
+    pub histogram: Option<Histogram>,
+}
+
+impl OutputSummary {
+    /// Prints the summary on two lines.
+    pub fn print(&self) {
+        println!("{}", self.render());
+    }
+
+    /// The summary as printed: the statistics, then the histogram, if any,
+    /// on a line of its own.
+    pub fn render(&self) -> String {
+        let text = |value: Option<f64>| value.map_or("-".to_owned(), |value| value.to_string());
+        let mut rendered = format!(
+            "{}: {} valid, {} nodata, min {}, max {}, mean {}, sd {}",
+            self.path,
+            self.valid,
+            self.nodata,
+            text(self.min),
+            text(self.max),
+            text(self.mean),
+            text(self.sd)
+        );
+        if let Some(histogram) = &self.histogram {
+            let counts: Vec<String> = histogram.counts.iter().map(|c| c.to_string()).collect();
+            rendered.push_str(&format!(
+                "\n  histogram from {} in bins of {}: {}",
+                histogram.start,
+                histogram.bin_width,
+                counts.join(" ")
+            ));
+        }
+        return rendered;
+    }
+}
+
+/// Running statistics of a stream of values, with a histogram whose range
+/// doubles whenever a value falls outside it, merging pairs of bins, so the
+/// values need not be known in advance.
+#[derive(Debug, Default)]
+struct Accumulator {
+    count: u64,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+    start: f64,
+    bin_width: f64,
+    bins: Vec<u64>,
+}
+
+impl Accumulator {
+    fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+            self.start = value;
+            self.bin_width = value.abs().max(1f64) * f64::EPSILON * BINS as f64;
+            self.bins = vec![0; BINS];
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        while value < self.start || value >= self.start + BINS as f64 * self.bin_width {
+            self.grow(value < self.start);
+        }
+        let bin = ((value - self.start) / self.bin_width) as usize;
+        self.bins[bin.min(BINS - 1)] += 1;
+    }
+
+    /// Doubles the bin width, extending the range down or up.
+    fn grow(&mut self, down: bool) {
+        let mut merged = vec![0; BINS];
+        let offset = if down { BINS / 2 } else { 0 };
+        for (ii, count) in self.bins.iter().enumerate() {
+            merged[offset + ii / 2] += count;
+        }
+        if down {
+            self.start -= BINS as f64 * self.bin_width;
+        }
+        self.bin_width *= 2f64;
+        self.bins = merged;
+    }
+
+    fn finish(self, path: &str, n_pixels: usize) -> OutputSummary {
+        let valid = self.count > 0;
+        let histogram = if valid {
+            let first = self.bins.iter().position(|&count| count > 0).unwrap_or(0);
+            let last = self.bins.iter().rposition(|&count| count > 0).unwrap_or(0);
+            Some(Histogram {
+                start: self.start + first as f64 * self.bin_width,
+                bin_width: self.bin_width,
+                counts: self.bins[first..=last].to_vec(),
+            })
+        } else {
+            None
+        };
+        let some = |value: f64| if valid { Some(value) } else { None };
+        return OutputSummary {
+            path: path.to_owned(),
+            valid: self.count,
+            nodata: (n_pixels as u64).saturating_sub(self.count),
+            min: some(self.min),
+            max: some(self.max),
+            mean: some(self.mean),
+            sd: some((self.m2 / self.count.max(1) as f64).sqrt()),
+            histogram,
+        };
+    }
+}
+
+/// A source accumulating the summary of every value read from it.
+pub struct Summarized<'a, S> {
+    source: &'a S,
+    nodata: Option<f64>,
+    accumulator: RefCell<Accumulator>,
+}
+
+impl<'a, S> Summarized<'a, S> {
+    /// Wraps `source`, leaving values equal to `nodata` out of the summary.
+    pub fn new(source: &'a S, nodata: Option<f64>) -> Self {
+        return Summarized {
+            source,
+            nodata,
+            accumulator: RefCell::new(Accumulator::default()),
+        };
+    }
+
+    /// Summary of the values read so far, for the output at `path` of
+    /// `n_pixels` pixels.
+    pub fn finish(self, path: &str, n_pixels: usize) -> OutputSummary {
+        return self.accumulator.into_inner().finish(path, n_pixels);
+    }
+}
+
+impl<'a, T, S> HdfSource<T> for Summarized<'a, S>
+where
+    T: Copy + Into<f64>,
+    S: HdfSource<T>,
+{
+    fn len(&self, path: &str) -> Result<usize> {
+        return self.source.len(path);
+    }
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> Result<Vec<T>> {
+        let vals = self.source.read_range(path, start, end)?;
+        let mut accumulator = self.accumulator.borrow_mut();
+        for &val in vals.iter() {
+            let value: f64 = val.into();
+            if value.is_finite() && Some(value) != self.nodata {
+                accumulator.add(value);
+            }
+        }
+        return Ok(vals);
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SUMMARIES: Mutex<Vec<OutputSummary>> = Mutex::new(Vec::new());
+
+/// Makes outputs summarized for the rest of the process.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    return ENABLED.load(Ordering::SeqCst);
+}
+
+/// Prints and records the summary of an output.
+pub fn record(summary: OutputSummary) {
+    summary.print();
+    if let Ok(mut summaries) = SUMMARIES.lock() {
+        summaries.push(summary);
+    }
+}
+
+/// Summaries recorded so far, leaving none.
+pub fn take() -> Vec<OutputSummary> {
+    return match SUMMARIES.lock() {
+        Ok(mut summaries) => std::mem::take(&mut *summaries),
+        Err(_) => vec![],
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Values(Vec<f32>);
+
+    impl HdfSource<f32> for Values {
+        fn len(&self, _path: &str) -> Result<usize> {
+            return Ok(self.0.len());
+        }
+
+        fn read_range(&self, _path: &str, start: usize, end: usize) -> Result<Vec<f32>> {
+            return Ok(self.0[start..end].to_vec());
+        }
+    }
+
+    #[test]
+    fn summaries_count_render_and_are_recorded() {
+        let source = Values(vec![1., 2., -9999., 3., f32::NAN, 4.]);
+        let summarized = Summarized::new(&source, Some(-9999.));
+        // Read in two strips, as outputs are.
+        let _: Vec<f32> = summarized.read_range("", 0, 3).unwrap();
+        let _: Vec<f32> = summarized.read_range("", 3, 6).unwrap();
+        // Two more pixels in skipped empty strips.
+        let summary = summarized.finish("agbd_mean.tif", 8);
+        assert_eq!((summary.valid, summary.nodata), (4, 4));
+        assert_eq!((summary.min, summary.max), (Some(1.), Some(4.)));
+        assert_eq!(summary.mean, Some(2.5));
+        assert_eq!(summary.sd, Some(1.25f64.sqrt()));
+        let histogram = summary.histogram.clone().unwrap();
+        assert_eq!(histogram.counts.iter().sum::<u64>(), 4);
+        assert_eq!(histogram.counts.first(), Some(&1));
+        assert_eq!(histogram.counts.last(), Some(&1));
+        assert!(histogram.start <= 1.);
+
+        let rendered = summary.render();
+        let mut lines = rendered.lines();
+        assert_eq!(
+            lines.next(),
+            Some(
+                format!(
+                    "agbd_mean.tif: 4 valid, 4 nodata, min 1, max 4, mean 2.5, sd {}",
+                    1.25f64.sqrt()
+                )
+                .as_str()
+            )
+        );
+        assert!(lines.next().unwrap().starts_with("  histogram from "));
+        assert_eq!(lines.next(), None);
+
+        let empty = Summarized::new(&source, None).finish("empty.tif", 3);
+        assert_eq!(
+            empty.render(),
+            "empty.tif: 0 valid, 3 nodata, min -, max -, mean -, sd -"
+        );
+
+        record(summary);
+        record(empty);
+        let recorded = take();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].path, "agbd_mean.tif");
+        assert!(take().is_empty());
+    }
+}
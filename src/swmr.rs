@@ -0,0 +1,117 @@
+//! Reading files still being written by another process, in HDF5's single
+//! writer, multiple reader mode (`--swmr`).
+//!
+//! The file is opened read-only with `H5F_ACC_SWMR_READ`, so the derived
+//! datasets go to the scratch file. A dataset may have grown since it was
+//! opened; before it is reversed it is refreshed, and with a wait it is
+//! polled until it holds the whole grid, so a product can be rasterized
+//! while its ingestion appends rows. SWMR needs HDF5 1.10.
+
+use crate::error::{Error, Result};
+use crate::h5_cache::ChunkCache;
+use crate::{allocation, interrupt, meta};
+use std::time::{Duration, Instant};
+
+/// How datasets still being written are waited for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwmrOptions {
+    /// Longest wait for a dataset to hold the whole grid; zero reads it as
+    /// it is.
+    pub wait: Duration,
+    /// Delay between two refreshes while waiting.
+    pub poll: Duration,
+}
+
+impl Default for SwmrOptions {
+    fn default() -> Self {
+        return SwmrOptions {
+            wait: Duration::from_secs(0),
+            poll: Duration::from_secs(1),
+        };
+    }
+}
+
+/// Opens the HDF5 file at `path` for SWMR reading, with `cache` as the
+/// chunk cache of its datasets, or HDF5's default without one.
+#[cfg(hdf5_1_10)]
+pub fn open_read(path: &str, cache: Option<ChunkCache>) -> Result<hdf5::File> {
+    use hdf5_sys::h5f::{H5Fopen, H5F_ACC_RDONLY, H5F_ACC_SWMR_READ};
+    use hdf5_sys::h5i::H5Idec_ref;
+    use hdf5_sys::h5p::{H5Pcreate, H5Pset_cache, H5P_CLS_FILE_ACCESS};
+    use std::ffi::CString;
+
+    let c_path =
+        CString::new(path).map_err(|_| Error::Invalid(format!("invalid file name '{}'", path)))?;
+    let file_id = hdf5::sync::sync(|| unsafe {
+        let fapl = H5Pcreate(*H5P_CLS_FILE_ACCESS);
+        if let Some(cache) = cache {
+            H5Pset_cache(fapl, 0, cache.nslots, cache.nbytes, cache.w0);
+        }
+        let file_id = H5Fopen(c_path.as_ptr(), H5F_ACC_RDONLY | H5F_ACC_SWMR_READ, fapl);
+        H5Idec_ref(fapl);
+        return file_id;
+    });
+    if file_id < 0 {
+        return Err(Error::Invalid(format!(
+            "could not open {} for SWMR reading; it must exist and be written with the \
+             HDF5 1.10 file format",
+            path
+        )));
+    }
+    return Ok(hdf5::File::from_id(file_id)?);
+}
+
+#[cfg(not(hdf5_1_10))]
+pub fn open_read(path: &str, _cache: Option<ChunkCache>) -> Result<hdf5::File> {
+    return Err(Error::Invalid(format!(
+        "cannot open {} for SWMR reading: it needs HDF5 1.10 or later",
+        path
+    )));
+}
+
+/// Reloads the metadata of `ds`, e.g. its extent, as last flushed by the
+/// writer.
+#[cfg(hdf5_1_10)]
+pub fn refresh(ds: &hdf5::Dataset) -> Result<()> {
+    use hdf5_sys::h5d::H5Drefresh;
+
+    let status = hdf5::sync::sync(|| unsafe { H5Drefresh(ds.id()) });
+    if status < 0 {
+        return Err(Error::Invalid(format!("could not refresh {}", ds.name())));
+    }
+    return Ok(());
+}
+
+#[cfg(not(hdf5_1_10))]
+pub fn refresh(_ds: &hdf5::Dataset) -> Result<()> {
+    return Ok(());
+}
+
+/// Refreshes the dataset at `path` of `file` until it holds at least `size`
+/// values or `options.wait` has passed, dropping what was known of it.
+pub fn wait_for(file: &hdf5::File, path: &str, size: usize, options: SwmrOptions) -> Result<()> {
+    let start = Instant::now();
+    let mut announced = false;
+    loop {
+        let ds = file.dataset(path)?;
+        refresh(&ds)?;
+        meta::forget(file, path);
+        allocation::forget(&ds);
+        if ds.size() >= size || start.elapsed() + options.poll > options.wait {
+            return Ok(());
+        }
+        if !announced {
+            println!(
+                "Waiting up to {:?} for {} to grow from {} to {} values",
+                options.wait,
+                path,
+                ds.size(),
+                size
+            );
+            announced = true;
+        }
+        drop(ds);
+        interrupt::check()?;
+        std::thread::sleep(options.poll);
+    }
+}
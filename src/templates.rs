@@ -0,0 +1,111 @@
+//! Output data types and the template rasters outputs of each are created
+//! from.
+//!
+//! Group outputs are written with the float template, or the byte one for
+//! counts. Datasets keep their own data type and are written with the
+//! template given for it, e.g. for `Int16` or `UInt32` classes, or else the
+//! float one.
+
+use crate::error::Result;
+use hdf5::types::{FloatSize, IntSize, TypeDescriptor};
+
+/// Band data types of outputs, named as in GDAL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RasterType {
+    Byte,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+const RASTER_TYPES: [(RasterType, &str); 7] = [
+    (RasterType::Byte, "Byte"),
+    (RasterType::Int16, "Int16"),
+    (RasterType::UInt16, "UInt16"),
+    (RasterType::Int32, "Int32"),
+    (RasterType::UInt32, "UInt32"),
+    (RasterType::Float32, "Float32"),
+    (RasterType::Float64, "Float64"),
+];
+
+impl RasterType {
+    /// Parses a GDAL data type name, ignoring case.
+    pub fn parse(text: &str) -> std::result::Result<RasterType, String> {
+        return RASTER_TYPES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(text.trim()))
+            .map(|(data_type, _)| *data_type)
+            .ok_or_else(|| {
+                let names: Vec<&str> = RASTER_TYPES.iter().map(|(_, name)| *name).collect();
+                return format!(
+                    "unknown data type '{}' (expected {})",
+                    text,
+                    names.join(", ")
+                );
+            });
+    }
+
+    pub fn name(self) -> &'static str {
+        return RASTER_TYPES
+            .iter()
+            .find(|(data_type, _)| *data_type == self)
+            .map(|(_, name)| *name)
+            .unwrap();
+    }
+
+    pub fn is_float(self) -> bool {
+        return self == RasterType::Float32 || self == RasterType::Float64;
+    }
+
+    /// Smallest of these holding every element of `ds`, if it is numeric:
+    /// `Int16` for `i8` and `Float64` for 64-bit integers, which no GDAL
+    /// type here holds exactly.
+    pub fn of_dataset(ds: &hdf5::Dataset) -> Result<Option<RasterType>> {
+        return Ok(match ds.dtype()?.to_descriptor()? {
+            TypeDescriptor::Unsigned(IntSize::U1) => Some(RasterType::Byte),
+            TypeDescriptor::Integer(IntSize::U1) => Some(RasterType::Int16),
+            TypeDescriptor::Integer(IntSize::U2) => Some(RasterType::Int16),
+            TypeDescriptor::Unsigned(IntSize::U2) => Some(RasterType::UInt16),
+            TypeDescriptor::Integer(IntSize::U4) => Some(RasterType::Int32),
+            TypeDescriptor::Unsigned(IntSize::U4) => Some(RasterType::UInt32),
+            TypeDescriptor::Float(FloatSize::U4) => Some(RasterType::Float32),
+            TypeDescriptor::Float(FloatSize::U8) => Some(RasterType::Float64),
+            TypeDescriptor::Integer(IntSize::U8) | TypeDescriptor::Unsigned(IntSize::U8) => {
+                Some(RasterType::Float64)
+            }
+            _ => None,
+        });
+    }
+}
+
+/// Template of the outputs of one data type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedTemplate {
+    pub data_type: RasterType,
+    pub path: String,
+}
+
+impl TypedTemplate {
+    /// Parses `TYPE=PATH`, e.g. `Int16=base_int16.tif`.
+    pub fn parse(text: &str) -> std::result::Result<TypedTemplate, String> {
+        return match text.split_once('=') {
+            Some((data_type, path)) if !path.trim().is_empty() => Ok(TypedTemplate {
+                data_type: RasterType::parse(data_type)?,
+                path: path.trim().to_owned(),
+            }),
+            _ => Err(format!("invalid template '{}', expected TYPE=PATH", text)),
+        };
+    }
+}
+
+/// Template given for `data_type` in `templates`, the last one if repeated.
+pub fn template_for(templates: &[TypedTemplate], data_type: RasterType) -> Option<&str> {
+    return templates
+        .iter()
+        .rev()
+        .find(|template| template.data_type == data_type)
+        .map(|template| template.path.as_str());
+}
@@ -0,0 +1,324 @@
+//! Tiling of the outputs (`--tile-scheme`) into a grid of smaller GeoTIFFs,
+//! for downstream tools that cannot handle one raster of the whole grid.
+//! The tiles of an output go to a `<output>_tiles` directory next to it,
+//! with an `index.geojson` of their footprints; the output itself is kept.
+
+use crate::error::{Error, Result};
+use crate::raster::GeoRef;
+use crate::stac;
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// Name of the tile index in each tile directory.
+pub const INDEX_NAME: &str = "index.geojson";
+
+/// Size of the tiles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileScheme {
+    /// Tiles of `width` by `height` pixels from the top left corner.
+    Pixels(usize, usize),
+    /// Tiles of `width` by `height` degrees aligned to multiples of their
+    /// size, e.g. 1°×1° tiles spanning whole degrees; geographic grids only.
+    Degrees(f64, f64),
+}
+
+impl TileScheme {
+    /// Parses `WIDTHxHEIGHT` pixels, such as `4096x4096`, or degrees with a
+    /// `deg` (or `°`) suffix, such as `1x1deg`; one size is used for both
+    /// sides.
+    pub fn parse(text: &str) -> std::result::Result<TileScheme, String> {
+        let invalid = || {
+            format!(
+                "invalid tile scheme '{}', expected WIDTHxHEIGHT pixels or WIDTHxHEIGHTdeg",
+                text
+            )
+        };
+        let lower = text
+            .trim()
+            .to_lowercase()
+            .replace('°', "deg")
+            .replace('×', "x");
+        let (sizes, degrees) = match lower.strip_suffix("deg") {
+            Some(sizes) => (sizes.replace("deg", ""), true),
+            None => (lower.clone(), false),
+        };
+        let mut parts = sizes.split('x').map(str::trim);
+        let (width, height) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(width), None, None) => (width, width),
+            (Some(width), Some(height), None) => (width, height),
+            _ => return Err(invalid()),
+        };
+        if degrees {
+            return match (width.parse::<f64>(), height.parse::<f64>()) {
+                (Ok(width), Ok(height)) if width > 0f64 && height > 0f64 => {
+                    Ok(TileScheme::Degrees(width, height))
+                }
+                _ => Err(invalid()),
+            };
+        }
+        return match (width.parse::<usize>(), height.parse::<usize>()) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => {
+                Ok(TileScheme::Pixels(width, height))
+            }
+            _ => Err(invalid()),
+        };
+    }
+}
+
+/// One tile of an output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tile {
+    /// File stem of the tile: `r<row>_c<col>` for pixel tiles, the corner
+    /// nearest to the origin such as `S16W048` for degree tiles.
+    pub name: String,
+    /// Column and row offsets and size in the output, in pixels.
+    pub window: (usize, usize, usize, usize),
+}
+
+impl Tile {
+    /// Bounds `[xmin, ymin, xmax, ymax]` of the tile in the CRS of `georef`.
+    pub fn bounds(&self, georef: &GeoRef) -> [f64; 4] {
+        let (x_off, y_off, xsize, ysize) = self.window;
+        let mut tile_georef = georef.clone();
+        let gt = &mut tile_georef.geo_transform;
+        gt[0] = georef.geo_transform[0] + x_off as f64 * georef.geo_transform[1];
+        gt[3] = georef.geo_transform[3] + y_off as f64 * georef.geo_transform[5];
+        return stac::native_bounds(&tile_georef, (xsize, ysize));
+    }
+}
+
+/// Tiles of `scheme` covering a grid of `size` pixels georeferenced by
+/// `georef`, from north to south and west to east.
+pub fn tiles(
+    scheme: TileScheme,
+    georef: &GeoRef,
+    (xsize, ysize): (usize, usize),
+) -> Result<Vec<Tile>> {
+    let mut tiles = vec![];
+    match scheme {
+        TileScheme::Pixels(width, height) => {
+            for (row, y_off) in (0..ysize).step_by(height).enumerate() {
+                for (col, x_off) in (0..xsize).step_by(width).enumerate() {
+                    tiles.push(Tile {
+                        name: format!("r{}_c{}", row, col),
+                        window: (
+                            x_off,
+                            y_off,
+                            width.min(xsize - x_off),
+                            height.min(ysize - y_off),
+                        ),
+                    });
+                }
+            }
+        }
+        TileScheme::Degrees(width, height) => {
+            let gt = &georef.geo_transform;
+            if !stac::is_geographic(&georef.crs) || gt[2] != 0f64 || gt[4] != 0f64 {
+                return Err(Error::Invalid(
+                    "degree tiles need a geographic grid without rotation".to_owned(),
+                ));
+            }
+            let [xmin, ymin, xmax, ymax] = stac::native_bounds(georef, (xsize, ysize));
+            // Pixel edges of the tile edges, snapped to the nearest pixel.
+            let col_of =
+                |x: f64| ((x - gt[0]) / gt[1]).round().max(0f64).min(xsize as f64) as usize;
+            let row_of =
+                |y: f64| ((y - gt[3]) / gt[5]).round().max(0f64).min(ysize as f64) as usize;
+            let mut north = ymax;
+            let mut jj = (ymax / height).ceil() as i64;
+            while north > ymin {
+                let south = (jj - 1) as f64 * height;
+                let (row_a, row_b) = (row_of(north.min(jj as f64 * height)), row_of(south));
+                let (y_off, y_end) = (row_a.min(row_b), row_a.max(row_b));
+                let mut ii = (xmin / width).floor() as i64;
+                while (ii as f64 * width) < xmax {
+                    let west = ii as f64 * width;
+                    let (col_a, col_b) = (col_of(west), col_of(west + width));
+                    let (x_off, x_end) = (col_a.min(col_b), col_a.max(col_b));
+                    if x_end > x_off && y_end > y_off {
+                        tiles.push(Tile {
+                            name: format!(
+                                "{}{}",
+                                coord_name(south, 'N', 'S', 2),
+                                coord_name(west, 'E', 'W', 3)
+                            ),
+                            window: (x_off, y_off, x_end - x_off, y_end - y_off),
+                        });
+                    }
+                    ii += 1;
+                }
+                north = south;
+                jj -= 1;
+            }
+        }
+    }
+    return Ok(tiles);
+}
+
+/// `value` as in tile names: its hemisphere letter and absolute value, with
+/// `digits` integer digits and any fraction after a `p`.
+fn coord_name(value: f64, positive: char, negative: char, digits: usize) -> String {
+    let letter = match value < 0f64 {
+        true => negative,
+        false => positive,
+    };
+    let value = value.abs();
+    let integer = format!("{:0width$}", value.trunc() as u64, width = digits);
+    let fraction = value.fract();
+    if fraction == 0f64 {
+        return format!("{}{}", letter, integer);
+    }
+    let fraction = format!("{}", fraction);
+    return format!(
+        "{}{}p{}",
+        letter,
+        integer,
+        fraction.trim_start_matches("0.")
+    );
+}
+
+/// Directory of the tiles of the output at `out_path`.
+pub fn tile_dir(out_path: &str) -> String {
+    let path = Path::new(out_path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| out_path.to_owned());
+    return path
+        .with_file_name(format!("{}_tiles", stem))
+        .to_string_lossy()
+        .into_owned();
+}
+
+/// Whether `path` is in a tile directory, so it is not tiled or linked
+/// itself.
+pub fn is_tile(path: &str) -> bool {
+    return Path::new(path)
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .map_or(false, |name| name.to_string_lossy().ends_with("_tiles"));
+}
+
+/// GeoJSON FeatureCollection of the footprints of `tiles`, in the CRS of
+/// `georef`, with the file of each tile relative to the index.
+pub fn index_geojson(tiles: &[Tile], georef: &GeoRef) -> Value {
+    let features: Vec<Value> = tiles
+        .iter()
+        .map(|tile| {
+            let [xmin, ymin, xmax, ymax] = tile.bounds(georef);
+            let (x_off, y_off, xsize, ysize) = tile.window;
+            return json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[
+                        [xmin, ymin],
+                        [xmax, ymin],
+                        [xmax, ymax],
+                        [xmin, ymax],
+                        [xmin, ymin],
+                    ]],
+                },
+                "properties": {
+                    "name": tile.name,
+                    "location": format!("{}.tif", tile.name),
+                    "x_off": x_off,
+                    "y_off": y_off,
+                    "x_size": xsize,
+                    "y_size": ysize,
+                },
+            });
+        })
+        .collect();
+    let mut index = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    // GeoJSON assumes WGS84; name other CRSs the way GDAL reads them.
+    if let Some(code) = stac::epsg_code(&georef.crs) {
+        index["crs"] = json!({
+            "type": "name",
+            "properties": {"name": format!("urn:ogc:def:crs:EPSG::{}", code)},
+        });
+    }
+    return index;
+}
+
+/// Splits the raster at `out_path` into the GeoTIFF tiles of `scheme`, with
+/// their index, pushing their paths to `created` before writing them.
+#[cfg(feature = "gdal")]
+pub fn write_tiles(out_path: &str, scheme: TileScheme, created: &mut Vec<String>) -> Result<()> {
+    let rast = gdal::Dataset::open(Path::new(out_path))?;
+    let georef = GeoRef {
+        geo_transform: rast.geo_transform()?,
+        crs: rast.projection(),
+    };
+    let tiles = tiles(scheme, &georef, rast.raster_size())?;
+    let dir = tile_dir(out_path);
+    std::fs::create_dir_all(&dir)?;
+    for tile in tiles.iter() {
+        let tile_path = Path::new(&dir).join(format!("{}.tif", tile.name));
+        let tile_path = tile_path.to_string_lossy().into_owned();
+        created.push(tile_path.clone());
+        translate_window(&rast, tile.window, &tile_path)?;
+    }
+    let index_path = Path::new(&dir).join(INDEX_NAME);
+    created.push(index_path.to_string_lossy().into_owned());
+    let file = std::fs::File::create(index_path)?;
+    serde_json::to_writer_pretty(file, &index_geojson(&tiles, &georef))?;
+    return Ok(());
+}
+
+/// Copies the pixels of `window` of `rast`, with its bands, types, nodata
+/// values and metadata, to a new GeoTIFF at `tile_path`.
+#[cfg(feature = "gdal")]
+fn translate_window(
+    rast: &gdal::Dataset,
+    (x_off, y_off, xsize, ysize): (usize, usize, usize, usize),
+    tile_path: &str,
+) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::raw::c_char;
+
+    let args: Vec<CString> = [
+        "-of".to_owned(),
+        "GTiff".to_owned(),
+        "-srcwin".to_owned(),
+        x_off.to_string(),
+        y_off.to_string(),
+        xsize.to_string(),
+        ysize.to_string(),
+        "-co".to_owned(),
+        "COMPRESS=DEFLATE".to_owned(),
+        "-co".to_owned(),
+        "TILED=YES".to_owned(),
+    ]
+    .iter()
+    .map(|arg| CString::new(arg.as_str()).unwrap())
+    .collect();
+    let mut argv: Vec<*mut c_char> = args.iter().map(|arg| arg.as_ptr() as *mut c_char).collect();
+    argv.push(std::ptr::null_mut());
+    let dest = CString::new(tile_path)
+        .map_err(|_| Error::Invalid(format!("invalid path {}", tile_path)))?;
+    // SAFETY: `argv` is NUL-terminated and outlives the options, and the
+    // dataset handle is valid while `rast` is borrowed.
+    let written = unsafe {
+        let options = gdal_sys::GDALTranslateOptionsNew(argv.as_mut_ptr(), std::ptr::null_mut());
+        let mut usage_error = 0;
+        let tile =
+            gdal_sys::GDALTranslate(dest.as_ptr(), rast.c_dataset(), options, &mut usage_error);
+        gdal_sys::GDALTranslateOptionsFree(options);
+        let written = !tile.is_null();
+        if written {
+            gdal_sys::GDALClose(tile);
+        }
+        written
+    };
+    if !written {
+        return Err(Error::Invalid(format!(
+            "could not write the tile {}",
+            tile_path
+        )));
+    }
+    return Ok(());
+}
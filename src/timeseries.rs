@@ -0,0 +1,156 @@
+//! Time series: datasets shaped (time, y, x), converted to one raster per
+//! time slice.
+
+use crate::error::{Error, Result};
+use crate::hdf::{self, HdfSource};
+use crate::timing::{self, Stage};
+use crate::{retry, status, strings};
+use ndarray::{s, SliceInfo};
+use std::collections::HashSet;
+use std::time::Instant;
+
+/// One time slice of a (time, y, x) dataset, read with its rows in output
+/// order (reversed if `flip`) so it can be written to a raster directly,
+/// without a `_rev` dataset.
+///
+/// The slice stands for a single dataset: the `path` given to
+/// [`HdfSource`] methods is ignored.
+pub struct TimeSlice<'a> {
+    ds: &'a hdf5::Dataset,
+    index: usize,
+    xsize: usize,
+    ysize: usize,
+    flip: bool,
+}
+
+impl<'a> TimeSlice<'a> {
+    pub fn new(
+        ds: &'a hdf5::Dataset,
+        index: usize,
+        xsize: usize,
+        ysize: usize,
+        flip: bool,
+    ) -> Self {
+        return TimeSlice {
+            ds,
+            index,
+            xsize,
+            ysize,
+            flip,
+        };
+    }
+}
+
+impl<'a, T: hdf5::H5Type + Clone> HdfSource<T> for TimeSlice<'a> {
+    fn len(&self, _path: &str) -> Result<usize> {
+        return Ok(self.xsize * self.ysize);
+    }
+
+    /// Reads whole rows: `start` and `end` must be multiples of the width.
+    fn read_range(&self, _path: &str, start: usize, end: usize) -> Result<Vec<T>> {
+        let yy = start / self.xsize;
+        let lines = (end - start) / self.xsize;
+        let src_yy = if self.flip {
+            self.ysize - yy - lines
+        } else {
+            yy
+        };
+        let slice_or_info = s![self.index, src_yy..src_yy + lines, ..];
+        let slice = SliceInfo::new(slice_or_info).unwrap();
+        let started = Instant::now();
+        let rows = retry::with_retries(&format!("reading {}", self.ds.name()), || {
+            return Ok(self.ds.read_slice_2d::<T, _>(&slice)?);
+        })?;
+        let bytes = rows.len() * std::mem::size_of::<T>();
+        timing::add(Stage::Read, started.elapsed(), bytes);
+        status::add_bytes(bytes);
+        if !self.flip {
+            return Ok(rows.iter().cloned().collect());
+        }
+        return Ok(rows.slice(s![..;-1, ..]).iter().cloned().collect());
+    }
+}
+
+/// Number of time slices of `ds`, failing unless it is shaped
+/// (time, `ysize`, `xsize`).
+pub fn time_steps(ds: &hdf5::Dataset, xsize: usize, ysize: usize) -> Result<usize> {
+    let shape = ds.shape();
+    if shape.len() != 3 || shape[1] != ysize || shape[2] != xsize {
+        return Err(Error::Invalid(format!(
+            "dataset {} is shaped {:?}, expected (time, {}, {})",
+            ds.name(),
+            shape,
+            ysize,
+            xsize
+        )));
+    }
+    return Ok(shape[0]);
+}
+
+/// Labels of the `n_steps` slices of `ds`, used in output file names.
+///
+/// `coord` is the path of a 1-d time coordinate dataset, or `@NAME` for an
+/// attribute of `ds`; its string or numeric values become the labels.
+/// Without it, slices are labelled by their zero-padded index (`t000`).
+pub fn time_labels(
+    file: &hdf5::File,
+    ds: &hdf5::Dataset,
+    n_steps: usize,
+    coord: Option<&str>,
+) -> Result<Vec<String>> {
+    let labels = match coord {
+        None => {
+            let width = n_steps.saturating_sub(1).to_string().len().max(3);
+            (0..n_steps)
+                .map(|index| format!("t{:0width$}", index, width = width))
+                .collect()
+        }
+        Some(coord) => match coord.strip_prefix('@') {
+            Some(attr) => coord_labels(&ds.attr(attr)?, coord)?,
+            None => coord_labels(&file.dataset(coord)?, coord)?,
+        },
+    };
+    if labels.len() != n_steps {
+        return Err(Error::Invalid(format!(
+            "time coordinate {} has {} values, but {} has {} time slices",
+            coord.unwrap_or(""),
+            labels.len(),
+            ds.name(),
+            n_steps
+        )));
+    }
+    let mut seen = HashSet::new();
+    for label in &labels {
+        if !seen.insert(label) {
+            return Err(Error::Invalid(format!(
+                "time coordinate {} has the label {} more than once",
+                coord.unwrap_or(""),
+                label
+            )));
+        }
+    }
+    return Ok(labels);
+}
+
+/// String values of `coord`, or its numbers without a trailing `.0`, made
+/// safe for file names.
+fn coord_labels(coord: &hdf5::Container, name: &str) -> Result<Vec<String>> {
+    let labels = match strings::read_strings(coord) {
+        Some(values) => values,
+        None => coord
+            .read_raw::<f64>()
+            .map_err(|_| {
+                Error::Invalid(format!(
+                    "time coordinate {} holds neither strings nor numbers",
+                    name
+                ))
+            })?
+            .iter()
+            .map(|value| value.to_string())
+            .collect(),
+    };
+    return Ok(labels
+        .iter()
+        .map(|label| hdf::flatten_path(label.trim()))
+        .collect());
+}
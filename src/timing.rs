@@ -0,0 +1,169 @@
+//! Time spent reading, computing and writing in each task of the run (a
+//! reversed dataset, a group's mean and sd or rasters, a converted dataset),
+//! with the bytes read and written, for the report.
+//!
+//! Like the status, timings are accumulated process-wide by the functions
+//! that read and write, and each task's are kept when it completes. Tasks
+//! run at once (the rasters of several groups) are told apart by the thread
+//! that started them and the threads helping them (see [`help`]); reads
+//! and writes of other threads, such as the workers of a reversal, count
+//! towards the only task running.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Reading HDF5 datasets, including decompression.
+    Read,
+    /// Writing HDF5 datasets and raster outputs, including compression.
+    Write,
+}
+
+/// Timings of one task. Reads of parallel workers are added up, so
+/// `read_s` may exceed `elapsed_s`; `compute_s` is the rest of the elapsed
+/// time (flipping rows, statistics, range checks), if any.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskTimings {
+    pub stage: String,
+    pub path: String,
+    pub elapsed_s: f64,
+    pub read_s: f64,
+    pub compute_s: f64,
+    pub write_s: f64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// MB read per second spent reading.
+    pub read_mb_s: Option<f64>,
+    /// MB written per second spent writing.
+    pub write_mb_s: Option<f64>,
+}
+
+struct Current {
+    helpers: Vec<ThreadId>,
+    stage: String,
+    path: String,
+    started: Instant,
+    read: Duration,
+    write: Duration,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+static CURRENT: Mutex<Vec<(ThreadId, Current)>> = Mutex::new(Vec::new());
+static RECORDED: Mutex<Vec<TaskTimings>> = Mutex::new(Vec::new());
+
+/// Starts timing the task `path` of `stage` on this thread, dropping the
+/// timings of a task of the thread that did not complete.
+pub fn start(stage: &str, path: &str) {
+    if let Ok(mut current) = CURRENT.lock() {
+        let id = thread::current().id();
+        current.retain(|(thread, _)| *thread != id);
+        current.push((
+            id,
+            Current {
+                helpers: vec![],
+                stage: stage.to_owned(),
+                path: path.to_owned(),
+                started: Instant::now(),
+                read: Duration::ZERO,
+                write: Duration::ZERO,
+                bytes_read: 0,
+                bytes_written: 0,
+            },
+        ));
+    }
+}
+
+/// Counts the reads and writes of this thread towards the task started on
+/// `owner`, until that task finishes.
+pub fn help(owner: ThreadId) {
+    if let Ok(mut current) = CURRENT.lock() {
+        let id = thread::current().id();
+        if let Some((_, task)) = current.iter_mut().find(|(thread, _)| *thread == owner) {
+            task.helpers.push(id);
+        }
+    }
+}
+
+/// Adds `elapsed` and `bytes` to `stage` of the task of this thread, or of
+/// the only task running, if any.
+pub fn add(stage: Stage, elapsed: Duration, bytes: usize) {
+    if let Ok(mut current) = CURRENT.lock() {
+        let id = thread::current().id();
+        let index = current
+            .iter()
+            .position(|(thread, task)| *thread == id || task.helpers.contains(&id));
+        let index = match index {
+            Some(index) => Some(index),
+            None if current.len() == 1 => Some(0),
+            None => None,
+        };
+        if let Some(index) = index {
+            let current = &mut current[index].1;
+            match stage {
+                Stage::Read => {
+                    current.read += elapsed;
+                    current.bytes_read += bytes as u64;
+                }
+                Stage::Write => {
+                    current.write += elapsed;
+                    current.bytes_written += bytes as u64;
+                }
+            }
+        }
+    }
+}
+
+fn mb_s(bytes: u64, seconds: f64) -> Option<f64> {
+    if seconds > 0f64 {
+        return Some(bytes as f64 / 1e6 / seconds);
+    }
+    return None;
+}
+
+/// Keeps the timings of the task of this thread.
+pub fn finish() {
+    let current = match CURRENT.lock() {
+        Ok(mut current) => {
+            let id = thread::current().id();
+            current
+                .iter()
+                .position(|(thread, _)| *thread == id)
+                .map(|index| current.remove(index).1)
+        }
+        Err(_) => None,
+    };
+    let current = match current {
+        Some(current) => current,
+        None => return,
+    };
+    let elapsed_s = current.started.elapsed().as_secs_f64();
+    let read_s = current.read.as_secs_f64();
+    let write_s = current.write.as_secs_f64();
+    let timings = TaskTimings {
+        stage: current.stage,
+        path: current.path,
+        elapsed_s,
+        read_s,
+        compute_s: (elapsed_s - read_s - write_s).max(0f64),
+        write_s,
+        bytes_read: current.bytes_read,
+        bytes_written: current.bytes_written,
+        read_mb_s: mb_s(current.bytes_read, read_s),
+        write_mb_s: mb_s(current.bytes_written, write_s),
+    };
+    if let Ok(mut recorded) = RECORDED.lock() {
+        recorded.push(timings);
+    }
+}
+
+/// Timings kept so far, leaving none.
+pub fn take() -> Vec<TaskTimings> {
+    return match RECORDED.lock() {
+        Ok(mut recorded) => std::mem::take(&mut *recorded),
+        Err(_) => vec![],
+    };
+}
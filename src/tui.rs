@@ -0,0 +1,558 @@
+//! Interactive terminal UI (`tui` feature).
+//!
+//! The HDF5 file is shown as a tree in which statistic groups and numeric
+//! datasets can be ticked, next to the main options. The run itself is the
+//! regular command line, started as a child process with the picked groups,
+//! datasets and options: its output fills a log pane and its progress is
+//! polled from the JSON status endpoint (`--status-port`).
+
+use crate::cli::Options;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph};
+use ratatui::Terminal;
+use read_hdf_as_raster::error::Result;
+use read_hdf_as_raster::hdf::{self, H5NodeType, TraversalOptions, ValueClass};
+use read_hdf_as_raster::meta;
+use read_hdf_as_raster::policy::ExistsPolicy;
+use read_hdf_as_raster::raster::OutputFormat;
+use read_hdf_as_raster::status::Status;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+type Term = Terminal<CrosstermBackend<std::io::Stdout>>;
+
+/// Lines of child output kept for the log pane.
+const LOG_LINES: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NodeKind {
+    /// A group with the statistic datasets, converted as a whole.
+    StatGroup,
+    Group,
+    /// A numeric dataset, which can be converted on its own.
+    Dataset,
+    Other,
+}
+
+struct Node {
+    path: String,
+    depth: usize,
+    kind: NodeKind,
+    ticked: bool,
+}
+
+impl Node {
+    fn tickable(&self) -> bool {
+        return self.kind == NodeKind::StatGroup || self.kind == NodeKind::Dataset;
+    }
+}
+
+/// Selection screen state.
+struct Picker {
+    nodes: Vec<Node>,
+    list: ListState,
+    formats: Vec<&'static str>,
+    format: usize,
+    policy: ExistsPolicy,
+    quicklook: bool,
+    verify: bool,
+}
+
+/// Runs the UI for the file at `h5_path` and returns the exit code of the
+/// run, or 0 if none was started.
+pub fn run(h5_path: &str, options: &Options) -> Result<i32> {
+    let mut picker = Picker::new(h5_path, options)?;
+    let mut terminal = enter()?;
+    let result = (|| -> Result<i32> {
+        if !pick(&mut terminal, &mut picker)? {
+            return Ok(0);
+        }
+        let port = free_port()?;
+        let args = child_args(std::env::args().skip(1).collect(), &picker, port);
+        let expected_tasks = picker.expected_tasks();
+        return follow(&mut terminal, &args, port, expected_tasks);
+    })();
+    leave(&mut terminal)?;
+    return result;
+}
+
+fn enter() -> Result<Term> {
+    enable_raw_mode()?;
+    execute!(std::io::stdout(), EnterAlternateScreen)?;
+    return Ok(Terminal::new(CrosstermBackend::new(std::io::stdout()))?);
+}
+
+fn leave(terminal: &mut Term) -> Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    return Ok(());
+}
+
+impl Picker {
+    fn new(h5_path: &str, options: &Options) -> Result<Picker> {
+        let file = hdf5::File::open(h5_path)?;
+        let traversal = TraversalOptions {
+            follow_external_links: options.follow_external_links,
+        };
+        let listing = hdf::ls_hdf5_with(&file, "".to_owned(), &traversal);
+        let (stat_groups, _) = hdf::stat_groups(&file, &listing, &options.stat_names.all());
+        let selected = |path: &str, names: &[String]| {
+            return names
+                .iter()
+                .any(|name| name.trim_start_matches('/') == path.trim_start_matches('/'));
+        };
+
+        let mut nodes = vec![];
+        for node in &listing {
+            let (path, kind) = match node {
+                H5NodeType::Group(path) if stat_groups.contains(&path.as_str()) => {
+                    (path, NodeKind::StatGroup)
+                }
+                H5NodeType::Group(path) => (path, NodeKind::Group),
+                H5NodeType::Dataset(path) => {
+                    let numeric = !path.ends_with("_rev")
+                        && meta::get(file, path)?.value_class() == ValueClass::Numeric;
+                    (
+                        path,
+                        if numeric {
+                            NodeKind::Dataset
+                        } else {
+                            NodeKind::Other
+                        },
+                    )
+                }
+                H5NodeType::Other(path, _) => (path, NodeKind::Other),
+            };
+            let ticked = match kind {
+                NodeKind::StatGroup => match &options.groups {
+                    None => true,
+                    Some(groups) => selected(path, groups),
+                },
+                NodeKind::Dataset => selected(path, &options.datasets),
+                _ => false,
+            };
+            nodes.push(Node {
+                path: path.clone(),
+                depth: path.trim_matches('/').matches('/').count(),
+                kind,
+                ticked,
+            });
+        }
+        nodes.sort_by(|a, b| a.path.split('/').cmp(b.path.split('/')));
+
+        let formats = OutputFormat::available();
+        let format = formats
+            .iter()
+            .position(|name| *name == options.format.name())
+            .unwrap_or(0);
+        let mut list = ListState::default();
+        list.select(if nodes.is_empty() { None } else { Some(0) });
+        return Ok(Picker {
+            nodes,
+            list,
+            formats,
+            format,
+            policy: options.exists_policy,
+            quicklook: options.quicklook.is_some(),
+            verify: options.verify.is_some(),
+        });
+    }
+
+    fn ticked(&self, kind: NodeKind) -> Vec<&str> {
+        return self
+            .nodes
+            .iter()
+            .filter(|node| node.ticked && node.kind == kind)
+            .map(|node| node.path.as_str())
+            .collect();
+    }
+
+    /// Tasks the run reports: three reversals, mean/sd and rasters per
+    /// group, and one per dataset.
+    fn expected_tasks(&self) -> u64 {
+        let groups = self.ticked(NodeKind::StatGroup).len() as u64;
+        return 5 * groups + self.ticked(NodeKind::Dataset).len() as u64;
+    }
+
+    fn move_by(&mut self, step: isize) {
+        if let Some(selected) = self.list.selected() {
+            let last = self.nodes.len() as isize - 1;
+            self.list
+                .select(Some((selected as isize + step).max(0).min(last) as usize));
+        }
+    }
+
+    fn toggle(&mut self) {
+        if let Some(node) = self.list.selected().map(|ii| &mut self.nodes[ii]) {
+            if node.tickable() {
+                node.ticked = !node.ticked;
+            }
+        }
+    }
+
+    fn toggle_all_groups(&mut self) {
+        let tick = self
+            .nodes
+            .iter()
+            .any(|node| node.kind == NodeKind::StatGroup && !node.ticked);
+        for node in self.nodes.iter_mut() {
+            if node.kind == NodeKind::StatGroup {
+                node.ticked = tick;
+            }
+        }
+    }
+
+    fn next_policy(&mut self) {
+        self.policy = match self.policy {
+            ExistsPolicy::SkipExisting => ExistsPolicy::Overwrite,
+            ExistsPolicy::Overwrite => ExistsPolicy::FailIfExists,
+            ExistsPolicy::FailIfExists => ExistsPolicy::SkipExisting,
+        };
+    }
+}
+
+fn policy_flag(policy: ExistsPolicy) -> &'static str {
+    return match policy {
+        ExistsPolicy::SkipExisting => "--skip-existing",
+        ExistsPolicy::Overwrite => "--overwrite",
+        ExistsPolicy::FailIfExists => "--fail-if-exists",
+    };
+}
+
+/// Shows the selection screen until the run is started (`true`) or the UI
+/// is closed (`false`).
+fn pick(terminal: &mut Term, picker: &mut Picker) -> Result<bool> {
+    loop {
+        terminal.draw(|frame| {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .split(frame.area());
+
+            let items: Vec<ListItem> = picker
+                .nodes
+                .iter()
+                .map(|node| {
+                    let mark = match (node.tickable(), node.ticked) {
+                        (false, _) => "   ",
+                        (true, true) => "[x]",
+                        (true, false) => "[ ]",
+                    };
+                    let name = node.path.rsplit('/').next().unwrap_or(&node.path);
+                    let suffix = match node.kind {
+                        NodeKind::StatGroup | NodeKind::Group => "/",
+                        _ => "",
+                    };
+                    let text = format!("{} {}{}{}", mark, "  ".repeat(node.depth), name, suffix);
+                    let style = match node.kind {
+                        NodeKind::Other => Style::default().add_modifier(Modifier::DIM),
+                        NodeKind::StatGroup => Style::default().add_modifier(Modifier::BOLD),
+                        _ => Style::default(),
+                    };
+                    return ListItem::new(text).style(style);
+                })
+                .collect();
+            let tree = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(" HDF5 file "))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            frame.render_stateful_widget(tree, columns[0], &mut picker.list);
+
+            let on_off = |on: bool| if on { "on" } else { "off" };
+            let lines = vec![
+                Line::from(format!(
+                    "groups:    {}",
+                    picker.ticked(NodeKind::StatGroup).len()
+                )),
+                Line::from(format!(
+                    "datasets:  {}",
+                    picker.ticked(NodeKind::Dataset).len()
+                )),
+                Line::from(""),
+                Line::from(format!("[f] format:    {}", picker.formats[picker.format])),
+                Line::from(format!("[p] existing:  {}", policy_flag(picker.policy))),
+                Line::from(format!("[l] quicklook: {}", on_off(picker.quicklook))),
+                Line::from(format!("[v] verify:    {}", on_off(picker.verify))),
+                Line::from(""),
+                Line::from("[space] tick   [a] all groups"),
+                Line::from("[enter] run    [q] quit"),
+            ];
+            let panel = Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(" Options "));
+            frame.render_widget(panel, columns[1]);
+        })?;
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => picker.move_by(-1),
+                KeyCode::Down | KeyCode::Char('j') => picker.move_by(1),
+                KeyCode::PageUp => picker.move_by(-20),
+                KeyCode::PageDown => picker.move_by(20),
+                KeyCode::Char(' ') => picker.toggle(),
+                KeyCode::Char('a') => picker.toggle_all_groups(),
+                KeyCode::Char('f') => picker.format = (picker.format + 1) % picker.formats.len(),
+                KeyCode::Char('p') => picker.next_policy(),
+                KeyCode::Char('l') => picker.quicklook = !picker.quicklook,
+                KeyCode::Char('v') => picker.verify = !picker.verify,
+                KeyCode::Enter => return Ok(true),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Command line of the run: the original arguments without the ones the
+/// picker decides, followed by the picked values.
+fn child_args(args: Vec<String>, picker: &Picker, port: u16) -> Vec<String> {
+    let mut dropped = vec![
+        ("--tui", 0),
+        ("--groups", 1),
+        ("--datasets", 1),
+        ("--format", 1),
+        ("--skip-existing", 0),
+        ("--overwrite", 0),
+        ("--fail-if-exists", 0),
+        ("--status-port", 1),
+    ];
+    if !picker.quicklook {
+        dropped.extend(&[
+            ("--quicklook", 0),
+            ("--quicklook-ramp", 1),
+            ("--quicklook-stretch", 1),
+        ]);
+    }
+    if !picker.verify {
+        dropped.extend(&[
+            ("--verify", 0),
+            ("--verify-windows", 1),
+            ("--verify-epsilon", 1),
+        ]);
+    }
+
+    let mut kept = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match dropped.iter().find(|(flag, _)| *flag == arg) {
+            Some((_, n_values)) => {
+                for _ in 0..*n_values {
+                    args.next();
+                }
+            }
+            None => kept.push(arg),
+        }
+    }
+    kept.push("--groups".to_owned());
+    kept.push(picker.ticked(NodeKind::StatGroup).join(","));
+    kept.push("--datasets".to_owned());
+    kept.push(picker.ticked(NodeKind::Dataset).join(","));
+    kept.push("--format".to_owned());
+    kept.push(picker.formats[picker.format].to_owned());
+    kept.push(policy_flag(picker.policy).to_owned());
+    if picker.quicklook {
+        kept.push("--quicklook".to_owned());
+    }
+    if picker.verify {
+        kept.push("--verify".to_owned());
+    }
+    kept.push("--status-port".to_owned());
+    kept.push(port.to_string());
+    return kept;
+}
+
+/// Completed fraction of the run: finished tasks plus the part done of the
+/// current one, so that a huge dataset still moves the bar.
+fn overall_ratio(status: &Status, expected_tasks: u64) -> f64 {
+    let mut done = status.tasks_completed as f64;
+    if status.percent < 100f32 {
+        done += status.percent as f64 / 100f64;
+    }
+    return (done / expected_tasks.max(1) as f64).min(1f64);
+}
+
+fn free_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+    return Ok(listener.local_addr()?.port());
+}
+
+/// Status of the run, read from its status endpoint.
+fn fetch_status(port: u16) -> Option<Status> {
+    let address: std::net::SocketAddr = ([127, 0, 0, 1], port).into();
+    let mut stream = TcpStream::connect_timeout(&address, Duration::from_millis(200)).ok()?;
+    stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .ok()?;
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    let body = &response[response.find("\r\n\r\n")? + 4..];
+    return serde_json::from_str(body).ok();
+}
+
+/// Forwards the lines of `output` to `lines`. Progress updates separated by
+/// carriage returns keep only the latest.
+fn forward_lines<R: Read + Send + 'static>(output: R, lines: mpsc::Sender<String>) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).split(b'\n') {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            let line = String::from_utf8_lossy(&line);
+            let latest = line
+                .rsplit('\r')
+                .find(|part| !part.is_empty())
+                .unwrap_or("");
+            if lines.send(latest.to_owned()).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Asks the run to stop after its current chunk, as Ctrl-C would.
+fn interrupt(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        let sent = Command::new("kill")
+            .args(&["-INT", &child.id().to_string()])
+            .status()
+            .map_or(false, |status| status.success());
+        if sent {
+            return;
+        }
+    }
+    let _ = child.kill();
+}
+
+/// Starts the run with `args` and shows its progress until it ends and the
+/// screen is closed. Returns the exit code of the run.
+fn follow(terminal: &mut Term, args: &[String], port: u16, expected_tasks: u64) -> Result<i32> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let (sender, lines) = mpsc::channel();
+    forward_lines(child.stdout.take().unwrap(), sender.clone());
+    forward_lines(child.stderr.take().unwrap(), sender);
+
+    let mut log: Vec<String> = vec![];
+    let mut status = Status::default();
+    let mut last_poll = Instant::now() - Duration::from_secs(1);
+    let mut exit_code = None;
+    let mut stopping = false;
+    loop {
+        log.extend(lines.try_iter());
+        if log.len() > LOG_LINES {
+            log.drain(..log.len() - LOG_LINES);
+        }
+        if exit_code.is_none() {
+            if last_poll.elapsed() >= Duration::from_millis(500) {
+                if let Some(latest) = fetch_status(port) {
+                    status = latest;
+                }
+                last_poll = Instant::now();
+            }
+            if let Some(exit) = child.try_wait()? {
+                exit_code = Some(exit.code().unwrap_or(1));
+            }
+        }
+
+        terminal.draw(|frame| {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(6),
+                    Constraint::Min(3),
+                ])
+                .split(frame.area());
+
+            let title = match exit_code {
+                None if stopping => " Stopping after the current chunk... ".to_owned(),
+                None => " Running ([q] stop) ".to_owned(),
+                Some(code) => format!(" Finished with exit code {} ([q] close) ", code),
+            };
+            let header = Paragraph::new(format!(
+                "{} {} ({} of {})",
+                status.stage, status.current, status.task_index, status.task_total
+            ))
+            .block(Block::default().borders(Borders::ALL).title(title));
+            frame.render_widget(header, rows[0]);
+
+            let task = Gauge::default()
+                .block(Block::default().borders(Borders::ALL).title(" Task "))
+                .ratio((status.percent as f64 / 100f64).max(0f64).min(1f64));
+            frame.render_widget(task, rows[1]);
+
+            let overall = Gauge::default()
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                    " Overall: {} of {} tasks, {:.1} MB/s, {:.0} s ",
+                    status.tasks_completed,
+                    expected_tasks,
+                    status.throughput_mb_s,
+                    status.elapsed_s
+                )))
+                .ratio(overall_ratio(&status, expected_tasks));
+            frame.render_widget(overall, rows[2]);
+
+            let errors: Vec<Line> = status
+                .errors
+                .iter()
+                .rev()
+                .take(4)
+                .map(|error| Line::from(error.as_str()))
+                .collect();
+            let errors = Paragraph::new(errors).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" Warnings and errors ({}) ", status.errors.len())),
+            );
+            frame.render_widget(errors, rows[3]);
+
+            let height = rows[4].height.saturating_sub(2) as usize;
+            let shown: Vec<Line> = log[log.len().saturating_sub(height)..]
+                .iter()
+                .map(|line| Line::from(line.as_str()))
+                .collect();
+            let output =
+                Paragraph::new(shown).block(Block::default().borders(Borders::ALL).title(" Log "));
+            frame.render_widget(output, rows[4]);
+        })?;
+
+        if event::poll(Duration::from_millis(100))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press
+                    && (key.code == KeyCode::Char('q') || key.code == KeyCode::Esc)
+                {
+                    match exit_code {
+                        Some(code) => return Ok(code),
+                        None if !stopping => {
+                            interrupt(&mut child);
+                            stopping = true;
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+}
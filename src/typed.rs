@@ -0,0 +1,242 @@
+//! Dispatch from a runtime [`RasterType`] to code generic over the element
+//! type, so the steps run for whatever type a dataset or output has are
+//! written once and a new type only needs an arm in [`dispatch`].
+
+use crate::raster::RasterValue;
+use crate::templates::RasterType;
+
+/// A step generic over the element type, run by [`dispatch`] with the type
+/// of a [`RasterType`].
+pub trait TypedJob {
+    type Output;
+
+    fn run<T: RasterValue + Send>(self) -> Self::Output;
+}
+
+/// Runs `job` with the element type of `data_type`.
+pub fn dispatch<J: TypedJob>(data_type: RasterType, job: J) -> J::Output {
+    return match data_type {
+        RasterType::Byte => job.run::<u8>(),
+        RasterType::Int16 => job.run::<i16>(),
+        RasterType::UInt16 => job.run::<u16>(),
+        RasterType::Int32 => job.run::<i32>(),
+        RasterType::UInt32 => job.run::<u32>(),
+        RasterType::Float32 => job.run::<f32>(),
+        RasterType::Float64 => job.run::<f64>(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+    use crate::hdf::HdfSource;
+    use crate::narrowing::Narrowed;
+    use crate::reverse::rev_array;
+    use crate::valid_range::RangeValue;
+    use hdf5::types::{FloatSize, IntSize, TypeDescriptor};
+    use ndarray::Array1;
+
+    struct Descriptor;
+
+    impl TypedJob for Descriptor {
+        type Output = TypeDescriptor;
+
+        fn run<T: RasterValue + Send>(self) -> TypeDescriptor {
+            return T::type_descriptor();
+        }
+    }
+
+    /// Flips a 3 by 2 grid of 0..6 and reads it back as `f64`.
+    struct Flip;
+
+    impl TypedJob for Flip {
+        type Output = Vec<f64>;
+
+        fn run<T: RasterValue + Send>(self) -> Vec<f64> {
+            let vals: Array1<T> = (0..6).map(|val| T::from_f64(val as f64)).collect();
+            return rev_array(vals, 2, 3)
+                .iter()
+                .map(|&val| val.into())
+                .collect();
+        }
+    }
+
+    #[test]
+    fn dispatch_runs_the_element_type_of_each_raster_type() {
+        let expected = [
+            (RasterType::Byte, TypeDescriptor::Unsigned(IntSize::U1)),
+            (RasterType::Int16, TypeDescriptor::Integer(IntSize::U2)),
+            (RasterType::UInt16, TypeDescriptor::Unsigned(IntSize::U2)),
+            (RasterType::Int32, TypeDescriptor::Integer(IntSize::U4)),
+            (RasterType::UInt32, TypeDescriptor::Unsigned(IntSize::U4)),
+            (RasterType::Float32, TypeDescriptor::Float(FloatSize::U4)),
+            (RasterType::Float64, TypeDescriptor::Float(FloatSize::U8)),
+        ];
+        for (data_type, descriptor) in expected.iter() {
+            assert_eq!(&dispatch(*data_type, Descriptor), descriptor);
+            assert_eq!(
+                dispatch(*data_type, Flip),
+                vec![3., 4., 5., 0., 1., 2.],
+                "{}",
+                data_type.name()
+            );
+        }
+    }
+
+    /// Values read as `f64` from any path.
+    struct Values(Vec<f64>);
+
+    impl HdfSource<f64> for Values {
+        fn len(&self, _path: &str) -> Result<usize> {
+            return Ok(self.0.len());
+        }
+
+        fn read_range(&self, _path: &str, start: usize, end: usize) -> Result<Vec<f64>> {
+            return Ok(self.0[start..end].to_vec());
+        }
+    }
+
+    /// Narrows `values` with `nodata` to the type dispatched and reads them
+    /// back as `f64`, with the nodata value of the output and the pixels
+    /// clipped.
+    struct Narrow {
+        values: Vec<f64>,
+        nodata: Option<f64>,
+        from_float: bool,
+    }
+
+    impl TypedJob for Narrow {
+        type Output = (Vec<f64>, Option<f64>, u64);
+
+        fn run<T: RasterValue + Send>(self) -> (Vec<f64>, Option<f64>, u64) {
+            let source = Values(self.values);
+            let narrowed = Narrowed::new::<T>(&source, self.nodata, self.from_float);
+            let vals: Vec<T> = narrowed.read_range("", 0, source.0.len()).unwrap();
+            let record = narrowed.finish("", "");
+            return (
+                vals.into_iter().map(|val| val.into()).collect(),
+                record.nodata,
+                record.pixels,
+            );
+        }
+    }
+
+    /// Lowest and highest values of the type dispatched, as `f64`.
+    struct Limits;
+
+    impl TypedJob for Limits {
+        type Output = (f64, f64);
+
+        fn run<T: RasterValue + Send>(self) -> (f64, f64) {
+            return (T::LOWEST, T::HIGHEST);
+        }
+    }
+
+    #[test]
+    fn values_in_range_round_trip_through_each_raster_type() {
+        let data_types = [
+            RasterType::Byte,
+            RasterType::Int16,
+            RasterType::UInt16,
+            RasterType::Int32,
+            RasterType::UInt32,
+            RasterType::Float32,
+            RasterType::Float64,
+        ];
+        for data_type in data_types.iter() {
+            let (lowest, highest) = dispatch(*data_type, Limits);
+            let values = vec![lowest, -1f64, 0f64, 1f64, 200f64, highest];
+            let (vals, nodata, clipped) = dispatch(
+                *data_type,
+                Narrow {
+                    values: values.clone(),
+                    nodata: None,
+                    from_float: false,
+                },
+            );
+            let expected: Vec<f64> = match lowest < 0f64 {
+                true => values,
+                // -1 is clipped to 0 for unsigned types.
+                false => vec![0f64, 0f64, 0f64, 1f64, 200f64, highest],
+            };
+            assert_eq!(vals, expected, "{}", data_type.name());
+            assert_eq!(nodata, None, "{}", data_type.name());
+            assert_eq!(clipped, (lowest == 0f64) as u64, "{}", data_type.name());
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_and_nodata_narrow_to_each_raster_type() {
+        // Rounding of halves, values past the limits of every type, the
+        // nodata value and NaN.
+        let values = vec![0f64, 2.5, -2.5, 1e300, -1e300, -9999f64, f64::NAN];
+        let expected: [(RasterType, Vec<f64>, f64, u64); 7] = [
+            // -9999 does not fit unsigned types, whose nodata becomes their
+            // highest value, which clipped values no longer take.
+            (
+                RasterType::Byte,
+                vec![0., 3., 0., 254., 0., 255., 255.],
+                255.,
+                3,
+            ),
+            (
+                RasterType::Int16,
+                vec![0., 3., -3., 32767., -32768., -9999., -9999.],
+                -9999.,
+                2,
+            ),
+            (
+                RasterType::UInt16,
+                vec![0., 3., 0., 65534., 0., 65535., 65535.],
+                65535.,
+                3,
+            ),
+            (
+                RasterType::Int32,
+                vec![0., 3., -3., 2147483647., -2147483648., -9999., -9999.],
+                -9999.,
+                2,
+            ),
+            (
+                RasterType::UInt32,
+                vec![0., 3., 0., 4294967294., 0., 4294967295., 4294967295.],
+                4294967295.,
+                3,
+            ),
+            (
+                RasterType::Float32,
+                vec![
+                    0.,
+                    2.5,
+                    -2.5,
+                    f32::MAX as f64,
+                    f32::MIN as f64,
+                    -9999.,
+                    -9999.,
+                ],
+                -9999.,
+                2,
+            ),
+            (
+                RasterType::Float64,
+                vec![0., 2.5, -2.5, 1e300, -1e300, -9999., -9999.],
+                -9999.,
+                0,
+            ),
+        ];
+        for (data_type, expected_vals, expected_nodata, expected_clipped) in expected.iter() {
+            let (vals, nodata, clipped) = dispatch(
+                *data_type,
+                Narrow {
+                    values: values.clone(),
+                    nodata: Some(-9999f64),
+                    from_float: true,
+                },
+            );
+            assert_eq!(&vals, expected_vals, "{}", data_type.name());
+            assert_eq!(nodata, Some(*expected_nodata), "{}", data_type.name());
+            assert_eq!(clipped, *expected_clipped, "{}", data_type.name());
+        }
+    }
+}
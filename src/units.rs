@@ -0,0 +1,243 @@
+//! Unit conversions applied to the values of chosen datasets and groups as
+//! they are written, with the converted units stored in the outputs.
+
+use crate::error::Result;
+use crate::hdf::HdfSource;
+use crate::valid_range::RangeValue;
+
+/// Units that can be converted into one another by name, with the size of
+/// each in the first unit of its list.
+const NAMED_UNITS: &[&[(&str, f64)]] = &[
+    // Mass per area, e.g. biomass density.
+    &[
+        ("Mg/ha", 1f64),
+        ("t/ha", 1f64),
+        ("kg/ha", 0.001),
+        ("kg/m2", 10f64),
+        ("g/m2", 0.01),
+    ],
+    // Length, e.g. canopy height.
+    &[("m", 1f64), ("cm", 0.01), ("mm", 0.001), ("km", 1000f64)],
+];
+
+/// Multiplies values by `scale`, giving values in `units`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitConversion {
+    pub scale: f64,
+    pub units: String,
+}
+
+impl UnitConversion {
+    /// Conversion between two named units, as `FROM:TO` (e.g. `Mg/ha:kg/m2`).
+    pub fn parse_named(text: &str) -> std::result::Result<Self, String> {
+        let (from, to) = split_pair(text, "FROM:TO")?;
+        for units in NAMED_UNITS {
+            let size = |name: &str| {
+                return units
+                    .iter()
+                    .find(|(unit, _)| unit.eq_ignore_ascii_case(name))
+                    .map(|(_, size)| *size);
+            };
+            if let (Some(from_size), Some(to_size)) = (size(from), size(to)) {
+                let units = units.iter().find(|(unit, _)| unit.eq_ignore_ascii_case(to));
+                return Ok(UnitConversion {
+                    scale: from_size / to_size,
+                    units: units.unwrap().0.to_owned(),
+                });
+            }
+        }
+        let known: Vec<String> = NAMED_UNITS
+            .iter()
+            .map(|units| {
+                let names: Vec<&str> = units.iter().map(|(unit, _)| *unit).collect();
+                return names.join(", ");
+            })
+            .collect();
+        return Err(format!(
+            "cannot convert {} to {}; named units are {}",
+            from,
+            to,
+            known.join("; ")
+        ));
+    }
+
+    /// Positive scale factor and the units it gives, as `FACTOR:UNITS`.
+    pub fn parse_scale(text: &str) -> std::result::Result<Self, String> {
+        let (scale, units) = split_pair(text, "FACTOR:UNITS")?;
+        return match scale.parse::<f64>() {
+            Ok(scale) if scale.is_finite() && scale > 0f64 => Ok(UnitConversion {
+                scale,
+                units: units.to_owned(),
+            }),
+            _ => Err(format!("invalid scale factor '{}'", scale)),
+        };
+    }
+}
+
+/// Splits `A:B` at its first colon.
+fn split_pair<'a>(
+    text: &'a str,
+    expected: &str,
+) -> std::result::Result<(&'a str, &'a str), String> {
+    return match text.split_once(':') {
+        Some((first, second)) if !first.trim().is_empty() && !second.trim().is_empty() => {
+            Ok((first.trim(), second.trim()))
+        }
+        _ => Err(format!(
+            "invalid conversion '{}', expected {}",
+            text, expected
+        )),
+    };
+}
+
+/// Conversion of the outputs of one group or dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitRule {
+    /// Group or dataset path, or the last component of a dataset path.
+    pub target: String,
+    pub conversion: UnitConversion,
+}
+
+impl UnitRule {
+    /// Parses `TARGET=CONVERSION`, the conversion being parsed by `parse`.
+    pub fn parse(
+        text: &str,
+        parse: fn(&str) -> std::result::Result<UnitConversion, String>,
+    ) -> std::result::Result<Self, String> {
+        return match text.split_once('=') {
+            Some((target, conversion)) if !target.trim().is_empty() => Ok(UnitRule {
+                target: target.trim().trim_matches('/').to_owned(),
+                conversion: parse(conversion)?,
+            }),
+            _ => Err(format!("invalid unit rule '{}', expected TARGET=...", text)),
+        };
+    }
+}
+
+/// Conversion of the group or dataset at `path`: the rule naming its whole
+/// path, or else its last component.
+pub fn conversion_for<'a>(rules: &'a [UnitRule], path: &str) -> Option<&'a UnitConversion> {
+    let path = path.trim_matches('/');
+    let name = path.rsplit('/').next().unwrap_or(path);
+    return rules
+        .iter()
+        .find(|rule| rule.target == path)
+        .or_else(|| rules.iter().find(|rule| rule.target == name))
+        .map(|rule| &rule.conversion);
+}
+
+/// A source scaling the values read from it, except NaN and `nodata`.
+/// Without a conversion, values are passed through.
+pub struct Scaled<'a, S> {
+    source: &'a S,
+    scale: Option<f64>,
+    nodata: Option<f64>,
+}
+
+impl<'a, S> Scaled<'a, S> {
+    pub fn new(source: &'a S, conversion: Option<&UnitConversion>, nodata: Option<f64>) -> Self {
+        return Scaled {
+            source,
+            scale: conversion.map(|conversion| conversion.scale),
+            nodata,
+        };
+    }
+}
+
+impl<'a, T, S> HdfSource<T> for Scaled<'a, S>
+where
+    T: RangeValue,
+    S: HdfSource<T>,
+{
+    fn len(&self, path: &str) -> Result<usize> {
+        return self.source.len(path);
+    }
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> Result<Vec<T>> {
+        let mut vals = self.source.read_range(path, start, end)?;
+        if let Some(scale) = self.scale {
+            for val in vals.iter_mut() {
+                let value: f64 = (*val).into();
+                if !value.is_nan() && Some(value) != self.nodata {
+                    *val = T::from_f64(value * scale);
+                }
+            }
+        }
+        return Ok(vals);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Values(Vec<f64>);
+
+    impl HdfSource<f64> for Values {
+        fn len(&self, _path: &str) -> Result<usize> {
+            return Ok(self.0.len());
+        }
+
+        fn read_range(&self, _path: &str, start: usize, end: usize) -> Result<Vec<f64>> {
+            return Ok(self.0[start..end].to_vec());
+        }
+    }
+
+    fn scale(conversion: &UnitConversion, vals: Vec<f64>) -> Vec<f64> {
+        let source = Values(vals);
+        let len = source.0.len();
+        return Scaled::new(&source, Some(conversion), Some(-9999.))
+            .read_range("", 0, len)
+            .unwrap();
+    }
+
+    #[test]
+    fn named_conversions_round_trip() {
+        let there = UnitConversion::parse_named("Mg/ha:kg/m2").unwrap();
+        assert_eq!(there.units, "kg/m2");
+        let back = UnitConversion::parse_named("KG/M2:mg/ha").unwrap();
+        assert_eq!(back.units, "Mg/ha");
+        let converted = scale(&there, vec![250., -9999., 0.]);
+        assert_eq!(converted, vec![25., -9999., 0.]);
+        let restored = scale(&back, converted);
+        assert!((restored[0] - 250.).abs() < 1e-9);
+        assert_eq!(restored[1..], [-9999., 0.]);
+    }
+
+    #[test]
+    fn rules_and_scales_parse() {
+        let rule = UnitRule::parse("/agbd/=0.001:Mg/ha", UnitConversion::parse_scale).unwrap();
+        assert_eq!(rule.target, "agbd");
+        assert_eq!(
+            rule.conversion,
+            UnitConversion {
+                scale: 0.001,
+                units: "Mg/ha".to_owned(),
+            }
+        );
+        let rules = [
+            rule,
+            UnitRule::parse("l4a/agbd=cm:m", UnitConversion::parse_named).unwrap(),
+        ];
+        assert_eq!(conversion_for(&rules, "/l4a/agbd").unwrap().units, "m");
+        assert_eq!(conversion_for(&rules, "l2/agbd").unwrap().units, "Mg/ha");
+        assert_eq!(conversion_for(&rules, "l2/rh98"), None);
+    }
+
+    #[test]
+    fn invalid_conversions_are_refused() {
+        for text in ["Mg/ha", ":kg/m2", "Mg/ha:", "Mg/ha:m", "parsec:m"].iter() {
+            assert!(UnitConversion::parse_named(text).is_err(), "{}", text);
+        }
+        for text in ["0:m", "-2:m", "inf:m", "x:m", "2"].iter() {
+            assert!(UnitConversion::parse_scale(text).is_err(), "{}", text);
+        }
+        for text in ["=2:m", "agbd", "agbd=2"].iter() {
+            assert!(
+                UnitRule::parse(text, UnitConversion::parse_scale).is_err(),
+                "{}",
+                text
+            );
+        }
+    }
+}
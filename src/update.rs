@@ -0,0 +1,204 @@
+//! Incremental updates (`--update`): products accumulated over time, e.g.
+//! as new satellite passes arrive, are updated by adding the sums and
+//! counts of an increment file to those of the converted file, element by
+//! element, instead of reprocessing everything. The datasets derived from
+//! the old sums (`*_rev`, `mean_rev`, `sd_rev`, ...) are removed so the run
+//! computes them again.
+
+use crate::chunking;
+use crate::error::{Error, Result};
+use crate::hdf::{self, HasMembers, HdfSource, ValueClass};
+use crate::interrupt;
+use crate::raster::RasterValue;
+use crate::reverse::{ChunkWriter, HdfChunks};
+use crate::row_order;
+use crate::stats::StatNames;
+use crate::status;
+use crate::templates::RasterType;
+use crate::typed::{self, TypedJob};
+use crate::valid_range::RangeValue;
+
+/// Adds the statistic datasets (`names`) of each of `groups` in `increment`
+/// to those of `file`, in chunks that fit `memory_limit`. Returns the
+/// groups updated: those of `groups` the increment has all of them for;
+/// the others are skipped with a message.
+pub fn add_increments(
+    file: &hdf5::File,
+    increment: &hdf5::File,
+    increment_path: &str,
+    groups: &[&str],
+    names: &StatNames,
+    memory_limit: usize,
+) -> Result<Vec<String>> {
+    let chunk_size = chunking::chunk_len(memory_limit, chunking::UPDATE_BYTES_PER_PIXEL);
+    if row_order::file_top_down(file)? != row_order::file_top_down(increment)? {
+        return Err(Error::Invalid(format!(
+            "the rows of {} run opposite to those of the converted file",
+            increment_path
+        )));
+    }
+    let mut updated = vec![];
+    let mut paths_updated = vec![];
+    for group in groups {
+        let paths: Vec<String> = names
+            .all()
+            .iter()
+            .map(|name| hdf::join_path(group, name))
+            .collect();
+        let missing = {
+            let _silence = hdf5::silence_errors();
+            paths.iter().find(|path| increment.dataset(path).is_err())
+        };
+        if let Some(path) = missing {
+            println!(
+                "Skipping group {}: {} has no {}",
+                group, increment_path, path
+            );
+            continue;
+        }
+        println!(
+            "Adding the increments of group {} from {}",
+            group, increment_path
+        );
+        paths_updated.extend(paths);
+        updated.push(group.to_string());
+    }
+    // Every dataset is checked before the first is written, so a failed
+    // update leaves the file as it was.
+    for path in &paths_updated {
+        add_dataset(file, increment, path, chunk_size, true)?;
+    }
+    for path in &paths_updated {
+        add_dataset(file, increment, path, chunk_size, false)?;
+    }
+    file.flush()?;
+    return Ok(updated);
+}
+
+/// Adds the dataset `path` of `increment` to that of `file`, in its type;
+/// with `check_only`, only checks that it can.
+fn add_dataset(
+    file: &hdf5::File,
+    increment: &hdf5::File,
+    path: &str,
+    chunk_size: usize,
+    check_only: bool,
+) -> Result<()> {
+    let ds = file.dataset(path)?;
+    let len = ds.size();
+    let increment_len = increment.dataset(path)?.size();
+    if increment_len != len {
+        return Err(Error::Invalid(format!(
+            "{} of the increment has {} elements, {} of the file has {}",
+            path, increment_len, path, len
+        )));
+    }
+    for ds in &[&ds, &increment.dataset(path)?] {
+        if hdf::value_class(ds)? != ValueClass::Numeric {
+            return Err(Error::Invalid(format!(
+                "{} is not numeric; only plain numeric datasets can be updated",
+                path
+            )));
+        }
+    }
+    let data_type = RasterType::of_dataset(&ds)?
+        .ok_or_else(|| Error::Invalid(format!("{} has an unsupported type", path)))?;
+    let pass = (chunk_size, check_only);
+    return typed::dispatch(
+        data_type,
+        AddJob {
+            file,
+            ds: &ds,
+            increment,
+            path,
+            pass,
+        },
+    );
+}
+
+/// [`add_as`] for the type dispatched.
+struct AddJob<'a> {
+    file: &'a hdf5::File,
+    ds: &'a hdf5::Dataset,
+    increment: &'a hdf5::File,
+    path: &'a str,
+    pass: (usize, bool),
+}
+
+impl TypedJob for AddJob<'_> {
+    type Output = Result<()>;
+
+    fn run<T: RasterValue + Send>(self) -> Result<()> {
+        return add_as::<T>(self.file, self.ds, self.increment, self.path, self.pass);
+    }
+}
+
+/// Adds `increment`'s `path` to `ds` chunk by chunk or, with `check_only`,
+/// fails if an integer sum would overflow `T`, e.g. a byte count passing
+/// 255.
+fn add_as<T: RasterValue>(
+    file: &hdf5::File,
+    ds: &hdf5::Dataset,
+    increment: &hdf5::File,
+    path: &str,
+    (chunk_size, check_only): (usize, bool),
+) -> Result<()> {
+    let len = ds.size();
+    if check_only {
+        if !T::IS_INTEGER {
+            return Ok(());
+        }
+        for start in (0..len).step_by(chunk_size) {
+            interrupt::check()?;
+            let end = (start + chunk_size).min(len);
+            let vals: Vec<T> = file.read_range(path, start, end)?;
+            let increments: Vec<T> = increment.read_range(path, start, end)?;
+            let overflow = vals.iter().zip(increments.iter()).position(|(&a, &b)| {
+                let sum = Into::<f64>::into(a) + Into::<f64>::into(b);
+                return sum > T::HIGHEST || sum < T::LOWEST;
+            });
+            if let Some(index) = overflow {
+                return Err(Error::Invalid(format!(
+                    "adding the increment would overflow {} at element {}; \
+                     store it with a wider type",
+                    path,
+                    start + index
+                )));
+            }
+        }
+        return Ok(());
+    }
+    let writer = HdfChunks(ds);
+    for start in (0..len).step_by(chunk_size) {
+        interrupt::check()?;
+        status::set_progress(start as f32 / len as f32);
+        let end = (start + chunk_size).min(len);
+        let vals: Vec<T> = file.read_range(path, start, end)?;
+        let increments: Vec<T> = increment.read_range(path, start, end)?;
+        let sums: Vec<T> = vals
+            .iter()
+            .zip(increments.iter())
+            .map(|(&a, &b)| T::from_f64(Into::<f64>::into(a) + Into::<f64>::into(b)))
+            .collect();
+        writer.write_range(start, end, &sums)?;
+    }
+    return Ok(());
+}
+
+/// Removes the datasets derived from the statistic datasets of `groups`
+/// (those ending in `_rev`) from `derived`, the converted or the scratch
+/// file, so they are computed again from the updated sums.
+pub fn remove_derived(derived: &hdf5::File, groups: &[String]) -> Result<()> {
+    for group in groups {
+        if !derived.link_exists(group) {
+            continue;
+        }
+        for name in derived.group(group)?.get_members()? {
+            if name.ends_with("_rev") {
+                derived.unlink(&hdf::join_path(group, &name))?;
+            }
+        }
+    }
+    derived.flush()?;
+    return Ok(());
+}
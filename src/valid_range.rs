@@ -0,0 +1,327 @@
+//! Valid ranges: values outside them are set to nodata or clamped while the
+//! outputs are written, and the pixels affected counted for the report.
+
+use crate::error::{Error, Result};
+use crate::hdf::HdfSource;
+use serde::Serialize;
+use std::cell::Cell;
+use std::sync::Mutex;
+
+/// What happens to values outside the valid range.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfRange {
+    /// Set to the nodata value of the output (NaN, or 0 for integer outputs
+    /// without one).
+    Nodata,
+    /// Set to the nearest end of the range.
+    Clamp,
+}
+
+impl Default for OutOfRange {
+    fn default() -> Self {
+        return OutOfRange::Nodata;
+    }
+}
+
+impl OutOfRange {
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        return match text {
+            "nodata" => Ok(OutOfRange::Nodata),
+            "clamp" => Ok(OutOfRange::Clamp),
+            _ => Err(format!(
+                "unknown out of range action '{}' (expected nodata or clamp)",
+                text
+            )),
+        };
+    }
+}
+
+/// Where the valid range of an output comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeOption {
+    /// The same range for every output.
+    Fixed(f64, f64),
+    /// The `valid_range`, or `valid_min` and `valid_max`, attributes of each
+    /// dataset converted.
+    Attributes,
+}
+
+impl RangeOption {
+    /// Parses `MIN,MAX` or `attrs`.
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        if text == "attrs" {
+            return Ok(RangeOption::Attributes);
+        }
+        let invalid = || format!("invalid valid range '{}', expected MIN,MAX or attrs", text);
+        let mut parts = text.split(',').map(|part| part.trim().parse::<f64>());
+        return match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(min)), Some(Ok(max)), None) if min <= max => Ok(RangeOption::Fixed(min, max)),
+            _ => Err(invalid()),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValidRange {
+    pub min: f64,
+    pub max: f64,
+    pub action: OutOfRange,
+}
+
+impl ValidRange {
+    /// Range of `ds` from its `valid_range` attribute, or its `valid_min`
+    /// and `valid_max` attributes (either may be missing), or `None` without
+    /// any of them.
+    pub fn from_attributes(ds: &hdf5::Dataset, action: OutOfRange) -> Result<Option<Self>> {
+        let _silence = hdf5::silence_errors();
+        let read = |name: &str| -> Result<Option<Vec<f64>>> {
+            return match ds.attr(name) {
+                Ok(attr) => Ok(Some(attr.read_raw::<f64>()?)),
+                Err(_) => Ok(None),
+            };
+        };
+        if let Some(range) = read("valid_range")? {
+            if range.len() != 2 || range[0] > range[1] {
+                return Err(Error::Invalid(format!(
+                    "valid_range of {} is not a min, max pair: {:?}",
+                    ds.name(),
+                    range
+                )));
+            }
+            return Ok(Some(ValidRange {
+                min: range[0],
+                max: range[1],
+                action,
+            }));
+        }
+        let first = |values: Option<Vec<f64>>| values.and_then(|values| values.first().copied());
+        let min = first(read("valid_min")?);
+        let max = first(read("valid_max")?);
+        if min.is_none() && max.is_none() {
+            return Ok(None);
+        }
+        return Ok(Some(ValidRange {
+            min: min.unwrap_or(f64::NEG_INFINITY),
+            max: max.unwrap_or(f64::INFINITY),
+            action,
+        }));
+    }
+}
+
+/// Element types whose values can be checked against a range as `f64`.
+pub trait RangeValue: Copy + Into<f64> {
+    /// Lowest and highest values of the type.
+    const LOWEST: f64;
+    const HIGHEST: f64;
+    const IS_INTEGER: bool;
+
+    /// Converts `value`, saturating at the limits of the type (NaN becomes 0
+    /// for integers).
+    fn from_f64(value: f64) -> Self;
+}
+
+macro_rules! impl_range_value {
+    ($is_integer:expr => $($ty:ty),*) => {
+        $(
+            impl RangeValue for $ty {
+                const LOWEST: f64 = <$ty>::MIN as f64;
+                const HIGHEST: f64 = <$ty>::MAX as f64;
+                const IS_INTEGER: bool = $is_integer;
+
+                fn from_f64(value: f64) -> Self {
+                    return value as $ty;
+                }
+            }
+        )*
+    };
+}
+
+impl_range_value!(true => u8, i8, u16, i16, u32, i32);
+impl_range_value!(false => f32, f64);
+
+/// A source replacing the values outside a valid range as they are read,
+/// counting them. Without a range, values are passed through.
+pub struct RangeFiltered<'a, S> {
+    source: &'a S,
+    range: Option<ValidRange>,
+    nodata: Option<f64>,
+    affected: Cell<u64>,
+}
+
+impl<'a, S> RangeFiltered<'a, S> {
+    /// Wraps `source`; values equal to `nodata` are never out of range.
+    pub fn new(source: &'a S, range: Option<ValidRange>, nodata: Option<f64>) -> Self {
+        return RangeFiltered {
+            source,
+            range,
+            nodata,
+            affected: Cell::new(0),
+        };
+    }
+
+    /// Record of the pixels replaced so far in the output at `path`, if
+    /// there is a range.
+    pub fn finish(&self, path: &str) -> Option<RangeRecord> {
+        return self.range.map(|range| RangeRecord {
+            path: path.to_owned(),
+            min: range.min,
+            max: range.max,
+            action: range.action,
+            pixels: self.affected.get(),
+        });
+    }
+}
+
+impl<'a, T, S> HdfSource<T> for RangeFiltered<'a, S>
+where
+    T: RangeValue,
+    S: HdfSource<T>,
+{
+    fn len(&self, path: &str) -> Result<usize> {
+        return self.source.len(path);
+    }
+
+    fn read_range(&self, path: &str, start: usize, end: usize) -> Result<Vec<T>> {
+        let mut vals = self.source.read_range(path, start, end)?;
+        let range = match self.range {
+            Some(range) => range,
+            None => return Ok(vals),
+        };
+        let mut affected = 0;
+        for val in vals.iter_mut() {
+            let value: f64 = (*val).into();
+            if value.is_nan() || Some(value) == self.nodata {
+                continue;
+            }
+            if value < range.min || value > range.max {
+                affected += 1;
+                *val = match range.action {
+                    OutOfRange::Nodata => T::from_f64(self.nodata.unwrap_or(f64::NAN)),
+                    OutOfRange::Clamp => T::from_f64(value.max(range.min).min(range.max)),
+                };
+            }
+        }
+        self.affected.set(self.affected.get() + affected);
+        return Ok(vals);
+    }
+}
+
+/// Pixels of an output outside its valid range.
+#[derive(Debug, Clone, Serialize)]
+pub struct RangeRecord {
+    pub path: String,
+    pub min: f64,
+    pub max: f64,
+    pub action: OutOfRange,
+    pub pixels: u64,
+}
+
+static RECORDED: Mutex<Vec<RangeRecord>> = Mutex::new(Vec::new());
+
+/// Prints and records the pixels of an output outside its valid range.
+pub fn record(record: RangeRecord) {
+    let action = match record.action {
+        OutOfRange::Nodata => "set to nodata",
+        OutOfRange::Clamp => "clamped",
+    };
+    println!(
+        "{}: {} pixels outside [{}, {}] {}",
+        record.path, record.pixels, record.min, record.max, action
+    );
+    if let Ok(mut recorded) = RECORDED.lock() {
+        recorded.push(record);
+    }
+}
+
+/// Records kept so far, leaving none.
+pub fn take() -> Vec<RangeRecord> {
+    return match RECORDED.lock() {
+        Ok(mut recorded) => std::mem::take(&mut *recorded),
+        Err(_) => vec![],
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Values read from any path.
+    struct Values(Vec<f32>);
+
+    impl HdfSource<f32> for Values {
+        fn len(&self, _path: &str) -> Result<usize> {
+            return Ok(self.0.len());
+        }
+
+        fn read_range(&self, _path: &str, start: usize, end: usize) -> Result<Vec<f32>> {
+            return Ok(self.0[start..end].to_vec());
+        }
+    }
+
+    fn filter(range: ValidRange, nodata: Option<f64>, vals: Vec<f32>) -> (Vec<f32>, u64) {
+        let source = Values(vals);
+        let filtered = RangeFiltered::new(&source, Some(range), nodata);
+        let vals: Vec<f32> = filtered.read_range("", 0, source.0.len()).unwrap();
+        return (vals, filtered.finish("").unwrap().pixels);
+    }
+
+    #[test]
+    fn parses_fixed_ranges_and_attrs() {
+        assert_eq!(
+            RangeOption::parse("0,100"),
+            Ok(RangeOption::Fixed(0., 100.))
+        );
+        assert_eq!(
+            RangeOption::parse(" -1.5 , 2 "),
+            Ok(RangeOption::Fixed(-1.5, 2.))
+        );
+        assert_eq!(RangeOption::parse("3,3"), Ok(RangeOption::Fixed(3., 3.)));
+        assert_eq!(RangeOption::parse("attrs"), Ok(RangeOption::Attributes));
+        for text in ["100,0", "1", "1,2,3", "a,b", "nan,1", ""].iter() {
+            assert!(RangeOption::parse(text).is_err(), "{}", text);
+        }
+        assert_eq!(OutOfRange::parse("clamp"), Ok(OutOfRange::Clamp));
+        assert!(OutOfRange::parse("drop").is_err());
+    }
+
+    #[test]
+    fn bounds_are_inclusive() {
+        let range = ValidRange {
+            min: 0.,
+            max: 100.,
+            action: OutOfRange::Nodata,
+        };
+        let (vals, pixels) = filter(range, Some(-9999.), vec![-0.5, 0., 50., 100., 100.5]);
+        assert_eq!(vals, vec![-9999., 0., 50., 100., -9999.]);
+        assert_eq!(pixels, 2);
+    }
+
+    #[test]
+    fn open_bounds_only_check_the_other_end() {
+        let range = ValidRange {
+            min: f64::NEG_INFINITY,
+            max: 10.,
+            action: OutOfRange::Clamp,
+        };
+        let (vals, pixels) = filter(range, None, vec![f32::MIN, -1e30, 10., 11.]);
+        assert_eq!(vals, vec![f32::MIN, -1e30, 10., 10.]);
+        assert_eq!(pixels, 1);
+    }
+
+    #[test]
+    fn nan_and_nodata_are_never_out_of_range() {
+        let range = ValidRange {
+            min: 0.,
+            max: 1.,
+            action: OutOfRange::Nodata,
+        };
+        let (vals, pixels) = filter(range, Some(-9999.), vec![f32::NAN, -9999., 2.]);
+        assert!(vals[0].is_nan());
+        assert_eq!(vals[1..], [-9999., -9999.]);
+        assert_eq!(pixels, 1);
+        // Without nodata, values out of range become NaN.
+        let (vals, _) = filter(range, None, vec![2.]);
+        assert!(vals[0].is_nan());
+    }
+}
@@ -0,0 +1,347 @@
+//! HDF5 virtual datasets (VDS), read as inputs and written as `_rev`
+//! datasets (`--h5-virtual`).
+//!
+//! A virtual dataset is read like any other, HDF5 fetching its values from
+//! the source datasets it maps, possibly in other files. Sources that
+//! cannot be found read as the fill value, so they are warned about before
+//! the dataset is converted (see [`check_sources`]).
+//!
+//! With `--h5-virtual` the `_rev` dataset of a dataset is a VDS mapping its
+//! rows, in output order, to those of the source instead of a copy of
+//! them: one mapping for a grid whose rows are kept, one per row for a
+//! flipped one. Its values are then read through the source, which must
+//! stay where it was. Virtual datasets need HDF5 1.10; with older libraries
+//! no dataset is virtual and `--h5-virtual` is an error.
+
+use crate::error::{Error, Result};
+use crate::policy::{Decision, ExistsPolicy};
+use crate::warnings::{self, Warning};
+use crate::{attrs, hdf, history, meta};
+use std::path::Path;
+
+/// Whether `ds` is a virtual dataset.
+#[cfg(hdf5_1_10)]
+pub fn is_virtual(ds: &hdf5::Dataset) -> bool {
+    use hdf5_sys::h5d::{H5D_layout_t, H5Dget_create_plist};
+    use hdf5_sys::h5i::H5Idec_ref;
+    use hdf5_sys::h5p::H5Pget_layout;
+
+    let _silence = hdf5::silence_errors();
+    return hdf5::sync::sync(|| unsafe {
+        let dcpl = H5Dget_create_plist(ds.id());
+        if dcpl < 0 {
+            return false;
+        }
+        let is_virtual = H5Pget_layout(dcpl) == H5D_layout_t::H5D_VIRTUAL;
+        H5Idec_ref(dcpl);
+        return is_virtual;
+    });
+}
+
+#[cfg(not(hdf5_1_10))]
+pub fn is_virtual(_ds: &hdf5::Dataset) -> bool {
+    return false;
+}
+
+/// Source file and dataset names of each mapping of the virtual dataset
+/// `ds`; the file is `.` for the file of `ds` itself.
+#[cfg(hdf5_1_10)]
+pub fn sources(ds: &hdf5::Dataset) -> Vec<(String, String)> {
+    use hdf5_sys::h5::hid_t;
+    use hdf5_sys::h5d::H5Dget_create_plist;
+    use hdf5_sys::h5i::H5Idec_ref;
+    use hdf5_sys::h5p::{H5Pget_virtual_count, H5Pget_virtual_dsetname, H5Pget_virtual_filename};
+    use std::os::raw::c_char;
+
+    // Both getters return the length of the name when given no buffer.
+    unsafe fn name_of(
+        get: unsafe extern "C" fn(hid_t, usize, *mut c_char, usize) -> isize,
+        dcpl: hid_t,
+        index: usize,
+    ) -> String {
+        let len = get(dcpl, index, std::ptr::null_mut(), 0);
+        if len < 0 {
+            return String::new();
+        }
+        let mut buf = vec![0u8; len as usize + 1];
+        get(dcpl, index, buf.as_mut_ptr() as *mut c_char, buf.len());
+        buf.truncate(len as usize);
+        return String::from_utf8_lossy(&buf).into_owned();
+    }
+
+    let _silence = hdf5::silence_errors();
+    return hdf5::sync::sync(|| unsafe {
+        let dcpl = H5Dget_create_plist(ds.id());
+        if dcpl < 0 {
+            return vec![];
+        }
+        let mut count = 0;
+        let mut sources = vec![];
+        if H5Pget_virtual_count(dcpl, &mut count) >= 0 {
+            for index in 0..count {
+                sources.push((
+                    name_of(H5Pget_virtual_filename, dcpl, index),
+                    name_of(H5Pget_virtual_dsetname, dcpl, index),
+                ));
+            }
+        }
+        H5Idec_ref(dcpl);
+        sources.sort();
+        sources.dedup();
+        return sources;
+    });
+}
+
+#[cfg(not(hdf5_1_10))]
+pub fn sources(_ds: &hdf5::Dataset) -> Vec<(String, String)> {
+    return vec![];
+}
+
+/// Warns about the sources of the virtual dataset `ds` of `file` that
+/// cannot be found, as files next to `file` or in the working directory,
+/// or as datasets of `file` itself. Does nothing for other datasets.
+pub fn check_sources(file: &hdf5::File, ds: &hdf5::Dataset) -> Result<()> {
+    if !is_virtual(ds) {
+        return Ok(());
+    }
+    let file_name = file.filename();
+    let dir = Path::new(&file_name)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    for (source_file, source_ds) in sources(ds) {
+        let found = match source_file.as_str() {
+            "." => file.link_exists(&source_ds),
+            _ => Path::new(&source_file).exists() || dir.join(&source_file).exists(),
+        };
+        if !found {
+            warnings::warn(
+                Warning::MissingSource,
+                format!(
+                    "virtual dataset {} maps {} of {}, which cannot be found; its pixels read \
+                     as the fill value",
+                    ds.name(),
+                    source_ds,
+                    source_file
+                ),
+            )?;
+        }
+    }
+    return Ok(());
+}
+
+/// Creates the `_rev` dataset of `base_ds` of `file` in `out_file` as a
+/// virtual dataset of type `T` mapping the rows of `base_ds`, flipped if
+/// `flip`, with its fill value and attributes. Like
+/// [`crate::reverse::reverse_ds_rows`], `base_ds` holds at least
+/// `xsize * ysize` values, flat or shaped (ysize, xsize).
+#[cfg(hdf5_1_10)]
+pub fn create_virtual_rev<T: hdf5::H5Type + Default>(
+    file: &hdf5::File,
+    out_file: &hdf5::File,
+    base_ds: &str,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    policy: ExistsPolicy,
+) -> Result<()> {
+    use hdf5_sys::h5::hsize_t;
+    use hdf5_sys::h5d::H5Dcreate2;
+    use hdf5_sys::h5i::H5Idec_ref;
+    use hdf5_sys::h5p::{
+        H5Pcreate, H5Pset_create_intermediate_group, H5Pset_fill_value, H5Pset_virtual,
+        H5P_CLS_DATASET_CREATE, H5P_CLS_LINK_CREATE, H5P_DEFAULT,
+    };
+    use hdf5_sys::h5s::{H5S_seloper_t, H5Screate_simple, H5Sselect_hyperslab};
+    use std::ffi::CString;
+
+    if base_ds.ends_with("_rev") {
+        return Ok(());
+    }
+    let ds_name_rev = format!("{}_rev", base_ds);
+    if policy.decide_dataset(out_file, &ds_name_rev)? == Decision::Skip {
+        return Ok(());
+    }
+    let ds = file.dataset(base_ds)?;
+    if hdf::value_class(&ds)? != hdf::ValueClass::Numeric {
+        return Err(Error::Invalid(format!(
+            "dataset {} is not numeric and cannot be mapped by a virtual dataset",
+            base_ds
+        )));
+    }
+    let shape = ds.shape();
+    if shape.len() == 2 && (shape[0], shape[1]) != (ysize, xsize) {
+        return Err(Error::Invalid(format!(
+            "dataset {} is shaped {:?}, not like the {}x{} grid ({}, {})",
+            base_ds, shape, xsize, ysize, ysize, xsize
+        )));
+    }
+    if shape.len() > 2 {
+        return Err(Error::Invalid(format!(
+            "dataset {} is shaped {:?}; only flat and 2-d datasets can be mapped virtually",
+            base_ds, shape
+        )));
+    }
+    if ds.size() < xsize * ysize {
+        return Err(Error::Invalid(format!(
+            "dataset {} has {} values, fewer than the {}x{} template",
+            base_ds,
+            ds.size(),
+            xsize,
+            ysize
+        )));
+    }
+    if ds.size() > xsize * ysize {
+        warnings::warn(
+            Warning::SizeMismatch,
+            format!(
+                "dataset {} has {} values, more than the {}x{} template; the rest are ignored",
+                base_ds,
+                ds.size(),
+                xsize,
+                ysize
+            ),
+        )?;
+    }
+    if hdf::loses_precision::<T>(&ds)? {
+        warnings::warn(
+            Warning::PrecisionLoss,
+            format!(
+                "dataset {} is converted to {}, which cannot hold all its values",
+                base_ds,
+                std::any::type_name::<T>()
+            ),
+        )?;
+    }
+    let fill_value = match attrs::user_fill_value::<T>(&ds)? {
+        Some(fill_value) => fill_value,
+        None => {
+            warnings::warn(
+                Warning::NodataFallback,
+                format!(
+                    "dataset {} has no fill value, using the {} default",
+                    base_ds,
+                    std::any::type_name::<T>()
+                ),
+            )?;
+            T::default()
+        }
+    };
+
+    let dtype = hdf5::Datatype::from_type::<T>()?;
+    let invalid_name = |name: &str| Error::Invalid(format!("invalid dataset name '{}'", name));
+    let c_name = CString::new(ds_name_rev.as_str()).map_err(|_| invalid_name(&ds_name_rev))?;
+    let c_source = CString::new(base_ds).map_err(|_| invalid_name(base_ds))?;
+    // Sources in the file of the VDS itself are named `.`, so the mapping
+    // survives renaming it.
+    let source_file = match file.filename() == out_file.filename() {
+        true => ".".to_owned(),
+        false => file.filename(),
+    };
+    let c_source_file = CString::new(source_file.as_str())
+        .map_err(|_| Error::Invalid(format!("invalid file name '{}'", source_file)))?;
+    let dims = [(xsize * ysize) as hsize_t];
+    let source_dims: Vec<hsize_t> = shape.iter().map(|&len| len as hsize_t).collect();
+    // One mapping of all rows when kept, else each row to its mirror.
+    let mappings: Vec<(usize, usize, usize)> = match flip {
+        false => vec![(0, 0, ysize)],
+        true => (0..ysize).map(|yy| (yy, ysize - 1 - yy, 1)).collect(),
+    };
+    meta::forget(out_file, &ds_name_rev);
+    let ds_id = hdf5::sync::sync(|| unsafe {
+        let dcpl = H5Pcreate(*H5P_CLS_DATASET_CREATE);
+        H5Pset_fill_value(dcpl, dtype.id(), &fill_value as *const T as *const _);
+        let space = H5Screate_simple(1, dims.as_ptr(), dims.as_ptr());
+        let source_space = H5Screate_simple(
+            source_dims.len() as i32,
+            source_dims.as_ptr(),
+            source_dims.as_ptr(),
+        );
+        let mut status = 0;
+        for &(out_row, in_row, rows) in &mappings {
+            let start = [(out_row * xsize) as hsize_t];
+            let count = [(rows * xsize) as hsize_t];
+            status |= H5Sselect_hyperslab(
+                space,
+                H5S_seloper_t::H5S_SELECT_SET,
+                start.as_ptr(),
+                std::ptr::null(),
+                count.as_ptr(),
+                std::ptr::null(),
+            );
+            let (source_start, source_count) = match source_dims.len() {
+                2 => (
+                    vec![in_row as hsize_t, 0],
+                    vec![rows as hsize_t, xsize as hsize_t],
+                ),
+                _ => (
+                    vec![(in_row * xsize) as hsize_t],
+                    vec![(rows * xsize) as hsize_t],
+                ),
+            };
+            status |= H5Sselect_hyperslab(
+                source_space,
+                H5S_seloper_t::H5S_SELECT_SET,
+                source_start.as_ptr(),
+                std::ptr::null(),
+                source_count.as_ptr(),
+                std::ptr::null(),
+            );
+            status |= H5Pset_virtual(
+                dcpl,
+                space,
+                c_source_file.as_ptr(),
+                c_source.as_ptr(),
+                source_space,
+            );
+        }
+        let ds_id = match status < 0 {
+            true => -1,
+            false => {
+                let lcpl = H5Pcreate(*H5P_CLS_LINK_CREATE);
+                H5Pset_create_intermediate_group(lcpl, 1);
+                let ds_id = H5Dcreate2(
+                    out_file.id(),
+                    c_name.as_ptr(),
+                    dtype.id(),
+                    space,
+                    lcpl,
+                    dcpl,
+                    H5P_DEFAULT,
+                );
+                H5Idec_ref(lcpl);
+                ds_id
+            }
+        };
+        H5Idec_ref(source_space);
+        H5Idec_ref(space);
+        H5Idec_ref(dcpl);
+        if ds_id >= 0 {
+            H5Idec_ref(ds_id);
+        }
+        return ds_id;
+    });
+    if ds_id < 0 {
+        return Err(Error::Invalid(format!(
+            "could not create {} as a virtual dataset of {}",
+            ds_name_rev, base_ds
+        )));
+    }
+    history::note_created(out_file, &ds_name_rev);
+    let ds_out = out_file.dataset(&ds_name_rev)?;
+    attrs::copy_attributes::<T>(&ds, &ds_out, attrs::REV_ATTRIBUTES)?;
+    return Ok(());
+}
+
+#[cfg(not(hdf5_1_10))]
+pub fn create_virtual_rev<T: hdf5::H5Type + Default>(
+    _file: &hdf5::File,
+    _out_file: &hdf5::File,
+    base_ds: &str,
+    _grid: (usize, usize),
+    _flip: bool,
+    _policy: ExistsPolicy,
+) -> Result<()> {
+    return Err(Error::Invalid(format!(
+        "cannot map {} by a virtual dataset: they need HDF5 1.10 or later",
+        base_ds
+    )));
+}
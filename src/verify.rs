@@ -0,0 +1,346 @@
+//! Read-back verification of written outputs.
+//!
+//! Random windows of each output are read back through GDAL and compared
+//! with values recomputed from the original (unreversed) statistic datasets,
+//! catching row order or buffer layout errors that would otherwise go
+//! unnoticed. The `_rev` datasets can be checked the same way as they are
+//! written, before any raster is produced (see [`verify_reverse`]).
+
+#[cfg(feature = "gdal")]
+use crate::block::{BandOrder, RasterBlock};
+use crate::compound;
+use crate::error::Result;
+use crate::hdf;
+use crate::raster::{self, OutputFormat, RasterValue};
+use crate::reverse::{ChunkReader, HdfChunks};
+use crate::stats::{self, StatNames};
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// Number of random windows checked per output.
+    pub windows: usize,
+    /// Side of the square windows, in pixels.
+    pub window_size: usize,
+    /// Largest accepted absolute difference; 0 requires bit-exact values.
+    pub epsilon: f64,
+    pub seed: u64,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        return VerifyOptions {
+            windows: 16,
+            window_size: 64,
+            epsilon: 0f64,
+            seed: 0x5eed,
+        };
+    }
+}
+
+/// Random rows of each `_rev` dataset checked by `--verify-reverse`.
+pub const REVERSE_CHECK_ROWS: usize = 16;
+
+/// An output pixel that differs from its recomputed value.
+#[derive(Debug, Clone, Serialize)]
+pub struct Mismatch {
+    pub path: String,
+    pub x: usize,
+    pub y: usize,
+    pub expected: f64,
+    pub actual: f64,
+}
+
+/// xorshift64*, enough to spread windows over the grid reproducibly.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let val = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        return (val % bound.max(1) as u64) as usize;
+    }
+}
+
+/// Window as (x, y, width, height) in output pixel coordinates.
+type Window = (usize, usize, usize, usize);
+
+/// Reads `rows` full rows starting at output row `out_row` from the original
+/// dataset at `ds_path`, flipped into output order if `flip`.
+pub(crate) fn read_source_rows<T: hdf5::H5Type + Clone>(
+    file: &hdf5::File,
+    ds_path: &str,
+    field: Option<&str>,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    out_row: usize,
+    rows: usize,
+) -> Result<Vec<T>> {
+    let ds = file.dataset(ds_path)?;
+    let src_row = if flip {
+        ysize - out_row - rows
+    } else {
+        out_row
+    };
+    let (start, end) = (src_row * xsize, (src_row + rows) * xsize);
+    let vals = match compound::field_reader(&ds, field)? {
+        Some(reader) => reader.read_range(start, end)?,
+        None => HdfChunks(&ds).read_range(start, end)?,
+    };
+    if !flip {
+        return Ok(vals.to_vec());
+    }
+    return Ok(crate::reverse::rev_array(vals, rows, xsize).to_vec());
+}
+
+/// Statistic `stat` (`mean`, `sd` or `count`) of `group` in `rows` full
+/// rows starting at output row `out_row`, recomputed from its original
+/// datasets; `wide` as given by [`stats::is_wide`]. Counts are saturated at
+/// 255, as in count outputs.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn read_stat_rows(
+    file: &hdf5::File,
+    group: &str,
+    stat_names: &StatNames,
+    field: Option<&str>,
+    stat: &str,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    (min_count, wide): (u32, bool),
+    out_row: usize,
+    rows: usize,
+) -> Result<Vec<f64>> {
+    let count = read_source_rows::<u32>(
+        file,
+        &hdf::join_path(group, &stat_names.count),
+        field,
+        (xsize, ysize),
+        flip,
+        out_row,
+        rows,
+    )?;
+    if stat == "count" {
+        return Ok(count
+            .iter()
+            .map(|&count| count.min(u8::MAX as u32) as f64)
+            .collect());
+    }
+    let (mean, sd) = stats::mean_sd_with(stat_names, wide, min_count, &count, |name| {
+        let path = hdf::join_path(group, name);
+        return read_source_rows::<f64>(file, &path, field, (xsize, ysize), flip, out_row, rows);
+    })?;
+    let vals = if stat == "sd" { sd } else { mean };
+    return Ok(vals.iter().map(|&val| val as f64).collect());
+}
+
+/// Count, mean and sd of `group` in `rows` full rows starting at output
+/// row `out_row`, recomputed from its original datasets like
+/// [`read_stat_rows`], with the counts unsaturated.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn read_group_rows(
+    file: &hdf5::File,
+    group: &str,
+    stat_names: &StatNames,
+    field: Option<&str>,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    (min_count, wide): (u32, bool),
+    out_row: usize,
+    rows: usize,
+) -> Result<(Vec<u32>, Vec<f32>, Vec<f32>)> {
+    let count = read_source_rows::<u32>(
+        file,
+        &hdf::join_path(group, &stat_names.count),
+        field,
+        (xsize, ysize),
+        flip,
+        out_row,
+        rows,
+    )?;
+    let (mean, sd) = stats::mean_sd_with(stat_names, wide, min_count, &count, |name| {
+        let path = hdf::join_path(group, name);
+        return read_source_rows::<f64>(file, &path, field, (xsize, ysize), flip, out_row, rows);
+    })?;
+    return Ok((count, mean, sd));
+}
+
+/// Crops the `window` out of full rows of width `xsize`.
+fn crop<T: Copy>(rows: &[T], xsize: usize, window: Window) -> Vec<T> {
+    let (x, _, width, height) = window;
+    let mut vals = Vec::with_capacity(width * height);
+    for row in 0..height {
+        vals.extend_from_slice(&rows[row * xsize + x..row * xsize + x + width]);
+    }
+    return vals;
+}
+
+/// Values of `window` of the output at `out_path`, read through GDAL.
+#[cfg(feature = "gdal")]
+fn read_output(
+    format: OutputFormat,
+    out_path: &str,
+    name: &str,
+    window: Window,
+) -> Result<Vec<f64>> {
+    let gdal_path = match format {
+        #[cfg(feature = "netcdf-sink")]
+        OutputFormat::Netcdf => format!("NETCDF:\"{}\":{}", out_path, name),
+        #[cfg(feature = "zarr-sink")]
+        OutputFormat::Zarr => format!("ZARR:\"{}\":/{}", out_path, name),
+        _ => {
+            let _ = name;
+            out_path.to_owned()
+        }
+    };
+    let (x, y, width, height) = window;
+    let rast = gdal::Dataset::open(std::path::Path::new(&gdal_path))?;
+    let buffer = rast.rasterband(format.band() as isize)?.read_as::<f64>(
+        (x as isize, y as isize),
+        (width, height),
+        (width, height),
+        None,
+    )?;
+    let order = match format {
+        OutputFormat::Gdal(options) => options.band_order,
+        #[allow(unreachable_patterns)]
+        _ => BandOrder::RowMajor,
+    };
+    return Ok(RasterBlock::from_ordered(buffer.data, width, height, order)?.into_rows());
+}
+
+#[cfg(not(feature = "gdal"))]
+fn read_output(_: OutputFormat, _: &str, _: &str, _: Window) -> Result<Vec<f64>> {
+    return Err(crate::error::Error::Invalid(
+        "--verify reads outputs back through GDAL and needs the gdal feature".to_owned(),
+    ));
+}
+
+fn same(expected: f64, actual: f64, epsilon: f64) -> bool {
+    if expected.is_nan() || actual.is_nan() {
+        return expected.is_nan() && actual.is_nan();
+    }
+    return (expected - actual).abs() <= epsilon;
+}
+
+/// Checks that `<ds_path>_rev` of `derived` holds the rows of `ds_path` of
+/// `file` in output order, reversed if `flip`: the first, middle and last
+/// rows, where off-by-one errors show, and `rows` random ones, compared as
+/// `T`, the type the reversed dataset was written as.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_reverse<T: RasterValue>(
+    file: &hdf5::File,
+    derived: &hdf5::File,
+    ds_path: &str,
+    field: Option<&str>,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    rows: usize,
+    seed: u64,
+) -> Result<Vec<Mismatch>> {
+    let mut mismatches = vec![];
+    if xsize == 0 || ysize == 0 {
+        return Ok(mismatches);
+    }
+    let rev_path = format!("{}_rev", ds_path);
+    let rev = derived.dataset(&rev_path)?;
+    let mut rng = Rng(seed | 1);
+    let mut out_rows = vec![0, ysize / 2, ysize - 1];
+    out_rows.extend((0..rows).map(|_| rng.below(ysize)));
+    out_rows.sort_unstable();
+    out_rows.dedup();
+    for y in out_rows {
+        let expected = read_source_rows::<T>(file, ds_path, field, (xsize, ysize), flip, y, 1)?;
+        let actual: ndarray::Array1<T> = HdfChunks(&rev).read_range(y * xsize, (y + 1) * xsize)?;
+        for (x, (&expected, &actual)) in expected.iter().zip(actual.iter()).enumerate() {
+            let (expected, actual): (f64, f64) = (expected.into(), actual.into());
+            if !same(expected, actual, 0f64) {
+                mismatches.push(Mismatch {
+                    path: rev_path.clone(),
+                    x,
+                    y,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    return Ok(mismatches);
+}
+
+/// Checks random windows of the mean, sd and count outputs of `group`
+/// against values recomputed from its `sum`, `sumsq` and `count` datasets.
+pub fn verify_group(
+    file: &hdf5::File,
+    group: &str,
+    stat_names: &StatNames,
+    field: Option<&str>,
+    format: OutputFormat,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    min_count: u32,
+    options: &VerifyOptions,
+) -> Result<Vec<Mismatch>> {
+    let mut mismatches = vec![];
+    if xsize == 0 || ysize == 0 {
+        return Ok(mismatches);
+    }
+    let wide = field.is_none() && stats::is_wide(file, group, stat_names, "")?;
+    let mut rng = Rng(options.seed | 1);
+    let width = options.window_size.min(xsize);
+    let height = options.window_size.min(ysize);
+
+    for _ in 0..options.windows {
+        let window = (
+            rng.below(xsize - width + 1),
+            rng.below(ysize - height + 1),
+            width,
+            height,
+        );
+        let (_, y, _, _) = window;
+
+        let read = |path: &str| -> Result<Vec<f64>> {
+            let rows = read_source_rows::<f64>(file, path, field, (xsize, ysize), flip, y, height)?;
+            return Ok(crop(&rows, xsize, window));
+        };
+        let count_path = hdf::join_path(group, &stat_names.count);
+        let count_rows =
+            read_source_rows::<u32>(file, &count_path, field, (xsize, ysize), flip, y, height)?;
+        let count = crop(&count_rows, xsize, window);
+        // Count outputs are bytes, saturated at 255.
+        let saturated: Vec<u8> = count
+            .iter()
+            .map(|&val| val.min(u8::MAX as u32) as u8)
+            .collect();
+        // As computed from the sums and counts written back.
+        let (mean, sd) = stats::mean_sd_with(stat_names, wide, min_count, &count, |name| {
+            return read(&hdf::join_path(group, name));
+        })?;
+
+        let expected: [(&str, Vec<f64>); 3] = [
+            ("mean", mean.iter().map(|&val| val as f64).collect()),
+            ("sd", sd.iter().map(|&val| val as f64).collect()),
+            ("count", saturated.iter().map(|&val| val as f64).collect()),
+        ];
+        for (stat, expected) in expected.iter() {
+            let out_path = raster::output_path(group, stat, format.extension());
+            if !std::path::Path::new(&out_path).exists() {
+                continue;
+            }
+            let actual = read_output(format, &out_path, stat, window)?;
+            for (ii, (&expected, &actual)) in expected.iter().zip(actual.iter()).enumerate() {
+                if !same(expected, actual, options.epsilon) {
+                    mismatches.push(Mismatch {
+                        path: out_path.clone(),
+                        x: window.0 + ii % width,
+                        y: window.1 + ii / width,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+    return Ok(mismatches);
+}
@@ -0,0 +1,119 @@
+//! Warnings raised during a run, by kind.
+//!
+//! Warnings are printed, added to the live status and kept for the report.
+//! In strict mode the data quality kinds (see [`Warning::is_data_quality`])
+//! become errors instead, so pipelines can fail on them.
+
+use crate::error::{Error, Result};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Warning {
+    /// A dataset holds more values than the template grid; the extra values
+    /// are ignored.
+    SizeMismatch,
+    /// A dataset defines no fill value, so the default of its type is used.
+    NodataFallback,
+    /// Values are converted to a type that cannot hold all of them exactly.
+    PrecisionLoss,
+    /// An output differs from the values recomputed from its source.
+    Verification,
+    /// A dataset or group is skipped.
+    Skipped,
+    /// The run could not be set up as asked (threads, GDAL options, Ctrl-C
+    /// handler, status server); outputs are not affected.
+    Environment,
+    /// A read or write failed and was retried (see [`crate::retry`]).
+    Retried,
+    /// A virtual dataset maps a source that cannot be found, read as the
+    /// fill value (see [`crate::vds`]).
+    MissingSource,
+    /// Means or sds of pixels with observations came out NaN or infinite
+    /// (see [`crate::nan_policy`]).
+    NonFinite,
+    /// A dimension scale is not regularly spaced (see
+    /// [`crate::dim_scales`]).
+    IrregularScale,
+}
+
+impl Warning {
+    pub fn name(self) -> &'static str {
+        return match self {
+            Warning::SizeMismatch => "size-mismatch",
+            Warning::NodataFallback => "nodata-fallback",
+            Warning::PrecisionLoss => "precision-loss",
+            Warning::Verification => "verification",
+            Warning::Skipped => "skipped",
+            Warning::Environment => "environment",
+            Warning::Retried => "retried",
+            Warning::MissingSource => "missing-source",
+            Warning::NonFinite => "non-finite",
+            Warning::IrregularScale => "irregular-scale",
+        };
+    }
+
+    /// Whether the warning is about the values written, and so an error in
+    /// strict mode.
+    pub fn is_data_quality(self) -> bool {
+        return match self {
+            Warning::SizeMismatch
+            | Warning::NodataFallback
+            | Warning::PrecisionLoss
+            | Warning::MissingSource
+            | Warning::IrregularScale => true,
+            Warning::Verification
+            | Warning::Skipped
+            | Warning::Environment
+            | Warning::Retried
+            | Warning::NonFinite => false,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WarningRecord {
+    pub kind: Warning,
+    pub message: String,
+}
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+static RECORDED: Mutex<Vec<WarningRecord>> = Mutex::new(Vec::new());
+
+/// Makes data quality warnings errors for the rest of the process.
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::SeqCst);
+}
+
+pub fn is_strict() -> bool {
+    return STRICT.load(Ordering::SeqCst);
+}
+
+/// Prints and records a warning of `kind`.
+pub fn record(kind: Warning, message: String) {
+    eprintln!("warning[{}]: {}", kind.name(), message);
+    crate::status::add_error(format!("{}: {}", kind.name(), message));
+    if let Ok(mut recorded) = RECORDED.lock() {
+        recorded.push(WarningRecord { kind, message });
+    }
+}
+
+/// Records a warning of `kind`, or returns it as `Error::Strict` if strict
+/// mode makes it an error.
+pub fn warn(kind: Warning, message: String) -> Result<()> {
+    if is_strict() && kind.is_data_quality() {
+        return Err(Error::Strict(kind, message));
+    }
+    record(kind, message);
+    return Ok(());
+}
+
+/// Warnings recorded so far, leaving none.
+pub fn take() -> Vec<WarningRecord> {
+    return match RECORDED.lock() {
+        Ok(mut recorded) => std::mem::take(&mut *recorded),
+        Err(_) => vec![],
+    };
+}
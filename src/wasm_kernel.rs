@@ -0,0 +1,225 @@
+//! [`StatisticKernel`]s loaded from WebAssembly modules, so per-pixel
+//! formulas can be written in any language that compiles to WASM and run
+//! without rebuilding the tool.
+//!
+//! A module has no imports and exports:
+//!
+//! - `memory`;
+//! - `alloc(size: i32) -> i32`, returning the address of `size` free bytes
+//!   aligned to 8;
+//! - `output_names() -> i32`, the address of a NUL-terminated,
+//!   comma-separated list of the output names;
+//! - optionally `input_names() -> i32`, the same for the group datasets read
+//!   as inputs;
+//! - `compute(len, count, mean, sd, inputs, outputs: i32)`, called for each
+//!   chunk of `len` pixels with the addresses of `len` `u32` counts, `len`
+//!   `f32` means and sds, an array of the `i32` addresses of the `len`
+//!   `f64` values of each input, and an array of the `i32` addresses of the
+//!   `len` `f32` values of each output, which it fills.
+//!
+//! All values are little-endian. Buffers are allocated once per instance
+//! and reused while chunks fit; modules are instantiated once per group
+//! written at once.
+
+use crate::error::{Error, Result};
+use crate::kernel::{ChunkInputs, StatisticKernel};
+use std::sync::Mutex;
+use wasmtime::{Engine, Linker, Memory, Module, Store, TypedFunc};
+
+/// Signature of the `compute` export.
+type ComputeFn = TypedFunc<(i32, i32, i32, i32, i32, i32), ()>;
+
+/// One instance of the module, with the buffer of its last call.
+struct WasmInstance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    compute: ComputeFn,
+    /// Address and size of the buffer allocated in the module.
+    buffer: (usize, usize),
+}
+
+/// A [`StatisticKernel`] running the `compute` export of a WASM module.
+pub struct WasmKernel {
+    path: String,
+    engine: Engine,
+    module: Module,
+    outputs: Vec<String>,
+    inputs: Vec<String>,
+    /// Instances not running a chunk.
+    idle: Mutex<Vec<WasmInstance>>,
+}
+
+impl WasmKernel {
+    /// Compiles the module at `path` and reads its input and output names.
+    pub fn load(path: &str) -> Result<WasmKernel> {
+        let engine = Engine::default();
+        let module = Module::from_file(&engine, path).map_err(|err| wasm_error(path, err))?;
+        let mut kernel = WasmKernel {
+            path: path.to_owned(),
+            engine,
+            module,
+            outputs: vec![],
+            inputs: vec![],
+            idle: Mutex::new(vec![]),
+        };
+        let (instance, names) = kernel.instantiate()?;
+        kernel.outputs = names.0;
+        kernel.inputs = names.1;
+        if kernel.outputs.is_empty() {
+            return Err(Error::Invalid(format!("{} names no outputs", path)));
+        }
+        kernel.idle.lock().unwrap().push(instance);
+        return Ok(kernel);
+    }
+
+    /// A new instance of the module, and its output and input names.
+    fn instantiate(&self) -> Result<(WasmInstance, (Vec<String>, Vec<String>))> {
+        return self
+            .try_instantiate()
+            .map_err(|err| wasm_error(&self.path, err));
+    }
+
+    fn try_instantiate(&self) -> wasmtime::Result<(WasmInstance, (Vec<String>, Vec<String>))> {
+        let mut store = Store::new(&self.engine, ());
+        let instance = Linker::new(&self.engine).instantiate(&mut store, &self.module)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("no memory export"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let compute = instance.get_typed_func(&mut store, "compute")?;
+        let output_names = instance.get_typed_func::<(), i32>(&mut store, "output_names")?;
+        let address = output_names.call(&mut store, ())?;
+        let outputs = read_names(&memory, &store, address)?;
+        let inputs = match instance.get_func(&mut store, "input_names") {
+            Some(func) => {
+                let address = func.typed::<(), i32>(&store)?.call(&mut store, ())?;
+                read_names(&memory, &store, address)?
+            }
+            None => vec![],
+        };
+        let instance = WasmInstance {
+            store,
+            memory,
+            alloc,
+            compute,
+            buffer: (0, 0),
+        };
+        return Ok((instance, (outputs, inputs)));
+    }
+}
+
+impl StatisticKernel for WasmKernel {
+    fn outputs(&self) -> Vec<String> {
+        return self.outputs.clone();
+    }
+
+    fn inputs(&self) -> Vec<String> {
+        return self.inputs.clone();
+    }
+
+    fn compute(&self, chunk: &ChunkInputs) -> Result<Vec<Vec<f32>>> {
+        let idle = self.idle.lock().unwrap().pop();
+        let mut instance = match idle {
+            Some(instance) => instance,
+            None => self.instantiate()?.0,
+        };
+        let outputs = instance
+            .run(chunk, self.outputs.len())
+            .map_err(|err| wasm_error(&self.path, err))?;
+        self.idle.lock().unwrap().push(instance);
+        return Ok(outputs);
+    }
+}
+
+impl WasmInstance {
+    /// Copies `chunk` into the module, calls `compute` and copies its
+    /// `n_outputs` outputs back.
+    fn run(&mut self, chunk: &ChunkInputs, n_outputs: usize) -> wasmtime::Result<Vec<Vec<f32>>> {
+        let len = chunk.len();
+        let n_inputs = chunk.datasets.len();
+        // Layout of the buffer: the f64 inputs first, so they stay aligned,
+        // then the counts, means, sds, outputs and address arrays.
+        let inputs_at: Vec<usize> = (0..n_inputs).map(|ii| ii * len * 8).collect();
+        let count_at = n_inputs * len * 8;
+        let mean_at = count_at + len * 4;
+        let sd_at = mean_at + len * 4;
+        let outputs_at: Vec<usize> = (0..n_outputs)
+            .map(|ii| sd_at + (ii + 1) * len * 4)
+            .collect();
+        let input_list_at = sd_at + (n_outputs + 1) * len * 4;
+        let output_list_at = input_list_at + n_inputs * 4;
+        let size = output_list_at + n_outputs * 4;
+
+        if self.buffer.1 < size {
+            let address = self.alloc.call(&mut self.store, to_i32(size)?)?;
+            self.buffer = (address as u32 as usize, size);
+        }
+        let base = self.buffer.0;
+        let mut bytes = vec![];
+        for vals in chunk.datasets.iter() {
+            bytes.extend(vals.iter().flat_map(|val| val.to_le_bytes()));
+        }
+        bytes.extend(chunk.count.iter().flat_map(|val| val.to_le_bytes()));
+        bytes.extend(chunk.mean.iter().flat_map(|val| val.to_le_bytes()));
+        bytes.extend(chunk.sd.iter().flat_map(|val| val.to_le_bytes()));
+        bytes.resize(input_list_at, 0);
+        for at in inputs_at.iter().chain(outputs_at.iter()) {
+            bytes.extend(to_i32(base + at)?.to_le_bytes());
+        }
+        self.memory.write(&mut self.store, base, &bytes)?;
+
+        let args = (
+            to_i32(len)?,
+            to_i32(base + count_at)?,
+            to_i32(base + mean_at)?,
+            to_i32(base + sd_at)?,
+            to_i32(base + input_list_at)?,
+            to_i32(base + output_list_at)?,
+        );
+        self.compute.call(&mut self.store, args)?;
+
+        let mut outputs = vec![];
+        let mut buf = vec![0u8; len * 4];
+        for at in outputs_at.iter() {
+            self.memory.read(&self.store, base + at, &mut buf)?;
+            outputs.push(
+                buf.chunks_exact(4)
+                    .map(|val| f32::from_le_bytes([val[0], val[1], val[2], val[3]]))
+                    .collect(),
+            );
+        }
+        return Ok(outputs);
+    }
+}
+
+/// Reads the comma-separated names in the NUL-terminated string at
+/// `address` of `memory`.
+fn read_names(memory: &Memory, store: &Store<()>, address: i32) -> wasmtime::Result<Vec<String>> {
+    let data = &memory.data(store)[address as u32 as usize..];
+    let end = data
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(|| wasmtime::Error::msg("unterminated names"))?;
+    let text = std::str::from_utf8(&data[..end])?;
+    return Ok(text
+        .split(',')
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect());
+}
+
+/// Address or size `val` as a WASM `i32`; memories are at most 4 GB.
+fn to_i32(val: usize) -> wasmtime::Result<i32> {
+    if val > u32::MAX as usize {
+        return Err(wasmtime::Error::msg(format!(
+            "{} bytes do not fit in WASM memory",
+            val
+        )));
+    }
+    return Ok(val as u32 as i32);
+}
+
+fn wasm_error(path: &str, err: wasmtime::Error) -> Error {
+    return Error::Wasm(format!("{}: {:#}", path, err));
+}
@@ -0,0 +1,414 @@
+//! Ingestion service converting the HDF5 files that arrive in a directory
+//! (`watch`).
+//!
+//! The directory is polled every interval for files whose names match a
+//! pattern (`*` and `?` wildcards). A file is queued once its size and
+//! modification time hold still between two polls, so files still being
+//! copied in are left until complete, and files are converted one at a
+//! time in the order they were queued, each in a process of its own with
+//! the flags of a profile, like the jobs of a batch (see [`crate::batch`]),
+//! to `<outdir>/<file stem>`, with its log and report there. Files
+//! converted, or that failed, are recorded with their size and modification
+//! time in [`STATE_NAME`] in the directory, so they are not converted again
+//! after a restart unless they change.
+
+use crate::batch::{self, Job, Manifest};
+use crate::error::{exit_code, Result};
+use crate::interrupt;
+use crate::warnings::{self, Warning};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Record of the files converted, in the directory watched.
+pub const STATE_NAME: &str = ".read_hdf_as_raster-watch.json";
+
+/// Longest sleep between two checks for Ctrl-C while waiting for a poll.
+const SLEEP_STEP: Duration = Duration::from_millis(200);
+
+/// What to watch and how to convert the files found.
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub dir: PathBuf,
+    pub pattern: String,
+    /// Directory of the output directories of the files.
+    pub outdir: PathBuf,
+    /// Profiles the files may be converted with.
+    pub manifest: Manifest,
+    pub profile: Option<String>,
+    pub interval: Duration,
+    /// Stop once the files found are converted, instead of waiting for
+    /// more.
+    pub once: bool,
+    /// Worker threads of each conversion, unless its profile sets them.
+    pub threads: usize,
+}
+
+/// Size and modification time of a file, telling whether it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Stamp {
+    size: u64,
+    modified_ms: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Stamp> {
+        let meta = std::fs::metadata(path).ok()?;
+        if !meta.is_file() {
+            return None;
+        }
+        let modified_ms = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_millis() as u64);
+        return Some(Stamp {
+            size: meta.len(),
+            modified_ms,
+        });
+    }
+}
+
+/// A file converted, or that failed to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Converted {
+    #[serde(flatten)]
+    stamp: Stamp,
+    exit_code: i32,
+    report: String,
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any characters
+/// and `?` for one.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Position after the last `*` and the character of the name it was
+    // matched up to, to backtrack to.
+    let (mut pp, mut nn) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while nn < name.len() {
+        match pattern.get(pp) {
+            Some('*') => {
+                star = Some((pp + 1, nn));
+                pp += 1;
+            }
+            Some(&ch) if ch == '?' || ch == name[nn] => {
+                pp += 1;
+                nn += 1;
+            }
+            _ => match star {
+                Some((after, from)) => {
+                    pp = after;
+                    nn = from + 1;
+                    star = Some((after, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    return pattern[pp..].iter().all(|&ch| ch == '*');
+}
+
+/// Queues the `candidates` found by a poll that are not converted yet and
+/// held still since the last poll, which found those in `growing`. Returns
+/// those left to check at the next poll.
+fn enqueue_stable(
+    candidates: Vec<(String, Stamp)>,
+    state: &BTreeMap<String, Converted>,
+    growing: &BTreeMap<String, Stamp>,
+    queue: &mut VecDeque<String>,
+) -> BTreeMap<String, Stamp> {
+    let mut seen = BTreeMap::new();
+    for (name, stamp) in candidates {
+        let done = state.get(&name).map_or(false, |done| done.stamp == stamp);
+        if done || queue.contains(&name) {
+            continue;
+        }
+        match growing.get(&name) {
+            Some(last) if *last == stamp => queue.push_back(name),
+            _ => {
+                seen.insert(name, stamp);
+            }
+        }
+    }
+    return seen;
+}
+
+impl Watch {
+    fn state_path(&self) -> PathBuf {
+        return self.dir.join(STATE_NAME);
+    }
+
+    /// Files converted at earlier runs. A state that cannot be read is set
+    /// aside with a warning, the files being converted again.
+    fn load_state(&self) -> BTreeMap<String, Converted> {
+        let path = self.state_path();
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return BTreeMap::new(),
+        };
+        return match serde_json::from_str(&text) {
+            Ok(state) => state,
+            Err(err) => {
+                let aside = path.with_extension("json.invalid");
+                let _ = std::fs::rename(&path, &aside);
+                warnings::record(
+                    Warning::Environment,
+                    format!(
+                        "{} is not valid ({}), moved to {}; the files found are converted again",
+                        path.display(),
+                        err,
+                        aside.display()
+                    ),
+                );
+                BTreeMap::new()
+            }
+        };
+    }
+
+    /// Saves `state` through a temporary file, so a crash while writing
+    /// leaves the previous state.
+    fn save_state(&self, state: &BTreeMap<String, Converted>) -> Result<()> {
+        let path = self.state_path();
+        let tmp = path.with_extension("json.tmp");
+        let file = std::fs::File::create(&tmp)?;
+        serde_json::to_writer_pretty(&file, state)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &path)?;
+        return Ok(());
+    }
+
+    /// Files of the directory matching the pattern, by name, oldest first.
+    fn candidates(&self) -> Result<Vec<(String, Stamp)>> {
+        let mut found = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name == STATE_NAME || !matches(&self.pattern, &name) {
+                continue;
+            }
+            if let Some(stamp) = Stamp::of(&entry.path()) {
+                found.push((name, stamp));
+            }
+        }
+        found.sort_by_key(|(name, stamp)| (stamp.modified_ms, name.clone()));
+        return Ok(found);
+    }
+
+    /// Job converting the file named `name`.
+    fn job(&self, name: &str) -> Job {
+        let stem = Path::new(name)
+            .file_stem()
+            .map_or(name.to_owned(), |stem| stem.to_string_lossy().into_owned());
+        return Job {
+            input: self.dir.join(name).to_string_lossy().into_owned(),
+            profile: self.profile.clone(),
+            outdir: Some(self.outdir.join(stem).to_string_lossy().into_owned()),
+        };
+    }
+
+    /// Converts the files arriving in the directory with the executable at
+    /// `exe` until Ctrl-C, or with `once` until those found are converted.
+    /// Returns the files converted and those that failed.
+    pub fn run(&self, exe: &Path) -> Result<(usize, usize)> {
+        let mut state = self.load_state();
+        // Files seen changing at the last poll, and files ready.
+        let mut growing: BTreeMap<String, Stamp> = BTreeMap::new();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        let (mut converted, mut failed) = (0, 0);
+        println!(
+            "Watching {} for {} every {:.1} s",
+            self.dir.display(),
+            self.pattern,
+            self.interval.as_secs_f64()
+        );
+        while !interrupt::is_interrupted() {
+            growing = enqueue_stable(self.candidates()?, &state, &growing, &mut queue);
+            while let Some(name) = queue.pop_front() {
+                if interrupt::is_interrupted() {
+                    break;
+                }
+                // Removed since queued.
+                if Stamp::of(&self.dir.join(&name)).is_none() {
+                    continue;
+                }
+                let job = self.job(&name);
+                println!("Converting {}", job.input);
+                let record = batch::run_job(&self.manifest, &job, exe, self.threads);
+                match record.exit_code {
+                    exit_code::OK => converted += 1,
+                    _ => failed += 1,
+                }
+                println!(
+                    "Finished {} with exit code {} in {:.1} s, log in {}",
+                    job.input, record.exit_code, record.seconds, record.log
+                );
+                // Interrupted conversions are tried again at the next start.
+                if record.exit_code == exit_code::INTERRUPTED {
+                    break;
+                }
+                // Stamped once converted, as the derived datasets written
+                // into the file change it.
+                let stamp = match Stamp::of(&self.dir.join(&name)) {
+                    Some(stamp) => stamp,
+                    None => continue,
+                };
+                state.insert(
+                    name,
+                    Converted {
+                        stamp,
+                        exit_code: record.exit_code,
+                        report: job.report_path().to_string_lossy().into_owned(),
+                    },
+                );
+                self.save_state(&state)?;
+            }
+            if self.once && growing.is_empty() && queue.is_empty() {
+                break;
+            }
+            let mut waited = Duration::from_secs(0);
+            while waited < self.interval && !interrupt::is_interrupted() {
+                let step = SLEEP_STEP.min(self.interval - waited);
+                std::thread::sleep(step);
+                waited += step;
+            }
+        }
+        return Ok((converted, failed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_granule_names() {
+        assert!(matches("*.h5", "GEDI04_A_2021_001.h5"));
+        assert!(matches("GEDI04_?_*.h5", "GEDI04_A_2021_001.h5"));
+        assert!(matches("*_001*", "GEDI04_A_2021_001.h5"));
+        assert!(!matches("*.h5", "GEDI04_A_2021_001.h5.part"));
+        assert!(!matches("GEDI02_*", "GEDI04_A_2021_001.h5"));
+        assert!(matches("*", ""));
+        assert!(!matches("?", ""));
+    }
+
+    fn stamp(size: u64, modified_ms: u64) -> Stamp {
+        return Stamp { size, modified_ms };
+    }
+
+    #[test]
+    fn files_are_queued_once_stable_for_two_polls() {
+        let state = BTreeMap::new();
+        let mut queue = VecDeque::new();
+        let growing = enqueue_stable(
+            vec![("a.h5".to_owned(), stamp(10, 1))],
+            &state,
+            &BTreeMap::new(),
+            &mut queue,
+        );
+        assert!(queue.is_empty());
+        // Still being copied in.
+        let growing = enqueue_stable(
+            vec![("a.h5".to_owned(), stamp(20, 2))],
+            &state,
+            &growing,
+            &mut queue,
+        );
+        assert!(queue.is_empty());
+        let growing = enqueue_stable(
+            vec![
+                ("a.h5".to_owned(), stamp(20, 2)),
+                ("b.h5".to_owned(), stamp(5, 3)),
+            ],
+            &state,
+            &growing,
+            &mut queue,
+        );
+        assert_eq!(queue, vec!["a.h5".to_owned()]);
+        assert_eq!(growing.keys().collect::<Vec<_>>(), vec!["b.h5"]);
+        // Queued files are not queued twice.
+        let growing = enqueue_stable(
+            vec![
+                ("a.h5".to_owned(), stamp(20, 2)),
+                ("b.h5".to_owned(), stamp(5, 3)),
+            ],
+            &state,
+            &growing,
+            &mut queue,
+        );
+        assert_eq!(queue, vec!["a.h5".to_owned(), "b.h5".to_owned()]);
+        assert!(growing.is_empty());
+    }
+
+    #[test]
+    fn converted_files_are_skipped_unless_changed() {
+        let mut state = BTreeMap::new();
+        state.insert(
+            "a.h5".to_owned(),
+            Converted {
+                stamp: stamp(20, 2),
+                exit_code: exit_code::OK,
+                report: "out/a/report.json".to_owned(),
+            },
+        );
+        let mut queue = VecDeque::new();
+        let mut growing = BTreeMap::new();
+        for _ in 0..2 {
+            growing = enqueue_stable(
+                vec![("a.h5".to_owned(), stamp(20, 2))],
+                &state,
+                &growing,
+                &mut queue,
+            );
+        }
+        assert!(queue.is_empty());
+        assert!(growing.is_empty());
+        // Replaced by a new version since converted.
+        for _ in 0..2 {
+            growing = enqueue_stable(
+                vec![("a.h5".to_owned(), stamp(30, 4))],
+                &state,
+                &growing,
+                &mut queue,
+            );
+        }
+        assert_eq!(queue, vec!["a.h5".to_owned()]);
+    }
+
+    #[test]
+    fn invalid_state_is_set_aside_and_state_saved_whole() {
+        let dir = std::env::temp_dir().join(format!("h2r-watch-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let watch = Watch {
+            dir: dir.clone(),
+            pattern: "*.h5".to_owned(),
+            outdir: dir.join("out"),
+            manifest: Manifest::default(),
+            profile: None,
+            interval: Duration::from_secs(1),
+            once: true,
+            threads: 1,
+        };
+        std::fs::write(watch.state_path(), "{ truncated").unwrap();
+        assert!(watch.load_state().is_empty());
+        assert!(!watch.state_path().exists());
+
+        let mut state = BTreeMap::new();
+        state.insert(
+            "a.h5".to_owned(),
+            Converted {
+                stamp: stamp(20, 2),
+                exit_code: exit_code::OK,
+                report: "out/a/report.json".to_owned(),
+            },
+        );
+        watch.save_state(&state).unwrap();
+        let loaded = watch.load_state();
+        assert_eq!(loaded.keys().collect::<Vec<_>>(), vec!["a.h5"]);
+        assert_eq!(loaded["a.h5"].stamp, stamp(20, 2));
+        assert!(!watch.state_path().with_extension("json.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
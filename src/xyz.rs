@@ -0,0 +1,334 @@
+//! XYZ (slippy map) PNG tile pyramids of a group statistic, rendered
+//! straight from the HDF5 datasets for web-map previews (`tiles`
+//! subcommand).
+//!
+//! The statistic is read in strips and subsampled to fit the memory limit,
+//! stretched between two percentiles and mapped through a colour ramp like
+//! quicklooks. Each 256×256 tile of `<dir>/<z>/<x>/<y>.png` samples it at
+//! the web mercator centres of its pixels, reprojected to the grid CRS
+//! (with GDAL unless the grid is geographic). Pixels without a value and
+//! tiles outside the grid are transparent and not written.
+
+use crate::error::{Error, Result};
+use crate::interrupt;
+use crate::quicklook::{self, ColorRamp};
+use crate::raster::{self, GeoRef};
+use crate::stac;
+use crate::stats::{self, StatNames};
+use crate::verify::read_stat_rows;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Side of the tiles in pixels.
+pub const TILE_SIZE: usize = 256;
+
+/// Largest zoom level rendered, about 0.6 m per pixel at the equator.
+pub const MAX_ZOOM: u8 = 18;
+
+/// Latitude limit of web mercator.
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+#[derive(Debug, Clone)]
+pub struct XyzOptions {
+    /// Lowest and highest zoom levels, by default 0 to the level whose
+    /// pixels match those of the grid.
+    pub zooms: Option<(u8, u8)>,
+    pub ramp: ColorRamp,
+    /// Percentiles mapped to the ends of the ramp.
+    pub stretch: (f64, f64),
+}
+
+impl Default for XyzOptions {
+    fn default() -> Self {
+        return XyzOptions {
+            zooms: None,
+            ramp: ColorRamp::Viridis,
+            stretch: (2f64, 98f64),
+        };
+    }
+}
+
+/// Parses a zoom level range `MIN-MAX`, such as `0-8`, or one level.
+pub fn parse_zooms(text: &str) -> std::result::Result<(u8, u8), String> {
+    let invalid = || {
+        format!(
+            "invalid zoom levels '{}', expected MIN-MAX between 0 and {}",
+            text, MAX_ZOOM
+        )
+    };
+    let mut parts = text.split('-').map(|part| part.trim().parse::<u8>());
+    let (min, max) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(zoom)), None, None) => (zoom, zoom),
+        (Some(Ok(min)), Some(Ok(max)), None) => (min, max),
+        _ => return Err(invalid()),
+    };
+    if min > max || max > MAX_ZOOM {
+        return Err(invalid());
+    }
+    return Ok((min, max));
+}
+
+/// The statistic sampled every `step` pixels, rows of `width` values.
+struct Sampled {
+    vals: Vec<f32>,
+    width: usize,
+    step: usize,
+}
+
+/// Maps longitudes and latitudes to pixel coordinates of the grid.
+struct ToGrid {
+    georef: GeoRef,
+    #[cfg(feature = "gdal")]
+    transform: Option<gdal::spatial_ref::CoordTransform>,
+}
+
+impl ToGrid {
+    fn new(georef: &GeoRef) -> Result<ToGrid> {
+        let geographic = stac::is_geographic(&georef.crs);
+        #[cfg(feature = "gdal")]
+        let transform = match geographic {
+            true => None,
+            false => {
+                use gdal::spatial_ref::{CoordTransform, SpatialRef};
+                // PROJ strings keep the longitude first, unlike EPSG:4326.
+                let wgs84 = SpatialRef::from_proj4("+proj=longlat +datum=WGS84 +no_defs")?;
+                let grid = SpatialRef::from_definition(&georef.crs)?;
+                Some(CoordTransform::new(&wgs84, &grid)?)
+            }
+        };
+        #[cfg(not(feature = "gdal"))]
+        {
+            if !geographic {
+                return Err(Error::Invalid(
+                    "tiles of grids that are not geographic need the gdal feature".to_owned(),
+                ));
+            }
+        }
+        return Ok(ToGrid {
+            georef: georef.clone(),
+            #[cfg(feature = "gdal")]
+            transform,
+        });
+    }
+
+    /// Replaces the longitudes `xs` and latitudes `ys` by the (fractional)
+    /// columns and rows of the grid at them.
+    fn apply(&self, xs: &mut [f64], ys: &mut [f64]) -> Result<()> {
+        #[cfg(feature = "gdal")]
+        {
+            if let Some(transform) = &self.transform {
+                transform.transform_coords(xs, ys, &mut vec![0f64; xs.len()])?;
+            }
+        }
+        let gt = &self.georef.geo_transform;
+        let det = gt[1] * gt[5] - gt[2] * gt[4];
+        for (x, y) in xs.iter_mut().zip(ys.iter_mut()) {
+            let (dx, dy) = (*x - gt[0], *y - gt[3]);
+            *x = (gt[5] * dx - gt[2] * dy) / det;
+            *y = (gt[1] * dy - gt[4] * dx) / det;
+        }
+        return Ok(());
+    }
+}
+
+/// Longitude and latitude of the web mercator pixel (`px`, `py`) of a zoom
+/// level `n_pixels` wide.
+fn mercator_lon_lat(px: f64, py: f64, n_pixels: f64) -> (f64, f64) {
+    let lon = px / n_pixels * 360f64 - 180f64;
+    let lat = (PI * (1f64 - 2f64 * py / n_pixels))
+        .sinh()
+        .atan()
+        .to_degrees();
+    return (lon, lat);
+}
+
+/// Tile column and row at `lon`, `lat` at zoom level `zoom`.
+fn tile_at(lon: f64, lat: f64, zoom: u8) -> (u32, u32) {
+    let n_tiles = (1u64 << zoom) as f64;
+    let lat = lat.max(-MAX_LATITUDE).min(MAX_LATITUDE).to_radians();
+    let x = (lon + 180f64) / 360f64 * n_tiles;
+    let y = (1f64 - (lat.tan() + 1f64 / lat.cos()).ln() / PI) / 2f64 * n_tiles;
+    let clamp = |val: f64| val.floor().max(0f64).min(n_tiles - 1f64) as u32;
+    return (clamp(x), clamp(y));
+}
+
+/// Zoom level whose pixels are about `degrees` wide.
+fn zoom_for(degrees: f64) -> u8 {
+    let zoom = (360f64 / (TILE_SIZE as f64 * degrees)).log2().ceil();
+    return zoom.max(0f64).min(MAX_ZOOM as f64) as u8;
+}
+
+/// Renders statistic `stat` (`mean`, `sd` or `count`) of `group`, on a grid
+/// of `size` pixels georeferenced by `georef`, as an XYZ pyramid in
+/// `out_dir`, reading strips of `n_lines` rows and keeping at most
+/// `max_values` samples. Returns the number of tiles written.
+#[allow(clippy::too_many_arguments)]
+pub fn render_group(
+    file: &hdf5::File,
+    group: &str,
+    stat_names: &StatNames,
+    field: Option<&str>,
+    stat: &str,
+    (xsize, ysize): (usize, usize),
+    flip: bool,
+    min_count: u32,
+    georef: &GeoRef,
+    (n_lines, max_values): (usize, usize),
+    options: &XyzOptions,
+    out_dir: &Path,
+) -> Result<usize> {
+    let to_grid = ToGrid::new(georef)?;
+    let footprint = stac::wgs84_bounds(&georef.crs, stac::native_bounds(georef, (xsize, ysize)))
+        .ok_or_else(|| Error::Invalid(format!("could not compute the footprint of {}", group)))?;
+
+    // Subsample so the values kept fit `max_values`.
+    let step = ((xsize * ysize) as f64 / max_values.max(1) as f64)
+        .sqrt()
+        .ceil()
+        .max(1f64) as usize;
+    let width = (xsize + step - 1) / step;
+    let wide = field.is_none() && stats::is_wide(file, group, stat_names, "")?;
+    let empty = raster::empty_value(stat);
+    let mut sampled = Sampled {
+        vals: Vec::with_capacity(width * ((ysize + step - 1) / step)),
+        width,
+        step,
+    };
+    for yy in (0..ysize).step_by(n_lines.max(1)) {
+        interrupt::check()?;
+        let lines = n_lines.min(ysize - yy);
+        let vals = read_stat_rows(
+            file,
+            group,
+            stat_names,
+            field,
+            stat,
+            (xsize, ysize),
+            flip,
+            (min_count, wide),
+            yy,
+            lines,
+        )?;
+        for row in (yy..yy + lines).filter(|row| row % step == 0) {
+            let offset = (row - yy) * xsize;
+            sampled.vals.extend(
+                vals[offset..offset + xsize]
+                    .iter()
+                    .step_by(step)
+                    .map(|&val| match val == empty {
+                        true => f32::NAN,
+                        false => val as f32,
+                    }),
+            );
+        }
+    }
+
+    let mut finite: Vec<f64> = sampled
+        .vals
+        .iter()
+        .filter(|val| val.is_finite())
+        .map(|&val| val as f64)
+        .collect();
+    finite.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let (low, high) = match finite.is_empty() {
+        true => (0f64, 1f64),
+        false => (
+            quicklook::percentile(&finite, options.stretch.0),
+            quicklook::percentile(&finite, options.stretch.1),
+        ),
+    };
+    let range = if high > low { high - low } else { 1f64 };
+    drop(finite);
+
+    let (min_zoom, max_zoom) = options
+        .zooms
+        .unwrap_or_else(|| (0, zoom_for((footprint[2] - footprint[0]) / xsize as f64)));
+    let [west, south, east, north] = footprint;
+    let mut n_written = 0;
+    for zoom in min_zoom..=max_zoom {
+        let (x_first, y_first) = tile_at(west, north, zoom);
+        let (x_last, y_last) = tile_at(east, south, zoom);
+        println!(
+            "Rendering zoom level {}: {} tiles...",
+            zoom,
+            (x_last - x_first + 1) as u64 * (y_last - y_first + 1) as u64
+        );
+        for tile_x in x_first..=x_last {
+            for tile_y in y_first..=y_last {
+                interrupt::check()?;
+                let rgba = render_tile(
+                    &sampled,
+                    &to_grid,
+                    (xsize, ysize),
+                    (zoom, tile_x, tile_y),
+                    |val| options.ramp.color((val - low) / range),
+                )?;
+                if rgba.chunks_exact(4).all(|pixel| pixel[3] == 0) {
+                    continue;
+                }
+                let dir = out_dir.join(zoom.to_string()).join(tile_x.to_string());
+                std::fs::create_dir_all(&dir)?;
+                write_png(&dir.join(format!("{}.png", tile_y)), &rgba)?;
+                n_written += 1;
+            }
+        }
+    }
+    return Ok(n_written);
+}
+
+/// RGBA pixels of tile (`x`, `y`) of zoom level `zoom`, sampling the
+/// nearest value of `sampled` at each pixel centre and colouring it with
+/// `color`.
+fn render_tile(
+    sampled: &Sampled,
+    to_grid: &ToGrid,
+    (xsize, ysize): (usize, usize),
+    (zoom, x, y): (u8, u32, u32),
+    color: impl Fn(f64) -> [u8; 3],
+) -> Result<Vec<u8>> {
+    let n_pixels = ((1u64 << zoom) * TILE_SIZE as u64) as f64;
+    let mut xs = Vec::with_capacity(TILE_SIZE * TILE_SIZE);
+    let mut ys = Vec::with_capacity(TILE_SIZE * TILE_SIZE);
+    for row in 0..TILE_SIZE {
+        for col in 0..TILE_SIZE {
+            let px = (x as usize * TILE_SIZE + col) as f64 + 0.5;
+            let py = (y as usize * TILE_SIZE + row) as f64 + 0.5;
+            let (lon, lat) = mercator_lon_lat(px, py, n_pixels);
+            xs.push(lon);
+            ys.push(lat);
+        }
+    }
+    to_grid.apply(&mut xs, &mut ys)?;
+    let mut rgba = Vec::with_capacity(TILE_SIZE * TILE_SIZE * 4);
+    for (&col, &row) in xs.iter().zip(ys.iter()) {
+        let inside = col >= 0f64 && row >= 0f64 && col < xsize as f64 && row < ysize as f64;
+        let val = match inside {
+            true => {
+                let (col, row) = (col as usize / sampled.step, row as usize / sampled.step);
+                sampled.vals[row * sampled.width + col]
+            }
+            false => f32::NAN,
+        };
+        if val.is_finite() {
+            rgba.extend_from_slice(&color(val as f64));
+            rgba.push(255);
+        } else {
+            rgba.extend_from_slice(&[0, 0, 0, 0]);
+        }
+    }
+    return Ok(rgba);
+}
+
+fn write_png(path: &Path, rgba: &[u8]) -> Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        TILE_SIZE as u32,
+        TILE_SIZE as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgba)?;
+    return Ok(());
+}
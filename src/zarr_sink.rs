@@ -0,0 +1,290 @@
+//! Zarr v3 output (`zarr-sink` feature).
+//!
+//! Each output is a directory store holding a group with the statistic array,
+//! its `x`/`y` pixel-centre coordinate arrays and a scalar `spatial_ref`
+//! array carrying the CRS and GDAL geotransform, following the CF and GeoZarr
+//! conventions read by xarray. Chunks are strips of whole rows, gzip
+//! compressed.
+
+use crate::error::Result;
+use crate::raster::{GeoRef, RasterSink};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Element types with a Zarr data type.
+pub trait ZarrValue: Copy {
+    const DATA_TYPE: &'static str;
+
+    fn fill_value() -> Value;
+
+    fn extend_le_bytes(vals: &[Self], bytes: &mut Vec<u8>);
+
+    /// Padding for the last, partial chunk.
+    fn padding() -> Self;
+}
+
+impl ZarrValue for u8 {
+    const DATA_TYPE: &'static str = "uint8";
+
+    fn fill_value() -> Value {
+        return json!(0);
+    }
+
+    fn extend_le_bytes(vals: &[u8], bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(vals);
+    }
+
+    fn padding() -> u8 {
+        return 0;
+    }
+}
+
+impl ZarrValue for f32 {
+    const DATA_TYPE: &'static str = "float32";
+
+    fn fill_value() -> Value {
+        return json!("NaN");
+    }
+
+    fn extend_le_bytes(vals: &[f32], bytes: &mut Vec<u8>) {
+        for val in vals {
+            bytes.extend_from_slice(&val.to_le_bytes());
+        }
+    }
+
+    fn padding() -> f32 {
+        return f32::NAN;
+    }
+}
+
+impl ZarrValue for f64 {
+    const DATA_TYPE: &'static str = "float64";
+
+    fn fill_value() -> Value {
+        return json!("NaN");
+    }
+
+    fn extend_le_bytes(vals: &[f64], bytes: &mut Vec<u8>) {
+        for val in vals {
+            bytes.extend_from_slice(&val.to_le_bytes());
+        }
+    }
+
+    fn padding() -> f64 {
+        return f64::NAN;
+    }
+}
+
+macro_rules! impl_zarr_integer {
+    ($($ty:ty => $data_type:expr),*) => {
+        $(
+            impl ZarrValue for $ty {
+                const DATA_TYPE: &'static str = $data_type;
+
+                fn fill_value() -> Value {
+                    return json!(0);
+                }
+
+                fn extend_le_bytes(vals: &[$ty], bytes: &mut Vec<u8>) {
+                    for val in vals {
+                        bytes.extend_from_slice(&val.to_le_bytes());
+                    }
+                }
+
+                fn padding() -> $ty {
+                    return 0;
+                }
+            }
+        )*
+    };
+}
+
+impl_zarr_integer!(i16 => "int16", u16 => "uint16", i32 => "int32", u32 => "uint32");
+
+pub struct ZarrSink {
+    root: PathBuf,
+    name: String,
+    georef: GeoRef,
+    units: Option<String>,
+    /// Metadata items added to the attributes of the array.
+    metadata: Vec<(String, String)>,
+}
+
+impl ZarrSink {
+    /// Creates the store at `out_path`, replacing an existing one, for the
+    /// array `name` georeferenced like `georef`.
+    pub fn create(out_path: &str, name: &str, georef: GeoRef) -> Result<ZarrSink> {
+        let root = PathBuf::from(out_path);
+        if root.exists() {
+            fs::remove_dir_all(&root)?;
+        }
+        fs::create_dir_all(&root)?;
+        write_json(
+            &root.join("zarr.json"),
+            &json!({
+                "zarr_format": 3,
+                "node_type": "group",
+                "attributes": {"Conventions": "CF-1.8"},
+            }),
+        )?;
+        return Ok(ZarrSink {
+            root,
+            name: name.to_owned(),
+            georef,
+            units: None,
+            metadata: vec![],
+        });
+    }
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(value)?)?;
+    return Ok(());
+}
+
+/// Writes the metadata of a 1-d or 2-d array named `name` chunked by
+/// `chunk_shape`.
+fn write_array_meta<T: ZarrValue>(
+    root: &Path,
+    name: &str,
+    shape: &[usize],
+    chunk_shape: &[usize],
+    dimension_names: &[&str],
+    attributes: Value,
+) -> Result<PathBuf> {
+    let dir = root.join(name);
+    fs::create_dir_all(&dir)?;
+    write_json(
+        &dir.join("zarr.json"),
+        &json!({
+            "zarr_format": 3,
+            "node_type": "array",
+            "shape": shape,
+            "data_type": T::DATA_TYPE,
+            "chunk_grid": {
+                "name": "regular",
+                "configuration": {"chunk_shape": chunk_shape},
+            },
+            "chunk_key_encoding": {
+                "name": "default",
+                "configuration": {"separator": "/"},
+            },
+            "fill_value": T::fill_value(),
+            "codecs": [
+                {"name": "bytes", "configuration": {"endian": "little"}},
+                {"name": "gzip", "configuration": {"level": 1}},
+            ],
+            "dimension_names": dimension_names,
+            "attributes": attributes,
+        }),
+    )?;
+    return Ok(dir);
+}
+
+/// Writes one chunk of `vals` at `key` (e.g. `c/3/0`) below `dir`.
+fn write_chunk<T: ZarrValue>(dir: &Path, key: &str, vals: &[T]) -> Result<()> {
+    let path = dir.join(key);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut bytes = Vec::with_capacity(vals.len() * std::mem::size_of::<T>());
+    T::extend_le_bytes(vals, &mut bytes);
+    let mut encoder = GzEncoder::new(fs::File::create(path)?, Compression::new(1));
+    encoder.write_all(&bytes)?;
+    encoder.finish()?;
+    return Ok(());
+}
+
+impl ZarrSink {
+    fn write_coordinates(&self, xsize: usize, ysize: usize) -> Result<()> {
+        let gt = self.georef.geo_transform;
+        let x: Vec<f64> = (0..xsize)
+            .map(|ii| gt[0] + (ii as f64 + 0.5) * gt[1])
+            .collect();
+        let y: Vec<f64> = (0..ysize)
+            .map(|ii| gt[3] + (ii as f64 + 0.5) * gt[5])
+            .collect();
+        let x_dir = write_array_meta::<f64>(
+            &self.root,
+            "x",
+            &[xsize],
+            &[xsize.max(1)],
+            &["x"],
+            json!({"standard_name": "projection_x_coordinate", "axis": "X"}),
+        )?;
+        write_chunk(&x_dir, "c/0", &x)?;
+        let y_dir = write_array_meta::<f64>(
+            &self.root,
+            "y",
+            &[ysize],
+            &[ysize.max(1)],
+            &["y"],
+            json!({"standard_name": "projection_y_coordinate", "axis": "Y"}),
+        )?;
+        write_chunk(&y_dir, "c/0", &y)?;
+
+        let gt_text = gt
+            .iter()
+            .map(|val| val.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        write_array_meta::<u8>(
+            &self.root,
+            "spatial_ref",
+            &[],
+            &[],
+            &[],
+            json!({"crs_wkt": self.georef.crs, "GeoTransform": gt_text}),
+        )?;
+        return Ok(());
+    }
+}
+
+impl<T: ZarrValue> RasterSink<T> for ZarrSink {
+    fn write_strips(
+        &mut self,
+        xsize: usize,
+        ysize: usize,
+        n_lines: usize,
+        next_strip: &mut dyn FnMut(usize, usize) -> Result<Vec<T>>,
+    ) -> Result<()> {
+        self.write_coordinates(xsize, ysize)?;
+        let mut attributes = json!({"long_name": self.name, "grid_mapping": "spatial_ref"});
+        if let Some(units) = &self.units {
+            attributes["units"] = json!(units);
+        }
+        for (name, value) in self.metadata.iter() {
+            attributes[name.as_str()] = json!(value);
+        }
+        let dir = write_array_meta::<T>(
+            &self.root,
+            &self.name,
+            &[ysize, xsize],
+            &[n_lines, xsize.max(1)],
+            &["y", "x"],
+            attributes,
+        )?;
+        for (chunk, yy) in (0..ysize).step_by(n_lines).enumerate() {
+            let lines = n_lines.min(ysize - yy);
+            let mut vals = next_strip(yy, lines)?;
+            vals.resize(n_lines * xsize, T::padding());
+            write_chunk(&dir, &format!("c/{}/0", chunk), &vals)?;
+        }
+        return Ok(());
+    }
+
+    fn set_units(&mut self, units: &str) -> Result<()> {
+        self.units = Some(units.to_owned());
+        return Ok(());
+    }
+
+    fn set_metadata(&mut self, domain: &str, items: &[(String, String)]) -> Result<()> {
+        for (name, value) in items {
+            let name = format!("{}_{}", domain.to_lowercase(), name);
+            self.metadata.push((name, value.clone()));
+        }
+        return Ok(());
+    }
+}